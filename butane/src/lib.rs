@@ -1,18 +1,36 @@
-pub use butane_codegen::{butane_type, dataresult, model};
+pub use butane_codegen::{
+    butane_type, dataresult, embed_migrations, model, projection, view, FromRow,
+};
+pub use butane_core::compat;
 pub use butane_core::custom;
 pub use butane_core::fkey::ForeignKey;
+pub use butane_core::json::Json;
 pub use butane_core::many::Many;
 pub use butane_core::migrations;
 pub use butane_core::query;
+pub use butane_core::retry;
+pub use butane_core::router;
+pub use butane_core::scope;
+pub use butane_core::search;
+pub use butane_core::sync;
+pub use butane_core::twophase;
+#[cfg(feature = "async")]
+pub use butane_core::DataObjectAsync;
 pub use butane_core::{
-    AsPrimaryKey, DataObject, DataResult, Error, FieldType, FromSql, ObjectState, Result, SqlType,
-    SqlVal, SqlValRef, ToSql,
+    AsPrimaryKey, DataObject, DataResult, Error, ErrorCode, FieldType, FromSql, ObjectState,
+    Result, SqlType, SqlVal, SqlValRef, ToSql,
 };
 
 pub mod db {
     pub use butane_core::db::*;
 }
 
+/// Re-exports for use by generated code. Not part of the public API.
+#[doc(hidden)]
+pub mod internal {
+    pub use butane_core::serde;
+}
+
 /// Macro to construct a [`BoolExpr`] (for use with a [`Query`]) from
 /// an expression with Rust syntax.
 ///
@@ -27,14 +45,54 @@ pub mod db {
 /// with a boolean value. `Foo`'s fields may be referred to as if they
 /// were variables.
 ///
+/// Fields of objects referred to by a [`ForeignKey`] field may be
+/// compared through it, as in `filter!(Post, blog.name == "Cats")`,
+/// including through several foreign keys, as `blog.author.name`. This
+/// is shorthand for `matches`, described below.
+///
 /// # Rust values
 /// To refer to values from the surrounding rust function, enclose
 /// them in braces, like `filter!(Foo, bar == {bar})`
 ///
+/// # Membership
+/// `bar in values` is true if `bar` is equal to one of `values`, a
+/// Rust expression evaluating to a collection such as `vec![1, 2]` or
+/// a variable `ids`, which need not be enclosed in braces. It may also
+/// be written `bar.is_in(values)`. Long lists are split into several
+/// SQL `IN` lists.
+///
+/// # Related objects
+/// `exists(Post.blog, expr)` is true if there is a `Post` whose
+/// foreign key `blog` refers to the object filtered and for which
+/// `expr`, a filter on `Post`, is true. For example,
+/// `filter!(Blog, exists(Post.blog, published == true))` matches the
+/// blogs with a published post. `!exists(...)` is its opposite, and
+/// `!` may also negate other conditions.
+///
 /// # Function-like operations
 /// Filters support some operations for which Rust does not have operators and which are instead
 /// represented syntactically as function calls.
-/// * `like`: parameter is a SQL LIKE expression string, e.g. `title.like("M%").
+/// * `like`: parameter is a SQL LIKE expression string, e.g. `title.like("M%")`.
+///   `\` escapes the wildcards `%` and `_`.
+/// * `ilike`: as `like`, ignoring case, e.g. `title.ilike("m%")`.
+/// * `eq_ignore_case`: as `==` with a string, ignoring case, e.g.
+///   `email.eq_ignore_case("Alice@example.com")`.
+/// * `is_null`, `is_not_null`: no parameter. Use with an `Option`
+///   field, e.g. `pub_time.is_null()`. Comparing with `== None` or
+///   `!= None` is equivalent.
+/// * `starts_with`, `ends_with`: parameter is a string matched
+///   literally, wildcards included, at the start or end of a string
+///   field, e.g. `title.starts_with("100%")`.
+/// * `between`: two parameters, the bounds of a range including both,
+///   e.g. `likes.between(10, 20)` or, for the last 30 days,
+///   `pub_time.between({ now - Duration::days(30) }, { now })`.
+/// * `extract`, `date_trunc`: parameter is a [`DatePart`] such as
+///   `Year` or `Month`. Use with a timestamp field, whose part as an
+///   integer or start of the part is then compared, e.g.
+///   `pub_time.extract(Year) == 2023` or
+///   `pub_time.date_trunc(Month) == { start_of_month }`. Objects may
+///   also be grouped by these, as by month with
+///   `.group_by(Post::fields().pub_time().date_trunc(DatePart::Month))`.
 /// * `matches`: Parameter is a sub-expression. Use with a
 ///   [`ForeignKey`] field to evaluate as true if the referent
 ///   matches. For example, to find all posts made in blogs by people
@@ -46,7 +104,23 @@ pub mod db {
 ///    `tags: Many<Tag>` we could filter to posts with a "cats" with
 ///    the following `tags.contains(tag == "cats"). If the expression
 ///    is single literal, it is assumed to be used to match the
-///    primary key.
+///    primary key. With a string field instead, parameter is a
+///    substring matched literally, e.g. `title.contains("50%")`.
+///
+/// # JSON fields
+/// Within a [`Json`] field, a value is reached by indexing with field
+/// names and array indexes, and may be compared with `==` or `!=`,
+/// e.g. `data["address"]["city"] == "Paris"`. `contains` is true if
+/// the value is an object with the given fields, an array with the
+/// given elements, or otherwise equal to the parameter, e.g.
+/// `data.contains(json!({"tags": ["vip"]}))`. These use the JSON
+/// operators of Postgres and the JSON functions of SQLite and MySQL.
+///
+/// # Filters built at runtime
+/// When the conditions are only known at runtime, as on a search
+/// screen, they may be built with [`Expr::col`] instead, which names a
+/// column with a string and checks the types of values as the filter
+/// is built, and combined with `and`, `or`, `!` and [`BoolExpr::all`].
 ///
 /// # Examples
 /// ```
@@ -64,9 +138,15 @@ pub mod db {
 /// let first_place = 1;
 /// let e2 = filter!(Contestant, rank == { first_place });
 /// let e3 = filter!(Contestant, name.like("A%"));
+/// let e4 = filter!(Contestant, name.starts_with("A") && nationality.contains({ "U" }));
+/// let ranks = vec![1, 2, 3];
+/// let e5 = filter!(Contestant, rank in ranks && nationality in ["US", "CA"]);
 ///```
 ///
 /// [`BoolExpr`]: crate::query::BoolExpr
+/// [`BoolExpr::all`]: crate::query::BoolExpr::all
+/// [`DatePart`]: crate::query::DatePart
+/// [`Expr::col`]: crate::query::Expr::col
 /// [`Query`]: crate::query::Query
 pub use butane_codegen::filter;
 
@@ -75,6 +155,12 @@ pub use butane_codegen::filter;
 /// Use as `query!(Foo, expr)`, where `Foo` is a model type. Returns [`Query`]`<Foo>`.
 ///
 /// Shorthand for `Foo::query().filter(`[`filter`]`!(Foo, expr))`
+///
+/// The results may be ordered by fields of `Foo`, as with
+/// `query!(Foo, expr, order_by(bar desc, baz))`, or `query!(Foo,
+/// order_by(bar))` for all objects. Each field is sorted in ascending
+/// order unless followed by `desc`, and may be followed by
+/// `nulls_first` or `nulls_last`. See [`Query::order_by`].
 //
 /// # Examples
 /// ```
@@ -90,14 +176,99 @@ pub use butane_codegen::filter;
 ///   nationality: String
 /// }
 /// let top_tier: Query<Contestant> = query!(Contestant, rank <= 10);
+/// let ranking: Query<Contestant> = query!(Contestant, rank <= 10, order_by(rank, name desc));
+/// let podium: Query<Contestant> = query!(Contestant, rank in [1, 2, 3], order_by(rank));
 ///```
 ///
 /// [`filter]: crate::filter
 /// [`Query`]: crate::query::Query
+/// [`Query::order_by`]: crate::query::Query::order_by
 #[macro_export]
 macro_rules! query {
-    ($model:ident, $filter:expr) => {
-        <$model as butane::DataResult>::query().filter(butane::filter!($model, $filter))
+    ($model:ident, order_by($($order:tt)*)) => {
+        butane::query!(@order $model, <$model as butane::DataResult>::query(); $($order)*)
+    };
+    // The filter is collected token by token, as with `in` it is not
+    // a Rust expression, until any `order_by`
+    ($model:ident, $($filter:tt)+) => {
+        butane::query!(@filter $model; []; $($filter)+)
+    };
+    (@filter $model:ident; [$($filter:tt)+]; , order_by($($order:tt)*)) => {
+        butane::query!(
+            @order $model,
+            <$model as butane::DataResult>::query().filter(butane::filter!($model, $($filter)+));
+            $($order)*
+        )
+    };
+    (@filter $model:ident; [$($filter:tt)+];) => {
+        <$model as butane::DataResult>::query().filter(butane::filter!($model, $($filter)+))
+    };
+    (@filter $model:ident; [$($filter:tt)*]; $next:tt $($rest:tt)*) => {
+        butane::query!(@filter $model; [$($filter)* $next]; $($rest)*)
+    };
+    (@order $model:ident, $query:expr; $(,)?) => {
+        $query
+    };
+    (@order $model:ident, $query:expr; , $($rest:tt)+) => {
+        butane::query!(@order $model, $query; $($rest)+)
+    };
+    (@order $model:ident, $query:expr; nulls_first $($rest:tt)*) => {
+        butane::query!(@order $model, $query.nulls_first(); $($rest)*)
+    };
+    (@order $model:ident, $query:expr; nulls_last $($rest:tt)*) => {
+        butane::query!(@order $model, $query.nulls_last(); $($rest)*)
+    };
+    (@order $model:ident, $query:expr; $field:ident desc $($rest:tt)*) => {
+        butane::query!(
+            @order $model,
+            $query.order_by($model::fields().$field(), butane::query::OrderDirection::Descending);
+            $($rest)*
+        )
+    };
+    (@order $model:ident, $query:expr; $field:ident asc $($rest:tt)*) => {
+        butane::query!(@order $model, $query; $field $($rest)*)
+    };
+    (@order $model:ident, $query:expr; $field:ident $($rest:tt)*) => {
+        butane::query!(
+            @order $model,
+            $query.order_by($model::fields().$field(), butane::query::OrderDirection::Ascending);
+            $($rest)*
+        )
+    };
+}
+
+/// Fields to set in a bulk update, for [`Query::update`].
+///
+/// Use as `set!(field = value, other = other_value)`, where each
+/// `value` is a Rust expression converting into the type of the
+/// field. Referring to a nonexistent field or giving a value of the
+/// wrong type is a compilation error.
+///
+/// # Examples
+/// ```no_run
+/// # use butane::db::ConnectionSpec;
+/// # use butane_codegen::model;
+/// # use butane::prelude::*;
+/// # use butane::{query, set};
+/// #[model]
+/// struct Contestant {
+///   #[pk]
+///   name: String,
+///   rank: i32,
+///   nationality: String
+/// }
+///
+/// let conn = butane::db::connect(&ConnectionSpec::new("sqlite", "foo.db")).unwrap();
+/// let moved = query!(Contestant, nationality == "US")
+///     .update(&conn, set!(nationality = "CA", rank = 0))
+///     .unwrap();
+///```
+///
+/// [`Query::update`]: crate::query::Query::update
+#[macro_export]
+macro_rules! set {
+    ($($field:ident = $value:expr),+ $(,)?) => {
+        |fields| vec![$(butane::query::FieldExpr::set(&fields.$field(), $value)),+]
     };
 }
 
@@ -165,6 +336,9 @@ pub mod prelude {
     //! used manually.
     #[doc(no_inline)]
     pub use crate::DataObject;
+    #[cfg(feature = "async")]
+    #[doc(no_inline)]
+    pub use crate::DataObjectAsync;
     #[doc(no_inline)]
     pub use crate::DataResult;
     pub use butane_core::db::BackendConnection;
@@ -1,5 +1,5 @@
 use butane::prelude::*;
-use butane::{dataresult, model};
+use butane::{dataresult, model, projection};
 use butane::{db::Connection, ForeignKey, Many, ObjectState};
 use chrono::{naive::NaiveDateTime, offset::Utc};
 
@@ -54,6 +54,25 @@ pub struct PostMetadata {
     pub pub_time: Option<NaiveDateTime>,
 }
 
+#[projection(Post)]
+#[derive(Debug)]
+pub struct PostApi {
+    pub id: i64,
+    pub title: String,
+    pub body: String,
+    #[butane(computed = "PostApi::excerpt")]
+    pub excerpt: String,
+}
+impl PostApi {
+    fn excerpt(&self) -> String {
+        self.body
+            .split_whitespace()
+            .take(3)
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
 #[model]
 #[derive(Debug)]
 #[table = "tags"]
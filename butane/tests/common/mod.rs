@@ -5,7 +5,9 @@ use butane::migrations::{MemMigrations, Migration, Migrations, MigrationsMut};
 pub mod blog;
 #[cfg(feature = "pg")]
 pub mod pg;
+// Not used by the tests which only run on SQLite, such as router.rs
 #[cfg(feature = "pg")]
+#[allow(unused_imports)]
 pub use pg::*;
 
 pub fn setup_db(backend: Box<dyn Backend>, conn: &mut Connection) {
@@ -76,6 +76,8 @@ fn create_tmp_server() -> PgServerState {
         .arg(&sockdir)
         .arg("-h")
         .arg("")
+        .arg("-c")
+        .arg("max_prepared_transactions=10")
         .stderr(Stdio::piped())
         .spawn()
         .expect("failed to run postgres");
@@ -0,0 +1,61 @@
+use butane::compat::Tolerant;
+use butane::db::{Connection, ConnectionMethods};
+use butane::model;
+use butane::prelude::*;
+
+mod common;
+
+#[model]
+#[derive(PartialEq, Eq, Debug)]
+struct Evolving {
+    id: i64,
+    name: String,
+    // Added by a migration not yet applied
+    note: Option<String>,
+}
+impl Evolving {
+    fn new(id: i64, name: &str) -> Self {
+        Evolving {
+            id,
+            name: name.to_string(),
+            note: None,
+            state: butane::ObjectState::default(),
+        }
+    }
+}
+
+fn missing_optional_column(conn: Connection) {
+    conn.execute("DROP TABLE Evolving;").unwrap();
+    conn.execute("CREATE TABLE Evolving (id BIGINT PRIMARY KEY, name TEXT NOT NULL);")
+        .unwrap();
+    assert!(Evolving::new(1, "first").save(&conn).is_err());
+
+    let tolerant = Tolerant::new(&conn).unwrap();
+    let mut obj = Evolving::new(1, "first");
+    obj.save(&tolerant).unwrap();
+    obj.name = "renamed".to_string();
+    obj.save(&tolerant).unwrap();
+    assert_eq!(Evolving::get(&tolerant, 1).unwrap(), obj);
+    let found = butane::query!(Evolving, name == "renamed")
+        .load(&tolerant)
+        .unwrap();
+    assert_eq!(found, vec![obj]);
+
+    // Values other than NULL cannot be written to the missing column
+    let mut noted = Evolving::new(2, "second");
+    noted.note = Some("note".to_string());
+    assert!(noted.save(&tolerant).is_err());
+}
+testall!(missing_optional_column);
+
+fn extra_column(conn: Connection) {
+    conn.execute("ALTER TABLE Evolving ADD COLUMN extra TEXT;")
+        .unwrap();
+    let tolerant = Tolerant::new(&conn).unwrap();
+    let mut obj = Evolving::new(1, "first");
+    obj.note = Some("note".to_string());
+    obj.save(&tolerant).unwrap();
+    assert_eq!(Evolving::get(&conn, 1).unwrap(), obj);
+    assert_eq!(Evolving::get(&tolerant, 1).unwrap(), obj);
+}
+testall!(extra_column);
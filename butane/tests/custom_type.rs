@@ -92,3 +92,40 @@ fn query_custom_type(conn: Connection) {
     assert_eq!(results[0], obj_bar)
 }
 testall!(query_custom_type);
+
+#[butane_type(Enum)]
+#[derive(PartialEq, Eq, Debug, Clone)]
+enum Currency {
+    Dollars,
+    Pounds,
+    Euros,
+}
+
+#[model]
+#[derive(PartialEq, Eq, Debug, Clone)]
+struct HasEnumField {
+    id: i64,
+    currency: Currency,
+}
+
+fn roundtrip_enum_type(conn: Connection) {
+    let mut dollars = HasEnumField {
+        id: 1,
+        currency: Currency::Dollars,
+        state: ObjectState::default(),
+    };
+    dollars.save(&conn).unwrap();
+    let mut euros = HasEnumField {
+        id: 2,
+        currency: Currency::Euros,
+        state: ObjectState::default(),
+    };
+    euros.save(&conn).unwrap();
+
+    assert_eq!(HasEnumField::get(&conn, 1).unwrap(), dollars);
+    let results = query!(HasEnumField, currency == { Currency::Euros })
+        .load(&conn)
+        .unwrap();
+    assert_eq!(results, vec![euros]);
+}
+testall!(roundtrip_enum_type);
@@ -0,0 +1,158 @@
+use butane::db::Connection;
+use butane::prelude::*;
+use butane::{colname, filter, find, model, query, Json, ObjectState};
+use serde_json::json;
+
+mod common;
+
+#[model]
+#[derive(PartialEq, Debug, Clone)]
+struct Customer {
+    id: i64,
+    name: String,
+    data: Json,
+}
+impl Customer {
+    fn new(id: i64, name: &str, data: serde_json::Value) -> Self {
+        Customer {
+            id,
+            name: name.to_string(),
+            data: Json::new(data),
+            state: ObjectState::default(),
+        }
+    }
+}
+
+fn setup_customers(conn: &Connection) {
+    Customer::new(
+        1,
+        "Amélie",
+        json!({
+            "address": {"city": "Paris", "lines": ["1 Rue de Rivoli"]},
+            "tags": ["vip", "early"],
+            "visits": 12,
+            "verified": true,
+        }),
+    )
+    .save(conn)
+    .unwrap();
+    Customer::new(
+        2,
+        "Bob",
+        json!({
+            "address": {"city": "London", "lines": ["10 Downing St"]},
+            "tags": ["early"],
+            "visits": 3,
+            "verified": false,
+        }),
+    )
+    .save(conn)
+    .unwrap();
+    Customer::new(3, "Chen", json!({"address": null, "tags": []}))
+        .save(conn)
+        .unwrap();
+}
+
+fn names(customers: Vec<Customer>) -> Vec<String> {
+    customers.into_iter().map(|c| c.name).collect()
+}
+
+fn json_roundtrip(conn: Connection) {
+    setup_customers(&conn);
+    let mut customer = find!(Customer, id == 2, &conn).unwrap();
+    assert_eq!(customer.data["address"]["city"], "London");
+
+    customer.data["visits"] = json!(4);
+    customer.save(&conn).unwrap();
+    let customer = Customer::get(&conn, 2).unwrap();
+    assert_eq!(customer.data["visits"], 4);
+}
+testall!(json_roundtrip);
+
+fn json_path_eq(conn: Connection) {
+    setup_customers(&conn);
+    let found = query!(Customer, data["address"]["city"] == "Paris")
+        .load(&conn)
+        .unwrap();
+    assert_eq!(names(found), ["Amélie"]);
+
+    let found = query!(Customer, data["address"]["lines"][0] == "10 Downing St")
+        .load(&conn)
+        .unwrap();
+    assert_eq!(names(found), ["Bob"]);
+
+    let found = query!(Customer, data["visits"] == 3).load(&conn).unwrap();
+    assert_eq!(names(found), ["Bob"]);
+
+    let found = query!(Customer, data["verified"] == true)
+        .load(&conn)
+        .unwrap();
+    assert_eq!(names(found), ["Amélie"]);
+
+    let found = query!(Customer, data["address"] == json!(null))
+        .load(&conn)
+        .unwrap();
+    assert_eq!(names(found), ["Chen"]);
+
+    // Values are compared with their type
+    let found = query!(Customer, data["visits"] == "3").load(&conn).unwrap();
+    assert!(found.is_empty());
+
+    // Customers without the value are matched by neither
+    let found = query!(Customer, data["visits"] != 3)
+        .order_asc(colname!(Customer, id))
+        .load(&conn)
+        .unwrap();
+    assert_eq!(names(found), ["Amélie"]);
+
+    let key = "address";
+    let found = query!(Customer, data[{ key }]["city"] == "London")
+        .load(&conn)
+        .unwrap();
+    assert_eq!(names(found), ["Bob"]);
+}
+testall!(json_path_eq);
+
+fn json_contains(conn: Connection) {
+    setup_customers(&conn);
+    let found = query!(
+        Customer,
+        data.contains(json!({"address": {"city": "Paris"}}))
+    )
+    .load(&conn)
+    .unwrap();
+    assert_eq!(names(found), ["Amélie"]);
+
+    let found = query!(Customer, data.contains(json!({"tags": ["early"]})))
+        .order_asc(colname!(Customer, id))
+        .load(&conn)
+        .unwrap();
+    assert_eq!(names(found), ["Amélie", "Bob"]);
+
+    let found = query!(Customer, data["tags"].contains(json!(["early", "vip"])))
+        .load(&conn)
+        .unwrap();
+    assert_eq!(names(found), ["Amélie"]);
+
+    let found = query!(Customer, data.contains(json!({"tags": []})))
+        .order_asc(colname!(Customer, id))
+        .load(&conn)
+        .unwrap();
+    assert_eq!(names(found), ["Amélie", "Bob", "Chen"]);
+
+    let found = query!(
+        Customer,
+        !data.contains(json!({"verified": true})) && name != "Chen"
+    )
+    .load(&conn)
+    .unwrap();
+    assert_eq!(names(found), ["Bob"]);
+
+    let expr = filter!(
+        Customer,
+        data["address"].contains(json!({"lines": ["1 Rue de Rivoli"]}))
+    );
+    let found = Customer::query().filter(expr).load(&conn).unwrap();
+    assert_eq!(names(found), ["Amélie"]);
+}
+testall!(json_contains);
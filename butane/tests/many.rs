@@ -1,6 +1,6 @@
 use butane::db::Connection;
 use butane::prelude::*;
-use butane::{model, Many, ObjectState};
+use butane::{model, query, Many, ObjectState};
 use paste;
 
 mod common;
@@ -31,6 +31,14 @@ struct AutoItem {
     val: String,
 }
 
+#[model]
+#[table = "tagged_items"]
+struct TaggedItem {
+    id: i64,
+    #[butane(many(table = "tagged_item_tags", column(name = "note", sqltype = "Text")))]
+    tags: Many<Tag>,
+}
+
 fn remove_one_from_many(conn: Connection) {
     let mut cats_blog = Blog::new(1, "Cats");
     cats_blog.save(&conn).unwrap();
@@ -114,3 +122,22 @@ fn cant_add_unsaved_to_many(_conn: Connection) {
         .expect_err("unexpectedly not error");
 }
 testall!(cant_add_unsaved_to_many);
+
+fn many_with_custom_table(conn: Connection) {
+    let mut item = TaggedItem {
+        id: 1,
+        tags: Many::default(),
+        state: ObjectState::default(),
+    };
+    item.tags.add(&create_tag(&conn, "blue")).unwrap();
+    item.tags.add(&create_tag(&conn, "red")).unwrap();
+    item.save(&conn).unwrap();
+
+    let item = TaggedItem::get(&conn, 1).unwrap();
+    assert_eq!(item.tags.load(&conn).unwrap().count(), 2);
+    let blue = query!(TaggedItem, tags.contains("blue"))
+        .load(&conn)
+        .unwrap();
+    assert_eq!(blue.len(), 1);
+}
+testall!(many_with_custom_table);
@@ -1,9 +1,14 @@
+use butane::db::{Connection, ConnectionMethods};
 use butane::migrations::{
-    adb::DeferredSqlType, adb::TypeIdentifier, adb::TypeKey, MemMigrations, Migration,
-    MigrationMut, Migrations, MigrationsMut,
+    adb, adb::DeferredSqlType, adb::ReferentialAction, adb::TypeIdentifier, adb::TypeKey,
+    copy_migration, copy_types, testing, Divergence, MemMigration, MemMigrations, Migration,
+    MigrationMut, Migrations, MigrationsMut, TableFilter, Verification,
+};
+use butane::{prelude::*, SqlType, SqlVal};
+use butane_core::codegen::{
+    butane_type_with_migrations, embed_migrations, filtered_fields, migration_group,
+    model_with_migrations, view_with_migrations, FilterUsage,
 };
-use butane::{db::Connection, prelude::*, SqlType, SqlVal};
-use butane_core::codegen::{butane_type_with_migrations, model_with_migrations};
 use proc_macro2::TokenStream;
 use quote::quote;
 
@@ -40,7 +45,7 @@ fn current_migration_basic() {
     assert_eq!(barcol.typeid().unwrap(), TypeIdentifier::Ty(SqlType::Text));
     assert!(!barcol.is_auto());
 
-    assert_eq!(table.pk(), Some(idcol))
+    assert_eq!(table.pk(), std::slice::from_ref(idcol))
 }
 
 #[test]
@@ -62,7 +67,45 @@ fn current_migration_pk_attribute() {
     let pkcol = table.column("name").unwrap();
     assert!(pkcol.is_pk());
 
-    assert_eq!(table.pk(), Some(pkcol))
+    assert_eq!(table.pk(), std::slice::from_ref(pkcol))
+}
+
+#[test]
+fn current_migration_type_params() {
+    let tokens = quote! {
+        struct Foo {
+            id: i64,
+            #[butane(length = 64)]
+            name: String,
+            #[butane(precision = 12, scale = 2)]
+            price: f64,
+            #[butane(precision = 5, scale = 0)]
+            count: f64,
+        }
+    };
+
+    let mut ms = MemMigrations::new();
+    model_with_migrations(tokens, &mut ms);
+    let db = ms.current().db().unwrap();
+    let table = db.get_table("Foo").expect("No Foo table");
+    let name = table.column("name").unwrap();
+    assert_eq!(name.params().length, Some(64));
+    assert_eq!(name.params().precision, None);
+    let price = table.column("price").unwrap();
+    assert_eq!(price.params().precision, Some(12));
+    assert_eq!(price.params().scale, Some(2));
+    // A scale of zero is the same as none
+    assert_eq!(table.column("count").unwrap().params().scale, None);
+    assert!(table.column("id").unwrap().params().is_empty());
+
+    for invalid in [
+        quote! { struct Bar { id: i64, #[butane(scale = 2)] price: f64 } },
+        quote! { struct Bar { id: i64, #[butane(precision = 2, scale = 3)] price: f64 } },
+        quote! { struct Bar { id: i64, #[butane(length = "long")] name: String } },
+    ] {
+        let tokens = model_with_migrations(invalid, &mut MemMigrations::new()).to_string();
+        assert!(tokens.contains("compile_error"));
+    }
 }
 
 #[test]
@@ -85,6 +128,35 @@ fn current_migration_default_attribute() {
     assert_eq!(*barcol.default(), Some(SqlVal::Text("turtle".to_string())));
 }
 
+#[test]
+fn current_migration_default_expr() {
+    let tokens = quote! {
+        struct Foo {
+            id: i64,
+            #[butane(default_expr = "CURRENT_TIMESTAMP")]
+            created: NaiveDateTime,
+        }
+    };
+
+    let mut ms = MemMigrations::new();
+    model_with_migrations(tokens, &mut ms);
+    let db = ms.current().db().unwrap();
+    let table = db.get_table("Foo").expect("No Foo table");
+    let created = table.column("created").unwrap();
+    assert_eq!(created.default_expr(), Some("CURRENT_TIMESTAMP"));
+    assert_eq!(*created.default(), None);
+    assert_eq!(table.column("id").unwrap().default_expr(), None);
+
+    for invalid in [
+        quote! { struct Bar { id: i64, #[butane(default_expr = 1)] n: i64 } },
+        quote! { struct Bar { id: i64, #[butane(default_expr = "")] n: i64 } },
+        quote! { struct Bar { id: i64, #[default = 1] #[butane(default_expr = "2")] n: i64 } },
+    ] {
+        let tokens = model_with_migrations(invalid, &mut MemMigrations::new()).to_string();
+        assert!(tokens.contains("compile_error"));
+    }
+}
+
 #[test]
 fn current_migration_auto_attribute() {
     let tokens = quote! {
@@ -103,6 +175,38 @@ fn current_migration_auto_attribute() {
     let table = db.get_table("Foo").expect("No Foo table");
     let idcol = table.column("id").unwrap();
     assert!(idcol.is_auto());
+    assert_eq!(idcol.auto_increment(), Some(adb::AutoIncrement::Serial));
+}
+
+#[test]
+fn current_migration_identity_attribute() {
+    let tokens = quote! {
+        struct Foo {
+            id: i64,
+            #[butane(identity)]
+            seq: i32,
+            bar: String,
+        }
+    };
+
+    let mut ms = MemMigrations::new();
+    model_with_migrations(tokens, &mut ms);
+    let db = ms.current().db().unwrap();
+    let table = db.get_table("Foo").expect("No Foo table");
+    let seq = table.column("seq").unwrap();
+    assert!(seq.is_auto());
+    assert!(!seq.is_pk());
+    assert_eq!(seq.auto_increment(), Some(adb::AutoIncrement::Identity));
+    let idcol = table.column("id").unwrap();
+    assert!(!idcol.is_auto());
+    assert_eq!(idcol.auto_increment(), None);
+
+    let tokens = model_with_migrations(
+        quote! { struct Bar { id: i64, #[butane(identity)] name: String } },
+        &mut MemMigrations::new(),
+    )
+    .to_string();
+    assert!(tokens.contains("compile_error"));
 }
 
 #[test]
@@ -162,6 +266,253 @@ fn current_migration_custom_type() {
     assert_eq!(col.typeid().unwrap(), TypeIdentifier::Ty(SqlType::Text));
 }
 
+#[test]
+fn current_migration_unique() {
+    let tokens = quote! {
+        #[derive(PartialEq, Eq, Debug, Clone)]
+        #[butane(unique_together("bar", "baz"))]
+        struct Foo {
+            id: i64,
+            #[butane(unique)]
+            bar: String,
+            baz: i32,
+        }
+    };
+
+    let mut ms = MemMigrations::new();
+    model_with_migrations(tokens, &mut ms);
+    let m = ms.current();
+    let db = m.db().unwrap();
+    let table = db.get_table("Foo").expect("No Foo table");
+    assert!(table.column("bar").unwrap().unique());
+    assert!(!table.column("baz").unwrap().unique());
+    assert_eq!(table.unique_constraints.len(), 1);
+    assert_eq!(table.unique_constraints[0].columns(), ["bar", "baz"]);
+}
+
+#[test]
+fn current_migration_index() {
+    let tokens = quote! {
+        #[butane(index("bar", "baz"))]
+        struct Foo {
+            id: i64,
+            #[butane(index)]
+            bar: String,
+            baz: i32,
+            #[unique]
+            qux: String,
+        }
+    };
+
+    let mut ms = MemMigrations::new();
+    model_with_migrations(tokens, &mut ms);
+    let db = ms.current().db().unwrap();
+    let table = db.get_table("Foo").expect("No Foo table");
+    assert_eq!(
+        table.indexes,
+        [adb::AIndex::new(["bar", "baz"]), adb::AIndex::new(["bar"])]
+    );
+    assert!(table.is_indexed("id"));
+    assert!(table.is_indexed("bar"));
+    assert!(!table.is_indexed("baz"));
+    assert!(table.is_indexed("qux"));
+}
+
+#[test]
+fn current_migration_partial_index() {
+    let tokens = quote! {
+        #[butane(index("bar", "baz", predicate = "NOT deleted"))]
+        struct Foo {
+            id: i64,
+            #[butane(index(predicate = "NOT deleted"))]
+            bar: String,
+            baz: i32,
+            deleted: bool,
+        }
+    };
+
+    let mut ms = MemMigrations::new();
+    model_with_migrations(tokens, &mut ms);
+    let db = ms.current().db().unwrap();
+    let table = db.get_table("Foo").expect("No Foo table");
+    assert_eq!(
+        table.indexes,
+        [
+            adb::AIndex::new(["bar", "baz"]).with_predicate("NOT deleted"),
+            adb::AIndex::new(["bar"]).with_predicate("NOT deleted")
+        ]
+    );
+    assert_eq!(table.indexes[1].name("Foo"), "Foo_bar_partial_idx");
+    // Only some rows are indexed
+    assert!(!table.is_indexed("bar"));
+}
+
+#[test]
+fn current_migration_comments() {
+    let tokens = quote! {
+        /// Things for sale.
+        ///
+        /// Sold by the piece.
+        struct Foo {
+            id: i64,
+            /// Name shown to shoppers.
+            #[butane(comment = "The shopper's name for it")]
+            name: String,
+            /// Price in cents.
+            price: i32,
+        }
+    };
+
+    let mut ms = MemMigrations::new();
+    model_with_migrations(tokens, &mut ms);
+    let db = ms.current().db().unwrap();
+    let table = db.get_table("Foo").expect("No Foo table");
+    assert_eq!(
+        table.comment.as_deref(),
+        Some("Things for sale.\n\nSold by the piece.")
+    );
+    assert_eq!(table.column("id").unwrap().comment(), None);
+    // The attribute takes precedence
+    assert_eq!(
+        table.column("name").unwrap().comment(),
+        Some("The shopper's name for it")
+    );
+    assert_eq!(
+        table.column("price").unwrap().comment(),
+        Some("Price in cents.")
+    );
+}
+
+#[test]
+fn current_migration_schema() {
+    let bar = quote! {
+        struct Bar {
+            id: i64,
+        }
+    };
+    let foo = quote! {
+        #[butane(schema = "billing")]
+        struct Foo {
+            id: i64,
+            bars: Many<Bar>,
+        }
+    };
+
+    let mut ms = MemMigrations::new();
+    model_with_migrations(bar, &mut ms);
+    model_with_migrations(foo, &mut ms);
+    let db = ms.current().db().unwrap();
+    for name in ["Foo", "Foo_bars_Many"] {
+        let table = db.get_table(name).expect("No table");
+        assert_eq!(table.schema.as_deref(), Some("billing"));
+    }
+    assert_eq!(db.get_table("Bar").expect("No Bar table").schema, None);
+}
+
+#[test]
+fn filter_usage() {
+    let expr: syn::Expr =
+        syn::parse_quote!(title == "x" && blog.matches(name == "y") || pub_time < { now });
+    let fields = filtered_fields(&expr);
+    assert_eq!(
+        fields.iter().collect::<Vec<_>>(),
+        ["blog", "pub_time", "title"]
+    );
+    // The foreign key of a path to a related object's field
+    let expr: syn::Expr = syn::parse_quote!(blog.owner.name == "y");
+    assert_eq!(filtered_fields(&expr).iter().collect::<Vec<_>>(), ["blog"]);
+    // `id in ids`, as rewritten for parsing, does not count `ids`
+    let expr: syn::Expr = syn::parse_quote!(id << ids);
+    assert_eq!(filtered_fields(&expr).iter().collect::<Vec<_>>(), ["id"]);
+    // Negated conditions count, but not those on related objects
+    let expr: syn::Expr = syn::parse_quote!(!(likes > 1) && !exists(Comment.post, id == 2));
+    assert_eq!(filtered_fields(&expr).iter().collect::<Vec<_>>(), ["likes"]);
+
+    let dir = std::env::temp_dir().join(format!("butane_filters_{}", std::process::id()));
+    let usage = FilterUsage::new(&dir);
+    usage.record("Post", fields).unwrap();
+    usage.record("Post", ["id".to_string()]).unwrap();
+    let loaded = usage.load().unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(loaded.len(), 1);
+    assert_eq!(
+        loaded["Post"].iter().collect::<Vec<_>>(),
+        ["blog", "id", "pub_time", "title"]
+    );
+}
+
+#[test]
+fn current_migration_check() {
+    let tokens = quote! {
+        #[butane(check = "price > 0")]
+        #[butane(check = "price < 1000")]
+        struct Foo {
+            id: i64,
+            price: i32,
+        }
+    };
+
+    let mut ms = MemMigrations::new();
+    model_with_migrations(tokens, &mut ms);
+    let m = ms.current();
+    let db = m.db().unwrap();
+    let table = db.get_table("Foo").expect("No Foo table");
+    assert_eq!(table.checks.len(), 2);
+    assert_eq!(table.checks[0].name(), "Foo_check_1");
+    assert_eq!(table.checks[0].expr(), "price > 0");
+    assert_eq!(table.checks[1].name(), "Foo_check_2");
+    assert_eq!(table.checks[1].expr(), "price < 1000");
+}
+
+#[test]
+fn current_migration_foreign_key() {
+    let bar = quote! {
+        struct Bar {
+            id: i64,
+            #[butane(foreign_key(on_delete = "set_null", on_update = "cascade"))]
+            foo: Option<ForeignKey<Foo>>,
+            other: ForeignKey<Foo>,
+        }
+    };
+    let foo = quote! {
+        #[table = "foos"]
+        struct Foo {
+            id: i64,
+        }
+    };
+
+    let mut ms = MemMigrations::new();
+    model_with_migrations(foo, &mut ms);
+    model_with_migrations(bar, &mut ms);
+    let m = ms.current();
+    let db = m.db().unwrap();
+    let table = db.get_table("Bar").expect("No Bar table");
+    assert!(table.column("other").unwrap().reference().is_none());
+    let fk = table.column("foo").unwrap().reference().unwrap();
+    assert_eq!(fk.table(), "foos");
+    assert_eq!(fk.column(), None);
+    assert_eq!(fk.on_delete(), ReferentialAction::SetNull);
+    assert_eq!(fk.on_update(), ReferentialAction::Cascade);
+
+    // Referenced tables must be created first and dropped last
+    let created: Vec<String> = adb::diff(&adb::ADB::new(), &db)
+        .into_iter()
+        .filter_map(|op| match op {
+            adb::Operation::AddTable(table) => Some(table.name),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(created, ["foos", "Bar"]);
+    let removed: Vec<String> = adb::diff(&db, &adb::ADB::new())
+        .into_iter()
+        .filter_map(|op| match op {
+            adb::Operation::RemoveTable(name) => Some(name),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(removed, ["Bar", "foos"]);
+}
+
 #[cfg(feature = "sqlite")]
 #[test]
 fn migration_add_field_sqlite() {
@@ -174,7 +525,7 @@ fn migration_add_field_sqlite() {
 				// getting sane looking downgrade sql and a test failure if it
 				// changes. If the change is innocuous, this test should just
 				// be updated.
-        "CREATE TABLE Foo__butane_tmp (id INTEGER NOT NULL PRIMARY KEY,bar TEXT NOT NULL);INSERT INTO Foo__butane_tmp SELECT id, bar FROM Foo;DROP TABLE Foo;ALTER TABLE Foo__butane_tmp RENAME TO Foo;",
+        "CREATE TABLE Foo__butane_tmp (/* butane:managed */id INTEGER NOT NULL PRIMARY KEY,bar TEXT NOT NULL);INSERT INTO Foo__butane_tmp SELECT id, bar FROM Foo;DROP TABLE Foo;ALTER TABLE Foo__butane_tmp RENAME TO Foo;",
     );
 }
 
@@ -196,7 +547,7 @@ fn migration_add_field_with_default_sqlite() {
         &mut common::sqlite_connection(),
         "ALTER TABLE Foo ADD COLUMN baz INTEGER NOT NULL DEFAULT 42;",
 				// See comments on migration_add_field_sqlite
-				"CREATE TABLE Foo__butane_tmp (id INTEGER NOT NULL PRIMARY KEY,bar TEXT NOT NULL);INSERT INTO Foo__butane_tmp SELECT id, bar FROM Foo;DROP TABLE Foo;ALTER TABLE Foo__butane_tmp RENAME TO Foo;"
+				"CREATE TABLE Foo__butane_tmp (/* butane:managed */id INTEGER NOT NULL PRIMARY KEY,bar TEXT NOT NULL);INSERT INTO Foo__butane_tmp SELECT id, bar FROM Foo;DROP TABLE Foo;ALTER TABLE Foo__butane_tmp RENAME TO Foo;"
     );
 }
 
@@ -222,8 +573,30 @@ fn migration_add_and_remove_field_sqlite() {
 				// getting sane looking downgrade sql and a test failure if it
 				// changes. If the change is innocuous, this test should just
 				// be updated.
-        "ALTER TABLE Foo ADD COLUMN baz INTEGER NOT NULL DEFAULT 0;CREATE TABLE Foo__butane_tmp (id INTEGER NOT NULL PRIMARY KEY,baz INTEGER NOT NULL);INSERT INTO Foo__butane_tmp SELECT id, baz FROM Foo;DROP TABLE Foo;ALTER TABLE Foo__butane_tmp RENAME TO Foo;",
-        "ALTER TABLE Foo ADD COLUMN bar TEXT NOT NULL DEFAULT '';CREATE TABLE Foo__butane_tmp (id INTEGER NOT NULL PRIMARY KEY,bar TEXT NOT NULL);INSERT INTO Foo__butane_tmp SELECT id, bar FROM Foo;DROP TABLE Foo;ALTER TABLE Foo__butane_tmp RENAME TO Foo;",
+        "ALTER TABLE Foo ADD COLUMN baz INTEGER NOT NULL DEFAULT 0;CREATE TABLE Foo__butane_tmp (/* butane:managed */id INTEGER NOT NULL PRIMARY KEY,baz INTEGER NOT NULL);INSERT INTO Foo__butane_tmp SELECT id, baz FROM Foo;DROP TABLE Foo;ALTER TABLE Foo__butane_tmp RENAME TO Foo;",
+        "ALTER TABLE Foo ADD COLUMN bar TEXT NOT NULL DEFAULT '';CREATE TABLE Foo__butane_tmp (/* butane:managed */id INTEGER NOT NULL PRIMARY KEY,bar TEXT NOT NULL);INSERT INTO Foo__butane_tmp SELECT id, bar FROM Foo;DROP TABLE Foo;ALTER TABLE Foo__butane_tmp RENAME TO Foo;",
+    );
+}
+
+#[cfg(feature = "sqlite")]
+#[test]
+fn migration_add_field_with_default_expr_sqlite() {
+    migration_add_field_with_default_expr(
+        &mut common::sqlite_connection(),
+        // SQLite cannot add a column with a non-constant default
+        "CREATE TABLE Foo__butane_tmp (/* butane:managed */id INTEGER NOT NULL PRIMARY KEY,bar TEXT NOT NULL,created TEXT NOT NULL DEFAULT (CURRENT_TIMESTAMP));INSERT INTO Foo__butane_tmp (id, bar) SELECT id, bar FROM Foo;DROP TABLE Foo;ALTER TABLE Foo__butane_tmp RENAME TO Foo;",
+        "CREATE TABLE Foo__butane_tmp (/* butane:managed */id INTEGER NOT NULL PRIMARY KEY,bar TEXT NOT NULL);INSERT INTO Foo__butane_tmp SELECT id, bar FROM Foo;DROP TABLE Foo;ALTER TABLE Foo__butane_tmp RENAME TO Foo;",
+    );
+}
+
+#[cfg(feature = "pg")]
+#[test]
+fn migration_add_field_with_default_expr_pg() {
+    let (mut conn, _data) = common::pg_connection();
+    migration_add_field_with_default_expr(
+        &mut conn,
+        "ALTER TABLE Foo ADD COLUMN created TIMESTAMP NOT NULL DEFAULT (CURRENT_TIMESTAMP);",
+        "ALTER TABLE Foo DROP COLUMN created;",
     );
 }
 
@@ -240,91 +613,2505 @@ fn migration_add_and_remove_field_pg() {
 
 #[cfg(feature = "sqlite")]
 #[test]
-fn migration_delete_table_sqlite() {
-    migration_delete_table(
+fn migration_change_default_sqlite() {
+    migration_change_default(
         &mut common::sqlite_connection(),
-        "DROP TABLE Foo;",
-        "CREATE TABLE Foo (id INTEGER NOT NULL PRIMARY KEY,bar TEXT NOT NULL);",
+        "CREATE TABLE Foo__butane_tmp (/* butane:managed */id INTEGER NOT NULL PRIMARY KEY,bar INTEGER NOT NULL DEFAULT 2);INSERT INTO Foo__butane_tmp SELECT id, bar FROM Foo;DROP TABLE Foo;ALTER TABLE Foo__butane_tmp RENAME TO Foo;",
+        "CREATE TABLE Foo__butane_tmp (/* butane:managed */id INTEGER NOT NULL PRIMARY KEY,bar INTEGER NOT NULL DEFAULT 1);INSERT INTO Foo__butane_tmp SELECT id, bar FROM Foo;DROP TABLE Foo;ALTER TABLE Foo__butane_tmp RENAME TO Foo;",
     );
 }
 
 #[cfg(feature = "pg")]
 #[test]
-fn migration_delete_table_pg() {
+fn migration_change_default_pg() {
     let (mut conn, _data) = common::pg_connection();
-    migration_delete_table(
+    migration_change_default(
         &mut conn,
-        "DROP TABLE Foo;",
-        "CREATE TABLE Foo (id BIGINT NOT NULL PRIMARY KEY,bar TEXT NOT NULL);",
+        "ALTER TABLE Foo ALTER COLUMN bar SET DEFAULT 2;",
+        "ALTER TABLE Foo ALTER COLUMN bar SET DEFAULT 1;",
     );
 }
 
-fn test_migrate(
-    conn: &mut Connection,
-    init_tokens: TokenStream,
-    v2_tokens: TokenStream,
-    expected_up_sql: &str,
-    expected_down_sql: &str,
-) {
-    let mut ms = MemMigrations::new();
-    let backend = conn.backend();
-    model_with_migrations(init_tokens, &mut ms);
-    assert!(ms.create_migration(&backend, "init", None).unwrap());
+#[cfg(feature = "sqlite")]
+#[test]
+fn migration_change_default_to_expr_sqlite() {
+    migration_change_default_to_expr(
+        &mut common::sqlite_connection(),
+        "CREATE TABLE Foo__butane_tmp (/* butane:managed */id INTEGER NOT NULL PRIMARY KEY,bar INTEGER NOT NULL);INSERT INTO Foo__butane_tmp SELECT id, bar FROM Foo;DROP TABLE Foo;ALTER TABLE Foo__butane_tmp RENAME TO Foo;CREATE TABLE Foo__butane_tmp (/* butane:managed */id INTEGER NOT NULL PRIMARY KEY,bar INTEGER NOT NULL DEFAULT (abs(random())));INSERT INTO Foo__butane_tmp SELECT id, bar FROM Foo;DROP TABLE Foo;ALTER TABLE Foo__butane_tmp RENAME TO Foo;",
+        "CREATE TABLE Foo__butane_tmp (/* butane:managed */id INTEGER NOT NULL PRIMARY KEY,bar INTEGER NOT NULL);INSERT INTO Foo__butane_tmp SELECT id, bar FROM Foo;DROP TABLE Foo;ALTER TABLE Foo__butane_tmp RENAME TO Foo;CREATE TABLE Foo__butane_tmp (/* butane:managed */id INTEGER NOT NULL PRIMARY KEY,bar INTEGER NOT NULL DEFAULT 1);INSERT INTO Foo__butane_tmp SELECT id, bar FROM Foo;DROP TABLE Foo;ALTER TABLE Foo__butane_tmp RENAME TO Foo;",
+    );
+}
 
-    model_with_migrations(v2_tokens, &mut ms);
-    assert!(ms
-        .create_migration(&backend, "v2", ms.latest().as_ref())
-        .unwrap());
+#[cfg(feature = "pg")]
+#[test]
+fn migration_change_default_to_expr_pg() {
+    let (mut conn, _data) = common::pg_connection();
+    // The old default is removed before the new one is set
+    migration_change_default_to_expr(
+        &mut conn,
+        "ALTER TABLE Foo ALTER COLUMN bar DROP DEFAULT;ALTER TABLE Foo ALTER COLUMN bar SET DEFAULT (abs(random()));",
+        "ALTER TABLE Foo ALTER COLUMN bar DROP DEFAULT;ALTER TABLE Foo ALTER COLUMN bar SET DEFAULT 1;",
+    );
+}
 
-    let mut to_apply = ms.unapplied_migrations(conn).unwrap();
-    assert_eq!(to_apply.len(), 2);
-    for m in &to_apply {
-        m.apply(conn).unwrap();
-    }
-    verify_sql(conn, &ms, expected_up_sql, expected_down_sql);
+#[cfg(feature = "pg")]
+#[test]
+fn migration_change_length_pg() {
+    let (mut conn, _data) = common::pg_connection();
+    migration_change_length(
+        &mut conn,
+        "ALTER TABLE Foo ALTER COLUMN bar TYPE VARCHAR(16) USING bar::VARCHAR(16);",
+        "ALTER TABLE Foo ALTER COLUMN bar TYPE VARCHAR(8) USING bar::VARCHAR(8);",
+    );
+}
 
-    // Now downgrade, just to make sure we can
-    to_apply.reverse();
-    for m in to_apply {
-        m.downgrade(conn).unwrap();
-    }
+#[cfg(feature = "sqlite")]
+#[test]
+fn migration_change_type_sqlite() {
+    migration_change_type(
+        &mut common::sqlite_connection(),
+        "CREATE TABLE Foo__butane_tmp (/* butane:managed */id INTEGER NOT NULL PRIMARY KEY,bar TEXT NOT NULL);INSERT INTO Foo__butane_tmp SELECT id, CAST(bar AS TEXT) FROM Foo;DROP TABLE Foo;ALTER TABLE Foo__butane_tmp RENAME TO Foo;",
+        "CREATE TABLE Foo__butane_tmp (/* butane:managed */id INTEGER NOT NULL PRIMARY KEY,bar INTEGER NOT NULL);INSERT INTO Foo__butane_tmp SELECT id, CAST(bar AS INTEGER) FROM Foo;DROP TABLE Foo;ALTER TABLE Foo__butane_tmp RENAME TO Foo;",
+    );
 }
 
-fn verify_sql(
-    conn: &Connection,
-    ms: &impl Migrations,
-    expected_up_sql: &str,
-    expected_down_sql: &str,
-) {
-    let backend = conn.backend();
-    let v2_migration = ms.latest().unwrap();
-    let strip = |s: String| s.replace("\n", "");
+#[cfg(feature = "pg")]
+#[test]
+fn migration_change_type_pg() {
+    let (mut conn, _data) = common::pg_connection();
+    migration_change_type(
+        &mut conn,
+        "ALTER TABLE Foo ALTER COLUMN bar TYPE TEXT USING bar::TEXT;",
+        "ALTER TABLE Foo ALTER COLUMN bar TYPE INTEGER USING bar::INTEGER;",
+    );
+}
 
-    let actual_up_sql = strip(v2_migration.up_sql(backend.name()).unwrap().unwrap());
-    assert_eq!(actual_up_sql, expected_up_sql);
-    let actual_down_sql = strip(v2_migration.down_sql(backend.name()).unwrap().unwrap());
-    assert_eq!(actual_down_sql, expected_down_sql);
+#[cfg(feature = "sqlite")]
+#[test]
+fn migration_change_type_with_default_sqlite() {
+    migration_change_type_with_default(
+        &mut common::sqlite_connection(),
+        "CREATE TABLE Foo__butane_tmp (/* butane:managed */id INTEGER NOT NULL PRIMARY KEY,bar INTEGER NOT NULL DEFAULT 2);INSERT INTO Foo__butane_tmp SELECT id, bar FROM Foo;DROP TABLE Foo;ALTER TABLE Foo__butane_tmp RENAME TO Foo;",
+        "CREATE TABLE Foo__butane_tmp (/* butane:managed */id INTEGER NOT NULL PRIMARY KEY,bar INTEGER NOT NULL DEFAULT 1);INSERT INTO Foo__butane_tmp SELECT id, bar FROM Foo;DROP TABLE Foo;ALTER TABLE Foo__butane_tmp RENAME TO Foo;",
+    );
 }
 
-fn migration_add_field(conn: &mut Connection, up_sql: &str, down_sql: &str) {
-    let init = quote! {
-        struct Foo {
-            id: i64,
-            bar: String,
-        }
+#[cfg(feature = "pg")]
+#[test]
+fn migration_change_type_with_default_pg() {
+    let (mut conn, _data) = common::pg_connection();
+    // The old default is dropped while the type changes
+    migration_change_type_with_default(
+        &mut conn,
+        "ALTER TABLE Foo ALTER COLUMN bar DROP DEFAULT;ALTER TABLE Foo ALTER COLUMN bar TYPE BIGINT USING bar::BIGINT;ALTER TABLE Foo ALTER COLUMN bar SET DEFAULT 2;",
+        "ALTER TABLE Foo ALTER COLUMN bar DROP DEFAULT;ALTER TABLE Foo ALTER COLUMN bar TYPE INTEGER USING bar::INTEGER;ALTER TABLE Foo ALTER COLUMN bar SET DEFAULT 1;",
+    );
+}
+
+#[cfg(feature = "sqlite")]
+#[test]
+fn migration_change_nullable_unique_sqlite() {
+    migration_change_nullable_unique(
+        &mut common::sqlite_connection(),
+        "CREATE TABLE Foo__butane_tmp (/* butane:managed */id INTEGER NOT NULL PRIMARY KEY,bar TEXT UNIQUE);INSERT INTO Foo__butane_tmp SELECT id, bar FROM Foo;DROP TABLE Foo;ALTER TABLE Foo__butane_tmp RENAME TO Foo;",
+        "CREATE TABLE Foo__butane_tmp (/* butane:managed */id INTEGER NOT NULL PRIMARY KEY,bar TEXT NOT NULL);INSERT INTO Foo__butane_tmp SELECT id, bar FROM Foo;DROP TABLE Foo;ALTER TABLE Foo__butane_tmp RENAME TO Foo;",
+    );
+}
+
+#[cfg(feature = "pg")]
+#[test]
+fn migration_change_nullable_unique_pg() {
+    let (mut conn, _data) = common::pg_connection();
+    migration_change_nullable_unique(
+        &mut conn,
+        "ALTER TABLE Foo ALTER COLUMN bar DROP NOT NULL;ALTER TABLE Foo ADD CONSTRAINT Foo_bar_key UNIQUE (bar);COMMENT ON CONSTRAINT Foo_bar_key ON Foo IS 'butane:managed';",
+        "ALTER TABLE Foo DROP CONSTRAINT Foo_bar_key;ALTER TABLE Foo ALTER COLUMN bar SET NOT NULL;",
+    );
+}
+
+#[cfg(feature = "sqlite")]
+#[test]
+fn migration_delete_table_sqlite() {
+    migration_delete_table(
+        &mut common::sqlite_connection(),
+        "DROP TABLE Foo;",
+        "CREATE TABLE Foo (/* butane:managed */id INTEGER NOT NULL PRIMARY KEY,bar TEXT NOT NULL);",
+    );
+}
+
+#[cfg(feature = "pg")]
+#[test]
+fn migration_delete_table_pg() {
+    let (mut conn, _data) = common::pg_connection();
+    migration_delete_table(
+        &mut conn,
+        "DROP TABLE Foo;",
+        "CREATE TABLE Foo (id BIGINT NOT NULL PRIMARY KEY,bar TEXT NOT NULL);COMMENT ON TABLE Foo IS 'butane:managed';",
+    );
+}
+
+#[cfg(feature = "sqlite")]
+#[test]
+fn migration_add_unique_together_sqlite() {
+    migration_add_unique_together(
+        &mut common::sqlite_connection(),
+        "CREATE TABLE Foo__butane_tmp (/* butane:managed */id INTEGER NOT NULL PRIMARY KEY,bar TEXT NOT NULL,baz INTEGER NOT NULL,UNIQUE (bar, baz));INSERT INTO Foo__butane_tmp SELECT id, bar, baz FROM Foo;DROP TABLE Foo;ALTER TABLE Foo__butane_tmp RENAME TO Foo;",
+        "CREATE TABLE Foo__butane_tmp (/* butane:managed */id INTEGER NOT NULL PRIMARY KEY,bar TEXT NOT NULL,baz INTEGER NOT NULL);INSERT INTO Foo__butane_tmp SELECT id, bar, baz FROM Foo;DROP TABLE Foo;ALTER TABLE Foo__butane_tmp RENAME TO Foo;",
+    );
+}
+
+#[cfg(feature = "pg")]
+#[test]
+fn migration_add_unique_together_pg() {
+    let (mut conn, _data) = common::pg_connection();
+    migration_add_unique_together(
+        &mut conn,
+        "ALTER TABLE Foo ADD CONSTRAINT Foo_bar_baz_key UNIQUE (bar, baz);COMMENT ON CONSTRAINT Foo_bar_baz_key ON Foo IS 'butane:managed';",
+        "ALTER TABLE Foo DROP CONSTRAINT Foo_bar_baz_key;",
+    );
+}
+
+#[cfg(feature = "sqlite")]
+#[test]
+fn migration_add_index_sqlite() {
+    migration_add_index(
+        &mut common::sqlite_connection(),
+        "CREATE INDEX Foo_bar_baz_idx ON Foo (/* butane:managed */bar, baz);CREATE INDEX Foo_bar_idx ON Foo (/* butane:managed */bar);",
+        "DROP INDEX Foo_bar_baz_idx;DROP INDEX Foo_bar_idx;",
+    );
+}
+
+#[cfg(feature = "pg")]
+#[test]
+fn migration_add_index_pg() {
+    let (mut conn, _data) = common::pg_connection();
+    migration_add_index(
+        &mut conn,
+        "CREATE INDEX Foo_bar_baz_idx ON Foo (bar, baz);COMMENT ON INDEX Foo_bar_baz_idx IS 'butane:managed';CREATE INDEX Foo_bar_idx ON Foo (bar);COMMENT ON INDEX Foo_bar_idx IS 'butane:managed';",
+        "DROP INDEX Foo_bar_baz_idx;DROP INDEX Foo_bar_idx;",
+    );
+}
+
+#[cfg(feature = "sqlite")]
+#[test]
+fn migration_add_partial_index_sqlite() {
+    migration_add_partial_index(
+        &mut common::sqlite_connection(),
+        "CREATE INDEX Foo_bar_partial_idx ON Foo (/* butane:managed */bar) WHERE NOT deleted;",
+        "DROP INDEX Foo_bar_partial_idx;",
+    );
+}
+
+#[cfg(feature = "pg")]
+#[test]
+fn migration_add_partial_index_pg() {
+    let (mut conn, _data) = common::pg_connection();
+    migration_add_partial_index(
+        &mut conn,
+        "CREATE INDEX Foo_bar_partial_idx ON Foo (bar) WHERE NOT deleted;COMMENT ON INDEX Foo_bar_partial_idx IS 'butane:managed';",
+        "DROP INDEX Foo_bar_partial_idx;",
+    );
+}
+
+#[cfg(feature = "sqlite")]
+#[test]
+fn migration_add_comments_sqlite() {
+    // SQLite cannot store comments
+    migration_add_comments(
+        &mut common::sqlite_connection(),
+        "ALTER TABLE Foo ADD COLUMN price INTEGER NOT NULL DEFAULT 0;",
+        "CREATE TABLE Foo__butane_tmp (/* butane:managed */id INTEGER NOT NULL PRIMARY KEY,name TEXT NOT NULL);INSERT INTO Foo__butane_tmp SELECT id, name FROM Foo;DROP TABLE Foo;ALTER TABLE Foo__butane_tmp RENAME TO Foo;",
+    );
+}
+
+#[cfg(feature = "pg")]
+#[test]
+fn migration_add_comments_pg() {
+    let (mut conn, _data) = common::pg_connection();
+    // The marker follows the table's comment on a line of its own
+    migration_add_comments(
+        &mut conn,
+        "COMMENT ON TABLE Foo IS 'Things for sale.butane:managed';ALTER TABLE Foo ADD COLUMN price INTEGER NOT NULL DEFAULT 0;COMMENT ON COLUMN Foo.price IS 'Price in cents.';COMMENT ON COLUMN Foo.name IS 'The shopper''s name for it.';",
+        "COMMENT ON TABLE Foo IS 'butane:managed';ALTER TABLE Foo DROP COLUMN price;COMMENT ON COLUMN Foo.name IS NULL;",
+    );
+}
+
+#[cfg(feature = "sqlite")]
+#[test]
+fn migration_rename_sqlite() {
+    migration_rename(
+        &mut common::sqlite_connection(),
+        "ALTER TABLE Foo RENAME COLUMN barBaz TO bar_baz;DROP INDEX Foo_barBaz_idx;CREATE INDEX Foo_bar_baz_idx ON Foo (/* butane:managed */bar_baz);ALTER TABLE Foo RENAME TO foo__butane_tmp;ALTER TABLE foo__butane_tmp RENAME TO foo;DROP INDEX Foo_bar_baz_idx;CREATE INDEX foo_bar_baz_idx ON foo (/* butane:managed */bar_baz);",
+        "ALTER TABLE foo RENAME TO Foo__butane_tmp;ALTER TABLE Foo__butane_tmp RENAME TO Foo;DROP INDEX foo_bar_baz_idx;CREATE INDEX Foo_bar_baz_idx ON Foo (/* butane:managed */bar_baz);ALTER TABLE Foo RENAME COLUMN bar_baz TO barBaz;DROP INDEX Foo_bar_baz_idx;CREATE INDEX Foo_barBaz_idx ON Foo (/* butane:managed */barBaz);",
+    );
+}
+
+#[cfg(feature = "pg")]
+#[test]
+fn migration_rename_pg() {
+    let (mut conn, _data) = common::pg_connection();
+    migration_rename(
+        &mut conn,
+        "ALTER TABLE Foo RENAME COLUMN barBaz TO bar_baz;ALTER INDEX Foo_barBaz_idx RENAME TO Foo_bar_baz_idx;ALTER TABLE Foo RENAME TO foo__butane_tmp;ALTER TABLE foo__butane_tmp RENAME TO foo;ALTER INDEX Foo_bar_baz_idx RENAME TO foo_bar_baz_idx__butane_tmp;ALTER INDEX foo_bar_baz_idx__butane_tmp RENAME TO foo_bar_baz_idx;",
+        "ALTER TABLE foo RENAME TO Foo__butane_tmp;ALTER TABLE Foo__butane_tmp RENAME TO Foo;ALTER INDEX foo_bar_baz_idx RENAME TO Foo_bar_baz_idx__butane_tmp;ALTER INDEX Foo_bar_baz_idx__butane_tmp RENAME TO Foo_bar_baz_idx;ALTER TABLE Foo RENAME COLUMN bar_baz TO barBaz;ALTER INDEX Foo_bar_baz_idx RENAME TO Foo_barBaz_idx;",
+    );
+}
+
+#[cfg(feature = "sqlite")]
+#[test]
+fn migration_set_schema_sqlite() {
+    // SQLite has no schemas, so only the index is added
+    migration_set_schema(
+        &mut common::sqlite_connection(),
+        "CREATE INDEX Foo_bar_idx ON Foo (/* butane:managed */bar);",
+        "DROP INDEX Foo_bar_idx;",
+    );
+}
+
+#[cfg(feature = "pg")]
+#[test]
+fn migration_set_schema_pg() {
+    let (mut conn, _data) = common::pg_connection();
+    migration_set_schema(
+        &mut conn,
+        "CREATE SCHEMA IF NOT EXISTS billing;ALTER TABLE Foo SET SCHEMA billing;CREATE INDEX Foo_bar_idx ON billing.Foo (bar);COMMENT ON INDEX billing.Foo_bar_idx IS 'butane:managed';",
+        "DO $$ BEGIN EXECUTE format('ALTER TABLE %s SET SCHEMA %I', 'billing.Foo', current_schema()); END $$;DROP INDEX Foo_bar_idx;",
+    );
+}
+
+#[cfg(feature = "sqlite")]
+#[test]
+fn migration_change_indexed_column_sqlite() {
+    let init = quote! {
+        struct Foo {
+            id: i64,
+            #[butane(index)]
+            bar: String,
+        }
+    };
+    let v2 = quote! {
+        struct Foo {
+            id: i64,
+            #[butane(index)]
+            bar: Option<String>,
+        }
+    };
+    // The index is dropped along with the old table, so is recreated
+    test_migrate(
+        &mut common::sqlite_connection(),
+        init,
+        v2,
+        "CREATE TABLE Foo__butane_tmp (/* butane:managed */id INTEGER NOT NULL PRIMARY KEY,bar TEXT );INSERT INTO Foo__butane_tmp SELECT id, bar FROM Foo;DROP TABLE Foo;ALTER TABLE Foo__butane_tmp RENAME TO Foo;CREATE INDEX Foo_bar_idx ON Foo (/* butane:managed */bar);",
+        "CREATE TABLE Foo__butane_tmp (/* butane:managed */id INTEGER NOT NULL PRIMARY KEY,bar TEXT NOT NULL);INSERT INTO Foo__butane_tmp SELECT id, bar FROM Foo;DROP TABLE Foo;ALTER TABLE Foo__butane_tmp RENAME TO Foo;CREATE INDEX Foo_bar_idx ON Foo (/* butane:managed */bar);",
+    );
+}
+
+#[cfg(feature = "sqlite")]
+#[test]
+fn migration_add_foreign_key_table_sqlite() {
+    migration_add_foreign_key_table(
+        &mut common::sqlite_connection(),
+        "CREATE TABLE Bar (/* butane:managed */id INTEGER NOT NULL PRIMARY KEY,foo INTEGER NOT NULL REFERENCES Foo ON DELETE CASCADE);",
+        "DROP TABLE Bar;",
+    );
+}
+
+#[cfg(feature = "pg")]
+#[test]
+fn migration_add_foreign_key_table_pg() {
+    let (mut conn, _data) = common::pg_connection();
+    migration_add_foreign_key_table(
+        &mut conn,
+        "CREATE TABLE Bar (id BIGINT NOT NULL PRIMARY KEY,foo BIGINT NOT NULL CONSTRAINT Bar_foo_fkey REFERENCES Foo ON DELETE CASCADE);COMMENT ON TABLE Bar IS 'butane:managed';COMMENT ON CONSTRAINT Bar_foo_fkey ON Bar IS 'butane:managed';",
+        "DROP TABLE Bar;",
+    );
+}
+
+#[cfg(feature = "sqlite")]
+#[test]
+fn migration_add_cyclic_foreign_keys_sqlite() {
+    migration_add_cyclic_foreign_keys(
+        &mut common::sqlite_connection(),
+        "CREATE TABLE Bar (/* butane:managed */id INTEGER NOT NULL PRIMARY KEY,baz INTEGER );CREATE TABLE Baz (/* butane:managed */id INTEGER NOT NULL PRIMARY KEY,bar INTEGER NOT NULL REFERENCES Bar ON DELETE CASCADE);CREATE TABLE Bar__butane_tmp (/* butane:managed */id INTEGER NOT NULL PRIMARY KEY,baz INTEGER REFERENCES Baz ON DELETE SET NULL);INSERT INTO Bar__butane_tmp SELECT id, baz FROM Bar;DROP TABLE Bar;ALTER TABLE Bar__butane_tmp RENAME TO Bar;",
+        "CREATE TABLE Bar__butane_tmp (/* butane:managed */id INTEGER NOT NULL PRIMARY KEY,baz INTEGER );INSERT INTO Bar__butane_tmp SELECT id, baz FROM Bar;DROP TABLE Bar;ALTER TABLE Bar__butane_tmp RENAME TO Bar;DROP TABLE Baz;DROP TABLE Bar;",
+    );
+}
+
+#[cfg(feature = "pg")]
+#[test]
+fn migration_add_cyclic_foreign_keys_pg() {
+    let (mut conn, _data) = common::pg_connection();
+    migration_add_cyclic_foreign_keys(
+        &mut conn,
+        "CREATE TABLE Bar (id BIGINT NOT NULL PRIMARY KEY,baz BIGINT );COMMENT ON TABLE Bar IS 'butane:managed';CREATE TABLE Baz (id BIGINT NOT NULL PRIMARY KEY,bar BIGINT NOT NULL CONSTRAINT Baz_bar_fkey REFERENCES Bar ON DELETE CASCADE);COMMENT ON TABLE Baz IS 'butane:managed';COMMENT ON CONSTRAINT Baz_bar_fkey ON Baz IS 'butane:managed';ALTER TABLE Bar ADD CONSTRAINT Bar_baz_fkey FOREIGN KEY (baz) REFERENCES Baz ON DELETE SET NULL;COMMENT ON CONSTRAINT Bar_baz_fkey ON Bar IS 'butane:managed';",
+        "ALTER TABLE Bar DROP CONSTRAINT Bar_baz_fkey;DROP TABLE Baz;DROP TABLE Bar;",
+    );
+}
+
+#[cfg(feature = "sqlite")]
+#[test]
+fn migration_add_check_sqlite() {
+    migration_add_check(
+        &mut common::sqlite_connection(),
+        "CREATE TABLE Foo__butane_tmp (/* butane:managed */id INTEGER NOT NULL PRIMARY KEY,price INTEGER NOT NULL,CONSTRAINT Foo_check_1 CHECK (price > 0));INSERT INTO Foo__butane_tmp SELECT id, price FROM Foo;DROP TABLE Foo;ALTER TABLE Foo__butane_tmp RENAME TO Foo;",
+        "CREATE TABLE Foo__butane_tmp (/* butane:managed */id INTEGER NOT NULL PRIMARY KEY,price INTEGER NOT NULL);INSERT INTO Foo__butane_tmp SELECT id, price FROM Foo;DROP TABLE Foo;ALTER TABLE Foo__butane_tmp RENAME TO Foo;",
+    );
+}
+
+#[cfg(feature = "pg")]
+#[test]
+fn migration_add_check_pg() {
+    let (mut conn, _data) = common::pg_connection();
+    migration_add_check(
+        &mut conn,
+        "ALTER TABLE Foo ADD CONSTRAINT Foo_check_1 CHECK (price > 0);COMMENT ON CONSTRAINT Foo_check_1 ON Foo IS 'butane:managed';",
+        "ALTER TABLE Foo DROP CONSTRAINT Foo_check_1;",
+    );
+}
+
+#[cfg(feature = "sqlite")]
+#[test]
+fn migration_views_sqlite() {
+    migration_views(
+        &mut common::sqlite_connection(),
+        "DROP VIEW Cheap;CREATE TABLE Foo__butane_tmp (/* butane:managed */id INTEGER NOT NULL PRIMARY KEY,price INTEGER NOT NULL,CONSTRAINT Foo_check_1 CHECK (price > 0));INSERT INTO Foo__butane_tmp SELECT id, price FROM Foo;DROP TABLE Foo;ALTER TABLE Foo__butane_tmp RENAME TO Foo;CREATE VIEW Cheap AS SELECT id FROM Foo WHERE price < 10;DROP VIEW Cheap;CREATE VIEW Cheap AS SELECT id, price FROM Foo WHERE price < 10;CREATE VIEW Pricey AS SELECT id FROM Foo WHERE price > 100;",
+        "DROP VIEW Pricey;DROP VIEW Cheap;CREATE TABLE Foo__butane_tmp (/* butane:managed */id INTEGER NOT NULL PRIMARY KEY,price INTEGER NOT NULL);INSERT INTO Foo__butane_tmp SELECT id, price FROM Foo;DROP TABLE Foo;ALTER TABLE Foo__butane_tmp RENAME TO Foo;CREATE VIEW Cheap AS SELECT id, price FROM Foo WHERE price < 10;DROP VIEW Cheap;CREATE VIEW Cheap AS SELECT id FROM Foo WHERE price < 10;",
+    );
+}
+
+#[cfg(feature = "pg")]
+#[test]
+fn migration_views_pg() {
+    let (mut conn, _data) = common::pg_connection();
+    migration_views(
+        &mut conn,
+        "ALTER TABLE Foo ADD CONSTRAINT Foo_check_1 CHECK (price > 0);COMMENT ON CONSTRAINT Foo_check_1 ON Foo IS 'butane:managed';DROP VIEW Cheap;CREATE VIEW Cheap AS SELECT id, price FROM Foo WHERE price < 10;CREATE VIEW Pricey AS SELECT id FROM Foo WHERE price > 100;",
+        "DROP VIEW Pricey;ALTER TABLE Foo DROP CONSTRAINT Foo_check_1;DROP VIEW Cheap;CREATE VIEW Cheap AS SELECT id FROM Foo WHERE price < 10;",
+    );
+}
+
+fn migration_views(conn: &mut Connection, up_sql: &str, down_sql: &str) {
+    use butane::db::BackendRows;
+
+    let mut ms = MemMigrations::new();
+    let backend = conn.backend();
+    model_with_migrations(
+        quote! {
+            struct Foo {
+                id: i64,
+                price: i32,
+            }
+        },
+        &mut ms,
+    );
+    view_with_migrations(
+        quote! { Cheap, "SELECT id FROM Foo WHERE price < 10" },
+        &mut ms,
+    );
+    assert!(ms.create_migration(&backend, "init", None).unwrap());
+
+    model_with_migrations(
+        quote! {
+            #[butane(check = "price > 0")]
+            struct Foo {
+                id: i64,
+                price: i32,
+            }
+        },
+        &mut ms,
+    );
+    view_with_migrations(
+        quote! { Cheap, "SELECT id, price FROM Foo WHERE price < 10" },
+        &mut ms,
+    );
+    view_with_migrations(
+        quote! { Pricey, "SELECT id FROM Foo WHERE price > 100" },
+        &mut ms,
+    );
+    assert!(ms
+        .create_migration(&backend, "v2", ms.latest().as_ref())
+        .unwrap());
+    assert!(!ms
+        .create_migration(&backend, "v3", ms.latest().as_ref())
+        .unwrap());
+
+    let mut to_apply = ms.unapplied_migrations(conn).unwrap();
+    for m in &to_apply {
+        m.apply(conn).unwrap();
+    }
+    verify_sql(conn, &ms, up_sql, down_sql);
+    conn.execute("INSERT INTO Foo (id, price) VALUES (1, 5), (2, 500);")
+        .unwrap();
+    let columns = [
+        butane::db::Column::new("id", SqlType::BigInt),
+        butane::db::Column::new("price", SqlType::Int),
+    ];
+    let mut rows = conn
+        .query("Cheap", &columns, None, None, None, None)
+        .unwrap();
+    let id = rows
+        .next()
+        .unwrap()
+        .unwrap()
+        .get(0, SqlType::BigInt)
+        .unwrap();
+    assert_eq!(SqlVal::from(id), SqlVal::BigInt(1));
+    assert!(rows.next().unwrap().is_none());
+    drop(rows);
+
+    to_apply.reverse();
+    for m in to_apply {
+        m.downgrade(conn).unwrap();
+    }
+}
+
+#[cfg(feature = "sqlite")]
+#[test]
+fn migration_enums_sqlite() {
+    migration_enums(
+        &mut common::sqlite_connection(),
+        "CREATE TABLE Price (/* butane:managed */id INTEGER NOT NULL PRIMARY KEY,currency TEXT NOT NULL);CREATE TABLE IF NOT EXISTS butane_migrations (/* butane:managed */name TEXT NOT NULL PRIMARY KEY);",
+        "",
+    );
+}
+
+#[cfg(feature = "pg")]
+#[test]
+fn migration_enums_pg() {
+    let (mut conn, _data) = common::pg_connection();
+    migration_enums(
+        &mut conn,
+        "CREATE TYPE Currency AS ENUM ('Dollars', 'Pounds');CREATE TABLE Price (id BIGINT NOT NULL PRIMARY KEY,currency Currency NOT NULL);COMMENT ON TABLE Price IS 'butane:managed';CREATE TABLE IF NOT EXISTS butane_migrations (name TEXT NOT NULL PRIMARY KEY);COMMENT ON TABLE butane_migrations IS 'butane:managed';",
+        "ALTER TYPE Currency ADD VALUE 'Euros';",
+    );
+}
+
+fn migration_enums(conn: &mut Connection, init_sql: &str, up_sql: &str) {
+    let mut ms = MemMigrations::new();
+    let backend = conn.backend();
+    let price = quote! {
+        struct Price {
+            id: i64,
+            currency: Currency,
+        }
+    };
+    butane_type_with_migrations(
+        quote! { Enum },
+        quote! {
+            enum Currency {
+                Dollars,
+                Pounds,
+            }
+        },
+        &mut ms,
+    );
+    model_with_migrations(price.clone(), &mut ms);
+    assert!(ms.create_migration(&backend, "init", None).unwrap());
+    let init = ms.latest().unwrap();
+    let strip = |s: String| s.replace("\n", "");
+    assert_eq!(
+        strip(init.up_sql(backend.name()).unwrap().unwrap()),
+        init_sql
+    );
+
+    butane_type_with_migrations(
+        quote! { Enum },
+        quote! {
+            enum Currency {
+                Dollars,
+                Pounds,
+                Euros,
+            }
+        },
+        &mut ms,
+    );
+    model_with_migrations(price, &mut ms);
+    assert!(ms
+        .create_migration(&backend, "v2", ms.latest().as_ref())
+        .unwrap());
+    // Values cannot be removed from an enum
+    verify_sql(conn, &ms, up_sql, "");
+
+    let mut to_apply = ms.unapplied_migrations(conn).unwrap();
+    for m in &to_apply {
+        m.apply(conn).unwrap();
+    }
+    conn.execute("INSERT INTO Price (id, currency) VALUES (1, 'Euros');")
+        .unwrap();
+    let columns = [butane::db::Column::new("currency", SqlType::Text)];
+    let mut rows = conn
+        .query("Price", &columns, None, None, None, None)
+        .unwrap();
+    let currency = rows.next().unwrap().unwrap().get(0, SqlType::Text).unwrap();
+    assert_eq!(SqlVal::from(currency), SqlVal::Text("Euros".to_string()));
+    drop(rows);
+
+    to_apply.reverse();
+    for m in to_apply {
+        m.downgrade(conn).unwrap();
+    }
+}
+
+#[cfg(feature = "sqlite")]
+#[test]
+fn idempotent_migration_sqlite() {
+    let v2 = quote! {
+        struct Bar {
+            id: i64,
+        }
+    };
+    idempotent_migration(
+        &mut common::sqlite_connection(),
+        v2,
+        "CREATE TABLE IF NOT EXISTS Bar (/* butane:managed */id INTEGER NOT NULL PRIMARY KEY);",
+        "DROP TABLE IF EXISTS Bar;",
+    );
+}
+
+#[cfg(feature = "pg")]
+#[test]
+fn idempotent_migration_pg() {
+    let (mut conn, _data) = common::pg_connection();
+    let v2 = quote! {
+        #[butane(check = "baz > 0")]
+        struct Foo {
+            id: i64,
+            baz: i64,
+        }
+    };
+    idempotent_migration(
+        &mut conn,
+        v2,
+        "ALTER TABLE Foo ADD COLUMN IF NOT EXISTS baz BIGINT NOT NULL DEFAULT 0;ALTER TABLE Foo DROP CONSTRAINT IF EXISTS Foo_check_1;ALTER TABLE Foo ADD CONSTRAINT Foo_check_1 CHECK (baz > 0);COMMENT ON CONSTRAINT Foo_check_1 ON Foo IS 'butane:managed';",
+        "ALTER TABLE Foo DROP CONSTRAINT IF EXISTS Foo_check_1;ALTER TABLE Foo DROP COLUMN IF EXISTS baz;",
+    );
+}
+
+#[cfg(feature = "pg")]
+#[test]
+fn idempotent_migration_old_postgres() {
+    use butane::db::{pg::PgBackend, Backend, Capabilities};
+    let mut ms = MemMigrations::new();
+    model_with_migrations(
+        quote! {
+            struct Foo {
+                id: i64,
+            }
+        },
+        &mut ms,
+    );
+    let v1 = ms.current().db().unwrap();
+    model_with_migrations(
+        quote! {
+            struct Foo {
+                id: i64,
+                baz: i64,
+            }
+        },
+        &mut ms,
+    );
+    let ops = adb::diff(&v1, &ms.current().db().unwrap());
+    // Postgres 9.5 has no ADD COLUMN IF NOT EXISTS
+    let backend = PgBackend::new().with_capabilities(Some(Capabilities::pg(90_500)));
+    assert_eq!(
+        backend
+            .create_idempotent_migration_sql(&v1, ops.clone())
+            .unwrap(),
+        "DO $$ BEGIN ALTER TABLE Foo ADD COLUMN baz BIGINT NOT NULL DEFAULT 0; EXCEPTION WHEN duplicate_column THEN NULL; END $$;"
+    );
+    assert_eq!(
+        PgBackend::new()
+            .create_idempotent_migration_sql(&v1, ops.clone())
+            .unwrap(),
+        "ALTER TABLE Foo ADD COLUMN IF NOT EXISTS baz BIGINT NOT NULL DEFAULT 0;"
+    );
+    // Only idempotent migrations guard against existing columns
+    assert_eq!(
+        backend.create_migration_sql(&v1, ops).unwrap(),
+        "ALTER TABLE Foo ADD COLUMN baz BIGINT NOT NULL DEFAULT 0;"
+    );
+}
+
+/// Creates an idempotent migration to `v2` and checks that its SQL
+/// may be applied more than once.
+fn idempotent_migration(
+    conn: &mut Connection,
+    v2: TokenStream,
+    expected_up_sql: &str,
+    expected_down_sql: &str,
+) {
+    let init = quote! {
+        struct Foo {
+            id: i64,
+        }
+    };
+    let mut ms = MemMigrations::new();
+    let backend = conn.backend();
+    model_with_migrations(init, &mut ms);
+    assert!(ms.create_migration(&backend, "init", None).unwrap());
+    model_with_migrations(v2, &mut ms);
+    assert!(ms
+        .create_idempotent_migration(&backend, "v2", ms.latest().as_ref())
+        .unwrap());
+    verify_sql(conn, &ms, expected_up_sql, expected_down_sql);
+
+    let v2 = ms.latest().unwrap();
+    for m in ms.unapplied_migrations(conn).unwrap() {
+        m.apply(conn).unwrap();
+    }
+    // As if interrupted after the migration's SQL ran, but before it
+    // was recorded as applied.
+    conn.execute(v2.up_sql(backend.name()).unwrap().unwrap())
+        .unwrap();
+    v2.downgrade(conn).unwrap();
+    conn.execute(v2.down_sql(backend.name()).unwrap().unwrap())
+        .unwrap();
+}
+
+#[cfg(feature = "sqlite")]
+#[test]
+fn introspect_sqlite() {
+    let db = introspect(&mut common::sqlite_connection());
+    let id = db.get_table("foo").unwrap().column("id").unwrap();
+    assert_eq!(id.typeid().unwrap(), TypeIdentifier::Ty(SqlType::BigInt));
+}
+
+#[cfg(feature = "pg")]
+#[test]
+fn introspect_pg() {
+    let (mut conn, _data) = common::pg_connection();
+    let db = introspect(&mut conn);
+    let id = db.get_table("foo").unwrap().column("id").unwrap();
+    assert_eq!(id.typeid().unwrap(), TypeIdentifier::Ty(SqlType::Int));
+    assert!(id.is_auto());
+}
+
+/// Applies migrations for some models and reads the resulting schema
+/// back from the database.
+fn introspect(conn: &mut Connection) -> adb::ADB {
+    let foo_tokens = quote! {
+        #[table = "foo"]
+        struct Foo {
+            #[auto]
+            id: i32,
+            #[butane(unique)]
+            name: String,
+            note: Option<String>,
+        }
+    };
+    let bar_tokens = quote! {
+        #[table = "bar"]
+        #[butane(unique_together("x", "y"))]
+        struct Bar {
+            id: i64,
+            #[butane(foreign_key(on_delete = "cascade"))]
+            foo: ForeignKey<Foo>,
+            x: i64,
+            y: i64,
+        }
+    };
+    let mut ms = MemMigrations::new();
+    let backend = conn.backend();
+    model_with_migrations(foo_tokens, &mut ms);
+    model_with_migrations(bar_tokens, &mut ms);
+    assert!(ms.create_migration(&backend, "init", None).unwrap());
+    for m in ms.unapplied_migrations(conn).unwrap() {
+        m.apply(conn).unwrap();
+    }
+
+    let db = conn.introspect().unwrap();
+    assert!(db.get_table("butane_migrations").is_some());
+    let foo_table = db.get_table("foo").unwrap();
+    assert!(foo_table.column("id").unwrap().is_pk());
+    assert!(foo_table.column("name").unwrap().unique());
+    assert!(!foo_table.column("name").unwrap().nullable());
+    assert!(foo_table.column("note").unwrap().nullable());
+    assert_eq!(
+        foo_table.column("note").unwrap().typeid().unwrap(),
+        TypeIdentifier::Ty(SqlType::Text)
+    );
+    let bar_table = db.get_table("bar").unwrap();
+    assert_eq!(bar_table.unique_constraints.len(), 1);
+    assert_eq!(bar_table.unique_constraints[0].columns(), ["x", "y"]);
+    let fk = bar_table.column("foo").unwrap().reference().unwrap();
+    assert_eq!(fk.table(), "foo");
+    assert_eq!(fk.column(), None);
+    assert_eq!(fk.on_delete(), ReferentialAction::Cascade);
+    assert_eq!(fk.on_update(), ReferentialAction::NoAction);
+
+    // Identical databases have no differences
+    assert!(adb::diff(&db, &conn.introspect().unwrap()).is_empty());
+    db
+}
+
+#[cfg(feature = "sqlite")]
+#[test]
+fn check_drift_sqlite() {
+    check_drift(&mut common::sqlite_connection());
+}
+
+#[cfg(feature = "pg")]
+#[test]
+fn check_drift_pg() {
+    let (mut conn, _data) = common::pg_connection();
+    check_drift(&mut conn);
+}
+
+fn check_drift(conn: &mut Connection) {
+    use butane::db::TableObject;
+
+    let foo_tokens = quote! {
+        #[butane(check = "count >= 0")]
+        struct Foo {
+            #[auto]
+            id: i32,
+            #[butane(unique)]
+            name: String,
+            flag: bool,
+            #[default = 0]
+            count: i32,
+            created: NaiveDateTime,
+        }
+    };
+    let bar_tokens = quote! {
+        #[butane(unique_together("x", "y"))]
+        #[butane(index("y", predicate = "x > 0"))]
+        struct Bar {
+            id: i64,
+            #[butane(foreign_key(on_delete = "cascade"))]
+            foo: Option<ForeignKey<Foo>>,
+            #[butane(index)]
+            x: i64,
+            y: Option<String>,
+        }
+    };
+    let mut ms = MemMigrations::new();
+    let backend = conn.backend();
+    // Nothing applied to nothing
+    assert!(ms.check_drift(conn).unwrap().is_empty());
+    model_with_migrations(foo_tokens, &mut ms);
+    model_with_migrations(bar_tokens, &mut ms);
+    assert!(ms.create_migration(&backend, "init", None).unwrap());
+    for m in ms.unapplied_migrations(conn).unwrap() {
+        m.apply(conn).unwrap();
+    }
+    let drift = ms.check_drift(conn).unwrap();
+    assert!(drift.is_empty(), "unexpected drift {:?}", drift);
+
+    conn.execute("ALTER TABLE Bar ADD COLUMN z INTEGER;")
+        .unwrap();
+    let drift = ms.check_drift(conn).unwrap();
+    assert_eq!(drift.len(), 1);
+    assert!(
+        matches!(&drift[0], adb::Operation::AddColumn(_, col) if col.name() == "z" && col.nullable())
+    );
+
+    // Tables created by other means are not butane's, so are not drift
+    conn.execute("CREATE TABLE Unmanaged (id INTEGER);")
+        .unwrap();
+    assert_eq!(ms.check_drift(conn).unwrap().len(), 1);
+    let mut managed: Vec<String> = conn
+        .managed_tables()
+        .unwrap()
+        .into_iter()
+        .map(|t| t.to_lowercase())
+        .collect();
+    managed.sort();
+    assert_eq!(managed, ["bar", "butane_migrations", "foo"]);
+
+    // Nor are indexes created by other means
+    conn.execute("CREATE INDEX Bar_manual_idx ON Bar (y, x);")
+        .unwrap();
+    assert_eq!(ms.check_drift(conn).unwrap().len(), 1);
+    let unmanaged = conn.unmanaged_objects().unwrap();
+    assert_eq!(unmanaged.len(), 1, "unexpected {:?}", unmanaged);
+    assert!(matches!(
+        &unmanaged[0].1,
+        TableObject::Index(index) if index.columns() == ["y", "x"]
+    ));
+
+    // But butane's indexes are
+    conn.execute("DROP INDEX Bar_x_idx;").unwrap();
+    let drift = ms.check_drift(conn).unwrap();
+    assert_eq!(drift.len(), 2);
+    assert!(drift.iter().any(|op| matches!(
+        op,
+        adb::Operation::RemoveIndex(_, index) if index.columns() == ["x"]
+    )));
+}
+
+#[cfg(feature = "sqlite")]
+#[test]
+fn merge_migrations_sqlite() {
+    merge_migrations(&mut common::sqlite_connection());
+}
+
+#[cfg(feature = "pg")]
+#[test]
+fn merge_migrations_pg() {
+    let (mut conn, _data) = common::pg_connection();
+    merge_migrations(&mut conn);
+}
+
+fn merge_migrations(conn: &mut Connection) {
+    let init = quote! {
+        struct Foo {
+            id: i64,
+            bar: String,
+        }
+    };
+    let bar = quote! {
+        struct Bar {
+            id: i64,
+        }
+    };
+    let foo_v2 = quote! {
+        struct Foo {
+            id: i64,
+            bar: String,
+            baz: i64,
+        }
+    };
+
+    let mut ms = MemMigrations::new();
+    let backend = conn.backend();
+    model_with_migrations(init, &mut ms);
+    assert!(ms.create_migration(&backend, "init", None).unwrap());
+    let init = ms.latest().unwrap();
+
+    // Two independent changes from init
+    model_with_migrations(bar, &mut ms);
+    assert!(ms.create_migration(&backend, "a", Some(&init)).unwrap());
+    ms.current().delete_table("Bar").unwrap();
+    model_with_migrations(foo_v2, &mut ms);
+    assert!(ms.create_migration(&backend, "b", Some(&init)).unwrap());
+    let a = ms.get_migration("a").unwrap();
+    let b = ms.get_migration("b").unwrap();
+    assert_eq!(ms.latest().unwrap().name(), "a");
+    // The database is on the branch of a
+    init.apply(conn).unwrap();
+    a.apply(conn).unwrap();
+
+    ms.create_merge_migration(&backend, "merge", &a, &[b])
+        .unwrap();
+    let merge = ms.latest().unwrap();
+    assert_eq!(merge.name(), "merge");
+    assert_eq!(merge.migration_from().unwrap().unwrap(), "a");
+    assert_eq!(merge.merged_from().unwrap(), ["b"]);
+    let db = merge.db().unwrap();
+    assert!(db.get_table("Bar").is_some());
+    assert!(db.get_table("Foo").unwrap().column("baz").is_some());
+    assert_eq!(merge.up_sql(backend.name()).unwrap().unwrap(), "");
+    assert_eq!(merge.down_sql(backend.name()).unwrap().unwrap(), "");
+
+    let names = |ms: Vec<MemMigration>| -> Vec<String> {
+        ms.iter().map(|m| m.name().to_string()).collect()
+    };
+    assert_eq!(
+        names(ms.all_migrations().unwrap()),
+        ["init", "a", "b", "merge"]
+    );
+    assert!(ms.divergence(conn).unwrap().is_empty());
+    let to_apply = ms.unapplied_migrations(conn).unwrap();
+    assert_eq!(names(to_apply.clone()), ["b", "merge"]);
+    for m in &to_apply {
+        m.apply(conn).unwrap();
+    }
+    assert!(ms.unapplied_migrations(conn).unwrap().is_empty());
+    assert_eq!(
+        ms.last_applied_migration(conn).unwrap().unwrap().name(),
+        "merge"
+    );
+    conn.execute("INSERT INTO Foo (id, bar, baz) VALUES (1, 'x', 2);")
+        .unwrap();
+    conn.execute("INSERT INTO Bar (id) VALUES (1);").unwrap();
+}
+
+#[cfg(all(feature = "sqlite", feature = "pg"))]
+#[test]
+fn sql_for_backend() {
+    let mut ms = MemMigrations::new();
+    model_with_migrations(
+        quote! {
+            struct Foo {
+                id: i64,
+            }
+        },
+        &mut ms,
+    );
+    let sqlite = butane::db::get_backend("sqlite").unwrap();
+    assert!(ms.create_migration(&sqlite, "init", None).unwrap());
+    let m = ms.latest().unwrap();
+    assert_eq!(
+        m.sql_for_backend(sqlite.as_ref())
+            .unwrap()
+            .replace("\n", ""),
+        "CREATE TABLE Foo (/* butane:managed */id INTEGER NOT NULL PRIMARY KEY);\
+         CREATE TABLE IF NOT EXISTS butane_migrations (/* butane:managed */name TEXT NOT NULL PRIMARY KEY);"
+    );
+    assert_eq!(
+        m.down_sql_for_backend(sqlite.as_ref()).unwrap(),
+        "DROP TABLE Foo;"
+    );
+    // The migration was only created for SQLite
+    let pg = butane::db::get_backend("pg").unwrap();
+    assert!(matches!(
+        m.sql_for_backend(pg.as_ref()),
+        Err(butane::Error::UnknownBackend(name)) if name == "pg"
+    ));
+}
+
+#[cfg(all(feature = "sqlite", feature = "datetime"))]
+#[test]
+fn convert_sqlite_timestamps() {
+    use butane::db::sqlite::{convert_timestamps_sql, SQLiteBackend};
+    use butane::db::{Backend, BackendConnection, Column, TimestampFormat};
+    use butane::SqlValRef;
+
+    let mut ms = MemMigrations::new();
+    model_with_migrations(
+        quote! {
+            struct Event {
+                id: i64,
+                at: NaiveDateTime,
+                note: Option<String>,
+            }
+        },
+        &mut ms,
+    );
+    let db = ms.current().db().unwrap();
+    let path = std::env::temp_dir().join(format!("butane_timestamps_{}.db", std::process::id()));
+    let path = path.to_str().unwrap();
+    let _ = std::fs::remove_file(path);
+    let backend = SQLiteBackend::new();
+    let mut conn = backend.connect(path).unwrap();
+    assert!(ms.create_migration(&backend, "init", None).unwrap());
+    ms.latest().unwrap().apply(&mut conn).unwrap();
+
+    let at = chrono::NaiveDate::from_ymd(2023, 1, 31).and_hms(12, 34, 56);
+    let columns = [
+        Column::new("id", SqlType::BigInt),
+        Column::new("at", SqlType::Timestamp),
+    ];
+    conn.insert_only(
+        "Event",
+        &columns,
+        &[SqlValRef::BigInt(1), SqlValRef::Timestamp(at)],
+    )
+    .unwrap();
+
+    let formats = [
+        TimestampFormat::Text,
+        TimestampFormat::Rfc3339,
+        TimestampFormat::UnixSeconds,
+        TimestampFormat::UnixMillis,
+        TimestampFormat::Text,
+    ];
+    for pair in formats.windows(2) {
+        conn.execute(&convert_timestamps_sql(&db, pair[0], pair[1]))
+            .unwrap();
+        let backend = SQLiteBackend::new().with_timestamp_format(pair[1]);
+        conn = backend.connect(path).unwrap();
+        // The column is declared as it would be with the format
+        let typeid = |db: &adb::ADB| {
+            db.get_table("Event")
+                .and_then(|table| table.column("at"))
+                .map(|col| col.typeid().unwrap())
+        };
+        assert_eq!(
+            typeid(&backend.as_introspected(&db)),
+            typeid(&conn.introspect().unwrap())
+        );
+        let mut rows = conn
+            .query("Event", &columns[1..], None, None, None, None)
+            .unwrap();
+        let row = rows.next().unwrap().unwrap();
+        assert_eq!(
+            SqlVal::from(row.get(0, SqlType::Timestamp).unwrap()),
+            SqlVal::Timestamp(at),
+            "{:?}",
+            pair
+        );
+    }
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn embed_migrations_macro() {
+    // This crate has no migrations other than current
+    let ms = butane::embed_migrations!().unwrap();
+    assert!(ms.latest().is_none());
+}
+
+#[cfg(feature = "sqlite")]
+#[test]
+fn embed_migrations_from_dir() {
+    let dir = std::env::temp_dir().join(format!("butane_embed_test_{}", std::process::id()));
+    let mut ms = butane::migrations::from_root(&dir);
+    model_with_migrations(
+        quote! {
+            struct Foo {
+                id: i64,
+            }
+        },
+        &mut ms,
+    );
+    let backend = butane::db::get_backend("sqlite").unwrap();
+    assert!(ms.create_migration(&backend, "init", None).unwrap());
+
+    let tokens = embed_migrations(&dir, None).to_string();
+    std::fs::remove_dir_all(&dir).unwrap();
+    assert!(tokens.contains("MemMigrations :: from_json"));
+    // Depends on the migration files so that it is rebuilt when they change
+    assert!(tokens.contains("include_bytes"));
+    assert!(tokens.contains("state.json"));
+    assert!(tokens.contains("Foo.table"));
+    assert!(!tokens.contains("current/"));
+}
+
+#[cfg(feature = "sqlite")]
+#[test]
+fn copy_migration_filtered_sqlite() {
+    copy_migration_filtered(&mut common::sqlite_connection());
+}
+
+#[cfg(feature = "pg")]
+#[test]
+fn copy_migration_filtered_pg() {
+    let (mut conn, _data) = common::pg_connection();
+    copy_migration_filtered(&mut conn);
+}
+
+fn copy_migration_filtered(conn: &mut Connection) {
+    let mut ms = MemMigrations::new();
+    let backend = conn.backend();
+    model_with_migrations(
+        quote! {
+            struct Foo {
+                id: i64,
+            }
+        },
+        &mut ms,
+    );
+    model_with_migrations(
+        quote! {
+            struct Unrelated {
+                id: i64,
+            }
+        },
+        &mut ms,
+    );
+    assert!(ms.create_migration(&backend, "init", None).unwrap());
+    model_with_migrations(
+        quote! {
+            struct Bar {
+                id: i64,
+                #[butane(foreign_key)]
+                foo: ForeignKey<Foo>,
+                tags: Many<Foo>,
+            }
+        },
+        &mut ms,
+    );
+    assert!(ms
+        .create_migration(&backend, "v2", ms.latest().as_ref())
+        .unwrap());
+    model_with_migrations(
+        quote! {
+            struct Unrelated {
+                id: i64,
+                name: String,
+            }
+        },
+        &mut ms,
+    );
+    assert!(ms
+        .create_migration(&backend, "v3", ms.latest().as_ref())
+        .unwrap());
+
+    let filter = TableFilter::new(vec!["Bar"]);
+    assert!(filter.matches("Bar_tags_Many"));
+    assert!(!filter.matches("Barn"));
+    let mut filtered = MemMigrations::new();
+    let mut prev = None;
+    for m in ms.all_migrations().unwrap() {
+        let mut new_m = filtered.new_migration(&m.name());
+        butane::migrations::copy_migration_filtered(&m, prev.as_ref(), &mut new_m, &filter)
+            .unwrap();
+        filtered.add_migration(new_m).unwrap();
+        prev = Some(m);
+    }
+    let names: Vec<String> = filtered
+        .all_migrations()
+        .unwrap()
+        .iter()
+        .map(|m| m.name().to_string())
+        .collect();
+    assert_eq!(names, vec!["init", "v2", "v3"]);
+    let db = filtered.latest().unwrap().db().unwrap();
+    let mut tables: Vec<&str> = db.tables().map(|t| t.name.as_str()).collect();
+    tables.sort_unstable();
+    // Foo is referenced by Bar, so is kept
+    assert_eq!(tables, vec!["Bar", "Bar_tags_Many", "Foo"]);
+
+    for m in filtered.unapplied_migrations(conn).unwrap() {
+        m.apply(conn).unwrap();
+    }
+    assert!(conn.has_table("Bar").unwrap());
+    assert!(conn.has_table("Bar_tags_Many").unwrap());
+    assert!(!conn.has_table("Unrelated").unwrap());
+    assert_eq!(
+        filtered
+            .latest()
+            .unwrap()
+            .up_sql(backend.name())
+            .unwrap()
+            .unwrap(),
+        ""
+    );
+    // The same migrations are recorded as applied
+    assert!(ms.unapplied_migrations(conn).unwrap().is_empty());
+}
+
+#[cfg(feature = "sqlite")]
+#[test]
+fn round_trip_sqlite() {
+    round_trip(common::sqlite_connection(), common::sqlite_connection());
+}
+
+#[cfg(feature = "pg")]
+#[test]
+fn round_trip_pg() {
+    let (conn, _data) = common::pg_connection();
+    let (broken_conn, _broken_data) = common::pg_connection();
+    round_trip(conn, broken_conn);
+}
+
+fn round_trip(mut conn: Connection, mut broken_conn: Connection) {
+    let mut ms = MemMigrations::new();
+    let backend = conn.backend();
+    model_with_migrations(
+        quote! {
+            struct Foo {
+                id: i64,
+                bar: String,
+            }
+        },
+        &mut ms,
+    );
+    assert!(ms.create_migration(&backend, "init", None).unwrap());
+    model_with_migrations(
+        quote! {
+            #[butane(unique_together("bar", "baz"))]
+            struct Foo {
+                id: i64,
+                #[butane(length = 32)]
+                bar: String,
+                baz: Option<i32>,
+                #[butane(precision = 8, scale = 2)]
+                price: f64,
+            }
+        },
+        &mut ms,
+    );
+    assert!(ms
+        .create_migration(&backend, "v2", ms.latest().as_ref())
+        .unwrap());
+    let expected = ms.latest().unwrap().db().unwrap();
+    testing::round_trip(&ms, &mut conn, &expected).unwrap();
+    assert!(ms.unapplied_migrations(&conn).unwrap().is_empty());
+
+    // A migration which does not undo itself is caught
+    let mut broken = MemMigrations::new();
+    for m in ms.all_migrations().unwrap() {
+        let mut new_m = broken.new_migration(&m.name());
+        copy_migration(&m, &mut new_m).unwrap();
+        if m.name() == "init" {
+            let up_sql = m.up_sql(backend.name()).unwrap().unwrap();
+            new_m.add_sql(backend.name(), &up_sql, "").unwrap();
+        }
+        broken.add_migration(new_m).unwrap();
+    }
+    match testing::round_trip(&broken, &mut broken_conn, &expected) {
+        Err(butane::Error::SchemaMismatch { stage, ops }) => {
+            assert_eq!(stage, "rolling back migrations");
+            assert!(!ops.is_empty());
+        }
+        other => panic!("expected a schema mismatch, got {:?}", other),
+    }
+}
+
+#[cfg(feature = "sqlite")]
+#[test]
+fn custom_state_table_sqlite() {
+    custom_state_table(&mut common::sqlite_connection());
+}
+
+#[cfg(feature = "pg")]
+#[test]
+fn custom_state_table_pg() {
+    let (mut conn, _data) = common::pg_connection();
+    custom_state_table(&mut conn);
+}
+
+fn custom_state_table(conn: &mut Connection) {
+    let dir = std::env::temp_dir().join(format!(
+        "butane_state_table_test_{}_{}",
+        conn.backend_name(),
+        std::process::id()
+    ));
+    let mut ms = butane::migrations::from_root(&dir);
+    ms.set_state_table("my_app_migrations").unwrap();
+    model_with_migrations(
+        quote! {
+            struct Foo {
+                id: i64,
+            }
+        },
+        &mut ms,
+    );
+    assert!(ms.create_migration(&conn.backend(), "init", None).unwrap());
+    ms.latest().unwrap().apply(conn).unwrap();
+    assert!(conn.has_table("my_app_migrations").unwrap());
+    assert!(!conn.has_table("butane_migrations").unwrap());
+    assert!(ms.last_applied_migration(conn).unwrap().is_some());
+    // Migrations using the default table know nothing of it
+    assert!(MemMigrations::new()
+        .last_applied_migration(conn)
+        .unwrap()
+        .is_none());
+
+    // The table name is carried over when embedding
+    let mut mem_ms = MemMigrations::new();
+    mem_ms.set_state_table(&ms.state_table());
+    for m in ms.all_migrations().unwrap() {
+        let mut new_m = mem_ms.new_migration(&m.name());
+        copy_migration(&m, &mut new_m).unwrap();
+        mem_ms.add_migration(new_m).unwrap();
+    }
+    let mem_ms = MemMigrations::from_json(&serde_json::to_string(&mem_ms).unwrap()).unwrap();
+    assert_eq!(mem_ms.state_table(), "my_app_migrations");
+    let init = mem_ms.last_applied_migration(conn).unwrap().unwrap();
+    init.downgrade(conn).unwrap();
+    assert!(ms.last_applied_migration(conn).unwrap().is_none());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[cfg(feature = "sqlite")]
+#[test]
+fn migration_group_sqlite() {
+    migration_groups(&mut common::sqlite_connection());
+}
+
+#[cfg(feature = "pg")]
+#[test]
+fn migration_group_pg() {
+    let (mut conn, _data) = common::pg_connection();
+    migration_groups(&mut conn);
+}
+
+fn migration_groups(conn: &mut Connection) {
+    let dir = std::env::temp_dir().join(format!(
+        "butane_group_test_{}_{}",
+        conn.backend_name(),
+        std::process::id()
+    ));
+    let backend = conn.backend();
+    let mut main = butane::migrations::from_root(dir.join("migrations"));
+    model_with_migrations(
+        quote! {
+            struct Blog {
+                id: i64,
+            }
+        },
+        &mut main,
+    );
+    let visit = quote! {
+        #[butane(migration_group = "analytics")]
+        struct Visit {
+            id: i64,
+            blog: ForeignKey<Blog>,
+        }
+    };
+    assert_eq!(migration_group(&visit).as_deref(), Some("analytics"));
+    let group_dir = dir.join("groups").join("analytics").join("migrations");
+    let mut group = butane::migrations::group_from_root(&group_dir, "analytics").unwrap();
+    assert_eq!(group.state_table(), "butane_migrations_analytics");
+    model_with_migrations(visit, &mut group);
+    // The group's model references a model of the main migrations
+    copy_types(main.current(), group.current()).unwrap();
+
+    assert!(main.create_migration(&backend, "init", None).unwrap());
+    assert!(group.create_migration(&backend, "init", None).unwrap());
+    let main_db = main.latest().unwrap().db().unwrap();
+    assert!(main_db.get_table("Visit").is_none());
+    let group_db = group.latest().unwrap().db().unwrap();
+    assert!(group_db.get_table("Blog").is_none());
+    assert_eq!(
+        group_db
+            .get_table("Visit")
+            .unwrap()
+            .column("blog")
+            .unwrap()
+            .typeid()
+            .unwrap(),
+        TypeIdentifier::Ty(SqlType::BigInt)
+    );
+
+    // Each set is applied and tracked independently
+    main.latest().unwrap().apply(conn).unwrap();
+    assert!(conn.has_table("Blog").unwrap());
+    assert!(!conn.has_table("Visit").unwrap());
+    assert_eq!(group.unapplied_migrations(conn).unwrap().len(), 1);
+    group.latest().unwrap().apply(conn).unwrap();
+    assert!(conn.has_table("Visit").unwrap());
+    assert!(conn.has_table("butane_migrations_analytics").unwrap());
+    assert!(main.unapplied_migrations(conn).unwrap().is_empty());
+    assert!(group.unapplied_migrations(conn).unwrap().is_empty());
+
+    // Reopening the group keeps its state table
+    let group = butane::migrations::group_from_root(&group_dir, "analytics").unwrap();
+    assert_eq!(group.state_table(), "butane_migrations_analytics");
+    group.latest().unwrap().downgrade(conn).unwrap();
+    assert!(!conn.has_table("Visit").unwrap());
+    assert!(main.unapplied_migrations(conn).unwrap().is_empty());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[cfg(feature = "sqlite")]
+#[test]
+fn connection_state_store_sqlite() {
+    connection_state_store(&mut common::sqlite_connection());
+}
+
+#[cfg(all(feature = "sqlite", feature = "pg"))]
+#[test]
+fn connection_state_store_pg() {
+    let (mut conn, _data) = common::pg_connection();
+    connection_state_store(&mut conn);
+}
+
+#[cfg(feature = "sqlite")]
+fn connection_state_store(conn: &mut Connection) {
+    use butane::db::ConnectionSpec;
+    use butane::migrations::ConnectionStateStore;
+
+    // Record the applied migrations in a separate sqlite database
+    let state_path = std::env::temp_dir().join(format!(
+        "butane_state_store_test_{}_{}.db",
+        conn.backend_name(),
+        std::process::id()
+    ));
+    let state_spec = || ConnectionSpec::new("sqlite", state_path.to_str().unwrap());
+    let mut ms = MemMigrations::new();
+    ms.set_state_store(ConnectionStateStore::new(state_spec(), "app_migrations"));
+    model_with_migrations(
+        quote! {
+            struct Foo {
+                id: i64,
+            }
+        },
+        &mut ms,
+    );
+    assert!(ms.create_migration(&conn.backend(), "init", None).unwrap());
+    assert_eq!(ms.unapplied_migrations(conn).unwrap().len(), 1);
+    ms.latest().unwrap().apply(conn).unwrap();
+    assert!(conn.has_table("Foo").unwrap());
+    assert!(ms.unapplied_migrations(conn).unwrap().is_empty());
+    let state_conn = butane::db::connect(&state_spec()).unwrap();
+    assert!(state_conn.has_table("app_migrations").unwrap());
+    // The target database's own table records nothing
+    assert!(MemMigrations::new()
+        .last_applied_migration(conn)
+        .unwrap()
+        .is_none());
+
+    let init = ms.last_applied_migration(conn).unwrap().unwrap();
+    init.downgrade(conn).unwrap();
+    assert!(!conn.has_table("Foo").unwrap());
+    assert!(ms.last_applied_migration(conn).unwrap().is_none());
+    std::fs::remove_file(&state_path).unwrap();
+}
+
+#[cfg(feature = "sqlite")]
+#[test]
+fn migration_timeout_sqlite() {
+    migration_timeout(
+        &mut common::sqlite_connection(),
+        "CREATE TABLE Bar AS WITH RECURSIVE c(x) AS \
+         (SELECT 1 UNION ALL SELECT x + 1 FROM c LIMIT 10000000000) SELECT count(*) AS n FROM c;",
+    );
+}
+
+#[cfg(feature = "pg")]
+#[test]
+fn migration_timeout_pg() {
+    let (mut conn, _data) = common::pg_connection();
+    migration_timeout(
+        &mut conn,
+        "CREATE TABLE Bar AS SELECT 1 AS n FROM pg_sleep(60);",
+    );
+}
+
+fn migration_timeout(conn: &mut Connection, slow_sql: &str) {
+    let backend = conn.backend();
+    let mut ms = MemMigrations::new();
+    model_with_migrations(
+        quote! {
+            struct Foo {
+                id: i64,
+            }
+        },
+        &mut ms,
+    );
+    assert!(ms.create_migration(&backend, "init", None).unwrap());
+    let init = ms.latest().unwrap();
+    init.apply_within(conn, std::time::Duration::from_secs(60))
+        .unwrap();
+
+    let mut slow = ms.new_migration("slow");
+    copy_migration(&init, &mut slow).unwrap();
+    slow.set_migration_from(Some("init".to_string())).unwrap();
+    slow.add_sql(backend.name(), slow_sql, "DROP TABLE Bar;")
+        .unwrap();
+    ms.add_migration(slow).unwrap();
+    let slow = ms.latest().unwrap();
+    match slow.apply_within(conn, std::time::Duration::from_millis(200)) {
+        Err(butane::Error::MigrationTimeout { name, .. }) => assert_eq!(name, "slow"),
+        other => panic!("expected a timeout, got {:?}", other),
+    }
+    // Rolled back and recorded as failed
+    assert!(!conn.has_table("Bar").unwrap());
+    assert_eq!(
+        ms.last_applied_migration(conn).unwrap().unwrap().name(),
+        "init"
+    );
+    assert_eq!(
+        conn.delete_where(
+            "butane_migrations_failures",
+            butane::query::BoolExpr::Eq("name", butane::query::Expr::Val(SqlVal::from("slow"))),
+        )
+        .unwrap(),
+        1
+    );
+}
+
+#[cfg(feature = "sqlite")]
+#[test]
+fn migration_verification_sqlite() {
+    migration_verification(&mut common::sqlite_connection());
+}
+
+#[cfg(feature = "pg")]
+#[test]
+fn migration_verification_pg() {
+    let (mut conn, _data) = common::pg_connection();
+    migration_verification(&mut conn);
+}
+
+fn migration_verification(conn: &mut Connection) {
+    let backend = conn.backend();
+    let mut ms = MemMigrations::new();
+    model_with_migrations(
+        quote! {
+            #[table = "foo"]
+            struct Foo {
+                id: i64,
+                name: Option<String>,
+            }
+        },
+        &mut ms,
+    );
+    assert!(ms.create_migration(&backend, "init", None).unwrap());
+    let init = ms.latest().unwrap();
+    init.apply(conn).unwrap();
+    conn.execute("INSERT INTO foo (id, name) VALUES (1, 'a'), (2, NULL);")
+        .unwrap();
+
+    let mut failing = ms.new_migration("failing");
+    copy_migration(&init, &mut failing).unwrap();
+    failing
+        .set_migration_from(Some("init".to_string()))
+        .unwrap();
+    failing
+        .add_sql(
+            backend.name(),
+            "CREATE TABLE bar (id BIGINT NOT NULL PRIMARY KEY);",
+            "DROP TABLE bar;",
+        )
+        .unwrap();
+    failing
+        .add_verification(Verification::NotNull {
+            table: "foo".to_string(),
+            column: "name".to_string(),
+        })
+        .unwrap();
+    ms.add_migration(failing).unwrap();
+    let failing = ms.latest().unwrap();
+    assert_eq!(failing.verifications().unwrap().len(), 1);
+    match failing.apply(conn) {
+        Err(butane::Error::VerificationFailed { migration, .. }) => {
+            assert_eq!(migration, "failing")
+        }
+        other => panic!("expected a failed verification, got {:?}", other),
+    }
+    // Rolled back
+    assert!(!conn.has_table("bar").unwrap());
+    assert_eq!(
+        ms.last_applied_migration(conn).unwrap().unwrap().name(),
+        "init"
+    );
+
+    // Follows the failing migration, which is never applied
+    let mut passing = ms.new_migration("passing");
+    copy_migration(&init, &mut passing).unwrap();
+    passing
+        .set_migration_from(Some("failing".to_string()))
+        .unwrap();
+    passing
+        .add_sql(
+            backend.name(),
+            "UPDATE foo SET name = 'b' WHERE name IS NULL;",
+            "",
+        )
+        .unwrap();
+    for verification in [
+        Verification::RowCountUnchanged("foo".to_string()),
+        Verification::NotNull {
+            table: "foo".to_string(),
+            column: "name".to_string(),
+        },
+        Verification::NoRows("SELECT id FROM foo WHERE id > 2".to_string()),
+    ] {
+        passing.add_verification(verification).unwrap();
+    }
+    ms.add_migration(passing).unwrap();
+    let passing = ms.latest().unwrap();
+    passing.apply(conn).unwrap();
+    assert_eq!(
+        ms.last_applied_migration(conn).unwrap().unwrap().name(),
+        "passing"
+    );
+}
+
+#[cfg(feature = "sqlite")]
+#[test]
+fn migration_set_not_null_sqlite() {
+    migration_set_not_null(&mut common::sqlite_connection());
+}
+
+#[cfg(feature = "pg")]
+#[test]
+fn migration_set_not_null_pg() {
+    let (mut conn, _data) = common::pg_connection();
+    migration_set_not_null(&mut conn);
+}
+
+/// Migrations creating a table with a nullable column, then making it
+/// NOT NULL with `backfill`.
+fn set_not_null_migrations(conn: &Connection, backfill: Option<adb::Backfill>) -> MemMigrations {
+    let backend = conn.backend();
+    let mut ms = MemMigrations::new();
+    model_with_migrations(
+        quote! {
+            #[table = "foo"]
+            struct Foo {
+                id: i64,
+                name: Option<String>,
+            }
+        },
+        &mut ms,
+    );
+    assert!(ms.create_migration(&backend, "init", None).unwrap());
+    let init = ms.latest().unwrap();
+    let old = init
+        .db()
+        .unwrap()
+        .get_table("foo")
+        .and_then(|t| t.column("name"))
+        .unwrap()
+        .clone();
+    let mut new = old.clone();
+    new.set_nullable(false);
+    ms.create_migration_with_ops(
+        &backend,
+        "not_null",
+        &init,
+        vec![adb::Operation::SetNotNull(
+            "foo".into(),
+            "name".into(),
+            backfill,
+        )],
+        vec![adb::Operation::ChangeColumn("foo".into(), new, old)],
+    )
+    .unwrap();
+    ms
+}
+
+fn migration_set_not_null(conn: &mut Connection) {
+    let ms = set_not_null_migrations(conn, None);
+    ms.get_migration("init").unwrap().apply(conn).unwrap();
+    conn.execute("INSERT INTO foo (id, name) VALUES (1, 'a'), (2, NULL), (3, NULL);")
+        .unwrap();
+    assert!(!ms
+        .latest()
+        .unwrap()
+        .db()
+        .unwrap()
+        .get_table("foo")
+        .unwrap()
+        .column("name")
+        .unwrap()
+        .nullable());
+    // Fails on the NULLs, and is rolled back
+    assert!(ms.latest().unwrap().apply(conn).is_err());
+    assert_eq!(
+        ms.last_applied_migration(conn).unwrap().unwrap().name(),
+        "init"
+    );
+
+    let ms = set_not_null_migrations(
+        conn,
+        Some(adb::Backfill::new("'n' || id").with_batch_size(1)),
+    );
+    let not_null = ms.latest().unwrap();
+    not_null.apply(conn).unwrap();
+    let filled = |conn: &Connection, name: &str| {
+        conn.delete_where(
+            "foo",
+            butane::query::BoolExpr::Eq("name", butane::query::Expr::Val(SqlVal::from(name))),
+        )
+        .unwrap()
+    };
+    assert_eq!(filled(conn, "a"), 1);
+    assert_eq!(filled(conn, "n2"), 1);
+    assert_eq!(filled(conn, "n3"), 1);
+    assert!(conn
+        .execute("INSERT INTO foo (id, name) VALUES (4, NULL);")
+        .is_err());
+
+    not_null.downgrade(conn).unwrap();
+    conn.execute("INSERT INTO foo (id, name) VALUES (4, NULL);")
+        .unwrap();
+}
+
+#[cfg(feature = "sqlite")]
+#[test]
+fn migration_divergence_sqlite() {
+    migration_divergence(&mut common::sqlite_connection());
+}
+
+#[cfg(feature = "pg")]
+#[test]
+fn migration_divergence_pg() {
+    let (mut conn, _data) = common::pg_connection();
+    migration_divergence(&mut conn);
+}
+
+fn migration_divergence(conn: &mut Connection) {
+    let backend = conn.backend();
+    let mut ours = MemMigrations::new();
+    model_with_migrations(
+        quote! {
+            struct Foo {
+                id: i64,
+            }
+        },
+        &mut ours,
+    );
+    assert!(ours.create_migration(&backend, "init", None).unwrap());
+    ours.latest().unwrap().apply(conn).unwrap();
+    assert!(ours.divergence(conn).unwrap().is_empty());
+
+    // A teammate creates and applies a migration we don't have
+    let mut theirs = MemMigrations::new();
+    let mut init = theirs.new_migration("init");
+    copy_migration(&ours.latest().unwrap(), &mut init).unwrap();
+    theirs.add_migration(init).unwrap();
+    model_with_migrations(
+        quote! {
+            struct Foo {
+                id: i64,
+                name: String,
+            }
+        },
+        &mut theirs,
+    );
+    let init = theirs.latest().unwrap();
+    assert!(theirs
+        .create_migration(&backend, "theirs", Some(&init))
+        .unwrap());
+    assert!(theirs.divergence(conn).unwrap().is_empty());
+    theirs.latest().unwrap().apply(conn).unwrap();
+    let unknown = |known| {
+        vec![Divergence::UnknownApplied {
+            name: "theirs".to_string(),
+            known,
+        }]
+    };
+    assert_eq!(ours.divergence(conn).unwrap(), unknown(false));
+
+    // Once pulled in, it is on a separate branch from ours
+    model_with_migrations(
+        quote! {
+            struct Foo {
+                id: i64,
+                count: i32,
+            }
+        },
+        &mut ours,
+    );
+    let init = ours.latest().unwrap();
+    assert!(ours
+        .create_migration(&backend, "ours", Some(&init))
+        .unwrap());
+    let mut pulled = ours.new_migration("theirs");
+    copy_migration(&theirs.latest().unwrap(), &mut pulled).unwrap();
+    ours.add_migration(pulled).unwrap();
+    assert_eq!(ours.latest().unwrap().name(), "ours");
+    assert_eq!(ours.divergence(conn).unwrap(), unknown(true));
+
+    // A migration whose parent has changed since it was created
+    let mut changed = ours.new_migration("init");
+    copy_migration(&init, &mut changed).unwrap();
+    changed
+        .add_sql(backend.name(), "SELECT 1;", "SELECT 1;")
+        .unwrap();
+    ours.add_migration(changed).unwrap();
+    let divergence = ours.divergence(conn).unwrap();
+    assert!(divergence.contains(&Divergence::ParentChanged {
+        name: "ours".to_string(),
+        parent: "init".to_string(),
+    }));
+}
+
+#[cfg(feature = "pg")]
+#[test]
+fn pg_identifier_case() {
+    use butane::db::pg::PgBackend;
+    use butane::db::{Backend, BackendRows, IdentifierCase};
+
+    let data = common::pg_setup();
+    let connstr = common::pg_connstr(&data);
+    let mut ms = MemMigrations::new();
+    model_with_migrations(
+        quote! {
+            #[butane(unique_together("title", "likes"))]
+            struct BlogPost {
+                id: i64,
+                title: String,
+                likes: i32,
+            }
+        },
+        &mut ms,
+    );
+    let backend = PgBackend::new().with_identifier_case(IdentifierCase::Preserve);
+    assert!(ms.create_migration(&backend, "init", None).unwrap());
+    let m = ms.latest().unwrap();
+    let up_sql = m.up_sql("pg").unwrap().unwrap();
+    assert!(up_sql.contains("CREATE TABLE \"BlogPost\" ("));
+    assert!(up_sql.contains("CONSTRAINT \"BlogPost_title_likes_key\" UNIQUE (title, likes)"));
+
+    let mut conn = Backend::connect(&backend, &connstr).unwrap();
+    m.apply(&mut conn).unwrap();
+    assert!(conn.has_table("BlogPost").unwrap());
+    assert!(!conn.has_table("blogpost").unwrap());
+    conn.insert_only(
+        "BlogPost",
+        &[
+            butane::db::Column::new("id", SqlType::BigInt),
+            butane::db::Column::new("title", SqlType::Text),
+            butane::db::Column::new("likes", SqlType::Int),
+        ],
+        &[
+            SqlVal::BigInt(1).as_ref(),
+            SqlVal::Text("Hello".to_string()).as_ref(),
+            SqlVal::Int(3).as_ref(),
+        ],
+    )
+    .unwrap();
+    let columns = [butane::db::Column::new("title", SqlType::Text)];
+    let expr = butane::query::BoolExpr::Eq("id", butane::query::Expr::Val(SqlVal::BigInt(1)));
+    let mut rows = conn
+        .query("BlogPost", &columns, Some(expr), None, None, None)
+        .unwrap();
+    let title = rows.next().unwrap().unwrap().get(0, SqlType::Text).unwrap();
+    assert_eq!(SqlVal::from(title), SqlVal::Text("Hello".to_string()));
+    drop(rows);
+    assert!(ms.check_drift(&conn).unwrap().is_empty());
+
+    // Folding the case, the same model refers to an unquoted table
+    let folding = Backend::connect(&PgBackend::new(), &connstr).unwrap();
+    assert!(!folding.has_table("BlogPost").unwrap());
+    folding
+        .execute("CREATE TABLE BlogPost (id BIGINT);")
+        .unwrap();
+    assert!(folding.has_table("BlogPost").unwrap());
+    assert!(folding.has_table("blogpost").unwrap());
+}
+
+#[test]
+fn error_codes() {
+    let mut ms = MemMigrations::new();
+    let tokens = model_with_migrations(
+        quote! {
+            struct Foo {
+                name: String,
+            }
+        },
+        &mut ms,
+    )
+    .to_string();
+    assert!(tokens.contains("compile_error"));
+    assert!(tokens.contains(butane::ErrorCode::NoPrimaryKey.code()));
+
+    let err =
+        match butane::db::ConnectionSpec::load(std::env::temp_dir().join("no_such_butane_dir")) {
+            Ok(_) => panic!("loaded a connection spec which does not exist"),
+            Err(err) => err,
+        };
+    assert_eq!(err.code(), Some(butane::ErrorCode::NoConnectionSpec));
+    assert!(err.hint().unwrap().contains("butane init"));
+}
+
+#[cfg(feature = "sqlite")]
+#[test]
+fn unapplied_migration_error_sqlite() {
+    unapplied_migration_error(&common::sqlite_connection());
+}
+
+#[cfg(feature = "pg")]
+#[test]
+fn unapplied_migration_error_pg() {
+    let (conn, _data) = common::pg_connection();
+    unapplied_migration_error(&conn);
+}
+
+fn unapplied_migration_error(conn: &Connection) {
+    let columns = [butane::db::Column::new("id", SqlType::BigInt)];
+    let err = match conn.query("Missing", &columns, None, None, None, None) {
+        Ok(_) => panic!("query of a missing table succeeded"),
+        Err(err) => err,
+    };
+    assert_eq!(err.code(), Some(butane::ErrorCode::UnappliedMigration));
+}
+
+fn membership_table() -> adb::ATable {
+    let mut table = adb::ATable::new("Membership".to_string());
+    for (name, pk) in [("group_id", true), ("user_id", true), ("role", false)] {
+        let ty = if pk { SqlType::BigInt } else { SqlType::Text };
+        table.add_column(adb::AColumn::new(
+            name,
+            DeferredSqlType::Known(ty),
+            false,
+            pk,
+            false,
+            false,
+            None,
+        ));
+    }
+    table
+}
+
+#[cfg(feature = "sqlite")]
+#[test]
+fn composite_pk_sqlite() {
+    composite_pk(&common::sqlite_connection());
+}
+
+#[cfg(feature = "pg")]
+#[test]
+fn composite_pk_pg() {
+    let (conn, _data) = common::pg_connection();
+    composite_pk(&conn);
+}
+
+fn composite_pk(conn: &Connection) {
+    use butane::db::BackendConnection;
+
+    let table = membership_table();
+    assert_eq!(table.pk().len(), 2);
+    let sql = conn
+        .backend()
+        .create_migration_sql(&adb::ADB::new(), vec![adb::Operation::AddTable(table)])
+        .unwrap();
+    assert!(sql.contains("PRIMARY KEY (group_id, user_id)"));
+    conn.execute(&sql).unwrap();
+
+    let introspected = conn.introspect().unwrap();
+    let table = introspected
+        .tables()
+        .find(|t| t.name.eq_ignore_ascii_case("Membership"))
+        .unwrap();
+    let pk: Vec<&str> = table.pk().iter().map(|c| c.name()).collect();
+    assert_eq!(pk, vec!["group_id", "user_id"]);
+
+    conn.execute("INSERT INTO Membership (group_id, user_id, role) VALUES (1, 1, 'owner');")
+        .unwrap();
+    conn.execute("INSERT INTO Membership (group_id, user_id, role) VALUES (1, 2, 'member');")
+        .unwrap();
+    assert!(conn
+        .execute("INSERT INTO Membership (group_id, user_id, role) VALUES (1, 2, 'owner');")
+        .is_err());
+}
+
+fn auto_table(pk_auto: Option<adb::AutoIncrement>, seq_auto: bool) -> adb::ATable {
+    let mut table = adb::ATable::new("Counter".to_string());
+    let mut id = adb::AColumn::new(
+        "id",
+        DeferredSqlType::KnownId(TypeIdentifier::Ty(SqlType::BigInt)),
+        false,
+        true,
+        false,
+        false,
+        None,
+    );
+    if let Some(kind) = pk_auto {
+        id = id.with_auto_increment(kind);
+    }
+    table.add_column(id);
+    let mut seq = adb::AColumn::new(
+        "seq",
+        DeferredSqlType::KnownId(TypeIdentifier::Ty(SqlType::Int)),
+        false,
+        false,
+        false,
+        false,
+        None,
+    );
+    if seq_auto {
+        seq = seq.with_auto_increment(adb::AutoIncrement::Identity);
+    }
+    table.add_column(seq);
+    table
+}
+
+fn auto_table_sql(backend: &str, table: adb::ATable) -> butane::Result<String> {
+    butane::db::get_backend(backend)
+        .unwrap()
+        .create_migration_sql(&adb::ADB::new(), vec![adb::Operation::AddTable(table)])
+}
+
+#[cfg(feature = "sqlite")]
+#[test]
+fn auto_increment_sqlite() {
+    assert_eq!(
+        auto_table_sql("sqlite", auto_table(None, false)).unwrap(),
+        "CREATE TABLE Counter (\n/* butane:managed */\nid INTEGER NOT NULL PRIMARY KEY,\nseq INTEGER NOT NULL\n);"
+    );
+    assert_eq!(
+        auto_table_sql(
+            "sqlite",
+            auto_table(Some(adb::AutoIncrement::Serial), false)
+        )
+        .unwrap(),
+        "CREATE TABLE Counter (\n/* butane:managed */\nid INTEGER NOT NULL PRIMARY KEY,\nseq INTEGER NOT NULL\n);"
+    );
+    assert_eq!(
+        auto_table_sql("sqlite", auto_table(Some(adb::AutoIncrement::Identity), false)).unwrap(),
+        "CREATE TABLE Counter (\n/* butane:managed */\nid INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,\nseq INTEGER NOT NULL\n);"
+    );
+    // Only a lone integer primary key can be auto on SQLite
+    assert!(matches!(
+        auto_table_sql("sqlite", auto_table(None, true)),
+        Err(butane::Error::UnsupportedAuto("sqlite", col)) if col == "Counter.seq"
+    ));
+}
+
+#[cfg(feature = "pg")]
+#[test]
+fn auto_increment_pg() {
+    assert_eq!(
+        auto_table_sql("pg", auto_table(None, false)).unwrap(),
+        "CREATE TABLE Counter (\nid BIGINT NOT NULL PRIMARY KEY,\nseq INTEGER NOT NULL\n);\nCOMMENT ON TABLE Counter IS 'butane:managed';"
+    );
+    assert_eq!(
+        auto_table_sql("pg", auto_table(Some(adb::AutoIncrement::Serial), false)).unwrap(),
+        "CREATE TABLE Counter (\nid BIGSERIAL NOT NULL PRIMARY KEY,\nseq INTEGER NOT NULL\n);\nCOMMENT ON TABLE Counter IS 'butane:managed';"
+    );
+    assert_eq!(
+        auto_table_sql("pg", auto_table(None, true)).unwrap(),
+        "CREATE TABLE Counter (\nid BIGINT NOT NULL PRIMARY KEY,\n\
+         seq INTEGER NOT NULL GENERATED BY DEFAULT AS IDENTITY\n);\nCOMMENT ON TABLE Counter IS 'butane:managed';"
+    );
+}
+
+#[cfg(feature = "mysql-experimental")]
+#[test]
+fn auto_increment_mysql() {
+    assert_eq!(
+        auto_table_sql("mysql", auto_table(Some(adb::AutoIncrement::Serial), false)).unwrap(),
+        "CREATE TABLE `Counter` (\n`id` BIGINT NOT NULL AUTO_INCREMENT PRIMARY KEY,\n`seq` INT NOT NULL\n) \
+         ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COMMENT='butane:managed';"
+    );
+    // Both kinds are AUTO_INCREMENT
+    assert_eq!(
+        auto_table_sql(
+            "mysql",
+            auto_table(Some(adb::AutoIncrement::Identity), false)
+        )
+        .unwrap(),
+        auto_table_sql("mysql", auto_table(Some(adb::AutoIncrement::Serial), false)).unwrap()
+    );
+    // An AUTO_INCREMENT column must be a key
+    assert!(matches!(
+        auto_table_sql("mysql", auto_table(None, true)),
+        Err(butane::Error::UnsupportedAuto("mysql", col)) if col == "Counter.seq"
+    ));
+}
+
+#[cfg(feature = "pg")]
+#[test]
+fn auto_increment_cockroach() {
+    let backend = butane::db::get_backend("cockroach").unwrap();
+    assert_eq!(backend.name(), "cockroach");
+    assert!(!backend.transactional_ddl());
+    // CockroachDB's SERIAL takes values from unique_rowid(), so serial
+    // columns are declared as identity columns
+    assert_eq!(
+        auto_table_sql(
+            "cockroach",
+            auto_table(Some(adb::AutoIncrement::Serial), false)
+        )
+        .unwrap(),
+        "CREATE TABLE Counter (\nid BIGINT NOT NULL PRIMARY KEY GENERATED BY DEFAULT AS IDENTITY,\n\
+         seq INTEGER NOT NULL\n);\nCOMMENT ON TABLE Counter IS 'butane:managed';"
+    );
+    let e = adb::AEnum::new("Mood", vec!["happy".to_string(), "sad".to_string()]);
+    assert_eq!(
+        backend
+            .create_idempotent_migration_sql(&adb::ADB::new(), vec![adb::Operation::AddEnum(e)])
+            .unwrap(),
+        "CREATE TYPE IF NOT EXISTS Mood AS ENUM ('happy', 'sad');"
+    );
+}
+
+/// Text keys are declared `VARCHAR`, as MySQL cannot index `TEXT`.
+#[cfg(feature = "mysql-experimental")]
+#[test]
+fn text_keys_mysql() {
+    let text = || DeferredSqlType::KnownId(TypeIdentifier::Ty(SqlType::Text));
+    let mut tag = adb::ATable::new("Tag".to_string());
+    tag.add_column(adb::AColumn::new(
+        "name",
+        text(),
+        false,
+        true,
+        false,
+        false,
+        None,
+    ));
+    tag.add_column(adb::AColumn::new(
+        "note",
+        text(),
+        true,
+        false,
+        false,
+        false,
+        Some(SqlVal::Text("it's \\".to_string())),
+    ));
+    let mut post = adb::ATable::new("Post".to_string());
+    post.add_column(
+        adb::AColumn::new("tag", text(), false, false, false, false, None)
+            .with_reference(adb::AForeignKey::new("Tag")),
+    );
+    let backend = butane::db::get_backend("mysql").unwrap();
+    let sql = backend
+        .create_migration_sql(
+            &adb::ADB::new(),
+            vec![
+                adb::Operation::AddTable(tag.clone()),
+                adb::Operation::AddTable(post),
+            ],
+        )
+        .unwrap();
+    assert_eq!(
+        sql,
+        "CREATE TABLE `Tag` (\n`name` VARCHAR(255) NOT NULL PRIMARY KEY,\n\
+         `note` TEXT DEFAULT ('it''s \\\\')\n) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COMMENT='butane:managed';\n\
+         CREATE TABLE `Post` (\n`tag` VARCHAR(255) NOT NULL,\n\
+         CONSTRAINT `Post_tag_fkey` FOREIGN KEY (`tag`) REFERENCES `Tag`(`name`)\n\
+         ) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COMMENT='butane:managed';"
+    );
+
+    // Indexing a text column first makes it VARCHAR
+    let mut db = adb::ADB::new();
+    db.replace_table(tag);
+    let index = adb::AIndex::new(["note"]);
+    let sql = backend
+        .create_migration_sql(
+            &db,
+            vec![adb::Operation::AddIndex("Tag".to_string(), index)],
+        )
+        .unwrap();
+    assert_eq!(
+        sql,
+        "ALTER TABLE `Tag` MODIFY COLUMN `note` VARCHAR(255) DEFAULT 'it''s \\\\';\n\
+         CREATE INDEX `Tag_note_idx` ON `Tag` (`note`) COMMENT 'butane:managed';"
+    );
+    let introspected = backend.as_introspected(&db);
+    let name = introspected
+        .get_table("Tag")
+        .unwrap()
+        .column("name")
+        .unwrap();
+    assert_eq!(name.params().length, Some(255));
+}
+
+#[test]
+fn composite_pk_columns_adjacent() {
+    let mut table = membership_table();
+    let key_column = |name: &str, pk| {
+        adb::AColumn::new(
+            name,
+            DeferredSqlType::Known(SqlType::BigInt),
+            false,
+            pk,
+            false,
+            false,
+            None,
+        )
+    };
+    table.add_column(key_column("tenant_id", true));
+    table.add_column(key_column("role", true));
+    let names = |columns: &[adb::AColumn]| -> Vec<String> {
+        columns.iter().map(|c| c.name().to_string()).collect()
+    };
+    assert_eq!(
+        names(table.pk()),
+        vec!["group_id", "user_id", "tenant_id", "role"]
+    );
+
+    table.add_column(key_column("user_id", false));
+    assert_eq!(names(table.pk()), vec!["group_id", "tenant_id", "role"]);
+    assert_eq!(
+        names(&table.columns),
+        vec!["group_id", "tenant_id", "role", "user_id"]
+    );
+}
+
+#[test]
+fn composite_pk_reference() {
+    let mut db = adb::ADB::new();
+    db.replace_table(membership_table());
+    let mut grant = adb::ATable::new("Grant".to_string());
+    grant.add_column(adb::AColumn::new(
+        "membership",
+        DeferredSqlType::Deferred(TypeKey::PK("Membership".to_string())),
+        false,
+        false,
+        false,
+        false,
+        None,
+    ));
+    db.replace_table(grant);
+    assert!(matches!(
+        db.resolve_types(),
+        Err(butane::Error::CompositeKeyReference(table)) if table == "Membership"
+    ));
+}
+
+#[test]
+fn validate_schema() {
+    let big_int = || DeferredSqlType::KnownId(TypeIdentifier::Ty(SqlType::BigInt));
+    let mut db = adb::ADB::new();
+    let mut foo = adb::ATable::new("Foo".to_string());
+    foo.add_column(adb::AColumn::new(
+        "id",
+        big_int(),
+        false,
+        true,
+        false,
+        false,
+        None,
+    ));
+    db.replace_table(foo);
+    // Tables storing Many fields need no primary key
+    let mut many = adb::ATable::new("Foo_tags_Many".to_string());
+    many.add_column(adb::AColumn::new_simple("owner", big_int()));
+    many.add_column(adb::AColumn::new_simple("has", big_int()));
+    db.replace_table(many);
+    assert!(db.validate().is_ok());
+
+    let mut bar = adb::ATable::new("Bar".to_string());
+    bar.add_column(adb::AColumn::new_simple("name", big_int()));
+    bar.columns
+        .push(adb::AColumn::new_simple("name", big_int()));
+    bar.add_column(adb::AColumn::new_simple(
+        "baz",
+        DeferredSqlType::Deferred(TypeKey::CustomType("Baz".to_string())),
+    ));
+    let mut col = adb::AColumn::new_simple("foo", big_int());
+    col.set_reference(Some(adb::AForeignKey::new("Foo").with_column("missing")));
+    bar.add_column(col);
+    db.replace_table(bar);
+    match db.validate() {
+        Err(butane::Error::InvalidSchema(problems)) => assert_eq!(
+            problems.iter().map(ToString::to_string).collect::<Vec<_>>(),
+            vec![
+                "Bar has no primary key",
+                "Bar.name is declared more than once",
+                "the type CustomType(Baz) of Bar.baz is not known",
+                "Bar.foo references Foo.missing, which does not exist",
+            ]
+        ),
+        other => panic!("expected InvalidSchema, got {:?}", other),
+    }
+}
+
+#[cfg(feature = "sqlite")]
+#[test]
+fn migrations_diff() {
+    let mut ms = MemMigrations::new();
+    let backend = butane::db::get_backend("sqlite").unwrap();
+    model_with_migrations(
+        quote! {
+            struct Foo {
+                id: i64,
+            }
+        },
+        &mut ms,
+    );
+    assert!(ms.create_migration(&backend, "init", None).unwrap());
+    model_with_migrations(
+        quote! {
+            struct Foo {
+                id: i64,
+                bar: String,
+            }
+        },
+        &mut ms,
+    );
+    assert!(ms
+        .create_migration(&backend, "v2", ms.latest().as_ref())
+        .unwrap());
+    model_with_migrations(
+        quote! {
+            struct Baz {
+                id: i64,
+            }
+        },
+        &mut ms,
+    );
+    assert!(ms
+        .create_migration(&backend, "v3", ms.latest().as_ref())
+        .unwrap());
+
+    let init = ms.get_migration("init").unwrap();
+    let v3 = ms.get_migration("v3").unwrap();
+    let ops = ms.diff(&init, &v3).unwrap();
+    assert_eq!(ops.len(), 2);
+    assert!(matches!(&ops[0], adb::Operation::AddTable(t) if t.name == "Baz"));
+    assert!(matches!(&ops[1], adb::Operation::AddColumn(t, c) if t == "Foo" && c.name() == "bar"));
+
+    let ops = ms.diff(&v3, &init).unwrap();
+    assert_eq!(ops.len(), 2);
+    assert!(matches!(&ops[0], adb::Operation::RemoveTable(t) if t == "Baz"));
+    assert!(ms.diff(&v3, &v3).unwrap().is_empty());
+}
+
+#[test]
+fn migrations_many_table() {
+    let mut ms = MemMigrations::new();
+    let backend = butane::db::get_backend("sqlite").unwrap();
+    let bar = quote! {
+        struct Bar {
+            id: i64,
+        }
+    };
+    model_with_migrations(bar, &mut ms);
+    model_with_migrations(
+        quote! {
+            struct Foo {
+                id: i64,
+                bars: Many<Bar>,
+            }
+        },
+        &mut ms,
+    );
+    assert!(ms.create_migration(&backend, "init", None).unwrap());
+    let db = ms.current().db().unwrap();
+    let table = db.get_table("Foo_bars_Many").expect("No join table");
+    assert_eq!(table.many, Some(adb::AManyField::new("Foo", "bars")));
+
+    // Renamed, with a metadata column
+    model_with_migrations(
+        quote! {
+            struct Foo {
+                id: i64,
+                #[butane(many(
+                    table = "foo_bars",
+                    column(name = "note", sqltype = "Text"),
+                    column(name = "added", sqltype = "BigInt", default_expr = "0")
+                ))]
+                bars: Many<Bar>,
+            }
+        },
+        &mut ms,
+    );
+    assert!(ms
+        .create_migration(&backend, "v2", ms.latest().as_ref())
+        .unwrap());
+    let db = ms.current().db().unwrap();
+    assert!(db.get_table("Foo_bars_Many").is_none());
+    let table = db.get_table("foo_bars").expect("No renamed join table");
+    assert!(table.column("note").unwrap().nullable());
+    assert!(!table.column("added").unwrap().nullable());
+    assert_eq!(table.column("added").unwrap().default_expr(), Some("0"));
+
+    // Removed
+    model_with_migrations(
+        quote! {
+            struct Foo {
+                id: i64,
+            }
+        },
+        &mut ms,
+    );
+    assert!(ms
+        .create_migration(&backend, "v3", ms.latest().as_ref())
+        .unwrap());
+
+    let init = ms.get_migration("init").unwrap();
+    let v2 = ms.get_migration("v2").unwrap();
+    let v3 = ms.get_migration("v3").unwrap();
+    let ops = ms.diff(&init, &v2).unwrap();
+    assert_eq!(ops.len(), 3);
+    assert!(
+        matches!(&ops[0], adb::Operation::RenameTable(old, new) if old == "Foo_bars_Many" && new == "foo_bars")
+    );
+    let mut added: Vec<&str> = ops[1..]
+        .iter()
+        .filter_map(|op| match op {
+            adb::Operation::AddColumn(t, c) if t == "foo_bars" => Some(c.name()),
+            _ => None,
+        })
+        .collect();
+    added.sort();
+    assert_eq!(added, ["added", "note"]);
+    let ops = ms.diff(&v2, &v3).unwrap();
+    assert_eq!(ops.len(), 1);
+    assert!(matches!(&ops[0], adb::Operation::RemoveTable(t) if t == "foo_bars"));
+}
+
+#[cfg(all(feature = "cbor", feature = "sqlite"))]
+#[test]
+fn mem_migrations_binary_roundtrip() {
+    let mut ms = MemMigrations::new();
+    model_with_migrations(
+        quote! {
+            struct Foo {
+                id: i64,
+                #[butane(unique)]
+                bar: String,
+            }
+        },
+        &mut ms,
+    );
+    let backend = butane::db::get_backend("sqlite").unwrap();
+    assert!(ms.create_migration(&backend, "init", None).unwrap());
+
+    let bytes = ms.to_bytes().unwrap();
+    assert!(bytes.len() < serde_json::to_string(&ms).unwrap().len());
+    let decoded = MemMigrations::from_bytes(&bytes).unwrap();
+    let latest = decoded.latest().unwrap();
+    assert_eq!(latest.name(), "init");
+    assert_eq!(
+        latest.up_sql("sqlite").unwrap(),
+        ms.latest().unwrap().up_sql("sqlite").unwrap()
+    );
+    let db = latest.db().unwrap();
+    let table = db.get_table("Foo").expect("No Foo table");
+    assert_eq!(table.columns.len(), 2);
+    assert!(table.column("bar").unwrap().unique());
+    assert!(MemMigrations::from_bytes(b"not cbor").is_err());
+}
+
+fn test_migrate(
+    conn: &mut Connection,
+    init_tokens: TokenStream,
+    v2_tokens: TokenStream,
+    expected_up_sql: &str,
+    expected_down_sql: &str,
+) {
+    let mut ms = MemMigrations::new();
+    let backend = conn.backend();
+    model_with_migrations(init_tokens, &mut ms);
+    assert!(ms.create_migration(&backend, "init", None).unwrap());
+
+    model_with_migrations(v2_tokens, &mut ms);
+    assert!(ms
+        .create_migration(&backend, "v2", ms.latest().as_ref())
+        .unwrap());
+
+    let mut to_apply = ms.unapplied_migrations(conn).unwrap();
+    assert_eq!(to_apply.len(), 2);
+    for m in &to_apply {
+        m.apply(conn).unwrap();
+    }
+    verify_sql(conn, &ms, expected_up_sql, expected_down_sql);
+
+    // Now downgrade, just to make sure we can
+    to_apply.reverse();
+    for m in to_apply {
+        m.downgrade(conn).unwrap();
+    }
+}
+
+fn verify_sql(
+    conn: &Connection,
+    ms: &impl Migrations,
+    expected_up_sql: &str,
+    expected_down_sql: &str,
+) {
+    let backend = conn.backend();
+    let v2_migration = ms.latest().unwrap();
+    let strip = |s: String| s.replace("\n", "");
+
+    let actual_up_sql = strip(v2_migration.up_sql(backend.name()).unwrap().unwrap());
+    assert_eq!(actual_up_sql, expected_up_sql);
+    let actual_down_sql = strip(v2_migration.down_sql(backend.name()).unwrap().unwrap());
+    assert_eq!(actual_down_sql, expected_down_sql);
+}
+
+fn migration_add_field(conn: &mut Connection, up_sql: &str, down_sql: &str) {
+    let init = quote! {
+        struct Foo {
+            id: i64,
+            bar: String,
+        }
+    };
+
+    let v2 = quote! {
+        struct Foo {
+            id: i64,
+            bar: String,
+            baz: u32,
+        }
+    };
+    test_migrate(conn, init, v2, up_sql, down_sql);
+}
+
+fn migration_add_field_with_default(conn: &mut Connection, up_sql: &str, down_sql: &str) {
+    let init = quote! {
+        struct Foo {
+            id: i64,
+            bar: String,
+        }
     };
 
     let v2 = quote! {
         struct Foo {
             id: i64,
             bar: String,
+            #[default=42]
             baz: u32,
         }
     };
     test_migrate(conn, init, v2, up_sql, down_sql);
 }
 
-fn migration_add_field_with_default(conn: &mut Connection, up_sql: &str, down_sql: &str) {
+fn migration_add_and_remove_field(conn: &mut Connection, up_sql: &str, down_sql: &str) {
     let init = quote! {
         struct Foo {
             id: i64,
@@ -335,26 +3122,383 @@ fn migration_add_field_with_default(conn: &mut Connection, up_sql: &str, down_sq
     let v2 = quote! {
         struct Foo {
             id: i64,
-            bar: String,
-            #[default=42]
             baz: u32,
         }
     };
     test_migrate(conn, init, v2, up_sql, down_sql);
 }
 
-fn migration_add_and_remove_field(conn: &mut Connection, up_sql: &str, down_sql: &str) {
+fn migration_add_unique_together(conn: &mut Connection, up_sql: &str, down_sql: &str) {
     let init = quote! {
         struct Foo {
             id: i64,
             bar: String,
+            baz: i32,
         }
     };
 
     let v2 = quote! {
+        #[butane(unique_together("bar", "baz"))]
         struct Foo {
             id: i64,
-            baz: u32,
+            bar: String,
+            baz: i32,
+        }
+    };
+    test_migrate(conn, init, v2, up_sql, down_sql);
+}
+
+fn migration_add_index(conn: &mut Connection, up_sql: &str, down_sql: &str) {
+    let init = quote! {
+        struct Foo {
+            id: i64,
+            bar: String,
+            baz: i32,
+        }
+    };
+
+    let v2 = quote! {
+        #[butane(index("bar", "baz"))]
+        struct Foo {
+            id: i64,
+            #[butane(index)]
+            bar: String,
+            baz: i32,
+        }
+    };
+    test_migrate(conn, init, v2, up_sql, down_sql);
+}
+
+fn migration_add_partial_index(conn: &mut Connection, up_sql: &str, down_sql: &str) {
+    let init = quote! {
+        struct Foo {
+            id: i64,
+            bar: String,
+            deleted: bool,
+        }
+    };
+
+    let v2 = quote! {
+        struct Foo {
+            id: i64,
+            #[butane(index(predicate = "NOT deleted"))]
+            bar: String,
+            deleted: bool,
+        }
+    };
+    test_migrate(conn, init, v2, up_sql, down_sql);
+}
+
+fn migration_rename(conn: &mut Connection, up_sql: &str, down_sql: &str) {
+    let foo_tokens = quote! {
+        struct Foo {
+            id: i64,
+            #[butane(index)]
+            barBaz: String,
+        }
+    };
+    let bar_tokens = quote! {
+        struct Bar {
+            id: i64,
+            #[butane(foreign_key)]
+            foo: ForeignKey<Foo>,
+        }
+    };
+
+    let mut ms = MemMigrations::new();
+    let backend = conn.backend();
+    model_with_migrations(foo_tokens, &mut ms);
+    model_with_migrations(bar_tokens, &mut ms);
+    assert!(ms.create_migration(&backend, "init", None).unwrap());
+    let init = ms.latest().unwrap();
+    ms.create_migration_with_ops(
+        &backend,
+        "rename",
+        &init,
+        vec![
+            adb::Operation::RenameColumn("Foo".into(), "barBaz".into(), "bar_baz".into()),
+            adb::Operation::RenameTable("Foo".into(), "foo".into()),
+        ],
+        vec![
+            adb::Operation::RenameTable("foo".into(), "Foo".into()),
+            adb::Operation::RenameColumn("Foo".into(), "bar_baz".into(), "barBaz".into()),
+        ],
+    )
+    .unwrap();
+
+    let db = ms.latest().unwrap().db().unwrap();
+    let table = db.get_table("foo").expect("No foo table");
+    assert!(table.column("bar_baz").is_some());
+    assert_eq!(table.indexes, [adb::AIndex::new(["bar_baz"])]);
+    let reference = db
+        .get_table("Bar")
+        .and_then(|t| t.column("foo"))
+        .and_then(|c| c.reference())
+        .expect("No reference");
+    assert_eq!(reference.table(), "foo");
+
+    let mut to_apply = ms.unapplied_migrations(conn).unwrap();
+    assert_eq!(to_apply.len(), 2);
+    for m in &to_apply {
+        m.apply(conn).unwrap();
+    }
+    verify_sql(conn, &ms, up_sql, down_sql);
+    to_apply.reverse();
+    for m in to_apply {
+        m.downgrade(conn).unwrap();
+    }
+}
+
+fn migration_set_schema(conn: &mut Connection, up_sql: &str, down_sql: &str) {
+    let init = quote! {
+        struct Foo {
+            id: i64,
+            bar: String,
+        }
+    };
+
+    let v2 = quote! {
+        #[butane(schema = "billing")]
+        struct Foo {
+            id: i64,
+            #[butane(index)]
+            bar: String,
+        }
+    };
+    test_migrate(conn, init, v2, up_sql, down_sql);
+}
+
+fn migration_add_check(conn: &mut Connection, up_sql: &str, down_sql: &str) {
+    let init = quote! {
+        struct Foo {
+            id: i64,
+            price: i32,
+        }
+    };
+
+    let v2 = quote! {
+        #[butane(check = "price > 0")]
+        struct Foo {
+            id: i64,
+            price: i32,
+        }
+    };
+    test_migrate(conn, init, v2, up_sql, down_sql);
+}
+
+fn migration_add_foreign_key_table(conn: &mut Connection, up_sql: &str, down_sql: &str) {
+    let init = quote! {
+        struct Foo {
+            id: i64,
+        }
+    };
+
+    let v2 = quote! {
+        struct Bar {
+            id: i64,
+            #[butane(foreign_key(on_delete = "cascade"))]
+            foo: ForeignKey<Foo>,
+        }
+    };
+    test_migrate(conn, init, v2, up_sql, down_sql);
+}
+
+fn migration_add_cyclic_foreign_keys(conn: &mut Connection, up_sql: &str, down_sql: &str) {
+    let mut ms = MemMigrations::new();
+    let backend = conn.backend();
+    model_with_migrations(
+        quote! {
+            struct Foo {
+                id: i64,
+            }
+        },
+        &mut ms,
+    );
+    assert!(ms.create_migration(&backend, "init", None).unwrap());
+
+    // Bar and Baz reference each other, so one of the references is
+    // added once both tables exist.
+    model_with_migrations(
+        quote! {
+            struct Bar {
+                id: i64,
+                #[butane(foreign_key(on_delete = "set_null"))]
+                baz: Option<ForeignKey<Baz>>,
+            }
+        },
+        &mut ms,
+    );
+    model_with_migrations(
+        quote! {
+            struct Baz {
+                id: i64,
+                #[butane(foreign_key(on_delete = "cascade"))]
+                bar: ForeignKey<Bar>,
+            }
+        },
+        &mut ms,
+    );
+    assert!(ms
+        .create_migration(&backend, "v2", ms.latest().as_ref())
+        .unwrap());
+
+    let mut to_apply = ms.unapplied_migrations(conn).unwrap();
+    for m in &to_apply {
+        m.apply(conn).unwrap();
+    }
+    verify_sql(conn, &ms, up_sql, down_sql);
+    to_apply.reverse();
+    for m in to_apply {
+        m.downgrade(conn).unwrap();
+    }
+}
+
+fn migration_add_comments(conn: &mut Connection, up_sql: &str, down_sql: &str) {
+    let init = quote! {
+        struct Foo {
+            id: i64,
+            name: String,
+        }
+    };
+
+    let v2 = quote! {
+        /// Things for sale.
+        struct Foo {
+            id: i64,
+            /// The shopper's name for it.
+            name: String,
+            /// Price in cents.
+            price: i32,
+        }
+    };
+    test_migrate(conn, init, v2, up_sql, down_sql);
+}
+
+fn migration_change_default(conn: &mut Connection, up_sql: &str, down_sql: &str) {
+    let init = quote! {
+        struct Foo {
+            id: i64,
+            #[default = 1]
+            bar: i32,
+        }
+    };
+
+    let v2 = quote! {
+        struct Foo {
+            id: i64,
+            #[default = 2]
+            bar: i32,
+        }
+    };
+    test_migrate(conn, init, v2, up_sql, down_sql);
+}
+
+fn migration_add_field_with_default_expr(conn: &mut Connection, up_sql: &str, down_sql: &str) {
+    let init = quote! {
+        struct Foo {
+            id: i64,
+            bar: String,
+        }
+    };
+
+    let v2 = quote! {
+        struct Foo {
+            id: i64,
+            bar: String,
+            #[butane(default_expr = "CURRENT_TIMESTAMP")]
+            created: NaiveDateTime,
+        }
+    };
+    test_migrate(conn, init, v2, up_sql, down_sql);
+}
+
+fn migration_change_default_to_expr(conn: &mut Connection, up_sql: &str, down_sql: &str) {
+    let init = quote! {
+        struct Foo {
+            id: i64,
+            #[default = 1]
+            bar: i32,
+        }
+    };
+
+    let v2 = quote! {
+        struct Foo {
+            id: i64,
+            #[butane(default_expr = "abs(random())")]
+            bar: i32,
+        }
+    };
+    test_migrate(conn, init, v2, up_sql, down_sql);
+}
+
+fn migration_change_length(conn: &mut Connection, up_sql: &str, down_sql: &str) {
+    let init = quote! {
+        struct Foo {
+            id: i64,
+            #[butane(length = 8)]
+            bar: String,
+        }
+    };
+
+    let v2 = quote! {
+        struct Foo {
+            id: i64,
+            #[butane(length = 16)]
+            bar: String,
+        }
+    };
+    test_migrate(conn, init, v2, up_sql, down_sql);
+}
+
+fn migration_change_type(conn: &mut Connection, up_sql: &str, down_sql: &str) {
+    let init = quote! {
+        struct Foo {
+            id: i64,
+            bar: i32,
+        }
+    };
+
+    let v2 = quote! {
+        struct Foo {
+            id: i64,
+            bar: String,
+        }
+    };
+    test_migrate(conn, init, v2, up_sql, down_sql);
+}
+
+fn migration_change_type_with_default(conn: &mut Connection, up_sql: &str, down_sql: &str) {
+    let init = quote! {
+        struct Foo {
+            id: i64,
+            #[default = 1]
+            bar: i32,
+        }
+    };
+
+    let v2 = quote! {
+        struct Foo {
+            id: i64,
+            #[default = 2]
+            bar: i64,
+        }
+    };
+    test_migrate(conn, init, v2, up_sql, down_sql);
+}
+
+fn migration_change_nullable_unique(conn: &mut Connection, up_sql: &str, down_sql: &str) {
+    let init = quote! {
+        struct Foo {
+            id: i64,
+            bar: String,
+        }
+    };
+
+    let v2 = quote! {
+        struct Foo {
+            id: i64,
+            #[unique]
+            bar: Option<String>,
         }
     };
     test_migrate(conn, init, v2, up_sql, down_sql);
@@ -0,0 +1,286 @@
+#![cfg(feature = "async")]
+use butane::db::blocking::BlockingConnection;
+use butane::db::nonblocking::{self, AsyncConnection, AsyncConnectionMethods};
+use butane::prelude::*;
+use butane::{model, query, set, ObjectState};
+use std::future::Future;
+use std::time::Duration;
+
+mod common;
+use common::blog::{Blog, Post, Tag};
+
+#[model]
+#[derive(Debug, PartialEq)]
+struct AsyncNote {
+    #[auto]
+    id: i64,
+    text: String,
+}
+impl AsyncNote {
+    fn new(text: &str) -> Self {
+        AsyncNote {
+            id: -1,
+            text: text.to_string(),
+            state: ObjectState::default(),
+        }
+    }
+}
+
+fn block_on<F: Future>(future: F) -> F::Output {
+    tokio::runtime::Builder::new_current_thread()
+        .enable_io()
+        .enable_time()
+        .build()
+        .unwrap()
+        .block_on(future)
+}
+
+async fn save_and_load(conn: &dyn AsyncConnectionMethods) {
+    let mut blog = Blog::new(1, "Cats");
+    blog.save_async(conn).await.unwrap();
+    assert_eq!(Blog::get_async(conn, 1).await.unwrap(), blog);
+    assert!(matches!(
+        Blog::get_async(conn, 2).await,
+        Err(butane::Error::NoSuchObject)
+    ));
+
+    let mut tag = Tag::new("asia");
+    tag.save_async(conn).await.unwrap();
+    let mut post = Post::new(1, "The Tiger", "The tiger is a cat.", &blog);
+    post.tags.add(&tag).unwrap();
+    post.save_async(conn).await.unwrap();
+    let tagged = query!(Post, tags.contains("asia"))
+        .load_async(conn)
+        .await
+        .unwrap();
+    assert_eq!(tagged.len(), 1);
+
+    post.likes = 5;
+    post.save_async(conn).await.unwrap();
+    let loaded = Post::query().load_first_async(conn).await.unwrap().unwrap();
+    assert_eq!(loaded.likes, 5);
+
+    // The pk is set on the first save
+    let mut note = AsyncNote::new("first");
+    note.save_async(conn).await.unwrap();
+    assert!(note.id > 0);
+    assert_eq!(AsyncNote::get_async(conn, note.id).await.unwrap(), note);
+
+    note.delete_async(conn).await.unwrap();
+    assert!(AsyncNote::get_async(conn, note.id).await.is_err());
+    assert_eq!(Post::query().delete_async(conn).await.unwrap(), 1);
+}
+
+#[cfg(feature = "sqlite")]
+const SLOW_SQLITE: &str = "CREATE TABLE slow AS WITH RECURSIVE c(x) AS \
+     (SELECT 1 UNION ALL SELECT x + 1 FROM c WHERE x < 5000000) \
+     SELECT count(*) AS n FROM c;";
+
+/// Drops the future of `slow_sql` partway, after which the connection
+/// must be discarded.
+async fn cancel_statement(conn: &dyn AsyncConnection, slow_sql: &str) {
+    // Not yet polled, so nothing was sent
+    drop(conn.execute(slow_sql));
+    assert!(!conn.is_closed());
+    assert!(!conn.has_table("missing").await.unwrap());
+
+    let statement = conn.execute(slow_sql);
+    assert!(tokio::time::timeout(Duration::from_millis(50), statement)
+        .await
+        .is_err());
+    assert!(conn.is_closed());
+    assert!(matches!(
+        conn.has_table("missing").await,
+        Err(butane::Error::ConnectionCancelled)
+    ));
+}
+
+#[cfg(feature = "sqlite")]
+#[test]
+fn spawn_blocking_sqlite() {
+    let mut conn = common::sqlite_connection();
+    common::setup_db(
+        Box::new(butane::db::sqlite::SQLiteBackend::new()),
+        &mut conn,
+    );
+    let conn = nonblocking::SpawnBlocking::new(conn);
+    assert_eq!(conn.backend_name(), "sqlite");
+    block_on(save_and_load(&conn));
+}
+
+#[cfg(feature = "sqlite")]
+#[test]
+fn connect_async_sqlite() {
+    block_on(async {
+        let conn = nonblocking::connect_async(&common::sqlite_connspec())
+            .await
+            .unwrap();
+        assert_eq!(conn.backend_name(), "sqlite");
+        assert!(!conn.is_closed());
+        conn.execute("CREATE TABLE t (x INTEGER);").await.unwrap();
+        assert!(conn.has_table("t").await.unwrap());
+        assert!(!conn.has_table("missing").await.unwrap());
+    });
+}
+
+#[cfg(feature = "sqlite")]
+#[test]
+fn cancel_sqlite() {
+    block_on(async {
+        let conn = nonblocking::connect_async(&common::sqlite_connspec())
+            .await
+            .unwrap();
+        cancel_statement(&conn, SLOW_SQLITE).await;
+    });
+}
+
+#[cfg(feature = "pg")]
+#[test]
+fn connect_async_pg() {
+    let (spec, _data) = common::pg_connspec();
+    let mut sync_conn = butane::db::connect(&spec).unwrap();
+    common::setup_db(Box::new(butane::db::pg::PgBackend::new()), &mut sync_conn);
+    block_on(async {
+        let conn = nonblocking::connect_async(&spec).await.unwrap();
+        assert_eq!(conn.backend_name(), "pg");
+        save_and_load(&conn).await;
+    });
+}
+
+#[cfg(feature = "pg")]
+#[test]
+fn cancel_pg() {
+    let (spec, _data) = common::pg_connspec();
+    block_on(async {
+        let conn = nonblocking::connect_async(&spec).await.unwrap();
+        cancel_statement(&conn, "SELECT pg_sleep(2);").await;
+    });
+}
+
+#[cfg(all(feature = "sqlite", feature = "deadpool"))]
+#[test]
+fn deadpool_sqlite() {
+    use nonblocking::pool::{deadpool, PoolConfig};
+    block_on(async {
+        let config = PoolConfig::default().with_max_size(1).with_min_idle(1);
+        let pool = deadpool::new(common::sqlite_connspec(), &config)
+            .await
+            .unwrap();
+        assert_eq!(pool.status().max_size, 1);
+        assert_eq!(pool.status().available, 1);
+
+        let conn = pool.get().await.unwrap();
+        conn.execute("CREATE TABLE t (x INTEGER);").await.unwrap();
+        drop(conn);
+        let conn = pool.get().await.unwrap();
+        assert!(conn.has_table("t").await.unwrap());
+
+        // The cancelled connection fails recycling and is replaced
+        cancel_statement(&**conn, SLOW_SQLITE).await;
+        drop(conn);
+        let conn = pool.get().await.unwrap();
+        assert!(!conn.is_closed());
+        assert!(!conn.has_table("t").await.unwrap());
+    });
+}
+
+#[cfg(all(feature = "sqlite", feature = "bb8"))]
+#[test]
+fn bb8_sqlite() {
+    use nonblocking::pool::{bb8, PoolConfig};
+    block_on(async {
+        let config = PoolConfig::default().with_max_size(1).with_min_idle(1);
+        let pool = bb8::new(common::sqlite_connspec(), &config).await.unwrap();
+        assert_eq!(pool.state().connections, 1);
+        assert_eq!(pool.state().idle_connections, 1);
+
+        let conn = pool.get().await.unwrap();
+        conn.execute("CREATE TABLE t (x INTEGER);").await.unwrap();
+        drop(conn);
+        let conn = pool.get().await.unwrap();
+        assert!(conn.has_table("t").await.unwrap());
+
+        // The cancelled connection is found broken and replaced
+        cancel_statement(&**conn, SLOW_SQLITE).await;
+        drop(conn);
+        let conn = pool.get().await.unwrap();
+        assert!(!conn.is_closed());
+        assert!(!conn.has_table("t").await.unwrap());
+    });
+}
+
+#[cfg(all(feature = "pg", feature = "deadpool"))]
+#[test]
+fn deadpool_pg() {
+    use nonblocking::pool::{deadpool, PoolConfig};
+    let (spec, _data) = common::pg_connspec();
+    block_on(async {
+        let config = PoolConfig::default()
+            .with_max_size(1)
+            .with_create_timeout(Some(Duration::from_secs(10)))
+            .with_recycle_timeout(Some(Duration::from_secs(10)));
+        let pool = deadpool::new(spec, &config).await.unwrap();
+        assert_eq!(pool.status().size, 0);
+        let conn = pool.get().await.unwrap();
+        cancel_statement(&**conn, "SELECT pg_sleep(2);").await;
+        drop(conn);
+        let conn = pool.get().await.unwrap();
+        assert!(!conn.has_table("missing").await.unwrap());
+    });
+}
+
+/// The synchronous API, run on an asynchronous connection.
+fn blocking_save_and_load(conn: &BlockingConnection) {
+    let mut blog = Blog::new(1, "Cats");
+    blog.save(conn).unwrap();
+    assert_eq!(Blog::get(conn, 1).unwrap(), blog);
+
+    let mut post = Post::new(1, "The Tiger", "The tiger is a cat.", &blog);
+    post.save(conn).unwrap();
+    post.likes = 5;
+    post.save(conn).unwrap();
+    assert_eq!(query!(Post, likes == 5).load(conn).unwrap().len(), 1);
+    assert_eq!(
+        Post::query().update(conn, set!(published = true)).unwrap(),
+        1
+    );
+    assert!(Post::get(conn, 1).unwrap().published);
+
+    let mut note = AsyncNote::new("first");
+    note.save(conn).unwrap();
+    assert!(note.id > 0);
+    note.delete(conn).unwrap();
+    assert!(AsyncNote::get(conn, note.id).is_err());
+    assert!(conn.ping().is_ok());
+}
+
+#[cfg(feature = "sqlite")]
+#[test]
+fn blocking_sqlite() {
+    let path = std::env::temp_dir().join(format!("butane_blocking_{}.db", std::process::id()));
+    let path = path.to_str().unwrap();
+    let _ = std::fs::remove_file(path);
+    let spec = butane::db::ConnectionSpec::new("sqlite", path);
+    let mut sync_conn = butane::db::connect(&spec).unwrap();
+    common::setup_db(
+        Box::new(butane::db::sqlite::SQLiteBackend::new()),
+        &mut sync_conn,
+    );
+    let conn = BlockingConnection::connect(&spec).unwrap();
+    assert_eq!(conn.backend_name(), "sqlite");
+    blocking_save_and_load(&conn);
+    drop(sync_conn);
+    drop(conn);
+    std::fs::remove_file(path).unwrap();
+}
+
+#[cfg(feature = "pg")]
+#[test]
+fn blocking_pg() {
+    let (spec, _data) = common::pg_connspec();
+    let mut sync_conn = butane::db::connect(&spec).unwrap();
+    common::setup_db(Box::new(butane::db::pg::PgBackend::new()), &mut sync_conn);
+    let conn = BlockingConnection::connect(&spec).unwrap();
+    assert_eq!(conn.backend_name(), "pg");
+    blocking_save_and_load(&conn);
+}
@@ -1,14 +1,16 @@
-use butane::db::Connection;
+use butane::db::{Connection, ConnectionMethods};
 use butane::prelude::*;
-use butane::query::BoolExpr;
-use butane::{colname, filter, find, query, Many};
-use chrono::{TimeZone, Utc};
+use butane::query::{avg, count, max, min, sum, BoolExpr, Cursor, DatePart, Expr, OrderDirection};
+use butane::{
+    colname, dataresult, filter, find, query, set, ForeignKey, FromRow, Many, SqlVal, ToSql,
+};
+use chrono::{NaiveDateTime, TimeZone, Timelike, Utc};
 use paste;
 use serde_json;
 
 mod common;
 use common::blog;
-use common::blog::{Blog, Post, PostMetadata, Tag};
+use common::blog::{Blog, Post, PostApi, PostMetadata, Tag};
 
 fn equality(conn: Connection) {
     blog::setup_blog(&conn);
@@ -32,6 +34,30 @@ fn equality_separate_dataresult(conn: Connection) {
 }
 testall!(equality_separate_dataresult);
 
+fn projection(conn: Connection) {
+    blog::setup_blog(&conn);
+    let mut posts = PostApi::list(&conn, Some(filter!(Post, likes >= 10))).unwrap();
+    assert_eq!(posts.len(), 2);
+    posts.sort_by_key(|p| p.id);
+    assert_eq!(posts[0].title, "Sir Charles");
+    assert_eq!(posts[0].excerpt, "Sir Charles (the");
+    assert_eq!(posts[1].title, "Mount Doom");
+    assert_eq!(PostApi::list(&conn, None).unwrap().len(), 4);
+
+    let post = PostApi::get(&conn, 1).unwrap();
+    assert_eq!(post.excerpt, "The tiger is");
+    let json = serde_json::to_value(&post).unwrap();
+    assert_eq!(json["excerpt"], "The tiger is");
+    assert_eq!(json["title"], "The Tiger");
+    let post: PostApi = serde_json::from_value(json).unwrap();
+    assert_eq!(post.id, 1);
+    assert!(matches!(
+        PostApi::get(&conn, 42),
+        Err(butane::Error::NoSuchObject)
+    ));
+}
+testall!(projection);
+
 fn ordered(conn: Connection) {
     blog::setup_blog(&conn);
     let posts = query!(Post, published == true)
@@ -45,6 +71,36 @@ fn ordered(conn: Connection) {
 }
 testall!(ordered);
 
+fn ordered_by(conn: Connection) {
+    blog::setup_blog(&conn);
+    let titles = |posts: Vec<Post>| posts.into_iter().map(|p| p.title).collect::<Vec<_>>();
+    let posts = Post::query()
+        .filter(filter!(Post, published == true))
+        .order_by(Post::fields().likes(), OrderDirection::Descending)
+        .load(&conn)
+        .unwrap();
+    assert_eq!(titles(posts), ["Sir Charles", "Mount Doom", "The Tiger"]);
+
+    let posts = query!(Post, order_by(blog desc, title))
+        .load(&conn)
+        .unwrap();
+    assert_eq!(
+        titles(posts),
+        ["Mount Doom", "Mt. Everest", "Sir Charles", "The Tiger"]
+    );
+
+    // Only The Tiger has a pub_time
+    let posts = query!(Post, published == true, order_by(pub_time nulls_first, title))
+        .load(&conn)
+        .unwrap();
+    assert_eq!(titles(posts), ["Mount Doom", "Sir Charles", "The Tiger"]);
+    let posts = query!(Post, published == true, order_by(pub_time desc nulls_last, title desc))
+        .load(&conn)
+        .unwrap();
+    assert_eq!(titles(posts), ["The Tiger", "Sir Charles", "Mount Doom"]);
+}
+testall!(ordered_by);
+
 fn comparison(conn: Connection) {
     blog::setup_blog(&conn);
     let mut posts = query!(Post, likes < 5).load(&conn).unwrap();
@@ -65,6 +121,440 @@ fn like(conn: Connection) {
 }
 testall!(like);
 
+fn string_match(conn: Connection) {
+    blog::setup_blog(&conn);
+    let blog: Blog = find!(Blog, name == "Cats", &conn).unwrap();
+    for (id, title) in [(5, "100% Cats"), (6, "1000 Cats"), (7, "Cats_and\\Dogs")] {
+        Post::new(id, title, "", &blog).save(&conn).unwrap();
+    }
+    let titles = |filter| -> Vec<String> {
+        let mut posts = Post::query().filter(filter).load(&conn).unwrap();
+        posts.sort_by_key(|p| p.id);
+        posts.into_iter().map(|p| p.title).collect()
+    };
+
+    assert_eq!(titles(filter!(Post, title.ilike("the %"))), ["The Tiger"]);
+    assert_eq!(titles(filter!(Post, title.like("100\\%%"))), ["100% Cats"]);
+
+    // Wildcards in the operand are matched literally
+    assert_eq!(
+        titles(filter!(Post, title.starts_with("100%"))),
+        ["100% Cats"]
+    );
+    assert_eq!(
+        titles(filter!(Post, title.ends_with("Cats"))),
+        ["100% Cats", "1000 Cats"]
+    );
+    assert_eq!(
+        titles(filter!(Post, title.contains("_and\\"))),
+        ["Cats_and\\Dogs"]
+    );
+    assert_eq!(
+        titles(filter!(Post, title.contains("s_a"))),
+        ["Cats_and\\Dogs"]
+    );
+    let word = "Tig";
+    assert_eq!(
+        titles(filter!(Post, title.contains({ word }))),
+        ["The Tiger"]
+    );
+    assert_eq!(
+        titles(filter!(
+            Post,
+            title.starts_with("Mt") || title.starts_with("Mo")
+        )),
+        ["Mount Doom", "Mt. Everest"]
+    );
+}
+testall!(string_match);
+
+fn ignore_case(conn: Connection) {
+    blog::setup_blog(&conn);
+    let blog = find!(Blog, name.eq_ignore_case("cATS"), &conn).unwrap();
+    assert_eq!(blog.name, "Cats");
+    let name = "MOUNTAINS".to_string();
+    let blog = find!(Blog, name.eq_ignore_case({ &name }), &conn).unwrap();
+    assert_eq!(blog.name, "Mountains");
+    // Not a pattern
+    assert!(query!(Blog, name.eq_ignore_case("cat%"))
+        .load(&conn)
+        .unwrap()
+        .is_empty());
+    let posts = query!(
+        Post,
+        title.eq_ignore_case("mt. everest") || title.ilike("%TIGER")
+    )
+    .load(&conn)
+    .unwrap();
+    assert_eq!(posts.len(), 2);
+}
+testall!(ignore_case);
+
+fn in_list(conn: Connection) {
+    blog::setup_blog(&conn);
+    let titles = |posts: Vec<Post>| -> Vec<String> { posts.into_iter().map(|p| p.title).collect() };
+
+    let ids = vec![1, 3];
+    let posts = query!(Post, id in ids, order_by(id)).load(&conn).unwrap();
+    assert_eq!(titles(posts), ["The Tiger", "Mount Doom"]);
+
+    let posts = query!(Post, title in ["Mt. Everest", "Sir Charles"] && published == true)
+        .load(&conn)
+        .unwrap();
+    assert_eq!(titles(posts), ["Sir Charles"]);
+
+    let posts = query!(Post, id in Vec::<i64>::new()).load(&conn).unwrap();
+    assert!(posts.is_empty());
+    let posts = query!(Post, id in Vec::<i64>::new() || likes > 15)
+        .load(&conn)
+        .unwrap();
+    assert_eq!(titles(posts), ["Sir Charles"]);
+
+    // Through a foreign key, and as a method
+    let posts = query!(Post, blog.name in ["Mountains"], order_by(id))
+        .load(&conn)
+        .unwrap();
+    assert_eq!(titles(posts), ["Mount Doom", "Mt. Everest"]);
+    let post = find!(Post, id.is_in(vec![2]), &conn).unwrap();
+    assert_eq!(post.title, "Sir Charles");
+
+    // Lists longer than a backend accepts in one IN are split
+    let ids: Vec<i64> = (4..5000).collect();
+    let posts = query!(Post, id in ids).load(&conn).unwrap();
+    assert_eq!(titles(posts), ["Mt. Everest"]);
+    let mut names: Vec<String> = (0..5000).map(|i| format!("blog {i}")).collect();
+    names.push("Cats".to_string());
+    let blogs = query!(Blog, name in names).load(&conn).unwrap();
+    assert_eq!(blogs.len(), 1);
+    assert_eq!(blogs[0].name, "Cats");
+
+    // Lists of more values than a statement may have parameters
+    let mut names: Vec<String> = (0..70000).map(|i| format!("blog {i}")).collect();
+    names.push("Mountains".to_string());
+    let blogs = query!(Blog, name in names).load(&conn).unwrap();
+    assert_eq!(blogs.len(), 1);
+    assert_eq!(blogs[0].name, "Mountains");
+    let sql = format!(
+        "SELECT id FROM Blog WHERE id IN ({})",
+        ["?"; 70000].join(", ")
+    );
+    let result: butane::Result<Vec<(i64,)>> = conn.query_raw(&sql, &vec![SqlVal::Int(0); 70000]);
+    match conn.backend_name() {
+        "pg" => assert!(matches!(
+            result,
+            Err(butane::Error::TooManyParameters(70000, "pg", 65535))
+        )),
+        _ => assert!(result.is_err()),
+    }
+}
+testall!(in_list);
+
+fn null_checks(conn: Connection) {
+    blog::setup_blog(&conn);
+    let posts = query!(Post, pub_time.is_not_null()).load(&conn).unwrap();
+    assert_eq!(posts.len(), 1);
+    assert_eq!(posts[0].title, "The Tiger");
+
+    let mut posts = query!(Post, pub_time.is_null() && published == true)
+        .load(&conn)
+        .unwrap();
+    posts.sort_by_key(|p| p.id);
+    assert_eq!(posts.len(), 2);
+    assert_eq!(posts[0].title, "Sir Charles");
+    assert_eq!(posts[1].title, "Mount Doom");
+
+    // Comparing with None, as a literal or a value, is equivalent
+    let none: Option<NaiveDateTime> = None;
+    assert_eq!(query!(Post, pub_time == None).load(&conn).unwrap().len(), 3);
+    assert_eq!(
+        query!(Post, pub_time == { none })
+            .load(&conn)
+            .unwrap()
+            .len(),
+        3
+    );
+    assert_eq!(query!(Post, pub_time != None).load(&conn).unwrap().len(), 1);
+}
+testall!(null_checks);
+
+fn raw_sql(conn: Connection) {
+    blog::setup_blog(&conn);
+    let mut posts = Post::query()
+        .filter(BoolExpr::raw("likes * 2 > ? AND title <> 'Why?'", [15]))
+        .load(&conn)
+        .unwrap();
+    posts.sort_by_key(|p| p.id);
+    assert_eq!(posts.len(), 2);
+    assert_eq!(posts[0].title, "Sir Charles");
+    assert_eq!(posts[1].title, "Mount Doom");
+    let posts = Post::query()
+        .filter(BoolExpr::raw("UPPER(title) = ?", ["THE TIGER"]))
+        .load(&conn)
+        .unwrap();
+    assert_eq!(posts.len(), 1);
+
+    let blogs: Vec<Blog> = conn
+        .query_raw(
+            "SELECT id, name FROM Blog WHERE name = ?",
+            &["Cats".to_sql()],
+        )
+        .unwrap();
+    assert_eq!(blogs.len(), 1);
+    assert_eq!(blogs[0].name, "Cats");
+
+    let counts: Vec<(String, i64)> = conn
+        .query_raw(
+            "SELECT b.name, COUNT(*) FROM Blog b JOIN Post p ON p.blog = b.id \
+             WHERE p.likes >= ? GROUP BY b.name ORDER BY b.name",
+            &[SqlVal::Int(0)],
+        )
+        .unwrap();
+    assert_eq!(
+        counts,
+        [("Cats".to_string(), 2), ("Mountains".to_string(), 2)]
+    );
+
+    let result: butane::Result<Vec<(i64,)>> =
+        conn.query_raw("SELECT id FROM Blog WHERE id = ?", &[]);
+    assert!(matches!(
+        result,
+        Err(butane::Error::RawParameterCount(1, 0))
+    ));
+}
+testall!(raw_sql);
+
+#[dataresult(Post)]
+struct PostState {
+    published: bool,
+}
+
+fn distinct(conn: Connection) {
+    blog::setup_blog(&conn);
+    let states = PostState::query()
+        .distinct()
+        .order_by(Post::fields().published(), OrderDirection::Ascending)
+        .load(&conn)
+        .unwrap();
+    let states: Vec<bool> = states.into_iter().map(|s| s.published).collect();
+    assert_eq!(states, [false, true]);
+    assert_eq!(PostState::query().load(&conn).unwrap().len(), 4);
+
+    // The most liked post of each blog
+    let posts = Post::query()
+        .distinct_on(Post::fields().blog())
+        .order_by(Post::fields().blog(), OrderDirection::Ascending)
+        .order_by(Post::fields().likes(), OrderDirection::Descending)
+        .load(&conn);
+    if conn.backend_name() == "pg" {
+        let titles: Vec<String> = posts.unwrap().into_iter().map(|p| p.title).collect();
+        assert_eq!(titles, ["Sir Charles", "Mount Doom"]);
+    } else {
+        assert!(matches!(
+            posts,
+            Err(butane::Error::DistinctOnUnsupported(_))
+        ));
+    }
+}
+testall!(distinct);
+
+fn select(conn: Connection) {
+    blog::setup_blog(&conn);
+    let posts = query!(Post, published == true)
+        .order_by(Post::fields().likes(), OrderDirection::Descending)
+        .select((Post::fields().id(), Post::fields().title()))
+        .load(&conn)
+        .unwrap();
+    assert_eq!(
+        posts,
+        [
+            (2, "Sir Charles".to_string()),
+            (3, "Mount Doom".to_string()),
+            (1, "The Tiger".to_string())
+        ]
+    );
+
+    let title = Post::query()
+        .order_by(Post::fields().likes(), OrderDirection::Ascending)
+        .select(Post::fields().title())
+        .load_first(&conn)
+        .unwrap();
+    assert_eq!(title, Some("Mt. Everest".to_string()));
+}
+testall!(select);
+
+#[derive(FromRow)]
+struct BlogLikes {
+    sum_likes: Option<i64>,
+    blog: ForeignKey<Blog>,
+    posts: i64,
+}
+
+#[derive(Debug, PartialEq, FromRow)]
+struct Totals {
+    count: i64,
+    sum_likes: Option<i64>,
+}
+
+#[derive(Debug, PartialEq, FromRow)]
+struct IdTitle {
+    title: String,
+    id: i64,
+}
+
+// Never read, as the fields do not match their columns
+#[allow(dead_code)]
+#[derive(FromRow)]
+struct RequiredSum {
+    sum_likes: i64,
+}
+
+#[allow(dead_code)]
+#[derive(FromRow)]
+struct NarrowCount {
+    count: i32,
+}
+
+fn from_row(conn: Connection) {
+    blog::setup_blog(&conn);
+    let fields = Post::fields();
+    let groups: Vec<BlogLikes> = Post::query()
+        .group_by(fields.blog())
+        .aggregate_into(&conn, (count().named("posts"), sum(fields.likes())))
+        .unwrap();
+    let groups: Vec<(i64, i64, Option<i64>)> = groups
+        .into_iter()
+        .map(|g| (g.blog.pk(), g.posts, g.sum_likes))
+        .collect();
+    assert_eq!(groups, [(1, 2, Some(24)), (2, 2, Some(10))]);
+
+    // Aggregates of no objects are null, other than the count
+    let totals: Totals = query!(Post, likes > 100)
+        .aggregate_into(&conn, (count(), sum(fields.likes())))
+        .unwrap();
+    assert_eq!(
+        totals,
+        Totals {
+            count: 0,
+            sum_likes: None
+        }
+    );
+
+    let posts: Vec<IdTitle> = query!(Post, published == true)
+        .order_by(fields.likes(), OrderDirection::Descending)
+        .select((fields.id(), fields.title()))
+        .load_into(&conn)
+        .unwrap();
+    assert_eq!(posts.iter().map(|p| p.id).collect::<Vec<_>>(), [2, 3, 1]);
+    assert_eq!(posts[0].title, "Sir Charles");
+
+    // Fields are checked against the columns before the query is run
+    assert!(matches!(
+        Post::query().aggregate_into::<Totals, _>(&conn, count()),
+        Err(butane::Error::NoSuchColumn(col)) if col == "sum_likes"
+    ));
+    assert!(matches!(
+        Post::query().aggregate_into::<RequiredSum, _>(&conn, sum(fields.likes())),
+        Err(butane::Error::SqlResultTypeMismatch { col, .. }) if col == "sum_likes"
+    ));
+    assert!(matches!(
+        Post::query().aggregate_into::<NarrowCount, _>(&conn, count()),
+        Err(butane::Error::SqlResultTypeMismatch { col, .. }) if col == "count"
+    ));
+    assert!(matches!(
+        Post::query()
+            .select(fields.title())
+            .load_into::<IdTitle>(&conn),
+        Err(butane::Error::NoSuchColumn(col)) if col == "id"
+    ));
+}
+testall!(from_row);
+
+fn bulk_update(conn: Connection) {
+    blog::setup_blog(&conn);
+    let updated = query!(Post, blog == 2)
+        .update(&conn, set!(published = true, likes = 1))
+        .unwrap();
+    assert_eq!(updated, 2);
+    let posts = query!(Post, blog == 2, order_by(id)).load(&conn).unwrap();
+    assert!(posts.iter().all(|p| p.published && p.likes == 1));
+    // The posts of the other blog are unchanged
+    let tiger = Post::get(&conn, 1).unwrap();
+    assert_eq!(tiger.likes, 4);
+
+    let updated = Post::query()
+        .update(&conn, set!(title = "Untitled", pub_time = None))
+        .unwrap();
+    assert_eq!(updated, 4);
+    let post = Post::get(&conn, 3).unwrap();
+    assert_eq!(post.title, "Untitled");
+    assert_eq!(post.pub_time, None);
+
+    // Would update more posts than the query loads
+    for query in [
+        Post::query().limit(1),
+        Post::query().offset(1),
+        query!(Post, order_by(id)),
+    ] {
+        assert!(matches!(
+            query.update(&conn, set!(likes = 0)),
+            Err(butane::Error::BulkWriteUnsupported(_))
+        ));
+    }
+    assert_eq!(Post::get(&conn, 1).unwrap().likes, 4);
+}
+testall!(bulk_update);
+
+fn bulk_delete(conn: Connection) {
+    blog::setup_blog(&conn);
+    // Would delete more posts than the query loads
+    for query in [
+        Post::query().limit(1),
+        Post::query().offset(3),
+        query!(Post, order_by(id)),
+    ] {
+        assert!(matches!(
+            query.delete(&conn),
+            Err(butane::Error::BulkWriteUnsupported(_))
+        ));
+    }
+    assert_eq!(Post::query().load(&conn).unwrap().len(), 4);
+
+    let deleted = query!(Post, blog == 2).delete(&conn).unwrap();
+    assert_eq!(deleted, 2);
+    let deleted = query!(Post, blog == 2).delete(&conn).unwrap();
+    assert_eq!(deleted, 0);
+    let deleted = Post::query().delete(&conn).unwrap();
+    assert_eq!(deleted, 2);
+    assert!(Post::query().load(&conn).unwrap().is_empty());
+}
+testall!(bulk_delete);
+
+fn exists_related(conn: Connection) {
+    blog::setup_blog(&conn);
+    // Blogs with an unpublished post
+    let blogs = query!(Blog, exists(Post.blog, published == false))
+        .load(&conn)
+        .unwrap();
+    let names: Vec<String> = blogs.into_iter().map(|b| b.name).collect();
+    assert_eq!(names, ["Mountains"]);
+
+    // Blogs without a post with over 15 likes
+    let blogs = query!(Blog, !exists(Post.blog, likes > 15), order_by(id))
+        .load(&conn)
+        .unwrap();
+    let names: Vec<String> = blogs.into_iter().map(|b| b.name).collect();
+    assert_eq!(names, ["Mountains"]);
+
+    let blogs = query!(
+        Blog,
+        name == "Cats" && exists(Post.blog, title.starts_with("The"))
+    )
+    .load(&conn)
+    .unwrap();
+    assert_eq!(blogs.len(), 1);
+}
+testall!(exists_related);
+
 fn combination(conn: Connection) {
     blog::setup_blog(&conn);
     let posts = query!(Post, published == true && likes < 5)
@@ -72,9 +562,72 @@ fn combination(conn: Connection) {
         .unwrap();
     assert_eq!(posts.len(), 1);
     assert_eq!(posts[0].title, "The Tiger");
+
+    // Nested conditions keep their grouping
+    let posts = query!(Post, published == false && (likes < 5 || likes > 15))
+        .load(&conn)
+        .unwrap();
+    assert_eq!(posts.len(), 1);
+    assert_eq!(posts[0].title, "Mt. Everest");
+    let posts = query!(Post, !(likes > 5 || published == false))
+        .load(&conn)
+        .unwrap();
+    assert_eq!(posts.len(), 1);
+    assert_eq!(posts[0].title, "The Tiger");
 }
 testall!(combination);
 
+fn dynamic_filter(conn: Connection) {
+    blog::setup_blog(&conn);
+    let titles = |expr: BoolExpr| {
+        let posts = Post::query()
+            .filter(expr)
+            .order_asc(colname!(Post, id))
+            .load(&conn)
+            .unwrap();
+        posts.into_iter().map(|p| p.title).collect::<Vec<_>>()
+    };
+    // Conditions as a search screen might collect them
+    let search: [(&str, Option<SqlVal>); 3] = [
+        ("title", Some("M%".to_sql())),
+        ("likes", Some(5.to_sql())),
+        ("published", None),
+    ];
+    let mut conds = Vec::new();
+    for (name, value) in search {
+        let Some(value) = value else { continue };
+        let col = Expr::col::<Post>(name).unwrap();
+        let cond = match name {
+            "title" => col.like(value),
+            "likes" => col.gt(value),
+            _ => col.eq(value),
+        };
+        conds.push(cond.unwrap());
+    }
+    assert_eq!(titles(BoolExpr::all(conds)), ["Mount Doom"]);
+    assert_eq!(titles(BoolExpr::all([])).len(), 4);
+
+    let likes = Expr::col::<Post>("likes").unwrap();
+    let expr = likes
+        .lt(1)
+        .unwrap()
+        .or(Expr::col::<Post>("title").unwrap().eq("The Tiger").unwrap());
+    assert_eq!(titles(expr.clone()), ["The Tiger", "Mt. Everest"]);
+    assert_eq!(titles(!expr), ["Sir Charles", "Mount Doom"]);
+    let expr = Expr::col::<Post>("blog").unwrap().eq(2i64).unwrap();
+    assert_eq!(titles(expr), ["Mount Doom", "Mt. Everest"]);
+
+    assert!(matches!(
+        Expr::col::<Post>("rating"),
+        Err(butane::Error::NoSuchColumn(_))
+    ));
+    assert!(matches!(
+        likes.eq("ten"),
+        Err(butane::Error::CannotConvertSqlVal(..))
+    ));
+}
+testall!(dynamic_filter);
+
 fn combination_allof(conn: Connection) {
     blog::setup_blog(&conn);
     let posts = Post::query()
@@ -122,6 +675,7 @@ fn fkey_match(conn: Connection) {
     let posts4 = query!(Post, blog.matches(name == "Cats"))
         .load(&conn)
         .unwrap();
+    let posts5 = query!(Post, blog.name == "Cats").load(&conn).unwrap();
 
     assert_eq!(posts.len(), 2);
     posts.sort_by(|p1, p2| p1.id.partial_cmp(&p2.id).unwrap());
@@ -130,9 +684,90 @@ fn fkey_match(conn: Connection) {
     assert_eq!(posts, posts2);
     assert_eq!(posts, posts3);
     assert_eq!(posts, posts4);
+    assert_eq!(posts, posts5);
+
+    let posts = query!(Post, blog.name.like("Mount%") && published == true)
+        .load(&conn)
+        .unwrap();
+    assert_eq!(posts.len(), 1);
+    assert_eq!(posts[0].title, "Mount Doom");
 }
 testall!(fkey_match);
 
+fn exists(conn: Connection) {
+    blog::setup_blog(&conn);
+    Blog::new(3, "Empty").save(&conn).unwrap();
+
+    let blogs = Blog::query()
+        .where_not_exists(colname!(Blog, id), Post::query(), colname!(Post, blog))
+        .load(&conn)
+        .unwrap();
+    assert_eq!(blogs.len(), 1);
+    assert_eq!(blogs[0].name, "Empty");
+
+    // Combined with the query's own filter
+    let blogs = query!(Blog, name != "Cats")
+        .where_exists(
+            colname!(Blog, id),
+            query!(Post, published == false || likes > 100),
+            colname!(Post, blog),
+        )
+        .load(&conn)
+        .unwrap();
+    assert_eq!(blogs.len(), 1);
+    assert_eq!(blogs[0].name, "Mountains");
+
+    let deleted = Blog::query()
+        .where_not_exists(colname!(Blog, id), Post::query(), colname!(Post, blog))
+        .delete(&conn)
+        .unwrap();
+    assert_eq!(deleted, 1);
+    assert_eq!(Blog::query().load(&conn).unwrap().len(), 2);
+}
+testall!(exists);
+
+fn subquery(conn: Connection) {
+    blog::setup_blog(&conn);
+    Blog::new(3, "Empty").save(&conn).unwrap();
+
+    let mut blogs = Blog::query()
+        .where_in(
+            colname!(Blog, id),
+            query!(Post, likes > 15),
+            colname!(Post, blog),
+        )
+        .load(&conn)
+        .unwrap();
+    assert_eq!(blogs.len(), 1);
+    assert_eq!(blogs.pop().unwrap().name, "Cats");
+
+    let mut blogs = Blog::query()
+        .where_not_in(
+            colname!(Blog, id),
+            query!(Post, likes > 15),
+            colname!(Post, blog),
+        )
+        .load(&conn)
+        .unwrap();
+    blogs.sort_by_key(|b| b.id);
+    assert_eq!(blogs.len(), 2);
+    assert_eq!(blogs[0].name, "Mountains");
+    assert_eq!(blogs[1].name, "Empty");
+
+    // Posts of the blogs named "Mountains", combined with their own filter
+    let posts = query!(Post, published == true)
+        .where_in(
+            colname!(Post, blog),
+            query!(Blog, name == "Mountains"),
+            colname!(Blog, id),
+        )
+        .load(&conn)
+        .unwrap();
+    assert_eq!(posts.len(), 1);
+    assert_eq!(posts[0].title, "Mount Doom");
+}
+testall!(subquery);
+
 fn many_load(conn: Connection) {
     blog::setup_blog(&conn);
     let post: Post = find!(Post, title == "The Tiger", &conn).unwrap();
@@ -204,6 +839,58 @@ fn by_timestamp(conn: Connection) {
 }
 testall!(by_timestamp);
 
+#[cfg(feature = "sqlite")]
+#[test]
+fn by_timestamp_sqlite_formats() {
+    use butane::db::sqlite::SQLiteBackend;
+    use butane::db::{Backend, TimestampFormat};
+
+    let published = Utc
+        .ymd(2023, 1, 31)
+        .and_hms_micro(12, 34, 56, 123456)
+        .naive_utc();
+    for (format, stored) in [
+        (TimestampFormat::Text, published.with_nanosecond(0)),
+        (TimestampFormat::Rfc3339, Some(published)),
+        (TimestampFormat::UnixSeconds, published.with_nanosecond(0)),
+        (
+            TimestampFormat::UnixMillis,
+            published.with_nanosecond(123_000_000),
+        ),
+    ] {
+        let backend = SQLiteBackend::new().with_timestamp_format(format);
+        let mut conn = backend.connect(":memory:").unwrap();
+        common::setup_db(Box::new(backend), &mut conn);
+        blog::setup_blog(&conn);
+        let mut post = find!(Post, title == "Mount Doom", &conn).unwrap();
+        post.pub_time = Some(published);
+        post.save(&conn).unwrap();
+        let post = find!(Post, title == "Mount Doom", &conn).unwrap();
+        assert_eq!(post.pub_time, stored, "{:?}", format);
+
+        // Comparisons and ordering still follow time order
+        let mut post = find!(Post, title == "The Tiger", &conn).unwrap();
+        post.pub_time = Some(Utc.ymd(1970, 5, 1).and_hms(1, 1, 1).naive_utc());
+        post.save(&conn).unwrap();
+        let posts = query!(Post, pub_time < { published })
+            .order_desc(colname!(Post, pub_time))
+            .load(&conn)
+            .unwrap();
+        assert_eq!(posts[0].title, "The Tiger", "{:?}", format);
+
+        // As do date functions
+        let posts = query!(
+            Post,
+            pub_time.date_trunc(Month) == { Utc.ymd(2023, 1, 1).and_hms(0, 0, 0).naive_utc() }
+                && pub_time.extract(Second) == 56
+        )
+        .load(&conn)
+        .unwrap();
+        assert_eq!(posts.len(), 1, "{:?}", format);
+        assert_eq!(posts[0].title, "Mount Doom", "{:?}", format);
+    }
+}
+
 fn limit(conn: Connection) {
     blog::setup_blog(&conn);
     let posts = Post::query()
@@ -230,3 +917,264 @@ fn offset(conn: Connection) {
     assert_eq!(posts[1].title, "The Tiger");
 }
 testall!(offset);
+
+fn paginated(conn: Connection) {
+    blog::setup_blog(&conn);
+    let page = |n: i32| {
+        Post::query()
+            .order_asc(colname!(Post, title))
+            .limit(3)
+            .offset(3 * n)
+    };
+    let titles = |posts: Vec<Post>| posts.into_iter().map(|p| p.title).collect::<Vec<_>>();
+    assert_eq!(
+        titles(page(0).load(&conn).unwrap()),
+        ["Mount Doom", "Mt. Everest", "Sir Charles"]
+    );
+    assert_eq!(titles(page(1).load(&conn).unwrap()), ["The Tiger"]);
+    assert!(page(2).load(&conn).unwrap().is_empty());
+
+    // The first result is taken after the offset, in order
+    let post = page(1).load_first(&conn).unwrap().unwrap();
+    assert_eq!(post.title, "The Tiger");
+    let post = Post::query()
+        .order_desc(colname!(Post, likes))
+        .load_first(&conn)
+        .unwrap()
+        .unwrap();
+    assert_eq!(post.title, "Sir Charles");
+}
+testall!(paginated);
+
+fn keyset_paginated(conn: Connection) {
+    blog::setup_blog(&conn);
+    let ids = |posts: Vec<Post>| posts.into_iter().map(|p| p.id).collect::<Vec<_>>();
+    let pages = Post::query().paginate(Post::fields().likes(), OrderDirection::Ascending, 2);
+    let page = pages.page(&conn, None).unwrap();
+    assert_eq!(ids(page.items), [4, 1]);
+    // The cursor survives being passed around as a string
+    let cursor = Cursor::decode(&page.next.unwrap().encode()).unwrap();
+    let page = pages.page(&conn, Some(&cursor)).unwrap();
+    assert_eq!(ids(page.items), [3, 2]);
+    assert!(page.next.is_none());
+
+    // Equal keys are ordered by primary key, and the filter applies
+    let pages = query!(Post, likes < 15).paginate(
+        Post::fields().published(),
+        OrderDirection::Descending,
+        1,
+    );
+    let page = pages.page(&conn, None).unwrap();
+    assert_eq!(ids(page.items), [3]);
+    let page = pages.page(&conn, page.next.as_ref()).unwrap();
+    assert_eq!(ids(page.items), [1]);
+    let page = pages.page(&conn, page.next.as_ref()).unwrap();
+    assert_eq!(ids(page.items), [4]);
+    assert!(page.next.is_none());
+
+    assert!(matches!(
+        Cursor::decode("not a cursor"),
+        Err(butane::Error::InvalidCursor)
+    ));
+}
+testall!(keyset_paginated);
+
+fn aggregates(conn: Connection) {
+    blog::setup_blog(&conn);
+    assert_eq!(Post::query().count(&conn).unwrap(), 4);
+    assert_eq!(query!(Post, published == true).count(&conn).unwrap(), 3);
+    assert_eq!(query!(Post, likes > 100).count(&conn).unwrap(), 0);
+
+    let fields = Post::fields();
+    let (posts, likes, fewest, most) = query!(Post, published == true)
+        .aggregate(
+            &conn,
+            (
+                count(),
+                sum(fields.likes()),
+                min(fields.likes()),
+                max(fields.title()),
+            ),
+        )
+        .unwrap();
+    assert_eq!(posts, 3);
+    assert_eq!(likes, Some(34));
+    assert_eq!(fewest, Some(4));
+    assert_eq!(most, Some("The Tiger".to_string()));
+    let mean = query!(Post, published == true)
+        .aggregate(&conn, avg(fields.likes()))
+        .unwrap()
+        .unwrap();
+    assert!((mean - 34.0 / 3.0).abs() < 1e-9);
+
+    // Aggregates of no objects are null, other than the count
+    let (posts, likes) = query!(Post, likes > 100)
+        .aggregate(&conn, (count(), sum(fields.likes())))
+        .unwrap();
+    assert_eq!(posts, 0);
+    assert_eq!(likes, None);
+}
+testall!(aggregates);
+
+fn grouped(conn: Connection) {
+    blog::setup_blog(&conn);
+    let fields = Post::fields();
+    let groups = Post::query()
+        .group_by(fields.blog())
+        .aggregate(&conn, (count(), sum(fields.likes())))
+        .unwrap();
+    let groups: Vec<(i64, (i64, Option<i64>))> = groups
+        .into_iter()
+        .map(|(blog, aggregates)| (blog.pk(), aggregates))
+        .collect();
+    assert_eq!(groups, [(1, (2, Some(24))), (2, (2, Some(10)))]);
+
+    let groups = Post::query()
+        .group_by((fields.blog(), fields.published()))
+        .count(&conn)
+        .unwrap();
+    let groups: Vec<(i64, bool, i64)> = groups
+        .into_iter()
+        .map(|((blog, published), count)| (blog.pk(), published, count))
+        .collect();
+    assert_eq!(groups, [(1, true, 2), (2, false, 1), (2, true, 1)]);
+
+    let groups = query!(Post, published == true)
+        .group_by(fields.blog())
+        .having(sum(fields.likes()).gt(15))
+        .count(&conn)
+        .unwrap();
+    assert_eq!(groups.len(), 1);
+    assert_eq!(groups[0].0.pk(), 1);
+    assert_eq!(groups[0].1, 2);
+
+    // Conditions given together must all hold
+    let groups = Post::query()
+        .group_by(fields.blog())
+        .having(count().ge(2))
+        .having(
+            max(fields.title())
+                .lt("Sir")
+                .or(avg(fields.likes()).gt(100.0)),
+        )
+        .count(&conn)
+        .unwrap();
+    assert_eq!(groups.len(), 1);
+    assert_eq!(groups[0].0.pk(), 2);
+}
+testall!(grouped);
+
+fn date_functions(conn: Connection) {
+    blog::setup_blog(&conn);
+    let time = |month, day, hour, min| Utc.ymd(2023, month, day).and_hms(hour, min, 0).naive_utc();
+    for (title, pub_time) in [
+        ("The Tiger", time(1, 15, 10, 0)),
+        ("Sir Charles", time(1, 31, 23, 59)),
+        ("Mount Doom", time(3, 2, 8, 30)),
+    ] {
+        let mut post = find!(Post, title == { title }, &conn).unwrap();
+        post.pub_time = Some(pub_time);
+        post.save(&conn).unwrap();
+    }
+    let titles = |posts: Vec<Post>| posts.into_iter().map(|p| p.title).collect::<Vec<_>>();
+
+    let posts = query!(
+        Post,
+        pub_time.between({ time(1, 1, 0, 0) }, { time(1, 31, 23, 59) })
+    )
+    .order_asc(colname!(Post, pub_time))
+    .load(&conn)
+    .unwrap();
+    assert_eq!(titles(posts), ["The Tiger", "Sir Charles"]);
+
+    let posts = query!(Post, likes.between(5, 10)).load(&conn).unwrap();
+    assert_eq!(titles(posts), ["Mount Doom"]);
+
+    let posts = query!(
+        Post,
+        pub_time.extract(Month) == 1 && pub_time.extract(Hour) >= 12
+    )
+    .load(&conn)
+    .unwrap();
+    assert_eq!(titles(posts), ["Sir Charles"]);
+
+    let posts = query!(Post, pub_time.date_trunc(Month) == { time(3, 1, 0, 0) })
+        .load(&conn)
+        .unwrap();
+    assert_eq!(titles(posts), ["Mount Doom"]);
+
+    let posts = query!(Post, pub_time.extract(Day).between(2, 15))
+        .order_asc(colname!(Post, title))
+        .load(&conn)
+        .unwrap();
+    assert_eq!(titles(posts), ["Mount Doom", "The Tiger"]);
+
+    // Posts per month
+    let fields = Post::fields();
+    let months = query!(Post, pub_time.is_not_null())
+        .group_by(fields.pub_time().date_trunc(DatePart::Month))
+        .count(&conn)
+        .unwrap();
+    assert_eq!(
+        months,
+        [(Some(time(1, 1, 0, 0)), 2), (Some(time(3, 1, 0, 0)), 1)]
+    );
+
+    let groups = query!(Post, published == true)
+        .group_by((fields.blog(), fields.pub_time().extract(DatePart::Year)))
+        .count(&conn)
+        .unwrap();
+    let groups: Vec<(i64, Option<i32>, i64)> = groups
+        .into_iter()
+        .map(|((blog, year), count)| (blog.pk(), year, count))
+        .collect();
+    assert_eq!(groups, [(1, Some(2023), 2), (2, Some(2023), 1)]);
+
+    let days = query!(Post, title == "Mount Doom")
+        .select(fields.pub_time().extract(DatePart::Day))
+        .load(&conn)
+        .unwrap();
+    assert_eq!(days, [Some(2)]);
+}
+testall!(date_functions);
+
+fn explain(conn: Connection) {
+    blog::setup_blog(&conn);
+    let plan = query!(Post, published == true)
+        .order_asc(colname!(Post, title))
+        .explain(&conn)
+        .unwrap();
+    assert!(plan.sql.starts_with("SELECT"), "{}", plan.sql);
+    assert!(!plan.nodes.is_empty());
+    assert!(plan
+        .iter()
+        .any(|node| node.detail.to_lowercase().contains("post")));
+
+    let plan = query!(Post, published == true)
+        .explain_analyze(&conn)
+        .unwrap();
+    assert!(!plan.nodes.is_empty());
+}
+testall!(explain);
+
+fn stream(conn: Connection) {
+    blog::setup_blog(&conn);
+    let titles: Vec<String> = Post::query()
+        .order_asc(colname!(Post, title))
+        .stream(&conn)
+        .unwrap()
+        .map(|post| post.map(|post| post.title))
+        .collect::<butane::Result<Vec<String>>>()
+        .unwrap();
+    assert_eq!(
+        titles,
+        ["Mount Doom", "Mt. Everest", "Sir Charles", "The Tiger"]
+    );
+
+    // The connection is usable again once the stream is dropped
+    let mut posts = query!(Post, published == true).stream(&conn).unwrap();
+    assert!(posts.next().unwrap().is_ok());
+    drop(posts);
+    assert_eq!(Post::query().load(&conn).unwrap().len(), 4);
+}
+testall!(stream);
@@ -25,6 +25,38 @@ fn r2d2_sqlite() {
     assert_eq!(pool.state().idle_connections, 3);
 }
 
+#[cfg(all(feature = "sqlite", feature = "r2d2"))]
+#[test]
+fn pool_sqlite() {
+    use butane::db::pool;
+    use butane::db::ConnectionMethods;
+    let pool: pool::Pool = pool::builder()
+        .max_size(2)
+        .build(db::ConnectionManager::new(common::sqlite_connspec()))
+        .unwrap();
+    assert_eq!(pool.idle_timeout(), Some(pool::IDLE_TIMEOUT));
+    assert_eq!(pool.max_lifetime(), Some(pool::MAX_LIFETIME));
+    assert!(pool.test_on_check_out());
+
+    // Connections may be shared between threads
+    let handles: Vec<_> = (0..2)
+        .map(|_| {
+            let pool = pool.clone();
+            std::thread::spawn(move || {
+                let conn: pool::PooledConnection = pool.get().unwrap();
+                conn.execute("CREATE TABLE IF NOT EXISTS t (x INTEGER);")
+                    .unwrap();
+            })
+        })
+        .collect();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+
+    let pool = pool::new(common::sqlite_connspec()).unwrap();
+    assert!(pool.get().unwrap().has_table("missing").is_ok());
+}
+
 #[cfg(all(feature = "pg", feature = "r2d2"))]
 #[test]
 fn r2d2_pq() {
@@ -0,0 +1,108 @@
+use butane::db::{BackendConnection, Connection, ConnectionSpec};
+use butane::prelude::*;
+use butane::retry::{self, RetryQueue};
+use butane::{model, Error};
+
+mod common;
+
+#[model]
+#[derive(PartialEq, Eq, Debug)]
+struct Queued {
+    id: i64,
+    name: String,
+}
+impl Queued {
+    fn new(id: i64, name: &str) -> Self {
+        Queued {
+            id,
+            name: name.to_string(),
+            state: butane::ObjectState::default(),
+        }
+    }
+}
+
+#[model]
+struct AutoQueued {
+    #[auto]
+    id: i64,
+    name: String,
+}
+
+fn spool_path(conn: &Connection, name: &str) -> std::path::PathBuf {
+    std::env::temp_dir().join(format!(
+        "butane_retry_{}_{}_{}",
+        name,
+        conn.backend_name(),
+        std::process::id()
+    ))
+}
+
+fn replay_from_file(conn: Connection) {
+    let path = spool_path(&conn, "file");
+    let queue = RetryQueue::in_file(&path).with_rate_limit(1000);
+    let mut obj = Queued::new(1, "first");
+    queue.defer_save(&mut obj).unwrap();
+    assert_eq!(queue.len().unwrap(), 1);
+    assert!(matches!(Queued::get(&conn, 1), Err(Error::NoSuchObject)));
+
+    // The object is now considered saved, so this is queued as an update
+    obj.name = "second".to_string();
+    queue.defer_save(&mut obj).unwrap();
+    // Replaying a write which in fact succeeded is harmless
+    let mut other = Queued::new(2, "other");
+    queue.defer_save(&mut other).unwrap();
+    other.save(&conn).unwrap();
+
+    assert_eq!(queue.replay(&conn).unwrap(), 3);
+    assert!(queue.is_empty().unwrap());
+    assert_eq!(Queued::get(&conn, 1).unwrap().name, "second");
+    assert_eq!(Queued::get(&conn, 2).unwrap(), other);
+
+    queue.delete(&conn, &other).unwrap();
+    assert!(queue.is_empty().unwrap());
+    assert!(matches!(Queued::get(&conn, 2), Err(Error::NoSuchObject)));
+    std::fs::remove_file(&path).unwrap();
+}
+testall!(replay_from_file);
+
+fn replay_from_table(mut conn: Connection) {
+    let path = spool_path(&conn, "table");
+    let spec = ConnectionSpec::new("sqlite", path.to_str().unwrap());
+    let queue = RetryQueue::in_table(spec, retry::DEFAULT_TABLE);
+    queue.defer_save(&mut Queued::new(1, "first")).unwrap();
+    queue.defer_save(&mut Queued::new(2, "second")).unwrap();
+    assert_eq!(queue.len().unwrap(), 2);
+
+    // A write which fails again stays queued
+    conn.execute("DROP TABLE Queued;").unwrap();
+    assert!(queue.replay(&conn).is_err());
+    assert_eq!(queue.len().unwrap(), 2);
+
+    conn.execute("CREATE TABLE Queued (id BIGINT PRIMARY KEY, name TEXT NOT NULL);")
+        .unwrap();
+    assert_eq!(queue.replay(&conn).unwrap(), 2);
+    assert!(queue.is_empty().unwrap());
+    assert_eq!(Queued::get(&conn, 2).unwrap().name, "second");
+    std::fs::remove_file(&path).unwrap();
+}
+testall!(replay_from_table);
+
+fn unspoolable(mut conn: Connection) {
+    let path = spool_path(&conn, "unspoolable");
+    let queue = RetryQueue::in_file(&path);
+    // The primary key is only known once inserted
+    let mut obj = AutoQueued {
+        id: -1,
+        name: "auto".to_string(),
+        state: butane::ObjectState::default(),
+    };
+    assert!(matches!(
+        queue.defer_save(&mut obj),
+        Err(Error::CannotSpool(_))
+    ));
+    // Errors which are not transient are not queued
+    conn.execute("DROP TABLE Queued;").unwrap();
+    assert!(queue.save(&conn, &mut Queued::new(1, "first")).is_err());
+    assert!(queue.is_empty().unwrap());
+}
+testall!(unspoolable);
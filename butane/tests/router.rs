@@ -0,0 +1,72 @@
+#![cfg(feature = "sqlite")]
+use butane::db::{Connection, ConnectionMethods};
+use butane::prelude::*;
+use butane::router::Router;
+
+mod common;
+use common::blog::Blog;
+
+fn database() -> Connection {
+    let mut conn = common::sqlite_connection();
+    common::setup_db(
+        Box::new(butane::db::sqlite::SQLiteBackend::new()),
+        &mut conn,
+    );
+    conn
+}
+
+/// Each database holding a blog named after it, so that reads show
+/// which was used.
+fn named_databases(names: &[&str]) -> Vec<Connection> {
+    names
+        .iter()
+        .map(|name| {
+            let conn = database();
+            Blog::new(1, name).save(&conn).unwrap();
+            conn
+        })
+        .collect()
+}
+
+fn read_from(conn: &impl ConnectionMethods) -> String {
+    Blog::get(conn, 1).unwrap().name
+}
+
+#[test]
+fn reads_from_replicas() {
+    let mut dbs = named_databases(&["primary", "replica1", "replica2"]);
+    let primary = dbs.remove(0);
+    let router = Router::new(primary, dbs);
+    assert_eq!(read_from(&router), "replica1");
+    assert_eq!(read_from(&router), "replica2");
+    assert_eq!(read_from(&router), "replica1");
+
+    // Writes go to the primary only
+    Blog::new(2, "Cats").save(&router).unwrap();
+    assert!(Blog::get(router.primary(), 2).is_ok());
+    assert!(Blog::get(&router.replicas()[0], 2).is_err());
+    assert!(!router.is_pinned());
+
+    router.pin();
+    assert_eq!(read_from(&router), "primary");
+    router.unpin();
+    assert_eq!(read_from(&router), "replica2");
+}
+
+#[test]
+fn pins_after_write() {
+    let mut dbs = named_databases(&["primary", "replica"]);
+    let primary = dbs.remove(0);
+    let router = Router::new(primary, dbs).pin_after_write(true);
+    assert_eq!(read_from(&router), "replica");
+    let mut blog = Blog::new(2, "Cats");
+    blog.save(&router).unwrap();
+    assert!(router.is_pinned());
+    assert_eq!(Blog::get(&router, 2).unwrap(), blog);
+}
+
+#[test]
+fn without_replicas() {
+    let router = Router::new(named_databases(&["primary"]).remove(0), Vec::new());
+    assert_eq!(read_from(&router), "primary");
+}
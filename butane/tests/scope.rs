@@ -0,0 +1,83 @@
+use butane::db::Connection;
+use butane::prelude::*;
+use butane::scope::{Owned, Scoped};
+use butane::{model, query, Error};
+
+mod common;
+
+#[model]
+#[derive(PartialEq, Eq, Debug)]
+struct ScopedNote {
+    id: i64,
+    owner: String,
+    text: String,
+}
+impl ScopedNote {
+    fn new(id: i64, owner: &str, text: &str) -> Self {
+        ScopedNote {
+            id,
+            owner: owner.to_string(),
+            text: text.to_string(),
+            state: butane::ObjectState::default(),
+        }
+    }
+}
+impl Owned for ScopedNote {
+    const OWNER_COLUMN: &'static str = "owner";
+}
+
+fn reads_in_scope(conn: Connection) {
+    ScopedNote::new(1, "alice", "a1").save(&conn).unwrap();
+    ScopedNote::new(2, "bob", "b1").save(&conn).unwrap();
+    ScopedNote::new(3, "alice", "a2").save(&conn).unwrap();
+
+    let alice = Scoped::new(&conn, "alice").model::<ScopedNote>();
+    let ids: Vec<i64> = ScopedNote::query()
+        .order_asc("id")
+        .load(&alice)
+        .unwrap()
+        .into_iter()
+        .map(|n| n.id)
+        .collect();
+    assert_eq!(ids, vec![1, 3]);
+    let found = query!(ScopedNote, text == "b1").load(&alice).unwrap();
+    assert!(found.is_empty());
+    assert!(matches!(
+        ScopedNote::get(&alice, 2),
+        Err(Error::NoSuchObject)
+    ));
+
+    // Models not registered with the wrapper are not scoped
+    let unscoped = Scoped::new(&conn, "alice");
+    assert_eq!(ScopedNote::query().load(&unscoped).unwrap().len(), 3);
+}
+testall!(reads_in_scope);
+
+fn writes_in_scope(conn: Connection) {
+    ScopedNote::new(1, "bob", "b1").save(&conn).unwrap();
+    let alice = Scoped::new(&conn, "alice").model::<ScopedNote>();
+
+    let mut note = ScopedNote::new(2, "alice", "a1");
+    note.save(&alice).unwrap();
+    note.text = "edited".to_string();
+    note.save(&alice).unwrap();
+    assert_eq!(ScopedNote::get(&conn, 2).unwrap(), note);
+
+    // Creating a row for another owner, or giving one away, is refused
+    assert!(matches!(
+        ScopedNote::new(3, "bob", "b2").save(&alice),
+        Err(Error::OutOfScope(_))
+    ));
+    note.owner = "bob".to_string();
+    assert!(matches!(note.save(&alice), Err(Error::OutOfScope(_))));
+
+    // Another owner's row cannot be updated or deleted
+    let mut theirs = ScopedNote::get(&conn, 1).unwrap();
+    theirs.text = "stolen".to_string();
+    assert!(matches!(theirs.save(&alice), Err(Error::NoSuchObject)));
+    theirs.delete(&alice).unwrap();
+    assert_eq!(ScopedNote::query().delete(&alice).unwrap(), 1);
+    assert_eq!(ScopedNote::get(&conn, 1).unwrap().text, "b1");
+    assert!(ScopedNote::get(&conn, 2).is_err());
+}
+testall!(writes_in_scope);
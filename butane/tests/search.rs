@@ -0,0 +1,109 @@
+use butane::db::Connection;
+use butane::prelude::*;
+use butane::search::{self, SearchIndex, Searchable};
+use butane::{model, query};
+
+mod common;
+
+#[model]
+#[derive(PartialEq, Eq, Debug)]
+struct SearchArticle {
+    id: i64,
+    title: String,
+    body: String,
+}
+impl SearchArticle {
+    fn new(id: i64, title: &str, body: &str) -> Self {
+        SearchArticle {
+            id,
+            title: title.to_string(),
+            body: body.to_string(),
+            state: butane::ObjectState::default(),
+        }
+    }
+}
+impl Searchable for SearchArticle {
+    const SEARCH_COLUMNS: &'static [&'static str] = &["title", "body"];
+}
+
+#[model]
+#[derive(PartialEq, Eq, Debug)]
+struct SearchRemark {
+    id: i64,
+    text: Option<String>,
+}
+impl SearchRemark {
+    fn new(id: i64, text: Option<&str>) -> Self {
+        SearchRemark {
+            id,
+            text: text.map(str::to_string),
+            state: butane::ObjectState::default(),
+        }
+    }
+}
+impl Searchable for SearchRemark {
+    const SEARCH_COLUMNS: &'static [&'static str] = &["text"];
+}
+
+fn search_index() -> SearchIndex {
+    SearchIndex::new()
+        .model::<SearchArticle>()
+        .model::<SearchRemark>()
+}
+
+fn search_across_models(conn: Connection) {
+    let index = search_index();
+    index.prepare(&conn).unwrap();
+    let tracked = index.track(&conn);
+    let mut rust = SearchArticle::new(1, "Rust ORM", "Butane is an ORM for Rust");
+    rust.save(&tracked).unwrap();
+    let mut pasta = SearchArticle::new(2, "Cooking", "Pasta recipes");
+    pasta.save(&tracked).unwrap();
+    SearchRemark::new(1, Some("What is an ORM?"))
+        .save(&tracked)
+        .unwrap();
+    SearchRemark::new(2, None).save(&tracked).unwrap();
+
+    let hits = search::query(&conn, "orm").unwrap();
+    assert_eq!(hits.len(), 2);
+    let article = hits.iter().find(|h| h.is::<SearchArticle>()).unwrap();
+    assert_eq!(article.pk::<SearchArticle>().unwrap(), Some(1));
+    assert_eq!(article.load::<SearchArticle>(&conn).unwrap(), Some(rust));
+    assert!(article.snippet().contains("<b>ORM</b>"));
+    let remark = hits.iter().find(|h| h.is::<SearchRemark>()).unwrap();
+    assert_eq!(remark.model(), "SearchRemark");
+    assert_eq!(remark.pk::<SearchArticle>().unwrap(), None);
+    assert_eq!(remark.pk::<SearchRemark>().unwrap(), Some(1));
+
+    // All of the words must match
+    let hits = search::query(&conn, "rust orm").unwrap();
+    assert_eq!(hits.len(), 1);
+    assert!(hits[0].is::<SearchArticle>());
+    assert_eq!(search::query(&conn, "\"rust").unwrap().len(), 1);
+    assert!(search::query(&conn, "  ").unwrap().is_empty());
+
+    // Updates and deletes are indexed
+    pasta.body = "Pasta with rust".to_string();
+    pasta.save(&tracked).unwrap();
+    assert_eq!(search::query(&conn, "rust").unwrap().len(), 2);
+    assert_eq!(search::query_top(&conn, "rust", 1).unwrap().len(), 1);
+    assert_eq!(query!(SearchArticle, id == 1).delete(&tracked).unwrap(), 1);
+    let hits = search::query(&conn, "orm").unwrap();
+    assert_eq!(hits.len(), 1);
+    assert!(hits[0].is::<SearchRemark>());
+}
+testall!(search_across_models);
+
+fn rebuild_index(conn: Connection) {
+    let index = search_index();
+    index.prepare(&conn).unwrap();
+    SearchArticle::new(1, "Unindexed", "Written without tracking")
+        .save(&conn)
+        .unwrap();
+    assert!(search::query(&conn, "tracking").unwrap().is_empty());
+    index.rebuild(&conn).unwrap();
+    let hits = search::query(&conn, "tracking").unwrap();
+    assert_eq!(hits.len(), 1);
+    assert_eq!(hits[0].pk::<SearchArticle>().unwrap(), Some(1));
+}
+testall!(rebuild_index);
@@ -0,0 +1,180 @@
+use butane::db::{BackendConnection, Column, Connection, ConnectionMethods};
+use butane::prelude::*;
+use butane::query::{BoolExpr, Expr};
+use butane::sync::{Change, Resolution, SyncEngine, SyncReport, LOG_TABLE};
+use butane::{model, Error, SqlType, SqlVal, SqlValRef};
+use std::collections::HashMap;
+
+mod common;
+
+#[model]
+#[derive(PartialEq, Eq, Debug)]
+struct SyncNote {
+    id: i64,
+    text: String,
+}
+impl SyncNote {
+    fn new(id: i64, text: &str) -> Self {
+        SyncNote {
+            id,
+            text: text.to_string(),
+            state: butane::ObjectState::default(),
+        }
+    }
+}
+
+/// A local replica: an in-memory SQLite database with the test schema.
+fn replica(engine: SyncEngine) -> (SyncEngine, Connection) {
+    let mut conn = common::sqlite_connection();
+    common::setup_db(conn.backend(), &mut conn);
+    engine.prepare(&conn).unwrap();
+    (engine, conn)
+}
+
+fn text(conn: &Connection, id: i64) -> String {
+    SyncNote::get(conn, id).unwrap().text
+}
+
+fn sync_between_replicas(remote: Connection) {
+    let (a, a_conn) = replica(SyncEngine::new("a"));
+    let (b, b_conn) = replica(SyncEngine::new("b"));
+
+    let mut note = SyncNote::new(1, "first");
+    note.save(&a.track(&a_conn)).unwrap();
+    assert_eq!(a.pending(&a_conn).unwrap(), 1);
+    assert_eq!(
+        a.sync(&a_conn, &remote).unwrap(),
+        SyncReport {
+            pulled: 0,
+            pushed: 1,
+            conflicts: 0
+        }
+    );
+    assert_eq!(a.pending(&a_conn).unwrap(), 0);
+    assert_eq!(text(&remote, 1), "first");
+
+    assert_eq!(b.sync(&b_conn, &remote).unwrap().pulled, 1);
+    assert_eq!(text(&b_conn, 1), "first");
+    // A replica does not pull its own changes back
+    assert_eq!(a.sync(&a_conn, &remote).unwrap().pulled, 0);
+
+    let note_b = SyncNote::get(&b_conn, 1).unwrap();
+    note_b.delete(&b.track(&b_conn)).unwrap();
+    b.sync(&b_conn, &remote).unwrap();
+    a.sync(&a_conn, &remote).unwrap();
+    assert!(matches!(
+        SyncNote::get(&a_conn, 1),
+        Err(Error::NoSuchObject)
+    ));
+    assert!(matches!(
+        SyncNote::get(&remote, 1),
+        Err(Error::NoSuchObject)
+    ));
+}
+testall!(sync_between_replicas);
+
+fn last_write_wins(remote: Connection) {
+    let (a, a_conn) = replica(SyncEngine::new("a"));
+    let (b, b_conn) = replica(SyncEngine::new("b"));
+    SyncNote::new(1, "first").save(&a.track(&a_conn)).unwrap();
+    a.sync(&a_conn, &remote).unwrap();
+    b.sync(&b_conn, &remote).unwrap();
+
+    let mut note_a = SyncNote::get(&a_conn, 1).unwrap();
+    note_a.text = "from a".to_string();
+    note_a.save(&a.track(&a_conn)).unwrap();
+    std::thread::sleep(std::time::Duration::from_millis(5));
+    let mut note_b = SyncNote::get(&b_conn, 1).unwrap();
+    note_b.text = "from b".to_string();
+    note_b.save(&b.track(&b_conn)).unwrap();
+
+    a.sync(&a_conn, &remote).unwrap();
+    // The change made by b is later, so overwrites that made by a
+    let report = b.sync(&b_conn, &remote).unwrap();
+    assert_eq!(report.conflicts, 1);
+    assert_eq!(report.pushed, 1);
+    assert_eq!(text(&b_conn, 1), "from b");
+    assert_eq!(text(&remote, 1), "from b");
+    a.sync(&a_conn, &remote).unwrap();
+    assert_eq!(text(&a_conn, 1), "from b");
+}
+testall!(last_write_wins);
+
+fn custom_merge(remote: Connection) {
+    let (a, a_conn) = replica(SyncEngine::new("a"));
+    let merge = |local: &Change, remote: &Change| match (local.value("text"), remote.value("text"))
+    {
+        (Some(SqlVal::Text(l)), Some(SqlVal::Text(r))) => {
+            let mut merged = HashMap::new();
+            merged.insert("text".to_string(), SqlVal::Text(format!("{}+{}", r, l)));
+            Resolution::Merged(merged)
+        }
+        _ => Resolution::KeepRemote,
+    };
+    let (b, b_conn) = replica(SyncEngine::new("b").with_strategy(merge));
+
+    SyncNote::new(1, "a").save(&a.track(&a_conn)).unwrap();
+    SyncNote::new(1, "b").save(&b.track(&b_conn)).unwrap();
+    a.sync(&a_conn, &remote).unwrap();
+    let report = b.sync(&b_conn, &remote).unwrap();
+    assert_eq!(report.conflicts, 1);
+    assert_eq!(text(&b_conn, 1), "a+b");
+    assert_eq!(text(&remote, 1), "a+b");
+}
+testall!(custom_merge);
+
+fn out_of_order_commits(remote: Connection) {
+    let (a, a_conn) = replica(SyncEngine::new("a"));
+    let (b, b_conn) = replica(SyncEngine::new("b"));
+    let (c, c_conn) = replica(SyncEngine::new("c"));
+    SyncNote::new(1, "from a").save(&a.track(&a_conn)).unwrap();
+    a.push(&a_conn, &remote).unwrap();
+    SyncNote::new(2, "from c").save(&c.track(&c_conn)).unwrap();
+    c.push(&c_conn, &remote).unwrap();
+
+    // The push of a commits only after that of c, with a lower id, has
+    // been pulled by b
+    let logged: Vec<(i64, String)> = remote
+        .query_raw(
+            &format!("SELECT id, data FROM {} ORDER BY id", LOG_TABLE),
+            &[],
+        )
+        .unwrap();
+    assert_eq!(logged.len(), 2);
+    let (id, data) = &logged[0];
+    remote
+        .delete_where(
+            LOG_TABLE,
+            BoolExpr::Eq("id", Expr::Val(SqlVal::BigInt(*id))),
+        )
+        .unwrap();
+    assert_eq!(b.pull(&b_conn, &remote).unwrap().pulled, 1);
+    assert_eq!(text(&b_conn, 2), "from c");
+    remote
+        .insert_only(
+            LOG_TABLE,
+            &[
+                Column::new("id", SqlType::BigInt),
+                Column::new("data", SqlType::Text),
+            ],
+            &[SqlValRef::BigInt(*id), SqlValRef::Text(data)],
+        )
+        .unwrap();
+
+    assert_eq!(b.pull(&b_conn, &remote).unwrap().pulled, 1);
+    assert_eq!(text(&b_conn, 1), "from a");
+    // Changes within the look-back window are pulled only once
+    assert_eq!(b.pull(&b_conn, &remote).unwrap().pulled, 0);
+}
+testall!(out_of_order_commits);
+
+fn track_before_prepare(conn: Connection) {
+    let engine = SyncEngine::new("a");
+    let mut note = SyncNote::new(1, "first");
+    assert!(matches!(
+        note.save(&engine.track(&conn)),
+        Err(Error::CannotSync(_))
+    ));
+    assert!(matches!(SyncNote::get(&conn, 1), Err(Error::NoSuchObject)));
+}
+testall!(track_before_prepare);
@@ -0,0 +1,129 @@
+use butane::db::{BackendConnection, Connection};
+use butane::prelude::*;
+use butane::twophase::{Coordinator, RecoveryReport};
+use butane::{model, Error};
+use std::path::PathBuf;
+use uuid_for_test::Uuid;
+
+mod common;
+
+#[model]
+#[derive(Debug)]
+struct Ledger {
+    id: i64,
+    balance: i64,
+}
+impl Ledger {
+    fn new(id: i64, balance: i64) -> Self {
+        Ledger {
+            id,
+            balance,
+            state: butane::ObjectState::default(),
+        }
+    }
+}
+
+fn log_path() -> PathBuf {
+    std::env::temp_dir().join(format!("butane_2pc_{}", Uuid::new_v4().simple()))
+}
+
+#[cfg(feature = "pg")]
+fn pg_database() -> (Connection, common::PgSetupData) {
+    let (mut conn, data) = common::pg_connection();
+    common::setup_db(conn.backend(), &mut conn);
+    (conn, data)
+}
+
+fn balance(conn: &Connection, id: i64) -> Option<i64> {
+    Ledger::get(conn, id).ok().map(|l| l.balance)
+}
+
+#[cfg(all(feature = "pg", feature = "sqlite"))]
+#[test]
+fn commit_across_databases() {
+    let (mut a, _a_data) = pg_database();
+    let (mut b, _b_data) = pg_database();
+    let coordinator = Coordinator::new("bank", log_path());
+
+    coordinator
+        .execute(&mut [&mut a, &mut b], |trans| {
+            Ledger::new(1, -10).save(&trans[0])?;
+            Ledger::new(1, 10).save(&trans[1])
+        })
+        .unwrap();
+    assert_eq!(balance(&a, 1), Some(-10));
+    assert_eq!(balance(&b, 1), Some(10));
+    assert!(a.prepared_transactions().unwrap().is_empty());
+    assert!(b.prepared_transactions().unwrap().is_empty());
+
+    let result: butane::Result<()> = coordinator.execute(&mut [&mut a, &mut b], |trans| {
+        Ledger::new(2, -10).save(&trans[0])?;
+        Err(Error::NoSuchObject)
+    });
+    assert!(matches!(result, Err(Error::NoSuchObject)));
+    assert_eq!(balance(&a, 2), None);
+
+    // SQLite cannot prepare, so the prepared Postgres transaction is rolled back
+    let mut local = common::sqlite_connection();
+    common::setup_db(local.backend(), &mut local);
+    let result = coordinator.execute(&mut [&mut a, &mut local], |trans| {
+        Ledger::new(3, -10).save(&trans[0])?;
+        Ledger::new(3, 10).save(&trans[1])
+    });
+    assert!(matches!(result, Err(Error::TwoPhaseUnsupported("sqlite"))));
+    assert_eq!(balance(&a, 3), None);
+    assert_eq!(balance(&local, 3), None);
+    assert!(a.prepared_transactions().unwrap().is_empty());
+}
+
+#[cfg(feature = "pg")]
+#[test]
+fn recover_in_doubt() {
+    let (mut a, _a_data) = pg_database();
+    let (mut b, _b_data) = pg_database();
+    let log = log_path();
+    // Transaction t1 was decided before the crash, t2 was not
+    std::fs::write(&log, "t1\n").unwrap();
+    let prepare = |conn: &mut Connection, id: i64, gid: &str| {
+        let trans = conn.transaction().unwrap();
+        Ledger::new(id, 1).save(&trans).unwrap();
+        trans.prepare(gid).unwrap();
+    };
+    prepare(&mut a, 1, "bank:t1:0");
+    prepare(&mut b, 1, "bank:t1:1");
+    prepare(&mut a, 2, "bank:t2:0");
+    prepare(&mut b, 3, "other:t3:0");
+    assert_eq!(balance(&a, 1), None);
+
+    let coordinator = Coordinator::new("bank", &log);
+    assert_eq!(
+        coordinator.recover(&[&a, &b]).unwrap(),
+        RecoveryReport {
+            committed: 2,
+            rolled_back: 1
+        }
+    );
+    assert_eq!(balance(&a, 1), Some(1));
+    assert_eq!(balance(&b, 1), Some(1));
+    assert_eq!(balance(&a, 2), None);
+    assert!(!log.exists());
+    // Transactions of other coordinators are left alone
+    assert_eq!(b.prepared_transactions().unwrap(), vec!["other:t3:0"]);
+    b.rollback_prepared("other:t3:0").unwrap();
+}
+
+#[cfg(feature = "sqlite")]
+#[test]
+fn unsupported_sqlite() {
+    let mut conn = common::sqlite_connection();
+    common::setup_db(conn.backend(), &mut conn);
+    let trans = conn.transaction().unwrap();
+    assert!(matches!(
+        trans.prepare("bank:t1:0"),
+        Err(Error::TwoPhaseUnsupported("sqlite"))
+    ));
+    assert!(matches!(
+        conn.prepared_transactions(),
+        Err(Error::TwoPhaseUnsupported("sqlite"))
+    ));
+}
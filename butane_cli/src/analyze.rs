@@ -0,0 +1,336 @@
+//! Inspects the data in the database to suggest schema improvements.
+
+use super::Result;
+use butane::db::{Backend, BackendRows, Column, ConnectionMethods};
+use butane::migrations::adb::{
+    AColumn, AIndex, ATable, DeferredSqlType, Operation, TypeIdentifier, ADB,
+};
+use butane::{SqlType, SqlVal};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+/// Distinct values are counted exactly up to this many per column.
+const MAX_DISTINCT: usize = 10_000;
+/// Number of logged queries filtering on a column before an index is
+/// suggested for it.
+const FREQUENT_FILTER: usize = 10;
+
+/// Statistics for the values of a single column.
+#[derive(Debug, Default)]
+pub struct ColumnStats {
+    pub rows: u64,
+    pub nulls: u64,
+    /// Number of distinct non-null values, or `None` if there are more
+    /// than [`MAX_DISTINCT`].
+    pub distinct: Option<usize>,
+    /// Longest text or blob value, in bytes.
+    pub max_len: Option<usize>,
+    /// Range of integer values.
+    pub min_int: Option<i64>,
+    pub max_int: Option<i64>,
+    seen: HashSet<String>,
+    overflowed: bool,
+}
+impl ColumnStats {
+    fn add(&mut self, val: &SqlVal) {
+        self.rows += 1;
+        let len = match val {
+            SqlVal::Null => {
+                self.nulls += 1;
+                return;
+            }
+            SqlVal::Text(s) => Some(s.len()),
+            SqlVal::Blob(b) => Some(b.len()),
+            SqlVal::Int(i) => {
+                self.add_int(*i as i64);
+                None
+            }
+            SqlVal::BigInt(i) => {
+                self.add_int(*i);
+                None
+            }
+            _ => None,
+        };
+        if let Some(len) = len {
+            self.max_len = Some(self.max_len.map_or(len, |max| max.max(len)));
+        }
+        if !self.overflowed {
+            self.seen.insert(val.to_string());
+            if self.seen.len() > MAX_DISTINCT {
+                self.overflowed = true;
+                self.seen.clear();
+            }
+        }
+    }
+    fn add_int(&mut self, i: i64) {
+        self.min_int = Some(self.min_int.map_or(i, |min| min.min(i)));
+        self.max_int = Some(self.max_int.map_or(i, |max| max.max(i)));
+    }
+    fn finish(&mut self) {
+        self.distinct = if self.overflowed {
+            None
+        } else {
+            Some(self.seen.len())
+        };
+        self.seen = HashSet::new();
+    }
+    /// Fraction of the values which are NULL.
+    pub fn null_ratio(&self) -> f64 {
+        if self.rows == 0 {
+            0.0
+        } else {
+            self.nulls as f64 / self.rows as f64
+        }
+    }
+}
+impl fmt::Display for ColumnStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:.0}% null", self.null_ratio() * 100.0)?;
+        match self.distinct {
+            Some(n) => write!(f, ", {} distinct", n)?,
+            None => write!(f, ", >{} distinct", MAX_DISTINCT)?,
+        }
+        if let Some(len) = self.max_len {
+            write!(f, ", max length {}", len)?;
+        }
+        if let (Some(min), Some(max)) = (self.min_int, self.max_int) {
+            write!(f, ", range {}..={}", min, max)?;
+        }
+        Ok(())
+    }
+}
+
+/// A suggested change to the schema.
+pub enum Suggestion {
+    /// The column has no NULL values, so may be made non-nullable.
+    NotNull { table: String, column: AColumn },
+    /// The values of the column all fit in a narrower type.
+    Narrower {
+        table: String,
+        column: AColumn,
+        to: SqlType,
+    },
+    /// The column is often filtered on but has no index.
+    Index {
+        table: String,
+        column: String,
+        filters: usize,
+    },
+}
+impl Suggestion {
+    /// The migration operation making the change, if it can be
+    /// expressed as one.
+    fn operation(&self) -> Option<Operation> {
+        let (table, old, new) = match self {
+            Suggestion::NotNull { table, column } => (
+                table,
+                column,
+                changed_column(column, column.typeid().ok()?, false),
+            ),
+            Suggestion::Narrower { table, column, to } => (
+                table,
+                column,
+                changed_column(column, TypeIdentifier::Ty(to.clone()), column.nullable()),
+            ),
+            Suggestion::Index { table, column, .. } => {
+                return Some(Operation::AddIndex(
+                    table.clone(),
+                    AIndex::new([column.clone()]),
+                ))
+            }
+        };
+        Some(Operation::ChangeColumn(table.clone(), old.clone(), new))
+    }
+}
+impl fmt::Display for Suggestion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Suggestion::NotNull { table, column } => write!(
+                f,
+                "{}.{} has no NULL values and could be NOT NULL",
+                table,
+                column.name()
+            ),
+            Suggestion::Narrower { table, column, to } => write!(
+                f,
+                "{}.{} could be of type {:?}, which fits all its values",
+                table,
+                column.name(),
+                to
+            ),
+            Suggestion::Index {
+                table,
+                column,
+                filters,
+            } => write!(
+                f,
+                "{}.{} is filtered on by {} logged queries and could be indexed",
+                table, column, filters
+            ),
+        }
+    }
+}
+
+fn changed_column(col: &AColumn, ty: TypeIdentifier, nullable: bool) -> AColumn {
+    let new = AColumn::new(
+        col.name(),
+        DeferredSqlType::KnownId(ty),
+        nullable,
+        col.is_pk(),
+        col.is_auto(),
+        col.unique(),
+        col.default().clone(),
+    );
+    match col.reference() {
+        Some(reference) => new.with_reference(reference.clone()),
+        None => new,
+    }
+}
+
+/// Compute statistics for each column of `table` by reading all of its rows.
+pub fn column_stats(
+    conn: &impl ConnectionMethods,
+    table: &ATable,
+) -> Result<Vec<(String, ColumnStats)>> {
+    let mut names = Vec::new();
+    let mut columns = Vec::new();
+    for col in &table.columns {
+        let ty = match col.typeid()? {
+            TypeIdentifier::Ty(SqlType::Custom(_)) | TypeIdentifier::Name(_) => continue,
+            TypeIdentifier::Ty(ty) => ty,
+            TypeIdentifier::Enum(_) => SqlType::Text,
+        };
+        // Column names must be 'static. This is a short-lived process,
+        // so leaking a name per column is of no concern.
+        let name: &'static str = Box::leak(col.name().to_string().into_boxed_str());
+        names.push(col.name().to_string());
+        columns.push(Column::new(name, ty));
+    }
+    let mut stats: Vec<ColumnStats> = columns.iter().map(|_| ColumnStats::default()).collect();
+    let mut rows = conn.query(&table.name, &columns, None, None, None, None)?;
+    while let Some(row) = rows.next()? {
+        for (i, (col, stat)) in columns.iter().zip(stats.iter_mut()).enumerate() {
+            stat.add(&SqlVal::from(row.get(i, col.ty().clone())?));
+        }
+    }
+    for stat in &mut stats {
+        stat.finish();
+    }
+    Ok(names.into_iter().zip(stats).collect())
+}
+
+/// Suggest changes to the columns of `table` given their statistics.
+pub fn suggest(table: &ATable, stats: &[(String, ColumnStats)]) -> Vec<Suggestion> {
+    let mut suggestions = Vec::new();
+    for (name, stat) in stats {
+        let col = match table.column(name) {
+            Some(col) => col,
+            None => continue,
+        };
+        if stat.rows == 0 {
+            continue;
+        }
+        if col.nullable() && stat.nulls == 0 {
+            suggestions.push(Suggestion::NotNull {
+                table: table.name.clone(),
+                column: col.clone(),
+            });
+        }
+        let fits_int = match (stat.min_int, stat.max_int) {
+            (Some(min), Some(max)) => min >= i32::MIN as i64 && max <= i32::MAX as i64,
+            _ => false,
+        };
+        // Keys must remain of the same type as the columns referring to
+        // them, so are left alone.
+        let is_key = col.is_pk() || col.reference().is_some();
+        if fits_int && !is_key && matches!(col.typeid(), Ok(TypeIdentifier::Ty(SqlType::BigInt))) {
+            suggestions.push(Suggestion::Narrower {
+                table: table.name.clone(),
+                column: col.clone(),
+                to: SqlType::Int,
+            });
+        }
+    }
+    suggestions
+}
+
+/// Count, for each column of each table in `db`, how many of the SQL
+/// statements in `log` filter on it. Statements are recognized loosely:
+/// the table is the one following `FROM` (or `UPDATE`), and the
+/// columns are the identifiers following `WHERE`.
+pub fn filter_counts(db: &ADB, log: &str) -> HashMap<(String, String), usize> {
+    let mut counts = HashMap::new();
+    for stmt in log.split(';') {
+        let words: Vec<String> = stmt
+            .split(|c: char| !(c.is_alphanumeric() || c == '_' || c == '.'))
+            .filter(|w| !w.is_empty())
+            .map(|w| w.to_string())
+            .collect();
+        let table = words
+            .windows(2)
+            .find(|w| w[0].eq_ignore_ascii_case("from") || w[0].eq_ignore_ascii_case("update"))
+            .and_then(|w| db.get_table(&w[1]));
+        let table = match table {
+            Some(table) => table,
+            None => continue,
+        };
+        let filter_start = match words.iter().position(|w| w.eq_ignore_ascii_case("where")) {
+            Some(pos) => pos + 1,
+            None => continue,
+        };
+        let mut filtered: HashSet<&str> = HashSet::new();
+        for word in &words[filter_start..] {
+            let name = word.rsplit('.').next().unwrap_or(word);
+            if let Some(col) = table.column(name) {
+                filtered.insert(col.name());
+            }
+        }
+        for col in filtered {
+            *counts
+                .entry((table.name.clone(), col.to_string()))
+                .or_insert(0) += 1;
+        }
+    }
+    counts
+}
+
+/// Suggest indexes for the columns frequently filtered on in `counts`
+/// which are not already indexed.
+pub fn suggest_indexes(db: &ADB, counts: &HashMap<(String, String), usize>) -> Vec<Suggestion> {
+    let mut suggestions: Vec<Suggestion> = counts
+        .iter()
+        .filter(|(_, n)| **n >= FREQUENT_FILTER)
+        .filter(|((table, column), _)| {
+            db.get_table(table)
+                .is_some_and(|t| t.column(column).is_some() && !t.is_indexed(column))
+        })
+        .map(|((table, column), filters)| Suggestion::Index {
+            table: table.clone(),
+            column: column.clone(),
+            filters: *filters,
+        })
+        .collect();
+    suggestions.sort_by(|a, b| match (a, b) {
+        (
+            Suggestion::Index {
+                table: ta,
+                column: ca,
+                ..
+            },
+            Suggestion::Index {
+                table: tb,
+                column: cb,
+                ..
+            },
+        ) => (ta, ca).cmp(&(tb, cb)),
+        _ => std::cmp::Ordering::Equal,
+    });
+    suggestions
+}
+
+/// SQL making the suggested changes, for review before changing the
+/// models to match.
+pub fn draft_sql(backend: &dyn Backend, db: &ADB, suggestions: &[Suggestion]) -> Result<String> {
+    let ops: Vec<Operation> = suggestions.iter().filter_map(|s| s.operation()).collect();
+    Ok(backend.create_migration_sql(db, ops)?)
+}
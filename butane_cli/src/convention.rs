@@ -0,0 +1,359 @@
+//! Renaming tables and columns to follow a naming convention.
+
+use super::Result;
+use butane::migrations::adb::{DeferredSqlType, Operation, TypeKey, ADB};
+use std::collections::BTreeMap;
+
+/// The names of the supported conventions, for the command line.
+pub const CONVENTIONS: &[&str] = &["snake_case", "camelCase", "PascalCase"];
+
+/// Suffix of the names of the tables storing `Many` fields.
+const MANY_SUFFIX: &str = "_Many";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Convention {
+    Snake,
+    Camel,
+    Pascal,
+}
+impl Convention {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "snake_case" => Some(Convention::Snake),
+            "camelCase" => Some(Convention::Camel),
+            "PascalCase" => Some(Convention::Pascal),
+            _ => None,
+        }
+    }
+
+    /// `name` written in this convention.
+    pub fn apply(self, name: &str) -> String {
+        let words = words(name);
+        match self {
+            Convention::Snake => words
+                .iter()
+                .map(|w| w.to_lowercase())
+                .collect::<Vec<_>>()
+                .join("_"),
+            Convention::Camel => words
+                .iter()
+                .enumerate()
+                .map(|(i, w)| {
+                    if i == 0 {
+                        w.to_lowercase()
+                    } else {
+                        capitalize(w)
+                    }
+                })
+                .collect(),
+            Convention::Pascal => words.iter().map(|w| capitalize(w)).collect(),
+        }
+    }
+}
+
+/// Splits `name` into words at underscores and at changes of case, so
+/// that `HTTPServer_name` is `HTTP`, `Server` and `name`.
+fn words(name: &str) -> Vec<String> {
+    let chars: Vec<char> = name.chars().collect();
+    let mut words: Vec<String> = Vec::new();
+    let mut word = String::new();
+    for (i, &c) in chars.iter().enumerate() {
+        if c == '_' || c == '-' {
+            if !word.is_empty() {
+                words.push(std::mem::take(&mut word));
+            }
+            continue;
+        }
+        if c.is_uppercase() && !word.is_empty() {
+            let prev = chars[i - 1];
+            let next_lower = chars.get(i + 1).is_some_and(|n| n.is_lowercase());
+            if prev.is_lowercase() || prev.is_ascii_digit() || (prev.is_uppercase() && next_lower) {
+                words.push(std::mem::take(&mut word));
+            }
+        }
+        word.push(c);
+    }
+    if !word.is_empty() {
+        words.push(word);
+    }
+    words
+}
+
+fn capitalize(word: &str) -> String {
+    let mut chars = word.chars();
+    match chars.next() {
+        Some(first) => first
+            .to_uppercase()
+            .chain(chars.flat_map(char::to_lowercase))
+            .collect(),
+        None => String::new(),
+    }
+}
+
+/// The renames making the tables and columns of `db` follow a
+/// convention, as operations to apply and to undo them.
+#[derive(Debug, Default)]
+pub struct Renames {
+    pub ops: Vec<Operation>,
+    pub down_ops: Vec<Operation>,
+    /// Changes to make to the models so that they match, by model.
+    pub suggestions: BTreeMap<String, Vec<String>>,
+}
+
+/// Computes the renames making `db` follow `convention`. The tables of
+/// `Many` fields are named after the table of their model and the
+/// field, so follow the renames of their models rather than the
+/// convention. Those given a custom name are left alone.
+pub fn renames(db: &ADB, convention: Convention) -> Result<Renames> {
+    let mut renames = Renames::default();
+    let mut table_renames: Vec<(String, String)> = Vec::new();
+    let mut tables: Vec<_> = db.tables().collect();
+    tables.sort_by(|a, b| a.name.cmp(&b.name));
+    for table in tables
+        .iter()
+        .filter(|t| t.many.is_none() && many_owner(db, &t.name).is_none())
+    {
+        let model = model_name(db, &table.name);
+        let new_name = convention.apply(&table.name);
+        if new_name != table.name {
+            check_unused(db.get_table(&new_name).is_some(), "table", &new_name)?;
+            table_renames.push((table.name.clone(), new_name.clone()));
+            let suggestion = if new_name == model {
+                "remove the #[table] attribute".to_string()
+            } else {
+                format!("set #[table = \"{}\"]", new_name)
+            };
+            renames.suggest(&model, suggestion);
+        }
+        for col in &table.columns {
+            let new_col = convention.apply(col.name());
+            if new_col != col.name() {
+                check_unused(table.column(&new_col).is_some(), "column", &new_col)?;
+                renames.ops.push(Operation::RenameColumn(
+                    table.name.clone(),
+                    col.name().to_string(),
+                    new_col.clone(),
+                ));
+                renames.down_ops.push(Operation::RenameColumn(
+                    table.name.clone(),
+                    new_col.clone(),
+                    col.name().to_string(),
+                ));
+                renames.suggest(
+                    &model,
+                    format!("rename the field {} to {}", col.name(), new_col),
+                );
+            }
+        }
+    }
+    let owner_renames: BTreeMap<String, String> = table_renames.iter().cloned().collect();
+    for table in tables.iter() {
+        if let Some((owner, field)) = many_owner(db, &table.name) {
+            if let Some(new_owner) = owner_renames.get(owner) {
+                let new_name = format!("{}_{}{}", new_owner, field, MANY_SUFFIX);
+                check_unused(db.get_table(&new_name).is_some(), "table", &new_name)?;
+                table_renames.push((table.name.clone(), new_name));
+            }
+        }
+    }
+    // Columns are renamed first, under the old table names, and
+    // restored last when undone.
+    for (old, new) in &table_renames {
+        renames
+            .ops
+            .push(Operation::RenameTable(old.clone(), new.clone()));
+    }
+    let column_down_ops = std::mem::take(&mut renames.down_ops);
+    for (old, new) in table_renames.iter().rev() {
+        renames
+            .down_ops
+            .push(Operation::RenameTable(new.clone(), old.clone()));
+    }
+    renames.down_ops.extend(column_down_ops.into_iter().rev());
+    Ok(renames)
+}
+
+impl Renames {
+    fn suggest(&mut self, model: &str, suggestion: String) {
+        self.suggestions
+            .entry(model.to_string())
+            .or_default()
+            .push(suggestion);
+    }
+}
+
+fn check_unused(exists: bool, kind: &str, name: &str) -> Result<()> {
+    if exists {
+        anyhow::bail!(
+            "Cannot rename to {} {}, which already exists. Rename it first.",
+            kind,
+            name
+        );
+    }
+    Ok(())
+}
+
+/// The table and field owning `table`, if it stores a `Many` field.
+fn many_owner<'a>(db: &ADB, table: &'a str) -> Option<(&'a str, &'a str)> {
+    let name = table.strip_suffix(MANY_SUFFIX)?;
+    // The field name may itself contain underscores, the table name is
+    // the longest prefix naming a table.
+    name.char_indices()
+        .filter(|(_, c)| *c == '_')
+        .map(|(i, _)| (&name[..i], &name[i + 1..]))
+        .rev()
+        .find(|(owner, _)| db.get_table(owner).is_some())
+}
+
+/// The name of the model stored in `table`, which is the table name
+/// unless the model gives a custom one.
+fn model_name(db: &ADB, table: &str) -> String {
+    db.types()
+        .iter()
+        .find_map(|(key, ty)| match (key, ty) {
+            (TypeKey::PK(model), DeferredSqlType::Deferred(TypeKey::PK(name))) if name == table => {
+                Some(model.clone())
+            }
+            _ => None,
+        })
+        .unwrap_or_else(|| table.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use butane::migrations::adb::{AColumn, ATable, DeferredSqlType, TypeIdentifier};
+    use butane::SqlType;
+
+    fn table(name: &str, columns: &[&str]) -> ATable {
+        let mut table = ATable::new(name.to_string());
+        for column in columns {
+            table.add_column(AColumn::new_simple(
+                *column,
+                DeferredSqlType::KnownId(TypeIdentifier::Ty(SqlType::BigInt)),
+            ));
+        }
+        table
+    }
+
+    /// The renames of `ops`, as `table` or `table.column` and the new name.
+    fn renamed(ops: &[Operation]) -> Vec<(String, String)> {
+        ops.iter()
+            .map(|op| match op {
+                Operation::RenameTable(old, new) => (old.clone(), new.clone()),
+                Operation::RenameColumn(table, old, new) => {
+                    (format!("{}.{}", table, old), new.clone())
+                }
+                op => panic!("unexpected operation {:?}", op),
+            })
+            .collect()
+    }
+
+    fn pairs(expected: &[(&str, &str)]) -> Vec<(String, String)> {
+        expected
+            .iter()
+            .map(|(old, new)| (old.to_string(), new.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn split_words() {
+        assert_eq!(words("HTTPServer_name"), ["HTTP", "Server", "name"]);
+        assert_eq!(words("blogPost"), ["blog", "Post"]);
+        assert_eq!(words("post2Tag"), ["post2", "Tag"]);
+        assert_eq!(words("__id"), ["id"]);
+    }
+
+    #[test]
+    fn apply_conventions() {
+        for name in CONVENTIONS {
+            assert!(Convention::parse(name).is_some());
+        }
+        assert_eq!(Convention::parse("kebab-case"), None);
+
+        let cases = [
+            ("BlogPost", "blog_post", "blogPost", "BlogPost"),
+            ("blog_post", "blog_post", "blogPost", "BlogPost"),
+            ("HTTPServer", "http_server", "httpServer", "HttpServer"),
+            ("id", "id", "id", "Id"),
+        ];
+        for (name, snake, camel, pascal) in cases {
+            assert_eq!(Convention::Snake.apply(name), snake);
+            assert_eq!(Convention::Camel.apply(name), camel);
+            assert_eq!(Convention::Pascal.apply(name), pascal);
+        }
+    }
+
+    #[test]
+    fn renames_tables_columns_and_join_tables() {
+        let mut db = ADB::new();
+        db.replace_table(table("BlogPost", &["id", "authorName"]));
+        db.replace_table(table("BlogPost_tagNames_Many", &["owner", "has"]));
+        let renames = renames(&db, Convention::Snake).unwrap();
+        assert_eq!(
+            renamed(&renames.ops),
+            pairs(&[
+                ("BlogPost.authorName", "author_name"),
+                ("BlogPost", "blog_post"),
+                ("BlogPost_tagNames_Many", "blog_post_tagNames_Many"),
+            ])
+        );
+        assert_eq!(
+            renamed(&renames.down_ops),
+            pairs(&[
+                ("blog_post_tagNames_Many", "BlogPost_tagNames_Many"),
+                ("blog_post", "BlogPost"),
+                ("BlogPost.author_name", "authorName"),
+            ])
+        );
+        assert_eq!(
+            renames.suggestions["BlogPost"],
+            [
+                "set #[table = \"blog_post\"]",
+                "rename the field authorName to author_name",
+            ]
+        );
+        assert_eq!(renames.suggestions.len(), 1);
+    }
+
+    #[test]
+    fn suggestions_for_custom_table_names() {
+        let mut db = ADB::new();
+        db.replace_table(table("blog_post", &["id"]));
+        db.add_type(
+            TypeKey::PK("BlogPost".to_string()),
+            DeferredSqlType::Deferred(TypeKey::PK("blog_post".to_string())),
+        );
+        db.replace_table(table("tags", &["id"]));
+        db.add_type(
+            TypeKey::PK("Tag".to_string()),
+            DeferredSqlType::Deferred(TypeKey::PK("tags".to_string())),
+        );
+        let pascal = renames(&db, Convention::Pascal).unwrap();
+        // Named after the model, so no longer needs a custom name
+        assert_eq!(
+            pascal.suggestions["BlogPost"],
+            ["remove the #[table] attribute", "rename the field id to Id"]
+        );
+        assert_eq!(
+            pascal.suggestions["Tag"],
+            ["set #[table = \"Tags\"]", "rename the field id to Id"]
+        );
+
+        let unchanged = renames(&db, Convention::Snake).unwrap();
+        assert!(unchanged.ops.is_empty());
+        assert!(unchanged.suggestions.is_empty());
+    }
+
+    #[test]
+    fn rename_to_existing_name() {
+        let mut db = ADB::new();
+        db.replace_table(table("BlogPost", &["id"]));
+        db.replace_table(table("blog_post", &["id"]));
+        assert!(renames(&db, Convention::Snake).is_err());
+
+        let mut db = ADB::new();
+        db.replace_table(table("post", &["id", "userId", "user_id"]));
+        assert!(renames(&db, Convention::Snake).is_err());
+    }
+}
@@ -0,0 +1,187 @@
+//! Scaffolding of a minimal, working butane project, e.g. as a
+//! starting point for a bug report.
+
+use super::Result;
+use butane::db::ConnectionSpec;
+use butane::migrations::{self, MigrationsMut};
+use std::path::Path;
+
+/// Models of the example, without the `#[model]` attribute so that
+/// they may also be used to create the initial migration.
+const MODELS: &[&str] = &[
+    "#[derive(Debug, Default)]
+pub struct Blog {
+    #[auto]
+    pub id: i64,
+    pub name: String,
+}",
+    "#[derive(Debug)]
+pub struct Post {
+    #[auto]
+    pub id: i64,
+    pub title: String,
+    pub blog: ForeignKey<Blog>,
+    pub likes: i32,
+    state: butane::ObjectState,
+}",
+];
+
+const MODELS_HEADER: &str = "use butane::{model, ForeignKey, ObjectState};
+";
+
+const MODELS_IMPLS: &str = "impl Blog {
+    pub fn new(name: impl Into<String>) -> Self {
+        Blog {
+            name: name.into(),
+            ..Default::default()
+        }
+    }
+}
+
+impl Post {
+    pub fn new(blog: &Blog, title: impl Into<String>) -> Self {
+        Post {
+            id: -1,
+            title: title.into(),
+            blog: blog.into(),
+            likes: 0,
+            state: ObjectState::default(),
+        }
+    }
+}
+";
+
+const MAIN: &str = "use butane::db::ConnectionSpec;
+use butane::migrations::{Migration, Migrations};
+use butane::prelude::*;
+use butane::query;
+
+mod models;
+use models::{Blog, Post};
+
+fn main() -> butane::Result<()> {
+    let dir = std::path::Path::new(env!(\"CARGO_MANIFEST_DIR\")).join(\".butane\");
+    let mut conn = butane::db::connect(&ConnectionSpec::load(&dir)?)?;
+    // Bring the database up to date with the models
+    let migrations = butane::embed_migrations!()?;
+    for m in migrations.unapplied_migrations(&conn)? {
+        m.apply(&mut conn)?;
+    }
+
+    let mut blog = Blog::new(\"Cats\");
+    blog.save(&conn)?;
+    let mut post = Post::new(&blog, \"The tabby\");
+    post.likes = 3;
+    post.save(&conn)?;
+
+    let posts = query!(Post, blog.matches(name == \"Cats\") && likes > 0).load(&conn)?;
+    for post in posts {
+        println!(\"{}: {} ({} likes)\", post.id, post.title, post.likes);
+    }
+    Ok(())
+}
+";
+
+/// Where the example project gets butane from. The example uses the
+/// API of the butane this CLI was built with, which may not have been
+/// published, so this is the butane sources alongside those of the CLI
+/// if present, and otherwise the butane repository.
+fn butane_source() -> String {
+    let local = Path::new(env!("CARGO_MANIFEST_DIR")).join("../butane");
+    match local.canonicalize() {
+        Ok(path) if path.join("Cargo.toml").exists() => {
+            format!("path = {:?}", path.to_string_lossy())
+        }
+        _ => format!("git = \"{}\"", env!("CARGO_PKG_REPOSITORY")),
+    }
+}
+
+/// Create an example project in the empty or nonexistent directory
+/// `dir`, using the database at `conn_str` with `backend`. The initial
+/// migration is named `migration_name`.
+pub fn generate(dir: &Path, backend: &str, conn_str: &str, migration_name: &str) -> Result<()> {
+    if dir.exists() && dir.read_dir()?.next().is_some() {
+        anyhow::bail!("{} is not empty", dir.display());
+    }
+    let name = dir
+        .file_name()
+        .and_then(|n| n.to_str())
+        .ok_or_else(|| anyhow::anyhow!("Cannot name a project after {}", dir.display()))?
+        .replace(|c: char| !c.is_ascii_alphanumeric(), "_");
+    let spec = ConnectionSpec::new(backend, conn_str);
+    let backend = spec.get_backend()?;
+
+    std::fs::create_dir_all(dir.join("src"))?;
+    // An empty workspace, so that the example builds on its own even
+    // when created within another project
+    std::fs::write(
+        dir.join("Cargo.toml"),
+        format!(
+            "[package]
+name = \"{}\"
+version = \"0.1.0\"
+edition = \"2018\"
+
+[dependencies]
+butane = {{ {}, features = [\"{}\"] }}
+
+[workspace]
+",
+            name,
+            butane_source(),
+            spec.backend_name
+        ),
+    )?;
+    std::fs::write(dir.join(".gitignore"), "/target\n*.db\n")?;
+    let models: Vec<String> = MODELS
+        .iter()
+        .map(|model| format!("#[model]\n{}\n", model))
+        .collect();
+    std::fs::write(
+        dir.join("src/models.rs"),
+        format!("{}\n{}\n{}", MODELS_HEADER, models.join("\n"), MODELS_IMPLS),
+    )?;
+    std::fs::write(dir.join("src/main.rs"), MAIN)?;
+
+    let butane_dir = dir.join(".butane");
+    std::fs::create_dir_all(&butane_dir)?;
+    spec.save(&butane_dir)?;
+    let mut ms = migrations::from_root(butane_dir.join("migrations"));
+    for model in MODELS {
+        let tokens = model
+            .parse()
+            .map_err(|e| anyhow::anyhow!("Invalid example model: {:?}", e))?;
+        butane_core::codegen::model_with_migrations(tokens, &mut ms);
+    }
+    ms.create_migration(&backend, migration_name, None)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process::Command;
+
+    #[test]
+    fn example_builds() {
+        let dir = std::env::temp_dir().join(format!("butane_example_{}", std::process::id()));
+        generate(&dir, "sqlite", "example.db", "init").unwrap();
+        // Dependencies are shared with the workspace, which has fetched
+        // them, and built in its target directory
+        let workspace = Path::new(env!("CARGO_MANIFEST_DIR")).join("..");
+        std::fs::copy(workspace.join("Cargo.lock"), dir.join("Cargo.lock")).unwrap();
+        let cargo = std::env::var("CARGO").unwrap_or_else(|_| "cargo".to_string());
+        let output = Command::new(cargo)
+            .args(["check", "--offline"])
+            .current_dir(&dir)
+            .env("CARGO_TARGET_DIR", workspace.join("target/example"))
+            .output()
+            .unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(
+            output.status.success(),
+            "{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+}
@@ -0,0 +1,546 @@
+//! Fabricates rows for load and performance testing.
+
+use super::Result;
+use butane::db::{BackendRows, Column, ConnectionMethods};
+use butane::migrations::adb::{AColumn, ATable, DeferredSqlType, TypeIdentifier, TypeKey, ADB};
+use butane::query::{AggregateExpr, AggregateFunction, Order, OrderDirection};
+use butane::{SqlType, SqlVal, SqlValRef};
+use fallible_iterator::FallibleIterator;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use std::convert::TryFrom;
+
+/// Maximum number of existing parent keys sampled for each foreign key.
+const MAX_PARENT_SAMPLE: i32 = 100_000;
+/// Fraction of values in nullable columns which are left NULL.
+const NULL_FRACTION: f64 = 0.1;
+
+const WORDS: &[&str] = &[
+    "alpha", "bravo", "butane", "charlie", "delta", "echo", "foxtrot", "golf", "hotel", "india",
+    "juliet", "kilo", "lima", "mike", "november", "oscar", "papa", "quebec", "romeo", "sierra",
+    "tango", "uniform", "victor", "whiskey", "xray", "yankee", "zulu",
+];
+
+/// Find the table storing `model`, which may be either a table name
+/// or the name of a model type with a custom table name.
+pub fn find_table<'a>(db: &'a ADB, model: &str) -> Option<&'a ATable> {
+    db.get_table(model)
+        .or_else(|| match db.types().get(&TypeKey::PK(model.to_string())) {
+            Some(DeferredSqlType::Deferred(TypeKey::PK(name))) => db.get_table(name),
+            _ => None,
+        })
+}
+
+/// Random number generator for fabricated data. The same seed
+/// produces the same values on every platform, provided the database
+/// contents the generator samples from are also the same.
+pub fn seeded_rng(seed: u64) -> ChaCha8Rng {
+    ChaCha8Rng::seed_from_u64(seed)
+}
+
+/// How values are generated for a single column.
+enum Generator {
+    /// Random values of the column's type.
+    Random(SqlType),
+    /// Sequential values, used where values must be unique.
+    Counter(SqlType, i64),
+    /// Keys sampled from existing rows of the referenced table.
+    Parent(Vec<SqlVal>),
+    /// Values drawn without replacement, for unique columns referring
+    /// to other tables or of enum types.
+    Distinct(Vec<SqlVal>),
+    /// The values of an enum.
+    OneOf(Vec<SqlVal>),
+}
+
+struct ColumnGen {
+    column: Column,
+    nullable: bool,
+    gen: Generator,
+}
+
+/// Insert `rows` rows of fabricated data into `table`. Columns
+/// referencing other tables with foreign keys are filled by sampling
+/// existing rows of those tables, and columns which must be unique are
+/// filled from counters. Values are not generated to satisfy check
+/// constraints.
+pub fn generate(
+    conn: &impl ConnectionMethods,
+    db: &ADB,
+    table: &ATable,
+    rows: u64,
+    rng: &mut impl Rng,
+) -> Result<()> {
+    // Text counters are prefixed with a random tag, so that runs with
+    // different seeds do not collide with each other, and start past
+    // the existing rows, so that runs with the same seed do not either.
+    let tag: u32 = rng.gen();
+    let mut gens: Vec<ColumnGen> = Vec::new();
+    for col in table.columns.iter().filter(|c| !c.is_auto()) {
+        gens.push(column_gen(conn, db, table, col, rng)?);
+    }
+    let columns: Vec<Column> = gens
+        .iter()
+        .map(|g| Column::new(g.column.name(), g.column.ty().clone()))
+        .collect();
+    for _ in 0..rows {
+        let values = gens
+            .iter_mut()
+            .map(|g| next_value(g, tag, rng))
+            .collect::<Result<Vec<SqlVal>>>()?;
+        let refs: Vec<SqlValRef> = values.iter().map(SqlVal::as_ref).collect();
+        conn.insert_only(&table.name, &columns, &refs)?;
+    }
+    Ok(())
+}
+
+fn column_gen(
+    conn: &impl ConnectionMethods,
+    db: &ADB,
+    table: &ATable,
+    col: &AColumn,
+    rng: &mut impl Rng,
+) -> Result<ColumnGen> {
+    let mut enum_values = None;
+    let ty = match col.typeid()? {
+        TypeIdentifier::Ty(ty) => ty,
+        TypeIdentifier::Name(name) => {
+            anyhow::bail!("Cannot generate values for custom type {}", name)
+        }
+        TypeIdentifier::Enum(name) => {
+            let e = db
+                .get_enum(&name)
+                .ok_or_else(|| anyhow::anyhow!("Enum {} does not exist", name))?;
+            enum_values = Some(e.values.iter().cloned().map(SqlVal::Text).collect());
+            SqlType::Text
+        }
+    };
+    if let SqlType::Custom(_) = ty {
+        anyhow::bail!("Cannot generate values for custom type of {}", col.name());
+    }
+    // Column names must be 'static. This is a short-lived process, so
+    // leaking a name per column is of no concern.
+    let column = Column::new(
+        Box::leak(col.name().to_string().into_boxed_str()),
+        ty.clone(),
+    );
+    let gen = if let Some(reference) = col.reference() {
+        let parent = db.get_table(reference.table()).ok_or_else(|| {
+            anyhow::anyhow!("Referenced table {} does not exist", reference.table())
+        })?;
+        let key = match reference.column() {
+            Some(name) => parent.column(name),
+            None => match parent.pk() {
+                [pk] => Some(pk),
+                _ => None,
+            },
+        }
+        .ok_or_else(|| anyhow::anyhow!("Cannot find key of table {}", parent.name))?;
+        let keys = sample_column(conn, parent, key)?;
+        if keys.is_empty() && !col.nullable() {
+            anyhow::bail!(
+                "Table {} has no rows for {}.{} to reference. Generate data for it first.",
+                parent.name,
+                table.name,
+                col.name()
+            );
+        }
+        if must_be_unique(table, col) {
+            Generator::Distinct(unused(conn, table, col, keys, rng)?)
+        } else {
+            Generator::Parent(keys)
+        }
+    } else if let Some(values) = enum_values {
+        if must_be_unique(table, col) {
+            Generator::Distinct(unused(conn, table, col, values, rng)?)
+        } else {
+            Generator::OneOf(values)
+        }
+    } else if must_be_unique(table, col) {
+        let start = match ty {
+            SqlType::Int | SqlType::BigInt => match max_value(conn, table, col, &ty)? {
+                Some(SqlVal::Int(v)) => v as i64 + 1,
+                Some(SqlVal::BigInt(v)) => v + 1,
+                _ => 1,
+            },
+            // Seconds since the epoch
+            SqlType::Timestamp => match max_value(conn, table, col, &ty)? {
+                Some(SqlVal::Timestamp(t)) => t.timestamp() + 1,
+                _ => start_of_2020().timestamp(),
+            },
+            SqlType::Bool => {
+                anyhow::bail!(
+                    "Cannot generate unique values for boolean column {}",
+                    col.name()
+                )
+            }
+            _ => row_count(conn, table)?,
+        };
+        Generator::Counter(ty, start)
+    } else {
+        Generator::Random(ty)
+    };
+    Ok(ColumnGen {
+        column,
+        nullable: col.nullable(),
+        gen,
+    })
+}
+
+fn must_be_unique(table: &ATable, col: &AColumn) -> bool {
+    col.is_pk()
+        || col.unique()
+        || table
+            .unique_constraints
+            .iter()
+            .any(|c| c.columns().iter().any(|name| name == col.name()))
+}
+
+fn sample_column(
+    conn: &impl ConnectionMethods,
+    table: &ATable,
+    col: &AColumn,
+) -> Result<Vec<SqlVal>> {
+    let ty = match col.typeid()? {
+        TypeIdentifier::Ty(ty) => ty,
+        TypeIdentifier::Name(name) => anyhow::bail!("Cannot sample keys of custom type {}", name),
+        TypeIdentifier::Enum(_) => SqlType::Text,
+    };
+    let name: &'static str = Box::leak(col.name().to_string().into_boxed_str());
+    let columns = [Column::new(name, ty.clone())];
+    // Sample in a fixed order so that seeded runs are reproducible
+    let order = [Order {
+        direction: OrderDirection::Ascending,
+        column: name,
+        nulls: None,
+    }];
+    let rows = conn.query(
+        &table.name,
+        &columns,
+        None,
+        Some(MAX_PARENT_SAMPLE),
+        None,
+        Some(&order),
+    )?;
+    Ok(rows
+        .mapped(|row| Ok(SqlVal::from(row.get(0, ty.clone())?)))
+        .collect()?)
+}
+
+/// The largest existing value of `col`, if there are any rows.
+fn max_value(
+    conn: &impl ConnectionMethods,
+    table: &ATable,
+    col: &AColumn,
+    ty: &SqlType,
+) -> Result<Option<SqlVal>> {
+    let name: &'static str = Box::leak(col.name().to_string().into_boxed_str());
+    let columns = [Column::new(name, ty.clone())];
+    let order = [Order {
+        direction: OrderDirection::Descending,
+        column: name,
+        nulls: None,
+    }];
+    let mut rows = conn.query(&table.name, &columns, None, Some(1), None, Some(&order))?;
+    Ok(match rows.next()? {
+        Some(row) => Some(SqlVal::from(row.get(0, ty.clone())?)),
+        None => None,
+    })
+}
+
+/// `values` not yet used in `col` of `table`, in random order.
+fn unused(
+    conn: &impl ConnectionMethods,
+    table: &ATable,
+    col: &AColumn,
+    mut values: Vec<SqlVal>,
+    rng: &mut impl Rng,
+) -> Result<Vec<SqlVal>> {
+    let used = sample_column(conn, table, col)?;
+    values.retain(|v| !used.contains(v));
+    values.shuffle(rng);
+    Ok(values)
+}
+
+/// The number of rows in `table`.
+fn row_count(conn: &impl ConnectionMethods, table: &ATable) -> Result<i64> {
+    let count = AggregateExpr {
+        function: AggregateFunction::Count,
+        column: None,
+        ty: SqlType::BigInt,
+    };
+    let rows = conn.aggregate(&table.name, &[], &[count], None, None)?;
+    Ok(match rows.first().and_then(|row| row.first()) {
+        Some(SqlVal::BigInt(n)) => *n,
+        _ => 0,
+    })
+}
+
+fn next_value(g: &mut ColumnGen, tag: u32, rng: &mut impl Rng) -> Result<SqlVal> {
+    let name = g.column.name();
+    if let Generator::Counter(ty, n) = &mut g.gen {
+        let val = match ty {
+            SqlType::Int => SqlVal::Int(
+                i32::try_from(*n).map_err(|_| anyhow::anyhow!("Ran out of values for {}", name))?,
+            ),
+            SqlType::BigInt => SqlVal::BigInt(*n),
+            SqlType::Real => SqlVal::Real(*n as f64),
+            SqlType::Text => SqlVal::Text(format!("{}-{:08x}-{}", name, tag, n)),
+            SqlType::Blob => SqlVal::Blob(format!("{:08x}-{}", tag, n).into_bytes()),
+            SqlType::Timestamp => SqlVal::Timestamp(
+                chrono::NaiveDateTime::from_timestamp_opt(*n, 0)
+                    .ok_or_else(|| anyhow::anyhow!("Ran out of values for {}", name))?,
+            ),
+            SqlType::Bool | SqlType::Custom(_) => {
+                anyhow::bail!(
+                    "Cannot generate unique values of type {:?} for {}",
+                    ty,
+                    name
+                )
+            }
+        };
+        *n += 1;
+        return Ok(val);
+    }
+    if g.nullable && rng.gen_bool(NULL_FRACTION) {
+        return Ok(SqlVal::Null);
+    }
+    Ok(match &mut g.gen {
+        Generator::Parent(keys) | Generator::OneOf(keys) => {
+            keys.choose(rng).cloned().unwrap_or(SqlVal::Null)
+        }
+        Generator::Distinct(values) => match values.pop() {
+            Some(value) => value,
+            None if g.nullable => SqlVal::Null,
+            None => anyhow::bail!("Ran out of distinct values for {}", name),
+        },
+        Generator::Random(ty) => random_value(ty, rng),
+        Generator::Counter(..) => unreachable!(),
+    })
+}
+
+fn random_value(ty: &SqlType, rng: &mut impl Rng) -> SqlVal {
+    match ty {
+        SqlType::Bool => SqlVal::Bool(rng.gen()),
+        SqlType::Int => SqlVal::Int(rng.gen_range(0..1000)),
+        SqlType::BigInt => SqlVal::BigInt(rng.gen_range(0..1_000_000)),
+        SqlType::Real => SqlVal::Real(rng.gen_range(0.0..1000.0)),
+        SqlType::Text => {
+            let len = rng.gen_range(1..=6);
+            let words: Vec<&str> = (0..len).map(|_| *WORDS.choose(rng).unwrap()).collect();
+            SqlVal::Text(words.join(" "))
+        }
+        SqlType::Blob => {
+            let len = rng.gen_range(0..64);
+            SqlVal::Blob((0..len).map(|_| rng.gen()).collect())
+        }
+        SqlType::Timestamp => {
+            // Some time within 2020. A fixed range rather than one
+            // relative to now keeps seeded runs reproducible.
+            let secs = rng.gen_range(0..366 * 24 * 60 * 60);
+            SqlVal::Timestamp(start_of_2020() + chrono::Duration::seconds(secs))
+        }
+        SqlType::Custom(_) => SqlVal::Null,
+    }
+}
+
+fn start_of_2020() -> chrono::NaiveDateTime {
+    chrono::NaiveDate::from_ymd_opt(2020, 1, 1)
+        .and_then(|date| date.and_hms_opt(0, 0, 0))
+        .expect("valid date")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use butane::db::{connect, BackendConnection, Connection, ConnectionSpec};
+    use butane::migrations::adb::Operation;
+
+    fn item_table() -> ATable {
+        let mut table = ATable::new("Item".to_string());
+        let ty = |ty| DeferredSqlType::Known(ty);
+        table.add_column(AColumn::new(
+            "id",
+            ty(SqlType::BigInt),
+            false,
+            true,
+            false,
+            false,
+            None,
+        ));
+        table.add_column(AColumn::new(
+            "code",
+            ty(SqlType::Text),
+            false,
+            false,
+            false,
+            true,
+            None,
+        ));
+        table.add_column(AColumn::new(
+            "score",
+            ty(SqlType::Int),
+            true,
+            false,
+            false,
+            false,
+            None,
+        ));
+        table
+    }
+
+    fn setup() -> (Connection, ADB) {
+        let conn = connect(&ConnectionSpec::new("sqlite", ":memory:")).unwrap();
+        let mut db = ADB::new();
+        create(&conn, &mut db, item_table());
+        (conn, db)
+    }
+
+    fn create(conn: &Connection, db: &mut ADB, table: ATable) {
+        let sql = conn
+            .backend()
+            .create_migration_sql(db, vec![Operation::AddTable(table.clone())])
+            .unwrap();
+        conn.execute(&sql).unwrap();
+        db.replace_table(table);
+    }
+
+    /// A table with a key column and a unique column of type `ty`.
+    fn unique_table(name: &str, ty: DeferredSqlType) -> ATable {
+        let mut table = ATable::new(name.to_string());
+        table.add_column(AColumn::new(
+            "id",
+            DeferredSqlType::Known(SqlType::BigInt),
+            false,
+            true,
+            true,
+            false,
+            None,
+        ));
+        table.add_column(AColumn::new("value", ty, false, false, false, true, None));
+        table
+    }
+
+    fn distinct_values(conn: &Connection, table: &str, ty: SqlType) -> (usize, usize) {
+        let columns = [Column::new("value", ty.clone())];
+        let mut values: Vec<SqlVal> = conn
+            .query(table, &columns, None, None, None, None)
+            .unwrap()
+            .mapped(|row| Ok(SqlVal::from(row.get(0, ty.clone())?)))
+            .collect()
+            .unwrap();
+        let len = values.len();
+        values.sort_by_key(|v| format!("{:?}", v));
+        values.dedup();
+        (len, values.len())
+    }
+
+    fn rows(conn: &Connection) -> Vec<Vec<SqlVal>> {
+        let columns = [
+            Column::new("id", SqlType::BigInt),
+            Column::new("code", SqlType::Text),
+            Column::new("score", SqlType::Int),
+        ];
+        let order = [Order {
+            direction: OrderDirection::Ascending,
+            column: "id",
+            nulls: None,
+        }];
+        conn.query("Item", &columns, None, None, None, Some(&order))
+            .unwrap()
+            .mapped(|row| {
+                (0..columns.len())
+                    .map(|i| Ok(SqlVal::from(row.get(i, columns[i].ty().clone())?)))
+                    .collect::<butane::Result<Vec<SqlVal>>>()
+            })
+            .collect()
+            .unwrap()
+    }
+
+    #[test]
+    fn same_seed_same_rows() {
+        let (first, db) = setup();
+        let table = db.get_table("Item").unwrap();
+        generate(&first, &db, table, 20, &mut seeded_rng(7)).unwrap();
+        let (second, _) = setup();
+        generate(&second, &db, table, 20, &mut seeded_rng(7)).unwrap();
+        assert_eq!(rows(&first).len(), 20);
+        assert_eq!(rows(&first), rows(&second));
+
+        let (third, _) = setup();
+        generate(&third, &db, table, 20, &mut seeded_rng(8)).unwrap();
+        assert_ne!(rows(&first), rows(&third));
+    }
+
+    #[test]
+    fn rerun_with_same_seed() {
+        let (conn, db) = setup();
+        let table = db.get_table("Item").unwrap();
+        generate(&conn, &db, table, 10, &mut seeded_rng(7)).unwrap();
+        generate(&conn, &db, table, 10, &mut seeded_rng(7)).unwrap();
+        let rows = rows(&conn);
+        assert_eq!(rows.len(), 20);
+        let mut codes: Vec<&SqlVal> = rows.iter().map(|row| &row[1]).collect();
+        codes.sort_by_key(|code| code.text().unwrap().to_string());
+        codes.dedup();
+        assert_eq!(codes.len(), 20);
+    }
+
+    #[test]
+    fn unique_values_of_column_type() {
+        let (conn, mut db) = setup();
+        let stamps = unique_table("Stamp", DeferredSqlType::Known(SqlType::Timestamp));
+        create(&conn, &mut db, stamps);
+        let table = db.get_table("Stamp").unwrap();
+        generate(&conn, &db, table, 5, &mut seeded_rng(7)).unwrap();
+        generate(&conn, &db, table, 5, &mut seeded_rng(7)).unwrap();
+        assert_eq!(
+            distinct_values(&conn, "Stamp", SqlType::Timestamp),
+            (10, 10)
+        );
+
+        let flags = unique_table("Flag", DeferredSqlType::Known(SqlType::Bool));
+        create(&conn, &mut db, flags);
+        let table = db.get_table("Flag").unwrap();
+        assert!(generate(&conn, &db, table, 1, &mut seeded_rng(7)).is_err());
+    }
+
+    #[test]
+    fn int_counter_out_of_range() {
+        let (conn, mut db) = setup();
+        create(
+            &conn,
+            &mut db,
+            unique_table("Counted", DeferredSqlType::Known(SqlType::Int)),
+        );
+        conn.execute(&format!(
+            "INSERT INTO Counted (value) VALUES ({});",
+            i32::MAX
+        ))
+        .unwrap();
+        let table = db.get_table("Counted").unwrap();
+        assert!(generate(&conn, &db, table, 1, &mut seeded_rng(7)).is_err());
+    }
+
+    #[test]
+    fn unique_references() {
+        let (conn, mut db) = setup();
+        let table = db.get_table("Item").unwrap().clone();
+        generate(&conn, &db, &table, 5, &mut seeded_rng(7)).unwrap();
+        let mut detail = unique_table(
+            "Detail",
+            DeferredSqlType::KnownId(TypeIdentifier::Ty(SqlType::BigInt)),
+        );
+        let mut value = detail.column("value").unwrap().clone();
+        value.set_reference(Some(butane::migrations::adb::AForeignKey::new("Item")));
+        detail.replace_column(value);
+        create(&conn, &mut db, detail);
+        let table = db.get_table("Detail").unwrap();
+        generate(&conn, &db, table, 3, &mut seeded_rng(7)).unwrap();
+        generate(&conn, &db, table, 2, &mut seeded_rng(7)).unwrap();
+        assert_eq!(distinct_values(&conn, "Detail", SqlType::BigInt), (5, 5));
+        // Every row of Item is referenced
+        assert!(generate(&conn, &db, table, 1, &mut seeded_rng(7)).is_err());
+    }
+}
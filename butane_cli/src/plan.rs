@@ -0,0 +1,214 @@
+//! Editing the operations of a migration before it is written.
+
+use super::{describe_operation, Result};
+use butane::migrations::adb::{self, Operation, ADB};
+use std::io::{BufRead, Write};
+
+const HELP: &str = "Commands:
+  list        show the operations
+  move N M    move operation N to position M
+  drop N      leave operation N out of the migration
+  rename N M  treat removing table or column N and adding table or column M as renaming it
+  done        write the migration
+  quit        leave without writing a migration";
+
+/// The operations of a migration from `from`, as edited so far.
+pub struct Plan {
+    from: ADB,
+    ops: Vec<Operation>,
+    /// The renames among `ops`, in the order they were marked.
+    renames: Vec<Operation>,
+}
+impl Plan {
+    pub fn new(from: ADB, ops: Vec<Operation>) -> Self {
+        Plan {
+            from,
+            ops,
+            renames: Vec::new(),
+        }
+    }
+
+    pub fn ops(&self) -> &[Operation] {
+        &self.ops
+    }
+
+    /// The schema after the plan's operations.
+    fn to_db(&self) -> ADB {
+        self.state_before(self.ops.len())
+    }
+
+    /// Operations undoing the plan's. Renames are undone first, so that
+    /// the remaining changes are found by comparing schemas.
+    pub fn down_ops(&self) -> Vec<Operation> {
+        let mut to = self.to_db();
+        let mut down_ops = Vec::new();
+        for rename in self.renames.iter().rev() {
+            let undo = match rename {
+                Operation::RenameTable(old, new) => {
+                    Operation::RenameTable(new.clone(), old.clone())
+                }
+                Operation::RenameColumn(table, old, new) => {
+                    Operation::RenameColumn(table.clone(), new.clone(), old.clone())
+                }
+                _ => continue,
+            };
+            to.transform_with(undo.clone());
+            down_ops.push(undo);
+        }
+        down_ops.extend(adb::diff(&to, &self.from));
+        down_ops
+    }
+
+    /// Move the operation at `from` to `to`.
+    pub fn move_op(&mut self, from: usize, to: usize) -> Result<()> {
+        self.check_index(from)?;
+        self.check_index(to)?;
+        let op = self.ops.remove(from);
+        self.ops.insert(to, op);
+        Ok(())
+    }
+
+    /// Leave the operation at `i` out of the migration.
+    pub fn drop_op(&mut self, i: usize) -> Result<()> {
+        self.check_index(i)?;
+        self.ops.remove(i);
+        Ok(())
+    }
+
+    /// Replace the removal of a table or column at `removed` and the
+    /// addition of one at `added` by a rename, followed by whatever
+    /// changes turn the removed one into the added one.
+    pub fn rename(&mut self, removed: usize, added: usize) -> Result<()> {
+        self.check_index(removed)?;
+        self.check_index(added)?;
+        let state = self.state_before(removed.min(added));
+        let (rename, changes) = match (&self.ops[removed], &self.ops[added]) {
+            (Operation::RemoveTable(old), Operation::AddTable(new)) => {
+                let mut renamed = match state.get_table(old) {
+                    Some(table) => table.clone(),
+                    None => anyhow::bail!("There is no table {} to rename", old),
+                };
+                renamed.name = new.name.clone();
+                let mut before = ADB::new();
+                before.replace_table(renamed);
+                let mut after = ADB::new();
+                after.replace_table(new.clone());
+                (
+                    Operation::RenameTable(old.clone(), new.name.clone()),
+                    adb::diff(&before, &after),
+                )
+            }
+            (Operation::RemoveColumn(table, old), Operation::AddColumn(added_table, new))
+                if table == added_table =>
+            {
+                let mut renamed = match state.get_table(table) {
+                    Some(t) if t.column(old).is_some() => t.clone(),
+                    _ => anyhow::bail!("There is no column {}.{} to rename", table, old),
+                };
+                renamed.rename_column(old, new.name());
+                let renamed = renamed.column(new.name()).unwrap();
+                let mut changes = Vec::new();
+                if renamed != new {
+                    changes.push(Operation::ChangeColumn(
+                        table.clone(),
+                        renamed.clone(),
+                        new.clone(),
+                    ));
+                }
+                (
+                    Operation::RenameColumn(table.clone(), old.clone(), new.name().to_string()),
+                    changes,
+                )
+            }
+            (removed, added) => anyhow::bail!(
+                "Cannot rename with \"{}\" and \"{}\": expected removing and adding a table, or removing and adding a column of the same table",
+                describe_operation(removed),
+                describe_operation(added)
+            ),
+        };
+        // The rename takes the place of the addition, after anything
+        // the added table or column depends on
+        let at = if removed < added { added - 1 } else { added };
+        self.ops.remove(removed);
+        self.ops.remove(at);
+        self.ops.insert(at, rename.clone());
+        for (i, op) in changes.into_iter().enumerate() {
+            self.ops.insert(at + 1 + i, op);
+        }
+        self.renames.push(rename);
+        Ok(())
+    }
+
+    fn state_before(&self, i: usize) -> ADB {
+        let mut state = self.from.clone();
+        for op in self.ops[..i].iter().cloned() {
+            state.transform_with(op);
+        }
+        state
+    }
+
+    fn check_index(&self, i: usize) -> Result<()> {
+        if i >= self.ops.len() {
+            anyhow::bail!("There is no operation {}", i + 1);
+        }
+        Ok(())
+    }
+
+    fn list(&self, output: &mut impl Write) -> Result<()> {
+        if self.ops.is_empty() {
+            writeln!(output, "No operations")?;
+        }
+        for (i, op) in self.ops.iter().enumerate() {
+            writeln!(output, "{:3}. {}", i + 1, describe_operation(op))?;
+        }
+        Ok(())
+    }
+}
+
+/// Let the user edit `plan` with commands read from `input`. Returns
+/// true once they are done, or false if they quit.
+pub fn edit(plan: &mut Plan, mut input: impl BufRead, mut output: impl Write) -> Result<bool> {
+    plan.list(&mut output)?;
+    writeln!(output, "{}", HELP)?;
+    loop {
+        write!(output, "> ")?;
+        output.flush()?;
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            return Ok(false);
+        }
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let result = match words.as_slice() {
+            [] => continue,
+            ["list"] => plan.list(&mut output),
+            ["move", from, to] => parse_index(from)
+                .and_then(|from| plan.move_op(from, parse_index(to)?))
+                .and_then(|_| plan.list(&mut output)),
+            ["drop", i] => parse_index(i)
+                .and_then(|i| plan.drop_op(i))
+                .and_then(|_| plan.list(&mut output)),
+            ["rename", removed, added] => parse_index(removed)
+                .and_then(|removed| plan.rename(removed, parse_index(added)?))
+                .and_then(|_| plan.list(&mut output)),
+            // Operations moved before what they depend on may leave an
+            // invalid schema
+            ["done"] => match plan.to_db().validate() {
+                Ok(()) => return Ok(true),
+                Err(e) => Err(e.into()),
+            },
+            ["quit"] => return Ok(false),
+            _ => writeln!(output, "{}", HELP).map_err(|e| e.into()),
+        };
+        if let Err(e) = result {
+            writeln!(output, "{}", e)?;
+        }
+    }
+}
+
+/// The index of the operation numbered `number` from 1.
+fn parse_index(number: &str) -> Result<usize> {
+    match number.parse::<usize>() {
+        Ok(n) if n > 0 => Ok(n - 1),
+        _ => anyhow::bail!("Expected an operation number, got {}", number),
+    }
+}
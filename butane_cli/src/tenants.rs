@@ -0,0 +1,104 @@
+//! Applying migrations to each tenant of a schema-per-tenant
+//! PostgreSQL deployment.
+
+use super::{apply_migration, get_migrations, Result};
+use butane::db::{self, BackendRows, ConnectionMethods, ConnectionSpec};
+use butane::migrations::Migrations;
+use butane::query::{BoolExpr, Expr};
+use butane::{SqlType, SqlVal};
+use fallible_iterator::FallibleIterator;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Schemas which already contain the butane migrations table.
+pub fn discover(spec: &ConnectionSpec) -> Result<Vec<String>> {
+    let conn = db::connect(spec)?;
+    let columns = [db::Column::new("schemaname", SqlType::Text)];
+    let rows = conn.query(
+        "pg_catalog.pg_tables",
+        &columns,
+        Some(BoolExpr::Eq(
+            "tablename",
+            Expr::Val(SqlVal::Text(get_migrations()?.state_table().into_owned())),
+        )),
+        None,
+        None,
+        None,
+    )?;
+    let mut schemas: Vec<String> = rows
+        .mapped(|row| Ok(SqlVal::from(row.get(0, SqlType::Text)?)))
+        .map(|val| Ok(val.text()?.to_string()))
+        .collect()?;
+    schemas.sort();
+    Ok(schemas)
+}
+
+/// Connection spec which operates on `schema` instead of the default.
+pub fn spec_for_schema(spec: &ConnectionSpec, schema: &str) -> Result<ConnectionSpec> {
+    if spec.backend_name != db::pg::BACKEND_NAME {
+        anyhow::bail!("Tenant schemas are only supported with the pg backend");
+    }
+    let mut spec = spec.clone();
+    spec.search_path = vec![schema.to_string()];
+    Ok(spec)
+}
+
+/// Apply unapplied migrations to each schema in `schemas`, working on
+/// up to `parallel` schemas at once. Reports the outcome for each
+/// schema as it completes and returns the number which failed. Each
+/// schema is given `max_duration`, if set, to migrate in.
+pub fn migrate_all(
+    spec: &ConnectionSpec,
+    schemas: Vec<String>,
+    parallel: usize,
+    max_duration: Option<Duration>,
+) -> Result<usize> {
+    let specs = schemas
+        .iter()
+        .map(|schema| Ok((schema.clone(), spec_for_schema(spec, schema)?)))
+        .collect::<Result<Vec<(String, ConnectionSpec)>>>()?;
+    let queue = Mutex::new(specs.into_iter());
+    let failures: Mutex<Vec<(String, String)>> = Mutex::new(Vec::new());
+    std::thread::scope(|scope| {
+        for _ in 0..parallel.max(1) {
+            scope.spawn(|| loop {
+                let next = queue.lock().unwrap().next();
+                let (schema, spec) = match next {
+                    Some(next) => next,
+                    None => break,
+                };
+                match migrate_one(&spec, max_duration) {
+                    Ok(0) => println!("[{}] up to date", schema),
+                    Ok(n) => println!("[{}] applied {} migrations", schema, n),
+                    Err(e) => {
+                        println!("[{}] failed: {}", schema, e);
+                        failures.lock().unwrap().push((schema, e.to_string()));
+                    }
+                }
+            });
+        }
+    });
+    let failures = failures.into_inner().unwrap();
+    println!(
+        "Migrated {} of {} tenant schemas",
+        schemas.len() - failures.len(),
+        schemas.len()
+    );
+    if !failures.is_empty() {
+        println!("Failures:");
+        for (schema, e) in &failures {
+            println!("  {}: {}", schema, e);
+        }
+    }
+    Ok(failures.len())
+}
+
+fn migrate_one(spec: &ConnectionSpec, max_duration: Option<Duration>) -> Result<usize> {
+    let mut conn = db::connect(spec)?;
+    let to_apply = get_migrations()?.unapplied_migrations(&conn)?;
+    let deadline = max_duration.map(|limit| Instant::now() + limit);
+    for m in &to_apply {
+        apply_migration(m, &mut conn, deadline)?;
+    }
+    Ok(to_apply.len())
+}
@@ -1,13 +1,41 @@
 use super::*;
-use proc_macro2::Span;
 use proc_macro2::TokenStream as TokenStream2;
+use proc_macro2::{Delimiter, Group, Punct, Spacing, Span, TokenTree};
 use quote::{quote, quote_spanned, ToTokens};
-use syn::{spanned::Spanned, BinOp, Expr, ExprBinary, ExprMethodCall, ExprPath, Ident, LitStr};
+use syn::{
+    spanned::Spanned, BinOp, Expr, ExprBinary, ExprCall, ExprField, ExprIndex, ExprMethodCall,
+    ExprPath, Ident, LitStr, Member, UnOp,
+};
 
 pub fn for_expr(dbres: &Ident, expr: &Expr) -> TokenStream2 {
     handle_expr(&quote!(<#dbres as butane::DataResult>::DBO::fields()), expr)
 }
 
+/// Rewrites the `in` of `field in values`, which is not a Rust
+/// operator, as `<<`, which is parsed with a suitable precedence and is
+/// otherwise unsupported in filters. Blocks are Rust code and left as
+/// they are.
+pub fn in_as_operator(tokens: TokenStream2) -> TokenStream2 {
+    tokens
+        .into_iter()
+        .flat_map(|token| match token {
+            TokenTree::Ident(ident) if ident == "in" => {
+                let mut first = Punct::new('<', Spacing::Joint);
+                first.set_span(ident.span());
+                let mut second = Punct::new('<', Spacing::Alone);
+                second.set_span(ident.span());
+                vec![TokenTree::Punct(first), TokenTree::Punct(second)]
+            }
+            TokenTree::Group(group) if group.delimiter() != Delimiter::Brace => {
+                let mut rewritten = Group::new(group.delimiter(), in_as_operator(group.stream()));
+                rewritten.set_span(group.span());
+                vec![TokenTree::Group(rewritten)]
+            }
+            token => vec![token],
+        })
+        .collect()
+}
+
 pub fn handle_expr(fields: &impl ToTokens, expr: &Expr) -> TokenStream2 {
     match expr {
         Expr::Binary(binop) => handle_bin_op(fields, binop),
@@ -16,6 +44,15 @@ pub fn handle_expr(fields: &impl ToTokens, expr: &Expr) -> TokenStream2 {
         Expr::Lit(lit) => lit.lit.clone().into_token_stream(),
         Expr::Block(block) => handle_block(&block.block),
         Expr::Group(group) => handle_expr(fields, group.expr.as_ref()),
+        Expr::Paren(paren) => handle_expr(fields, paren.expr.as_ref()),
+        Expr::Unary(unary) if matches!(unary.op, UnOp::Not(_)) => {
+            let inner = handle_expr(fields, &unary.expr);
+            quote!(butane::query::BoolExpr::Not(Box::new(#inner)))
+        }
+        Expr::Call(call) => handle_fn_call(call),
+        Expr::Index(index) => handle_index(fields, index),
+        // A value, such as `json!(...)`
+        Expr::Macro(mac) => mac.into_token_stream(),
         _ => {
             let lit = LitStr::new(
                 &format!(
@@ -40,7 +77,19 @@ fn handle_block(block: &syn::Block) -> TokenStream2 {
 }
 
 fn handle_bin_op(fields: &impl ToTokens, binop: &ExprBinary) -> TokenStream2 {
+    if let Expr::Field(field) = binop.left.as_ref() {
+        return through_foreign_keys(fields, field, |fields, member| {
+            let mut binop = binop.clone();
+            *binop.left = member;
+            handle_bin_op(fields, &binop)
+        });
+    }
     let left = handle_expr(fields, &binop.left);
+    if let BinOp::Shl(_) = binop.op {
+        // `field in values`, where the values are a Rust expression
+        let values = &binop.right;
+        return quote!(#left.is_in(#values));
+    }
     let right = handle_expr(fields, &binop.right);
     match binop.op {
         BinOp::Eq(_) => quote!(#left.eq(&#right)),
@@ -56,23 +105,104 @@ fn handle_bin_op(fields: &impl ToTokens, binop: &ExprBinary) -> TokenStream2 {
 }
 
 fn handle_call(fields: &impl ToTokens, mcall: &ExprMethodCall) -> TokenStream2 {
+    if let Expr::Field(field) = mcall.receiver.as_ref() {
+        return through_foreign_keys(fields, field, |fields, member| {
+            let mut mcall = mcall.clone();
+            *mcall.receiver = member;
+            handle_call(fields, &mcall)
+        });
+    }
     let method = mcall.method.to_string();
     match method.as_str() {
-        "contains" | "matches" => {
-            if mcall.args.len() != 1 {
-                return make_compile_error!(mcall.span()=> "expected one argument to '{}'", method);
-            };
+        "contains" | "matches" | "like" | "ilike" | "eq_ignore_case" | "starts_with"
+        | "ends_with" | "is_in"
+            if mcall.args.len() != 1 =>
+        {
+            return make_compile_error!(mcall.span()=> "expected one argument to '{}'", method);
+        }
+        "extract" | "date_trunc" if mcall.args.len() != 1 => {
+            return make_compile_error!(mcall.span()=> "expected a date part as argument to '{}'", method);
+        }
+        "between" if mcall.args.len() != 2 => {
+            return make_compile_error!(mcall.span()=> "expected two arguments to 'between'");
+        }
+        "is_null" | "is_not_null" if !mcall.args.is_empty() => {
+            return make_compile_error!(mcall.span()=> "expected no arguments to '{}'", method);
         }
         _ => (),
     };
     match method.as_str() {
         "matches" => handle_in(fields, &mcall.receiver, mcall.args.first().unwrap()),
         "contains" => handle_contains(fields, &mcall.receiver, mcall.args.first().unwrap()),
-        "like" => handle_like(fields, &mcall.receiver, mcall.args.first().unwrap()),
+        "like" | "ilike" | "eq_ignore_case" | "starts_with" | "ends_with" => handle_like(
+            fields,
+            &mcall.receiver,
+            &mcall.method,
+            mcall.args.first().unwrap(),
+        ),
+        "is_null" | "is_not_null" => {
+            let fex = fieldexpr(fields, &mcall.receiver);
+            let method = &mcall.method;
+            quote!(#fex.#method())
+        }
+        "extract" | "date_trunc" => {
+            let fex = receiver_expr(fields, &mcall.receiver);
+            let method = &mcall.method;
+            let part = match mcall.args.first().unwrap() {
+                // A variant of DatePart, as `Year`
+                Expr::Path(path) if path.path.get_ident().is_some() => {
+                    let part = path.path.get_ident().unwrap();
+                    quote!(butane::query::DatePart::#part)
+                }
+                part => part.into_token_stream(),
+            };
+            quote!(#fex.#method(#part))
+        }
+        "between" => {
+            let fex = receiver_expr(fields, &mcall.receiver);
+            let low = handle_expr(fields, &mcall.args[0]);
+            let high = handle_expr(fields, &mcall.args[1]);
+            quote!(#fex.between(&#low, &#high))
+        }
+        "is_in" => {
+            let fex = fieldexpr(fields, &mcall.receiver);
+            let values = mcall.args.first().unwrap();
+            quote!(#fex.is_in(#values))
+        }
         _ => make_compile_error!("Unknown method call {}", method),
     }
 }
 
+/// `exists(Post.blog, expr)`, true if a `Post` whose foreign key
+/// `blog` refers to the object filtered matches `expr`, a filter on
+/// `Post`.
+fn handle_fn_call(call: &ExprCall) -> TokenStream2 {
+    match call.func.as_ref() {
+        Expr::Path(path) if path.path.is_ident("exists") => (),
+        func => return make_compile_error!(func.span()=> "Unknown function call"),
+    };
+    if call.args.len() != 2 {
+        return make_compile_error!(call.span()=> "expected two arguments to 'exists'");
+    }
+    let (model, fk) = match &call.args[0] {
+        Expr::Field(ExprField {
+            base,
+            member: Member::Named(fk),
+            ..
+        }) => match base.as_ref() {
+            Expr::Path(path) if path.path.get_ident().is_some() => {
+                (path.path.get_ident().unwrap(), fk)
+            }
+            base => return make_compile_error!(base.span()=> "expected a model type"),
+        },
+        arg => return make_compile_error!(arg.span()=> "expected a foreign key as Model.field"),
+    };
+    let related = quote!(<#model as butane::DataResult>::DBO);
+    let q = handle_expr(&quote!(#related::fields()), &call.args[1]);
+    let span = call.args[0].span();
+    quote_spanned!(span=> #related::fields().#fk().exists::<#related>(#q))
+}
+
 fn handle_in(fields: &impl ToTokens, receiver: &Expr, expr: &Expr) -> TokenStream2 {
     let fex = fieldexpr(fields, receiver);
     match expr {
@@ -90,11 +220,12 @@ fn handle_in(fields: &impl ToTokens, receiver: &Expr, expr: &Expr) -> TokenStrea
 }
 
 fn handle_contains(fields: &impl ToTokens, receiver: &Expr, expr: &Expr) -> TokenStream2 {
-    let fex = fieldexpr(fields, receiver);
+    let fex = receiver_expr(fields, receiver);
     match expr {
         Expr::Lit(lit) => {
-            // treat this as matching the primary key
-            quote!(#fex.containspk(#lit))
+            // treat this as matching the primary key, or a substring
+            // of a string field
+            quote!(butane::query::ContainsLiteral::contains_literal(&#fex, #lit))
         }
         _ => {
             // Arbitrary expression
@@ -105,16 +236,56 @@ fn handle_contains(fields: &impl ToTokens, receiver: &Expr, expr: &Expr) -> Toke
     }
 }
 
-fn handle_like(fields: &impl ToTokens, receiver: &Expr, expr: &Expr) -> TokenStream2 {
+/// `data["address"]["lines"][0]`, the value at a path within a JSON
+/// field. Keys are field names or array indexes, given as literals or
+/// in blocks.
+fn handle_index(fields: &impl ToTokens, index: &ExprIndex) -> TokenStream2 {
+    let base = match index.expr.as_ref() {
+        Expr::Index(inner) => handle_index(fields, inner),
+        Expr::Path(path) if path.path.get_ident().is_some() => fieldexpr(fields, &path.path),
+        base => return make_compile_error!(base.span()=> "expected a JSON field of the model"),
+    };
+    let key = match index.index.as_ref() {
+        Expr::Lit(lit) => lit.lit.clone().into_token_stream(),
+        Expr::Block(block) => {
+            let key = handle_block(&block.block);
+            quote!({ #key })
+        }
+        key => return make_compile_error!(key.span()=> "expected a literal or block as JSON key"),
+    };
+    let span = index.span();
+    quote_spanned!(span=> #base.get(#key))
+}
+
+/// The receiver of a method call: a field, a value within a JSON
+/// field, or a function of a field such as `pub_time.extract(Year)`.
+fn receiver_expr(fields: &impl ToTokens, receiver: &Expr) -> TokenStream2 {
+    match receiver {
+        Expr::Index(index) => handle_index(fields, index),
+        Expr::MethodCall(mcall) => handle_call(fields, mcall),
+        _ => fieldexpr(fields, receiver),
+    }
+}
+
+fn handle_like(
+    fields: &impl ToTokens,
+    receiver: &Expr,
+    method: &Ident,
+    expr: &Expr,
+) -> TokenStream2 {
     let fex = fieldexpr(fields, receiver);
     match expr {
-        Expr::Binary(_) => make_compile_error!("Unexpected binary expression as parameter to like"),
-        Expr::Call(_) => make_compile_error!("Unexpected call expression as parameter to like"),
+        Expr::Binary(_) => {
+            make_compile_error!("Unexpected binary expression as parameter to {}", method)
+        }
+        Expr::Call(_) => {
+            make_compile_error!("Unexpected call expression as parameter to {}", method)
+        }
         _ => {
             // Arbitrary expression
             let q = handle_expr(fields, expr);
             let span = receiver.span();
-            quote_spanned!(span=> #fex.like(#q))
+            quote_spanned!(span=> #fex.#method(#q))
         }
     }
 }
@@ -134,3 +305,52 @@ where
     let span = field.span();
     quote_spanned!(span=> #fields.#fieldexpr_ident())
 }
+
+/// A filter on a field of a related object, as `blog.name == "Cats"`,
+/// matching through the foreign key to it, and any further ones of a
+/// longer path such as `post.blog.name`. `filter` builds the filter on
+/// the last field of the path, given the fields of its model.
+fn through_foreign_keys<F>(fields: &impl ToTokens, field: &ExprField, filter: F) -> TokenStream2
+where
+    F: FnOnce(&TokenStream2, Expr) -> TokenStream2,
+{
+    let mut keys = Vec::new();
+    let mut base = field.base.as_ref();
+    loop {
+        match base {
+            Expr::Field(f) => {
+                keys.push(&f.member);
+                base = f.base.as_ref();
+            }
+            Expr::Path(path) if path.path.get_ident().is_some() => break,
+            _ => return make_compile_error!(base.span()=> "expected a field of the model"),
+        }
+    }
+    let first = match base {
+        Expr::Path(path) => path.path.get_ident().unwrap(),
+        _ => unreachable!(),
+    };
+    let member = match &field.member {
+        Member::Named(ident) => ident.clone(),
+        Member::Unnamed(_) => {
+            return make_compile_error!(field.member.span()=> "expected a named field")
+        }
+    };
+    let mut fexs = vec![quote!(#fields.#first())];
+    for key in keys.iter().rev() {
+        let prev = fexs.last().unwrap();
+        fexs.push(quote!(#prev.fields().#key()));
+    }
+    let last = fexs.last().unwrap();
+    let last_fields = quote!(#last.fields());
+    let member = Expr::Path(ExprPath {
+        attrs: Vec::new(),
+        qself: None,
+        path: member.into(),
+    });
+    let span = field.span();
+    fexs.iter().rev().fold(
+        filter(&last_fields, member),
+        |inner, fex| quote_spanned!(span=> #fex.subfilter(#inner)),
+    )
+}
@@ -31,6 +31,54 @@ mod filter;
 ///    type
 /// * `#[unique]` on a field indicates that the field's value must be unique
 ///    (perhaps implemented as the SQL UNIQUE constraint by some backends).
+///   May also be written `#[butane(unique)]`.
+/// * `#[butane(unique_together("a", "b"))]` on the struct indicates that
+///   the combination of values of the named fields must be unique. May be
+///   given more than once for multiple independent groups.
+/// * `#[butane(check = "price > 0")]` on the struct adds an SQL `CHECK`
+///   constraint which every row must satisfy. May be given more than once.
+///   The constraints are named after the table and the order in which
+///   they are declared.
+/// * `#[butane(foreign_key)]` on a `ForeignKey` field enforces the
+///   relationship with an SQL `FOREIGN KEY` constraint. The actions taken
+///   when the referenced object is deleted or its primary key changes may
+///   be given as `#[butane(foreign_key(on_delete = "cascade", on_update = "restrict"))]`,
+///   with each one of `no_action` (the default), `restrict`, `cascade`,
+///   `set_null` or `set_default`. `set_null` requires an `Option<ForeignKey<T>>`
+///   field. Note that SQLite only enforces foreign keys when the
+///   `foreign_keys` pragma is enabled.
+/// * `#[butane(index)]` on a field indexes the column, and
+///   `#[butane(index("a", "b"))]` on the struct indexes the named fields
+///   together. May be given more than once. A partial index, of only
+///   the rows satisfying an SQL predicate, is declared with
+///   `#[butane(index(predicate = "deleted = false"))]` on a field or
+///   `#[butane(index("a", "b", predicate = "deleted = false"))]` on the struct.
+/// * `#[model(schema = "billing")]` creates the model's tables in the
+///   named Postgres schema (may also be written `#[butane(schema = "billing")]`).
+///   Queries name the tables without their schema, so it must be on the
+///   connection's search path, see [`ConnectionSpec::with_search_path`](butane_core::db::ConnectionSpec::with_search_path).
+///   Other backends ignore the schema.
+/// * Doc comments on the struct and its fields are stored as comments
+///   on the table and its columns, with `COMMENT ON` in Postgres. They
+///   may be overridden with `#[butane(comment = "...")]`. Other
+///   backends ignore comments.
+/// * The values of a `Many` field are stored in a join table named
+///   `{table}_{field}_Many`, with `owner` and `has` columns. It may be
+///   given another name, and extra columns, with
+///   `#[butane(many(table = "post_tags", column(name = "added", sqltype = "Timestamp", default_expr = "CURRENT_TIMESTAMP")))]`.
+///   Only `owner` and `has` are written when values are added, so extra
+///   columns are nullable unless they have a `default_expr`. Renaming
+///   the join table keeps its rows, and removing the field drops it.
+/// * `#[butane(migration_group = "analytics")]` puts the model's
+///   tables in a migration group rather than the main migrations. Each
+///   group has its own migrations, in `.butane/groups/<group>/migrations`,
+///   and its own table recording which of them have been applied
+///   (`butane_migrations_<group>`), so an optional part of an
+///   application can ship its schema separately and only be migrated
+///   where it is enabled. Models in a group may use the types and
+///   reference the models of the main migrations. Pass `--group` to
+///   the `butane` CLI to manage a group's migrations, and embed them
+///   with `embed_migrations!(group = "analytics")`.
 /// * `[default]` should be used on fields added by later migrations to avoid errors on existing objects.
 ///     Unnecessary if the new field is an `Option<>`
 ///
@@ -53,8 +101,22 @@ mod filter;
 /// [`FieldType`]: crate::FieldType
 /// [`Many`]: butane_core::many::Many
 #[proc_macro_attribute]
-pub fn model(_args: TokenStream, input: TokenStream) -> TokenStream {
-    codegen::model_with_migrations(input.into(), &mut migrations_for_dir()).into()
+pub fn model(args: TokenStream, input: TokenStream) -> TokenStream {
+    let args = TokenStream2::from(args);
+    let input = TokenStream2::from(input);
+    // Arguments are options as for the `#[butane(...)]` helper attribute
+    let input = if args.is_empty() {
+        input
+    } else {
+        quote!(#[butane(#args)] #input)
+    };
+    match codegen::migration_group(&input) {
+        Some(group) => match migrations_for_group(&group) {
+            Ok(mut ms) => codegen::model_with_migrations(input, &mut ms).into(),
+            Err(e) => make_compile_error!("Failed to open migration group {}: {}", group, e).into(),
+        },
+        None => codegen::model_with_migrations(input, &mut migrations_for_dir()).into(),
+    }
 }
 
 /// Attribute macro which generates an implementation of
@@ -79,6 +141,64 @@ pub fn dataresult(args: TokenStream, input: TokenStream) -> TokenStream {
     codegen::dataresult(args.into(), input.into()).into()
 }
 
+/// Attribute macro declaring a projection of a model for API layers:
+/// a [`DataResult`](butane_core::DataResult) with a subset of the
+/// model's fields, as with [dataresult](macro@dataresult), along with
+///
+/// * computed fields, annotated `#[butane(computed = "path")]` with a
+///   function taking `&Self` and returning the field's value. They are
+///   computed once the other fields are loaded, which are not yet
+///   available while the field's type's `Default` value is in place.
+/// * `list(&conn, filter)` and `get(&conn, pk)` methods loading the
+///   projection of the matching objects.
+/// * `serde::Serialize` and `serde::Deserialize` derives, unless the
+///   struct already derives `Serialize`.
+///
+/// ```ignore
+/// #[projection(Post)]
+/// pub struct PostApi {
+///   pub id: i64,
+///   pub title: String,
+///   pub body: String,
+///   #[butane(computed = "PostApi::excerpt")]
+///   pub excerpt: String,
+/// }
+/// impl PostApi {
+///   fn excerpt(&self) -> String {
+///     self.body.chars().take(100).collect()
+///   }
+/// }
+///
+/// let published = PostApi::list(&conn, Some(filter!(Post, published == true)))?;
+/// ```
+#[proc_macro_attribute]
+pub fn projection(args: TokenStream, input: TokenStream) -> TokenStream {
+    codegen::projection(args.into(), input.into()).into()
+}
+
+/// Derive macro for [`FromRow`](butane_core::query::FromRow), reading a
+/// struct from the rows of a grouped or aggregate query, or from the
+/// fields selected by a query. Each field is read from the column of
+/// the same name: that of a field grouped by or selected, or an
+/// aggregate [named](butane_core::query::Aggregate::named) after it.
+///
+/// ```ignore
+/// #[derive(FromRow)]
+/// struct BlogLikes {
+///   blog: ForeignKey<Blog>,
+///   posts: i64,
+///   sum_likes: Option<i64>,
+/// }
+///
+/// let likes: Vec<BlogLikes> = Post::query()
+///     .group_by(Post::fields().blog())
+///     .aggregate_into(&conn, (count().named("posts"), sum(Post::fields().likes())))?;
+/// ```
+#[proc_macro_derive(FromRow)]
+pub fn derive_from_row(input: TokenStream) -> TokenStream {
+    codegen::from_row(input.into()).into()
+}
+
 #[proc_macro]
 pub fn filter(input: TokenStream) -> TokenStream {
     let input: TokenStream2 = input.into();
@@ -106,7 +226,7 @@ pub fn filter(input: TokenStream) -> TokenStream {
         return make_compile_error!("Expected filter!(Type, expression)").into();
     }
 
-    let expr: TokenStream2 = args.into_iter().skip(2).collect();
+    let expr: TokenStream2 = filter::in_as_operator(args.into_iter().skip(2).collect());
     let expr: Expr = match syn::parse2(expr) {
         Ok(expr) => expr,
         Err(_) => {
@@ -116,9 +236,23 @@ pub fn filter(input: TokenStream) -> TokenStream {
             .into()
         }
     };
+    #[cfg(feature = "index-suggestions")]
+    record_filter_usage(&tyid, &expr);
     filter::for_expr(&tyid, &expr).into()
 }
 
+/// Records the fields filtered on for `butane suggest-indexes`. This
+/// is only advisory, so failures do not fail the build.
+#[cfg(feature = "index-suggestions")]
+fn record_filter_usage(tyid: &Ident, expr: &Expr) {
+    let mut dir = migrations_dir();
+    dir.pop();
+    dir.push("filters");
+    codegen::FilterUsage::new(dir)
+        .record(&tyid.to_string(), codegen::filtered_fields(expr))
+        .ok();
+}
+
 /// Attribute macro which marks a type as being available to butane
 /// for use in models.
 ///
@@ -149,16 +283,115 @@ pub fn filter(input: TokenStream) -> TokenStream {
 ///  }
 /// }
 /// ```
+///
+/// An enum whose variants have no fields may instead be given the
+/// parameter `Enum`. Its `ToSql`, `FromSql` and `FieldType`
+/// implementations are then generated, storing each variant as its
+/// name. On Postgres, migrations create it as a native enum type
+/// (`CREATE TYPE ... AS ENUM`) and add newly declared variants with
+/// `ALTER TYPE ... ADD VALUE`. Postgres cannot remove values from an
+/// enum, so removed variants remain in the database type. Other
+/// backends store the variants as text.
+///
+/// ```ignore
+/// #[butane_type(Enum)]
+/// pub enum Currency {
+///   Dollars,
+///   Pounds,
+///   Euros,
+/// }
+/// ```
 #[proc_macro_attribute]
 pub fn butane_type(args: TokenStream, input: TokenStream) -> TokenStream {
     codegen::butane_type_with_migrations(args.into(), input.into(), &mut migrations_for_dir())
         .into()
 }
 
+/// Declares an SQL view, managed by migrations like the tables of
+/// models. Takes the name of the view and the `SELECT` statement
+/// defining it. Changing the statement replaces the view in the next
+/// migration. As with models, removing the declaration does not remove
+/// the view; use `butane delete view <VIEW>`.
+///
+/// E.g.
+/// ```ignore
+/// view!(PublishedPost, "SELECT id, title FROM Post WHERE published");
+/// ```
+#[proc_macro]
+pub fn view(input: TokenStream) -> TokenStream {
+    codegen::view_with_migrations(input.into(), &mut migrations_for_dir()).into()
+}
+
+/// Embeds the migrations from the project's `.butane/migrations`
+/// directory at compile time. Evaluates to a
+/// `Result<butane::migrations::MemMigrations>`, so there is no
+/// generated source file (as with `butane embed`) to keep in sync.
+///
+/// E.g.
+/// ```ignore
+/// let migrations = butane::embed_migrations!()?;
+/// for m in migrations.unapplied_migrations(&conn)? {
+///     m.apply(&mut conn)?;
+/// }
+/// ```
+///
+/// May be given the names of tables to embed, separated by commas, so
+/// that a binary creates only the tables for the models it uses (and
+/// the tables they reference). See
+/// [`copy_migration_filtered`](butane_core::migrations::copy_migration_filtered).
+/// ```ignore
+/// let migrations = butane::embed_migrations!(Post, Blog)?;
+/// ```
+///
+/// Or may be given a migration group, to embed its migrations rather
+/// than the main ones.
+/// ```ignore
+/// let analytics = butane::embed_migrations!(group = "analytics")?;
+/// ```
+#[proc_macro]
+pub fn embed_migrations(input: TokenStream) -> TokenStream {
+    if let Ok(syn::MetaNameValue {
+        path,
+        lit: syn::Lit::Str(group),
+        ..
+    }) = syn::parse::<syn::MetaNameValue>(input.clone())
+    {
+        if path.is_ident("group") {
+            return codegen::embed_migrations(&group_dir(&group.value()), None).into();
+        }
+    }
+    let filter = if input.is_empty() {
+        None
+    } else {
+        let parser = syn::punctuated::Punctuated::<Ident, syn::Token![,]>::parse_terminated;
+        match syn::parse::Parser::parse(parser, input) {
+            Ok(names) => Some(migrations::TableFilter::new(
+                names.iter().map(|name| name.to_string()),
+            )),
+            Err(_) => return make_compile_error!("Expected embed_migrations!(Table, ...)").into(),
+        }
+    };
+    codegen::embed_migrations(&migrations_dir(), filter.as_ref()).into()
+}
+
 fn migrations_for_dir() -> migrations::FsMigrations {
     migrations::from_root(&migrations_dir())
 }
 
+fn migrations_for_group(group: &str) -> Result<migrations::FsMigrations> {
+    migrations::group_from_root(group_dir(group), group)
+}
+
+/// Directory of the migrations of the migration group `group`.
+fn group_dir(group: &str) -> PathBuf {
+    let mut dir = migrations_dir();
+    dir.pop();
+    dir.push("groups");
+    dir.push(group);
+    dir.push("migrations");
+    dir
+}
+
 fn migrations_dir() -> PathBuf {
     let mut dir = PathBuf::from(
         std::env::var("CARGO_MANIFEST_DIR").expect("CARGO_MANIFEST_DIR expected to be set"),
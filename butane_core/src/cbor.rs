@@ -0,0 +1,15 @@
+//! The compact binary (CBOR) form of serializable butane types.
+
+use crate::{Error, Result};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+pub(crate) fn to_bytes<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    ciborium::ser::into_writer(value, &mut bytes).map_err(|e| Error::Cbor(e.to_string()))?;
+    Ok(bytes)
+}
+
+pub(crate) fn from_bytes<T: DeserializeOwned>(bytes: &[u8]) -> Result<T> {
+    ciborium::de::from_reader(bytes).map_err(|e| Error::Cbor(e.to_string()))
+}
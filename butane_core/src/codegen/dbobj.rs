@@ -1,5 +1,5 @@
 use super::*;
-use crate::migrations::adb::{DeferredSqlType, TypeIdentifier};
+use crate::migrations::adb::{AIndex, DeferredSqlType, ReferentialAction, TypeIdentifier};
 use crate::SqlType;
 use proc_macro2::TokenStream as TokenStream2;
 use proc_macro2::{Ident, Span};
@@ -10,6 +10,16 @@ use syn::{spanned::Spanned, Field, ItemStruct};
 #[derive(Default)]
 pub struct Config {
     pub table_name: Option<String>,
+    /// Groups of field names which must be unique in combination.
+    pub unique_together: Vec<Vec<String>>,
+    /// SQL expressions for check constraints on the table.
+    pub checks: Vec<String>,
+    /// Indexes over groups of fields.
+    pub indexes: Vec<AIndex>,
+    /// Postgres schema containing the tables.
+    pub schema: Option<String>,
+    /// Comment documenting the table.
+    pub comment: Option<String>,
 }
 
 // implement the DataObject trait
@@ -18,7 +28,7 @@ pub fn impl_dbobject(ast_struct: &ItemStruct, config: &Config) -> TokenStream2 {
     let tablelit = make_tablelit(config, tyname);
     let fields_type = fields_type(tyname);
 
-    let err = verify_fields(ast_struct);
+    let err = verify_fields(ast_struct).or_else(|| verify_unique_together(ast_struct, config));
     if let Some(err) = err {
         return err;
     }
@@ -33,13 +43,13 @@ pub fn impl_dbobject(ast_struct: &ItemStruct, config: &Config) -> TokenStream2 {
     let save_cols = columns(ast_struct, |f| !is_auto(f) && f != &pk_field);
 
     let mut post_insert: Vec<TokenStream2> = Vec::new();
-    add_post_insert_for_auto(&pk_field, &mut post_insert);
+    add_post_insert_for_auto(ast_struct, &pk_field, &mut post_insert);
     post_insert.push(quote!(self.state.saved = true;));
 
     let numdbfields = fields(ast_struct).filter(|f| is_row_field(f)).count();
     let many_save: TokenStream2 = fields(ast_struct).filter(|f| is_many_to_many(f)).map(|f| {
         let ident = f.ident.clone().expect("Fields must be named for butane");
-        let many_table_lit = many_table_lit(ast_struct, f);
+        let many_table_lit = many_table_lit(ast_struct, config, f);
         let pksqltype =
             quote!(<<Self as butane::DataObject>::PKType as butane::FieldType>::SQLTYPE);
         // Save  needs to ensure_initialized
@@ -52,7 +62,64 @@ pub fn impl_dbobject(ast_struct: &ItemStruct, config: &Config) -> TokenStream2 {
     let values: Vec<TokenStream2> = push_values(ast_struct, |_| true);
     let values_no_pk: Vec<TokenStream2> = push_values(ast_struct, |f: &Field| f != &pk_field);
 
-    let dataresult = impl_dataresult(ast_struct, tyname);
+    let dataresult = impl_dataresult(ast_struct, tyname, config);
+    #[cfg(feature = "async")]
+    let dataobject_async = {
+        let many_save_async: TokenStream2 = fields(ast_struct).filter(|f| is_many_to_many(f)).map(|f| {
+            let ident = f.ident.clone().expect("Fields must be named for butane");
+            let many_table_lit = many_table_lit(ast_struct, config, f);
+            let pksqltype =
+                quote!(<<Self as butane::DataObject>::PKType as butane::FieldType>::SQLTYPE);
+            quote!(
+                self.#ident.ensure_init(#many_table_lit, butane::ToSql::to_sql(self.pk()), #pksqltype);
+                self.#ident.save_async(conn).await?;
+            )
+        }).collect();
+        quote!(
+            impl butane::DataObjectAsync for #tyname {
+                fn save_async<'a>(
+                    &'a mut self,
+                    conn: &'a dyn butane::db::nonblocking::AsyncConnectionMethods,
+                ) -> butane::db::nonblocking::BoxFuture<'a, butane::Result<()>> {
+                    use butane::DataObject;
+                    Box::pin(async move {
+                        let mut values: Vec<butane::SqlValRef> = Vec::with_capacity(#numdbfields);
+                        let pkcol = butane::db::Column::new(
+                            #pklit,
+                            <#pktype as butane::FieldType>::SQLTYPE);
+                        if self.state.saved {
+                            #(#values_no_pk)*
+                            if values.len() > 0 {
+                                conn.update(Self::TABLE,
+                                            pkcol,
+                                            butane::ToSql::to_sql_ref(self.pk()),
+                                            &[#save_cols], &values).await?;
+                            }
+                        } else {
+                            #(#values)*
+                            let pk = conn.insert_returning_pk(Self::TABLE, &[#insert_cols], &pkcol, &values).await?;
+                            #(#post_insert)*
+                        }
+                        #many_save_async
+                        Ok(())
+                    })
+                }
+                fn delete_async<'a>(
+                    &'a self,
+                    conn: &'a dyn butane::db::nonblocking::AsyncConnectionMethods,
+                ) -> butane::db::nonblocking::BoxFuture<'a, butane::Result<()>> {
+                    use butane::ToSql;
+                    use butane::DataObject;
+                    // Taken before the future, which then does not
+                    // borrow the object
+                    let pk = self.pk().to_sql();
+                    Box::pin(async move { conn.delete(Self::TABLE, Self::PKCOL, pk).await })
+                }
+            }
+        )
+    };
+    #[cfg(not(feature = "async"))]
+    let dataobject_async = TokenStream2::new();
     quote!(
                 #dataresult
         impl butane::DataObject for #tyname {
@@ -92,6 +159,7 @@ pub fn impl_dbobject(ast_struct: &ItemStruct, config: &Config) -> TokenStream2 {
                 conn.delete(Self::TABLE, Self::PKCOL, self.pk().to_sql())
             }
         }
+        #dataobject_async
         impl butane::ToSql for #tyname {
             fn to_sql(&self) -> butane::SqlVal {
                 use butane::DataObject;
@@ -137,7 +205,7 @@ pub fn impl_dbobject(ast_struct: &ItemStruct, config: &Config) -> TokenStream2 {
     )
 }
 
-pub fn impl_dataresult(ast_struct: &ItemStruct, dbo: &Ident) -> TokenStream2 {
+pub fn impl_dataresult(ast_struct: &ItemStruct, dbo: &Ident, config: &Config) -> TokenStream2 {
     let tyname = &ast_struct.ident;
     let numdbfields = fields(ast_struct).filter(|f| is_row_field(f)).count();
     let rows = rows_for_from(ast_struct);
@@ -151,10 +219,20 @@ pub fn impl_dataresult(ast_struct: &ItemStruct, dbo: &Ident) -> TokenStream2 {
                 .ident
                 .clone()
                 .expect("Fields must be named for butane");
-            let many_table_lit = many_table_lit(ast_struct, f);
+            let many_table_lit = many_table_lit(ast_struct, config, f);
             let pksqltype = quote!(<<Self as butane::DataObject>::PKType as butane::FieldType>::SQLTYPE);
             quote!(obj.#ident.ensure_init(#many_table_lit, butane::ToSql::to_sql(obj.pk()), #pksqltype);)
         }).collect();
+    // Computed once the other fields are loaded
+    let computed_init: TokenStream2 = fields(ast_struct)
+        .filter_map(|f| match get_computed(f) {
+            Ok(Some(path)) => {
+                let ident = f.ident.clone().expect("Fields must be named for butane");
+                Some(quote!(obj.#ident = #path(&obj);))
+            }
+            _ => None,
+        })
+        .collect();
 
     let dbo_is_self = dbo == tyname;
     let ctor = if dbo_is_self {
@@ -186,6 +264,7 @@ pub fn impl_dataresult(ast_struct: &ItemStruct, dbo: &Ident) -> TokenStream2 {
                                 }
                                 #ctor
                                 #many_init
+                                #computed_init
                                 Ok(obj)
                         }
                     fn query() -> butane::query::Query<Self> {
@@ -203,13 +282,13 @@ fn make_tablelit(config: &Config, tyname: &Ident) -> LitStr {
     }
 }
 
-pub fn add_fieldexprs(ast_struct: &ItemStruct) -> TokenStream2 {
+pub fn add_fieldexprs(ast_struct: &ItemStruct, config: &Config) -> TokenStream2 {
     let tyname = &ast_struct.ident;
     let vis = &ast_struct.vis;
     let fieldexprs: Vec<TokenStream2> = fields(ast_struct)
         .map(|f| {
             if is_many_to_many(f) {
-                fieldexpr_func_many(f, ast_struct)
+                fieldexpr_func_many(f, ast_struct, config)
             } else {
                 fieldexpr_func_regular(f, ast_struct)
             }
@@ -247,10 +326,10 @@ fn fieldexpr_func_regular(f: &Field, ast_struct: &ItemStruct) -> TokenStream2 {
     )
 }
 
-fn fieldexpr_func_many(f: &Field, ast_struct: &ItemStruct) -> TokenStream2 {
+fn fieldexpr_func_many(f: &Field, ast_struct: &ItemStruct, config: &Config) -> TokenStream2 {
     let tyname = &ast_struct.ident;
     let fty = get_foreign_type_argument(&f.ty, "Many").expect("Many field misdetected");
-    let many_table_lit = many_table_lit(ast_struct, f);
+    let many_table_lit = many_table_lit(ast_struct, config, f);
     fieldexpr_func(
         f,
         ast_struct,
@@ -315,6 +394,8 @@ fn rows_for_from(ast_struct: &ItemStruct) -> Vec<TokenStream2> {
                 ret
             } else if is_many_to_many(f) {
                 quote!(#ident: butane::Many::new())
+            } else if is_computed(f) {
+                quote!(#ident: Default::default())
             } else {
                 make_compile_error!(f.span()=> "Unexpected struct field")
             }
@@ -342,21 +423,18 @@ where
         .collect()
 }
 
-fn many_table_lit(ast_struct: &ItemStruct, field: &Field) -> LitStr {
-    let tyname = &ast_struct.ident;
-    let ident = field
-        .ident
-        .clone()
-        .expect("Fields must be named for butane");
-    make_lit(&format!("{}_{}_Many", &tyname, &ident))
+fn many_table_lit(ast_struct: &ItemStruct, config: &Config, field: &Field) -> LitStr {
+    let table = match &config.table_name {
+        Some(name) => name.clone(),
+        None => ast_struct.ident.to_string(),
+    };
+    make_lit(&many_table_name(&table, field))
 }
 
 fn verify_fields(ast_struct: &ItemStruct) -> Option<TokenStream2> {
-    let pk_field = pk_field(ast_struct);
-    if pk_field.is_none() {
-        return Some(make_compile_error!(ast_struct.span() => "No pk field found"));
+    if pk_field(ast_struct).is_none() {
+        return Some(super::no_pk_error(ast_struct));
     };
-    let pk_field = pk_field.unwrap();
     for f in fields(ast_struct) {
         if is_auto(f) {
             match get_primitive_sql_type(&f.ty) {
@@ -369,22 +447,92 @@ fn verify_fields(ast_struct: &ItemStruct) -> Option<TokenStream2> {
                     ))
                 }
             }
-            if &pk_field != f {
-                return Some(
-                    quote_spanned!(f.span() => compile_error!("Auto is currently only supported for the primary key")),
-                );
+        }
+        if let Ok(Some(fk)) = get_foreign_key(f) {
+            let set_null = fk.on_delete() == ReferentialAction::SetNull
+                || fk.on_update() == ReferentialAction::SetNull;
+            if set_null && !is_option(f) {
+                return Some(make_compile_error!(f.span() =>
+                    "set_null requires an Option<ForeignKey> field"));
+            }
+        }
+    }
+    None
+}
+
+fn verify_unique_together(ast_struct: &ItemStruct, config: &Config) -> Option<TokenStream2> {
+    let groups = config
+        .unique_together
+        .iter()
+        .map(|group| ("unique_together", group.as_slice()))
+        .chain(
+            config
+                .indexes
+                .iter()
+                .map(|index| ("index", index.columns())),
+        );
+    for (option, group) in groups {
+        if group.is_empty() {
+            return Some(make_compile_error!(ast_struct.span() =>
+                "{} requires at least one field name", option));
+        }
+        for name in group {
+            let found = fields(ast_struct)
+                .any(|f| is_row_field(f) && matches!(&f.ident, Some(ident) if ident == name));
+            if !found {
+                return Some(make_compile_error!(ast_struct.span() =>
+                    "{} refers to unknown field {}", option, name));
             }
         }
     }
     None
 }
 
-fn add_post_insert_for_auto(pk_field: &Field, post_insert: &mut Vec<TokenStream2>) {
-    if !is_auto(pk_field) {
+fn add_post_insert_for_auto(
+    ast_struct: &ItemStruct,
+    pk_field: &Field,
+    post_insert: &mut Vec<TokenStream2>,
+) {
+    if is_auto(pk_field) {
+        let pkident = pk_field.ident.clone().unwrap();
+        post_insert.push(quote!(self.#pkident = butane::FromSql::from_sql(pk)?;));
+    }
+    // The values generated for other auto fields are read back
+    let auto_fields: Vec<&Field> = fields(ast_struct)
+        .filter(|f| is_auto(f) && *f != pk_field)
+        .collect();
+    if auto_fields.is_empty() {
         return;
     }
-    let pkident = pk_field.ident.clone().unwrap();
-    post_insert.push(quote!(self.#pkident = butane::FromSql::from_sql(pk)?;));
+    let pklit = make_ident_literal_str(&pk_field.ident.clone().unwrap());
+    let columns = auto_fields.iter().map(|f| {
+        let lit = make_ident_literal_str(&f.ident.clone().unwrap());
+        let ty = &f.ty;
+        quote!(butane::db::Column::new(#lit, <#ty as butane::FieldType>::SQLTYPE))
+    });
+    let assignments = auto_fields.iter().enumerate().map(|(i, f)| {
+        let ident = f.ident.clone().unwrap();
+        let ty = &f.ty;
+        quote!(
+            self.#ident = butane::FromSql::from_sql_ref(
+                row.get(#i, <#ty as butane::FieldType>::SQLTYPE)?)?;
+        )
+    });
+    post_insert.push(quote!(
+        let generated = [#(#columns),*];
+        let mut rows = conn.query(
+            Self::TABLE,
+            &generated,
+            Some(butane::query::BoolExpr::Eq(
+                #pklit,
+                butane::query::Expr::Val(butane::ToSql::to_sql(self.pk())))),
+            Some(1),
+            None,
+            None)?;
+        if let Some(row) = rows.next()? {
+            #(#assignments)*
+        }
+    ));
 }
 
 /// Builds code for pushing SqlVals for each column satisfying predicate into a vec called `values`
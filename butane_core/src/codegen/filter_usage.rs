@@ -0,0 +1,121 @@
+//! Record of the columns of each model filtered on by the `filter!`
+//! macro (and so by `query!` and `find!`), from which indexes may be
+//! suggested.
+
+use crate::Result;
+use fs2::FileExt;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::OpenOptions;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use syn::{BinOp, Expr};
+
+/// The columns filtered on, kept as one JSON file per type in a
+/// directory (by convention `.butane/filters`). Columns are only ever
+/// added; remove the directory to forget filters which are no longer
+/// used.
+#[derive(Debug)]
+pub struct FilterUsage {
+    root: PathBuf,
+}
+impl FilterUsage {
+    pub fn new(root: impl AsRef<Path>) -> Self {
+        FilterUsage {
+            root: root.as_ref().to_path_buf(),
+        }
+    }
+
+    /// Record that `columns` of the type named `ty` are filtered on.
+    pub fn record(&self, ty: &str, columns: impl IntoIterator<Item = String>) -> Result<()> {
+        std::fs::create_dir_all(&self.root)?;
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(self.root.join(format!("{}.json", ty)))?;
+        // The macro may be expanded by several compilations at once
+        file.lock_exclusive()?;
+        let mut contents = String::new();
+        file.read_to_string(&mut contents)?;
+        let mut recorded: BTreeSet<String> = if contents.is_empty() {
+            BTreeSet::new()
+        } else {
+            serde_json::from_str(&contents)?
+        };
+        let before = recorded.len();
+        recorded.extend(columns);
+        if recorded.len() != before {
+            file.set_len(0)?;
+            file.seek(SeekFrom::Start(0))?;
+            file.write_all(serde_json::to_string(&recorded)?.as_bytes())?;
+        }
+        file.unlock()?;
+        Ok(())
+    }
+
+    /// The recorded columns, by the name of the type filtered on.
+    pub fn load(&self) -> Result<BTreeMap<String, BTreeSet<String>>> {
+        let mut usage = BTreeMap::new();
+        if !self.root.exists() {
+            return Ok(usage);
+        }
+        for entry in std::fs::read_dir(&self.root)? {
+            let path = entry?.path();
+            if path.extension().and_then(|e| e.to_str()) != Some("json") {
+                continue;
+            }
+            let ty = match path.file_stem().and_then(|s| s.to_str()) {
+                Some(ty) => ty.to_string(),
+                None => continue,
+            };
+            let columns: BTreeSet<String> = serde_json::from_str(&std::fs::read_to_string(&path)?)?;
+            usage.insert(ty, columns);
+        }
+        Ok(usage)
+    }
+}
+
+/// The names of the fields compared in the filter expression `expr`,
+/// as written for `filter!`. Fields of related objects filtered on
+/// with `matches` or `contains`, or through a path such as
+/// `blog.name`, are not included, as their type is not known here, but
+/// the foreign key leading to them is.
+pub fn filtered_fields(expr: &Expr) -> BTreeSet<String> {
+    let mut fields = BTreeSet::new();
+    add_filtered_fields(expr, &mut fields);
+    fields
+}
+
+fn add_filtered_fields(expr: &Expr, fields: &mut BTreeSet<String>) {
+    match expr {
+        Expr::Binary(binop) => match binop.op {
+            BinOp::And(_)
+            | BinOp::Or(_)
+            | BinOp::Eq(_)
+            | BinOp::Ne(_)
+            | BinOp::Lt(_)
+            | BinOp::Gt(_)
+            | BinOp::Le(_)
+            | BinOp::Ge(_) => {
+                add_filtered_fields(&binop.left, fields);
+                add_filtered_fields(&binop.right, fields);
+            }
+            // `field in values`, as rewritten for parsing
+            BinOp::Shl(_) => add_filtered_fields(&binop.left, fields),
+            _ => (),
+        },
+        Expr::MethodCall(mcall) => add_filtered_fields(&mcall.receiver, fields),
+        Expr::Path(path) if !path.path.is_ident("None") => {
+            if let Some(ident) = path.path.get_ident() {
+                fields.insert(ident.to_string());
+            }
+        }
+        Expr::Field(field) => add_filtered_fields(&field.base, fields),
+        Expr::Index(index) => add_filtered_fields(&index.expr, fields),
+        Expr::Group(group) => add_filtered_fields(&group.expr, fields),
+        Expr::Paren(paren) => add_filtered_fields(&paren.expr, fields),
+        Expr::Unary(unary) => add_filtered_fields(&unary.expr, fields),
+        _ => (),
+    }
+}
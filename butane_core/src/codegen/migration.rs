@@ -1,5 +1,5 @@
 use super::*;
-use crate::migrations::adb::{AColumn, ATable};
+use crate::migrations::adb::{ACheckConstraint, AColumn, AManyField, ATable, AUniqueConstraint};
 use crate::migrations::{MigrationMut, MigrationsMut};
 use crate::Result;
 use syn::{Field, ItemStruct};
@@ -13,7 +13,20 @@ where
     M: MigrationMut,
 {
     let current_migration = ms.current();
-    for table in create_atables(ast_struct, config) {
+    let tables = create_atables(ast_struct, config);
+    // Join tables of Many fields which have been removed or renamed
+    let model = ast_struct.ident.to_string();
+    let stale: Vec<String> = current_migration
+        .tables()?
+        .into_iter()
+        .filter(|t| t.many.as_ref().is_some_and(|m| m.model == model))
+        .filter(|t| !tables.iter().any(|new| new.name == t.name))
+        .map(|t| t.name.clone())
+        .collect();
+    for name in stale {
+        current_migration.delete_table(&name)?;
+    }
+    for table in tables {
         current_migration.write_table(&table)?;
     }
     if let Some(name) = &config.table_name {
@@ -35,6 +48,16 @@ fn create_atables(ast_struct: &ItemStruct, config: &dbobj::Config) -> Vec<ATable
     let mut table = ATable::new(name);
     let pk = pk_field(ast_struct)
         .expect("No primary key found. Expected 'id' field or field with #[pk] attribute.");
+    for group in &config.unique_together {
+        table.add_unique_constraint(AUniqueConstraint::new(group));
+    }
+    for index in &config.indexes {
+        table.add_index(index.clone());
+    }
+    for (i, expr) in config.checks.iter().enumerate() {
+        let name = format!("{}_check_{}", table.name, i + 1);
+        table.add_check(ACheckConstraint::new(name, expr));
+    }
     let mut result: Vec<ATable> = Vec::new();
     for f in fields(ast_struct) {
         let name = f
@@ -43,7 +66,7 @@ fn create_atables(ast_struct: &ItemStruct, config: &dbobj::Config) -> Vec<ATable
             .expect("db object fields must be named")
             .to_string();
         if is_row_field(f) {
-            let col = AColumn::new(
+            let mut col = AColumn::new(
                 name,
                 get_deferred_sql_type(&f.ty),
                 is_nullable(f),
@@ -51,23 +74,48 @@ fn create_atables(ast_struct: &ItemStruct, config: &dbobj::Config) -> Vec<ATable
                 is_auto(f),
                 is_unique(f),
                 get_default(f).expect("Malformed default attribute"),
-            );
+            )
+            .with_params(get_type_params(f).expect("Malformed type parameters"));
+            if let Some(kind) = get_auto_increment(f) {
+                col = col.with_auto_increment(kind);
+            }
+            if let Some(expr) = get_default_expr(f).expect("Malformed default_expr attribute") {
+                col = col.with_default_expr(expr);
+            }
+            if let Some(fk) = get_foreign_key(f).expect("Malformed foreign_key attribute") {
+                col = col.with_reference(fk);
+            }
+            if let Some(comment) = get_comment(&f.attrs) {
+                col = col.with_comment(comment);
+            }
+            if let Some(index) = get_index(f, col.name()) {
+                table.add_index(index);
+            }
             table.add_column(col);
         } else if is_many_to_many(f) {
-            result.push(many_table(&table.name, f, &pk));
+            result.push(many_table(
+                &ast_struct.ident.to_string(),
+                &table.name,
+                f,
+                &pk,
+            ));
         }
     }
+    table.comment = config.comment.clone();
     result.push(table);
+    for table in &mut result {
+        table.schema = config.schema.clone();
+    }
     result
 }
 
-fn many_table(main_table_name: &str, many_field: &Field, pk_field: &Field) -> ATable {
+fn many_table(model: &str, main_table_name: &str, many_field: &Field, pk_field: &Field) -> ATable {
     let field_name = many_field
         .ident
         .clone()
         .expect("fields must be named")
         .to_string();
-    let mut table = ATable::new(format!("{}_{}_Many", main_table_name, field_name));
+    let mut table = ATable::new(many_table_name(main_table_name, many_field));
     let col = AColumn::new_simple("owner", get_deferred_sql_type(&pk_field.ty));
     table.add_column(col);
     let col = AColumn::new_simple(
@@ -76,6 +124,13 @@ fn many_table(main_table_name: &str, many_field: &Field, pk_field: &Field) -> AT
             .unwrap_or_else(|| panic!("Mis-identified Many field {}", field_name)),
     );
     table.add_column(col);
+    for col in get_many_options(many_field)
+        .expect("Malformed many attribute")
+        .columns
+    {
+        table.add_column(col);
+    }
+    table.many = Some(AManyField::new(model, field_name));
     table
 }
 
@@ -1,14 +1,20 @@
-use crate::migrations::adb::{DeferredSqlType, TypeIdentifier, TypeKey};
-use crate::migrations::{MigrationMut, MigrationsMut};
+use crate::migrations::adb::{
+    AColumn, AEnum, AForeignKey, AIndex, ATypeParams, AView, AutoIncrement, DeferredSqlType,
+    ReferentialAction, TypeIdentifier, TypeKey,
+};
+use crate::migrations::{
+    copy_migration, copy_migration_filtered, FsMigrations, MemMigrations, Migration, MigrationMut,
+    Migrations, MigrationsMut, TableFilter,
+};
 use crate::{SqlType, SqlVal};
 use proc_macro2::TokenStream as TokenStream2;
 use proc_macro2::{Ident, Span, TokenTree};
-use quote::{quote, ToTokens};
+use quote::{quote, quote_spanned, ToTokens};
 use regex::Regex;
 use syn::parse_quote;
 use syn::{
-    punctuated::Punctuated, Attribute, Field, ItemEnum, ItemStruct, ItemType, Lit, LitStr, Meta,
-    MetaNameValue, NestedMeta,
+    punctuated::Punctuated, spanned::Spanned, Attribute, Field, ItemEnum, ItemStruct, ItemType,
+    Lit, LitStr, Meta, MetaNameValue, NestedMeta,
 };
 
 #[macro_export]
@@ -24,8 +30,11 @@ macro_rules! make_compile_error {
 }
 
 mod dbobj;
+mod filter_usage;
 mod migration;
 
+pub use filter_usage::{filtered_fields, FilterUsage};
+
 pub fn model_with_migrations<M>(
     input: TokenStream2,
     ms: &mut impl MigrationsMut<M = M>,
@@ -50,10 +59,33 @@ where
 
     let vis = &ast_struct.vis;
 
-    migration::write_table_to_disk(ms, &ast_struct, &config).unwrap();
+    if pk_field(&ast_struct).is_none() {
+        return no_pk_error(&ast_struct);
+    }
+    for f in fields(&ast_struct) {
+        if let Err(e) = get_type_params(f) {
+            return e.ts;
+        }
+        if let Err(e) = get_default_expr(f) {
+            return e.ts;
+        }
+        if let Err(e) = get_many_options(f) {
+            return e.ts;
+        }
+    }
+    if let Err(e) = migration::write_table_to_disk(ms, &ast_struct, &config) {
+        return match e.code() {
+            Some(code) => {
+                make_compile_error!(ast_struct.span() => "{} [{}]. {}", e, code.code(), code.hint())
+            }
+            None => {
+                make_compile_error!(ast_struct.span() => "Failed to write migration info: {}", e)
+            }
+        };
+    }
 
     let impltraits = dbobj::impl_dbobject(&ast_struct, &config);
-    let fieldexprs = dbobj::add_fieldexprs(&ast_struct);
+    let fieldexprs = dbobj::add_fieldexprs(&ast_struct, &config);
 
     let fields: Punctuated<Field, syn::token::Comma> =
         match remove_helper_field_attributes(&mut ast_struct.fields) {
@@ -94,7 +126,7 @@ pub fn dataresult(args: TokenStream2, input: TokenStream2) -> TokenStream2 {
 
     let vis = &ast_struct.vis;
 
-    let impltraits = dbobj::impl_dataresult(&ast_struct, &dbo);
+    let impltraits = dbobj::impl_dataresult(&ast_struct, &dbo, &dbobj::Config::default());
 
     let fields = match remove_helper_field_attributes(&mut ast_struct.fields) {
         Ok(fields) => &fields.named,
@@ -113,6 +145,124 @@ pub fn dataresult(args: TokenStream2, input: TokenStream2) -> TokenStream2 {
     )
 }
 
+/// Code generation for `#[derive(FromRow)]`. Reads each field from the
+/// column of the same name.
+pub fn from_row(input: TokenStream2) -> TokenStream2 {
+    let ast_struct: ItemStruct = match syn::parse2(input) {
+        Ok(ast_struct) => ast_struct,
+        Err(_) => return make_compile_error!("FromRow may only be derived for structs"),
+    };
+    let named = match &ast_struct.fields {
+        syn::Fields::Named(named) => named,
+        _ => return make_compile_error!(ast_struct.span() => "FromRow requires named fields"),
+    };
+    let idents: Vec<&Ident> = named
+        .named
+        .iter()
+        .map(|f| f.ident.as_ref().expect("fields must be named"))
+        .collect();
+    let row_fields = named.named.iter().map(|f| {
+        let name = make_ident_literal_str(f.ident.as_ref().expect("fields must be named"));
+        let ty = &f.ty;
+        let optional = is_option(f);
+        quote!(butane::query::RowField::new(#name, <#ty as butane::FieldType>::SQLTYPE, #optional))
+    });
+    let ident = &ast_struct.ident;
+    let (impl_generics, ty_generics, where_clause) = ast_struct.generics.split_for_impl();
+    quote!(
+        impl #impl_generics butane::query::FromRow for #ident #ty_generics #where_clause {
+            fn fields() -> Vec<butane::query::RowField> {
+                vec![#(#row_fields),*]
+            }
+            fn read(
+                values: &mut dyn Iterator<Item = butane::SqlVal>,
+            ) -> butane::Result<Self> {
+                Ok(#ident {
+                    #(#idents: butane::FromSql::from_sql(values.next().ok_or_else(|| {
+                        butane::Error::BoundsError("Too few values in row for FromRow".to_string())
+                    })?)?,)*
+                })
+            }
+        }
+    )
+}
+
+/// Code generation for `#[projection(Model)]`. Like
+/// [`dataresult`], but also supports computed fields and generates
+/// query methods and serde derives.
+pub fn projection(args: TokenStream2, input: TokenStream2) -> TokenStream2 {
+    let dbo: Ident = match syn::parse2(args) {
+        Ok(dbo) => dbo,
+        Err(_) => return make_compile_error!("Expected projection(Model)"),
+    };
+    let mut ast_struct: ItemStruct = match syn::parse2(input) {
+        Ok(ast_struct) => ast_struct,
+        Err(_) => return make_compile_error!("projection may only be used on structs"),
+    };
+    for f in fields(&ast_struct) {
+        if let Err(e) = get_computed(f) {
+            return e.ts;
+        }
+    }
+
+    let attrs: Vec<Attribute> = filter_helper_attributes(&ast_struct);
+    let derives = if has_derive_serialize(&attrs) {
+        TokenStream2::new()
+    } else {
+        quote!(
+            #[derive(butane::internal::serde::Serialize, butane::internal::serde::Deserialize)]
+            #[serde(crate = "butane::internal::serde")]
+        )
+    };
+
+    let vis = &ast_struct.vis;
+    let impltraits = dbobj::impl_dataresult(&ast_struct, &dbo, &dbobj::Config::default());
+    let fields = match remove_helper_field_attributes(&mut ast_struct.fields) {
+        Ok(fields) => &fields.named,
+        Err(err) => return err,
+    };
+    let ident = ast_struct.ident;
+
+    quote!(
+        #(#attrs)*
+        #derives
+        #vis struct #ident {
+            #fields
+        }
+        #impltraits
+        impl #ident {
+            /// Load the projection of each object matching `filter`,
+            /// or of all objects if it is `None`.
+            #vis fn list(
+                conn: &impl butane::db::ConnectionMethods,
+                filter: Option<butane::query::BoolExpr>,
+            ) -> butane::Result<Vec<Self>> {
+                let mut query = <Self as butane::DataResult>::query();
+                if let Some(filter) = filter {
+                    query = query.filter(filter);
+                }
+                query.load(conn)
+            }
+            /// Load the projection of the object with the primary key `id`.
+            #vis fn get(
+                conn: &impl butane::db::ConnectionMethods,
+                id: impl std::borrow::Borrow<<#dbo as butane::DataObject>::PKType>,
+            ) -> butane::Result<Self> {
+                let filter = butane::query::BoolExpr::Eq(
+                    <#dbo as butane::DataObject>::PKCOL,
+                    butane::query::Expr::Val(butane::ToSql::to_sql(id.borrow())),
+                );
+                <Self as butane::DataResult>::query()
+                    .filter(filter)
+                    .limit(1)
+                    .load(conn)?
+                    .pop()
+                    .ok_or(butane::Error::NoSuchObject)
+            }
+        }
+    )
+}
+
 fn parse_butane_type_args(args: TokenStream2) -> std::result::Result<TypeIdentifier, TokenStream2> {
     let args: Vec<TokenTree> = args.into_iter().collect();
     if args.is_empty() {
@@ -123,7 +273,7 @@ fn parse_butane_type_args(args: TokenStream2) -> std::result::Result<TypeIdentif
         _ => return Err(quote!(compile_error!("Unexpected tokens in butane_type");)),
     };
     if args.len() == 1 {
-        return Ok(match sqltype_from_name(&tyid) {
+        return Ok(match sqltype_from_name(&tyid.to_string()) {
             Some(ty) => ty,
             None => {
                 eprintln!("No SqlType value named {}", tyid);
@@ -163,6 +313,13 @@ where
         })
     }
 
+    if tyinfo.is_none() && is_enum_arg(&args) {
+        return match syn::parse2::<ItemEnum>(input) {
+            Ok(item) => enum_type_with_migrations(item, ms),
+            Err(_) => quote!(compile_error!("butane_type(Enum) may only be used on an enum");),
+        };
+    }
+
     if tyinfo.is_none() {
         // For types below here, we need the SqlType given to us
         let sqltype = match parse_butane_type_args(args) {
@@ -196,11 +353,170 @@ where
     }
 }
 
+fn is_enum_arg(args: &TokenStream2) -> bool {
+    let args: Vec<TokenTree> = args.clone().into_iter().collect();
+    matches!(args.as_slice(), [TokenTree::Ident(id)] if id == "Enum")
+}
+
+/// Record `item`, an enum of unit variants, as an enumerated type and
+/// implement `ToSql`, `FromSql` and `FieldType` for it. Each variant
+/// is stored as its name.
+fn enum_type_with_migrations<M>(item: ItemEnum, ms: &mut impl MigrationsMut<M = M>) -> TokenStream2
+where
+    M: MigrationMut,
+{
+    if let Some(v) = item
+        .variants
+        .iter()
+        .find(|v| !matches!(v.fields, syn::Fields::Unit))
+    {
+        return make_compile_error!(v.span() => "butane_type(Enum) variants may not have fields");
+    }
+    let ident = &item.ident;
+    let name = ident.to_string();
+    let variants: Vec<&Ident> = item.variants.iter().map(|v| &v.ident).collect();
+    let values: Vec<LitStr> = variants.iter().map(|v| make_ident_literal_str(v)).collect();
+    let aenum = AEnum::new(
+        name.clone(),
+        variants.iter().map(|v| v.to_string()).collect(),
+    );
+    let recorded = add_custom_type(
+        ms,
+        name.clone(),
+        DeferredSqlType::KnownId(TypeIdentifier::Enum(name)),
+    )
+    .and_then(|_| ms.current().write_enum(&aenum));
+    if let Err(e) = recorded {
+        return make_compile_error!(ident.span() => "Failed to write migration info: {}", e);
+    }
+    quote!(
+        #item
+        impl butane::ToSql for #ident {
+            fn to_sql(&self) -> butane::SqlVal {
+                butane::ToSql::to_sql_ref(self).into()
+            }
+            fn to_sql_ref(&self) -> butane::SqlValRef<'_> {
+                butane::SqlValRef::Text(match self {
+                    #(Self::#variants => #values,)*
+                })
+            }
+        }
+        impl butane::FromSql for #ident {
+            fn from_sql_ref(val: butane::SqlValRef) -> butane::Result<Self> {
+                match val {
+                    #(butane::SqlValRef::Text(#values) => Ok(Self::#variants),)*
+                    _ => Err(butane::Error::CannotConvertSqlVal(
+                        butane::SqlType::Text,
+                        val.into(),
+                    )),
+                }
+            }
+        }
+        impl butane::FieldType for #ident {
+            type RefType = Self;
+            const SQLTYPE: butane::SqlType = butane::SqlType::Text;
+        }
+    )
+}
+
+/// Record the view declared by `input`, of the form `Name, "SELECT
+/// ..."`, in the current migration. Expands to nothing.
+pub fn view_with_migrations<M>(
+    input: TokenStream2,
+    ms: &mut impl MigrationsMut<M = M>,
+) -> TokenStream2
+where
+    M: MigrationMut,
+{
+    let parser = |input: syn::parse::ParseStream| -> syn::Result<(Ident, LitStr)> {
+        let name: Ident = input.parse()?;
+        input.parse::<syn::Token![,]>()?;
+        let query: LitStr = input.parse()?;
+        Ok((name, query))
+    };
+    let (name, query) = match syn::parse::Parser::parse2(parser, input) {
+        Ok(parsed) => parsed,
+        Err(e) => return e.to_compile_error(),
+    };
+    let view = AView::new(name.to_string(), query.value());
+    match ms.current().write_view(&view) {
+        Ok(()) => TokenStream2::new(),
+        Err(e) => make_compile_error!(name.span() => "Failed to write migration info: {}", e),
+    }
+}
+
 fn make_ident_literal_str(ident: &Ident) -> LitStr {
     let as_str = format!("{}", ident);
     LitStr::new(&as_str, Span::call_site())
 }
 
+/// Generate an expression evaluating to
+/// `Result<butane::migrations::MemMigrations>` containing all the
+/// migrations in `dir` except for the current one. The generated code
+/// also depends on each migration file so that the embedding is
+/// rebuilt when migrations change.
+pub fn embed_migrations(dir: &std::path::Path, filter: Option<&TableFilter>) -> TokenStream2 {
+    if !dir.exists() {
+        return make_compile_error!(
+            "No butane migrations directory found at {}. Add at least one model to your project and build.",
+            dir.display()
+        );
+    }
+    let ms = FsMigrations::new(dir.to_path_buf());
+    let mut mem_ms = MemMigrations::new();
+    mem_ms.set_state_table(&ms.state_table());
+    let mut files: Vec<std::path::PathBuf> = vec![dir.join("state.json")];
+    let embedded = ms.all_migrations().and_then(|all| {
+        let mut prev = None;
+        for m in all {
+            let mut new_m = mem_ms.new_migration(&m.name());
+            match filter {
+                Some(filter) => copy_migration_filtered(&m, prev.as_ref(), &mut new_m, filter)?,
+                None => copy_migration(&m, &mut new_m)?,
+            }
+            mem_ms.add_migration(new_m)?;
+            for entry in std::fs::read_dir(dir.join(m.name().as_ref()))? {
+                let path = entry?.path();
+                if path.file_name() != Some(std::ffi::OsStr::new("lock")) {
+                    files.push(path);
+                }
+            }
+            prev = Some(m);
+        }
+        Ok(serde_json::to_string(&mem_ms)?)
+    });
+    let json = match embedded {
+        Ok(json) => make_lit(&json),
+        Err(e) => return make_compile_error!("Failed to embed migrations: {}", e),
+    };
+    let files: Vec<LitStr> = files
+        .iter()
+        .filter(|path| path.exists())
+        .map(|path| make_lit(&path.to_string_lossy()))
+        .collect();
+    quote!({
+        #(const _: &[u8] = include_bytes!(#files);)*
+        butane::migrations::MemMigrations::from_json(#json)
+    })
+}
+
+/// The migration group of the model `input`, given as
+/// `#[butane(migration_group = "analytics")]`. None for models in the
+/// main migrations.
+pub fn migration_group(input: &TokenStream2) -> Option<String> {
+    let ast_struct: ItemStruct = syn::parse2(input.clone()).ok()?;
+    butane_attr_options(&ast_struct.attrs)
+        .into_iter()
+        .find_map(|option| match option {
+            NestedMeta::Meta(Meta::NameValue(MetaNameValue {
+                path,
+                lit: Lit::Str(s),
+                ..
+            })) if path.is_ident("migration_group") => Some(s.value()),
+            _ => None,
+        })
+}
+
 pub fn make_lit(s: &str) -> LitStr {
     LitStr::new(s, Span::call_site())
 }
@@ -210,12 +526,15 @@ fn filter_helper_attributes(ast_struct: &ItemStruct) -> Vec<Attribute> {
         .attrs
         .clone()
         .into_iter()
-        .filter(|a| !a.path.is_ident("table"))
+        .filter(|a| !a.path.is_ident("table") && !a.path.is_ident("butane"))
         .collect()
 }
 
 fn config_from_attributes(ast_struct: &ItemStruct) -> dbobj::Config {
-    let mut config = dbobj::Config::default();
+    let mut config = dbobj::Config {
+        comment: get_comment(&ast_struct.attrs),
+        ..Default::default()
+    };
     for attr in &ast_struct.attrs {
         if let Ok(Meta::NameValue(MetaNameValue {
             path,
@@ -228,9 +547,65 @@ fn config_from_attributes(ast_struct: &ItemStruct) -> dbobj::Config {
             }
         }
     }
+    for option in butane_attr_options(&ast_struct.attrs) {
+        if let NestedMeta::Meta(Meta::NameValue(MetaNameValue {
+            path,
+            lit: Lit::Str(s),
+            ..
+        })) = &option
+        {
+            if path.is_ident("check") {
+                config.checks.push(s.value());
+            } else if path.is_ident("schema") {
+                config.schema = Some(s.value());
+            }
+        }
+        if let NestedMeta::Meta(Meta::List(ml)) = option {
+            let names = ml
+                .nested
+                .iter()
+                .filter_map(|nm| match nm {
+                    NestedMeta::Lit(Lit::Str(s)) => Some(s.value()),
+                    _ => None,
+                })
+                .collect();
+            if ml.path.is_ident("unique_together") {
+                config.unique_together.push(names);
+            } else if ml.path.is_ident("index") {
+                let index = AIndex::new(names);
+                config.indexes.push(match index_predicate(&ml) {
+                    Some(predicate) => index.with_predicate(predicate),
+                    None => index,
+                });
+            }
+        }
+    }
     config
 }
 
+/// Options given in `#[butane(...)]` helper attributes.
+fn butane_attr_options(attrs: &[Attribute]) -> Vec<NestedMeta> {
+    attrs
+        .iter()
+        .filter(|a| a.path.is_ident("butane"))
+        .filter_map(|a| match a.parse_meta() {
+            Ok(Meta::List(ml)) => Some(ml.nested.into_iter()),
+            _ => None,
+        })
+        .flatten()
+        .collect()
+}
+
+/// Tests for a bare flag such as `unique` in `#[butane(unique)]`.
+fn has_butane_flag(attrs: &[Attribute], name: &str) -> bool {
+    butane_attr_options(attrs)
+        .iter()
+        .any(|option| match option {
+            NestedMeta::Meta(Meta::Path(path)) => path.is_ident(name),
+            _ => false,
+        })
+}
+
 fn remove_helper_field_attributes(
     fields: &mut syn::Fields,
 ) -> std::result::Result<&syn::FieldsNamed, TokenStream2> {
@@ -243,6 +618,7 @@ fn remove_helper_field_attributes(
                         && !a.path.is_ident("sqltype")
                         && !a.path.is_ident("default")
                         && !a.path.is_ident("unique")
+                        && !a.path.is_ident("butane")
                 });
             }
             Ok(fields)
@@ -273,6 +649,11 @@ fn remove_existing_state_field(
         .collect()
 }
 
+fn no_pk_error(ast_struct: &ItemStruct) -> TokenStream2 {
+    let code = crate::ErrorCode::NoPrimaryKey;
+    make_compile_error!(ast_struct.span() => "No pk field found [{}]. {}", code.code(), code.hint())
+}
+
 fn pk_field(ast_struct: &ItemStruct) -> Option<Field> {
     let pk_by_attribute =
         fields(ast_struct).find(|f| f.attrs.iter().any(|attr| attr.path.is_ident("pk")));
@@ -287,11 +668,56 @@ fn pk_field(ast_struct: &ItemStruct) -> Option<Field> {
 }
 
 fn is_auto(field: &Field) -> bool {
-    field.attrs.iter().any(|attr| attr.path.is_ident("auto"))
+    get_auto_increment(field).is_some()
+}
+
+/// How the values of an auto field are generated: `#[auto]` is serial
+/// and `#[butane(identity)]` an identity column.
+fn get_auto_increment(field: &Field) -> Option<AutoIncrement> {
+    if has_butane_flag(&field.attrs, "identity") {
+        Some(AutoIncrement::Identity)
+    } else if field.attrs.iter().any(|attr| attr.path.is_ident("auto")) {
+        Some(AutoIncrement::Serial)
+    } else {
+        None
+    }
 }
 
 fn is_unique(field: &Field) -> bool {
     field.attrs.iter().any(|attr| attr.path.is_ident("unique"))
+        || has_butane_flag(&field.attrs, "unique")
+}
+
+/// The index on the column `column` declared by `#[butane(index)]`,
+/// or `#[butane(index(predicate = "..."))]` for a partial index.
+fn get_index(field: &Field, column: &str) -> Option<AIndex> {
+    if has_butane_flag(&field.attrs, "index") {
+        return Some(AIndex::new([column]));
+    }
+    butane_attr_options(&field.attrs)
+        .iter()
+        .find_map(|option| match option {
+            NestedMeta::Meta(Meta::List(ml)) if ml.path.is_ident("index") => {
+                let index = AIndex::new([column]);
+                Some(match index_predicate(ml) {
+                    Some(predicate) => index.with_predicate(predicate),
+                    None => index,
+                })
+            }
+            _ => None,
+        })
+}
+
+/// The `predicate = "..."` given in an `index(...)` option.
+fn index_predicate(ml: &syn::MetaList) -> Option<String> {
+    ml.nested.iter().find_map(|nm| match nm {
+        NestedMeta::Meta(Meta::NameValue(MetaNameValue {
+            path,
+            lit: Lit::Str(s),
+            ..
+        })) if path.is_ident("predicate") => Some(s.value()),
+        _ => None,
+    })
 }
 
 fn fields(ast_struct: &ItemStruct) -> impl Iterator<Item = &Field> {
@@ -321,6 +747,113 @@ fn is_many_to_many(field: &Field) -> bool {
     get_many_sql_type(field).is_some()
 }
 
+/// The join table of a `Many` field, as customised with
+/// `#[butane(many(table = "post_tags", column(name = "added", sqltype =
+/// "Timestamp", default_expr = "CURRENT_TIMESTAMP")))]`.
+#[derive(Default)]
+struct ManyOptions {
+    table: Option<String>,
+    /// Columns besides `owner` and `has`. Only those two are written
+    /// when values are added, so the others are nullable unless they
+    /// have a default.
+    columns: Vec<AColumn>,
+}
+
+fn get_many_options(field: &Field) -> std::result::Result<ManyOptions, CompilerErrorMsg> {
+    let mut options = ManyOptions::default();
+    let nested = butane_attr_options(&field.attrs)
+        .into_iter()
+        .find_map(|option| match option {
+            NestedMeta::Meta(Meta::List(ml)) if ml.path.is_ident("many") => Some(ml.nested),
+            _ => None,
+        });
+    let nested = match nested {
+        None => return Ok(options),
+        Some(nested) => nested,
+    };
+    if !is_many_to_many(field) {
+        return Err(
+            make_compile_error!(field.span()=> "many is only supported on Many fields").into(),
+        );
+    }
+    for option in nested {
+        match option {
+            NestedMeta::Meta(Meta::NameValue(MetaNameValue {
+                path,
+                lit: Lit::Str(value),
+                ..
+            })) if path.is_ident("table") => options.table = Some(value.value()),
+            NestedMeta::Meta(Meta::List(ml)) if ml.path.is_ident("column") => {
+                options.columns.push(many_column(ml)?)
+            }
+            _ => return Err(make_compile_error!("malformed many attribute").into()),
+        }
+    }
+    Ok(options)
+}
+
+/// An extra column of a join table given as `column(name = "added",
+/// sqltype = "Timestamp", default_expr = "CURRENT_TIMESTAMP")`.
+fn many_column(ml: syn::MetaList) -> std::result::Result<AColumn, CompilerErrorMsg> {
+    let mut name = None;
+    let mut sqltype = None;
+    let mut default_expr = None;
+    for option in ml.nested {
+        let (path, value) = match option {
+            NestedMeta::Meta(Meta::NameValue(MetaNameValue {
+                path,
+                lit: Lit::Str(value),
+                ..
+            })) => (path, value.value()),
+            _ => return Err(make_compile_error!("malformed many column").into()),
+        };
+        if path.is_ident("name") {
+            name = Some(value);
+        } else if path.is_ident("sqltype") {
+            sqltype = Some(sqltype_from_name(&value).ok_or_else(|| {
+                CompilerErrorMsg::from(make_compile_error!("No SqlType value named {}", value))
+            })?);
+        } else if path.is_ident("default_expr") {
+            default_expr = Some(value);
+        } else {
+            return Err(make_compile_error!("unknown many column option").into());
+        }
+    }
+    let (name, sqltype) = match (name, sqltype) {
+        (Some(name), Some(sqltype)) => (name, sqltype),
+        _ => return Err(make_compile_error!("many column requires name and sqltype").into()),
+    };
+    if name == "owner" || name == "has" {
+        return Err(make_compile_error!("{} is already a column of the join table", name).into());
+    }
+    let mut col = AColumn::new(
+        name,
+        DeferredSqlType::KnownId(sqltype),
+        default_expr.is_none(),
+        false,
+        false,
+        false,
+        None,
+    );
+    if let Some(expr) = default_expr {
+        col = col.with_default_expr(expr);
+    }
+    Ok(col)
+}
+
+/// Name of the join table storing the `Many` field `field` of the
+/// model stored in the table `table`.
+fn many_table_name(table: &str, field: &Field) -> String {
+    match get_many_options(field).ok().and_then(|o| o.table) {
+        Some(name) => name,
+        None => format!(
+            "{}_{}_Many",
+            table,
+            field.ident.as_ref().expect("fields must be named")
+        ),
+    }
+}
+
 fn is_option(field: &Field) -> bool {
     get_foreign_type_argument(&field.ty, "Option").is_some()
 }
@@ -328,7 +861,29 @@ fn is_option(field: &Field) -> bool {
 /// Check for special fields which won't correspond to rows and don't
 /// implement FieldType
 fn is_row_field(f: &Field) -> bool {
-    !is_many_to_many(f)
+    !is_many_to_many(f) && !is_computed(f)
+}
+
+fn is_computed(f: &Field) -> bool {
+    matches!(get_computed(f), Ok(Some(_)))
+}
+
+/// The function computing the value of a field from the other fields
+/// of its struct, given as `#[butane(computed = "path")]`.
+fn get_computed(field: &Field) -> std::result::Result<Option<syn::Path>, CompilerErrorMsg> {
+    for option in butane_attr_options(&field.attrs) {
+        if let NestedMeta::Meta(Meta::NameValue(mnv)) = option {
+            if mnv.path.is_ident("computed") {
+                return match mnv.lit {
+                    Lit::Str(s) => s.parse().map(Some).map_err(|_| {
+                        make_compile_error!(s.span()=> "Expected the path of a function").into()
+                    }),
+                    _ => Err(make_compile_error!("computed must be a string literal").into()),
+                };
+            }
+        }
+    }
+    Ok(None)
 }
 
 fn get_foreign_type_argument<'a>(ty: &'a syn::Type, tyname: &'static str) -> Option<&'a syn::Path> {
@@ -400,6 +955,160 @@ fn get_default(field: &Field) -> std::result::Result<Option<SqlVal>, CompilerErr
     Ok(Some(sqlval_from_lit(lit)?))
 }
 
+/// Comment documenting a table or column, given as
+/// `#[butane(comment = "...")]` or otherwise taken from the doc comments.
+fn get_comment(attrs: &[Attribute]) -> Option<String> {
+    let explicit = butane_attr_options(attrs)
+        .into_iter()
+        .find_map(|option| match option {
+            NestedMeta::Meta(Meta::NameValue(MetaNameValue {
+                path,
+                lit: Lit::Str(s),
+                ..
+            })) if path.is_ident("comment") => Some(s.value()),
+            _ => None,
+        });
+    explicit.or_else(|| {
+        let doc = attrs
+            .iter()
+            .filter(|a| a.path.is_ident("doc"))
+            .filter_map(|a| match a.parse_meta() {
+                Ok(Meta::NameValue(MetaNameValue {
+                    lit: Lit::Str(s), ..
+                })) => Some(s.value().trim().to_string()),
+                _ => None,
+            })
+            .collect::<Vec<String>>()
+            .join("\n");
+        let doc = doc.trim();
+        (!doc.is_empty()).then(|| doc.to_string())
+    })
+}
+
+/// Default computed by the database, given as
+/// `#[butane(default_expr = "CURRENT_TIMESTAMP")]`.
+fn get_default_expr(field: &Field) -> std::result::Result<Option<String>, CompilerErrorMsg> {
+    let lit = butane_attr_options(&field.attrs)
+        .into_iter()
+        .find_map(|option| match option {
+            NestedMeta::Meta(Meta::NameValue(MetaNameValue { path, lit, .. }))
+                if path.is_ident("default_expr") =>
+            {
+                Some(lit)
+            }
+            _ => None,
+        });
+    let expr = match lit {
+        None => return Ok(None),
+        Some(Lit::Str(s)) => s.value(),
+        Some(_) => return Err(make_compile_error!("default_expr must be a string").into()),
+    };
+    if expr.trim().is_empty() {
+        return Err(make_compile_error!(field.span()=> "default_expr cannot be empty").into());
+    }
+    if get_default(field).ok().flatten().is_some() {
+        return Err(make_compile_error!(
+            field.span()=> "default_expr cannot be combined with default"
+        )
+        .into());
+    }
+    Ok(Some(expr))
+}
+
+/// Foreign key constraint requested with `#[butane(foreign_key)]` or
+/// `#[butane(foreign_key(on_delete = "cascade", on_update = "restrict"))]`.
+fn get_foreign_key(field: &Field) -> std::result::Result<Option<AForeignKey>, CompilerErrorMsg> {
+    let options = butane_attr_options(&field.attrs)
+        .into_iter()
+        .find_map(|option| match option {
+            NestedMeta::Meta(Meta::Path(path)) if path.is_ident("foreign_key") => {
+                Some(Punctuated::new())
+            }
+            NestedMeta::Meta(Meta::List(ml)) if ml.path.is_ident("foreign_key") => Some(ml.nested),
+            _ => None,
+        });
+    let options = match options {
+        None => return Ok(None),
+        Some(options) => options,
+    };
+    let mut fk = match get_deferred_sql_type(&field.ty) {
+        DeferredSqlType::Deferred(TypeKey::PK(tyname)) => AForeignKey::new(tyname),
+        _ => {
+            return Err(
+                make_compile_error!("foreign_key is only supported on ForeignKey fields").into(),
+            )
+        }
+    };
+    for option in options {
+        let (path, value) = match option {
+            NestedMeta::Meta(Meta::NameValue(MetaNameValue {
+                path,
+                lit: Lit::Str(value),
+                ..
+            })) => (path, value.value()),
+            _ => return Err(make_compile_error!("malformed foreign_key attribute").into()),
+        };
+        let action: ReferentialAction = value
+            .parse()
+            .map_err(|e: crate::Error| CompilerErrorMsg::from(make_compile_error!("{}", e)))?;
+        if path.is_ident("on_delete") {
+            fk = fk.with_on_delete(action);
+        } else if path.is_ident("on_update") {
+            fk = fk.with_on_update(action);
+        } else {
+            return Err(make_compile_error!("unknown foreign_key option").into());
+        }
+    }
+    Ok(Some(fk))
+}
+
+/// Parameters of the column type given as `#[butane(length = 255)]`
+/// or `#[butane(precision = 12, scale = 2)]`.
+fn get_type_params(field: &Field) -> std::result::Result<ATypeParams, CompilerErrorMsg> {
+    let mut params = ATypeParams::default();
+    for option in butane_attr_options(&field.attrs) {
+        let (param, lit) = match option {
+            NestedMeta::Meta(Meta::NameValue(MetaNameValue { path, lit, .. })) => {
+                match ["length", "precision", "scale"]
+                    .iter()
+                    .find(|name| path.is_ident(name))
+                {
+                    Some(name) => (*name, lit),
+                    None => continue,
+                }
+            }
+            _ => continue,
+        };
+        let value: u32 = match lit {
+            Lit::Int(i) => i.base10_parse().map_err(|_| {
+                CompilerErrorMsg::from(make_compile_error!(i.span()=> "{} is too large", param))
+            })?,
+            _ => return Err(make_compile_error!("{} must be an integer", param).into()),
+        };
+        match param {
+            "length" => params.length = Some(value),
+            "precision" => params.precision = Some(value),
+            // A scale of zero is the same as none
+            _ => params.scale = Some(value).filter(|s| *s != 0),
+        }
+    }
+    if params.length.is_some() && params.precision.is_some() {
+        return Err(
+            make_compile_error!(field.span()=> "length cannot be combined with precision").into(),
+        );
+    }
+    match (params.precision, params.scale) {
+        (None, Some(_)) => {
+            Err(make_compile_error!(field.span()=> "scale requires precision").into())
+        }
+        (Some(precision), Some(scale)) if scale > precision => Err(make_compile_error!(
+            field.span()=> "scale cannot exceed precision"
+        )
+        .into()),
+        _ => Ok(params),
+    }
+}
+
 fn some_id(ty: SqlType) -> Option<TypeIdentifier> {
     Some(TypeIdentifier::Ty(ty))
 }
@@ -430,6 +1139,8 @@ fn get_primitive_sql_type(ty: &syn::Type) -> Option<DeferredSqlType> {
         return some_known(SqlType::Text);
     } else if *ty == parse_quote!(Vec<u8>) {
         return some_known(SqlType::Blob);
+    } else if is_json(ty) {
+        return some_known(SqlType::Text);
     }
 
     #[cfg(feature = "datetime")]
@@ -449,6 +1160,19 @@ fn get_primitive_sql_type(ty: &syn::Type) -> Option<DeferredSqlType> {
     None
 }
 
+/// Whether `ty` is `Json`, with or without a type argument, stored as
+/// text whatever its argument.
+fn is_json(ty: &syn::Type) -> bool {
+    match ty {
+        syn::Type::Path(typath) => typath
+            .path
+            .segments
+            .last()
+            .is_some_and(|seg| seg.ident == "Json"),
+        _ => false,
+    }
+}
+
 fn has_derive_serialize(attrs: &[Attribute]) -> bool {
     for attr in attrs {
         if let Ok(Meta::List(ml)) = attr.parse_meta() {
@@ -498,9 +1222,8 @@ where
     current_migration.add_type(key, ty)
 }
 
-fn sqltype_from_name(name: &Ident) -> Option<TypeIdentifier> {
-    let name = name.to_string();
-    match name.as_ref() {
+fn sqltype_from_name(name: &str) -> Option<TypeIdentifier> {
+    match name {
         "Bool" => return some_id(SqlType::Bool),
         "Int" => return some_id(SqlType::Int),
         "BigInt" => return some_id(SqlType::BigInt),
@@ -511,7 +1234,7 @@ fn sqltype_from_name(name: &Ident) -> Option<TypeIdentifier> {
         "Blob" => return some_id(SqlType::Blob),
         _ => (),
     }
-    if let Some(custom_name) = Regex::new(r"^Custom\((.*)\)$").unwrap().captures(&name) {
+    if let Some(custom_name) = Regex::new(r"^Custom\((.*)\)$").unwrap().captures(name) {
         if let Some(pg_name) = Regex::new(r"^Pg\((.*)\)$")
             .unwrap()
             .captures(custom_name.get(1).unwrap().as_str())
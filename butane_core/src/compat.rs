@@ -0,0 +1,291 @@
+//! Tolerance of schema differences during rolling deploys.
+//!
+//! When migrations only add tables and columns, binaries built for
+//! neighbouring versions of the schema can run side by side, provided
+//! each tolerates the columns it does not know about. Queries name the
+//! columns they read, so columns added for newer binaries are never
+//! read by older ones. [Tolerant] additionally lets newer binaries run
+//! against the schema before the migration adding their columns has
+//! been applied: missing columns read as NULL, so their `Option`
+//! fields are `None`, and NULL values written to them are skipped.
+//!
+//! Writing other values to a missing column, or reading one into a
+//! field which is not an `Option`, still fails.
+use crate::db::{
+    BackendConnection, BackendRow, BackendRows, Column, ConnectionMethods, OwnedRow, QueryPlan,
+    RawQueryResult, Statement,
+};
+use crate::migrations::adb::ADB;
+use crate::query::{AggregateExpr, Assignment, BoolExpr, Having, Order};
+use crate::{Result, SqlType, SqlVal, SqlValRef};
+use std::collections::{HashMap, HashSet};
+
+/// Connection wrapper reading and writing through a database whose
+/// schema may lack columns of the models used with it. See the
+/// [module documentation](self).
+///
+/// Tables are matched without regard to case, as Postgres may fold
+/// identifiers to lower case. Tables not in the schema are passed
+/// through unchanged.
+pub struct Tolerant<'c, C> {
+    conn: &'c C,
+    /// The lower-cased names of the columns of each table, keyed by
+    /// the lower-cased table name.
+    columns: HashMap<String, HashSet<String>>,
+}
+
+impl<'c, C: BackendConnection> Tolerant<'c, C> {
+    /// Wrap `conn`, reading the schema of its database. Create a new
+    /// wrapper after the schema changes.
+    pub fn new(conn: &'c C) -> Result<Self> {
+        Ok(Self::with_schema(conn, &conn.introspect()?))
+    }
+}
+
+impl<'c, C: ConnectionMethods> Tolerant<'c, C> {
+    /// Wrap `conn`, whose database has the given schema. Useful with
+    /// transactions, which cannot read the schema themselves.
+    pub fn with_schema(conn: &'c C, schema: &ADB) -> Self {
+        let columns = schema
+            .tables()
+            .map(|table| {
+                (
+                    table.name.to_lowercase(),
+                    table
+                        .columns
+                        .iter()
+                        .map(|c| c.name().to_lowercase())
+                        .collect(),
+                )
+            })
+            .collect();
+        Tolerant { conn, columns }
+    }
+
+    /// Whether `table` is known to lack `column`.
+    fn lacks(&self, table: &str, column: &str) -> bool {
+        self.columns
+            .get(&table.to_lowercase())
+            .is_some_and(|cols| !cols.contains(&column.to_lowercase()))
+    }
+
+    /// `columns` and `values` without the NULL values of missing columns.
+    fn present<'v>(
+        &self,
+        table: &str,
+        columns: &[Column],
+        values: &[SqlValRef<'v>],
+    ) -> (Vec<Column>, Vec<SqlValRef<'v>>) {
+        columns
+            .iter()
+            .zip(values)
+            .filter(|(col, val)| !(matches!(val, SqlValRef::Null) && self.lacks(table, col.name())))
+            .map(|(col, val)| (col.clone(), val.clone()))
+            .unzip()
+    }
+
+    /// The rows read by `run` given those of `columns` which `table`
+    /// has, with NULL for the others.
+    fn filled<'a>(
+        &self,
+        table: &str,
+        columns: &[Column],
+        run: impl FnOnce(&[Column]) -> Result<RawQueryResult<'a>>,
+    ) -> Result<RawQueryResult<'a>> {
+        if !columns.iter().any(|c| self.lacks(table, c.name())) {
+            return run(columns);
+        }
+        let mut present: Vec<Column> = Vec::new();
+        let mut positions: Vec<(Option<usize>, SqlType)> = Vec::new();
+        for col in columns {
+            if self.lacks(table, col.name()) {
+                positions.push((None, col.ty().clone()));
+            } else {
+                positions.push((Some(present.len()), col.ty().clone()));
+                present.push(col.clone());
+            }
+        }
+        let rows = run(&present)?;
+        Ok(Box::new(FilledRows {
+            rows,
+            positions,
+            current: None,
+        }))
+    }
+}
+
+impl<'c, C: ConnectionMethods> ConnectionMethods for Tolerant<'c, C> {
+    fn execute(&self, sql: &str) -> Result<()> {
+        self.conn.execute(sql)
+    }
+    fn query<'a, 'b, 'd: 'a>(
+        &'d self,
+        table: &str,
+        columns: &'b [Column],
+        expr: Option<BoolExpr>,
+        limit: Option<i32>,
+        offset: Option<i32>,
+        sort: Option<&[Order]>,
+    ) -> Result<RawQueryResult<'a>> {
+        self.filled(table, columns, |columns| {
+            self.conn.query(table, columns, expr, limit, offset, sort)
+        })
+    }
+    fn query_stream<'a, 'b, 'd: 'a>(
+        &'d self,
+        table: &str,
+        columns: &'b [Column],
+        expr: Option<BoolExpr>,
+        limit: Option<i32>,
+        offset: Option<i32>,
+        sort: Option<&[Order]>,
+    ) -> Result<RawQueryResult<'a>> {
+        self.filled(table, columns, |columns| {
+            self.conn
+                .query_stream(table, columns, expr, limit, offset, sort)
+        })
+    }
+    #[allow(clippy::too_many_arguments)]
+    fn query_distinct<'a, 'b, 'd: 'a>(
+        &'d self,
+        table: &str,
+        columns: &'b [Column],
+        distinct: &crate::query::Distinct,
+        expr: Option<BoolExpr>,
+        limit: Option<i32>,
+        offset: Option<i32>,
+        sort: Option<&[Order]>,
+    ) -> Result<RawQueryResult<'a>> {
+        self.filled(table, columns, |columns| {
+            self.conn
+                .query_distinct(table, columns, distinct, expr, limit, offset, sort)
+        })
+    }
+    fn insert_returning_pk(
+        &self,
+        table: &str,
+        columns: &[Column],
+        pkcol: &Column,
+        values: &[SqlValRef<'_>],
+    ) -> Result<SqlVal> {
+        let (columns, values) = self.present(table, columns, values);
+        self.conn
+            .insert_returning_pk(table, &columns, pkcol, &values)
+    }
+    fn insert_only(&self, table: &str, columns: &[Column], values: &[SqlValRef<'_>]) -> Result<()> {
+        let (columns, values) = self.present(table, columns, values);
+        self.conn.insert_only(table, &columns, &values)
+    }
+    fn insert_or_replace(
+        &self,
+        table: &str,
+        columns: &[Column],
+        pkcol: &Column,
+        values: &[SqlValRef<'_>],
+    ) -> Result<()> {
+        let (columns, values) = self.present(table, columns, values);
+        self.conn.insert_or_replace(table, &columns, pkcol, &values)
+    }
+    fn update(
+        &self,
+        table: &str,
+        pkcol: Column,
+        pk: SqlValRef,
+        columns: &[Column],
+        values: &[SqlValRef<'_>],
+    ) -> Result<()> {
+        let (columns, values) = self.present(table, columns, values);
+        self.conn.update(table, pkcol, pk, &columns, &values)
+    }
+    fn delete_where(&self, table: &str, expr: BoolExpr) -> Result<usize> {
+        self.conn.delete_where(table, expr)
+    }
+    fn update_where(
+        &self,
+        table: &str,
+        assignments: &[Assignment],
+        expr: BoolExpr,
+    ) -> Result<usize> {
+        // Columns missing from the database are not set, as with update
+        let present: Vec<Assignment> = assignments
+            .iter()
+            .filter(|a| !self.lacks(table, a.column))
+            .cloned()
+            .collect();
+        if present.is_empty() {
+            return Ok(0);
+        }
+        self.conn.update_where(table, &present, expr)
+    }
+    fn has_table(&self, table: &str) -> Result<bool> {
+        self.conn.has_table(table)
+    }
+    fn execute_batch(&self, statements: &[Statement]) -> Result<()> {
+        self.conn.execute_batch(statements)
+    }
+    fn explain(
+        &self,
+        table: &str,
+        columns: &[Column],
+        expr: Option<BoolExpr>,
+        limit: Option<i32>,
+        offset: Option<i32>,
+        sort: Option<&[Order]>,
+        analyze: bool,
+    ) -> Result<QueryPlan> {
+        let present: Vec<Column> = columns
+            .iter()
+            .filter(|c| !self.lacks(table, c.name()))
+            .cloned()
+            .collect();
+        self.conn
+            .explain(table, &present, expr, limit, offset, sort, analyze)
+    }
+    fn aggregate(
+        &self,
+        table: &str,
+        groups: &[Column],
+        aggregates: &[AggregateExpr],
+        expr: Option<BoolExpr>,
+        having: Option<Having>,
+    ) -> Result<Vec<Vec<SqlVal>>> {
+        self.conn.aggregate(table, groups, aggregates, expr, having)
+    }
+    fn query_raw_rows<'a, 'd: 'a>(
+        &'d self,
+        sql: &str,
+        params: &[SqlVal],
+    ) -> Result<RawQueryResult<'a>> {
+        // Raw rows are not adapted to the schema, as with execute
+        self.conn.query_raw_rows(sql, params)
+    }
+}
+
+/// Rows with NULL in place of the missing columns.
+struct FilledRows<'a> {
+    rows: RawQueryResult<'a>,
+    /// For each requested column, its position in `rows` if present.
+    positions: Vec<(Option<usize>, SqlType)>,
+    current: Option<OwnedRow>,
+}
+
+impl BackendRows for FilledRows<'_> {
+    fn next(&mut self) -> Result<Option<&dyn BackendRow>> {
+        self.current = match self.rows.next()? {
+            None => None,
+            Some(row) => Some(OwnedRow(
+                self.positions
+                    .iter()
+                    .map(|(idx, ty)| match idx {
+                        Some(idx) => Ok(SqlVal::from(row.get(*idx, ty.clone())?)),
+                        None => Ok(SqlVal::Null),
+                    })
+                    .collect::<Result<Vec<SqlVal>>>()?,
+            )),
+        };
+        Ok(self.current.as_ref().map(|row| row as &dyn BackendRow))
+    }
+    fn current(&self) -> Option<&dyn BackendRow> {
+        self.current.as_ref().map(|row| row as &dyn BackendRow)
+    }
+}
@@ -0,0 +1,124 @@
+//! A blocking facade over the [asynchronous
+//! connections](super::nonblocking), with the `async` feature, in the
+//! style of `reqwest::blocking`. A [BlockingConnection] implements
+//! [ConnectionMethods] by running the methods of an [AsyncConnection]
+//! to completion, so synchronous code such as
+//! [DataObject::save][crate::DataObject::save] runs on the same
+//! implementation as asynchronous code.
+//!
+//! Each connection has a single-threaded tokio runtime of its own, so
+//! its methods must not be called from within an asynchronous context,
+//! where they would block the runtime it is run by; tokio panics if
+//! they are.
+//!
+//! ```ignore
+//! let conn = butane::db::blocking::BlockingConnection::connect(&spec)?;
+//! let mut post = Post::get(&conn, 1)?;
+//! post.published = true;
+//! post.save(&conn)?;
+//! ```
+use super::connmethods::VecRows;
+use super::nonblocking::{connect_async, AsyncConnection};
+use super::*;
+use crate::query::{Assignment, BoolExpr, Order};
+use crate::{Result, SqlVal, SqlValRef};
+use std::future::Future;
+
+/// A database connection whose methods block on those of an
+/// [AsyncConnection]. Transactions are not available, as asynchronous
+/// connections do not have them yet.
+pub struct BlockingConnection {
+    conn: Box<dyn AsyncConnection>,
+    runtime: tokio::runtime::Runtime,
+}
+impl BlockingConnection {
+    /// Connect to the database of `spec` with [connect_async], on a
+    /// runtime of the connection's own.
+    pub fn connect(spec: &ConnectionSpec) -> Result<Self> {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+        let conn = runtime.block_on(connect_async(spec))?;
+        Ok(BlockingConnection { conn, runtime })
+    }
+    pub fn backend_name(&self) -> &'static str {
+        self.conn.backend_name()
+    }
+    /// See [AsyncConnection::is_closed].
+    pub fn is_closed(&self) -> bool {
+        self.conn.is_closed()
+    }
+    /// See [AsyncConnection::ping].
+    pub fn ping(&self) -> Result<()> {
+        self.block_on(self.conn.ping())
+    }
+    fn block_on<T>(&self, future: impl Future<Output = Result<T>>) -> Result<T> {
+        self.runtime.block_on(future)
+    }
+}
+
+impl ConnectionMethods for BlockingConnection {
+    fn execute(&self, sql: &str) -> Result<()> {
+        self.block_on(self.conn.execute(sql))
+    }
+    fn query<'a, 'b, 'c: 'a>(
+        &'c self,
+        table: &str,
+        columns: &'b [Column],
+        expr: Option<BoolExpr>,
+        limit: Option<i32>,
+        offset: Option<i32>,
+        sort: Option<&[Order]>,
+    ) -> Result<RawQueryResult<'a>> {
+        let rows = self.block_on(self.conn.query(table, columns, expr, limit, offset, sort))?;
+        Ok(Box::new(VecRows::new(rows)))
+    }
+    fn insert_returning_pk(
+        &self,
+        table: &str,
+        columns: &[Column],
+        pkcol: &Column,
+        values: &[SqlValRef<'_>],
+    ) -> Result<SqlVal> {
+        self.block_on(self.conn.insert_returning_pk(table, columns, pkcol, values))
+    }
+    fn insert_only(&self, table: &str, columns: &[Column], values: &[SqlValRef<'_>]) -> Result<()> {
+        self.block_on(self.conn.insert_only(table, columns, values))
+    }
+    fn insert_or_replace(
+        &self,
+        table: &str,
+        columns: &[Column],
+        pkcol: &Column,
+        values: &[SqlValRef<'_>],
+    ) -> Result<()> {
+        self.block_on(self.conn.insert_or_replace(table, columns, pkcol, values))
+    }
+    fn update(
+        &self,
+        table: &str,
+        pkcol: Column,
+        pk: SqlValRef,
+        columns: &[Column],
+        values: &[SqlValRef<'_>],
+    ) -> Result<()> {
+        self.block_on(self.conn.update(table, pkcol, pk, columns, values))
+    }
+    fn delete_where(&self, table: &str, expr: BoolExpr) -> Result<usize> {
+        self.block_on(self.conn.delete_where(table, expr))
+    }
+    fn update_where(
+        &self,
+        table: &str,
+        assignments: &[Assignment],
+        expr: BoolExpr,
+    ) -> Result<usize> {
+        self.block_on(self.conn.update_where(table, assignments, expr))
+    }
+    fn has_table(&self, table: &str) -> Result<bool> {
+        self.block_on(self.conn.has_table(table))
+    }
+    fn execute_batch(&self, statements: &[Statement]) -> Result<()> {
+        self.block_on(self.conn.execute_batch(statements))
+    }
+}
@@ -0,0 +1,126 @@
+//! Caching of prepared statements within a connection.
+
+#[cfg(any(feature = "sqlite", feature = "pg"))]
+use std::collections::VecDeque;
+#[cfg(any(feature = "sqlite", feature = "pg"))]
+use std::sync::Mutex;
+
+/// The number of prepared statements a connection keeps unless
+/// configured otherwise, e.g. with
+/// [ConnectionSpec::with_statement_cache_capacity][super::ConnectionSpec::with_statement_cache_capacity].
+pub const DEFAULT_STATEMENT_CACHE_CAPACITY: usize = 16;
+
+/// Statistics of the cache of prepared statements of a connection.
+/// See [BackendConnection::statement_cache_stats][super::BackendConnection::statement_cache_stats].
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct StatementCacheStats {
+    /// Statements found in the cache.
+    pub hits: u64,
+    /// Statements prepared as they were not in the cache.
+    pub misses: u64,
+    /// The number of statements in the cache.
+    pub len: usize,
+    /// The most statements the cache keeps.
+    pub capacity: usize,
+}
+
+/// Prepared statements keyed by their SQL, evicting the least
+/// recently used beyond its capacity. A capacity of zero disables
+/// caching. Shared between a connection and its transactions.
+#[cfg(any(feature = "sqlite", feature = "pg"))]
+pub struct StatementCache<S> {
+    inner: Mutex<CacheInner<S>>,
+}
+
+#[cfg(any(feature = "sqlite", feature = "pg"))]
+struct CacheInner<S> {
+    /// Most recently used last.
+    entries: VecDeque<(String, S)>,
+    capacity: usize,
+    hits: u64,
+    misses: u64,
+}
+
+#[cfg(any(feature = "sqlite", feature = "pg"))]
+impl<S: Clone> StatementCache<S> {
+    pub fn new(capacity: usize) -> Self {
+        StatementCache {
+            inner: Mutex::new(CacheInner {
+                entries: VecDeque::new(),
+                capacity,
+                hits: 0,
+                misses: 0,
+            }),
+        }
+    }
+
+    /// The statement cached for `sql`, counting a hit or a miss.
+    pub fn get(&self, sql: &str) -> Option<S> {
+        let mut inner = self.lock();
+        match inner.entries.iter().position(|(key, _)| key == sql) {
+            Some(i) => {
+                inner.hits += 1;
+                let entry = inner.entries.remove(i).unwrap();
+                let stmt = entry.1.clone();
+                inner.entries.push_back(entry);
+                Some(stmt)
+            }
+            None => {
+                inner.misses += 1;
+                None
+            }
+        }
+    }
+
+    /// Cache `stmt`, just prepared for `sql`.
+    pub fn insert(&self, sql: &str, stmt: S) {
+        let mut inner = self.lock();
+        if inner.capacity == 0 {
+            return;
+        }
+        inner.entries.retain(|(key, _)| key != sql);
+        if inner.entries.len() >= inner.capacity {
+            inner.entries.pop_front();
+        }
+        inner.entries.push_back((sql.to_string(), stmt));
+    }
+
+    /// The statement cached for `sql`, or that returned by `prepare`,
+    /// which is then cached.
+    pub fn get_or_prepare<E>(
+        &self,
+        sql: &str,
+        prepare: impl FnOnce() -> Result<S, E>,
+    ) -> Result<S, E> {
+        if let Some(stmt) = self.get(sql) {
+            return Ok(stmt);
+        }
+        let stmt = prepare()?;
+        self.insert(sql, stmt.clone());
+        Ok(stmt)
+    }
+
+    /// Forget all statements, e.g. as the schema they were prepared
+    /// against may have changed.
+    #[cfg(feature = "pg")]
+    pub fn clear(&self) {
+        self.lock().entries.clear();
+    }
+
+    pub fn stats(&self) -> StatementCacheStats {
+        let inner = self.lock();
+        StatementCacheStats {
+            hits: inner.hits,
+            misses: inner.misses,
+            len: inner.entries.len(),
+            capacity: inner.capacity,
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, CacheInner<S>> {
+        // The cache is left consistent by a panic, so poisoning is ignored
+        self.inner
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+}
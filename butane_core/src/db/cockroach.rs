@@ -0,0 +1,142 @@
+//! CockroachDB database backend
+//!
+//! CockroachDB speaks the Postgres protocol, so its connections are
+//! those of the [pg][super::pg] backend, made with a [PgBackend] using
+//! [Dialect::Cockroach]. Its migrations differ from those of Postgres:
+//!
+//! * Auto columns are identity columns, as CockroachDB's `SERIAL`
+//!   columns take random, 64-bit values from `unique_rowid()`.
+//! * Schema changes cannot be mixed freely with other statements in a
+//!   transaction, so migrations are applied one statement at a time.
+//!   A migration which fails part way through is not rolled back. Use
+//!   [create_idempotent_migration_sql][Backend::create_idempotent_migration_sql]
+//!   to write migrations which can be re-run after such a failure.
+//! * Columns change type with CockroachDB's general `ALTER COLUMN TYPE`,
+//!   which must be enabled for the session.
+//! * Backfills for `SetNotNull` are a single `UPDATE`, as CockroachDB
+//!   has no `ctid` to batch them by.
+use super::helper;
+use super::pg::{self, Dialect, PgBackend};
+use super::*;
+use crate::migrations::adb::{AColumn, AEnum, ATable, AutoIncrement, Operation, ADB};
+use crate::{Result, SqlVal};
+
+/// The name of the cockroach backend.
+pub const BACKEND_NAME: &str = "cockroach";
+
+/// CockroachDB [Backend][crate::db::Backend] implementation: a
+/// [PgBackend] generating CockroachDB's dialect of SQL.
+pub fn backend() -> PgBackend {
+    PgBackend::new().with_dialect(Dialect::Cockroach)
+}
+
+pub(super) fn migration_sql(
+    current: &ADB,
+    ops: Vec<Operation>,
+    idempotent: bool,
+    capabilities: Capabilities,
+    q: helper::QuoteIdent,
+) -> Result<String> {
+    let mut current = as_declared(current);
+    Ok(ops
+        .into_iter()
+        .map(|o| {
+            let o = op_as_declared(o);
+            let sql = sql_for_op(&mut current, &o, idempotent, capabilities, q);
+            current.transform_with(o);
+            sql
+        })
+        .collect::<Result<Vec<String>>>()?
+        .join("\n"))
+}
+
+fn sql_for_op(
+    current: &mut ADB,
+    op: &Operation,
+    idempotent: bool,
+    capabilities: Capabilities,
+    q: helper::QuoteIdent,
+) -> Result<String> {
+    match op {
+        Operation::AddEnum(e) if idempotent => create_enum_if_not_exists(e, q),
+        Operation::ChangeColumn(_, old, new)
+            if pg::col_sqltype(old, q)? != pg::col_sqltype(new, q)? =>
+        {
+            Ok(format!(
+                "SET enable_experimental_alter_column_type_general = true;\n{}",
+                pg::sql_for_op(current, op, idempotent, capabilities, q)?
+            ))
+        }
+        Operation::SetNotNull(tbl, name, Some(backfill)) => {
+            let tbl = pg::table_ident(current, tbl, q);
+            Ok(format!(
+                "UPDATE {tbl} SET {col} = ({expr}) WHERE {col} IS NULL;\n\
+                 ALTER TABLE {tbl} ALTER COLUMN {col} SET NOT NULL;",
+                tbl = tbl,
+                col = q(name),
+                expr = backfill.expr
+            ))
+        }
+        // Tables without a schema are in the first schema of the search
+        // path, by default public
+        Operation::SetTableSchema(tbl, None) => Ok(format!(
+            "ALTER TABLE {}{} SET SCHEMA public;",
+            if idempotent { "IF EXISTS " } else { "" },
+            pg::table_ident(current, tbl, q)
+        )),
+        _ => pg::sql_for_op(current, op, idempotent, capabilities, q),
+    }
+}
+
+fn create_enum_if_not_exists(e: &AEnum, q: helper::QuoteIdent) -> Result<String> {
+    let values = e
+        .values
+        .iter()
+        .map(|v| helper::sql_literal_value(SqlVal::Text(v.clone())))
+        .collect::<Result<Vec<String>>>()?
+        .join(", ");
+    Ok(format!(
+        "CREATE TYPE IF NOT EXISTS {} AS ENUM ({});",
+        q(&e.name),
+        values
+    ))
+}
+
+/// `db` as its migrations declare it to CockroachDB, with identity
+/// columns in place of serial ones.
+pub(super) fn as_declared(db: &ADB) -> ADB {
+    let mut db = db.clone();
+    let tables: Vec<ATable> = db.tables().map(table_as_declared).collect();
+    for table in tables {
+        db.replace_table(table);
+    }
+    db
+}
+
+fn op_as_declared(op: Operation) -> Operation {
+    match op {
+        Operation::AddTable(table) => Operation::AddTable(table_as_declared(&table)),
+        Operation::AddTableIfNotExists(table) => {
+            Operation::AddTableIfNotExists(table_as_declared(&table))
+        }
+        Operation::AddColumn(tbl, col) => Operation::AddColumn(tbl, column_as_declared(col)),
+        Operation::ChangeColumn(tbl, old, new) => {
+            Operation::ChangeColumn(tbl, column_as_declared(old), column_as_declared(new))
+        }
+        op => op,
+    }
+}
+
+fn table_as_declared(table: &ATable) -> ATable {
+    let mut table = table.clone();
+    table.columns = table.columns.into_iter().map(column_as_declared).collect();
+    table
+}
+
+fn column_as_declared(col: AColumn) -> AColumn {
+    if col.auto_increment() == Some(AutoIncrement::Serial) {
+        col.with_auto_increment(AutoIncrement::Identity)
+    } else {
+        col
+    }
+}
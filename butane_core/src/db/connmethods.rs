@@ -1,8 +1,12 @@
 //! Not expected to be called directly by most users. Used by code
 //! generated by `#[model]`, `query!`, and other macros.
 
-use crate::query::{BoolExpr, Expr, Order};
-use crate::{Result, SqlType, SqlVal, SqlValRef};
+use crate::query::{
+    AggregateExpr, Assignment, BoolExpr, DateFunction, Distinct, Expr, Having, Order,
+};
+use crate::sqlval::FieldType;
+use crate::{DataResult, Error, Result, SqlType, SqlVal, SqlValRef};
+use serde::{Deserialize, Serialize};
 use std::ops::{Deref, DerefMut};
 use std::vec::Vec;
 
@@ -21,6 +25,37 @@ pub trait ConnectionMethods {
         offset: Option<i32>,
         sort: Option<&[Order]>,
     ) -> Result<RawQueryResult<'a>>;
+    /// Like [query](Self::query), but the rows are read from the
+    /// database in batches as they are iterated over, rather than all
+    /// at once. Postgres reads them through a cursor. SQLite's `query`
+    /// already reads them one at a time, and backends which cannot do
+    /// either return the rows of `query`.
+    fn query_stream<'a, 'b, 'c: 'a>(
+        &'c self,
+        table: &str,
+        columns: &'b [Column],
+        expr: Option<BoolExpr>,
+        limit: Option<i32>,
+        offset: Option<i32>,
+        sort: Option<&[Order]>,
+    ) -> Result<RawQueryResult<'a>> {
+        self.query(table, columns, expr, limit, offset, sort)
+    }
+    /// Like [query](Self::query), leaving out the rows which are
+    /// duplicates as given by `distinct`.
+    #[allow(clippy::too_many_arguments)]
+    fn query_distinct<'a, 'b, 'c: 'a>(
+        &'c self,
+        _table: &str,
+        _columns: &'b [Column],
+        _distinct: &Distinct,
+        _expr: Option<BoolExpr>,
+        _limit: Option<i32>,
+        _offset: Option<i32>,
+        _sort: Option<&[Order]>,
+    ) -> Result<RawQueryResult<'a>> {
+        Err(Error::DistinctUnsupported)
+    }
     fn insert_returning_pk(
         &self,
         table: &str,
@@ -51,19 +86,213 @@ pub trait ConnectionMethods {
         Ok(())
     }
     fn delete_where(&self, table: &str, expr: BoolExpr) -> Result<usize>;
+    /// Set the columns of `assignments` in all rows matching `expr`,
+    /// returning the number of rows updated.
+    fn update_where(
+        &self,
+        table: &str,
+        assignments: &[Assignment],
+        expr: BoolExpr,
+    ) -> Result<usize>;
     /// Tests if a table exists in the database.
     fn has_table(&self, table: &str) -> Result<bool>;
+    /// Run `statements` together, all or none of them taking effect.
+    /// SQLite runs them in one transaction, and Postgres in one
+    /// transaction with each statement prepared once. Within a
+    /// transaction, a savepoint is used instead. The statements are
+    /// still sent one at a time, each once the previous has completed:
+    /// only the asynchronous Postgres connection pipelines them.
+    fn execute_batch(&self, statements: &[Statement]) -> Result<()>;
+    /// Like `insert_only` for each of `rows`, which backends insert
+    /// with [execute_batch](Self::execute_batch).
+    fn insert_only_many(
+        &self,
+        table: &str,
+        columns: &[Column],
+        rows: &[Vec<SqlValRef<'_>>],
+    ) -> Result<()> {
+        for values in rows {
+            self.insert_only(table, columns, values)?;
+        }
+        Ok(())
+    }
+    /// The backend's plan for the query [query](Self::query) would run
+    /// with the same arguments. With `analyze`, backends which can
+    /// (Postgres) run the query and include what they measured.
+    #[allow(clippy::too_many_arguments)]
+    fn explain(
+        &self,
+        _table: &str,
+        _columns: &[Column],
+        _expr: Option<BoolExpr>,
+        _limit: Option<i32>,
+        _offset: Option<i32>,
+        _sort: Option<&[Order]>,
+        _analyze: bool,
+    ) -> Result<QueryPlan> {
+        Err(Error::ExplainUnsupported)
+    }
+    /// The values of `aggregates` over the rows of `table` for which
+    /// `expr` is true, each read as its type. With `groups`, the rows
+    /// are grouped by the values of those columns, and a row is
+    /// returned for each group whose aggregates satisfy `having`,
+    /// with the group's values before those of the aggregates, ordered
+    /// by the group's values. Otherwise a single row is returned.
+    fn aggregate(
+        &self,
+        _table: &str,
+        _groups: &[Column],
+        _aggregates: &[AggregateExpr],
+        _expr: Option<BoolExpr>,
+        _having: Option<Having>,
+    ) -> Result<Vec<Vec<SqlVal>>> {
+        Err(Error::AggregateUnsupported)
+    }
+    /// The rows returned by the query `sql`, in which each `?` is a
+    /// placeholder for the next of `params` and `??` is a literal `?`.
+    /// See [query_raw](Self::query_raw) to read them.
+    fn query_raw_rows<'a, 'c: 'a>(
+        &'c self,
+        _sql: &str,
+        _params: &[SqlVal],
+    ) -> Result<RawQueryResult<'a>> {
+        Err(Error::RawQueryUnsupported)
+    }
+    /// Run the query `sql`, for queries which cannot otherwise be
+    /// expressed, with placeholders as for
+    /// [query_raw_rows](Self::query_raw_rows), reading each row as a
+    /// model, or other [DataResult], or a tuple of field values.
+    /// The columns must be selected in the order of the fields.
+    ///
+    /// ```ignore
+    /// let counts: Vec<(String, i64)> = conn.query_raw(
+    ///     "SELECT name, COUNT(*) FROM Blog JOIN Post ON Post.blog = Blog.id GROUP BY name",
+    ///     &[],
+    /// )?;
+    /// ```
+    fn query_raw<T: RawResult>(&self, sql: &str, params: &[SqlVal]) -> Result<Vec<T>>
+    where
+        Self: Sized,
+    {
+        let mut rows = self.query_raw_rows(sql, params)?;
+        let mut results = Vec::new();
+        while let Some(row) = rows.next()? {
+            results.push(T::from_raw_row(row)?);
+        }
+        Ok(results)
+    }
+}
+
+/// A type each row of [ConnectionMethods::query_raw] can be read as:
+/// a [DataResult], or a tuple of up to four field types.
+pub trait RawResult: Sized {
+    fn from_raw_row(row: &dyn BackendRow) -> Result<Self>;
+}
+
+impl<T: DataResult> RawResult for T {
+    fn from_raw_row(row: &dyn BackendRow) -> Result<Self> {
+        T::from_row(row)
+    }
+}
+
+macro_rules! impl_raw_result_tuple {
+    ($($t:ident $idx:tt),+) => {
+        impl<$($t: FieldType),+> RawResult for ($($t,)+) {
+            fn from_raw_row(row: &dyn BackendRow) -> Result<Self> {
+                if row.len() < [$($idx),+].len() {
+                    return Err(Error::BoundsError(
+                        "raw query returned too few columns".to_string(),
+                    ));
+                }
+                Ok(($($t::from_sql_ref(row.get($idx, $t::SQLTYPE)?)?,)+))
+            }
+        }
+    };
+}
+
+impl_raw_result_tuple!(A 0);
+impl_raw_result_tuple!(A 0, B 1);
+impl_raw_result_tuple!(A 0, B 1, C 2);
+impl_raw_result_tuple!(A 0, B 1, C 2, D 3);
+
+/// A backend's plan for running a query, as given by
+/// [ConnectionMethods::explain].
+#[derive(Clone, Debug, PartialEq)]
+pub struct QueryPlan {
+    /// The SQL of the query which was explained.
+    pub sql: String,
+    /// The outermost steps of the plan.
+    pub nodes: Vec<PlanNode>,
+}
+impl QueryPlan {
+    /// Iterate over every step of the plan, each before its children.
+    pub fn iter(&self) -> impl Iterator<Item = &PlanNode> {
+        let mut stack: Vec<&PlanNode> = self.nodes.iter().rev().collect();
+        std::iter::from_fn(move || {
+            let node = stack.pop()?;
+            stack.extend(node.children.iter().rev());
+            Some(node)
+        })
+    }
+}
+
+/// A step of a [QueryPlan].
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PlanNode {
+    /// Description of the step, e.g. `SCAN foo` on SQLite or
+    /// `Seq Scan on foo` on Postgres.
+    pub detail: String,
+    /// Any further properties the backend gives for the step, such as
+    /// the estimated cost and rows on Postgres, by the backend's names
+    /// for them.
+    pub properties: serde_json::Map<String, serde_json::Value>,
+    /// The steps whose results this one uses.
+    pub children: Vec<PlanNode>,
+}
+
+/// A statement with the values of its placeholders, for
+/// [ConnectionMethods::execute_batch]. Placeholders are written as the
+/// backend expects: `?` for SQLite and MySQL, and `$1`, `$2` and so on
+/// for Postgres.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Statement {
+    pub sql: String,
+    pub values: Vec<SqlVal>,
+}
+impl Statement {
+    pub fn new(sql: impl Into<String>, values: Vec<SqlVal>) -> Self {
+        Statement {
+            sql: sql.into(),
+            values,
+        }
+    }
 }
 
 /// Represents a database column. Most users do not need to use this
 /// directly.
+#[derive(Clone, Debug)]
 pub struct Column {
     name: &'static str,
     ty: SqlType,
+    date_function: Option<DateFunction>,
 }
 impl Column {
     pub const fn new(name: &'static str, ty: SqlType) -> Self {
-        Column { name, ty }
+        Column {
+            name,
+            ty,
+            date_function: None,
+        }
+    }
+    /// The value of `function` of this timestamp column, rather than
+    /// the column itself, as for grouping by month. Its type is that
+    /// of the function's value.
+    pub fn with_date_function(mut self, function: DateFunction) -> Self {
+        self.date_function = Some(function);
+        self
+    }
+    pub fn date_function(&self) -> Option<DateFunction> {
+        self.date_function
     }
     pub fn name(&self) -> &'static str {
         self.name
@@ -84,6 +313,62 @@ pub trait BackendRow {
     }
 }
 
+/// A row whose values are owned rather than borrowed from the query
+/// which returned it, so that it may be kept after the query, e.g. in
+/// an external cache, or sent to another process. It serializes with
+/// serde as a sequence of [SqlVal], whose representation is stable:
+/// each value is tagged with the name of its variant. With the `cbor`
+/// feature, [to_bytes](Self::to_bytes) gives a compact binary form.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct OwnedRow(pub Vec<SqlVal>);
+impl OwnedRow {
+    /// Copy the values of `row`, which has the given columns.
+    pub fn from_row(row: &dyn BackendRow, columns: &[Column]) -> Result<Self> {
+        columns
+            .iter()
+            .enumerate()
+            .map(|(idx, col)| Ok(SqlVal::from(row.get(idx, col.ty().clone())?)))
+            .collect::<Result<Vec<SqlVal>>>()
+            .map(OwnedRow)
+    }
+    /// Copy every row of `rows`, which have the given columns.
+    pub fn from_rows(mut rows: RawQueryResult, columns: &[Column]) -> Result<Vec<Self>> {
+        let mut owned = Vec::new();
+        while let Some(row) = rows.next()? {
+            owned.push(OwnedRow::from_row(row, columns)?);
+        }
+        Ok(owned)
+    }
+    /// Query results made of `rows`, for loading objects from rows
+    /// kept with [from_rows](Self::from_rows), e.g. with
+    /// [DataResult::from_row][crate::DataResult::from_row].
+    pub fn into_rows(rows: Vec<Self>) -> RawQueryResult<'static> {
+        Box::new(VecRows::new(rows))
+    }
+    /// Serialize to a compact binary (CBOR) form.
+    #[cfg(feature = "cbor")]
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        crate::cbor::to_bytes(self)
+    }
+    /// Deserialize the binary form produced by [to_bytes](Self::to_bytes).
+    #[cfg(feature = "cbor")]
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        crate::cbor::from_bytes(bytes)
+    }
+}
+impl BackendRow for OwnedRow {
+    fn get(&self, idx: usize, _ty: SqlType) -> Result<SqlValRef<'_>> {
+        self.0
+            .get(idx)
+            .map(SqlVal::as_ref)
+            .ok_or_else(|| Error::BoundsError(format!("Row has no column {}", idx)))
+    }
+    fn len(&self) -> usize {
+        self.0.len()
+    }
+}
+
 /// Abstraction of rows returned from a query. Most users do not need
 /// to deal with this directly and should use the `query!` macro or
 /// [Query](crate::query::Query) type.
@@ -139,7 +424,6 @@ pub(crate) struct VecRows<T> {
     idx: usize,
 }
 impl<T> VecRows<T> {
-    #[allow(unused)] // Not used with all feature combinations
     pub fn new(rows: Vec<T>) -> Self {
         VecRows { rows, idx: 0 }
     }
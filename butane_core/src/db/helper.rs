@@ -3,12 +3,18 @@
 // may occur if no backends are selected
 #![allow(unused)]
 
-use super::Column;
-use crate::migrations::adb::{AColumn, TypeIdentifier};
+use super::{Column, Statement};
+use crate::migrations::adb::{
+    AColumn, AForeignKey, AIndex, ATable, AUniqueConstraint, DeferredSqlType, ReferentialAction,
+    TypeIdentifier, ADB,
+};
 use crate::query::Expr::{Condition, Placeholder, Val};
-use crate::query::{BoolExpr::*, Expr, Join, Order, OrderDirection};
+use crate::query::{
+    AggregateExpr, Assignment, BoolExpr::*, DateExpr, DateFunction, Distinct, Expr, Having, Join,
+    NullsOrder, Order, OrderDirection,
+};
 use crate::Error;
-use crate::{query, Result, SqlType, SqlVal};
+use crate::{query, Result, SqlType, SqlVal, SqlValRef};
 use std::borrow::Cow;
 use std::fmt::Write;
 
@@ -19,16 +25,215 @@ pub trait PlaceholderSource {
     fn next_placeholder(&mut self) -> Cow<str>;
 }
 
+/// Renders an identifier (a table, column or constraint name) for
+/// inclusion in SQL, e.g. by quoting it.
+pub type QuoteIdent = for<'a> fn(&'a str) -> Cow<'a, str>;
+
+/// A [QuoteIdent] which writes identifiers as they are.
+pub fn bare_ident(name: &str) -> Cow<'_, str> {
+    Cow::Borrowed(name)
+}
+
+/// Writes `cond` as an operand of `AND`, or of `NOT` if `negated`, in
+/// parentheses if it would otherwise bind less tightly than them.
+fn sql_operand<F, P, W>(
+    cond: Box<query::BoolExpr>,
+    negated: bool,
+    f: &F,
+    values: &mut Vec<SqlVal>,
+    pls: &mut P,
+    w: &mut W,
+) where
+    F: Fn(Expr, &mut Vec<SqlVal>, &mut P, &mut W),
+    P: PlaceholderSource,
+    W: Write,
+{
+    // Raw SQL may itself contain any operator
+    let loose = match *cond {
+        Or(..) | Raw { .. } => true,
+        And(..) | AllOf(..) => negated,
+        _ => false,
+    };
+    if loose {
+        w.write_str("(").unwrap();
+    }
+    f(Condition(cond), values, pls, w);
+    if loose {
+        w.write_str(")").unwrap();
+    }
+}
+
+/// A path within JSON as an argument of the SQLite JSON functions:
+/// `rest` is relative to `base`, the SQL for a path, or to the root.
+struct JsonPathSql {
+    base: Option<String>,
+    rest: String,
+}
+impl JsonPathSql {
+    fn new(path: &[query::JsonKey]) -> Self {
+        JsonPathSql {
+            base: None,
+            rest: query::json_path(path)[1..].to_string(),
+        }
+    }
+    fn key(&self, key: query::JsonKey) -> Self {
+        JsonPathSql {
+            base: self.base.clone(),
+            rest: format!("{}{}", self.rest, &query::json_path(&[key])[1..]),
+        }
+    }
+    fn write<F, P, W>(&self, f: &F, values: &mut Vec<SqlVal>, pls: &mut P, w: &mut W)
+    where
+        F: Fn(Expr, &mut Vec<SqlVal>, &mut P, &mut W),
+        P: PlaceholderSource,
+        W: Write,
+    {
+        match &self.base {
+            None => f(Val(SqlVal::Text(format!("${}", self.rest))), values, pls, w),
+            Some(base) if self.rest.is_empty() => w.write_str(base).unwrap(),
+            Some(base) => {
+                write!(w, "{} || ", base).unwrap();
+                f(Val(SqlVal::Text(self.rest.clone())), values, pls, w);
+            }
+        }
+    }
+}
+
+/// Writes the condition that the JSON in `src` at `path` is equal to
+/// `value`, comparing its type as SQLite does not.
+fn sql_json_eq<F, P, W>(
+    src: &str,
+    path: &JsonPathSql,
+    value: serde_json::Value,
+    f: &F,
+    values: &mut Vec<SqlVal>,
+    pls: &mut P,
+    w: &mut W,
+) where
+    F: Fn(Expr, &mut Vec<SqlVal>, &mut P, &mut W),
+    P: PlaceholderSource,
+    W: Write,
+{
+    use serde_json::Value;
+    let mut json_fn = |name: &str, values: &mut Vec<SqlVal>, pls: &mut P, w: &mut W| {
+        write!(w, "{}({}, ", name, src).unwrap();
+        path.write(f, values, pls, w);
+        w.write_str(")").unwrap();
+    };
+    let (ty, val) = match value {
+        Value::Null => ("'null'", None),
+        Value::Bool(true) => ("'true'", None),
+        Value::Bool(false) => ("'false'", None),
+        Value::Number(n) => match n.as_i64() {
+            Some(i) => ("'integer'", Some(SqlVal::BigInt(i))),
+            None => ("'real'", n.as_f64().map(SqlVal::Real)),
+        },
+        Value::String(s) => ("'text'", Some(SqlVal::Text(s))),
+        // Objects and arrays are compared as minified text
+        value @ (Value::Array(_) | Value::Object(_)) => {
+            json_fn("json_extract", values, pls, w);
+            w.write_str(" = json(").unwrap();
+            f(Val(SqlVal::Text(value.to_string())), values, pls, w);
+            w.write_str(")").unwrap();
+            return;
+        }
+    };
+    let Some(val) = val else {
+        json_fn("json_type", values, pls, w);
+        write!(w, " = {}", ty).unwrap();
+        return;
+    };
+    w.write_str("(").unwrap();
+    json_fn("json_type", values, pls, w);
+    write!(w, " = {} AND ", ty).unwrap();
+    json_fn("json_extract", values, pls, w);
+    w.write_str(" = ").unwrap();
+    f(Val(val), values, pls, w);
+    w.write_str(")").unwrap();
+}
+
+/// Writes the condition that the JSON in `src` at `path` contains
+/// `value`, as for [JsonContains], matching the elements of arrays in
+/// subqueries nested `depth` deep.
+#[allow(clippy::too_many_arguments)]
+fn sql_json_contains<F, P, W>(
+    src: &str,
+    path: &JsonPathSql,
+    value: serde_json::Value,
+    depth: usize,
+    f: &F,
+    values: &mut Vec<SqlVal>,
+    pls: &mut P,
+    w: &mut W,
+) where
+    F: Fn(Expr, &mut Vec<SqlVal>, &mut P, &mut W),
+    P: PlaceholderSource,
+    W: Write,
+{
+    use serde_json::Value;
+    let ty = match &value {
+        Value::Object(_) => "object",
+        Value::Array(_) => "array",
+        _ => return sql_json_eq(src, path, value, f, values, pls, w),
+    };
+    write!(w, "(json_type({}, ", src).unwrap();
+    path.write(f, values, pls, w);
+    write!(w, ") = '{}'", ty).unwrap();
+    match value {
+        Value::Object(fields) => {
+            for (name, value) in fields {
+                w.write_str(" AND ").unwrap();
+                let path = path.key(query::JsonKey::Field(name));
+                sql_json_contains(src, &path, value, depth, f, values, pls, w);
+            }
+        }
+        Value::Array(elements) => {
+            // The JSON of each element is that searched by json_each,
+            // at its full path
+            let alias = format!("butane_json{}", depth);
+            let inner_src = format!("{}.json", alias);
+            let inner_path = JsonPathSql {
+                base: Some(format!("{}.fullkey", alias)),
+                rest: String::new(),
+            };
+            for element in elements {
+                write!(w, " AND EXISTS (SELECT 1 FROM json_each({}, ", src).unwrap();
+                path.write(f, values, pls, w);
+                write!(w, ") AS {} WHERE ", alias).unwrap();
+                sql_json_contains(
+                    &inner_src,
+                    &inner_path,
+                    element,
+                    depth + 1,
+                    f,
+                    values,
+                    pls,
+                    w,
+                );
+                w.write_str(")").unwrap();
+            }
+        }
+        _ => unreachable!(),
+    }
+    w.write_str(")").unwrap();
+}
+
 /// Writes to `w` the SQL to express the expression given in `expr`. Values contained in `expr` are rendered
 /// as placeholders in the SQL string and the actual values are added to `values`.
-pub fn sql_for_expr<F, P, W>(expr: Expr, f: F, values: &mut Vec<SqlVal>, pls: &mut P, w: &mut W)
-where
+pub fn sql_for_expr<F, P, W>(
+    expr: Expr,
+    f: F,
+    values: &mut Vec<SqlVal>,
+    pls: &mut P,
+    w: &mut W,
+    q: QuoteIdent,
+) where
     F: Fn(Expr, &mut Vec<SqlVal>, &mut P, &mut W),
     P: PlaceholderSource,
     W: Write,
 {
     match expr {
-        Expr::Column(name) => w.write_str(name),
+        Expr::Column(name) => w.write_str(&q(name)),
         Val(v) => match v {
             // No risk of SQL injection with integers and the
             // different sizes are tricky with the PG backend's binary
@@ -41,26 +246,51 @@ where
             }
         },
         Placeholder => w.write_str(&pls.next_placeholder()),
+        // Standard SQL, but for date_trunc as in Postgres
+        Expr::Date(date) => match date.function {
+            DateFunction::Extract(part) => write!(
+                w,
+                "CAST(EXTRACT({} FROM {}) AS INTEGER)",
+                part.sql_name(),
+                q(date.column)
+            ),
+            DateFunction::Trunc(part) => write!(
+                w,
+                "date_trunc('{}', {})",
+                part.sql_name().to_lowercase(),
+                q(date.column)
+            ),
+        },
         Condition(c) => match *c {
             True => write!(w, "TRUE"),
             Eq(col, ex) => match ex {
-                Expr::Val(SqlVal::Null) => write!(w, "{} IS NULL", col),
-                _ => write!(w, "{} = ", col).and_then(|_| Ok(f(ex, values, pls, w))),
+                Expr::Val(SqlVal::Null) => write!(w, "{} IS NULL", q(col)),
+                _ => write!(w, "{} = ", q(col)).and_then(|_| Ok(f(ex, values, pls, w))),
             },
             Ne(col, ex) => match ex {
-                Expr::Val(SqlVal::Null) => write!(w, "{} IS NOT NULL", col),
-                _ => write!(w, "{} <> ", col).and_then(|_| Ok(f(ex, values, pls, w))),
+                Expr::Val(SqlVal::Null) => write!(w, "{} IS NOT NULL", q(col)),
+                _ => write!(w, "{} <> ", q(col)).and_then(|_| Ok(f(ex, values, pls, w))),
             },
-            Lt(col, ex) => write!(w, "{} < ", col).and_then(|_| Ok(f(ex, values, pls, w))),
-            Gt(col, ex) => write!(w, "{} > ", col).and_then(|_| Ok(f(ex, values, pls, w))),
-            Le(col, ex) => write!(w, "{} <= ", col).and_then(|_| Ok(f(ex, values, pls, w))),
-            Ge(col, ex) => write!(w, "{} >= ", col).and_then(|_| Ok(f(ex, values, pls, w))),
-            Like(col, ex) => write!(w, "{} like ", col).and_then(|_| Ok(f(ex, values, pls, w))),
+            Lt(col, ex) => write!(w, "{} < ", q(col)).and_then(|_| Ok(f(ex, values, pls, w))),
+            Gt(col, ex) => write!(w, "{} > ", q(col)).and_then(|_| Ok(f(ex, values, pls, w))),
+            Le(col, ex) => write!(w, "{} <= ", q(col)).and_then(|_| Ok(f(ex, values, pls, w))),
+            Ge(col, ex) => write!(w, "{} >= ", q(col)).and_then(|_| Ok(f(ex, values, pls, w))),
+            Like(col, ex) => write!(w, "{} like ", q(col)).and_then(|_| Ok(f(ex, values, pls, w))),
+            ILike(col, ex) => {
+                write!(w, "LOWER({}) like LOWER(", q(col)).unwrap();
+                f(ex, values, pls, w);
+                write!(w, ")")
+            }
+            EqIgnoreCase(col, ex) => {
+                write!(w, "LOWER({}) = LOWER(", q(col)).unwrap();
+                f(ex, values, pls, w);
+                write!(w, ")")
+            }
             AllOf(conds) => {
                 let mut remaining = conds.len();
                 for cond in conds {
                     // future perf improvement: figure out a clean way to avoid the extra boxing
-                    f(Condition(Box::new(cond)), values, pls, w);
+                    sql_operand(Box::new(cond), false, &f, values, pls, w);
                     if remaining > 1 {
                         write!(w, " AND ").unwrap();
                         remaining -= 1;
@@ -69,9 +299,9 @@ where
                 Ok(())
             }
             And(a, b) => {
-                f(Condition(a), values, pls, w);
+                sql_operand(a, false, &f, values, pls, w);
                 write!(w, " AND ").unwrap();
-                f(Condition(b), values, pls, w);
+                sql_operand(b, false, &f, values, pls, w);
                 Ok(())
             }
             Or(a, b) => {
@@ -80,14 +310,21 @@ where
                 f(Condition(b), values, pls, w);
                 Ok(())
             }
-            Not(a) => write!(w, "NOT ").and_then(|_| Ok(f(Condition(a), values, pls, w))),
+            Not(a) => write!(w, "NOT ").and_then(|_| Ok(sql_operand(a, true, &f, values, pls, w))),
             Subquery {
                 col,
                 tbl2,
                 tbl2_col,
                 expr,
             } => {
-                write!(w, "{} IN (SELECT {} FROM {} WHERE ", col, tbl2_col, tbl2).unwrap();
+                write!(
+                    w,
+                    "{} IN (SELECT {} FROM {} WHERE ",
+                    q(col),
+                    q(tbl2_col),
+                    q(&tbl2)
+                )
+                .unwrap();
                 f(Expr::Condition(expr), values, pls, w);
                 write!(w, ")").unwrap();
                 Ok(())
@@ -100,36 +337,366 @@ where
                 expr,
             } => {
                 // <col> IN (SELECT <col2> FROM <tbl2> <joins> WHERE <expr>)
-                write!(w, "{} IN (SELECT ", col).unwrap();
-                sql_column(col2, w);
-                write!(w, " FROM {} ", tbl2).unwrap();
-                sql_joins(joins, w);
+                write!(w, "{} IN (SELECT ", q(col)).unwrap();
+                sql_column(col2, w, q);
+                write!(w, " FROM {} ", q(&tbl2)).unwrap();
+                sql_joins(joins, w, q);
                 write!(w, " WHERE ").unwrap();
                 f(Expr::Condition(expr), values, pls, w);
                 write!(w, ")").unwrap();
                 Ok(())
             }
+            Exists {
+                tbl,
+                col,
+                tbl2,
+                tbl2_col,
+                expr,
+            } => {
+                // EXISTS (SELECT 1 FROM <tbl2> WHERE <tbl2>.<tbl2_col> = <tbl>.<col> AND (<expr>)),
+                // aliasing tbl2 if it is also the outer table
+                let inner = if tbl == tbl2 {
+                    Cow::Borrowed("butane_exists")
+                } else {
+                    q(&tbl2)
+                };
+                write!(w, "EXISTS (SELECT 1 FROM {}", q(&tbl2)).unwrap();
+                if tbl == tbl2 {
+                    write!(w, " AS {}", inner).unwrap();
+                }
+                write!(
+                    w,
+                    " WHERE {}.{} = {}.{} AND (",
+                    inner,
+                    q(tbl2_col),
+                    q(&tbl),
+                    q(col)
+                )
+                .unwrap();
+                f(Expr::Condition(expr), values, pls, w);
+                write!(w, "))").unwrap();
+                Ok(())
+            }
+            Raw { sql, params } => {
+                let parts = sql_raw_parts(&sql);
+                let mut params = params.into_iter();
+                for (i, part) in parts.iter().enumerate() {
+                    if i > 0 {
+                        match params.next() {
+                            Some(param) => f(Expr::Val(param), values, pls, w),
+                            None => f(Placeholder, values, pls, w),
+                        }
+                    }
+                    w.write_str(part).unwrap();
+                }
+                Ok(())
+            }
+            Date(date, cmp, val) => {
+                f(Expr::Date(date), values, pls, w);
+                write!(w, " {} ", cmp.sql_op()).unwrap();
+                f(Val(val), values, pls, w);
+                Ok(())
+            }
+            JsonEq { col, path, value } => {
+                let path = JsonPathSql::new(&path);
+                Ok(sql_json_eq(&q(col), &path, value, &f, values, pls, w))
+            }
+            JsonContains { col, path, value } => {
+                let path = JsonPathSql::new(&path);
+                Ok(sql_json_contains(
+                    &q(col),
+                    &path,
+                    value,
+                    0,
+                    &f,
+                    values,
+                    pls,
+                    w,
+                ))
+            }
+            // Not all backends accept an empty list
+            In(_, vals) if vals.is_empty() => write!(w, "FALSE"),
             In(col, vals) => {
-                write!(w, "{} IN (", col).unwrap();
-                let mut remaining = vals.len();
-                for val in vals {
-                    f(Expr::Val(val), values, pls, w);
-                    if remaining > 1 {
-                        write!(w, ", ").unwrap();
-                        remaining -= 1;
+                // Long lists are split, as some backends limit the
+                // length of a single list, and combined with OR
+                let chunks = vals.len().div_ceil(IN_LIST_CHUNK);
+                if chunks > 1 {
+                    write!(w, "(").unwrap();
+                }
+                let mut vals = vals.into_iter();
+                for chunk in 0..chunks {
+                    if chunk > 0 {
+                        write!(w, " OR ").unwrap();
                     }
+                    write!(w, "{} IN (", q(col)).unwrap();
+                    for i in 0..IN_LIST_CHUNK {
+                        let Some(val) = vals.next() else { break };
+                        if i > 0 {
+                            write!(w, ", ").unwrap();
+                        }
+                        f(Expr::Val(val), values, pls, w);
+                    }
+                    write!(w, ")").unwrap();
                 }
-                write!(w, ")")
+                if chunks > 1 {
+                    write!(w, ")").unwrap();
+                }
+                Ok(())
             }
         },
     }
     .unwrap()
 }
 
-pub fn sql_select(columns: &[Column], table: &str, w: &mut impl Write) {
+/// Splits raw SQL at its `?` placeholders, outside of quoted strings
+/// and identifiers, turning each `??` into a literal `?`. There is one
+/// more part than there are placeholders.
+pub fn sql_raw_parts(sql: &str) -> Vec<String> {
+    let mut parts = vec![String::new()];
+    let mut quote: Option<char> = None;
+    let mut chars = sql.chars().peekable();
+    while let Some(c) = chars.next() {
+        let part = parts.last_mut().unwrap();
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (None, '\'' | '"') => quote = Some(c),
+            (None, '?') if chars.peek() == Some(&'?') => {
+                chars.next();
+            }
+            (None, '?') => {
+                parts.push(String::new());
+                continue;
+            }
+            _ => (),
+        }
+        part.push(c);
+    }
+    parts
+}
+
+/// `sql` with its `?` placeholders, as split by [sql_raw_parts],
+/// replaced by those of the backend, checking there is one for each
+/// of `params`.
+pub fn sql_raw(sql: &str, params: &[SqlVal], pls: &mut impl PlaceholderSource) -> Result<String> {
+    let parts = sql_raw_parts(sql);
+    if parts.len() - 1 != params.len() {
+        return Err(Error::RawParameterCount(parts.len() - 1, params.len()));
+    }
+    let mut raw = String::new();
+    for (i, part) in parts.iter().enumerate() {
+        if i > 0 {
+            raw.push_str(&pls.next_placeholder());
+        }
+        raw.push_str(part);
+    }
+    Ok(raw)
+}
+
+/// The most values written in a single `IN` list, longer lists being
+/// split into several.
+const IN_LIST_CHUNK: usize = 1000;
+
+/// Whether the values of the `IN` list `vals` should be bound as a
+/// single parameter by backends able to. Values other than integers
+/// take a parameter each, so long lists of them could exceed the
+/// number of parameters a statement may have.
+pub fn in_list_as_param(vals: &[SqlVal]) -> bool {
+    vals.len() > IN_LIST_CHUNK
+        && !vals
+            .iter()
+            .all(|val| matches!(val, SqlVal::Int(_) | SqlVal::BigInt(_)))
+}
+
+/// As [sql_select], leaving out duplicate rows as given by `distinct`.
+#[allow(clippy::too_many_arguments)]
+pub fn sql_select_distinct<F, P, W>(
+    columns: &[Column],
+    distinct: &Distinct,
+    table: &str,
+    f: F,
+    values: &mut Vec<SqlVal>,
+    pls: &mut P,
+    w: &mut W,
+    q: QuoteIdent,
+) where
+    F: Fn(Expr, &mut Vec<SqlVal>, &mut P, &mut W),
+    P: PlaceholderSource,
+    W: Write,
+{
+    write!(w, "SELECT DISTINCT ").unwrap();
+    if let Distinct::On(on) = distinct {
+        let on: Vec<Cow<str>> = on.iter().map(|col| q(col)).collect();
+        write!(w, "ON ({}) ", on.join(", ")).unwrap();
+    }
+    list_result_columns(columns, &f, values, pls, w, q);
+    write!(w, " FROM {}", q(table)).unwrap();
+}
+
+/// Write the `SELECT` of `columns` from `table`. Functions of columns
+/// are written with `f`, as by [sql_for_expr].
+pub fn sql_select<F, P, W>(
+    columns: &[Column],
+    table: &str,
+    f: F,
+    values: &mut Vec<SqlVal>,
+    pls: &mut P,
+    w: &mut W,
+    q: QuoteIdent,
+) where
+    F: Fn(Expr, &mut Vec<SqlVal>, &mut P, &mut W),
+    P: PlaceholderSource,
+    W: Write,
+{
+    write!(w, "SELECT ").unwrap();
+    list_result_columns(columns, &f, values, pls, w, q);
+    write!(w, " FROM {}", q(table)).unwrap();
+}
+
+/// Write the `SELECT` of the `groups` columns and `aggregates` over
+/// the rows of `table`, each aggregate cast to the type given by
+/// `cast`, if any, for backends on which it would otherwise not be
+/// read as its type. Functions of columns are written with `f`.
+#[allow(clippy::too_many_arguments)]
+pub fn sql_select_aggregates<F, P, W>(
+    groups: &[Column],
+    aggregates: &[AggregateExpr],
+    table: &str,
+    f: F,
+    values: &mut Vec<SqlVal>,
+    pls: &mut P,
+    w: &mut W,
+    q: QuoteIdent,
+    cast: fn(&AggregateExpr) -> Option<&'static str>,
+) where
+    F: Fn(Expr, &mut Vec<SqlVal>, &mut P, &mut W),
+    P: PlaceholderSource,
+    W: Write,
+{
     write!(w, "SELECT ").unwrap();
-    list_columns(columns, w);
-    write!(w, " FROM {}", table).unwrap();
+    list_result_columns(groups, &f, values, pls, w, q);
+    aggregates
+        .iter()
+        .fold(if groups.is_empty() { "" } else { ", " }, |sep, a| {
+            w.write_str(sep).unwrap();
+            match cast(a) {
+                Some(ty) => {
+                    write!(w, "CAST(").unwrap();
+                    sql_aggregate(a, w, q);
+                    write!(w, " AS {})", ty).unwrap();
+                }
+                None => sql_aggregate(a, w, q),
+            }
+            ", "
+        });
+    write!(w, " FROM {}", q(table)).unwrap();
+}
+
+fn sql_aggregate(aggregate: &AggregateExpr, w: &mut impl Write, q: QuoteIdent) {
+    let arg = match aggregate.column {
+        Some(column) => q(column),
+        None => Cow::Borrowed("*"),
+    };
+    write!(w, "{}({})", aggregate.function.sql_name(), arg).unwrap();
+}
+
+/// Write the `GROUP BY` of `groups` and the `HAVING` of `having`,
+/// with the groups ordered by their values. The values of `having`
+/// are written with `f`, as by [sql_for_expr].
+pub fn sql_group_by<F, P, W>(
+    groups: &[Column],
+    having: Option<Having>,
+    f: F,
+    values: &mut Vec<SqlVal>,
+    pls: &mut P,
+    w: &mut W,
+    q: QuoteIdent,
+) where
+    F: Fn(Expr, &mut Vec<SqlVal>, &mut P, &mut W),
+    P: PlaceholderSource,
+    W: Write,
+{
+    if !groups.is_empty() {
+        write!(w, " GROUP BY ").unwrap();
+        list_result_columns(groups, &f, values, pls, w, q);
+    }
+    if let Some(having) = having {
+        write!(w, " HAVING ").unwrap();
+        sql_having(having, &f, values, pls, w, q);
+    }
+    if !groups.is_empty() {
+        write!(w, " ORDER BY ").unwrap();
+        list_result_columns(groups, &f, values, pls, w, q);
+    }
+}
+
+fn sql_having<F, P, W>(
+    having: Having,
+    f: &F,
+    values: &mut Vec<SqlVal>,
+    pls: &mut P,
+    w: &mut W,
+    q: QuoteIdent,
+) where
+    F: Fn(Expr, &mut Vec<SqlVal>, &mut P, &mut W),
+    P: PlaceholderSource,
+    W: Write,
+{
+    let (aggregate, op, val) = match having {
+        Having::Eq(a, val) => (a, "=", val),
+        Having::Ne(a, val) => (a, "<>", val),
+        Having::Lt(a, val) => (a, "<", val),
+        Having::Gt(a, val) => (a, ">", val),
+        Having::Le(a, val) => (a, "<=", val),
+        Having::Ge(a, val) => (a, ">=", val),
+        Having::And(a, b) => {
+            write!(w, "(").unwrap();
+            sql_having(*a, f, values, pls, w, q);
+            write!(w, ") AND (").unwrap();
+            sql_having(*b, f, values, pls, w, q);
+            write!(w, ")").unwrap();
+            return;
+        }
+        Having::Or(a, b) => {
+            write!(w, "(").unwrap();
+            sql_having(*a, f, values, pls, w, q);
+            write!(w, ") OR (").unwrap();
+            sql_having(*b, f, values, pls, w, q);
+            write!(w, ")").unwrap();
+            return;
+        }
+        Having::Not(a) => {
+            write!(w, "NOT (").unwrap();
+            sql_having(*a, f, values, pls, w, q);
+            write!(w, ")").unwrap();
+            return;
+        }
+    };
+    sql_aggregate(&aggregate, w, q);
+    write!(w, " {} ", op).unwrap();
+    f(Val(val), values, pls, w);
+}
+
+/// The values of `groups` and `aggregates` in each of `rows`.
+pub fn aggregate_rows(
+    mut rows: super::RawQueryResult<'_>,
+    groups: &[Column],
+    aggregates: &[AggregateExpr],
+) -> Result<Vec<Vec<SqlVal>>> {
+    let types: Vec<&SqlType> = groups
+        .iter()
+        .map(|c| c.ty())
+        .chain(aggregates.iter().map(|a| &a.ty))
+        .collect();
+    let mut result = Vec::new();
+    while let Some(row) = rows.next()? {
+        let values = types
+            .iter()
+            .enumerate()
+            .map(|(idx, ty)| Ok(row.get(idx, (*ty).clone())?.into()))
+            .collect::<Result<Vec<SqlVal>>>()?;
+        result.push(values);
+    }
+    Ok(result)
 }
 
 pub fn sql_insert_with_placeholders(
@@ -137,11 +704,12 @@ pub fn sql_insert_with_placeholders(
     columns: &[Column],
     pls: &mut impl PlaceholderSource,
     w: &mut impl Write,
+    q: QuoteIdent,
 ) {
-    write!(w, "INSERT INTO {} ", table).unwrap();
+    write!(w, "INSERT INTO {} ", q(table)).unwrap();
     if !columns.is_empty() {
         write!(w, "(").unwrap();
-        list_columns(columns, w);
+        list_columns(columns, w, q);
         write!(w, ") VALUES (").unwrap();
         columns.iter().fold("", |sep, _| {
             write!(w, "{}{}", sep, pls.next_placeholder()).unwrap();
@@ -153,19 +721,46 @@ pub fn sql_insert_with_placeholders(
     }
 }
 
+/// A statement of `sql` for the values of each of `rows`.
+pub fn statements_for_rows(sql: &str, rows: &[Vec<SqlValRef<'_>>]) -> Vec<Statement> {
+    rows.iter()
+        .map(|values| Statement::new(sql, values.iter().cloned().map(SqlVal::from).collect()))
+        .collect()
+}
+
 pub fn sql_update_with_placeholders(
     table: &str,
     pkcol: Column,
     columns: &[Column],
     pls: &mut impl PlaceholderSource,
     w: &mut impl Write,
+    q: QuoteIdent,
 ) {
-    write!(w, "UPDATE {} SET ", table).unwrap();
+    write!(w, "UPDATE {} SET ", q(table)).unwrap();
     columns.iter().fold("", |sep, c| {
-        write!(w, "{}{} = {}", sep, c.name(), pls.next_placeholder()).unwrap();
+        write!(w, "{}{} = {}", sep, q(c.name()), pls.next_placeholder()).unwrap();
+        ", "
+    });
+    write!(w, " WHERE {} = {}", q(pkcol.name()), pls.next_placeholder()).unwrap();
+}
+
+/// Writes `UPDATE table SET` with a placeholder for the value of each
+/// of `assignments`, pushing the values onto `values`. The `WHERE`
+/// clause is left to the caller.
+pub fn sql_update_set(
+    table: &str,
+    assignments: &[Assignment],
+    values: &mut Vec<SqlVal>,
+    pls: &mut impl PlaceholderSource,
+    w: &mut impl Write,
+    q: QuoteIdent,
+) {
+    write!(w, "UPDATE {} SET ", q(table)).unwrap();
+    assignments.iter().fold("", |sep, a| {
+        write!(w, "{}{} = {}", sep, q(a.column), pls.next_placeholder()).unwrap();
+        values.push(a.value.clone());
         ", "
     });
-    write!(w, " WHERE {} = {}", pkcol.name(), pls.next_placeholder()).unwrap();
 }
 
 pub fn sql_limit(limit: i32, w: &mut impl Write) {
@@ -176,14 +771,47 @@ pub fn sql_offset(offset: i32, w: &mut impl Write) {
     write!(w, " OFFSET {}", offset).unwrap();
 }
 
-pub fn sql_order(order: &[Order], w: &mut impl Write) {
+pub fn sql_order(order: &[Order], w: &mut impl Write, q: QuoteIdent) {
+    write_order(order, w, q, true)
+}
+
+/// As [sql_order], for backends without `NULLS FIRST` and `NULLS
+/// LAST`, on which terms placing nulls are sorted on whether the column
+/// is null first.
+pub fn sql_order_emulating_nulls(order: &[Order], w: &mut impl Write, q: QuoteIdent) {
+    write_order(order, w, q, false)
+}
+
+fn write_order(order: &[Order], w: &mut impl Write, q: QuoteIdent, nulls_keyword: bool) {
     write!(w, " ORDER BY ").unwrap();
     order.iter().fold("", |sep, o| {
         let sql_dir = match o.direction {
             OrderDirection::Ascending => "ASC",
             OrderDirection::Descending => "DESC",
         };
-        write!(w, "{}{} {}", sep, o.column, sql_dir).unwrap();
+        match (o.nulls, nulls_keyword) {
+            (None, _) => write!(w, "{}{} {}", sep, q(o.column), sql_dir),
+            (Some(nulls), true) => {
+                let sql_nulls = match nulls {
+                    NullsOrder::First => "FIRST",
+                    NullsOrder::Last => "LAST",
+                };
+                write!(w, "{}{} {} NULLS {}", sep, q(o.column), sql_dir, sql_nulls)
+            }
+            (Some(nulls), false) => {
+                let nulls_dir = match nulls {
+                    NullsOrder::First => "DESC",
+                    NullsOrder::Last => "ASC",
+                };
+                let col = q(o.column);
+                write!(
+                    w,
+                    "{}{} IS NULL {}, {} {}",
+                    sep, col, nulls_dir, col, sql_dir
+                )
+            }
+        }
+        .unwrap();
         ", "
     });
 }
@@ -207,17 +835,178 @@ pub fn column_default(col: &AColumn) -> Result<SqlVal> {
             SqlType::Timestamp => SqlVal::Timestamp(NaiveDateTime::from_timestamp(0, 0)),
             SqlType::Custom(_) => return Err(Error::NoCustomDefault),
         },
-        TypeIdentifier::Name(_) => return Err(Error::NoCustomDefault),
+        TypeIdentifier::Name(_) | TypeIdentifier::Enum(_) => return Err(Error::NoCustomDefault),
     })
 }
 
-pub fn list_columns(columns: &[Column], w: &mut impl Write) {
-    let mut colnames: Vec<&'static str> = Vec::new();
-    columns.iter().for_each(|c| colnames.push(c.name()));
+/// The `PRIMARY KEY` table constraint of `table` if its key is
+/// composite. A single-column key is declared with its column instead.
+pub fn sql_composite_pk(table: &ATable, q: QuoteIdent) -> Option<String> {
+    let pk = table.pk();
+    if pk.len() < 2 {
+        return None;
+    }
+    let columns: Vec<Cow<str>> = pk.iter().map(|c| q(c.name())).collect();
+    Some(format!("PRIMARY KEY ({})", columns.join(", ")))
+}
+
+/// The steps renaming `old` to `new`, as pairs of names. Names which
+/// differ only in case are renamed via a temporary name, as the
+/// database may not tell them apart.
+pub fn rename_steps(old: &str, new: &str) -> Vec<(String, String)> {
+    if old != new && old.eq_ignore_ascii_case(new) {
+        let tmp = format!("{}__butane_tmp", new);
+        vec![(old.to_string(), tmp.clone()), (tmp, new.to_string())]
+    } else {
+        vec![(old.to_string(), new.to_string())]
+    }
+}
+
+/// The `WHERE` clause of a partial index, preceded by a space, or
+/// nothing if `index` is not partial.
+pub fn sql_index_predicate(index: &AIndex) -> String {
+    match index.predicate() {
+        Some(predicate) => format!(" WHERE {}", predicate),
+        None => String::new(),
+    }
+}
+
+/// The `REFERENCES` clause of a column with the foreign key
+/// `reference`, given the referenced table as written in SQL.
+pub fn sql_references(reference: &AForeignKey, table: &str, q: QuoteIdent) -> String {
+    let mut sql = format!("REFERENCES {}", table);
+    if let Some(column) = reference.column() {
+        write!(sql, "({})", q(column)).unwrap();
+    }
+    if reference.on_delete() != ReferentialAction::NoAction {
+        write!(sql, " ON DELETE {}", reference.on_delete().sql()).unwrap();
+    }
+    if reference.on_update() != ReferentialAction::NoAction {
+        write!(sql, " ON UPDATE {}", reference.on_update().sql()).unwrap();
+    }
+    sql
+}
+
+/// The [ReferentialAction] named by the SQL keywords `sql`, as reported
+/// by database introspection.
+pub fn referential_action_from_sql(sql: &str) -> ReferentialAction {
+    match sql.to_uppercase().as_str() {
+        "RESTRICT" => ReferentialAction::Restrict,
+        "CASCADE" => ReferentialAction::Cascade,
+        "SET NULL" => ReferentialAction::SetNull,
+        "SET DEFAULT" => ReferentialAction::SetDefault,
+        _ => ReferentialAction::NoAction,
+    }
+}
+
+/// `db` as it would be read back by
+/// [introspect][crate::db::BackendConnection::introspect]. Column
+/// defaults and check constraints are dropped, and names are folded to
+/// lower case if `fold_case`. Whether columns are auto is dropped unless
+/// `auto_visible`, as are type parameters unless `params_visible`, and
+/// column types are transformed with `ty`.
+pub fn as_introspected(
+    db: &ADB,
+    fold_case: bool,
+    auto_visible: bool,
+    params_visible: bool,
+    ty: impl Fn(TypeIdentifier) -> TypeIdentifier,
+) -> ADB {
+    let name = |n: &str| {
+        if fold_case {
+            n.to_lowercase()
+        } else {
+            n.to_string()
+        }
+    };
+    let mut out = ADB::new();
+    for table in db.tables() {
+        let mut new_table = ATable::new(name(&table.name));
+        for col in &table.columns {
+            let typeid = match col.typeid() {
+                Ok(typeid) => ty(typeid),
+                Err(_) => continue,
+            };
+            let mut new_col = AColumn::new(
+                name(col.name()),
+                DeferredSqlType::KnownId(typeid),
+                col.nullable(),
+                col.is_pk(),
+                auto_visible && col.is_auto(),
+                col.unique(),
+                None,
+            );
+            if let (true, Some(kind)) = (auto_visible, col.auto_increment()) {
+                new_col = new_col.with_auto_increment(kind);
+            }
+            if params_visible {
+                new_col = new_col.with_params(*col.params());
+            }
+            if let Some(r) = col.reference() {
+                let mut reference = AForeignKey::new(name(r.table()))
+                    .with_on_delete(r.on_delete())
+                    .with_on_update(r.on_update());
+                if let Some(column) = r.column() {
+                    reference = reference.with_column(name(column));
+                }
+                new_col = new_col.with_reference(reference);
+            }
+            new_table.add_column(new_col);
+        }
+        for constraint in &table.unique_constraints {
+            new_table.add_unique_constraint(AUniqueConstraint::new(
+                constraint.columns().iter().map(|c| name(c)),
+            ));
+        }
+        for index in &table.indexes {
+            let mut new_index = AIndex::new(index.columns().iter().map(|c| name(c)));
+            if let Some(predicate) = index.predicate() {
+                new_index = new_index.with_predicate(predicate);
+            }
+            new_table.add_index(new_index);
+        }
+        out.replace_table(new_table);
+    }
+    out
+}
+
+pub fn list_columns(columns: &[Column], w: &mut impl Write, q: QuoteIdent) {
+    let colnames: Vec<Cow<str>> = columns.iter().map(|c| q(c.name())).collect();
     write!(w, "{}", colnames.as_slice().join(",")).unwrap();
 }
 
-fn sql_joins(joins: Vec<Join>, w: &mut impl Write) {
+/// As [list_columns], writing those which are functions of a column
+/// with `f`.
+fn list_result_columns<F, P, W>(
+    columns: &[Column],
+    f: &F,
+    values: &mut Vec<SqlVal>,
+    pls: &mut P,
+    w: &mut W,
+    q: QuoteIdent,
+) where
+    F: Fn(Expr, &mut Vec<SqlVal>, &mut P, &mut W),
+    P: PlaceholderSource,
+    W: Write,
+{
+    for (i, column) in columns.iter().enumerate() {
+        if i > 0 {
+            w.write_str(",").unwrap();
+        }
+        match column.date_function() {
+            Some(function) => {
+                let date = DateExpr {
+                    column: column.name(),
+                    function,
+                };
+                f(Expr::Date(date), values, pls, w)
+            }
+            None => w.write_str(&q(column.name())).unwrap(),
+        }
+    }
+}
+
+fn sql_joins(joins: Vec<Join>, w: &mut impl Write, q: QuoteIdent) {
     for join in joins {
         match join {
             Join::Inner {
@@ -226,19 +1015,19 @@ fn sql_joins(joins: Vec<Join>, w: &mut impl Write) {
                 col2,
             } => {
                 // INNER JOIN <join_table> ON <col1> = <col2>
-                write!(w, "INNER JOIN {} ON ", join_table).unwrap();
-                sql_column(col1, w);
+                write!(w, "INNER JOIN {} ON ", q(join_table)).unwrap();
+                sql_column(col1, w, q);
                 w.write_str(" = ").unwrap();
-                sql_column(col2, w);
+                sql_column(col2, w, q);
             }
         }
     }
 }
 
-fn sql_column(col: query::Column, w: &mut impl Write) {
+fn sql_column(col: query::Column, w: &mut impl Write, q: QuoteIdent) {
     match col.table() {
-        Some(table) => write!(w, "{}.{}", table, col.name()),
-        None => w.write_str(col.name()),
+        Some(table) => write!(w, "{}.{}", q(table), q(col.name())),
+        None => w.write_str(&q(col.name())),
     }
     .unwrap()
 }
@@ -251,7 +1040,7 @@ pub fn sql_literal_value(val: SqlVal) -> Result<String> {
         Int(val) => Ok(val.to_string()),
         BigInt(val) => Ok(val.to_string()),
         Real(val) => Ok(val.to_string()),
-        Text(val) => Ok(format!("'{}'", val)),
+        Text(val) => Ok(format!("'{}'", val.replace('\'', "''"))),
         Blob(val) => Ok(format!("x'{}'", hex::encode_upper(val))),
         #[cfg(feature = "datetime")]
         Timestamp(ndt) => Ok(ndt.format("'%Y-%m-%dT%H:%M:%S%.f'").to_string()),
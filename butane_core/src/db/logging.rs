@@ -0,0 +1,78 @@
+//! Logging of the statements run by a connection, see
+//! [Connection::set_logger](super::Connection::set_logger).
+#[cfg(any(feature = "sqlite", feature = "pg", feature = "mysql"))]
+use crate::{SqlVal, SqlValRef};
+#[cfg(any(feature = "sqlite", feature = "pg", feature = "mysql"))]
+use std::fmt::Write;
+use std::sync::Arc;
+use std::time::Duration;
+#[cfg(any(feature = "sqlite", feature = "pg", feature = "mysql"))]
+use std::time::Instant;
+
+/// Text parameters longer than this are abbreviated in summaries.
+#[cfg(any(feature = "sqlite", feature = "pg", feature = "mysql"))]
+const MAX_TEXT_LEN: usize = 64;
+
+/// A statement run by a connection, as given to its logger.
+#[derive(Clone, Debug)]
+pub struct QueryInfo<'a> {
+    /// The SQL of the statement, with placeholders for its parameters.
+    pub sql: &'a str,
+    /// Summary of the parameters bound to the placeholders, in order.
+    /// Long text is abbreviated and blobs given only by their length.
+    pub params: String,
+    /// How long the statement took to run. Queries are timed until
+    /// their first row is ready, not until all are read.
+    pub duration: Duration,
+    /// Whether the statement succeeded.
+    pub succeeded: bool,
+}
+
+/// Function called with each statement run by a connection.
+pub type QueryLogger = Arc<dyn Fn(&QueryInfo<'_>) + Send + Sync>;
+
+/// Run `f`, which runs the statement `sql` with the parameters
+/// `values`, logging it to `logger` if there is one.
+#[cfg(any(feature = "sqlite", feature = "pg", feature = "mysql"))]
+pub(crate) fn log<'v, T, E>(
+    logger: Option<&QueryLogger>,
+    sql: &str,
+    values: impl IntoIterator<Item = SqlValRef<'v>>,
+    f: impl FnOnce() -> Result<T, E>,
+) -> Result<T, E> {
+    let logger = match logger {
+        Some(logger) => logger,
+        None => return f(),
+    };
+    let start = Instant::now();
+    let result = f();
+    logger(&QueryInfo {
+        sql,
+        params: summarize(values),
+        duration: start.elapsed(),
+        succeeded: result.is_ok(),
+    });
+    result
+}
+
+/// `values` as a list such as `[1, 'text', <3 bytes>]`.
+#[cfg(any(feature = "sqlite", feature = "pg", feature = "mysql"))]
+fn summarize<'v>(values: impl IntoIterator<Item = SqlValRef<'v>>) -> String {
+    let mut summary = String::from("[");
+    for (i, value) in values.into_iter().enumerate() {
+        if i > 0 {
+            summary.push_str(", ");
+        }
+        match value {
+            SqlValRef::Text(text) if text.chars().count() > MAX_TEXT_LEN => {
+                let prefix: String = text.chars().take(MAX_TEXT_LEN).collect();
+                write!(summary, "'{}...'", prefix).unwrap();
+            }
+            SqlValRef::Text(text) => write!(summary, "'{}'", text).unwrap(),
+            SqlValRef::Blob(blob) => write!(summary, "<{} bytes>", blob.len()).unwrap(),
+            value => write!(summary, "{}", SqlVal::from(value)).unwrap(),
+        }
+    }
+    summary.push(']');
+    summary
+}
@@ -17,6 +17,32 @@ macro_rules! connection_method_wrapper {
                 self.wrapped_connection_methods()?
                     .query(table, columns, expr, limit, offset, sort)
             }
+            fn query_stream<'a, 'b, 'c: 'a>(
+                &'c self,
+                table: &str,
+                columns: &'b [Column],
+                expr: Option<BoolExpr>,
+                limit: Option<i32>,
+                offset: Option<i32>,
+                sort: Option<&[$crate::query::Order]>,
+            ) -> Result<RawQueryResult<'a>> {
+                self.wrapped_connection_methods()?
+                    .query_stream(table, columns, expr, limit, offset, sort)
+            }
+            #[allow(clippy::too_many_arguments)]
+            fn query_distinct<'a, 'b, 'c: 'a>(
+                &'c self,
+                table: &str,
+                columns: &'b [Column],
+                distinct: &$crate::query::Distinct,
+                expr: Option<BoolExpr>,
+                limit: Option<i32>,
+                offset: Option<i32>,
+                sort: Option<&[$crate::query::Order]>,
+            ) -> Result<RawQueryResult<'a>> {
+                self.wrapped_connection_methods()?
+                    .query_distinct(table, columns, distinct, expr, limit, offset, sort)
+            }
             fn insert_returning_pk(
                 &self,
                 table: &str,
@@ -60,9 +86,62 @@ macro_rules! connection_method_wrapper {
             fn delete_where(&self, table: &str, expr: BoolExpr) -> Result<usize> {
                 self.wrapped_connection_methods()?.delete_where(table, expr)
             }
+            fn update_where(
+                &self,
+                table: &str,
+                assignments: &[$crate::query::Assignment],
+                expr: BoolExpr,
+            ) -> Result<usize> {
+                self.wrapped_connection_methods()?
+                    .update_where(table, assignments, expr)
+            }
             fn has_table(&self, table: &str) -> Result<bool> {
                 self.wrapped_connection_methods()?.has_table(table)
             }
+            fn execute_batch(&self, statements: &[$crate::db::Statement]) -> Result<()> {
+                self.wrapped_connection_methods()?.execute_batch(statements)
+            }
+            fn insert_only_many(
+                &self,
+                table: &str,
+                columns: &[Column],
+                rows: &[Vec<SqlValRef<'_>>],
+            ) -> Result<()> {
+                self.wrapped_connection_methods()?
+                    .insert_only_many(table, columns, rows)
+            }
+            fn explain(
+                &self,
+                table: &str,
+                columns: &[Column],
+                expr: Option<BoolExpr>,
+                limit: Option<i32>,
+                offset: Option<i32>,
+                sort: Option<&[$crate::query::Order]>,
+                analyze: bool,
+            ) -> Result<$crate::db::QueryPlan> {
+                self.wrapped_connection_methods()?
+                    .explain(table, columns, expr, limit, offset, sort, analyze)
+            }
+            fn aggregate(
+                &self,
+                table: &str,
+                groups: &[Column],
+                aggregates: &[$crate::query::AggregateExpr],
+                expr: Option<BoolExpr>,
+                having: Option<$crate::query::Having>,
+            ) -> Result<Vec<Vec<SqlVal>>> {
+                self.wrapped_connection_methods()?
+                    .aggregate(table, groups, aggregates, expr, having)
+            }
+            fn query_raw_rows<'a, 'c: 'a>(
+                &'c self,
+                sql: &str,
+                params: &[SqlVal],
+            ) -> Result<RawQueryResult<'a>> {
+                self.wrapped_connection_methods()?
+                    .query_raw_rows(sql, params)
+            }
         }
     };
 }
@@ -0,0 +1,1968 @@
+//! MySQL and MariaDB database backend
+//!
+//! The backend is experimental, enabled by the `mysql-experimental`
+//! feature of butane. Only the SQL it generates is tested: it has not
+//! been run against a server in the test suite, so may not work.
+//!
+//! MySQL commits implicitly before and after each DDL statement, so a
+//! migration which fails part way through is not rolled back. Use
+//! [create_idempotent_migration_sql][Backend::create_idempotent_migration_sql]
+//! to write migrations which can be re-run after such a failure.
+use super::connmethods::VecRows;
+use super::helper;
+use super::logging;
+use super::*;
+use crate::migrations::adb::{
+    ACheckConstraint, AColumn, AForeignKey, AIndex, ATable, ATypeParams, AUniqueConstraint, AView,
+    AutoIncrement, Backfill, DeferredSqlType, Operation, TypeIdentifier, ADB,
+};
+use crate::{debug, query};
+use crate::{Result, SqlType, SqlVal, SqlValRef};
+use ::mysql::prelude::Queryable;
+#[cfg(feature = "datetime")]
+use chrono::{Datelike, NaiveDate, Timelike};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt::Write;
+
+/// The name of the mysql backend.
+pub const BACKEND_NAME: &str = "mysql";
+
+/// Length of the `VARCHAR` declared for a text column without a length
+/// which is part of a key or index, as MySQL cannot index `TEXT`.
+const KEY_TEXT_LENGTH: u32 = 255;
+
+/// MySQL [Backend][crate::db::Backend] implementation. Also supports
+/// MariaDB.
+#[derive(Default)]
+pub struct MySqlBackend {
+    timeouts: Timeouts,
+}
+impl MySqlBackend {
+    pub fn new() -> MySqlBackend {
+        MySqlBackend::default()
+    }
+    /// Set the `max_execution_time` and `innodb_lock_wait_timeout` of
+    /// connections made by this backend to those of `timeouts`. The
+    /// former only limits `SELECT` statements, and the latter is
+    /// rounded up to whole seconds.
+    pub fn with_timeouts(mut self, timeouts: Timeouts) -> MySqlBackend {
+        self.timeouts = timeouts;
+        self
+    }
+}
+impl MySqlBackend {
+    fn connect(&self, url: &str) -> Result<MySqlConnection> {
+        let conn = MySqlConnection::open(url)?;
+        let mut sql = String::new();
+        if let Some(timeout) = self.timeouts.statement {
+            write!(
+                sql,
+                "SET SESSION max_execution_time = {};",
+                timeout.as_millis()
+            )
+            .unwrap();
+        }
+        if let Some(timeout) = self.timeouts.lock {
+            let secs = timeout.as_millis().div_ceil(1000);
+            write!(
+                sql,
+                "SET SESSION innodb_lock_wait_timeout = {};",
+                secs.max(1)
+            )
+            .unwrap();
+        }
+        if !sql.is_empty() {
+            conn.client.conn.try_borrow_mut()?.query_drop(sql)?;
+        }
+        Ok(conn)
+    }
+}
+impl Backend for MySqlBackend {
+    fn name(&self) -> &'static str {
+        BACKEND_NAME
+    }
+
+    fn create_migration_sql(&self, current: &ADB, ops: Vec<Operation>) -> Result<String> {
+        migration_sql(current, ops, false)
+    }
+
+    fn create_idempotent_migration_sql(
+        &self,
+        current: &ADB,
+        ops: Vec<Operation>,
+    ) -> Result<String> {
+        migration_sql(current, ops, true)
+    }
+
+    fn as_introspected(&self, db: &ADB) -> ADB {
+        let mut out = helper::as_introspected(db, false, true, true, |typeid| match typeid {
+            // Enum values are stored as text
+            TypeIdentifier::Enum(_) => TypeIdentifier::Ty(SqlType::Text),
+            _ => typeid,
+        });
+        let tables: Vec<ATable> = out.tables().cloned().collect();
+        for mut table in tables {
+            let declared = match db.get_table(&table.name) {
+                Some(declared) => declared,
+                None => continue,
+            };
+            table.columns = table
+                .columns
+                .into_iter()
+                .map(|mut col| {
+                    // Both kinds are AUTO_INCREMENT
+                    if col.is_auto() {
+                        col = col.with_auto_increment(AutoIncrement::Serial);
+                    }
+                    let is_text = matches!(col.typeid(), Ok(TypeIdentifier::Ty(SqlType::Text)));
+                    if is_text && col.params().length.is_none() && is_key(declared, col.name()) {
+                        let params = ATypeParams {
+                            length: Some(KEY_TEXT_LENGTH),
+                            ..*col.params()
+                        };
+                        col = col.with_params(params);
+                    }
+                    col
+                })
+                .collect();
+            // Partial indexes are created on all rows
+            table.indexes = table
+                .indexes
+                .iter()
+                .map(|index| AIndex::new(index.columns().iter().cloned()))
+                .collect();
+            out.replace_table(table);
+        }
+        out
+    }
+
+    fn connect(&self, url: &str) -> Result<Connection> {
+        Ok(Connection {
+            conn: Box::new(self.connect(url)?),
+        })
+    }
+}
+
+/// MySQL database connection.
+pub struct MySqlConnection {
+    client: MySqlClient<::mysql::Conn>,
+}
+impl MySqlConnection {
+    fn open(url: &str) -> Result<Self> {
+        Ok(MySqlConnection {
+            client: MySqlClient::new(::mysql::Conn::new(url)?, None),
+        })
+    }
+
+    // For use with connection_method_wrapper macro
+    #[allow(clippy::unnecessary_wraps)]
+    fn wrapped_connection_methods(&self) -> Result<&MySqlClient<::mysql::Conn>> {
+        Ok(&self.client)
+    }
+}
+connection_method_wrapper!(MySqlConnection);
+
+impl BackendConnection for MySqlConnection {
+    fn transaction_with(&mut self, options: TransactionOptions) -> Result<Transaction<'_>> {
+        // Session variables would outlast the transaction
+        if options.timeouts != Timeouts::default() {
+            return Err(Error::TimeoutUnsupported("transaction", BACKEND_NAME));
+        }
+        let isolation_level = options.isolation_level.map(|level| match level {
+            IsolationLevel::ReadCommitted => ::mysql::IsolationLevel::ReadCommitted,
+            IsolationLevel::RepeatableRead => ::mysql::IsolationLevel::RepeatableRead,
+            IsolationLevel::Serializable => ::mysql::IsolationLevel::Serializable,
+        });
+        let access_mode = if options.read_only {
+            ::mysql::AccessMode::ReadOnly
+        } else {
+            ::mysql::AccessMode::ReadWrite
+        };
+        let logger = self.client.logger.clone();
+        let trans = self.client.conn.get_mut().start_transaction(
+            ::mysql::TxOpts::default()
+                .set_isolation_level(isolation_level)
+                .set_access_mode(Some(access_mode)),
+        )?;
+        Ok(Transaction::new(Box::new(MySqlTransaction::new(
+            trans, logger,
+        ))))
+    }
+    fn backend(&self) -> Box<dyn Backend> {
+        Box::new(MySqlBackend::new())
+    }
+    fn backend_name(&self) -> &'static str {
+        BACKEND_NAME
+    }
+    fn capabilities(&self) -> Capabilities {
+        // Neither is supported by MySQL, nor used by this backend
+        Capabilities {
+            returning: false,
+            add_column_if_not_exists: false,
+        }
+    }
+    fn is_closed(&self) -> bool {
+        false
+    }
+    fn set_query_logger(&mut self, logger: Option<QueryLogger>) -> Result<()> {
+        self.client.logger = logger;
+        Ok(())
+    }
+    fn introspect(&self) -> Result<ADB> {
+        let mut client = self.client.conn.try_borrow_mut()?;
+        introspect(&mut *client)
+    }
+    fn managed_tables(&self) -> Result<Vec<String>> {
+        let names: Vec<String> = self.client.conn.try_borrow_mut()?.exec(
+            "SELECT table_name FROM information_schema.tables \
+             WHERE table_schema = DATABASE() AND table_type = 'BASE TABLE' \
+             AND RIGHT(table_comment, CHAR_LENGTH(?)) = ?;",
+            (MANAGED_MARKER, MANAGED_MARKER),
+        )?;
+        Ok(names)
+    }
+    fn unmanaged_objects(&self) -> Result<Vec<(String, TableObject)>> {
+        // Constraints cannot carry comments, so only indexes are marked
+        // on their own
+        let mut client = self.client.conn.try_borrow_mut()?;
+        Ok(introspect_indexes(&mut *client)?
+            .into_iter()
+            .filter(|(_, _, managed)| !managed)
+            .map(|(table, index, _)| (table, TableObject::Index(index)))
+            .collect())
+    }
+}
+
+fn introspect(client: &mut impl Queryable) -> Result<ADB> {
+    // Single columns which are primary keys or unique on their own,
+    // keyed by (table, column), and unique constraints spanning
+    // several columns.
+    let mut pks: Vec<(String, String)> = Vec::new();
+    let mut unique: Vec<(String, String)> = Vec::new();
+    let mut unique_constraints: Vec<(String, AUniqueConstraint)> = Vec::new();
+    let rows: Vec<(String, String, String, String)> = client.query(
+        "SELECT tc.table_name, tc.constraint_name, tc.constraint_type, k.column_name \
+         FROM information_schema.table_constraints tc \
+         JOIN information_schema.key_column_usage k ON k.constraint_schema = tc.constraint_schema \
+         AND k.table_name = tc.table_name AND k.constraint_name = tc.constraint_name \
+         WHERE tc.table_schema = DATABASE() AND tc.constraint_type IN ('PRIMARY KEY', 'UNIQUE') \
+         ORDER BY tc.table_name, tc.constraint_name, k.ordinal_position;",
+    )?;
+    let mut constraints: Vec<((String, String, String), Vec<String>)> = Vec::new();
+    for (table, name, kind, column) in rows {
+        let key = (table, name, kind);
+        match constraints.last_mut() {
+            Some((last, columns)) if *last == key => columns.push(column),
+            _ => constraints.push((key, vec![column])),
+        }
+    }
+    for ((table, _, kind), mut columns) in constraints {
+        if kind == "PRIMARY KEY" {
+            pks.extend(columns.into_iter().map(|c| (table.clone(), c)));
+        } else if columns.len() == 1 {
+            unique.push((table, columns.remove(0)));
+        } else {
+            unique_constraints.push((table, AUniqueConstraint::new(columns)));
+        }
+    }
+
+    let mut references: HashMap<(String, String), AForeignKey> = HashMap::new();
+    let rows: Vec<(String, String, String, String, String, String)> = client.query(
+        "SELECT k.table_name, k.column_name, k.referenced_table_name, k.referenced_column_name, \
+         rc.delete_rule, rc.update_rule FROM information_schema.key_column_usage k \
+         JOIN information_schema.referential_constraints rc \
+         ON rc.constraint_schema = k.constraint_schema AND rc.constraint_name = k.constraint_name \
+         WHERE k.table_schema = DATABASE() AND k.referenced_table_name IS NOT NULL;",
+    )?;
+    for (table, column, ftable, fcolumn, delete, update) in rows {
+        let mut reference = AForeignKey::new(ftable.as_str())
+            .with_on_delete(helper::referential_action_from_sql(&delete))
+            .with_on_update(helper::referential_action_from_sql(&update));
+        // Butane references the primary key implicitly
+        if !pks.contains(&(ftable, fcolumn.clone())) {
+            reference = reference.with_column(fcolumn);
+        }
+        references.insert((table, column), reference);
+    }
+
+    let mut tables: HashMap<String, ATable> = HashMap::new();
+    let names: Vec<String> = client.query(
+        "SELECT table_name FROM information_schema.tables \
+         WHERE table_schema = DATABASE() AND table_type = 'BASE TABLE';",
+    )?;
+    for name in names {
+        tables.insert(name.clone(), ATable::new(name));
+    }
+    let rows: Vec<::mysql::Row> = client.query(
+        "SELECT table_name, column_name, data_type, column_type, is_nullable, \
+         character_maximum_length, numeric_precision, numeric_scale, extra \
+         FROM information_schema.columns WHERE table_schema = DATABASE() \
+         ORDER BY table_name, ordinal_position;",
+    )?;
+    for row in rows {
+        let value = |idx: usize| -> Result<Option<String>> {
+            row.get_opt::<Option<String>, _>(idx)
+                .transpose()
+                .map(Option::flatten)
+                .map_err(|e| Error::MySql(e.into()))
+        };
+        let number = |idx: usize| -> Result<Option<u32>> {
+            row.get_opt::<Option<u32>, _>(idx)
+                .transpose()
+                .map(Option::flatten)
+                .map_err(|e| Error::MySql(e.into()))
+        };
+        let table_name = value(0)?.unwrap_or_default();
+        let table = match tables.get_mut(&table_name) {
+            Some(table) => table,
+            // A view
+            None => continue,
+        };
+        let key = (table_name, value(1)?.unwrap_or_default());
+        let data_type = value(2)?.unwrap_or_default();
+        let column_type = value(3)?.unwrap_or_default();
+        let precision = number(6)?;
+        let (typeid, params) = match data_type.as_str() {
+            "varchar" => (
+                TypeIdentifier::Ty(SqlType::Text),
+                ATypeParams {
+                    length: number(5)?,
+                    ..Default::default()
+                },
+            ),
+            "decimal" => (
+                TypeIdentifier::Ty(SqlType::Real),
+                ATypeParams {
+                    precision,
+                    // A scale of zero is left unset, as by butane
+                    scale: number(7)?.filter(|s| *s != 0),
+                    ..Default::default()
+                },
+            ),
+            _ => (sqltype_from_sql(&column_type), ATypeParams::default()),
+        };
+        let auto = value(8)?.is_some_and(|extra| extra.contains("auto_increment"));
+        let mut col = AColumn::new(
+            key.1.as_str(),
+            DeferredSqlType::KnownId(typeid),
+            value(4)?.as_deref() == Some("YES"),
+            pks.contains(&key),
+            auto,
+            unique.contains(&key),
+            None,
+        );
+        if let Some(reference) = references.remove(&key) {
+            col = col.with_reference(reference);
+        }
+        table.add_column(col.with_params(params));
+    }
+    for (table, constraint) in unique_constraints {
+        if let Some(table) = tables.get_mut(&table) {
+            table.add_unique_constraint(constraint);
+        }
+    }
+    for (table, index, _) in introspect_indexes(client)? {
+        if let Some(table) = tables.get_mut(&table) {
+            table.add_index(index);
+        }
+    }
+
+    let mut db = ADB::new();
+    for (_, table) in tables {
+        db.replace_table(table);
+    }
+    Ok(db)
+}
+
+/// The rows of `information_schema.statistics` describing one index,
+/// with the name of each of its columns in order.
+struct IndexRows {
+    table: String,
+    name: String,
+    comment: String,
+    columns: Vec<Option<String>>,
+}
+
+/// The indexes of columns which are not unique, as butane declares
+/// them, each with the name of its table and whether it carries
+/// [MANAGED_MARKER]. These include the indexes MySQL creates for
+/// foreign keys.
+fn introspect_indexes(client: &mut impl Queryable) -> Result<Vec<(String, AIndex, bool)>> {
+    let rows: Vec<(String, String, Option<String>, String)> = client.query(
+        "SELECT table_name, index_name, column_name, index_comment FROM information_schema.statistics \
+         WHERE table_schema = DATABASE() AND non_unique = 1 \
+         ORDER BY table_name, index_name, seq_in_index;",
+    )?;
+    let mut indexes: Vec<IndexRows> = Vec::new();
+    for (table, name, column, comment) in rows {
+        match indexes.last_mut() {
+            Some(last) if last.table == table && last.name == name && last.comment == comment => {
+                last.columns.push(column)
+            }
+            _ => indexes.push(IndexRows {
+                table,
+                name,
+                comment,
+                columns: vec![column],
+            }),
+        }
+    }
+    Ok(indexes
+        .into_iter()
+        .filter_map(|index| {
+            // Indexes of expressions have no column name, and are not
+            // declared by butane
+            let columns: Option<Vec<String>> = index.columns.into_iter().collect();
+            let managed = index.comment == MANAGED_MARKER;
+            Some((index.table, AIndex::new(columns?), managed))
+        })
+        .collect())
+}
+
+/// The type butane would have declared as the MySQL column type `sql`.
+fn sqltype_from_sql(sql: &str) -> TypeIdentifier {
+    match sql.to_lowercase().as_str() {
+        "tinyint(1)" => TypeIdentifier::Ty(SqlType::Bool),
+        "int" | "int(11)" => TypeIdentifier::Ty(SqlType::Int),
+        "bigint" | "bigint(20)" => TypeIdentifier::Ty(SqlType::BigInt),
+        "double" => TypeIdentifier::Ty(SqlType::Real),
+        "text" => TypeIdentifier::Ty(SqlType::Text),
+        #[cfg(feature = "datetime")]
+        "datetime(6)" => TypeIdentifier::Ty(SqlType::Timestamp),
+        "longblob" => TypeIdentifier::Ty(SqlType::Blob),
+        _ => TypeIdentifier::Name(sql.to_string()),
+    }
+}
+
+/// Shared functionality between connection and transaction: a MySQL
+/// connection or transaction on one.
+struct MySqlClient<C> {
+    conn: RefCell<C>,
+    logger: Option<QueryLogger>,
+}
+impl<C> MySqlClient<C> {
+    fn new(conn: C, logger: Option<QueryLogger>) -> Self {
+        MySqlClient {
+            conn: RefCell::new(conn),
+            logger,
+        }
+    }
+    /// Run `f`, which runs `sql` with `values`, logging it.
+    fn logged<'v, T>(
+        &self,
+        sql: &str,
+        values: impl IntoIterator<Item = SqlValRef<'v>>,
+        f: impl FnOnce() -> Result<T>,
+    ) -> Result<T> {
+        logging::log(self.logger.as_ref(), sql, values, f)
+    }
+}
+
+/// A MySQL connection, or a transaction on one.
+trait MySqlQueryable: Queryable {
+    /// The statements beginning, committing and rolling back a batch of
+    /// statements, nested within the transaction if any.
+    const BATCH: [&'static str; 3];
+}
+impl MySqlQueryable for ::mysql::Conn {
+    const BATCH: [&'static str; 3] = ["START TRANSACTION", "COMMIT", "ROLLBACK"];
+}
+impl MySqlQueryable for ::mysql::Transaction<'_> {
+    const BATCH: [&'static str; 3] = [
+        "SAVEPOINT butane_batch",
+        "RELEASE SAVEPOINT butane_batch",
+        "ROLLBACK TO SAVEPOINT butane_batch",
+    ];
+}
+
+impl<C: MySqlQueryable> MySqlClient<C> {
+    /// Run the statement `sql` with `values`, returning the number of
+    /// rows affected.
+    fn run(&self, sql: &str, values: &[SqlValRef<'_>]) -> Result<u64> {
+        self.logged(sql, values.iter().cloned(), || {
+            let mut client = self.conn.try_borrow_mut()?;
+            let result = client.exec_iter(sql, params(values.iter().cloned()))?;
+            Ok(result.affected_rows())
+        })
+    }
+}
+
+impl<C: MySqlQueryable> ConnectionMethods for MySqlClient<C> {
+    fn execute(&self, sql: &str) -> Result<()> {
+        if cfg!(feature = "log") {
+            debug!("execute sql {}", sql);
+        }
+        self.logged(sql, [], || {
+            Ok(self.conn.try_borrow_mut()?.query_drop(sql)?)
+        })
+    }
+
+    fn query<'a, 'b, 'c: 'a>(
+        &'c self,
+        table: &str,
+        columns: &'b [Column],
+        expr: Option<BoolExpr>,
+        limit: Option<i32>,
+        offset: Option<i32>,
+        order: Option<&[query::Order]>,
+    ) -> Result<RawQueryResult<'a>> {
+        let (sqlquery, values) = query_sql(table, columns, None, expr, limit, offset, order);
+        if cfg!(feature = "log") {
+            debug!("query sql {}", sqlquery);
+        }
+
+        let rows: Vec<::mysql::Row> =
+            self.logged(&sqlquery, values.iter().map(SqlVal::as_ref), || {
+                Ok(self
+                    .conn
+                    .try_borrow_mut()?
+                    .exec(sqlquery.as_str(), params(values.iter().map(SqlVal::as_ref)))?)
+            })?;
+        for row in &rows {
+            check_columns(row, columns)?;
+        }
+        Ok(Box::new(VecRows::new(rows)))
+    }
+    fn query_distinct<'a, 'b, 'c: 'a>(
+        &'c self,
+        table: &str,
+        columns: &'b [Column],
+        distinct: &query::Distinct,
+        expr: Option<BoolExpr>,
+        limit: Option<i32>,
+        offset: Option<i32>,
+        order: Option<&[query::Order]>,
+    ) -> Result<RawQueryResult<'a>> {
+        if let query::Distinct::On(_) = distinct {
+            return Err(Error::DistinctOnUnsupported(BACKEND_NAME));
+        }
+        let (sqlquery, values) =
+            query_sql(table, columns, Some(distinct), expr, limit, offset, order);
+        if cfg!(feature = "log") {
+            debug!("query sql {}", sqlquery);
+        }
+
+        let rows: Vec<::mysql::Row> =
+            self.logged(&sqlquery, values.iter().map(SqlVal::as_ref), || {
+                Ok(self
+                    .conn
+                    .try_borrow_mut()?
+                    .exec(sqlquery.as_str(), params(values.iter().map(SqlVal::as_ref)))?)
+            })?;
+        for row in &rows {
+            check_columns(row, columns)?;
+        }
+        Ok(Box::new(VecRows::new(rows)))
+    }
+    fn aggregate(
+        &self,
+        table: &str,
+        groups: &[Column],
+        aggregates: &[query::AggregateExpr],
+        expr: Option<BoolExpr>,
+        having: Option<query::Having>,
+    ) -> Result<Vec<Vec<SqlVal>>> {
+        let mut sqlquery = String::new();
+        let mut values: Vec<SqlVal> = Vec::new();
+        helper::sql_select_aggregates(
+            groups,
+            aggregates,
+            table,
+            sql_for_expr,
+            &mut values,
+            &mut MySqlPlaceholderSource,
+            &mut sqlquery,
+            q,
+            aggregate_cast,
+        );
+        if let Some(expr) = expr {
+            sqlquery.write_str(" WHERE ").unwrap();
+            sql_for_expr(
+                query::Expr::Condition(Box::new(expr)),
+                &mut values,
+                &mut MySqlPlaceholderSource,
+                &mut sqlquery,
+            );
+        }
+        helper::sql_group_by(
+            groups,
+            having,
+            sql_for_expr,
+            &mut values,
+            &mut MySqlPlaceholderSource,
+            &mut sqlquery,
+            q,
+        );
+        if cfg!(feature = "log") {
+            debug!("aggregate sql {}", sqlquery);
+        }
+
+        let rows: Vec<::mysql::Row> =
+            self.logged(&sqlquery, values.iter().map(SqlVal::as_ref), || {
+                Ok(self
+                    .conn
+                    .try_borrow_mut()?
+                    .exec(sqlquery.as_str(), params(values.iter().map(SqlVal::as_ref)))?)
+            })?;
+        helper::aggregate_rows(Box::new(VecRows::new(rows)), groups, aggregates)
+    }
+    fn query_raw_rows<'a, 'c: 'a>(
+        &'c self,
+        sql: &str,
+        params: &[SqlVal],
+    ) -> Result<RawQueryResult<'a>> {
+        let sqlquery = helper::sql_raw(sql, params, &mut MySqlPlaceholderSource)?;
+        if cfg!(feature = "log") {
+            debug!("query sql {}", sqlquery);
+        }
+
+        let rows: Vec<::mysql::Row> =
+            self.logged(&sqlquery, params.iter().map(SqlVal::as_ref), || {
+                Ok(self.conn.try_borrow_mut()?.exec(
+                    sqlquery.as_str(),
+                    self::params(params.iter().map(SqlVal::as_ref)),
+                )?)
+            })?;
+        Ok(Box::new(VecRows::new(rows)))
+    }
+    fn insert_returning_pk(
+        &self,
+        table: &str,
+        columns: &[Column],
+        pkcol: &Column,
+        values: &[SqlValRef<'_>],
+    ) -> Result<SqlVal> {
+        let sql = sql_insert_with_placeholders(table, columns);
+        if cfg!(feature = "log") {
+            debug!("insert sql {}", sql);
+        }
+        let id = self.logged(&sql, values.iter().cloned(), || {
+            let mut client = self.conn.try_borrow_mut()?;
+            let result = client.exec_iter(sql.as_str(), params(values.iter().cloned()))?;
+            Ok(result.last_insert_id())
+        })?;
+        // The key is generated only if it was not given
+        if let Some(idx) = columns.iter().position(|c| c.name() == pkcol.name()) {
+            return Ok(values[idx].clone().into());
+        }
+        match (id, pkcol.ty()) {
+            (Some(id), SqlType::Int) => Ok(SqlVal::Int(id as i32)),
+            (Some(id), _) => Ok(SqlVal::BigInt(id as i64)),
+            (None, _) => Err(Error::Internal("could not get pk".to_string())),
+        }
+    }
+    fn insert_only(&self, table: &str, columns: &[Column], values: &[SqlValRef<'_>]) -> Result<()> {
+        let sql = sql_insert_with_placeholders(table, columns);
+        self.run(&sql, values)?;
+        Ok(())
+    }
+    fn insert_only_many(
+        &self,
+        table: &str,
+        columns: &[Column],
+        rows: &[Vec<SqlValRef<'_>>],
+    ) -> Result<()> {
+        let sql = sql_insert_with_placeholders(table, columns);
+        self.execute_batch(&helper::statements_for_rows(&sql, rows))
+    }
+    fn insert_or_replace(
+        &self,
+        table: &str,
+        columns: &[Column],
+        _pkcol: &Column,
+        values: &[SqlValRef<'_>],
+    ) -> Result<()> {
+        let mut sql = sql_insert_with_placeholders(table, columns);
+        // Conflicts on any unique key update the row, not only on the
+        // primary key
+        write!(&mut sql, " ON DUPLICATE KEY UPDATE ").unwrap();
+        columns.iter().fold("", |sep, c| {
+            write!(&mut sql, "{}{} = VALUES({})", sep, q(c.name()), q(c.name())).unwrap();
+            ", "
+        });
+        self.run(&sql, values)?;
+        Ok(())
+    }
+    fn update(
+        &self,
+        table: &str,
+        pkcol: Column,
+        pk: SqlValRef,
+        columns: &[Column],
+        values: &[SqlValRef<'_>],
+    ) -> Result<()> {
+        let mut sql = String::new();
+        helper::sql_update_with_placeholders(
+            table,
+            pkcol,
+            columns,
+            &mut MySqlPlaceholderSource,
+            &mut sql,
+            q,
+        );
+        if cfg!(feature = "log") {
+            debug!("update sql {}", sql);
+        }
+        let values = [values, &[pk]].concat();
+        self.run(&sql, &values)?;
+        Ok(())
+    }
+    fn delete_where(&self, table: &str, expr: BoolExpr) -> Result<usize> {
+        let mut sql = String::new();
+        let mut values: Vec<SqlVal> = Vec::new();
+        write!(&mut sql, "DELETE FROM {} WHERE ", q(table)).unwrap();
+        sql_for_expr(
+            query::Expr::Condition(Box::new(expr)),
+            &mut values,
+            &mut MySqlPlaceholderSource,
+            &mut sql,
+        );
+        let values: Vec<SqlValRef<'_>> = values.iter().map(SqlVal::as_ref).collect();
+        Ok(self.run(&sql, &values)? as usize)
+    }
+    fn update_where(
+        &self,
+        table: &str,
+        assignments: &[query::Assignment],
+        expr: BoolExpr,
+    ) -> Result<usize> {
+        let mut sql = String::new();
+        let mut values: Vec<SqlVal> = Vec::new();
+        helper::sql_update_set(
+            table,
+            assignments,
+            &mut values,
+            &mut MySqlPlaceholderSource,
+            &mut sql,
+            q,
+        );
+        sql.push_str(" WHERE ");
+        sql_for_expr(
+            query::Expr::Condition(Box::new(expr)),
+            &mut values,
+            &mut MySqlPlaceholderSource,
+            &mut sql,
+        );
+        if cfg!(feature = "log") {
+            debug!("update sql {}", sql);
+        }
+        let values: Vec<SqlValRef<'_>> = values.iter().map(SqlVal::as_ref).collect();
+        Ok(self.run(&sql, &values)? as usize)
+    }
+    fn has_table(&self, table: &str) -> Result<bool> {
+        let sql = "SELECT 1 FROM information_schema.tables \
+                   WHERE table_schema = DATABASE() AND table_name = ?;";
+        let found: Option<i32> = self.logged(sql, [SqlValRef::Text(table)], || {
+            Ok(self.conn.try_borrow_mut()?.exec_first(sql, (table,))?)
+        })?;
+        Ok(found.is_some())
+    }
+    fn execute_batch(&self, statements: &[Statement]) -> Result<()> {
+        let [begin, commit, rollback] = C::BATCH;
+        let mut conn = self.conn.try_borrow_mut()?;
+        conn.query_drop(begin)?;
+        let result = statements.iter().try_for_each(|statement| {
+            if cfg!(feature = "log") {
+                debug!("batch sql {}", statement.sql);
+            }
+            self.logged(
+                &statement.sql,
+                statement.values.iter().map(SqlVal::as_ref),
+                || {
+                    Ok(conn.exec_drop(
+                        statement.sql.as_str(),
+                        params(statement.values.iter().map(SqlVal::as_ref)),
+                    )?)
+                },
+            )
+        });
+        match result {
+            Ok(()) => conn.query_drop(commit)?,
+            Err(_) => conn.query_drop(rollback)?,
+        }
+        result
+    }
+}
+
+struct MySqlTransaction<'c> {
+    trans: Option<MySqlClient<::mysql::Transaction<'c>>>,
+}
+impl<'c> MySqlTransaction<'c> {
+    fn new(trans: ::mysql::Transaction<'c>, logger: Option<QueryLogger>) -> Self {
+        MySqlTransaction {
+            trans: Some(MySqlClient::new(trans, logger)),
+        }
+    }
+    fn get(&self) -> Result<&MySqlClient<::mysql::Transaction<'c>>> {
+        match &self.trans {
+            None => Err(Self::already_consumed()),
+            Some(trans) => Ok(trans),
+        }
+    }
+    fn wrapped_connection_methods(&self) -> Result<&MySqlClient<::mysql::Transaction<'c>>> {
+        self.get()
+    }
+    fn already_consumed() -> Error {
+        Error::Internal("transaction has already been consumed".to_string())
+    }
+}
+connection_method_wrapper!(MySqlTransaction<'_>);
+impl<'c> BackendTransaction<'c> for MySqlTransaction<'c> {
+    fn commit(&mut self) -> Result<()> {
+        match self.trans.take() {
+            None => Err(Self::already_consumed()),
+            Some(trans) => Ok(trans.conn.into_inner().commit()?),
+        }
+    }
+    fn rollback(&mut self) -> Result<()> {
+        match self.trans.take() {
+            None => Err(Self::already_consumed()),
+            Some(trans) => Ok(trans.conn.into_inner().rollback()?),
+        }
+    }
+    fn prepare(&mut self, _gid: &str) -> Result<()> {
+        Err(Error::TwoPhaseUnsupported(BACKEND_NAME))
+    }
+    fn savepoint(&mut self) -> Result<Box<dyn BackendTransaction<'_> + '_>> {
+        Ok(Box::new(MySqlSavepoint::new(self.get()?, 1)?))
+    }
+    // Workaround for https://github.com/rust-lang/rfcs/issues/2765
+    fn connection_methods(&self) -> &dyn ConnectionMethods {
+        self
+    }
+    fn connection_methods_mut(&mut self) -> &mut dyn ConnectionMethods {
+        self
+    }
+}
+
+/// A savepoint within a transaction, named by its depth. The mysql
+/// crate has no savepoints, so they are managed here, rolling back
+/// when dropped as its transactions do.
+struct MySqlSavepoint<'a, 'c> {
+    client: &'a MySqlClient<::mysql::Transaction<'c>>,
+    depth: usize,
+    open: bool,
+}
+impl<'a, 'c> MySqlSavepoint<'a, 'c> {
+    fn new(client: &'a MySqlClient<::mysql::Transaction<'c>>, depth: usize) -> Result<Self> {
+        let savepoint = MySqlSavepoint {
+            client,
+            depth,
+            open: false,
+        };
+        savepoint.run("SAVEPOINT")?;
+        Ok(MySqlSavepoint {
+            open: true,
+            ..savepoint
+        })
+    }
+    /// Run `command` on this savepoint.
+    fn run(&self, command: &str) -> Result<()> {
+        let sql = format!("{} butane_savepoint_{}", command, self.depth);
+        if cfg!(feature = "log") {
+            debug!("execute sql {}", sql);
+        }
+        self.client.conn.try_borrow_mut()?.query_drop(sql)?;
+        Ok(())
+    }
+    /// Run `command`, ending this savepoint.
+    fn end(&mut self, command: &str) -> Result<()> {
+        if !self.open {
+            return Err(MySqlTransaction::already_consumed());
+        }
+        self.open = false;
+        self.run(command)
+    }
+    fn wrapped_connection_methods(&self) -> Result<&MySqlClient<::mysql::Transaction<'c>>> {
+        if !self.open {
+            return Err(MySqlTransaction::already_consumed());
+        }
+        Ok(self.client)
+    }
+}
+connection_method_wrapper!(MySqlSavepoint<'_, '_>);
+impl<'a, 'c> BackendTransaction<'a> for MySqlSavepoint<'a, 'c> {
+    fn commit(&mut self) -> Result<()> {
+        self.end("RELEASE SAVEPOINT")
+    }
+    fn rollback(&mut self) -> Result<()> {
+        self.end("ROLLBACK TO SAVEPOINT")?;
+        // Rolling back leaves the savepoint, so it is then released
+        self.run("RELEASE SAVEPOINT")
+    }
+    fn prepare(&mut self, _gid: &str) -> Result<()> {
+        Err(Error::TwoPhaseUnsupported(BACKEND_NAME))
+    }
+    fn savepoint(&mut self) -> Result<Box<dyn BackendTransaction<'_> + '_>> {
+        Ok(Box::new(MySqlSavepoint::new(
+            self.wrapped_connection_methods()?,
+            self.depth + 1,
+        )?))
+    }
+    // Workaround for https://github.com/rust-lang/rfcs/issues/2765
+    fn connection_methods(&self) -> &dyn ConnectionMethods {
+        self
+    }
+    fn connection_methods_mut(&mut self) -> &mut dyn ConnectionMethods {
+        self
+    }
+}
+impl Drop for MySqlSavepoint<'_, '_> {
+    fn drop(&mut self) {
+        if self.open {
+            // Errors cannot be returned from drop, and leave the
+            // transaction to be rolled back as a whole.
+            let _ = BackendTransaction::rollback(self);
+        }
+    }
+}
+
+/// `values` as the parameters of a statement.
+fn params<'a>(values: impl Iterator<Item = SqlValRef<'a>>) -> ::mysql::Params {
+    let values: Vec<::mysql::Value> = values.map(|v| mysql_value(&v)).collect();
+    if values.is_empty() {
+        ::mysql::Params::Empty
+    } else {
+        ::mysql::Params::Positional(values)
+    }
+}
+
+fn mysql_value(valref: &SqlValRef<'_>) -> ::mysql::Value {
+    use ::mysql::Value;
+    use SqlValRef::*;
+    match valref {
+        Bool(b) => Value::Int(*b as i64),
+        Int(i) => Value::Int(*i as i64),
+        BigInt(i) => Value::Int(*i),
+        Real(r) => Value::Double(*r),
+        Text(t) => Value::Bytes(t.as_bytes().to_vec()),
+        Blob(b) => Value::Bytes(b.to_vec()),
+        #[cfg(feature = "datetime")]
+        Timestamp(dt) => Value::Date(
+            dt.year() as u16,
+            dt.month() as u8,
+            dt.day() as u8,
+            dt.hour() as u8,
+            dt.minute() as u8,
+            dt.second() as u8,
+            dt.nanosecond() / 1000,
+        ),
+        Null => Value::NULL,
+        Custom(_) => panic!("Custom types not supported by mysql backend"),
+    }
+}
+
+fn check_columns(row: &::mysql::Row, cols: &[Column]) -> Result<()> {
+    if cols.len() != row.len() {
+        Err(Error::Internal(format!(
+            "mysql returns columns {} doesn't match requested columns {}",
+            row.len(),
+            cols.len()
+        )))
+    } else {
+        Ok(())
+    }
+}
+
+impl BackendRow for ::mysql::Row {
+    fn get(&self, idx: usize, ty: SqlType) -> Result<SqlValRef<'_>> {
+        let val = self
+            .as_ref(idx)
+            .ok_or_else(|| Error::Internal(format!("no mysql column {}", idx)))?;
+        sql_valref_from_mysql(val, &ty).ok_or_else(|| Error::SqlResultTypeMismatch {
+            col: idx.to_string(),
+            detail: format!(
+                "{:?} is not compatible with expected column type {}",
+                val, ty
+            ),
+        })?
+    }
+    fn len(&self) -> usize {
+        ::mysql::Row::len(self)
+    }
+}
+
+/// The value of type `ty` in `val`, or `None` if `val` does not hold
+/// such a value.
+fn sql_valref_from_mysql<'a>(
+    val: &'a ::mysql::Value,
+    ty: &SqlType,
+) -> Option<Result<SqlValRef<'a>>> {
+    use ::mysql::Value;
+    let valref = match (ty, val) {
+        (_, Value::NULL) => SqlValRef::Null,
+        (SqlType::Bool, Value::Int(i)) => SqlValRef::Bool(*i != 0),
+        (SqlType::Int, Value::Int(i)) => match i32::try_from(*i) {
+            Ok(i) => SqlValRef::Int(i),
+            Err(_) => return Some(Err(Error::OutOfRange)),
+        },
+        (SqlType::BigInt, Value::Int(i)) => SqlValRef::BigInt(*i),
+        (SqlType::Real, Value::Double(r)) => SqlValRef::Real(*r),
+        (SqlType::Real, Value::Float(r)) => SqlValRef::Real(*r as f64),
+        // DECIMAL values are sent as text
+        (SqlType::Real, Value::Bytes(b)) => {
+            match std::str::from_utf8(b).ok().and_then(|s| s.parse().ok()) {
+                Some(r) => SqlValRef::Real(r),
+                None => return None,
+            }
+        }
+        (SqlType::Text, Value::Bytes(b)) => match std::str::from_utf8(b) {
+            Ok(text) => SqlValRef::Text(text),
+            Err(e) => return Some(Err(Error::Generic(Box::new(e)))),
+        },
+        (SqlType::Blob, Value::Bytes(b)) => SqlValRef::Blob(b),
+        #[cfg(feature = "datetime")]
+        (SqlType::Timestamp, Value::Date(year, month, day, hour, min, sec, micros)) => {
+            match NaiveDate::from_ymd_opt(*year as i32, *month as u32, *day as u32)
+                .and_then(|d| d.and_hms_micro_opt(*hour as u32, *min as u32, *sec as u32, *micros))
+            {
+                Some(dt) => SqlValRef::Timestamp(dt),
+                None => return Some(Err(Error::OutOfRange)),
+            }
+        }
+        (SqlType::Custom(v), _) => {
+            return Some(Err(Error::IncompatibleCustomT(v.clone(), BACKEND_NAME)))
+        }
+        _ => return None,
+    };
+    Some(Ok(valref))
+}
+
+/// Sums of integers are DECIMAL, sent as text, so they are cast to
+/// integers.
+fn aggregate_cast(aggregate: &query::AggregateExpr) -> Option<&'static str> {
+    match (aggregate.function, &aggregate.ty) {
+        (query::AggregateFunction::Sum, SqlType::BigInt) => Some("SIGNED"),
+        _ => None,
+    }
+}
+
+fn query_sql(
+    table: &str,
+    columns: &[Column],
+    distinct: Option<&query::Distinct>,
+    expr: Option<BoolExpr>,
+    limit: Option<i32>,
+    offset: Option<i32>,
+    order: Option<&[query::Order]>,
+) -> (String, Vec<SqlVal>) {
+    let mut sqlquery = String::new();
+    let mut values: Vec<SqlVal> = Vec::new();
+    let pls = &mut MySqlPlaceholderSource;
+    match distinct {
+        Some(distinct) => helper::sql_select_distinct(
+            columns,
+            distinct,
+            table,
+            sql_for_expr,
+            &mut values,
+            pls,
+            &mut sqlquery,
+            q,
+        ),
+        None => helper::sql_select(
+            columns,
+            table,
+            sql_for_expr,
+            &mut values,
+            pls,
+            &mut sqlquery,
+            q,
+        ),
+    }
+    if let Some(expr) = expr {
+        sqlquery.write_str(" WHERE ").unwrap();
+        sql_for_expr(
+            query::Expr::Condition(Box::new(expr)),
+            &mut values,
+            &mut MySqlPlaceholderSource,
+            &mut sqlquery,
+        );
+    }
+
+    if let Some(order) = order {
+        helper::sql_order_emulating_nulls(order, &mut sqlquery, q)
+    }
+
+    if let Some(limit) = limit {
+        helper::sql_limit(limit, &mut sqlquery)
+    } else if offset.is_some() {
+        // MySQL has no OFFSET without LIMIT
+        write!(&mut sqlquery, " LIMIT {}", u64::MAX).unwrap();
+    }
+
+    if let Some(offset) = offset {
+        helper::sql_offset(offset, &mut sqlquery)
+    }
+
+    (sqlquery, values)
+}
+
+fn sql_for_expr<W>(
+    expr: query::Expr,
+    values: &mut Vec<SqlVal>,
+    pls: &mut MySqlPlaceholderSource,
+    w: &mut W,
+) where
+    W: Write,
+{
+    let expr = match expr {
+        query::Expr::Date(date) => {
+            let column = q(date.column);
+            match date.function {
+                query::DateFunction::Extract(part) => {
+                    write!(w, "EXTRACT({} FROM {})", part.sql_name(), column).unwrap()
+                }
+                query::DateFunction::Trunc(part) => write!(
+                    w,
+                    "CAST(DATE_FORMAT({}, '{}') AS DATETIME(6))",
+                    column,
+                    trunc_format(part)
+                )
+                .unwrap(),
+            }
+            return;
+        }
+        query::Expr::Condition(c) => match *c {
+            query::BoolExpr::JsonEq { col, path, value } => {
+                write!(w, "JSON_EXTRACT({}, ", q(col)).unwrap();
+                let path = SqlVal::Text(query::json_path(&path));
+                sql_for_expr(query::Expr::Val(path), values, pls, w);
+                write!(w, ") = CAST(").unwrap();
+                sql_for_expr(
+                    query::Expr::Val(SqlVal::Text(value.to_string())),
+                    values,
+                    pls,
+                    w,
+                );
+                write!(w, " AS JSON)").unwrap();
+                return;
+            }
+            query::BoolExpr::JsonContains { col, path, value } => {
+                write!(w, "JSON_CONTAINS({}, ", q(col)).unwrap();
+                // The candidate comes before the path
+                let path = SqlVal::Text(query::json_path(&path));
+                sql_for_expr(
+                    query::Expr::Val(SqlVal::Text(value.to_string())),
+                    values,
+                    pls,
+                    w,
+                );
+                write!(w, ", ").unwrap();
+                sql_for_expr(query::Expr::Val(path), values, pls, w);
+                write!(w, ")").unwrap();
+                return;
+            }
+            c => query::Expr::Condition(Box::new(c)),
+        },
+        expr => expr,
+    };
+    helper::sql_for_expr(expr, sql_for_expr, values, pls, w, q)
+}
+
+/// The format of `DATE_FORMAT` for the start of the `part` of a time.
+fn trunc_format(part: query::DatePart) -> &'static str {
+    match part {
+        query::DatePart::Year => "%Y-01-01 00:00:00",
+        query::DatePart::Month => "%Y-%m-01 00:00:00",
+        query::DatePart::Day => "%Y-%m-%d 00:00:00",
+        query::DatePart::Hour => "%Y-%m-%d %H:00:00",
+        query::DatePart::Minute => "%Y-%m-%d %H:%i:00",
+        query::DatePart::Second => "%Y-%m-%d %H:%i:%s",
+    }
+}
+
+/// Quotes an identifier, as MySQL reserves many words which make
+/// natural table and column names.
+fn q(name: &str) -> Cow<'_, str> {
+    Cow::Owned(format!("`{}`", name.replace('`', "``")))
+}
+
+/// MySQL has no `DEFAULT VALUES`, an empty list of columns is used instead.
+fn sql_insert_with_placeholders(table: &str, columns: &[Column]) -> String {
+    let mut sql = String::new();
+    if columns.is_empty() {
+        write!(&mut sql, "INSERT INTO {} () VALUES ()", q(table)).unwrap();
+    } else {
+        helper::sql_insert_with_placeholders(
+            table,
+            columns,
+            &mut MySqlPlaceholderSource,
+            &mut sql,
+            q,
+        );
+    }
+    sql
+}
+
+/// Like [helper::sql_literal_value], but escaping backslashes, which
+/// MySQL treats as escape characters in strings.
+fn sql_literal_value(val: SqlVal) -> Result<String> {
+    match val {
+        SqlVal::Text(text) => Ok(format!(
+            "'{}'",
+            text.replace('\\', "\\\\").replace('\'', "''")
+        )),
+        val => helper::sql_literal_value(val),
+    }
+}
+
+fn migration_sql(current: &ADB, ops: Vec<Operation>, idempotent: bool) -> Result<String> {
+    let mut current: ADB = (*current).clone();
+    Ok(ops
+        .into_iter()
+        .map(|o| {
+            let sql = sql_for_op(&mut current, &o, idempotent);
+            current.transform_with(o);
+            sql
+        })
+        .collect::<Result<Vec<String>>>()?
+        .join("\n"))
+}
+
+fn sql_for_op(current: &mut ADB, op: &Operation, idempotent: bool) -> Result<String> {
+    match op {
+        Operation::AddTable(table) => {
+            check_auto(table)?;
+            create_table_and_indexes(table, current, idempotent)
+        }
+        Operation::AddTableIfNotExists(table) => {
+            check_auto(table)?;
+            create_table_and_indexes(table, current, true)
+        }
+        Operation::RemoveTable(name) => Ok(drop_table(name, idempotent)),
+        Operation::AddColumn(tbl, col) => add_column(current, tbl, col),
+        Operation::RemoveColumn(tbl, name) => Ok(remove_column(current, tbl, name)),
+        Operation::ChangeColumn(tbl, old, new) => change_column(current, tbl, old, new),
+        Operation::ChangeColumnDefault(tbl, name, default) => {
+            change_column_default(current, tbl, name, default)
+        }
+        Operation::ChangeColumnDefaultExpr(tbl, name, expr) => {
+            Ok(change_column_default_expr(tbl, name, expr.as_deref()))
+        }
+        Operation::AddUniqueConstraint(tbl, constraint) => retyping_keys(
+            current,
+            tbl,
+            |table| table.add_unique_constraint(constraint.clone()),
+            format!(
+                "ALTER TABLE {} ADD {};",
+                q(tbl),
+                define_unique_constraint(tbl, constraint)
+            ),
+        ),
+        Operation::RemoveUniqueConstraint(tbl, constraint) => retyping_keys(
+            current,
+            tbl,
+            |table| table.remove_unique_constraint(constraint),
+            drop_index(tbl, &constraint.name(tbl)),
+        ),
+        Operation::AddCheck(tbl, name, expr) => Ok(format!(
+            "ALTER TABLE {} ADD {};",
+            q(tbl),
+            define_check(&ACheckConstraint::new(name, expr))
+        )),
+        Operation::RemoveCheck(tbl, name) => Ok(format!(
+            "ALTER TABLE {} DROP CONSTRAINT {};",
+            q(tbl),
+            q(name)
+        )),
+        Operation::AddIndex(tbl, index) => retyping_keys(
+            current,
+            tbl,
+            |table| table.add_index(index.clone()),
+            create_index(tbl, index),
+        ),
+        Operation::RemoveIndex(tbl, index) => retyping_keys(
+            current,
+            tbl,
+            |table| table.remove_index(index),
+            drop_index(tbl, &index.name(tbl)),
+        ),
+        Operation::AddForeignKey(tbl, column, reference) => {
+            let add = format!(
+                "ALTER TABLE {} ADD {};",
+                q(tbl),
+                define_foreign_key(tbl, column, reference, current, None)?
+            );
+            retyping_keys(
+                current,
+                tbl,
+                |table| {
+                    if let Some(col) = table.columns.iter_mut().find(|c| c.name() == column) {
+                        col.set_reference(Some(reference.clone()));
+                    }
+                },
+                add,
+            )
+        }
+        Operation::RemoveForeignKey(tbl, column) => retyping_keys(
+            current,
+            tbl,
+            |table| {
+                if let Some(col) = table.columns.iter_mut().find(|c| c.name() == column) {
+                    col.set_reference(None);
+                }
+            },
+            drop_foreign_key(tbl, column),
+        ),
+        Operation::SetNotNull(tbl, name, backfill) => {
+            set_not_null(current, tbl, name, backfill.as_ref())
+        }
+        Operation::RenameTable(old, new) => Ok(rename_table(current, old, new)),
+        Operation::RenameColumn(tbl, old, new) => Ok(rename_column(current, tbl, old, new)),
+        // Schemas are databases in MySQL, tables are kept in the
+        // database connected to
+        Operation::SetTableSchema(..) => Ok(String::new()),
+        Operation::SetTableComment(tbl, comment) => Ok(format!(
+            "ALTER TABLE {} COMMENT = {};",
+            q(tbl),
+            sql_literal_value(SqlVal::Text(managed_comment(comment.as_deref())))?
+        )),
+        Operation::SetColumnComment(tbl, name, comment) => {
+            modify_column(current, tbl, name, |col| col.set_comment(comment.clone()))
+        }
+        Operation::AddView(view) => Ok(create_view(view, idempotent)),
+        Operation::RemoveView(name) => Ok(drop_view(name, idempotent)),
+        Operation::ReplaceView(view) => Ok(create_view(view, true)),
+        // MySQL enums belong to a column, enum values are stored as text
+        Operation::AddEnum(_) | Operation::RemoveEnum(_) | Operation::AddEnumValue(..) => {
+            Ok(String::new())
+        }
+    }
+}
+
+/// MySQL requires an `AUTO_INCREMENT` column to be a key. Only a lone
+/// primary key is supported, as on SQLite.
+fn check_auto(table: &ATable) -> Result<()> {
+    let lone_pk = table.pk().len() == 1;
+    match table
+        .columns
+        .iter()
+        .find(|col| col.is_auto() && !(col.is_pk() && lone_pk))
+    {
+        Some(col) => Err(Error::UnsupportedAuto(
+            BACKEND_NAME,
+            format!("{}.{}", table.name, col.name()),
+        )),
+        None => Ok(()),
+    }
+}
+
+/// Whether the column `name` of `table` is part of a key or index, and
+/// so cannot be `TEXT`.
+fn is_key(table: &ATable, name: &str) -> bool {
+    let contains = |columns: &[String]| columns.iter().any(|c| c == name);
+    table
+        .column(name)
+        .is_some_and(|c| c.is_pk() || c.unique() || c.reference().is_some())
+        || table
+            .unique_constraints
+            .iter()
+            .any(|c| contains(c.columns()))
+        || table.indexes.iter().any(|i| contains(i.columns()))
+}
+
+/// Runs `sql`, which changes `tbl_name` as `change` does, changing the
+/// type of text columns which become keys before it and of those which
+/// stop being keys after it.
+fn retyping_keys(
+    current: &ADB,
+    tbl_name: &str,
+    change: impl FnOnce(&mut ATable),
+    sql: String,
+) -> Result<String> {
+    let table = match current.get_table(tbl_name) {
+        Some(table) => table,
+        None => return Ok(sql),
+    };
+    let mut changed = table.clone();
+    change(&mut changed);
+    let mut before: Vec<String> = Vec::new();
+    let mut after: Vec<String> = Vec::new();
+    for (old, new) in table.columns.iter().zip(&changed.columns) {
+        if col_sqltype(old, table)? != col_sqltype(new, &changed)? {
+            let modify = format!(
+                "ALTER TABLE {} MODIFY COLUMN {};",
+                q(tbl_name),
+                define_column(new, &changed, false)?
+            );
+            if is_key(&changed, new.name()) {
+                before.push(modify);
+            } else {
+                after.push(modify);
+            }
+        }
+    }
+    Ok(before
+        .into_iter()
+        .chain(std::iter::once(sql))
+        .chain(after)
+        .collect::<Vec<String>>()
+        .join("\n"))
+}
+
+fn create_table(table: &ATable, db: &ADB, allow_exists: bool) -> Result<String> {
+    let coldefs = table
+        .columns
+        .iter()
+        .map(|col| define_column(col, table, true))
+        .chain(helper::sql_composite_pk(table, q).map(Ok))
+        .chain(
+            table
+                .unique_constraints
+                .iter()
+                .map(|c| Ok(define_unique_constraint(&table.name, c))),
+        )
+        .chain(table.columns.iter().filter_map(|col| {
+            col.reference()
+                .map(|r| define_foreign_key(&table.name, col.name(), r, db, Some(table)))
+        }))
+        .chain(table.checks.iter().map(|c| Ok(define_check(c))))
+        .collect::<Result<Vec<String>>>()?
+        .join(",\n");
+    let modifier = if allow_exists { "IF NOT EXISTS " } else { "" };
+    Ok(format!(
+        "CREATE TABLE {}{} (\n{}\n) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COMMENT={};",
+        modifier,
+        q(&table.name),
+        coldefs,
+        sql_literal_value(SqlVal::Text(managed_comment(table.comment.as_deref())))?
+    ))
+}
+
+/// The comment stored for a table documented with `comment`, ending
+/// with [MANAGED_MARKER] on its own line.
+fn managed_comment(comment: Option<&str>) -> String {
+    match comment {
+        Some(comment) => format!("{}\n{}", comment, MANAGED_MARKER),
+        None => MANAGED_MARKER.to_string(),
+    }
+}
+
+fn create_table_and_indexes(table: &ATable, db: &ADB, allow_exists: bool) -> Result<String> {
+    Ok(std::iter::once(create_table(table, db, allow_exists)?)
+        .chain(
+            table
+                .indexes
+                .iter()
+                .map(|index| create_index(&table.name, index)),
+        )
+        .collect::<Vec<String>>()
+        .join("\n"))
+}
+
+/// MySQL has no `CREATE INDEX IF NOT EXISTS`, nor partial indexes, so
+/// the index is created on all rows.
+fn create_index(tbl_name: &str, index: &AIndex) -> String {
+    if index.predicate().is_some() {
+        crate::warn!(
+            "MySQL does not support partial indexes, {} is created on all rows",
+            index.name(tbl_name)
+        );
+    }
+    format!(
+        "CREATE INDEX {} ON {} ({}) COMMENT '{}';",
+        q(&index.name(tbl_name)),
+        q(tbl_name),
+        index
+            .columns()
+            .iter()
+            .map(|c| q(c))
+            .collect::<Vec<_>>()
+            .join(", "),
+        MANAGED_MARKER
+    )
+}
+
+/// Drops the index `name` of the table `tbl_name`, including that of a
+/// unique constraint.
+fn drop_index(tbl_name: &str, name: &str) -> String {
+    format!("ALTER TABLE {} DROP INDEX {};", q(tbl_name), q(name))
+}
+
+/// The definition of `col` of `table`. The column is declared the
+/// primary key or unique only if `keys`, as they are kept when the
+/// column is modified.
+fn define_column(col: &AColumn, table: &ATable, keys: bool) -> Result<String> {
+    let sqltype = col_sqltype(col, table)?;
+    let mut parts: Vec<String> = vec![q(col.name()).into_owned(), sqltype.to_string()];
+    if !col.nullable() {
+        parts.push("NOT NULL".to_string());
+    }
+    if col.is_auto() {
+        parts.push("AUTO_INCREMENT".to_string());
+    }
+    if keys && col.is_pk() && table.pk().len() == 1 {
+        parts.push("PRIMARY KEY".to_string());
+    }
+    if keys && col.unique() {
+        parts.push("UNIQUE".to_string());
+    }
+    if let Some(expr) = col.default_expr() {
+        parts.push(format!("DEFAULT ({})", expr));
+    } else if let Some(default) = col.default() {
+        parts.push(format!("DEFAULT {}", sql_default(default, &sqltype)?));
+    }
+    if let Some(comment) = col.comment() {
+        parts.push(format!(
+            "COMMENT {}",
+            sql_literal_value(SqlVal::Text(comment.to_string()))?
+        ));
+    }
+    Ok(parts.join(" "))
+}
+
+/// The default `value` of a column of type `sqltype`. MySQL only
+/// accepts defaults of `TEXT` and `BLOB` columns as expressions.
+fn sql_default(value: &SqlVal, sqltype: &str) -> Result<String> {
+    let literal = sql_literal_value(value.clone())?;
+    if sqltype == "TEXT" || sqltype == "LONGBLOB" {
+        Ok(format!("({})", literal))
+    } else {
+        Ok(literal)
+    }
+}
+
+fn define_unique_constraint(tbl_name: &str, constraint: &AUniqueConstraint) -> String {
+    format!(
+        "CONSTRAINT {} UNIQUE ({})",
+        q(&constraint.name(tbl_name)),
+        constraint
+            .columns()
+            .iter()
+            .map(|c| q(c))
+            .collect::<Vec<_>>()
+            .join(", ")
+    )
+}
+
+fn foreign_key_name(tbl_name: &str, column: &str) -> String {
+    format!("{}_{}_fkey", tbl_name, column)
+}
+
+/// The foreign key constraint of the column `column` of the table
+/// `tbl_name`. MySQL ignores `REFERENCES` in a column definition and
+/// requires the referenced column to be named, so the primary key of
+/// the referenced table is looked up in `db`, or in `creating` if that
+/// is the referenced table.
+fn define_foreign_key(
+    tbl_name: &str,
+    column: &str,
+    reference: &AForeignKey,
+    db: &ADB,
+    creating: Option<&ATable>,
+) -> Result<String> {
+    let reference = match reference.column() {
+        Some(_) => reference.clone(),
+        None => {
+            let table = creating
+                .filter(|t| t.name == reference.table())
+                .or_else(|| db.get_table(reference.table()))
+                .ok_or_else(|| {
+                    Error::Internal(format!(
+                        "{}.{} references unknown table {}",
+                        tbl_name,
+                        column,
+                        reference.table()
+                    ))
+                })?;
+            match table.pk() {
+                [pk] => reference.clone().with_column(pk.name()),
+                _ => return Err(Error::CompositeKeyReference(table.name.clone())),
+            }
+        }
+    };
+    Ok(format!(
+        "CONSTRAINT {} FOREIGN KEY ({}) {}",
+        q(&foreign_key_name(tbl_name, column)),
+        q(column),
+        helper::sql_references(&reference, &q(reference.table()), q)
+    ))
+}
+
+fn drop_foreign_key(tbl_name: &str, column: &str) -> String {
+    format!(
+        "ALTER TABLE {} DROP FOREIGN KEY {};",
+        q(tbl_name),
+        q(&foreign_key_name(tbl_name, column))
+    )
+}
+
+fn define_check(check: &ACheckConstraint) -> String {
+    format!("CONSTRAINT {} CHECK ({})", q(check.name()), check.expr())
+}
+
+/// The type of `col` of `table`. Text columns without a length are
+/// `TEXT`, unless they are keys, which are `VARCHAR(255)`.
+fn col_sqltype(col: &AColumn, table: &ATable) -> Result<Cow<'static, str>> {
+    let ty = match col.typeid()? {
+        TypeIdentifier::Name(_) | TypeIdentifier::Enum(_) if !col.params().is_empty() => {
+            return Err(Error::InvalidTypeParams(col.name().to_string()))
+        }
+        TypeIdentifier::Name(name) => return Ok(Cow::Owned(name)),
+        TypeIdentifier::Enum(_) => SqlType::Text,
+        TypeIdentifier::Ty(ty) => ty,
+    };
+    let params = col.params();
+    let valid_params = match ty {
+        _ if col.is_auto() => params.is_empty(),
+        SqlType::Text => params.precision.is_none() && params.scale.is_none(),
+        SqlType::Real => {
+            params.length.is_none() && (params.precision.is_some() || params.scale.is_none())
+        }
+        _ => params.is_empty(),
+    };
+    if !valid_params {
+        return Err(Error::InvalidTypeParams(col.name().to_string()));
+    }
+    if col.is_auto() {
+        return match ty {
+            SqlType::Int => Ok(Cow::Borrowed("INT")),
+            SqlType::BigInt => Ok(Cow::Borrowed("BIGINT")),
+            _ => Err(Error::InvalidAuto(col.name().to_string())),
+        };
+    }
+    Ok(match ty {
+        SqlType::Bool => Cow::Borrowed("BOOLEAN"),
+        SqlType::Int => Cow::Borrowed("INT"),
+        SqlType::BigInt => Cow::Borrowed("BIGINT"),
+        SqlType::Real => match params.precision {
+            Some(precision) => Cow::Owned(format!(
+                "DECIMAL({}, {})",
+                precision,
+                params.scale.unwrap_or(0)
+            )),
+            None => Cow::Borrowed("DOUBLE"),
+        },
+        SqlType::Text => match params.length {
+            Some(length) => Cow::Owned(format!("VARCHAR({})", length)),
+            None if is_key(table, col.name()) => {
+                Cow::Owned(format!("VARCHAR({})", KEY_TEXT_LENGTH))
+            }
+            None => Cow::Borrowed("TEXT"),
+        },
+        #[cfg(feature = "datetime")]
+        SqlType::Timestamp => Cow::Borrowed("DATETIME(6)"),
+        SqlType::Blob => Cow::Borrowed("LONGBLOB"),
+        SqlType::Custom(c) => return Err(Error::IncompatibleCustomT(c, BACKEND_NAME)),
+    })
+}
+
+fn drop_table(name: &str, idempotent: bool) -> String {
+    format!("DROP TABLE {}{};", if_exists(idempotent), q(name))
+}
+
+fn if_exists(idempotent: bool) -> &'static str {
+    if idempotent {
+        "IF EXISTS "
+    } else {
+        ""
+    }
+}
+
+/// Adds `col` to the table `tbl_name`. The existing rows of a `NOT
+/// NULL` column without a default are given the implicit default of
+/// its type, such as 0 or the empty string.
+fn add_column(current: &ADB, tbl_name: &str, col: &AColumn) -> Result<String> {
+    let mut table = current
+        .get_table(tbl_name)
+        .cloned()
+        .unwrap_or_else(|| ATable::new(tbl_name.to_string()));
+    table.add_column(col.clone());
+    check_auto(&table)?;
+    let mut sql = format!(
+        "ALTER TABLE {} ADD COLUMN {}",
+        q(tbl_name),
+        define_column(col, &table, true)?
+    );
+    if let Some(reference) = col.reference() {
+        write!(
+            sql,
+            ", ADD {}",
+            define_foreign_key(tbl_name, col.name(), reference, current, Some(&table))?
+        )
+        .unwrap();
+    }
+    sql.push(';');
+    Ok(sql)
+}
+
+fn remove_column(current: &ADB, tbl_name: &str, name: &str) -> String {
+    let drop = format!("ALTER TABLE {} DROP COLUMN {};", q(tbl_name), q(name));
+    let referencing = current
+        .get_table(tbl_name)
+        .and_then(|t| t.column(name))
+        .is_some_and(|c| c.reference().is_some());
+    // The foreign key must be dropped before its column
+    if referencing {
+        format!("{}\n{}", drop_foreign_key(tbl_name, name), drop)
+    } else {
+        drop
+    }
+}
+
+/// Alter the column `old` of the table `tbl_name` to match `new`. The
+/// whole column is redefined, so its type, nullability, default and
+/// comment are changed together.
+fn change_column(current: &ADB, tbl_name: &str, old: &AColumn, new: &AColumn) -> Result<String> {
+    let table = match current.get_table(tbl_name) {
+        Some(table) => table,
+        None => {
+            crate::warn!(
+                "Cannot alter column {} from table {} that does not exist",
+                &old.name(),
+                tbl_name
+            );
+            return Ok(String::new());
+        }
+    };
+    let mut new_table = table.clone();
+    new_table.replace_column(new.clone());
+    check_auto(&new_table)?;
+    let mut stmts: Vec<String> = Vec::new();
+    let reference_changed = old.reference() != new.reference();
+    if reference_changed && old.reference().is_some() {
+        stmts.push(drop_foreign_key(tbl_name, old.name()));
+    }
+    let mut changes: Vec<String> = Vec::new();
+    // MySQL names the index of a unique column after it
+    if old.unique() && !new.unique() {
+        changes.push(format!("DROP INDEX {}", q(old.name())));
+    }
+    changes.push(format!(
+        "MODIFY COLUMN {}",
+        define_column(new, &new_table, false)?
+    ));
+    // Made in the same statement, as an AUTO_INCREMENT column must
+    // always be a key
+    if old.is_pk() != new.is_pk() {
+        if !table.pk().is_empty() {
+            changes.push("DROP PRIMARY KEY".to_string());
+        }
+        let pk = new_table.pk();
+        if !pk.is_empty() {
+            changes.push(format!(
+                "ADD PRIMARY KEY ({})",
+                pk.iter()
+                    .map(|c| q(c.name()))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ));
+        }
+    }
+    if new.unique() && !old.unique() {
+        changes.push(format!(
+            "ADD UNIQUE INDEX {} ({})",
+            q(new.name()),
+            q(new.name())
+        ));
+    }
+    stmts.push(format!(
+        "ALTER TABLE {} {};",
+        q(tbl_name),
+        changes.join(", ")
+    ));
+    if let Some(reference) = new.reference().filter(|_| reference_changed) {
+        stmts.push(format!(
+            "ALTER TABLE {} ADD {};",
+            q(tbl_name),
+            define_foreign_key(tbl_name, new.name(), reference, current, Some(&new_table))?
+        ));
+    }
+    Ok(stmts.join("\n"))
+}
+
+/// Redefines the column `name` of the table `tbl_name` as changed by
+/// `change`, for changes MySQL can only make to the whole column.
+fn modify_column(
+    current: &ADB,
+    tbl_name: &str,
+    name: &str,
+    change: impl FnOnce(&mut AColumn),
+) -> Result<String> {
+    let table = match current.get_table(tbl_name) {
+        Some(table) => table,
+        None => return Ok(String::new()),
+    };
+    let mut col = match table.column(name) {
+        Some(col) => col.clone(),
+        None => return Ok(String::new()),
+    };
+    change(&mut col);
+    Ok(format!(
+        "ALTER TABLE {} MODIFY COLUMN {};",
+        q(tbl_name),
+        define_column(&col, table, false)?
+    ))
+}
+
+fn change_column_default(
+    current: &ADB,
+    tbl_name: &str,
+    name: &str,
+    default: &Option<SqlVal>,
+) -> Result<String> {
+    let action = match (default, current.get_table(tbl_name)) {
+        (Some(default), Some(table)) => {
+            let sqltype = match table.column(name) {
+                Some(col) => col_sqltype(col, table)?,
+                None => Cow::Borrowed(""),
+            };
+            format!("SET DEFAULT {}", sql_default(default, &sqltype)?)
+        }
+        (Some(default), None) => format!("SET DEFAULT {}", sql_literal_value(default.clone())?),
+        (None, _) => "DROP DEFAULT".to_string(),
+    };
+    Ok(format!(
+        "ALTER TABLE {} ALTER COLUMN {} {};",
+        q(tbl_name),
+        q(name),
+        action
+    ))
+}
+
+fn change_column_default_expr(tbl_name: &str, name: &str, expr: Option<&str>) -> String {
+    let action = match expr {
+        Some(expr) => format!("SET DEFAULT ({})", expr),
+        None => "DROP DEFAULT".to_string(),
+    };
+    format!(
+        "ALTER TABLE {} ALTER COLUMN {} {};",
+        q(tbl_name),
+        q(name),
+        action
+    )
+}
+
+/// Make the column `name` of the table `tbl_name` NOT NULL, after
+/// filling in its NULLs with `backfill`. MySQL updates all rows at
+/// once, and in strict mode fails to modify the column if any NULL is
+/// left.
+fn set_not_null(
+    current: &ADB,
+    tbl_name: &str,
+    name: &str,
+    backfill: Option<&Backfill>,
+) -> Result<String> {
+    let modify = modify_column(current, tbl_name, name, |col| col.set_nullable(false))?;
+    Ok(match backfill {
+        Some(backfill) => format!(
+            "UPDATE {} SET {} = ({}) WHERE {} IS NULL;\n{}",
+            q(tbl_name),
+            q(name),
+            backfill.expr,
+            q(name),
+            modify
+        ),
+        None => modify,
+    })
+}
+
+/// Renames the table `old` to `new`, along with its indexes and unique
+/// constraints, whose names are derived from the table name.
+fn rename_table(current: &ADB, old: &str, new: &str) -> String {
+    let mut stmts: Vec<String> = helper::rename_steps(old, new)
+        .into_iter()
+        .map(|(from, to)| format!("RENAME TABLE {} TO {};", q(&from), q(&to)))
+        .collect();
+    if let Some(table) = current.get_table(old) {
+        for index in &table.indexes {
+            stmts.push(rename_index(new, &index.name(old), &index.name(new)));
+        }
+        for constraint in &table.unique_constraints {
+            stmts.push(rename_index(
+                new,
+                &constraint.name(old),
+                &constraint.name(new),
+            ));
+        }
+    }
+    stmts.join("\n")
+}
+
+/// Renames the column `old` of the table `tbl_name` to `new`, along with
+/// the indexes and unique constraints whose names are derived from it.
+fn rename_column(current: &ADB, tbl_name: &str, old: &str, new: &str) -> String {
+    let mut stmts: Vec<String> = helper::rename_steps(old, new)
+        .into_iter()
+        .map(|(from, to)| {
+            format!(
+                "ALTER TABLE {} RENAME COLUMN {} TO {};",
+                q(tbl_name),
+                q(&from),
+                q(&to)
+            )
+        })
+        .collect();
+    if let Some(table) = current.get_table(tbl_name) {
+        // MySQL names the index of a unique column after it
+        if table.column(old).is_some_and(|c| c.unique()) {
+            stmts.push(rename_index(tbl_name, old, new));
+        }
+        let mut renamed = table.clone();
+        renamed.rename_column(old, new);
+        for (index, renamed) in table.indexes.iter().zip(&renamed.indexes) {
+            if index != renamed {
+                stmts.push(rename_index(
+                    tbl_name,
+                    &index.name(tbl_name),
+                    &renamed.name(tbl_name),
+                ));
+            }
+        }
+        for (constraint, renamed) in table
+            .unique_constraints
+            .iter()
+            .zip(&renamed.unique_constraints)
+        {
+            if constraint != renamed {
+                stmts.push(rename_index(
+                    tbl_name,
+                    &constraint.name(tbl_name),
+                    &renamed.name(tbl_name),
+                ));
+            }
+        }
+    }
+    stmts.join("\n")
+}
+
+/// Renames the index `old` of the table `tbl_name`, including that of a
+/// unique constraint.
+fn rename_index(tbl_name: &str, old: &str, new: &str) -> String {
+    helper::rename_steps(old, new)
+        .into_iter()
+        .map(|(from, to)| {
+            format!(
+                "ALTER TABLE {} RENAME INDEX {} TO {};",
+                q(tbl_name),
+                q(&from),
+                q(&to)
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn create_view(view: &AView, allow_exists: bool) -> String {
+    let modifier = if allow_exists { "OR REPLACE " } else { "" };
+    format!(
+        "CREATE {}VIEW {} AS {};",
+        modifier,
+        q(&view.name),
+        view.query
+    )
+}
+
+fn drop_view(name: &str, idempotent: bool) -> String {
+    format!("DROP VIEW {}{};", if_exists(idempotent), q(name))
+}
+
+struct MySqlPlaceholderSource;
+impl helper::PlaceholderSource for MySqlPlaceholderSource {
+    fn next_placeholder(&mut self) -> Cow<'_, str> {
+        Cow::Borrowed("?")
+    }
+}
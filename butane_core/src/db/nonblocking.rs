@@ -0,0 +1,604 @@
+//! Asynchronous database connections, with the `async` feature.
+//!
+//! * [AsyncConnectionMethods] is the asynchronous counterpart of
+//!   [ConnectionMethods]. Models implement
+//!   [DataObjectAsync][crate::DataObjectAsync], whose methods, like
+//!   [Query::load_async][crate::query::Query::load_async], require one.
+//! * [AsyncConnection] is the counterpart of [BackendConnection].
+//! * [AsyncBackend] connects asynchronously. The Postgres backend
+//!   implements it on tokio-postgres. The drivers of the other backends
+//!   are synchronous, so their connections are run on tokio's blocking
+//!   thread pool by [SpawnBlocking]. [connect_async] picks whichever
+//!   suits a [ConnectionSpec].
+//! * [BlockingConnection][super::blocking::BlockingConnection] runs an
+//!   [AsyncConnection] synchronously, for the methods of
+//!   [DataObject][crate::DataObject].
+//!
+//! The futures must be run by a tokio runtime, with IO enabled for
+//! Postgres connections. Transactions are not
+//! yet available asynchronously.
+//!
+//! # Cancellation
+//! Dropping the future of a method before it completes may leave the
+//! statement running, or leave it unknown whether it took effect, so
+//! the connection is no longer trusted: every later method fails with
+//! [Error::ConnectionCancelled] and [AsyncConnection::is_closed]
+//! reports true, so that pools discard it. Connect again to continue.
+//! A future dropped before it is first polled does not run its
+//! statement and leaves the connection usable.
+//!
+//! ```ignore
+//! let conn = butane::db::nonblocking::connect_async(&spec).await?;
+//! let mut post = Post::get_async(&conn, 1).await?;
+//! post.published = true;
+//! post.save_async(&conn).await?;
+//! ```
+use super::*;
+use crate::query::{Assignment, BoolExpr, Expr, Order};
+use crate::{Error, Result, SqlVal, SqlValRef};
+use async_trait::async_trait;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+#[cfg(any(feature = "bb8", feature = "deadpool"))]
+pub mod pool;
+
+/// A boxed future which may be sent between threads, as returned by
+/// the methods of [DataObjectAsync][crate::DataObjectAsync].
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// Asynchronous counterpart of [ConnectionMethods]. Most users do not
+/// need to call these methods directly and will instead use methods
+/// on [DataObjectAsync][crate::DataObjectAsync] or
+/// [Query][crate::query::Query]. See the [module
+/// documentation](self) on cancelling them.
+#[async_trait]
+pub trait AsyncConnectionMethods: Send + Sync {
+    async fn execute(&self, sql: &str) -> Result<()>;
+    /// Unlike [ConnectionMethods::query], the rows are read before
+    /// they are returned.
+    async fn query(
+        &self,
+        table: &str,
+        columns: &[Column],
+        expr: Option<BoolExpr>,
+        limit: Option<i32>,
+        offset: Option<i32>,
+        sort: Option<&[Order]>,
+    ) -> Result<Vec<OwnedRow>>;
+    async fn insert_returning_pk(
+        &self,
+        table: &str,
+        columns: &[Column],
+        pkcol: &Column,
+        values: &[SqlValRef<'_>],
+    ) -> Result<SqlVal>;
+    /// Like `insert_returning_pk` but with no return value
+    async fn insert_only(
+        &self,
+        table: &str,
+        columns: &[Column],
+        values: &[SqlValRef<'_>],
+    ) -> Result<()>;
+    /// Insert unless there's a conflict on the primary key column, in which case update
+    async fn insert_or_replace(
+        &self,
+        table: &str,
+        columns: &[Column],
+        pkcol: &Column,
+        values: &[SqlValRef<'_>],
+    ) -> Result<()>;
+    async fn update(
+        &self,
+        table: &str,
+        pkcol: Column,
+        pk: SqlValRef<'_>,
+        columns: &[Column],
+        values: &[SqlValRef<'_>],
+    ) -> Result<()>;
+    async fn delete(&self, table: &str, pkcol: &'static str, pk: SqlVal) -> Result<()> {
+        self.delete_where(table, BoolExpr::Eq(pkcol, Expr::Val(pk)))
+            .await?;
+        Ok(())
+    }
+    async fn delete_where(&self, table: &str, expr: BoolExpr) -> Result<usize>;
+    /// See [ConnectionMethods::update_where].
+    async fn update_where(
+        &self,
+        table: &str,
+        assignments: &[Assignment],
+        expr: BoolExpr,
+    ) -> Result<usize>;
+    /// Tests if a table exists in the database.
+    async fn has_table(&self, table: &str) -> Result<bool>;
+    /// See [ConnectionMethods::execute_batch]. Postgres sends the
+    /// statements together, without waiting for each to complete.
+    async fn execute_batch(&self, statements: &[Statement]) -> Result<()>;
+}
+
+/// Asynchronous database connection, the counterpart of
+/// [BackendConnection].
+#[async_trait]
+pub trait AsyncConnection: AsyncConnectionMethods + 'static {
+    fn backend_name(&self) -> &'static str;
+    /// Check that the database can still be reached through this
+    /// connection, which is not the case once a statement was
+    /// cancelled.
+    async fn ping(&self) -> Result<()> {
+        self.execute("SELECT 1").await
+    }
+    /// Tests if the connection has been closed, or may no longer be
+    /// used because a statement was cancelled. Backends which do not
+    /// support this check should return false.
+    fn is_closed(&self) -> bool;
+    /// See [BackendConnection::statement_cache_stats].
+    fn statement_cache_stats(&self) -> Option<StatementCacheStats> {
+        None
+    }
+}
+
+/// Database backend which connects asynchronously.
+#[async_trait]
+pub trait AsyncBackend: Send + Sync {
+    async fn connect_async(&self, conn_str: &str) -> Result<Box<dyn AsyncConnection>>;
+}
+
+/// Connect to a database asynchronously. Postgres connections use
+/// tokio-postgres, and those of other backends are run with
+/// [SpawnBlocking].
+pub async fn connect_async(spec: &ConnectionSpec) -> Result<Box<dyn AsyncConnection>> {
+    #[cfg(feature = "pg")]
+    if let Some(backend) = spec.pg_backend() {
+        return backend.connect_async(&spec.conn_str).await;
+    }
+    Ok(Box::new(SpawnBlocking::connect(spec.clone()).await?))
+}
+
+#[async_trait]
+impl AsyncConnectionMethods for Box<dyn AsyncConnection> {
+    async fn execute(&self, sql: &str) -> Result<()> {
+        self.deref().execute(sql).await
+    }
+    async fn query(
+        &self,
+        table: &str,
+        columns: &[Column],
+        expr: Option<BoolExpr>,
+        limit: Option<i32>,
+        offset: Option<i32>,
+        sort: Option<&[Order]>,
+    ) -> Result<Vec<OwnedRow>> {
+        self.deref()
+            .query(table, columns, expr, limit, offset, sort)
+            .await
+    }
+    async fn insert_returning_pk(
+        &self,
+        table: &str,
+        columns: &[Column],
+        pkcol: &Column,
+        values: &[SqlValRef<'_>],
+    ) -> Result<SqlVal> {
+        self.deref()
+            .insert_returning_pk(table, columns, pkcol, values)
+            .await
+    }
+    async fn insert_only(
+        &self,
+        table: &str,
+        columns: &[Column],
+        values: &[SqlValRef<'_>],
+    ) -> Result<()> {
+        self.deref().insert_only(table, columns, values).await
+    }
+    async fn insert_or_replace(
+        &self,
+        table: &str,
+        columns: &[Column],
+        pkcol: &Column,
+        values: &[SqlValRef<'_>],
+    ) -> Result<()> {
+        self.deref()
+            .insert_or_replace(table, columns, pkcol, values)
+            .await
+    }
+    async fn update(
+        &self,
+        table: &str,
+        pkcol: Column,
+        pk: SqlValRef<'_>,
+        columns: &[Column],
+        values: &[SqlValRef<'_>],
+    ) -> Result<()> {
+        self.deref().update(table, pkcol, pk, columns, values).await
+    }
+    async fn delete_where(&self, table: &str, expr: BoolExpr) -> Result<usize> {
+        self.deref().delete_where(table, expr).await
+    }
+    async fn update_where(
+        &self,
+        table: &str,
+        assignments: &[Assignment],
+        expr: BoolExpr,
+    ) -> Result<usize> {
+        self.deref().update_where(table, assignments, expr).await
+    }
+    async fn has_table(&self, table: &str) -> Result<bool> {
+        self.deref().has_table(table).await
+    }
+    async fn execute_batch(&self, statements: &[Statement]) -> Result<()> {
+        self.deref().execute_batch(statements).await
+    }
+}
+#[async_trait]
+impl AsyncConnection for Box<dyn AsyncConnection> {
+    fn backend_name(&self) -> &'static str {
+        self.deref().backend_name()
+    }
+    async fn ping(&self) -> Result<()> {
+        self.deref().ping().await
+    }
+    fn is_closed(&self) -> bool {
+        self.deref().is_closed()
+    }
+    fn statement_cache_stats(&self) -> Option<StatementCacheStats> {
+        self.deref().statement_cache_stats()
+    }
+}
+
+/// Records whether the future of a statement on a connection was
+/// dropped before it completed. See the [module
+/// documentation](self#cancellation).
+#[derive(Debug, Default)]
+pub(crate) struct Cancellation {
+    cancelled: AtomicBool,
+}
+impl Cancellation {
+    pub(crate) fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Acquire)
+    }
+    /// Runs `statement`, unless an earlier one was cancelled. The
+    /// connection is marked cancelled if the returned future is
+    /// dropped after it is first polled but before it completes.
+    pub(crate) async fn run<T>(&self, statement: impl Future<Output = Result<T>>) -> Result<T> {
+        if self.is_cancelled() {
+            return Err(Error::ConnectionCancelled);
+        }
+        let mut guard = CancelGuard {
+            cancelled: &self.cancelled,
+            completed: false,
+        };
+        let result = statement.await;
+        guard.completed = true;
+        result
+    }
+}
+
+struct CancelGuard<'a> {
+    cancelled: &'a AtomicBool,
+    completed: bool,
+}
+impl Drop for CancelGuard<'_> {
+    fn drop(&mut self) {
+        if !self.completed {
+            self.cancelled.store(true, Ordering::Release);
+        }
+    }
+}
+
+/// Wraps an [AsyncConnection] whose statements are not cancel-safe on
+/// their own, failing every method once one was cancelled.
+#[cfg(feature = "pg")]
+pub(crate) struct Cancellable<C> {
+    conn: C,
+    cancellation: Cancellation,
+}
+#[cfg(feature = "pg")]
+impl<C> Cancellable<C> {
+    pub(crate) fn new(conn: C) -> Self {
+        Cancellable {
+            conn,
+            cancellation: Cancellation::default(),
+        }
+    }
+}
+
+#[cfg(feature = "pg")]
+#[async_trait]
+impl<C: AsyncConnectionMethods> AsyncConnectionMethods for Cancellable<C> {
+    async fn execute(&self, sql: &str) -> Result<()> {
+        self.cancellation.run(self.conn.execute(sql)).await
+    }
+    async fn query(
+        &self,
+        table: &str,
+        columns: &[Column],
+        expr: Option<BoolExpr>,
+        limit: Option<i32>,
+        offset: Option<i32>,
+        sort: Option<&[Order]>,
+    ) -> Result<Vec<OwnedRow>> {
+        self.cancellation
+            .run(self.conn.query(table, columns, expr, limit, offset, sort))
+            .await
+    }
+    async fn insert_returning_pk(
+        &self,
+        table: &str,
+        columns: &[Column],
+        pkcol: &Column,
+        values: &[SqlValRef<'_>],
+    ) -> Result<SqlVal> {
+        self.cancellation
+            .run(self.conn.insert_returning_pk(table, columns, pkcol, values))
+            .await
+    }
+    async fn insert_only(
+        &self,
+        table: &str,
+        columns: &[Column],
+        values: &[SqlValRef<'_>],
+    ) -> Result<()> {
+        self.cancellation
+            .run(self.conn.insert_only(table, columns, values))
+            .await
+    }
+    async fn insert_or_replace(
+        &self,
+        table: &str,
+        columns: &[Column],
+        pkcol: &Column,
+        values: &[SqlValRef<'_>],
+    ) -> Result<()> {
+        self.cancellation
+            .run(self.conn.insert_or_replace(table, columns, pkcol, values))
+            .await
+    }
+    async fn update(
+        &self,
+        table: &str,
+        pkcol: Column,
+        pk: SqlValRef<'_>,
+        columns: &[Column],
+        values: &[SqlValRef<'_>],
+    ) -> Result<()> {
+        self.cancellation
+            .run(self.conn.update(table, pkcol, pk, columns, values))
+            .await
+    }
+    async fn delete_where(&self, table: &str, expr: BoolExpr) -> Result<usize> {
+        self.cancellation
+            .run(self.conn.delete_where(table, expr))
+            .await
+    }
+    async fn update_where(
+        &self,
+        table: &str,
+        assignments: &[Assignment],
+        expr: BoolExpr,
+    ) -> Result<usize> {
+        self.cancellation
+            .run(self.conn.update_where(table, assignments, expr))
+            .await
+    }
+    async fn has_table(&self, table: &str) -> Result<bool> {
+        self.cancellation.run(self.conn.has_table(table)).await
+    }
+    async fn execute_batch(&self, statements: &[Statement]) -> Result<()> {
+        self.cancellation
+            .run(self.conn.execute_batch(statements))
+            .await
+    }
+}
+#[cfg(feature = "pg")]
+#[async_trait]
+impl<C: AsyncConnection> AsyncConnection for Cancellable<C> {
+    fn backend_name(&self) -> &'static str {
+        self.conn.backend_name()
+    }
+    async fn ping(&self) -> Result<()> {
+        self.cancellation.run(self.conn.ping()).await
+    }
+    fn is_closed(&self) -> bool {
+        self.cancellation.is_cancelled() || self.conn.is_closed()
+    }
+    fn statement_cache_stats(&self) -> Option<StatementCacheStats> {
+        self.conn.statement_cache_stats()
+    }
+}
+
+/// Runs a synchronous [Connection] on tokio's blocking thread pool,
+/// for backends without an asynchronous driver. Methods called
+/// concurrently run one after another. A statement whose future is
+/// dropped keeps running on the pool, and the connection fails every
+/// later method.
+pub struct SpawnBlocking {
+    conn: Arc<Mutex<Connection>>,
+    backend_name: &'static str,
+    cancellation: Cancellation,
+}
+impl SpawnBlocking {
+    pub fn new(conn: Connection) -> Self {
+        SpawnBlocking {
+            backend_name: conn.backend_name(),
+            conn: Arc::new(Mutex::new(conn)),
+            cancellation: Cancellation::default(),
+        }
+    }
+    /// Connect to the database of `spec` on the blocking thread pool.
+    pub async fn connect(spec: ConnectionSpec) -> Result<Self> {
+        let conn = spawn_blocking(move || connect(&spec)).await?;
+        Ok(SpawnBlocking::new(conn))
+    }
+    async fn with_conn<T, F>(&self, f: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&Connection) -> Result<T> + Send + 'static,
+    {
+        let conn = self.conn.clone();
+        self.cancellation
+            .run(spawn_blocking(move || {
+                let conn = conn.lock().map_err(|_| {
+                    Error::Internal("connection lost to a panic during a statement".to_string())
+                })?;
+                f(&conn)
+            }))
+            .await
+    }
+}
+
+async fn spawn_blocking<T, F>(f: F) -> Result<T>
+where
+    T: Send + 'static,
+    F: FnOnce() -> Result<T> + Send + 'static,
+{
+    tokio::task::spawn_blocking(f)
+        .await
+        .map_err(|e| Error::Generic(Box::new(e)))?
+}
+
+fn owned_values(values: &[SqlValRef<'_>]) -> Vec<SqlVal> {
+    values.iter().map(|v| SqlVal::from(v.clone())).collect()
+}
+
+fn value_refs(values: &[SqlVal]) -> Vec<SqlValRef<'_>> {
+    values.iter().map(SqlVal::as_ref).collect()
+}
+
+#[async_trait]
+impl AsyncConnectionMethods for SpawnBlocking {
+    async fn execute(&self, sql: &str) -> Result<()> {
+        let sql = sql.to_string();
+        self.with_conn(move |conn| ConnectionMethods::execute(conn, &sql))
+            .await
+    }
+    async fn query(
+        &self,
+        table: &str,
+        columns: &[Column],
+        expr: Option<BoolExpr>,
+        limit: Option<i32>,
+        offset: Option<i32>,
+        sort: Option<&[Order]>,
+    ) -> Result<Vec<OwnedRow>> {
+        let table = table.to_string();
+        let columns = columns.to_vec();
+        let sort = sort.map(<[Order]>::to_vec);
+        self.with_conn(move |conn| {
+            let rows = conn.query(&table, &columns, expr, limit, offset, sort.as_deref())?;
+            OwnedRow::from_rows(rows, &columns)
+        })
+        .await
+    }
+    async fn insert_returning_pk(
+        &self,
+        table: &str,
+        columns: &[Column],
+        pkcol: &Column,
+        values: &[SqlValRef<'_>],
+    ) -> Result<SqlVal> {
+        let table = table.to_string();
+        let columns = columns.to_vec();
+        let pkcol = pkcol.clone();
+        let values = owned_values(values);
+        self.with_conn(move |conn| {
+            conn.insert_returning_pk(&table, &columns, &pkcol, &value_refs(&values))
+        })
+        .await
+    }
+    async fn insert_only(
+        &self,
+        table: &str,
+        columns: &[Column],
+        values: &[SqlValRef<'_>],
+    ) -> Result<()> {
+        let table = table.to_string();
+        let columns = columns.to_vec();
+        let values = owned_values(values);
+        self.with_conn(move |conn| conn.insert_only(&table, &columns, &value_refs(&values)))
+            .await
+    }
+    async fn insert_or_replace(
+        &self,
+        table: &str,
+        columns: &[Column],
+        pkcol: &Column,
+        values: &[SqlValRef<'_>],
+    ) -> Result<()> {
+        let table = table.to_string();
+        let columns = columns.to_vec();
+        let pkcol = pkcol.clone();
+        let values = owned_values(values);
+        self.with_conn(move |conn| {
+            conn.insert_or_replace(&table, &columns, &pkcol, &value_refs(&values))
+        })
+        .await
+    }
+    async fn update(
+        &self,
+        table: &str,
+        pkcol: Column,
+        pk: SqlValRef<'_>,
+        columns: &[Column],
+        values: &[SqlValRef<'_>],
+    ) -> Result<()> {
+        let table = table.to_string();
+        let pk = SqlVal::from(pk);
+        let columns = columns.to_vec();
+        let values = owned_values(values);
+        self.with_conn(move |conn| {
+            conn.update(&table, pkcol, pk.as_ref(), &columns, &value_refs(&values))
+        })
+        .await
+    }
+    async fn delete_where(&self, table: &str, expr: BoolExpr) -> Result<usize> {
+        let table = table.to_string();
+        self.with_conn(move |conn| conn.delete_where(&table, expr))
+            .await
+    }
+    async fn update_where(
+        &self,
+        table: &str,
+        assignments: &[Assignment],
+        expr: BoolExpr,
+    ) -> Result<usize> {
+        let table = table.to_string();
+        let assignments = assignments.to_vec();
+        self.with_conn(move |conn| conn.update_where(&table, &assignments, expr))
+            .await
+    }
+    async fn has_table(&self, table: &str) -> Result<bool> {
+        let table = table.to_string();
+        self.with_conn(move |conn| conn.has_table(&table)).await
+    }
+    async fn execute_batch(&self, statements: &[Statement]) -> Result<()> {
+        let statements = statements.to_vec();
+        self.with_conn(move |conn| conn.execute_batch(&statements))
+            .await
+    }
+}
+#[async_trait]
+impl AsyncConnection for SpawnBlocking {
+    fn backend_name(&self) -> &'static str {
+        self.backend_name
+    }
+    async fn ping(&self) -> Result<()> {
+        self.with_conn(|conn| conn.ping()).await
+    }
+    fn is_closed(&self) -> bool {
+        if self.cancellation.is_cancelled() {
+            return true;
+        }
+        // A connection in use by a statement is not closed
+        match self.conn.try_lock() {
+            Ok(conn) => conn.is_closed(),
+            Err(_) => false,
+        }
+    }
+    fn statement_cache_stats(&self) -> Option<StatementCacheStats> {
+        // Not known while a statement is running
+        self.conn.try_lock().ok()?.statement_cache_stats()
+    }
+}
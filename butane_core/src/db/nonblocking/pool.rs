@@ -0,0 +1,85 @@
+//! Pools of [AsyncConnection]s, the asynchronous counterpart of
+//! `db::pool`. The `deadpool` and `bb8` modules, with the features of
+//! the same names, each provide a manager for their pool crate.
+//!
+//! Both open connections with [connect_async][super::connect_async]
+//! and ping connections before handing them out again. A connection
+//! whose statement was cancelled fails that check, as described in the
+//! [module documentation](super#cancellation), so it is discarded
+//! rather than handed out again.
+//!
+//! ```ignore
+//! let config = PoolConfig::default().with_max_size(16).with_min_idle(4);
+//! let pool = butane::db::nonblocking::pool::deadpool::new(spec, &config).await?;
+//! let conn = pool.get().await?;
+//! let post = Post::get_async(&**conn, 1).await?;
+//! ```
+use super::AsyncConnection;
+use crate::Result;
+use std::time::Duration;
+
+#[cfg(feature = "bb8")]
+pub mod bb8;
+#[cfg(feature = "deadpool")]
+pub mod deadpool;
+
+/// The longest to wait for a connection from a pool made with the
+/// default [PoolConfig].
+pub const WAIT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Settings of a pool, applied by the `new` functions of the pool
+/// modules.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct PoolConfig {
+    /// The most connections the pool holds, 10 by default.
+    pub max_size: usize,
+    /// How many connections are opened as the pool is created, so that
+    /// the first requests need not wait for them. None by default.
+    pub min_idle: usize,
+    /// The longest to wait for a connection, including opening it,
+    /// [WAIT_TIMEOUT] by default.
+    pub wait_timeout: Option<Duration>,
+    /// The longest to wait for a new connection to be opened.
+    pub create_timeout: Option<Duration>,
+    /// The longest to wait for a connection to be checked before it is
+    /// handed out again.
+    pub recycle_timeout: Option<Duration>,
+}
+impl PoolConfig {
+    pub fn with_max_size(mut self, max_size: usize) -> Self {
+        self.max_size = max_size;
+        self
+    }
+    pub fn with_min_idle(mut self, min_idle: usize) -> Self {
+        self.min_idle = min_idle;
+        self
+    }
+    pub fn with_wait_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.wait_timeout = timeout;
+        self
+    }
+    pub fn with_create_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.create_timeout = timeout;
+        self
+    }
+    pub fn with_recycle_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.recycle_timeout = timeout;
+        self
+    }
+}
+impl Default for PoolConfig {
+    fn default() -> Self {
+        PoolConfig {
+            max_size: 10,
+            min_idle: 0,
+            wait_timeout: Some(WAIT_TIMEOUT),
+            create_timeout: None,
+            recycle_timeout: None,
+        }
+    }
+}
+
+/// Checks a connection before it is handed out again.
+async fn recycle(conn: &dyn AsyncConnection) -> Result<()> {
+    conn.ping().await
+}
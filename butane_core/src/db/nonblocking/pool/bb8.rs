@@ -0,0 +1,98 @@
+//! Pools of [AsyncConnection]s with [bb8].
+use super::PoolConfig;
+use crate::db::nonblocking::{connect_async, AsyncConnection};
+use crate::db::ConnectionSpec;
+use crate::{Error, Result};
+use async_trait::async_trait;
+use std::future::Future;
+use std::time::Duration;
+
+pub use ::bb8;
+
+/// A pool of asynchronous butane connections.
+pub type Pool = ::bb8::Pool<ConnectionManager>;
+/// A connection checked out of a [Pool]. It dereferences to a
+/// `Box<dyn AsyncConnection>`, and is returned to the pool when
+/// dropped.
+pub type PooledConnection<'a> = ::bb8::PooledConnection<'a, ConnectionManager>;
+
+/// BB8 support for Butane. Implements [`bb8::ManageConnection`].
+/// Unlike bb8's own settings, its timeouts limit opening and checking
+/// a connection alone.
+pub struct ConnectionManager {
+    spec: ConnectionSpec,
+    create_timeout: Option<Duration>,
+    recycle_timeout: Option<Duration>,
+}
+impl ConnectionManager {
+    pub fn new(spec: ConnectionSpec) -> Self {
+        ConnectionManager {
+            spec,
+            create_timeout: None,
+            recycle_timeout: None,
+        }
+    }
+    pub fn with_create_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.create_timeout = timeout;
+        self
+    }
+    pub fn with_recycle_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.recycle_timeout = timeout;
+        self
+    }
+}
+
+#[async_trait]
+impl ::bb8::ManageConnection for ConnectionManager {
+    type Connection = Box<dyn AsyncConnection>;
+    type Error = Error;
+
+    async fn connect(&self) -> Result<Self::Connection> {
+        with_timeout(self.create_timeout, connect_async(&self.spec)).await
+    }
+
+    async fn is_valid(&self, conn: &mut Self::Connection) -> Result<()> {
+        with_timeout(self.recycle_timeout, super::recycle(conn.as_ref())).await
+    }
+
+    fn has_broken(&self, conn: &mut Self::Connection) -> bool {
+        conn.is_closed()
+    }
+}
+
+async fn with_timeout<T>(
+    timeout: Option<Duration>,
+    future: impl Future<Output = Result<T>>,
+) -> Result<T> {
+    match timeout {
+        Some(timeout) => tokio::time::timeout(timeout, future)
+            .await
+            .map_err(|e| Error::Generic(Box::new(e)))?,
+        None => future.await,
+    }
+}
+
+/// A builder for a [Pool] with the settings of `config`, other than its
+/// create and recycle timeouts, which are those of the
+/// [ConnectionManager] it is built with. Bb8 always limits the wait for
+/// a connection, so without [PoolConfig::wait_timeout] it waits for
+/// bb8's default of 30 seconds.
+pub fn builder(config: &PoolConfig) -> ::bb8::Builder<ConnectionManager> {
+    let builder = Pool::builder()
+        .max_size(config.max_size as u32)
+        .min_idle(Some(config.min_idle.min(config.max_size) as u32));
+    match config.wait_timeout {
+        Some(timeout) => builder.connection_timeout(timeout),
+        None => builder,
+    }
+}
+
+/// Create a [Pool] of connections to `spec` with the settings of
+/// `config`. It opens [PoolConfig::min_idle] connections before it is
+/// returned, and again as they are closed.
+pub async fn new(spec: ConnectionSpec, config: &PoolConfig) -> Result<Pool> {
+    let manager = ConnectionManager::new(spec)
+        .with_create_timeout(config.create_timeout)
+        .with_recycle_timeout(config.recycle_timeout);
+    builder(config).build(manager).await
+}
@@ -0,0 +1,72 @@
+//! Pools of [AsyncConnection]s with [deadpool].
+use super::PoolConfig;
+use crate::db::nonblocking::{connect_async, AsyncConnection};
+use crate::db::ConnectionSpec;
+use crate::{Error, Result};
+use ::deadpool::managed::{self, Metrics, RecycleResult};
+use ::deadpool::Runtime;
+
+pub use ::deadpool;
+
+/// A pool of asynchronous butane connections.
+pub type Pool = managed::Pool<Manager>;
+/// A connection checked out of a [Pool]. It dereferences to a
+/// `Box<dyn AsyncConnection>`, and is returned to the pool when
+/// dropped.
+pub type Object = managed::Object<Manager>;
+/// The error of [Pool::get].
+pub type PoolError = managed::PoolError<Error>;
+
+/// Deadpool support for Butane. Implements [`managed::Manager`].
+pub struct Manager {
+    spec: ConnectionSpec,
+}
+impl Manager {
+    pub fn new(spec: ConnectionSpec) -> Self {
+        Manager { spec }
+    }
+}
+
+impl managed::Manager for Manager {
+    type Type = Box<dyn AsyncConnection>;
+    type Error = Error;
+
+    async fn create(&self) -> Result<Self::Type> {
+        connect_async(&self.spec).await
+    }
+
+    async fn recycle(&self, conn: &mut Self::Type, _: &Metrics) -> RecycleResult<Error> {
+        Ok(super::recycle(conn.as_ref()).await?)
+    }
+}
+
+/// A builder for a [Pool] with the settings of `config`, run on tokio.
+/// Unlike [new], it does not open [PoolConfig::min_idle] connections.
+pub fn builder(spec: ConnectionSpec, config: &PoolConfig) -> managed::PoolBuilder<Manager> {
+    Pool::builder(Manager::new(spec))
+        .runtime(Runtime::Tokio1)
+        .max_size(config.max_size)
+        .wait_timeout(config.wait_timeout)
+        .create_timeout(config.create_timeout)
+        .recycle_timeout(config.recycle_timeout)
+}
+
+/// Create a [Pool] of connections to `spec` with the settings of
+/// `config`, opening [PoolConfig::min_idle] connections before it is
+/// returned. Deadpool does not open connections again as they are
+/// closed, so the number idle may later fall below that.
+pub async fn new(spec: ConnectionSpec, config: &PoolConfig) -> Result<Pool> {
+    let pool = builder(spec, config)
+        .build()
+        .map_err(|e| Error::Generic(Box::new(e)))?;
+    let count = config.min_idle.min(config.max_size);
+    let warm = futures_util::future::try_join_all((0..count).map(|_| pool.get()))
+        .await
+        .map_err(|e| match e {
+            managed::PoolError::Backend(e) => e,
+            e => Error::Generic(Box::new(e)),
+        })?;
+    // Returned to the pool, idle
+    drop(warm);
+    Ok(pool)
+}
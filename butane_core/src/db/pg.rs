@@ -1,9 +1,16 @@
 //! Postgresql database backend
+use super::cache::StatementCache;
 use super::connmethods::VecRows;
 use super::helper;
+use super::logging;
 use super::*;
-use crate::custom::{SqlTypeCustom, SqlValRefCustom};
-use crate::migrations::adb::{AColumn, ATable, Operation, TypeIdentifier, ADB};
+use crate::custom::{SqlTypeCustom, SqlValCustom, SqlValRefCustom};
+use crate::migrations::adb::{
+    ACheckConstraint, AColumn, AEnum, AForeignKey, AIndex, ATable, ATypeParams, AUniqueConstraint,
+    AView, AutoIncrement, Backfill, DeferredSqlType, Operation, ReferentialAction, TypeIdentifier,
+    ADB,
+};
+use crate::search::{SearchHit, INDEX_TABLE};
 use crate::{debug, query};
 use crate::{Result, SqlType, SqlVal, SqlValRef};
 use bytes::BufMut;
@@ -12,36 +19,142 @@ use chrono::NaiveDateTime;
 use postgres::fallible_iterator::FallibleIterator;
 use postgres::GenericClient;
 use std::cell::RefCell;
+use std::collections::HashMap;
 use std::fmt::Write;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+#[cfg(feature = "async")]
+mod nonblocking;
 
 /// The name of the postgres backend.
 pub const BACKEND_NAME: &str = "pg";
 
+/// The dialect of SQL spoken by a database using the Postgres protocol.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum Dialect {
+    #[default]
+    Postgres,
+    /// CockroachDB, see [cockroach][crate::db::cockroach].
+    Cockroach,
+}
+impl Dialect {
+    fn backend_name(&self) -> &'static str {
+        match self {
+            Dialect::Postgres => BACKEND_NAME,
+            Dialect::Cockroach => super::cockroach::BACKEND_NAME,
+        }
+    }
+}
+
 /// Pg [Backend][crate::db::Backend] implementation.
 #[derive(Default)]
-pub struct PgBackend {}
+pub struct PgBackend {
+    identifier_case: IdentifierCase,
+    search_path: Vec<String>,
+    capabilities: Option<Capabilities>,
+    dialect: Dialect,
+    tls: PgTlsOptions,
+    statement_cache_capacity: Option<usize>,
+    timeouts: Timeouts,
+}
 impl PgBackend {
     pub fn new() -> PgBackend {
-        PgBackend {}
+        PgBackend::default()
+    }
+    /// Write identifiers in SQL according to `identifier_case`, both in
+    /// migrations and in the queries of connections made by this backend.
+    pub fn with_identifier_case(mut self, identifier_case: IdentifierCase) -> PgBackend {
+        self.identifier_case = identifier_case;
+        self
+    }
+    /// Set the `search_path` of connections made by this backend to
+    /// `schemas`, so that the tables of models in those schemas are
+    /// found. If empty, the server's default is kept.
+    pub fn with_search_path(
+        mut self,
+        schemas: impl IntoIterator<Item = impl Into<String>>,
+    ) -> PgBackend {
+        self.search_path = schemas.into_iter().map(Into::into).collect();
+        self
+    }
+    /// Assume the server has `capabilities`, if given, rather than
+    /// detecting them from its version when connecting. Also used for
+    /// the SQL of migrations.
+    pub fn with_capabilities(mut self, capabilities: Option<Capabilities>) -> PgBackend {
+        self.capabilities = capabilities;
+        self
+    }
+    /// Generate migrations in `dialect`, and name the backend of
+    /// connections made by this backend after it.
+    pub fn with_dialect(mut self, dialect: Dialect) -> PgBackend {
+        self.dialect = dialect;
+        self
+    }
+    /// Use `tls` for the connections made by this backend.
+    pub fn with_tls(mut self, tls: PgTlsOptions) -> PgBackend {
+        self.tls = tls;
+        self
+    }
+    /// Cache up to `capacity` prepared statements in each connection
+    /// made by this backend, rather than
+    /// [DEFAULT_STATEMENT_CACHE_CAPACITY].
+    pub fn with_statement_cache_capacity(mut self, capacity: Option<usize>) -> PgBackend {
+        self.statement_cache_capacity = capacity;
+        self
+    }
+    /// Set the `statement_timeout` and `lock_timeout` of connections
+    /// made by this backend to those of `timeouts`.
+    pub fn with_timeouts(mut self, timeouts: Timeouts) -> PgBackend {
+        self.timeouts = timeouts;
+        self
+    }
+    fn statement_cache_capacity(&self) -> usize {
+        self.statement_cache_capacity
+            .unwrap_or(DEFAULT_STATEMENT_CACHE_CAPACITY)
     }
 }
 impl PgBackend {
     fn connect(&self, params: &str) -> Result<PgConnection> {
-        PgConnection::open(params)
+        let (params, tls) = self.tls.parse_url(params)?;
+        let tls = socket_tls(&params.parse()?, tls);
+        PgConnection::open(&params, tls, self)
     }
 }
 impl Backend for PgBackend {
     fn name(&self) -> &'static str {
-        BACKEND_NAME
+        self.dialect.backend_name()
     }
 
     fn create_migration_sql(&self, current: &ADB, ops: Vec<Operation>) -> Result<String> {
-        let mut current: ADB = (*current).clone();
-        Ok(ops
-            .iter()
-            .map(|o| sql_for_op(&mut current, o))
-            .collect::<Result<Vec<String>>>()?
-            .join("\n"))
+        self.migration_sql(current, ops, false)
+    }
+
+    fn create_idempotent_migration_sql(
+        &self,
+        current: &ADB,
+        ops: Vec<Operation>,
+    ) -> Result<String> {
+        self.migration_sql(current, ops, true)
+    }
+
+    fn as_introspected(&self, db: &ADB) -> ADB {
+        let db = match self.dialect {
+            Dialect::Postgres => Cow::Borrowed(db),
+            Dialect::Cockroach => Cow::Owned(super::cockroach::as_declared(db)),
+        };
+        // Unquoted identifiers are folded to lower case
+        let fold_case = self.identifier_case == IdentifierCase::Fold;
+        helper::as_introspected(&db, fold_case, true, true, |typeid| match typeid {
+            // Read back as the name of the type
+            TypeIdentifier::Enum(name) if fold_case => TypeIdentifier::Name(name.to_lowercase()),
+            TypeIdentifier::Enum(name) => TypeIdentifier::Name(name),
+            _ => typeid,
+        })
+    }
+
+    fn transactional_ddl(&self) -> bool {
+        self.dialect == Dialect::Postgres
     }
 
     fn connect(&self, path: &str) -> Result<Connection> {
@@ -50,50 +163,654 @@ impl Backend for PgBackend {
         })
     }
 }
+impl PgBackend {
+    fn migration_sql(
+        &self,
+        current: &ADB,
+        ops: Vec<Operation>,
+        idempotent: bool,
+    ) -> Result<String> {
+        let capabilities = self.capabilities.unwrap_or_default();
+        let q = quoter(self.identifier_case);
+        match self.dialect {
+            Dialect::Postgres => migration_sql(current, ops, idempotent, capabilities, q),
+            Dialect::Cockroach => {
+                super::cockroach::migration_sql(current, ops, idempotent, capabilities, q)
+            }
+        }
+    }
+}
 
 /// Pg database connection.
 pub struct PgConnection {
     conn: RefCell<postgres::Client>,
+    /// The connection string, kept to reconnect.
+    params: String,
+    auto_reconnect: bool,
+    identifier_case: IdentifierCase,
+    search_path: Vec<String>,
+    capabilities: Capabilities,
+    dialect: Dialect,
+    tls: PgTlsOptions,
+    timeouts: Timeouts,
+    statements: Arc<StatementCache<postgres::Statement>>,
+    logger: Option<QueryLogger>,
 }
 impl PgConnection {
-    fn open(params: &str) -> Result<Self> {
+    /// Connect with `params` and `tls`, and the other options of
+    /// `backend`.
+    fn open(params: &str, tls: PgTlsOptions, backend: &PgBackend) -> Result<Self> {
+        let session = session_sql(
+            &backend.search_path,
+            backend.identifier_case,
+            &backend.timeouts,
+        );
+        let mut conn = Self::connect(params, &tls, &session)?;
+        let capabilities = match backend.capabilities {
+            Some(capabilities) => capabilities,
+            None => server_capabilities(&mut conn)?,
+        };
         Ok(PgConnection {
-            conn: RefCell::new(Self::connect(params)?),
+            conn: RefCell::new(conn),
+            params: params.to_string(),
+            auto_reconnect: false,
+            identifier_case: backend.identifier_case,
+            search_path: backend.search_path.clone(),
+            capabilities,
+            dialect: backend.dialect,
+            tls,
+            timeouts: backend.timeouts,
+            statements: Arc::new(StatementCache::new(backend.statement_cache_capacity())),
+            logger: None,
         })
     }
-    fn connect(params: &str) -> Result<postgres::Client> {
-        cfg_if::cfg_if! {
-            if #[cfg(feature = "tls")] {
-                let connector = native_tls::TlsConnector::new()?;
-                let connector = postgres_native_tls::MakeTlsConnector::new(connector);
-            } else {
-                let connector = postgres::NoTls;
-            }
+    /// Connect, then run `session` to set up the session.
+    fn connect(params: &str, tls: &PgTlsOptions, session: &str) -> Result<postgres::Client> {
+        let mut config: postgres::Config = params.parse()?;
+        if let Some(ssl_mode) = pg_ssl_mode(tls) {
+            config.ssl_mode(ssl_mode);
+        }
+        let mut conn = config.connect(tls_connector(tls)?)?;
+        if !session.is_empty() {
+            conn.batch_execute(session)?;
+        }
+        Ok(conn)
+    }
+    /// Replace the client with a new connection if it has been lost
+    /// and auto-reconnect is enabled.
+    fn reconnect_if_lost(&self) -> Result<()> {
+        if !self.auto_reconnect || !self.conn.try_borrow_mut()?.is_closed() {
+            return Ok(());
+        }
+        self.reconnect()
+    }
+    /// Replace the client with a new connection.
+    fn reconnect(&self) -> Result<()> {
+        let session = session_sql(&self.search_path, self.identifier_case, &self.timeouts);
+        let conn = Self::connect(&self.params, &self.tls, &session)?;
+        *self.conn.try_borrow_mut()? = conn;
+        // Statements were prepared on the lost connection
+        self.statements.clear();
+        Ok(())
+    }
+}
+
+/// Tests whether `e` shows the connection to the server was lost,
+/// rather than a request failing on a working connection.
+fn is_lost_connection(e: &postgres::Error) -> bool {
+    e.is_closed()
+        || e.code() == Some(&postgres::error::SqlState::ADMIN_SHUTDOWN)
+        || std::error::Error::source(e).is_some_and(|s| s.is::<std::io::Error>())
+}
+
+/// The capabilities of the server `conn` is connected to, from its
+/// version. Servers which do not report their version, as some forks
+/// do not, are assumed to be recent.
+fn server_capabilities(conn: &mut postgres::Client) -> Result<Capabilities> {
+    let version: Option<String> = conn
+        .query_opt(SERVER_VERSION_SQL, &[])?
+        .and_then(|row| row.get(0));
+    Ok(capabilities_of_version(version))
+}
+
+const SERVER_VERSION_SQL: &str = "SELECT current_setting('server_version_num', true)";
+
+fn capabilities_of_version(version: Option<String>) -> Capabilities {
+    match version.and_then(|v| v.parse().ok()) {
+        Some(version) => Capabilities::pg(version),
+        None => Capabilities::default(),
+    }
+}
+
+/// The statement setting the `search_path` to `search_path`, unless it
+/// is empty.
+fn search_path_sql(search_path: &[String], identifier_case: IdentifierCase) -> Option<String> {
+    if search_path.is_empty() {
+        return None;
+    }
+    let q = quoter(identifier_case);
+    let schemas = search_path
+        .iter()
+        .map(|schema| q(schema))
+        .collect::<Vec<_>>()
+        .join(", ");
+    Some(format!("SET search_path TO {};", schemas))
+}
+
+/// The statements setting `timeouts` with `set`, which is `SET` or
+/// `SET LOCAL`.
+fn timeouts_sql(timeouts: &Timeouts, set: &str) -> String {
+    let mut sql = String::new();
+    if let Some(timeout) = timeouts.statement {
+        write!(sql, "{} statement_timeout = {};", set, timeout.as_millis()).unwrap();
+    }
+    if let Some(timeout) = timeouts.lock {
+        write!(sql, "{} lock_timeout = {};", set, timeout.as_millis()).unwrap();
+    }
+    sql
+}
+
+/// The statements setting up each session: its `search_path` and
+/// timeouts.
+fn session_sql(
+    search_path: &[String],
+    identifier_case: IdentifierCase,
+    timeouts: &Timeouts,
+) -> String {
+    let mut sql = search_path_sql(search_path, identifier_case).unwrap_or_default();
+    sql.push_str(&timeouts_sql(timeouts, "SET"));
+    sql
+}
+
+/// `tls`, unless `config` only connects through Unix-domain sockets,
+/// as with `host=/var/run/postgresql`. The server does not use TLS on
+/// those, so as libpq any TLS options are ignored.
+fn socket_tls(config: &postgres::Config, tls: PgTlsOptions) -> PgTlsOptions {
+    #[cfg(unix)]
+    {
+        use postgres::config::Host;
+        let hosts = config.get_hosts();
+        if !hosts.is_empty() && hosts.iter().all(|host| matches!(host, Host::Unix(_))) {
+            return PgTlsOptions {
+                ssl_mode: Some(SslMode::Disable),
+                ..Default::default()
+            };
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = config;
+    tls
+}
+
+/// Sums of integers are NUMERIC for BIGINT columns, and averages
+/// NUMERIC for integers, so they are cast to the types they are read as.
+fn aggregate_cast(aggregate: &query::AggregateExpr) -> Option<&'static str> {
+    match (aggregate.function, &aggregate.ty) {
+        (query::AggregateFunction::Sum, SqlType::BigInt) => Some("BIGINT"),
+        (query::AggregateFunction::Sum | query::AggregateFunction::Avg, SqlType::Real) => {
+            Some("DOUBLE PRECISION")
         }
-        Ok(postgres::Client::connect(params, connector)?)
+        _ => None,
+    }
+}
+
+/// The `sslmode` of the connection string replacing any given there.
+/// The server's certificate is checked by the [tls_connector].
+fn pg_ssl_mode(tls: &PgTlsOptions) -> Option<postgres::config::SslMode> {
+    use postgres::config::SslMode as PgSslMode;
+    tls.ssl_mode.map(|mode| match mode {
+        SslMode::Disable => PgSslMode::Disable,
+        SslMode::Prefer => PgSslMode::Prefer,
+        SslMode::Require | SslMode::VerifyCa | SslMode::VerifyFull => PgSslMode::Require,
+    })
+}
+
+#[cfg(feature = "tls")]
+fn tls_connector(tls: &PgTlsOptions) -> Result<postgres_native_tls::MakeTlsConnector> {
+    let mut builder = native_tls::TlsConnector::builder();
+    if let Some(path) = &tls.root_cert {
+        builder.add_root_certificate(native_tls::Certificate::from_pem(&fs::read(path)?)?);
+    }
+    match (&tls.client_cert, &tls.client_key) {
+        (Some(cert), Some(key)) => {
+            builder.identity(native_tls::Identity::from_pkcs8(
+                &fs::read(cert)?,
+                &fs::read(key)?,
+            )?);
+        }
+        (None, None) => (),
+        _ => {
+            return Err(Error::InvalidConnectionOption(
+                "client_cert and client_key must be given together".to_string(),
+            ))
+        }
+    }
+    // As libpq, prefer and require only check the certificate against
+    // a root certificate given for the purpose
+    let (verify_cert, verify_hostname) = match tls.ssl_mode {
+        None | Some(SslMode::VerifyFull) => (true, true),
+        Some(SslMode::VerifyCa) => (true, false),
+        Some(SslMode::Disable) | Some(SslMode::Prefer) | Some(SslMode::Require) => {
+            let verify = tls.root_cert.is_some();
+            (verify, false)
+        }
+    };
+    builder.danger_accept_invalid_certs(!verify_cert);
+    builder.danger_accept_invalid_hostnames(!verify_hostname);
+    Ok(postgres_native_tls::MakeTlsConnector::new(builder.build()?))
+}
+
+#[cfg(not(feature = "tls"))]
+fn tls_connector(tls: &PgTlsOptions) -> Result<postgres::NoTls> {
+    if tls.requires_tls() || tls.root_cert.is_some() || tls.client_cert.is_some() {
+        return Err(Error::InvalidConnectionOption(
+            "TLS requires the tls feature".to_string(),
+        ));
+    }
+    Ok(postgres::NoTls)
+}
+
+/// Cancels queries through a separate connection to the server.
+struct PgCancelHandle(postgres::CancelToken, PgTlsOptions);
+impl CancelHandle for PgCancelHandle {
+    fn cancel(&self) -> Result<()> {
+        Ok(self.0.cancel_query(tls_connector(&self.1)?)?)
     }
 }
 impl PgConnectionLike for PgConnection {
     type Client = postgres::Client;
     fn cell(&self) -> Result<&RefCell<Self::Client>> {
+        self.reconnect_if_lost()?;
         Ok(&self.conn)
     }
+    fn identifier_case(&self) -> IdentifierCase {
+        self.identifier_case
+    }
+    fn capabilities(&self) -> Capabilities {
+        self.capabilities
+    }
+    fn statements(&self) -> &StatementCache<postgres::Statement> {
+        &self.statements
+    }
+    fn logger(&self) -> Option<&QueryLogger> {
+        self.logger.as_ref()
+    }
+    fn in_transaction(&self) -> bool {
+        false
+    }
 }
 impl BackendConnection for PgConnection {
-    fn transaction(&mut self) -> Result<Transaction<'_>> {
-        let trans: postgres::Transaction<'_> = self.conn.get_mut().transaction()?;
-        let trans = Box::new(PgTransaction::new(trans));
+    fn transaction_with(&mut self, options: TransactionOptions) -> Result<Transaction<'_>> {
+        self.reconnect_if_lost()?;
+        let mut builder = self
+            .conn
+            .get_mut()
+            .build_transaction()
+            .read_only(options.read_only);
+        if let Some(level) = options.isolation_level {
+            builder = builder.isolation_level(match level {
+                IsolationLevel::ReadCommitted => postgres::IsolationLevel::ReadCommitted,
+                IsolationLevel::RepeatableRead => postgres::IsolationLevel::RepeatableRead,
+                IsolationLevel::Serializable => postgres::IsolationLevel::Serializable,
+            });
+        }
+        let mut trans: postgres::Transaction<'_> = builder.start()?;
+        let timeouts = timeouts_sql(&options.timeouts, "SET LOCAL");
+        if !timeouts.is_empty() {
+            trans.batch_execute(&timeouts)?;
+        }
+        let trans = Box::new(PgTransaction::new(
+            trans,
+            self.identifier_case,
+            self.capabilities,
+            self.statements.clone(),
+            self.logger.clone(),
+        ));
         Ok(Transaction::new(trans))
     }
     fn backend(&self) -> Box<dyn Backend> {
-        Box::new(PgBackend {})
+        Box::new(
+            PgBackend::new()
+                .with_identifier_case(self.identifier_case)
+                .with_search_path(self.search_path.clone())
+                .with_capabilities(Some(self.capabilities))
+                .with_dialect(self.dialect)
+                .with_tls(self.tls.clone())
+                .with_statement_cache_capacity(Some(self.statements.stats().capacity))
+                .with_timeouts(self.timeouts),
+        )
     }
     fn backend_name(&self) -> &'static str {
-        BACKEND_NAME
+        self.dialect.backend_name()
+    }
+    fn capabilities(&self) -> Capabilities {
+        self.capabilities
     }
     fn is_closed(&self) -> bool {
         self.conn.borrow().is_closed()
     }
+    fn introspect(&self) -> Result<ADB> {
+        let mut client = self.cell()?.try_borrow_mut()?;
+        introspect(&mut *client)
+    }
+    fn managed_tables(&self) -> Result<Vec<String>> {
+        let sql = format!(
+            "SELECT cl.relname::text FROM pg_class cl \
+             WHERE cl.relkind IN ('r', 'p') AND cl.relnamespace IN ({}) \
+             AND right(obj_description(cl.oid, 'pg_class'), length($1)) = $1;",
+            INTROSPECT_SCHEMAS
+        );
+        let rows = self
+            .cell()?
+            .try_borrow_mut()?
+            .query(sql.as_str(), &[&MANAGED_MARKER])?;
+        Ok(rows.iter().map(|row| row.get(0)).collect())
+    }
+    fn unmanaged_objects(&self) -> Result<Vec<(String, TableObject)>> {
+        let mut client = self.cell()?.try_borrow_mut()?;
+        let mut objects: Vec<(String, TableObject)> = introspect_indexes(&mut *client)?
+            .into_iter()
+            .filter(|(_, _, managed)| !managed)
+            .map(|(table, index, _)| (table, TableObject::Index(index)))
+            .collect();
+        let sql = format!(
+            "SELECT cl.relname::text, c.contype::text, \
+             ARRAY(SELECT a.attname::text FROM unnest(c.conkey) WITH ORDINALITY k(attnum, i) \
+                   JOIN pg_attribute a ON a.attrelid = c.conrelid AND a.attnum = k.attnum ORDER BY k.i) \
+             FROM pg_constraint c JOIN pg_class cl ON cl.oid = c.conrelid \
+             WHERE c.contype IN ('u', 'f') AND c.connamespace IN ({}) \
+             AND coalesce(obj_description(c.oid, 'pg_constraint'), '') <> $1;",
+            INTROSPECT_SCHEMAS
+        );
+        for row in client.query(sql.as_str(), &[&MANAGED_MARKER])? {
+            let kind: String = row.get(1);
+            let mut columns: Vec<String> = row.get(2);
+            let object = match kind.as_str() {
+                "u" => TableObject::UniqueConstraint(AUniqueConstraint::new(columns)),
+                // Only foreign keys of single columns are introspected
+                _ if columns.len() == 1 => TableObject::ForeignKey(columns.remove(0)),
+                _ => continue,
+            };
+            objects.push((row.get(0), object));
+        }
+        Ok(objects)
+    }
+    fn prepared_transactions(&self) -> Result<Vec<String>> {
+        let rows = self.cell()?.try_borrow_mut()?.query(
+            "SELECT gid FROM pg_prepared_xacts WHERE database = current_database();",
+            &[],
+        )?;
+        Ok(rows.iter().map(|row| row.get(0)).collect())
+    }
+    fn commit_prepared(&self, gid: &str) -> Result<()> {
+        self.execute(&format!("COMMIT PREPARED {};", gid_literal(gid)?))
+    }
+    fn rollback_prepared(&self, gid: &str) -> Result<()> {
+        self.execute(&format!("ROLLBACK PREPARED {};", gid_literal(gid)?))
+    }
+    fn cancel_handle(&self) -> Result<Box<dyn CancelHandle>> {
+        let token = self.cell()?.try_borrow_mut()?.cancel_token();
+        Ok(Box::new(PgCancelHandle(token, self.tls.clone())))
+    }
+    fn statement_cache_stats(&self) -> Option<StatementCacheStats> {
+        Some(self.statements.stats())
+    }
+    fn set_query_logger(&mut self, logger: Option<QueryLogger>) -> Result<()> {
+        self.logger = logger;
+        Ok(())
+    }
+    fn ping(&self) -> Result<()> {
+        // Unlike execute, leaves cached statements in place
+        let result = self.cell()?.try_borrow_mut()?.batch_execute("SELECT 1");
+        match result {
+            // The client may only notice the lost connection once a
+            // request fails
+            Err(e)
+                if self.auto_reconnect
+                    && (is_lost_connection(&e) || self.conn.try_borrow_mut()?.is_closed()) =>
+            {
+                self.reconnect()?;
+                Ok(self.cell()?.try_borrow_mut()?.batch_execute("SELECT 1")?)
+            }
+            result => Ok(result?),
+        }
+    }
+    fn set_auto_reconnect(&mut self, enabled: bool) -> Result<()> {
+        self.auto_reconnect = enabled;
+        Ok(())
+    }
+    fn create_search_index(&self) -> Result<()> {
+        // The simple configuration does not stem, matching SQLite
+        self.execute(&format!(
+            "CREATE TABLE IF NOT EXISTS {0} (model TEXT NOT NULL, pk TEXT NOT NULL, body TEXT NOT NULL, \
+             tsv TSVECTOR GENERATED ALWAYS AS (to_tsvector('simple', body)) STORED, \
+             PRIMARY KEY (model, pk));\n\
+             CREATE INDEX IF NOT EXISTS {0}_tsv ON {0} USING GIN (tsv);",
+            INDEX_TABLE
+        ))
+    }
+    fn search(&self, terms: &str, limit: Option<i32>) -> Result<Vec<SearchHit>> {
+        let limit = match limit {
+            Some(limit) => limit.to_string(),
+            None => "ALL".to_string(),
+        };
+        let sql = format!(
+            "SELECT model, pk, ts_headline('simple', body, q) FROM {}, plainto_tsquery('simple', $1) q \
+             WHERE tsv @@ q ORDER BY ts_rank(tsv, q) DESC LIMIT {};",
+            INDEX_TABLE, limit
+        );
+        debug!("search sql {}", sql);
+        let rows = self
+            .cell()?
+            .try_borrow_mut()?
+            .query(sql.as_str(), &[&terms])?;
+        rows.iter()
+            .map(|row| SearchHit::from_index(row.get(0), row.get(1), row.get(2)))
+            .collect()
+    }
+}
+
+/// The name of a prepared transaction as a string literal.
+fn gid_literal(gid: &str) -> Result<String> {
+    helper::sql_literal_value(SqlVal::Text(gid.to_string()))
+}
+
+/// Schemas on the search path, which are those introspected.
+const INTROSPECT_SCHEMAS: &str =
+    "SELECT oid FROM pg_namespace WHERE nspname = ANY(current_schemas(false))";
+
+fn introspect(client: &mut impl GenericClient) -> Result<ADB> {
+    // Single columns which are primary keys or unique on their own,
+    // keyed by (table, column), and unique constraints spanning
+    // several columns.
+    let mut pks: Vec<(String, String)> = Vec::new();
+    let mut unique: Vec<(String, String)> = Vec::new();
+    let mut unique_constraints: Vec<(String, AUniqueConstraint)> = Vec::new();
+    let sql = format!(
+        "SELECT cl.relname::text, c.contype::text, \
+         ARRAY(SELECT a.attname::text FROM unnest(c.conkey) WITH ORDINALITY k(attnum, i) \
+               JOIN pg_attribute a ON a.attrelid = c.conrelid AND a.attnum = k.attnum ORDER BY k.i) \
+         FROM pg_constraint c JOIN pg_class cl ON cl.oid = c.conrelid \
+         WHERE c.contype IN ('p', 'u') AND c.connamespace IN ({});",
+        INTROSPECT_SCHEMAS
+    );
+    for row in client.query(sql.as_str(), &[])? {
+        let table: String = row.get(0);
+        let kind: String = row.get(1);
+        let mut columns: Vec<String> = row.get(2);
+        if kind == "p" {
+            pks.extend(columns.into_iter().map(|c| (table.clone(), c)));
+        } else if columns.len() == 1 {
+            unique.push((table, columns.remove(0)));
+        } else {
+            unique_constraints.push((table, AUniqueConstraint::new(columns)));
+        }
+    }
+
+    let mut references: HashMap<(String, String), AForeignKey> = HashMap::new();
+    let sql = format!(
+        "SELECT cl.relname::text, a.attname::text, fcl.relname::text, fa.attname::text, \
+         c.confdeltype::text, c.confupdtype::text \
+         FROM pg_constraint c \
+         JOIN pg_class cl ON cl.oid = c.conrelid \
+         JOIN pg_class fcl ON fcl.oid = c.confrelid \
+         JOIN pg_attribute a ON a.attrelid = c.conrelid AND a.attnum = c.conkey[1] \
+         JOIN pg_attribute fa ON fa.attrelid = c.confrelid AND fa.attnum = c.confkey[1] \
+         WHERE c.contype = 'f' AND array_length(c.conkey, 1) = 1 AND c.connamespace IN ({});",
+        INTROSPECT_SCHEMAS
+    );
+    for row in client.query(sql.as_str(), &[])? {
+        let ftable: String = row.get(2);
+        let fcolumn: String = row.get(3);
+        let delete: String = row.get(4);
+        let update: String = row.get(5);
+        let mut reference = AForeignKey::new(ftable.as_str())
+            .with_on_delete(referential_action_from_code(&delete))
+            .with_on_update(referential_action_from_code(&update));
+        // Butane references the primary key implicitly
+        if !pks.contains(&(ftable, fcolumn.clone())) {
+            reference = reference.with_column(fcolumn);
+        }
+        references.insert((row.get(0), row.get(1)), reference);
+    }
+
+    let mut tables: HashMap<String, ATable> = HashMap::new();
+    let sql = format!(
+        "SELECT cl.relname::text FROM pg_class cl \
+         WHERE cl.relkind IN ('r', 'p') AND cl.relnamespace IN ({});",
+        INTROSPECT_SCHEMAS
+    );
+    for row in client.query(sql.as_str(), &[])? {
+        let name: String = row.get(0);
+        tables.insert(name.clone(), ATable::new(name));
+    }
+    let rows = client.query(
+        "SELECT table_name::text, column_name::text, udt_name::text, is_nullable::text, \
+         column_default::text, character_maximum_length::int, numeric_precision::int, \
+         numeric_scale::int, is_identity::text FROM information_schema.columns \
+         WHERE table_schema = ANY(current_schemas(false)) ORDER BY table_name, ordinal_position;",
+        &[],
+    )?;
+    for row in rows {
+        let table_name: String = row.get(0);
+        let table = match tables.get_mut(&table_name) {
+            Some(table) => table,
+            // A view
+            None => continue,
+        };
+        let key = (table_name, row.get::<_, String>(1));
+        let ty: String = row.get(2);
+        let nullable: String = row.get(3);
+        let default: Option<String> = row.get(4);
+        let length: Option<i32> = row.get(5);
+        let precision: Option<i32> = row.get(6);
+        let scale: Option<i32> = row.get(7);
+        let identity: Option<String> = row.get(8);
+        let (typeid, params) = match (ty.as_str(), precision) {
+            ("varchar", _) => (
+                TypeIdentifier::Ty(SqlType::Text),
+                ATypeParams {
+                    length: length.map(|l| l as u32),
+                    ..Default::default()
+                },
+            ),
+            ("numeric", Some(precision)) => (
+                TypeIdentifier::Ty(SqlType::Real),
+                ATypeParams {
+                    precision: Some(precision as u32),
+                    // A scale of zero is left unset, as by butane
+                    scale: scale.filter(|s| *s != 0).map(|s| s as u32),
+                    ..Default::default()
+                },
+            ),
+            _ => (sqltype_from_udt(&ty), ATypeParams::default()),
+        };
+        let mut col = AColumn::new(
+            key.1.as_str(),
+            DeferredSqlType::KnownId(typeid),
+            nullable == "YES",
+            pks.contains(&key),
+            // SERIAL and BIGSERIAL columns default to the next value of a sequence
+            default.is_some_and(|d| d.starts_with("nextval(")),
+            unique.contains(&key),
+            None,
+        );
+        if identity.as_deref() == Some("YES") {
+            col = col.with_auto_increment(AutoIncrement::Identity);
+        }
+        if let Some(reference) = references.remove(&key) {
+            col = col.with_reference(reference);
+        }
+        table.add_column(col.with_params(params));
+    }
+    for (table, constraint) in unique_constraints {
+        if let Some(table) = tables.get_mut(&table) {
+            table.add_unique_constraint(constraint);
+        }
+    }
+
+    for (table, index, _) in introspect_indexes(client)? {
+        if let Some(table) = tables.get_mut(&table) {
+            table.add_index(index);
+        }
+    }
+
+    let mut db = ADB::new();
+    for (_, table) in tables {
+        db.replace_table(table);
+    }
+    Ok(db)
+}
+
+/// The indexes of columns which are not unique, as butane declares
+/// them, each with the name of its table and whether it carries
+/// [MANAGED_MARKER].
+fn introspect_indexes(client: &mut impl GenericClient) -> Result<Vec<(String, AIndex, bool)>> {
+    let sql = format!(
+        "SELECT cl.relname::text, \
+         ARRAY(SELECT a.attname::text FROM unnest(ix.indkey::int2[]) WITH ORDINALITY k(attnum, i) \
+               JOIN pg_attribute a ON a.attrelid = ix.indrelid AND a.attnum = k.attnum ORDER BY k.i), \
+         pg_get_expr(ix.indpred, ix.indrelid), \
+         coalesce(obj_description(ix.indexrelid, 'pg_class'), '') = $1 \
+         FROM pg_index ix JOIN pg_class cl ON cl.oid = ix.indrelid \
+         WHERE NOT ix.indisunique AND ix.indexprs IS NULL AND cl.relnamespace IN ({});",
+        INTROSPECT_SCHEMAS
+    );
+    let mut indexes = Vec::new();
+    for row in client.query(sql.as_str(), &[&MANAGED_MARKER])? {
+        let mut index = AIndex::new(row.get::<_, Vec<String>>(1));
+        if let Some(predicate) = row.get::<_, Option<String>>(2) {
+            index = index.with_predicate(predicate);
+        }
+        indexes.push((row.get(0), index, row.get(3)));
+    }
+    Ok(indexes)
+}
+
+/// The type butane would have declared as the pg type `udt`.
+fn sqltype_from_udt(udt: &str) -> TypeIdentifier {
+    match udt {
+        "bool" => TypeIdentifier::Ty(SqlType::Bool),
+        "int4" => TypeIdentifier::Ty(SqlType::Int),
+        "int8" => TypeIdentifier::Ty(SqlType::BigInt),
+        "float8" => TypeIdentifier::Ty(SqlType::Real),
+        "text" => TypeIdentifier::Ty(SqlType::Text),
+        #[cfg(feature = "datetime")]
+        "timestamp" => TypeIdentifier::Ty(SqlType::Timestamp),
+        "bytea" => TypeIdentifier::Ty(SqlType::Blob),
+        _ => TypeIdentifier::Name(udt.to_string()),
+    }
+}
+
+/// The [ReferentialAction] for a `pg_constraint` action code.
+fn referential_action_from_code(code: &str) -> ReferentialAction {
+    match code {
+        "r" => ReferentialAction::Restrict,
+        "c" => ReferentialAction::Cascade,
+        "n" => ReferentialAction::SetNull,
+        "d" => ReferentialAction::SetDefault,
+        _ => ReferentialAction::NoAction,
+    }
 }
 
 type DynToSqlPg<'a> = (dyn postgres::types::ToSql + Sync + 'a);
@@ -111,6 +828,72 @@ fn sqlvalref_for_pg_query<'a>(v: &'a SqlValRef<'a>) -> &'a dyn postgres::types::
 pub trait PgConnectionLike {
     type Client: postgres::GenericClient;
     fn cell(&self) -> Result<&RefCell<Self::Client>>;
+    fn identifier_case(&self) -> IdentifierCase;
+    fn capabilities(&self) -> Capabilities;
+    fn statements(&self) -> &StatementCache<postgres::Statement>;
+    fn logger(&self) -> Option<&QueryLogger>;
+    /// Whether statements run within a transaction begun by butane.
+    fn in_transaction(&self) -> bool;
+}
+
+/// Run `f`, which runs `sql` with `values` on `conn`, logging it.
+fn logged<'v, T: PgConnectionLike + ?Sized, R>(
+    conn: &T,
+    sql: &str,
+    values: impl IntoIterator<Item = SqlValRef<'v>>,
+    f: impl FnOnce() -> Result<R>,
+) -> Result<R> {
+    let values: Vec<SqlValRef<'v>> = values.into_iter().collect();
+    check_param_count(values.len())?;
+    logging::log(conn.logger(), sql, values, f)
+}
+
+/// The most parameters a statement may have, their number being sent
+/// to the server as a 16-bit integer.
+const MAX_PARAMS: usize = u16::MAX as usize;
+
+/// Fails if a statement with `count` parameters cannot be run.
+fn check_param_count(count: usize) -> Result<()> {
+    if count > MAX_PARAMS {
+        return Err(Error::TooManyParameters(count, BACKEND_NAME, MAX_PARAMS));
+    }
+    Ok(())
+}
+
+/// Run the statement `sql` with `values` on `conn`, returning the
+/// number of rows affected.
+fn run<T: PgConnectionLike + ?Sized>(conn: &T, sql: &str, values: &[SqlValRef<'_>]) -> Result<u64> {
+    logged(conn, sql, values.iter().cloned(), || {
+        let params: Vec<&DynToSqlPg> = values.iter().map(|v| v as &DynToSqlPg).collect();
+        let stmt = prepare_cached(conn, sql, &[])?;
+        Ok(conn
+            .cell()?
+            .try_borrow_mut()?
+            .execute(&stmt, params.as_slice())?)
+    })
+}
+
+/// The statement for `sql`, prepared with parameters of `types` unless
+/// cached by `conn`.
+fn prepare_cached<T: PgConnectionLike + ?Sized>(
+    conn: &T,
+    sql: &str,
+    types: &[postgres::types::Type],
+) -> Result<postgres::Statement> {
+    conn.statements()
+        .get_or_prepare(&statement_key(sql, types), || {
+            Ok(conn.cell()?.try_borrow_mut()?.prepare_typed(sql, types)?)
+        })
+}
+
+/// The key of a statement in the [StatementCache]. Statements prepared
+/// with different parameter types differ.
+fn statement_key<'a>(sql: &'a str, types: &[postgres::types::Type]) -> Cow<'a, str> {
+    if types.is_empty() {
+        Cow::Borrowed(sql)
+    } else {
+        Cow::Owned(format!("{}\n{:?}", sql, types))
+    }
 }
 
 impl<T> ConnectionMethods for T
@@ -121,7 +904,12 @@ where
         if cfg!(feature = "log") {
             debug!("execute sql {}", sql);
         }
-        self.cell()?.try_borrow_mut()?.batch_execute(sql.as_ref())?;
+        logged(self, sql, [], || {
+            Ok(self.cell()?.try_borrow_mut()?.batch_execute(sql)?)
+        })?;
+        // The statement may have changed the schema cached statements
+        // were prepared against
+        self.statements().clear();
         Ok(())
     }
 
@@ -134,54 +922,93 @@ where
         offset: Option<i32>,
         order: Option<&[query::Order]>,
     ) -> Result<RawQueryResult<'a>> {
-        let mut sqlquery = String::new();
-        helper::sql_select(columns, table, &mut sqlquery);
-        let mut values: Vec<SqlVal> = Vec::new();
-        if let Some(expr) = expr {
-            sqlquery.write_str(" WHERE ").unwrap();
-            sql_for_expr(
-                query::Expr::Condition(Box::new(expr)),
-                &mut values,
-                &mut PgPlaceholderSource::new(),
-                &mut sqlquery,
-            );
-        }
-
-        if let Some(order) = order {
-            helper::sql_order(order, &mut sqlquery)
-        }
-
-        if let Some(limit) = limit {
-            helper::sql_limit(limit, &mut sqlquery)
-        }
-
-        if let Some(offset) = offset {
-            helper::sql_offset(offset, &mut sqlquery)
-        }
-
-        if cfg!(feature = "log") {
-            debug!("query sql {}", sqlquery);
-        }
+        let (sqlquery, values) = query_sql(
+            table,
+            columns,
+            None,
+            expr,
+            limit,
+            offset,
+            order,
+            quoter(self.identifier_case()),
+        );
         eprintln!("query sql {}", sqlquery);
 
         let types: Vec<postgres::types::Type> = values.iter().map(pgtype_for_val).collect();
-        let stmt = self
-            .cell()?
-            .try_borrow_mut()?
-            .prepare_typed(&sqlquery, types.as_ref())?;
         // todo avoid intermediate vec?
-        let rowvec: Vec<postgres::Row> = self
-            .cell()?
-            .try_borrow_mut()?
-            .query_raw(&stmt, values.iter().map(sqlval_for_pg_query))?
-            .map_err(Error::Postgres)
-            .map(|r| {
-                check_columns(&r, columns)?;
-                Ok(r)
-            })
-            .collect()?;
+        let rowvec: Vec<postgres::Row> =
+            logged(self, &sqlquery, values.iter().map(SqlVal::as_ref), || {
+                let stmt = prepare_cached(self, &sqlquery, &types)?;
+                self.cell()?
+                    .try_borrow_mut()?
+                    .query_raw(&stmt, values.iter().map(sqlval_for_pg_query))?
+                    .map_err(Error::Postgres)
+                    .map(|r| {
+                        check_columns(&r, columns)?;
+                        Ok(r)
+                    })
+                    .collect()
+            })?;
         Ok(Box::new(VecRows::new(rowvec)))
     }
+    fn query_distinct<'a, 'b, 'c: 'a>(
+        &'c self,
+        table: &str,
+        columns: &'b [Column],
+        distinct: &query::Distinct,
+        expr: Option<BoolExpr>,
+        limit: Option<i32>,
+        offset: Option<i32>,
+        order: Option<&[query::Order]>,
+    ) -> Result<RawQueryResult<'a>> {
+        let (sqlquery, values) = query_sql(
+            table,
+            columns,
+            Some(distinct),
+            expr,
+            limit,
+            offset,
+            order,
+            quoter(self.identifier_case()),
+        );
+
+        let types: Vec<postgres::types::Type> = values.iter().map(pgtype_for_val).collect();
+        let rowvec: Vec<postgres::Row> =
+            logged(self, &sqlquery, values.iter().map(SqlVal::as_ref), || {
+                let stmt = prepare_cached(self, &sqlquery, &types)?;
+                self.cell()?
+                    .try_borrow_mut()?
+                    .query_raw(&stmt, values.iter().map(sqlval_for_pg_query))?
+                    .map_err(Error::Postgres)
+                    .map(|r| {
+                        check_columns(&r, columns)?;
+                        Ok(r)
+                    })
+                    .collect()
+            })?;
+        Ok(Box::new(VecRows::new(rowvec)))
+    }
+    fn query_stream<'a, 'b, 'c: 'a>(
+        &'c self,
+        table: &str,
+        columns: &'b [Column],
+        expr: Option<BoolExpr>,
+        limit: Option<i32>,
+        offset: Option<i32>,
+        order: Option<&[query::Order]>,
+    ) -> Result<RawQueryResult<'a>> {
+        let (sqlquery, values) = query_sql(
+            table,
+            columns,
+            None,
+            expr,
+            limit,
+            offset,
+            order,
+            quoter(self.identifier_case()),
+        );
+        Ok(Box::new(PgCursor::open(self, &sqlquery, &values, columns)?))
+    }
     fn insert_returning_pk(
         &self,
         table: &str,
@@ -189,40 +1016,39 @@ where
         pkcol: &Column,
         values: &[SqlValRef<'_>],
     ) -> Result<SqlVal> {
-        let mut sql = String::new();
-        helper::sql_insert_with_placeholders(
+        let capabilities = self.capabilities();
+        let sql = insert_returning_pk_sql(
             table,
             columns,
-            &mut PgPlaceholderSource::new(),
-            &mut sql,
+            pkcol,
+            capabilities,
+            quoter(self.identifier_case()),
         );
-        write!(&mut sql, " RETURNING {}", pkcol.name()).unwrap();
-        if cfg!(feature = "log") {
-            debug!("insert sql {}", sql);
+        if !capabilities.returning {
+            run(self, &sql, values)?;
+            let lastval_sql = lastval_sql(pkcol);
+            return logged(self, &lastval_sql, [], || {
+                let lastval = prepare_cached(self, &lastval_sql, &[])?;
+                let row = self.cell()?.try_borrow_mut()?.query_one(&lastval, &[])?;
+                sql_val_from_postgres(&row, 0, pkcol)
+            });
         }
 
         // use query instead of execute so we can get our result back
-        let pk: Option<SqlVal> = self
-            .cell()?
-            .try_borrow_mut()?
-            .query_raw(sql.as_str(), values.iter().map(sqlvalref_for_pg_query))?
-            .map_err(Error::Postgres)
-            .map(|r| sql_val_from_postgres(&r, 0, pkcol))
-            .nth(0)?;
+        let pk: Option<SqlVal> = logged(self, &sql, values.iter().cloned(), || {
+            let stmt = prepare_cached(self, &sql, &[])?;
+            self.cell()?
+                .try_borrow_mut()?
+                .query_raw(&stmt, values.iter().map(sqlvalref_for_pg_query))?
+                .map_err(Error::Postgres)
+                .map(|r| sql_val_from_postgres(&r, 0, pkcol))
+                .nth(0)
+        })?;
         pk.ok_or_else(|| Error::Internal("could not get pk".to_string()))
     }
     fn insert_only(&self, table: &str, columns: &[Column], values: &[SqlValRef<'_>]) -> Result<()> {
-        let mut sql = String::new();
-        helper::sql_insert_with_placeholders(
-            table,
-            columns,
-            &mut PgPlaceholderSource::new(),
-            &mut sql,
-        );
-        let params: Vec<&DynToSqlPg> = values.iter().map(|v| v as &DynToSqlPg).collect();
-        self.cell()?
-            .try_borrow_mut()?
-            .execute(sql.as_str(), params.as_slice())?;
+        let sql = insert_sql(table, columns, quoter(self.identifier_case()));
+        run(self, &sql, values)?;
         Ok(())
     }
     fn insert_or_replace<'a>(
@@ -233,11 +1059,14 @@ where
         values: &[SqlValRef<'a>],
     ) -> Result<()> {
         let mut sql = String::new();
-        sql_insert_or_replace_with_placeholders(table, columns, pkcol, &mut sql);
-        let params: Vec<&DynToSqlPg> = values.iter().map(|v| v as &DynToSqlPg).collect();
-        self.cell()?
-            .try_borrow_mut()?
-            .execute(sql.as_str(), params.as_slice())?;
+        sql_insert_or_replace_with_placeholders(
+            table,
+            columns,
+            pkcol,
+            &mut sql,
+            quoter(self.identifier_case()),
+        );
+        run(self, &sql, values)?;
         Ok(())
     }
     fn update(
@@ -248,62 +1077,440 @@ where
         columns: &[Column],
         values: &[SqlValRef<'_>],
     ) -> Result<()> {
-        let mut sql = String::new();
-        helper::sql_update_with_placeholders(
+        let sql = update_sql(table, pkcol, columns, quoter(self.identifier_case()));
+        let placeholder_values = [values, &[pk]].concat();
+        run(self, &sql, &placeholder_values)?;
+        Ok(())
+    }
+    fn delete_where(&self, table: &str, expr: BoolExpr) -> Result<usize> {
+        let (sql, values) = delete_where_sql(table, expr, quoter(self.identifier_case()));
+        let values: Vec<SqlValRef<'_>> = values.iter().map(SqlVal::as_ref).collect();
+        let cnt = run(self, &sql, &values)?;
+        Ok(cnt as usize)
+    }
+    fn update_where(
+        &self,
+        table: &str,
+        assignments: &[query::Assignment],
+        expr: BoolExpr,
+    ) -> Result<usize> {
+        let (sql, values) =
+            update_where_sql(table, assignments, expr, quoter(self.identifier_case()));
+        let values: Vec<SqlValRef<'_>> = values.iter().map(SqlVal::as_ref).collect();
+        let cnt = run(self, &sql, &values)?;
+        Ok(cnt as usize)
+    }
+    fn has_table(&self, table: &str) -> Result<bool> {
+        let table = stored_name(table, self.identifier_case());
+        logged(self, HAS_TABLE_SQL, [SqlValRef::Text(&table)], || {
+            let stmt = prepare_cached(self, HAS_TABLE_SQL, &[])?;
+            let rows = self
+                .cell()?
+                .try_borrow_mut()?
+                .query(&stmt, &[&table.as_ref()])?;
+            Ok(!rows.is_empty())
+        })
+    }
+    fn explain(
+        &self,
+        table: &str,
+        columns: &[Column],
+        expr: Option<BoolExpr>,
+        limit: Option<i32>,
+        offset: Option<i32>,
+        order: Option<&[query::Order]>,
+        analyze: bool,
+    ) -> Result<QueryPlan> {
+        let (sqlquery, values) = query_sql(
             table,
-            pkcol,
             columns,
-            &mut PgPlaceholderSource::new(),
-            &mut sql,
+            None,
+            expr,
+            limit,
+            offset,
+            order,
+            quoter(self.identifier_case()),
+        );
+        let options = if analyze {
+            "ANALYZE, FORMAT JSON"
+        } else {
+            "FORMAT JSON"
+        };
+        let sql = format!("EXPLAIN ({}) {}", options, sqlquery);
+        let types: Vec<postgres::types::Type> = values.iter().map(pgtype_for_val).collect();
+        let plan: serde_json::Value =
+            logged(self, &sql, values.iter().map(SqlVal::as_ref), || {
+                let stmt = prepare_cached(self, &sql, &types)?;
+                let params: Vec<&DynToSqlPg> = values.iter().map(|v| v as &DynToSqlPg).collect();
+                Ok(self
+                    .cell()?
+                    .try_borrow_mut()?
+                    .query_one(&stmt, params.as_slice())?
+                    .try_get(0)?)
+            })?;
+        // The plan is given as [{"Plan": {...}, ...}], with the
+        // timings of the whole query alongside the outermost node
+        let nodes = match plan {
+            serde_json::Value::Array(statements) => statements
+                .into_iter()
+                .filter_map(|statement| match statement {
+                    serde_json::Value::Object(mut statement) => {
+                        let plan = statement.remove("Plan")?;
+                        let mut node = plan_node(plan);
+                        node.properties.append(&mut statement);
+                        Some(node)
+                    }
+                    _ => None,
+                })
+                .collect(),
+            _ => Vec::new(),
+        };
+        Ok(QueryPlan {
+            sql: sqlquery,
+            nodes,
+        })
+    }
+    fn aggregate(
+        &self,
+        table: &str,
+        groups: &[Column],
+        aggregates: &[query::AggregateExpr],
+        expr: Option<BoolExpr>,
+        having: Option<query::Having>,
+    ) -> Result<Vec<Vec<SqlVal>>> {
+        let q = quoter(self.identifier_case());
+        let mut sqlquery = String::new();
+        let mut values: Vec<SqlVal> = Vec::new();
+        let mut pls = PgPlaceholderSource::new();
+        helper::sql_select_aggregates(
+            groups,
+            aggregates,
+            table,
+            |expr, values, pls, w| sql_for_expr(expr, values, pls, w, q),
+            &mut values,
+            &mut pls,
+            &mut sqlquery,
+            q,
+            aggregate_cast,
+        );
+        if let Some(expr) = expr {
+            sqlquery.write_str(" WHERE ").unwrap();
+            sql_for_expr(
+                query::Expr::Condition(Box::new(expr)),
+                &mut values,
+                &mut pls,
+                &mut sqlquery,
+                q,
+            );
+        }
+        helper::sql_group_by(
+            groups,
+            having,
+            |expr, values, pls, w| sql_for_expr(expr, values, pls, w, q),
+            &mut values,
+            &mut pls,
+            &mut sqlquery,
+            q,
         );
-        let placeholder_values = [values, &[pk]].concat();
-        let params: Vec<&DynToSqlPg> = placeholder_values
-            .iter()
-            .map(|v| v as &DynToSqlPg)
-            .collect();
         if cfg!(feature = "log") {
-            debug!("update sql {}", sql);
+            debug!("aggregate sql {}", sqlquery);
         }
-        self.cell()?
-            .try_borrow_mut()?
-            .execute(sql.as_str(), params.as_slice())?;
+
+        let types: Vec<postgres::types::Type> = values.iter().map(pgtype_for_val).collect();
+        let rows: Vec<postgres::Row> =
+            logged(self, &sqlquery, values.iter().map(SqlVal::as_ref), || {
+                let stmt = prepare_cached(self, &sqlquery, &types)?;
+                let params: Vec<&DynToSqlPg> = values.iter().map(|v| v as &DynToSqlPg).collect();
+                Ok(self
+                    .cell()?
+                    .try_borrow_mut()?
+                    .query(&stmt, params.as_slice())?)
+            })?;
+        helper::aggregate_rows(Box::new(VecRows::new(rows)), groups, aggregates)
+    }
+    fn query_raw_rows<'a, 'c: 'a>(
+        &'c self,
+        sql: &str,
+        params: &[SqlVal],
+    ) -> Result<RawQueryResult<'a>> {
+        let sqlquery = helper::sql_raw(sql, params, &mut PgPlaceholderSource::new())?;
+        if cfg!(feature = "log") {
+            debug!("query sql {}", sqlquery);
+        }
+
+        let types: Vec<postgres::types::Type> = params.iter().map(pgtype_for_val).collect();
+        let rows: Vec<postgres::Row> =
+            logged(self, &sqlquery, params.iter().map(SqlVal::as_ref), || {
+                let stmt = prepare_cached(self, &sqlquery, &types)?;
+                let params: Vec<&DynToSqlPg> = params.iter().map(|v| v as &DynToSqlPg).collect();
+                Ok(self
+                    .cell()?
+                    .try_borrow_mut()?
+                    .query(&stmt, params.as_slice())?)
+            })?;
+        Ok(Box::new(VecRows::new(rows)))
+    }
+    fn execute_batch(&self, statements: &[Statement]) -> Result<()> {
+        let prepared = statements
+            .iter()
+            .map(|statement| prepare_cached(self, &statement.sql, &[]))
+            .collect::<Result<Vec<postgres::Statement>>>()?;
+        let mut client = self.cell()?.try_borrow_mut()?;
+        // The synchronous client cannot pipeline, so each statement
+        // waits for the previous. A savepoint if already in a
+        // transaction, rolled back when dropped on error
+        let mut tx = client.transaction()?;
+        for (statement, stmt) in statements.iter().zip(&prepared) {
+            if cfg!(feature = "log") {
+                debug!("batch sql {}", statement.sql);
+            }
+            let params: Vec<&DynToSqlPg> =
+                statement.values.iter().map(|v| v as &DynToSqlPg).collect();
+            logged(
+                self,
+                &statement.sql,
+                statement.values.iter().map(SqlVal::as_ref),
+                || Ok(tx.execute(stmt, params.as_slice())?),
+            )?;
+        }
+        tx.commit()?;
         Ok(())
     }
-    fn delete_where(&self, table: &str, expr: BoolExpr) -> Result<usize> {
-        let mut sql = String::new();
-        let mut values: Vec<SqlVal> = Vec::new();
-        write!(&mut sql, "DELETE FROM {} WHERE ", table).unwrap();
+    fn insert_only_many(
+        &self,
+        table: &str,
+        columns: &[Column],
+        rows: &[Vec<SqlValRef<'_>>],
+    ) -> Result<()> {
+        let sql = insert_sql(table, columns, quoter(self.identifier_case()));
+        self.execute_batch(&helper::statements_for_rows(&sql, rows))
+    }
+}
+
+/// A node of a plan as given by `EXPLAIN (FORMAT JSON)`, described by
+/// its type and relation, with its subplans as children.
+fn plan_node(plan: serde_json::Value) -> PlanNode {
+    let mut properties = match plan {
+        serde_json::Value::Object(properties) => properties,
+        _ => return PlanNode::default(),
+    };
+    let children = match properties.remove("Plans") {
+        Some(serde_json::Value::Array(plans)) => plans.into_iter().map(plan_node).collect(),
+        _ => Vec::new(),
+    };
+    let mut detail = properties
+        .get("Node Type")
+        .and_then(serde_json::Value::as_str)
+        .unwrap_or_default()
+        .to_string();
+    if let Some(relation) = properties
+        .get("Relation Name")
+        .and_then(serde_json::Value::as_str)
+    {
+        write!(detail, " on {}", relation).unwrap();
+    }
+    PlanNode {
+        detail,
+        properties,
+        children,
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn query_sql(
+    table: &str,
+    columns: &[Column],
+    distinct: Option<&query::Distinct>,
+    expr: Option<BoolExpr>,
+    limit: Option<i32>,
+    offset: Option<i32>,
+    order: Option<&[query::Order]>,
+    q: helper::QuoteIdent,
+) -> (String, Vec<SqlVal>) {
+    let mut sqlquery = String::new();
+    let mut values: Vec<SqlVal> = Vec::new();
+    let mut pls = PgPlaceholderSource::new();
+    let f = |expr, values: &mut Vec<SqlVal>, pls: &mut PgPlaceholderSource, w: &mut String| {
+        sql_for_expr(expr, values, pls, w, q)
+    };
+    match distinct {
+        Some(distinct) => helper::sql_select_distinct(
+            columns,
+            distinct,
+            table,
+            f,
+            &mut values,
+            &mut pls,
+            &mut sqlquery,
+            q,
+        ),
+        None => helper::sql_select(columns, table, f, &mut values, &mut pls, &mut sqlquery, q),
+    }
+    if let Some(expr) = expr {
+        sqlquery.write_str(" WHERE ").unwrap();
         sql_for_expr(
             query::Expr::Condition(Box::new(expr)),
             &mut values,
-            &mut PgPlaceholderSource::new(),
-            &mut sql,
+            &mut pls,
+            &mut sqlquery,
+            q,
         );
-        let params: Vec<&DynToSqlPg> = values.iter().map(|v| v as &DynToSqlPg).collect();
-        let cnt = self
-            .cell()?
-            .try_borrow_mut()?
-            .execute(sql.as_str(), params.as_slice())?;
-        Ok(cnt as usize)
     }
-    fn has_table(&self, table: &str) -> Result<bool> {
-        // future improvement, should be schema-aware
-        let stmt = self
-            .cell()?
-            .try_borrow_mut()?
-            .prepare("SELECT table_name FROM information_schema.tables WHERE table_name=$1;")?;
-        let rows = self.cell()?.try_borrow_mut()?.query(&stmt, &[&table])?;
-        Ok(!rows.is_empty())
+
+    if let Some(order) = order {
+        helper::sql_order(order, &mut sqlquery, q)
+    }
+
+    if let Some(limit) = limit {
+        helper::sql_limit(limit, &mut sqlquery)
+    }
+
+    if let Some(offset) = offset {
+        helper::sql_offset(offset, &mut sqlquery)
+    }
+
+    if cfg!(feature = "log") {
+        debug!("query sql {}", sqlquery);
+    }
+    (sqlquery, values)
+}
+
+fn insert_sql(table: &str, columns: &[Column], q: helper::QuoteIdent) -> String {
+    let mut sql = String::new();
+    helper::sql_insert_with_placeholders(
+        table,
+        columns,
+        &mut PgPlaceholderSource::new(),
+        &mut sql,
+        q,
+    );
+    sql
+}
+
+/// The SQL inserting a row and, if the server supports `RETURNING`,
+/// returning its pk. Otherwise the pk is read with [lastval_sql].
+fn insert_returning_pk_sql(
+    table: &str,
+    columns: &[Column],
+    pkcol: &Column,
+    capabilities: Capabilities,
+    q: helper::QuoteIdent,
+) -> String {
+    let mut sql = insert_sql(table, columns, q);
+    if capabilities.returning {
+        write!(&mut sql, " RETURNING {}", q(pkcol.name())).unwrap();
+    }
+    if cfg!(feature = "log") {
+        debug!("insert sql {}", sql);
+    }
+    sql
+}
+
+/// The value most recently taken from a sequence, such as that of the
+/// pk column, by this session.
+fn lastval_sql(pkcol: &Column) -> String {
+    let cast = match pkcol.ty() {
+        SqlType::Int => "INTEGER",
+        _ => "BIGINT",
+    };
+    format!("SELECT CAST(lastval() AS {})", cast)
+}
+
+/// The SQL updating a row, with the placeholders of `columns` followed
+/// by that of the pk.
+fn update_sql(table: &str, pkcol: Column, columns: &[Column], q: helper::QuoteIdent) -> String {
+    let mut sql = String::new();
+    helper::sql_update_with_placeholders(
+        table,
+        pkcol,
+        columns,
+        &mut PgPlaceholderSource::new(),
+        &mut sql,
+        q,
+    );
+    if cfg!(feature = "log") {
+        debug!("update sql {}", sql);
+    }
+    sql
+}
+
+/// The SQL deleting the rows matching `expr`, and the values of its
+/// placeholders.
+fn delete_where_sql(table: &str, expr: BoolExpr, q: helper::QuoteIdent) -> (String, Vec<SqlVal>) {
+    let mut sql = String::new();
+    let mut values: Vec<SqlVal> = Vec::new();
+    write!(&mut sql, "DELETE FROM {} WHERE ", q(table)).unwrap();
+    sql_for_expr(
+        query::Expr::Condition(Box::new(expr)),
+        &mut values,
+        &mut PgPlaceholderSource::new(),
+        &mut sql,
+        q,
+    );
+    (sql, values)
+}
+
+/// The SQL setting `assignments` in the rows matching `expr`, and the
+/// values of its placeholders.
+fn update_where_sql(
+    table: &str,
+    assignments: &[query::Assignment],
+    expr: BoolExpr,
+    q: helper::QuoteIdent,
+) -> (String, Vec<SqlVal>) {
+    let mut sql = String::new();
+    let mut values: Vec<SqlVal> = Vec::new();
+    let mut pls = PgPlaceholderSource::new();
+    helper::sql_update_set(table, assignments, &mut values, &mut pls, &mut sql, q);
+    sql.push_str(" WHERE ");
+    sql_for_expr(
+        query::Expr::Condition(Box::new(expr)),
+        &mut values,
+        &mut pls,
+        &mut sql,
+        q,
+    );
+    if cfg!(feature = "log") {
+        debug!("update sql {}", sql);
+    }
+    (sql, values)
+}
+
+// Only tables visible through the search path count, so that a
+// connection using another schema does not see them.
+const HAS_TABLE_SQL: &str = "SELECT table_name FROM information_schema.tables WHERE table_name=$1 \
+     AND table_schema = ANY(current_schemas(false));";
+
+/// `name` as stored by the server: unquoted names are folded to lower
+/// case.
+fn stored_name(name: &str, identifier_case: IdentifierCase) -> Cow<'_, str> {
+    match identifier_case {
+        IdentifierCase::Fold => Cow::Owned(name.to_lowercase()),
+        IdentifierCase::Preserve => Cow::Borrowed(name),
     }
 }
 
 struct PgTransaction<'c> {
     trans: Option<RefCell<postgres::Transaction<'c>>>,
+    identifier_case: IdentifierCase,
+    capabilities: Capabilities,
+    statements: Arc<StatementCache<postgres::Statement>>,
+    logger: Option<QueryLogger>,
 }
 impl<'c> PgTransaction<'c> {
-    fn new(trans: postgres::Transaction<'c>) -> Self {
+    fn new(
+        trans: postgres::Transaction<'c>,
+        identifier_case: IdentifierCase,
+        capabilities: Capabilities,
+        statements: Arc<StatementCache<postgres::Statement>>,
+        logger: Option<QueryLogger>,
+    ) -> Self {
         PgTransaction {
             trans: Some(RefCell::new(trans)),
+            identifier_case,
+            capabilities,
+            statements,
+            logger,
         }
     }
     fn get(&self) -> Result<&RefCell<postgres::Transaction<'c>>> {
@@ -321,6 +1528,21 @@ impl<'c> PgConnectionLike for PgTransaction<'c> {
     fn cell(&self) -> Result<&RefCell<Self::Client>> {
         self.get()
     }
+    fn identifier_case(&self) -> IdentifierCase {
+        self.identifier_case
+    }
+    fn capabilities(&self) -> Capabilities {
+        self.capabilities
+    }
+    fn statements(&self) -> &StatementCache<postgres::Statement> {
+        &self.statements
+    }
+    fn logger(&self) -> Option<&QueryLogger> {
+        self.logger.as_ref()
+    }
+    fn in_transaction(&self) -> bool {
+        true
+    }
 }
 
 impl<'c> BackendTransaction<'c> for PgTransaction<'c> {
@@ -336,6 +1558,30 @@ impl<'c> BackendTransaction<'c> for PgTransaction<'c> {
             Some(trans) => Ok(trans.into_inner().rollback()?),
         }
     }
+    fn prepare(&mut self, gid: &str) -> Result<()> {
+        match self.trans.take() {
+            None => Err(Self::already_consumed()),
+            Some(trans) => {
+                let mut trans = trans.into_inner();
+                trans.batch_execute(&format!("PREPARE TRANSACTION {};", gid_literal(gid)?))?;
+                // The transaction has been ended by preparing it, this
+                // only releases the client.
+                Ok(trans.commit()?)
+            }
+        }
+    }
+    fn savepoint(&mut self) -> Result<Box<dyn BackendTransaction<'_> + '_>> {
+        let trans = self.trans.as_mut().ok_or_else(Self::already_consumed)?;
+        // A transaction within a transaction is a savepoint
+        let savepoint = trans.get_mut().transaction()?;
+        Ok(Box::new(PgTransaction::new(
+            savepoint,
+            self.identifier_case,
+            self.capabilities,
+            self.statements.clone(),
+            self.logger.clone(),
+        )))
+    }
     // Workaround for https://github.com/rust-lang/rfcs/issues/2765
     fn connection_methods(&self) -> &dyn ConnectionMethods {
         self
@@ -376,7 +1622,16 @@ impl<'a> postgres::types::ToSql for SqlValRef<'a> {
             Bool(b) => b.to_sql_checked(requested_ty, out),
             Int(i) => i.to_sql_checked(requested_ty, out),
             BigInt(i) => i.to_sql_checked(requested_ty, out),
+            Real(r) if *requested_ty == postgres::types::Type::NUMERIC => {
+                Numeric(*r).to_sql(requested_ty, out)
+            }
             Real(r) => r.to_sql_checked(requested_ty, out),
+            // Enum values are sent as their labels, which the text
+            // impl does not accept.
+            Text(t) if matches!(requested_ty.kind(), postgres::types::Kind::Enum(_)) => {
+                out.put(t.as_bytes());
+                Ok(postgres::types::IsNull::No)
+            }
             Text(t) => t.to_sql_checked(requested_ty, out),
             Blob(b) => b.to_sql_checked(requested_ty, out),
             #[cfg(feature = "datetime")]
@@ -393,13 +1648,124 @@ impl<'a> postgres::types::ToSql for SqlValRef<'a> {
             }
         }
     }
-    fn accepts(_ty: &postgres::types::Type) -> bool {
-        // Unfortunately this is a type method rather than an instance
-        // method.  Declare acceptance of all the types we can support
-        // and do the actual checking in the to_sql method
-        true
+    fn accepts(_ty: &postgres::types::Type) -> bool {
+        // Unfortunately this is a type method rather than an instance
+        // method.  Declare acceptance of all the types we can support
+        // and do the actual checking in the to_sql method
+        true
+    }
+    postgres::types::to_sql_checked!();
+}
+
+/// A float stored as a Postgres NUMERIC, for columns declared with a
+/// precision.
+#[derive(Debug)]
+struct Numeric(f64);
+
+const NUMERIC_POS: u16 = 0x0000;
+const NUMERIC_NEG: u16 = 0x4000;
+const NUMERIC_NAN: u16 = 0xC000;
+
+impl postgres::types::ToSql for Numeric {
+    fn to_sql(
+        &self,
+        _ty: &postgres::types::Type,
+        out: &mut bytes::BytesMut,
+    ) -> std::result::Result<
+        postgres::types::IsNull,
+        Box<dyn std::error::Error + 'static + Sync + Send>,
+    > {
+        // The binary format is a sequence of base 10000 digits, with
+        // the weight of the first and the number of decimal digits
+        // after the point.
+        let (sign, weight, dscale, digits) = if self.0.is_nan() {
+            (NUMERIC_NAN, 0, 0, Vec::new())
+        } else if self.0.is_infinite() {
+            return Err(Box::new(Error::OutOfRange));
+        } else {
+            // Display writes floats in full without an exponent
+            let text = self.0.abs().to_string();
+            let (int, frac) = text.split_once('.').unwrap_or((&text, ""));
+            let int = format!("{:0>width$}", int, width = int.len().div_ceil(4) * 4);
+            let frac_digits = format!("{:0<width$}", frac, width = frac.len().div_ceil(4) * 4);
+            let mut digits: Vec<i16> = int
+                .as_bytes()
+                .chunks(4)
+                .chain(frac_digits.as_bytes().chunks(4))
+                .map(|chunk| std::str::from_utf8(chunk).unwrap().parse().unwrap())
+                .collect();
+            let mut weight = (int.len() / 4) as i16 - 1;
+            while digits.last() == Some(&0) {
+                digits.pop();
+            }
+            while digits.first() == Some(&0) {
+                digits.remove(0);
+                weight -= 1;
+            }
+            let sign = if self.0 < 0.0 {
+                NUMERIC_NEG
+            } else {
+                NUMERIC_POS
+            };
+            let weight = if digits.is_empty() { 0 } else { weight };
+            (sign, weight, frac.len() as u16, digits)
+        };
+        out.put_i16(digits.len() as i16);
+        out.put_i16(weight);
+        out.put_u16(sign);
+        out.put_u16(dscale);
+        for digit in digits {
+            out.put_i16(digit);
+        }
+        Ok(postgres::types::IsNull::No)
+    }
+    fn accepts(ty: &postgres::types::Type) -> bool {
+        *ty == postgres::types::Type::NUMERIC
+    }
+    postgres::types::to_sql_checked!();
+}
+
+impl<'a> postgres::types::FromSql<'a> for Numeric {
+    fn from_sql(
+        _ty: &postgres::types::Type,
+        raw: &'a [u8],
+    ) -> std::result::Result<Self, Box<dyn std::error::Error + 'static + Sync + Send>> {
+        let word = |i: usize| -> std::result::Result<[u8; 2], Error> {
+            raw.get(i * 2..i * 2 + 2)
+                .map(|w| [w[0], w[1]])
+                .ok_or_else(|| Error::Internal("truncated numeric value".to_string()))
+        };
+        let ndigits = i16::from_be_bytes(word(0)?) as i32;
+        let weight = i16::from_be_bytes(word(1)?) as i32;
+        let sign = u16::from_be_bytes(word(2)?);
+        if sign == NUMERIC_NAN {
+            return Ok(Numeric(f64::NAN));
+        }
+        let digit = |i: i32| -> std::result::Result<i16, Error> {
+            if i < 0 || i >= ndigits {
+                Ok(0)
+            } else {
+                Ok(i16::from_be_bytes(word(4 + i as usize)?))
+            }
+        };
+        let mut text = String::new();
+        if sign == NUMERIC_NEG {
+            text.push('-');
+        }
+        text.push('0');
+        for i in 0..=weight {
+            write!(text, "{:04}", digit(i)?).unwrap();
+        }
+        text.push('.');
+        for i in (weight + 1)..ndigits {
+            write!(text, "{:04}", digit(i)?).unwrap();
+        }
+        text.push('0');
+        Ok(Numeric(text.parse()?))
+    }
+    fn accepts(ty: &postgres::types::Type) -> bool {
+        *ty == postgres::types::Type::NUMERIC
     }
-    postgres::types::to_sql_checked!();
 }
 
 fn check_type_match(
@@ -427,14 +1793,17 @@ impl<'a> postgres::types::FromSql<'a> for SqlValRef<'a> {
             Type::INT4 => Ok(SqlValRef::Int(i32::from_sql(ty, raw)?)),
             Type::INT8 => Ok(SqlValRef::BigInt(i64::from_sql(ty, raw)?)),
             Type::FLOAT8 => Ok(SqlValRef::Real(f64::from_sql(ty, raw)?)),
-            Type::TEXT => Ok(SqlValRef::Text(postgres::types::FromSql::from_sql(
-                ty, raw,
-            )?)),
+            Type::TEXT | Type::VARCHAR | Type::NAME => Ok(SqlValRef::Text(
+                postgres::types::FromSql::from_sql(ty, raw)?,
+            )),
             Type::BYTEA => Ok(SqlValRef::Blob(postgres::types::FromSql::from_sql(
                 ty, raw,
             )?)),
             #[cfg(feature = "datetime")]
             Type::TIMESTAMP => Ok(SqlValRef::Timestamp(NaiveDateTime::from_sql(ty, raw)?)),
+            _ if matches!(ty.kind(), postgres::types::Kind::Enum(_)) => {
+                Ok(SqlValRef::Text(std::str::from_utf8(raw)?))
+            }
             _ => Ok(SqlValRef::Custom(SqlValRefCustom::PgBytes {
                 ty: ty.clone(),
                 data: raw,
@@ -470,8 +1839,110 @@ fn check_columns(row: &postgres::Row, cols: &[Column]) -> Result<()> {
     }
 }
 
+/// The number of rows [PgCursor] fetches at a time.
+const CURSOR_BATCH: usize = 1000;
+
+/// Distinguishes the cursors of a process from one another.
+static CURSOR_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// Rows of a query fetched in batches through a cursor. Cursors only
+/// exist within a transaction, so one is begun for the cursor if there
+/// is none, and committed when the cursor is dropped.
+struct PgCursor<'c, T: PgConnectionLike + ?Sized> {
+    conn: &'c T,
+    name: String,
+    own_transaction: bool,
+    rows: std::vec::IntoIter<postgres::Row>,
+    current: Option<postgres::Row>,
+    exhausted: bool,
+}
+impl<'c, T: PgConnectionLike + ?Sized> PgCursor<'c, T> {
+    /// Declare a cursor for `sql` with `values` on `conn`, and fetch its
+    /// first batch, whose rows should have `columns`.
+    fn open(conn: &'c T, sql: &str, values: &[SqlVal], columns: &[Column]) -> Result<Self> {
+        let mut cursor = PgCursor {
+            conn,
+            name: format!(
+                "butane_cursor_{}",
+                CURSOR_COUNT.fetch_add(1, Ordering::Relaxed)
+            ),
+            own_transaction: false,
+            rows: Vec::new().into_iter(),
+            current: None,
+            exhausted: false,
+        };
+        if !conn.in_transaction() {
+            cursor.batch("BEGIN")?;
+            cursor.own_transaction = true;
+        }
+        let declare = format!("DECLARE {} NO SCROLL CURSOR FOR {}", cursor.name, sql);
+        let types: Vec<postgres::types::Type> = values.iter().map(pgtype_for_val).collect();
+        logged(conn, &declare, values.iter().map(SqlVal::as_ref), || {
+            let mut client = conn.cell()?.try_borrow_mut()?;
+            // Not cached, as the name of each cursor differs
+            let stmt = client.prepare_typed(&declare, &types)?;
+            let params: Vec<&DynToSqlPg> = values.iter().map(|v| v as &DynToSqlPg).collect();
+            client.execute(&stmt, params.as_slice())?;
+            Ok(())
+        })?;
+        cursor.fetch()?;
+        if let Some(row) = cursor.rows.as_slice().first() {
+            check_columns(row, columns)?;
+        }
+        Ok(cursor)
+    }
+    fn fetch(&mut self) -> Result<()> {
+        let sql = format!("FETCH {} FROM {}", CURSOR_BATCH, self.name);
+        let conn = self.conn;
+        let rows = logged(conn, &sql, [], || {
+            Ok(conn.cell()?.try_borrow_mut()?.query(sql.as_str(), &[])?)
+        })?;
+        self.exhausted = rows.len() < CURSOR_BATCH;
+        self.rows = rows.into_iter();
+        Ok(())
+    }
+    fn batch(&self, sql: &str) -> Result<()> {
+        logged(self.conn, sql, [], || {
+            Ok(self.conn.cell()?.try_borrow_mut()?.batch_execute(sql)?)
+        })
+    }
+}
+impl<T: PgConnectionLike + ?Sized> BackendRows for PgCursor<'_, T> {
+    fn next(&mut self) -> Result<Option<&dyn BackendRow>> {
+        if self.rows.len() == 0 && !self.exhausted {
+            self.fetch()?;
+        }
+        self.current = self.rows.next();
+        Ok(self.current.as_ref().map(|row| row as &dyn BackendRow))
+    }
+    fn current(&self) -> Option<&dyn BackendRow> {
+        self.current.as_ref().map(|row| row as &dyn BackendRow)
+    }
+}
+impl<T: PgConnectionLike + ?Sized> Drop for PgCursor<'_, T> {
+    fn drop(&mut self) {
+        // Committing a transaction closes its cursors. Errors cannot
+        // be reported from a drop, so are ignored.
+        let end = if self.own_transaction {
+            Cow::Borrowed("COMMIT")
+        } else {
+            Cow::Owned(format!("CLOSE {}", self.name))
+        };
+        let _ = self.batch(&end);
+    }
+}
+
 impl BackendRow for postgres::Row {
-    fn get(&self, idx: usize, _ty: SqlType) -> Result<SqlValRef> {
+    fn get(&self, idx: usize, ty: SqlType) -> Result<SqlValRef> {
+        // Real columns with a precision are stored as NUMERIC
+        if ty == SqlType::Real
+            && self.columns().get(idx).map(|c| c.type_()) == Some(&postgres::types::Type::NUMERIC)
+        {
+            return Ok(match self.try_get::<_, Option<Numeric>>(idx)? {
+                Some(Numeric(r)) => SqlValRef::Real(r),
+                None => SqlValRef::Null,
+            });
+        }
         Ok(self.try_get(idx)?)
     }
     fn len(&self) -> usize {
@@ -484,10 +1955,153 @@ fn sql_for_expr<W>(
     values: &mut Vec<SqlVal>,
     pls: &mut PgPlaceholderSource,
     w: &mut W,
+    q: helper::QuoteIdent,
 ) where
     W: Write,
 {
-    helper::sql_for_expr(expr, sql_for_expr, values, pls, w)
+    let expr = match expr {
+        query::Expr::Condition(c) => match *c {
+            query::BoolExpr::ILike(col, ex) => {
+                write!(w, "{} ILIKE ", q(col)).unwrap();
+                sql_for_expr(ex, values, pls, w, q);
+                return;
+            }
+            query::BoolExpr::JsonEq { col, path, value } => {
+                sql_json_path(col, &path, values, pls, w, q);
+                write!(w, " = ").unwrap();
+                sql_json_value(value, values, pls, w, q);
+                return;
+            }
+            query::BoolExpr::JsonContains { col, path, value } => {
+                sql_json_path(col, &path, values, pls, w, q);
+                write!(w, " @> ").unwrap();
+                sql_json_value(value, values, pls, w, q);
+                return;
+            }
+            query::BoolExpr::In(col, vals) if helper::in_list_as_param(&vals) => {
+                match pg_array(&vals) {
+                    Some((array, text)) => {
+                        // Text may also be compared with enum columns
+                        let cast = if text { "::text" } else { "" };
+                        write!(w, "{}{} = ANY(", q(col), cast).unwrap();
+                        sql_for_expr(query::Expr::Val(array), values, pls, w, q);
+                        write!(w, ")").unwrap();
+                        return;
+                    }
+                    None => query::Expr::Condition(Box::new(query::BoolExpr::In(col, vals))),
+                }
+            }
+            c => query::Expr::Condition(Box::new(c)),
+        },
+        expr => expr,
+    };
+    helper::sql_for_expr(
+        expr,
+        |expr, values, pls, w| sql_for_expr(expr, values, pls, w, q),
+        values,
+        pls,
+        w,
+        q,
+    )
+}
+
+/// `vals` as a single array value, and whether they are text, if all
+/// are of the same type and not null.
+fn pg_array(vals: &[SqlVal]) -> Option<(SqlVal, bool)> {
+    use postgres::types::{ToSql, Type};
+    let ty = match vals.first()?.sqltype()? {
+        SqlType::Bool => Type::BOOL_ARRAY,
+        SqlType::Int => Type::INT4_ARRAY,
+        SqlType::BigInt => Type::INT8_ARRAY,
+        SqlType::Real => Type::FLOAT8_ARRAY,
+        SqlType::Text => Type::TEXT_ARRAY,
+        SqlType::Blob => Type::BYTEA_ARRAY,
+        #[cfg(feature = "datetime")]
+        SqlType::Timestamp => Type::TIMESTAMP_ARRAY,
+        SqlType::Custom(_) => return None,
+    };
+    let first = std::mem::discriminant(&vals[0]);
+    if vals.iter().any(|val| std::mem::discriminant(val) != first) {
+        return None;
+    }
+    let mut data = bytes::BytesMut::new();
+    vals.to_sql(&ty, &mut data).ok()?;
+    let text = ty == Type::TEXT_ARRAY;
+    let array = SqlVal::Custom(Box::new(SqlValCustom::Pg {
+        ty,
+        data: data.to_vec(),
+    }));
+    Some((array, text))
+}
+
+/// Writes the value at `path` within the JSON stored as text in `col`,
+/// as `jsonb`.
+fn sql_json_path<W: Write>(
+    col: &'static str,
+    path: &[query::JsonKey],
+    values: &mut Vec<SqlVal>,
+    pls: &mut PgPlaceholderSource,
+    w: &mut W,
+    q: helper::QuoteIdent,
+) {
+    write!(w, "({}::jsonb", q(col)).unwrap();
+    for key in path {
+        match key {
+            query::JsonKey::Field(name) => {
+                write!(w, " -> ").unwrap();
+                sql_for_expr(
+                    query::Expr::Val(SqlVal::Text(name.clone())),
+                    values,
+                    pls,
+                    w,
+                    q,
+                );
+                // Both text and integer keys are accepted by `->`
+                write!(w, "::text").unwrap();
+            }
+            query::JsonKey::Index(index) => write!(w, " -> {}", index).unwrap(),
+        }
+    }
+    write!(w, ")").unwrap();
+}
+
+fn sql_json_value<W: Write>(
+    value: serde_json::Value,
+    values: &mut Vec<SqlVal>,
+    pls: &mut PgPlaceholderSource,
+    w: &mut W,
+    q: helper::QuoteIdent,
+) {
+    sql_for_expr(
+        query::Expr::Val(SqlVal::Text(value.to_string())),
+        values,
+        pls,
+        w,
+        q,
+    );
+    write!(w, "::text::jsonb").unwrap();
+}
+
+/// The [QuoteIdent][helper::QuoteIdent] for `identifier_case`.
+fn quoter(identifier_case: IdentifierCase) -> helper::QuoteIdent {
+    match identifier_case {
+        IdentifierCase::Fold => helper::bare_ident,
+        IdentifierCase::Preserve => quote_preserving_case,
+    }
+}
+
+/// Quote `name` unless it is a lower case identifier, which Pg treats
+/// the same whether quoted or not.
+fn quote_preserving_case(name: &str) -> Cow<'_, str> {
+    let plain = name.starts_with(|c: char| c.is_ascii_lowercase() || c == '_')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_');
+    if plain {
+        Cow::Borrowed(name)
+    } else {
+        Cow::Owned(format!("\"{}\"", name.replace('"', "\"\"")))
+    }
 }
 
 fn sql_val_from_postgres<I>(row: &postgres::Row, idx: I, col: &Column) -> Result<SqlVal>
@@ -510,58 +2124,482 @@ where
     }
 }
 
-fn sql_for_op(current: &mut ADB, op: &Operation) -> Result<String> {
+fn migration_sql(
+    current: &ADB,
+    ops: Vec<Operation>,
+    idempotent: bool,
+    capabilities: Capabilities,
+    q: helper::QuoteIdent,
+) -> Result<String> {
+    let mut current: ADB = (*current).clone();
+    Ok(ops
+        .into_iter()
+        .map(|o| {
+            let sql = sql_for_op(&mut current, &o, idempotent, capabilities, q);
+            current.transform_with(o);
+            sql
+        })
+        .collect::<Result<Vec<String>>>()?
+        .join("\n"))
+}
+
+pub(super) fn sql_for_op(
+    current: &mut ADB,
+    op: &Operation,
+    idempotent: bool,
+    capabilities: Capabilities,
+    q: helper::QuoteIdent,
+) -> Result<String> {
     match op {
-        Operation::AddTable(table) => Ok(create_table(table, false)?),
-        Operation::AddTableIfNotExists(table) => Ok(create_table(table, true)?),
-        Operation::RemoveTable(name) => Ok(drop_table(name)),
-        Operation::AddColumn(tbl, col) => add_column(tbl, col),
-        Operation::RemoveColumn(tbl, name) => Ok(remove_column(tbl, name)),
-        Operation::ChangeColumn(tbl, old, new) => change_column(current, tbl, old, Some(new)),
+        Operation::AddTable(table) => create_table_and_indexes(table, current, idempotent, q),
+        Operation::AddTableIfNotExists(table) => create_table_and_indexes(table, current, true, q),
+        Operation::RemoveTable(name) => Ok(drop_table(&table_ident(current, name, q), idempotent)),
+        Operation::AddColumn(tbl, col) => {
+            add_column(tbl, col, current, idempotent, capabilities, q)
+        }
+        Operation::RemoveColumn(tbl, name) => Ok(remove_column(
+            &table_ident(current, tbl, q),
+            name,
+            idempotent,
+            q,
+        )),
+        Operation::ChangeColumn(tbl, old, new) => change_column(current, tbl, old, new, q),
+        Operation::ChangeColumnDefault(tbl, name, default) => {
+            change_column_default(&table_ident(current, tbl, q), name, default, q)
+        }
+        Operation::ChangeColumnDefaultExpr(tbl, name, expr) => Ok(change_column_default_expr(
+            &table_ident(current, tbl, q),
+            name,
+            expr.as_deref(),
+            q,
+        )),
+        Operation::AddUniqueConstraint(tbl, constraint) => Ok(add_constraint(
+            &table_ident(current, tbl, q),
+            &constraint.name(tbl),
+            &define_unique_constraint(tbl, constraint, q),
+            idempotent,
+            q,
+        )),
+        Operation::RemoveUniqueConstraint(tbl, constraint) => Ok(drop_constraint(
+            &table_ident(current, tbl, q),
+            &constraint.name(tbl),
+            idempotent,
+            q,
+        )),
+        Operation::AddCheck(tbl, name, expr) => Ok(add_constraint(
+            &table_ident(current, tbl, q),
+            name,
+            &define_check(&ACheckConstraint::new(name, expr), q),
+            idempotent,
+            q,
+        )),
+        Operation::RemoveCheck(tbl, name) => Ok(drop_constraint(
+            &table_ident(current, tbl, q),
+            name,
+            idempotent,
+            q,
+        )),
+        Operation::AddIndex(tbl, index) => Ok(create_index(
+            table_schema(current, tbl),
+            tbl,
+            index,
+            idempotent,
+            q,
+        )),
+        Operation::RemoveIndex(tbl, index) => Ok(drop_index(
+            table_schema(current, tbl),
+            tbl,
+            index,
+            idempotent,
+            q,
+        )),
+        Operation::AddForeignKey(tbl, column, reference) => Ok(add_constraint(
+            &table_ident(current, tbl, q),
+            &foreign_key_name(tbl, column),
+            &define_foreign_key(tbl, column, reference, current, q),
+            idempotent,
+            q,
+        )),
+        Operation::RemoveForeignKey(tbl, column) => Ok(drop_constraint(
+            &table_ident(current, tbl, q),
+            &foreign_key_name(tbl, column),
+            idempotent,
+            q,
+        )),
+        Operation::SetNotNull(tbl, name, backfill) => Ok(set_not_null(
+            &table_ident(current, tbl, q),
+            name,
+            backfill.as_ref(),
+            q,
+        )),
+        Operation::RenameTable(old, new) => Ok(rename_table(current, old, new, idempotent, q)),
+        Operation::RenameColumn(tbl, old, new) => {
+            Ok(rename_column(current, tbl, old, new, idempotent, q))
+        }
+        Operation::SetTableSchema(tbl, schema) => set_table_schema(
+            &table_ident(current, tbl, q),
+            schema.as_deref(),
+            idempotent,
+            q,
+        ),
+        Operation::SetTableComment(tbl, comment) => comment_on(
+            &format!("TABLE {}", table_ident(current, tbl, q)),
+            Some(&managed_comment(comment.as_deref())),
+        ),
+        Operation::SetColumnComment(tbl, name, comment) => comment_on(
+            &format!("COLUMN {}.{}", table_ident(current, tbl, q), q(name)),
+            comment.as_deref(),
+        ),
+        Operation::AddView(view) => Ok(create_view(view, idempotent, q)),
+        Operation::RemoveView(name) => Ok(drop_view(name, idempotent, q)),
+        Operation::ReplaceView(view) => Ok(format!(
+            "{}\n{}",
+            drop_view(&view.name, idempotent, q),
+            create_view(view, idempotent, q)
+        )),
+        Operation::AddEnum(e) => create_enum(e, idempotent, q),
+        Operation::RemoveEnum(name) => Ok(drop_enum(name, idempotent, q)),
+        Operation::AddEnumValue(name, value) => add_enum_value(name, value, idempotent, q),
+    }
+}
+
+/// `name` qualified with `schema`, if any, for use in SQL.
+fn qualified(schema: Option<&str>, name: &str, q: helper::QuoteIdent) -> String {
+    match schema {
+        Some(schema) => format!("{}.{}", q(schema), q(name)),
+        None => q(name).into_owned(),
     }
 }
 
-fn create_table(table: &ATable, allow_exists: bool) -> Result<String> {
+fn table_schema<'a>(db: &'a ADB, name: &str) -> Option<&'a str> {
+    db.get_table(name).and_then(|t| t.schema.as_deref())
+}
+
+/// The table `name` of `db` as written in SQL.
+pub(super) fn table_ident(db: &ADB, name: &str, q: helper::QuoteIdent) -> String {
+    qualified(table_schema(db, name), name, q)
+}
+
+/// Creates `table`, which may reference the tables of `db`.
+fn create_table(
+    table: &ATable,
+    db: &ADB,
+    allow_exists: bool,
+    q: helper::QuoteIdent,
+) -> Result<String> {
+    let composite_pk = helper::sql_composite_pk(table, q);
+    let inline_pk = composite_pk.is_none();
     let coldefs = table
         .columns
         .iter()
-        .map(define_column)
+        .map(|col| define_column(col, &table.name, inline_pk, db, q))
+        .chain(composite_pk.map(Ok))
+        .chain(
+            table
+                .unique_constraints
+                .iter()
+                .map(|c| Ok(define_unique_constraint(&table.name, c, q))),
+        )
+        .chain(table.checks.iter().map(|c| Ok(define_check(c, q))))
         .collect::<Result<Vec<String>>>()?
         .join(",\n");
     let modifier = if allow_exists { "IF NOT EXISTS " } else { "" };
-    Ok(format!(
+    let tbl = qualified(table.schema.as_deref(), &table.name, q);
+    let mut stmts = vec![format!(
         "CREATE TABLE {}{} (\n{}\n);",
-        modifier, table.name, coldefs
-    ))
+        modifier, tbl, coldefs
+    )];
+    stmts.push(comment_on(
+        &format!("TABLE {}", tbl),
+        Some(&managed_comment(table.comment.as_deref())),
+    )?);
+    let constraints = derived_constraint_names(table, &table.name)
+        .into_iter()
+        .chain(table.checks.iter().map(|c| c.name().to_string()));
+    for name in constraints {
+        stmts.push(mark_constraint(&tbl, &name, q));
+    }
+    for col in &table.columns {
+        if let Some(comment) = col.comment() {
+            stmts.push(comment_on(
+                &format!("COLUMN {}.{}", tbl, q(col.name())),
+                Some(comment),
+            )?);
+        }
+    }
+    Ok(stmts.join("\n"))
+}
+
+/// The comment stored for a table documented with `comment`, ending
+/// with [MANAGED_MARKER] on its own line.
+fn managed_comment(comment: Option<&str>) -> String {
+    match comment {
+        Some(comment) => format!("{}\n{}", comment, MANAGED_MARKER),
+        None => MANAGED_MARKER.to_string(),
+    }
+}
+
+/// Sets the comment on `object`, such as `TABLE foo`, or removes it if
+/// `None`.
+fn comment_on(object: &str, comment: Option<&str>) -> Result<String> {
+    let comment = match comment {
+        Some(comment) => helper::sql_literal_value(SqlVal::Text(comment.to_string()))?,
+        None => "NULL".to_string(),
+    };
+    Ok(format!("COMMENT ON {} IS {};", object, comment))
+}
+
+fn create_table_and_indexes(
+    table: &ATable,
+    db: &ADB,
+    allow_exists: bool,
+    q: helper::QuoteIdent,
+) -> Result<String> {
+    Ok(table
+        .schema
+        .iter()
+        .map(|schema| create_schema(schema, q))
+        .chain(std::iter::once(create_table(table, db, allow_exists, q)?))
+        .chain(create_indexes(table, allow_exists, q))
+        .collect::<Vec<String>>()
+        .join("\n"))
+}
+
+fn create_schema(schema: &str, q: helper::QuoteIdent) -> String {
+    format!("CREATE SCHEMA IF NOT EXISTS {};", q(schema))
+}
+
+/// Moves the table `tbl` (as written in SQL) to `schema`, or to the
+/// current schema if `None`.
+fn set_table_schema(
+    tbl: &str,
+    schema: Option<&str>,
+    idempotent: bool,
+    q: helper::QuoteIdent,
+) -> Result<String> {
+    match schema {
+        Some(schema) => Ok(format!(
+            "{}\nALTER TABLE {}{} SET SCHEMA {};",
+            create_schema(schema, q),
+            if_exists(idempotent),
+            tbl,
+            q(schema)
+        )),
+        // The current schema cannot be named directly in ALTER TABLE
+        None => Ok(format!(
+            "DO $$ BEGIN EXECUTE format('ALTER TABLE {}%s SET SCHEMA %I', {}, current_schema()); END $$;",
+            if_exists(idempotent),
+            helper::sql_literal_value(SqlVal::Text(tbl.to_string()))?
+        )),
+    }
+}
+
+fn create_indexes(
+    table: &ATable,
+    allow_exists: bool,
+    q: helper::QuoteIdent,
+) -> impl Iterator<Item = String> + '_ {
+    table.indexes.iter().map(move |index| {
+        create_index(table.schema.as_deref(), &table.name, index, allow_exists, q)
+    })
 }
 
-fn define_column(col: &AColumn) -> Result<String> {
+/// Creates `index` on the table `tbl_name` in `schema`, where the index
+/// is also created.
+fn create_index(
+    schema: Option<&str>,
+    tbl_name: &str,
+    index: &AIndex,
+    allow_exists: bool,
+    q: helper::QuoteIdent,
+) -> String {
+    let modifier = if allow_exists { "IF NOT EXISTS " } else { "" };
+    let name = index.name(tbl_name);
+    format!(
+        "CREATE INDEX {}{} ON {} ({}){};\nCOMMENT ON INDEX {} IS '{}';",
+        modifier,
+        q(&name),
+        qualified(schema, tbl_name, q),
+        index
+            .columns()
+            .iter()
+            .map(|c| q(c))
+            .collect::<Vec<_>>()
+            .join(", "),
+        helper::sql_index_predicate(index),
+        qualified(schema, &name, q),
+        MANAGED_MARKER
+    )
+}
+
+fn drop_index(
+    schema: Option<&str>,
+    tbl_name: &str,
+    index: &AIndex,
+    idempotent: bool,
+    q: helper::QuoteIdent,
+) -> String {
+    format!(
+        "DROP INDEX {}{};",
+        if_exists(idempotent),
+        qualified(schema, &index.name(tbl_name), q)
+    )
+}
+
+/// The definition of `col` of the table `tbl_name`, declaring it the
+/// primary key if it is part of the key and `inline_pk`. It may
+/// reference the tables of `db`.
+fn define_column(
+    col: &AColumn,
+    tbl_name: &str,
+    inline_pk: bool,
+    db: &ADB,
+    q: helper::QuoteIdent,
+) -> Result<String> {
     let mut constraints: Vec<String> = Vec::new();
     if !col.nullable() {
         constraints.push("NOT NULL".to_string());
     }
-    if col.is_pk() {
+    if col.is_pk() && inline_pk {
         constraints.push("PRIMARY KEY".to_string());
     }
+    if col.auto_increment() == Some(AutoIncrement::Identity) {
+        constraints.push("GENERATED BY DEFAULT AS IDENTITY".to_string());
+    }
     if col.unique() {
-        constraints.push("UNIQUE".to_string());
+        constraints.push(format!(
+            "CONSTRAINT {} UNIQUE",
+            q(&unique_key_name(tbl_name, col.name()))
+        ));
+    }
+    if let Some(reference) = col.reference() {
+        let table = table_ident(db, reference.table(), q);
+        constraints.push(format!(
+            "CONSTRAINT {} {}",
+            q(&foreign_key_name(tbl_name, col.name())),
+            helper::sql_references(reference, &table, q)
+        ));
+    }
+    if let Some(expr) = col.default_expr() {
+        constraints.push(format!("DEFAULT ({})", expr));
+    } else if let Some(default) = col.default() {
+        constraints.push(format!(
+            "DEFAULT {}",
+            helper::sql_literal_value(default.clone())?
+        ));
     }
     Ok(format!(
         "{} {} {}",
-        &col.name(),
-        col_sqltype(col)?,
+        q(col.name()),
+        col_sqltype(col, q)?,
         constraints.join(" ")
     ))
 }
 
-fn col_sqltype(col: &AColumn) -> Result<Cow<str>> {
+fn define_unique_constraint(
+    tbl_name: &str,
+    constraint: &AUniqueConstraint,
+    q: helper::QuoteIdent,
+) -> String {
+    format!(
+        "CONSTRAINT {} UNIQUE ({})",
+        q(&constraint.name(tbl_name)),
+        constraint
+            .columns()
+            .iter()
+            .map(|c| q(c))
+            .collect::<Vec<_>>()
+            .join(", ")
+    )
+}
+
+/// The name of the constraint making the column `column` of the table
+/// `tbl_name` unique on its own, as Postgres would name it.
+fn unique_key_name(tbl_name: &str, column: &str) -> String {
+    format!("{}_{}_key", tbl_name, column)
+}
+
+/// The name of the foreign key constraint of the column `column` of
+/// the table `tbl_name`, as Postgres would name it.
+fn foreign_key_name(tbl_name: &str, column: &str) -> String {
+    format!("{}_{}_fkey", tbl_name, column)
+}
+
+/// The names of the constraints of `table` which are derived from the
+/// name `tbl_name`: those declared with its columns and its unique
+/// constraints.
+fn derived_constraint_names(table: &ATable, tbl_name: &str) -> Vec<String> {
+    let mut names = Vec::new();
+    for col in &table.columns {
+        if col.unique() {
+            names.push(unique_key_name(tbl_name, col.name()));
+        }
+        if col.reference().is_some() {
+            names.push(foreign_key_name(tbl_name, col.name()));
+        }
+    }
+    names.extend(table.unique_constraints.iter().map(|c| c.name(tbl_name)));
+    names
+}
+
+/// Marks the constraint `name` of the table `tbl` (as written in SQL)
+/// as created by butane.
+fn mark_constraint(tbl: &str, name: &str, q: helper::QuoteIdent) -> String {
+    format!(
+        "COMMENT ON CONSTRAINT {} ON {} IS '{}';",
+        q(name),
+        tbl,
+        MANAGED_MARKER
+    )
+}
+
+fn define_foreign_key(
+    tbl_name: &str,
+    column: &str,
+    reference: &AForeignKey,
+    db: &ADB,
+    q: helper::QuoteIdent,
+) -> String {
+    format!(
+        "CONSTRAINT {} FOREIGN KEY ({}) {}",
+        q(&foreign_key_name(tbl_name, column)),
+        q(column),
+        helper::sql_references(reference, &table_ident(db, reference.table(), q), q)
+    )
+}
+
+fn define_check(check: &ACheckConstraint, q: helper::QuoteIdent) -> String {
+    format!("CONSTRAINT {} CHECK ({})", q(check.name()), check.expr())
+}
+
+pub(super) fn col_sqltype(col: &AColumn, q: helper::QuoteIdent) -> Result<Cow<'_, str>> {
     match col.typeid()? {
+        TypeIdentifier::Name(_) | TypeIdentifier::Enum(_) if !col.params().is_empty() => {
+            Err(Error::InvalidTypeParams(col.name().to_string()))
+        }
         TypeIdentifier::Name(name) => Ok(Cow::Owned(name)),
+        TypeIdentifier::Enum(name) => Ok(Cow::Owned(q(&name).into_owned())),
         TypeIdentifier::Ty(ty) => {
-            if col.is_auto() {
-                match ty {
-                    SqlType::Int => Ok(Cow::Borrowed("SERIAL")),
-                    SqlType::BigInt => Ok(Cow::Borrowed("BIGSERIAL")),
+            let params = col.params();
+            let valid_params = match ty {
+                _ if col.is_auto() => params.is_empty(),
+                SqlType::Text => params.precision.is_none() && params.scale.is_none(),
+                SqlType::Real => {
+                    params.length.is_none()
+                        && (params.precision.is_some() || params.scale.is_none())
+                }
+                _ => params.is_empty(),
+            };
+            if !valid_params {
+                return Err(Error::InvalidTypeParams(col.name().to_string()));
+            }
+            if let Some(kind) = col.auto_increment() {
+                // Identity columns are declared by a constraint
+                match (ty, kind) {
+                    (SqlType::Int, AutoIncrement::Serial) => Ok(Cow::Borrowed("SERIAL")),
+                    (SqlType::BigInt, AutoIncrement::Serial) => Ok(Cow::Borrowed("BIGSERIAL")),
+                    (SqlType::Int, AutoIncrement::Identity) => Ok(Cow::Borrowed("INTEGER")),
+                    (SqlType::BigInt, AutoIncrement::Identity) => Ok(Cow::Borrowed("BIGINT")),
                     _ => Err(Error::InvalidAuto(col.name().to_string())),
                 }
             } else {
@@ -569,8 +2607,18 @@ fn col_sqltype(col: &AColumn) -> Result<Cow<str>> {
                     SqlType::Bool => Cow::Borrowed("BOOLEAN"),
                     SqlType::Int => Cow::Borrowed("INTEGER"),
                     SqlType::BigInt => Cow::Borrowed("BIGINT"),
-                    SqlType::Real => Cow::Borrowed("DOUBLE PRECISION"),
-                    SqlType::Text => Cow::Borrowed("TEXT"),
+                    SqlType::Real => match params.precision {
+                        Some(precision) => Cow::Owned(format!(
+                            "NUMERIC({}, {})",
+                            precision,
+                            params.scale.unwrap_or(0)
+                        )),
+                        None => Cow::Borrowed("DOUBLE PRECISION"),
+                    },
+                    SqlType::Text => match params.length {
+                        Some(length) => Cow::Owned(format!("VARCHAR({})", length)),
+                        None => Cow::Borrowed("TEXT"),
+                    },
                     #[cfg(feature = "datetime")]
                     SqlType::Timestamp => Cow::Borrowed("TIMESTAMP"),
                     SqlType::Blob => Cow::Borrowed("BYTEA"),
@@ -583,34 +2631,405 @@ fn col_sqltype(col: &AColumn) -> Result<Cow<str>> {
     }
 }
 
-fn drop_table(name: &str) -> String {
-    format!("DROP TABLE {};", name)
+/// Renames the table `old` to `new`, along with its indexes and unique
+/// constraints, whose names are derived from the table name.
+fn rename_table(
+    current: &ADB,
+    old: &str,
+    new: &str,
+    idempotent: bool,
+    q: helper::QuoteIdent,
+) -> String {
+    let schema = table_schema(current, old);
+    let mut stmts: Vec<String> = helper::rename_steps(old, new)
+        .into_iter()
+        .map(|(from, to)| {
+            format!(
+                "ALTER TABLE {}{} RENAME TO {};",
+                if_exists(idempotent),
+                qualified(schema, &from, q),
+                q(&to)
+            )
+        })
+        .collect();
+    if let Some(table) = current.get_table(old) {
+        for index in &table.indexes {
+            stmts.push(rename_index(
+                schema,
+                &index.name(old),
+                &index.name(new),
+                idempotent,
+                q,
+            ));
+        }
+        for constraint in &table.unique_constraints {
+            stmts.push(rename_constraint(
+                &qualified(schema, new, q),
+                &constraint.name(old),
+                &constraint.name(new),
+                q,
+            ));
+        }
+    }
+    stmts.join("\n")
+}
+
+/// Renames the column `old` of the table `tbl_name` to `new`, along with
+/// the indexes and unique constraints whose names are derived from it.
+fn rename_column(
+    current: &ADB,
+    tbl_name: &str,
+    old: &str,
+    new: &str,
+    idempotent: bool,
+    q: helper::QuoteIdent,
+) -> String {
+    let schema = table_schema(current, tbl_name);
+    let tbl = qualified(schema, tbl_name, q);
+    let mut stmts: Vec<String> = helper::rename_steps(old, new)
+        .into_iter()
+        .map(|(from, to)| {
+            format!(
+                "ALTER TABLE {}{} RENAME COLUMN {} TO {};",
+                if_exists(idempotent),
+                tbl,
+                q(&from),
+                q(&to)
+            )
+        })
+        .collect();
+    if let Some(table) = current.get_table(tbl_name) {
+        let mut renamed = table.clone();
+        renamed.rename_column(old, new);
+        for (index, renamed) in table.indexes.iter().zip(&renamed.indexes) {
+            if index != renamed {
+                stmts.push(rename_index(
+                    schema,
+                    &index.name(tbl_name),
+                    &renamed.name(tbl_name),
+                    idempotent,
+                    q,
+                ));
+            }
+        }
+        for (constraint, renamed) in table
+            .unique_constraints
+            .iter()
+            .zip(&renamed.unique_constraints)
+        {
+            if constraint != renamed {
+                stmts.push(rename_constraint(
+                    &tbl,
+                    &constraint.name(tbl_name),
+                    &renamed.name(tbl_name),
+                    q,
+                ));
+            }
+        }
+    }
+    stmts.join("\n")
+}
+
+fn rename_index(
+    schema: Option<&str>,
+    old: &str,
+    new: &str,
+    idempotent: bool,
+    q: helper::QuoteIdent,
+) -> String {
+    helper::rename_steps(old, new)
+        .into_iter()
+        .map(|(from, to)| {
+            format!(
+                "ALTER INDEX {}{} RENAME TO {};",
+                if_exists(idempotent),
+                qualified(schema, &from, q),
+                q(&to)
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Renames the constraint `old` of the table `tbl` (as written in SQL).
+fn rename_constraint(tbl: &str, old: &str, new: &str, q: helper::QuoteIdent) -> String {
+    helper::rename_steps(old, new)
+        .into_iter()
+        .map(|(from, to)| {
+            format!(
+                "ALTER TABLE {} RENAME CONSTRAINT {} TO {};",
+                tbl,
+                q(&from),
+                q(&to)
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Drops the table `tbl` (as written in SQL).
+fn drop_table(tbl: &str, idempotent: bool) -> String {
+    format!("DROP TABLE {}{};", if_exists(idempotent), tbl)
+}
+
+fn create_view(view: &AView, allow_exists: bool, q: helper::QuoteIdent) -> String {
+    // Postgres has no CREATE VIEW IF NOT EXISTS
+    let modifier = if allow_exists { "OR REPLACE " } else { "" };
+    format!(
+        "CREATE {}VIEW {} AS {};",
+        modifier,
+        q(&view.name),
+        view.query
+    )
+}
+
+fn drop_view(name: &str, idempotent: bool, q: helper::QuoteIdent) -> String {
+    format!("DROP VIEW {}{};", if_exists(idempotent), q(name))
+}
+
+fn create_enum(e: &AEnum, allow_exists: bool, q: helper::QuoteIdent) -> Result<String> {
+    let values = e
+        .values
+        .iter()
+        .map(|v| helper::sql_literal_value(SqlVal::Text(v.clone())))
+        .collect::<Result<Vec<String>>>()?
+        .join(", ");
+    let create = format!("CREATE TYPE {} AS ENUM ({});", q(&e.name), values);
+    if allow_exists {
+        // Postgres has no CREATE TYPE IF NOT EXISTS
+        Ok(format!(
+            "DO $$ BEGIN {} EXCEPTION WHEN duplicate_object THEN NULL; END $$;",
+            create
+        ))
+    } else {
+        Ok(create)
+    }
+}
+
+fn drop_enum(name: &str, idempotent: bool, q: helper::QuoteIdent) -> String {
+    format!("DROP TYPE {}{};", if_exists(idempotent), q(name))
+}
+
+fn add_enum_value(
+    name: &str,
+    value: &str,
+    idempotent: bool,
+    q: helper::QuoteIdent,
+) -> Result<String> {
+    Ok(format!(
+        "ALTER TYPE {} ADD VALUE {}{};",
+        q(name),
+        if idempotent { "IF NOT EXISTS " } else { "" },
+        helper::sql_literal_value(SqlVal::Text(value.to_string()))?
+    ))
+}
+
+fn if_exists(idempotent: bool) -> &'static str {
+    if idempotent {
+        "IF EXISTS "
+    } else {
+        ""
+    }
+}
+
+fn add_column(
+    tbl_name: &str,
+    col: &AColumn,
+    db: &ADB,
+    idempotent: bool,
+    capabilities: Capabilities,
+    q: helper::QuoteIdent,
+) -> Result<String> {
+    let modifier = if idempotent && capabilities.add_column_if_not_exists {
+        "IF NOT EXISTS "
+    } else {
+        ""
+    };
+    let tbl = table_ident(db, tbl_name, q);
+    let add = if col.default().is_some() || col.default_expr().is_some() {
+        // Declared by the column definition
+        format!(
+            "ALTER TABLE {} ADD COLUMN {}{};",
+            tbl,
+            modifier,
+            define_column(col, tbl_name, true, db, q)?
+        )
+    } else {
+        let default: SqlVal = helper::column_default(col)?;
+        format!(
+            "ALTER TABLE {} ADD COLUMN {}{} DEFAULT {};",
+            tbl,
+            modifier,
+            define_column(col, tbl_name, true, db, q)?,
+            helper::sql_literal_value(default)?
+        )
+    };
+    let add = if idempotent && !capabilities.add_column_if_not_exists {
+        // Older servers have no ADD COLUMN IF NOT EXISTS
+        format!(
+            "DO $$ BEGIN {} EXCEPTION WHEN duplicate_column THEN NULL; END $$;",
+            add
+        )
+    } else {
+        add
+    };
+    let mut stmts = vec![add];
+    if col.unique() {
+        stmts.push(mark_constraint(
+            &tbl,
+            &unique_key_name(tbl_name, col.name()),
+            q,
+        ));
+    }
+    if col.reference().is_some() {
+        stmts.push(mark_constraint(
+            &tbl,
+            &foreign_key_name(tbl_name, col.name()),
+            q,
+        ));
+    }
+    if let Some(comment) = col.comment() {
+        stmts.push(comment_on(
+            &format!("COLUMN {}.{}", tbl, q(col.name())),
+            Some(comment),
+        )?);
+    }
+    Ok(stmts.join("\n"))
+}
+
+fn change_column_default_expr(
+    tbl: &str,
+    name: &str,
+    expr: Option<&str>,
+    q: helper::QuoteIdent,
+) -> String {
+    let action = match expr {
+        Some(expr) => format!("SET DEFAULT ({})", expr),
+        None => "DROP DEFAULT".to_string(),
+    };
+    format!("ALTER TABLE {} ALTER COLUMN {} {};", tbl, q(name), action)
 }
 
-fn add_column(tbl_name: &str, col: &AColumn) -> Result<String> {
-    let default: SqlVal = helper::column_default(col)?;
+fn change_column_default(
+    tbl: &str,
+    name: &str,
+    default: &Option<SqlVal>,
+    q: helper::QuoteIdent,
+) -> Result<String> {
+    let action = match default {
+        Some(default) => format!(
+            "SET DEFAULT {}",
+            helper::sql_literal_value(default.clone())?
+        ),
+        None => "DROP DEFAULT".to_string(),
+    };
     Ok(format!(
-        "ALTER TABLE {} ADD COLUMN {} DEFAULT {};",
-        tbl_name,
-        define_column(col)?,
-        helper::sql_literal_value(default)?
+        "ALTER TABLE {} ALTER COLUMN {} {};",
+        tbl,
+        q(name),
+        action
     ))
 }
 
-fn remove_column(tbl_name: &str, name: &str) -> String {
-    format!("ALTER TABLE {} DROP COLUMN {};", tbl_name, name)
+/// Make the column `name` of `tbl` (as written in SQL) NOT NULL, after
+/// filling in its NULLs `backfill.batch_size` rows at a time. Postgres
+/// itself checks no NULLs are left. The backfill stops with an error if
+/// its expression gives NULL, which would otherwise be updated forever.
+fn set_not_null(
+    tbl: &str,
+    name: &str,
+    backfill: Option<&Backfill>,
+    q: helper::QuoteIdent,
+) -> String {
+    let set = format!("ALTER TABLE {} ALTER COLUMN {} SET NOT NULL;", tbl, q(name));
+    let backfill = match backfill {
+        Some(backfill) => backfill,
+        None => return set,
+    };
+    let message = format!("Backfill of {}.{} gave NULL", tbl, name).replace('\'', "''");
+    format!(
+        "DO $$
+DECLARE
+  total bigint;
+  filled bigint;
+BEGIN
+  LOOP
+    WITH updated AS (
+      UPDATE {tbl} SET {col} = ({expr})
+      WHERE ctid IN (SELECT ctid FROM {tbl} WHERE {col} IS NULL LIMIT {batch})
+      RETURNING {col}
+    )
+    SELECT count(*), count({col}) INTO total, filled FROM updated;
+    EXIT WHEN total = 0;
+    IF filled < total THEN
+      RAISE EXCEPTION '{message}';
+    END IF;
+  END LOOP;
+END $$;
+{set}",
+        tbl = tbl,
+        col = q(name),
+        expr = backfill.expr,
+        batch = backfill.batch_size,
+        message = message,
+        set = set
+    )
+}
+
+fn remove_column(tbl: &str, name: &str, idempotent: bool, q: helper::QuoteIdent) -> String {
+    format!(
+        "ALTER TABLE {} DROP COLUMN {}{};",
+        tbl,
+        if_exists(idempotent),
+        q(name)
+    )
+}
+
+/// Add to the table `tbl` (as written in SQL) the constraint named
+/// `name` defined by `definition`. Pg has no `ADD CONSTRAINT IF NOT
+/// EXISTS`, so for idempotence any existing constraint of the same name
+/// is dropped first.
+fn add_constraint(
+    tbl: &str,
+    name: &str,
+    definition: &str,
+    idempotent: bool,
+    q: helper::QuoteIdent,
+) -> String {
+    let add = format!(
+        "ALTER TABLE {} ADD {};\n{}",
+        tbl,
+        definition,
+        mark_constraint(tbl, name, q)
+    );
+    if idempotent {
+        format!("{}\n{}", drop_constraint(tbl, name, true, q), add)
+    } else {
+        add
+    }
+}
+
+fn drop_constraint(tbl: &str, name: &str, idempotent: bool, q: helper::QuoteIdent) -> String {
+    format!(
+        "ALTER TABLE {} DROP CONSTRAINT {}{};",
+        tbl,
+        if_exists(idempotent),
+        q(name)
+    )
 }
 
-fn copy_table(old: &ATable, new: &ATable) -> String {
+fn copy_table(old: &ATable, new: &ATable, q: helper::QuoteIdent) -> String {
     let column_names = new
         .columns
         .iter()
-        .map(|col| col.name())
-        .collect::<Vec<&str>>()
+        .map(|col| q(col.name()))
+        .collect::<Vec<Cow<str>>>()
         .join(", ");
     format!(
         "INSERT INTO {} SELECT {} FROM {};",
-        &new.name, column_names, &old.name
+        qualified(new.schema.as_deref(), &new.name, q),
+        column_names,
+        qualified(old.schema.as_deref(), &old.name, q)
     )
 }
 
@@ -618,14 +3037,17 @@ fn tmp_table_name(name: &str) -> String {
     format!("{}__butane_tmp", name)
 }
 
+/// Alter the column `old` of the table `tbl_name` in place to match
+/// `new`. Changes to the primary key or to how the column's values are
+/// generated cannot be made in place, so the table is rebuilt instead.
 fn change_column(
     current: &mut ADB,
     tbl_name: &str,
     old: &AColumn,
-    new: Option<&AColumn>,
+    new: &AColumn,
+    q: helper::QuoteIdent,
 ) -> Result<String> {
-    let table = current.get_table(tbl_name);
-    if table.is_none() {
+    if current.get_table(tbl_name).is_none() {
         crate::warn!(
             "Cannot alter column {} from table {} that does not exist",
             &old.name(),
@@ -633,21 +3055,122 @@ fn change_column(
         );
         return Ok(String::new());
     }
-    let old_table = table.unwrap();
+    if old.is_pk() != new.is_pk() || old.auto_increment() != new.auto_increment() {
+        return rebuild_table(current, tbl_name, new, q);
+    }
+    let tbl = table_ident(current, tbl_name, q);
+    let name = q(new.name());
+    let alter = |action: String| format!("ALTER TABLE {} ALTER COLUMN {} {};", tbl, name, action);
+    let unique_name = unique_key_name(tbl_name, new.name());
+    let fk_name = foreign_key_name(tbl_name, new.name());
+    let mut stmts: Vec<String> = Vec::new();
+    let reference_changed = old.reference() != new.reference();
+    if reference_changed && old.reference().is_some() {
+        stmts.push(drop_constraint(&tbl, &fk_name, false, q));
+    }
+    if old.unique() && !new.unique() {
+        stmts.push(drop_constraint(&tbl, &unique_name, false, q));
+    }
+    let old_type = col_sqltype(old, q)?;
+    let new_type = col_sqltype(new, q)?;
+    let type_changed = old_type != new_type;
+    let has_default = |col: &AColumn| col.default().is_some() || col.default_expr().is_some();
+    // The old default may not convert to the new type
+    let default_dropped = type_changed && has_default(old);
+    if default_dropped {
+        stmts.push(alter("DROP DEFAULT".to_string()));
+    }
+    if type_changed {
+        stmts.push(alter(format!(
+            "TYPE {} USING {}::{}",
+            new_type, name, new_type
+        )));
+    }
+    if default_dropped || old.default() != new.default() || old.default_expr() != new.default_expr()
+    {
+        if let Some(expr) = new.default_expr() {
+            stmts.push(change_column_default_expr(&tbl, new.name(), Some(expr), q));
+        } else if new.default().is_some() || !default_dropped {
+            stmts.push(change_column_default(&tbl, new.name(), new.default(), q)?);
+        }
+    }
+    if old.nullable() != new.nullable() {
+        stmts.push(alter(
+            if new.nullable() {
+                "DROP NOT NULL"
+            } else {
+                "SET NOT NULL"
+            }
+            .to_string(),
+        ));
+    }
+    if new.unique() && !old.unique() {
+        stmts.push(add_constraint(
+            &tbl,
+            &unique_name,
+            &format!("CONSTRAINT {} UNIQUE ({})", q(&unique_name), name),
+            false,
+            q,
+        ));
+    }
+    if let Some(reference) = new.reference().filter(|_| reference_changed) {
+        stmts.push(add_constraint(
+            &tbl,
+            &fk_name,
+            &define_foreign_key(tbl_name, new.name(), reference, current, q),
+            false,
+            q,
+        ));
+    }
+    if old.comment() != new.comment() {
+        stmts.push(comment_on(
+            &format!("COLUMN {}.{}", tbl, name),
+            new.comment(),
+        )?);
+    }
+    Ok(stmts.join("\n"))
+}
+
+/// Recreates the table `tbl_name` with its column of the same name
+/// replaced by `new`, copying its rows.
+fn rebuild_table(
+    current: &mut ADB,
+    tbl_name: &str,
+    new: &AColumn,
+    q: helper::QuoteIdent,
+) -> Result<String> {
+    let old_table = current.get_table(tbl_name).expect("table exists");
     let mut new_table = old_table.clone();
     new_table.name = tmp_table_name(&new_table.name);
-    match new {
-        Some(col) => new_table.replace_column(col.clone()),
-        None => new_table.remove_column(old.name()),
-    }
-    let stmts: [&str; 4] = [
-        &create_table(&new_table, false)?,
-        &copy_table(old_table, &new_table),
-        &drop_table(&old_table.name),
-        &format!("ALTER TABLE {} RENAME TO {};", &new_table.name, tbl_name),
+    new_table.replace_column(new.clone());
+    let schema = old_table.schema.as_deref();
+    let mut stmts: Vec<String> = vec![
+        create_table(&new_table, current, false, q)?,
+        copy_table(old_table, &new_table, q),
+        drop_table(&qualified(schema, &old_table.name, q), false),
+        format!(
+            "ALTER TABLE {} RENAME TO {};",
+            qualified(schema, &new_table.name, q),
+            q(tbl_name)
+        ),
     ];
-    let result = stmts.join("\n");
+    // Constraint names are derived from the table name and are not
+    // changed by renaming the table.
+    let renames = derived_constraint_names(&new_table, &new_table.name)
+        .into_iter()
+        .zip(derived_constraint_names(&new_table, tbl_name));
+    for (from, to) in renames {
+        stmts.push(format!(
+            "ALTER TABLE {} RENAME CONSTRAINT {} TO {};",
+            qualified(schema, tbl_name, q),
+            q(&from),
+            q(&to)
+        ));
+    }
     new_table.name = old_table.name.clone();
+    // Dropping the old table dropped its indexes
+    stmts.extend(create_indexes(&new_table, false, q));
+    let result = stmts.join("\n");
     current.replace_table(new_table);
     Ok(result)
 }
@@ -657,10 +3180,11 @@ pub fn sql_insert_or_replace_with_placeholders(
     columns: &[Column],
     pkcol: &Column,
     w: &mut impl Write,
+    q: helper::QuoteIdent,
 ) {
     write!(w, "INSERT ").unwrap();
-    write!(w, "INTO {} (", table).unwrap();
-    helper::list_columns(columns, w);
+    write!(w, "INTO {} (", q(table)).unwrap();
+    helper::list_columns(columns, w, q);
     write!(w, ") VALUES (").unwrap();
     columns.iter().fold(1, |n, _| {
         let sep = if n == 1 { "" } else { ", " };
@@ -668,11 +3192,11 @@ pub fn sql_insert_or_replace_with_placeholders(
         n + 1
     });
     write!(w, ")").unwrap();
-    write!(w, " ON CONFLICT ({}) DO UPDATE SET (", pkcol.name()).unwrap();
-    helper::list_columns(columns, w);
+    write!(w, " ON CONFLICT ({}) DO UPDATE SET (", q(pkcol.name())).unwrap();
+    helper::list_columns(columns, w, q);
     write!(w, ") = (").unwrap();
     columns.iter().fold("", |sep, c| {
-        write!(w, "{}excluded.{}", sep, c.name()).unwrap();
+        write!(w, "{}excluded.{}", sep, q(c.name())).unwrap();
         ", "
     });
     write!(w, ")").unwrap();
@@ -686,7 +3210,9 @@ fn pgtype_for_val(val: &SqlVal) -> postgres::types::Type {
         Some(SqlType::Int) => postgres::types::Type::INT4,
         Some(SqlType::BigInt) => postgres::types::Type::INT8,
         Some(SqlType::Real) => postgres::types::Type::FLOAT8,
-        Some(SqlType::Text) => postgres::types::Type::TEXT,
+        // Left for postgres to infer, as text may also be compared
+        // with enum columns
+        Some(SqlType::Text) => Type::UNKNOWN,
         Some(SqlType::Blob) => postgres::types::Type::BYTEA,
         #[cfg(feature = "datetime")]
         Some(SqlType::Timestamp) => postgres::types::Type::TIMESTAMP,
@@ -698,7 +3224,7 @@ fn pgtype_for_val(val: &SqlVal) -> postgres::types::Type {
 }
 
 struct PgPlaceholderSource {
-    n: i8,
+    n: usize,
 }
 impl PgPlaceholderSource {
     fn new() -> Self {
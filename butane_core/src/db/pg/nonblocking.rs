@@ -0,0 +1,243 @@
+//! Asynchronous Postgres connections, on tokio-postgres.
+use super::*;
+use crate::db::cache::StatementCache;
+use crate::db::nonblocking::{AsyncBackend, AsyncConnection, AsyncConnectionMethods, Cancellable};
+use async_trait::async_trait;
+
+#[async_trait]
+impl AsyncBackend for PgBackend {
+    async fn connect_async(&self, conn_str: &str) -> Result<Box<dyn AsyncConnection>> {
+        let (conn_str, tls) = self.tls.parse_url(conn_str)?;
+        let mut config: tokio_postgres::Config = conn_str.parse()?;
+        let tls = socket_tls(&conn_str.parse()?, tls);
+        if let Some(ssl_mode) = pg_ssl_mode(&tls) {
+            config.ssl_mode(ssl_mode);
+        }
+        let (client, connection) = config.connect(tls_connector(&tls)?).await?;
+        // The connection exchanges messages with the server until the
+        // client is dropped. Its errors are also those of the client.
+        tokio::spawn(connection);
+        let capabilities = match self.capabilities {
+            Some(capabilities) => capabilities,
+            None => {
+                let version: Option<String> = client
+                    .query_opt(SERVER_VERSION_SQL, &[])
+                    .await?
+                    .and_then(|row| row.get(0));
+                capabilities_of_version(version)
+            }
+        };
+        let session = session_sql(&self.search_path, self.identifier_case, &self.timeouts);
+        if !session.is_empty() {
+            client.batch_execute(&session).await?;
+        }
+        Ok(Box::new(Cancellable::new(AsyncPgConnection {
+            client,
+            identifier_case: self.identifier_case,
+            capabilities,
+            dialect: self.dialect,
+            statements: StatementCache::new(self.statement_cache_capacity()),
+        })))
+    }
+}
+
+/// Pg database connection on tokio-postgres. A statement whose future
+/// is dropped may be left half sent, or leave a transaction of
+/// [execute_batch][AsyncConnectionMethods::execute_batch] open, so the
+/// connection is wrapped in [Cancellable] to be discarded afterwards.
+struct AsyncPgConnection {
+    client: tokio_postgres::Client,
+    identifier_case: IdentifierCase,
+    capabilities: Capabilities,
+    dialect: Dialect,
+    statements: StatementCache<tokio_postgres::Statement>,
+}
+impl AsyncPgConnection {
+    fn q(&self) -> helper::QuoteIdent {
+        quoter(self.identifier_case)
+    }
+    /// The statement for `sql`, prepared with parameters of `types`
+    /// unless cached.
+    async fn prepare(
+        &self,
+        sql: &str,
+        types: &[postgres::types::Type],
+    ) -> Result<tokio_postgres::Statement> {
+        let key = statement_key(sql, types);
+        if let Some(stmt) = self.statements.get(&key) {
+            return Ok(stmt);
+        }
+        let stmt = self.client.prepare_typed(sql, types).await?;
+        self.statements.insert(&key, stmt.clone());
+        Ok(stmt)
+    }
+}
+
+fn params<'a, T: postgres::types::ToSql + Sync>(
+    values: &'a [T],
+) -> Result<Vec<&'a DynToSqlPg<'a>>> {
+    check_param_count(values.len())?;
+    Ok(values.iter().map(|v| v as &DynToSqlPg).collect())
+}
+
+#[async_trait]
+impl AsyncConnectionMethods for AsyncPgConnection {
+    async fn execute(&self, sql: &str) -> Result<()> {
+        if cfg!(feature = "log") {
+            debug!("execute sql {}", sql);
+        }
+        self.client.batch_execute(sql).await?;
+        self.statements.clear();
+        Ok(())
+    }
+    async fn query(
+        &self,
+        table: &str,
+        columns: &[Column],
+        expr: Option<BoolExpr>,
+        limit: Option<i32>,
+        offset: Option<i32>,
+        sort: Option<&[query::Order]>,
+    ) -> Result<Vec<OwnedRow>> {
+        let (sql, values) = query_sql(table, columns, None, expr, limit, offset, sort, self.q());
+        let types: Vec<postgres::types::Type> = values.iter().map(pgtype_for_val).collect();
+        let stmt = self.prepare(&sql, &types).await?;
+        let rows = self.client.query(&stmt, &params(&values)?).await?;
+        rows.iter()
+            .map(|row| {
+                check_columns(row, columns)?;
+                OwnedRow::from_row(row, columns)
+            })
+            .collect()
+    }
+    async fn insert_returning_pk(
+        &self,
+        table: &str,
+        columns: &[Column],
+        pkcol: &Column,
+        values: &[SqlValRef<'_>],
+    ) -> Result<SqlVal> {
+        let sql = insert_returning_pk_sql(table, columns, pkcol, self.capabilities, self.q());
+        let stmt = self.prepare(&sql, &[]).await?;
+        if !self.capabilities.returning {
+            self.client.execute(&stmt, &params(values)?).await?;
+            let lastval = self.prepare(&lastval_sql(pkcol), &[]).await?;
+            let row = self.client.query_one(&lastval, &[]).await?;
+            return sql_val_from_postgres(&row, 0, pkcol);
+        }
+        match self.client.query_opt(&stmt, &params(values)?).await? {
+            Some(row) => sql_val_from_postgres(&row, 0, pkcol),
+            None => Err(Error::Internal("could not get pk".to_string())),
+        }
+    }
+    async fn insert_only(
+        &self,
+        table: &str,
+        columns: &[Column],
+        values: &[SqlValRef<'_>],
+    ) -> Result<()> {
+        let sql = insert_sql(table, columns, self.q());
+        let stmt = self.prepare(&sql, &[]).await?;
+        self.client.execute(&stmt, &params(values)?).await?;
+        Ok(())
+    }
+    async fn insert_or_replace(
+        &self,
+        table: &str,
+        columns: &[Column],
+        pkcol: &Column,
+        values: &[SqlValRef<'_>],
+    ) -> Result<()> {
+        let mut sql = String::new();
+        sql_insert_or_replace_with_placeholders(table, columns, pkcol, &mut sql, self.q());
+        let stmt = self.prepare(&sql, &[]).await?;
+        self.client.execute(&stmt, &params(values)?).await?;
+        Ok(())
+    }
+    async fn update(
+        &self,
+        table: &str,
+        pkcol: Column,
+        pk: SqlValRef<'_>,
+        columns: &[Column],
+        values: &[SqlValRef<'_>],
+    ) -> Result<()> {
+        let sql = update_sql(table, pkcol, columns, self.q());
+        let placeholder_values = [values, &[pk]].concat();
+        let stmt = self.prepare(&sql, &[]).await?;
+        self.client
+            .execute(&stmt, &params(&placeholder_values)?)
+            .await?;
+        Ok(())
+    }
+    async fn delete_where(&self, table: &str, expr: BoolExpr) -> Result<usize> {
+        let (sql, values) = delete_where_sql(table, expr, self.q());
+        let stmt = self.prepare(&sql, &[]).await?;
+        let cnt = self.client.execute(&stmt, &params(&values)?).await?;
+        Ok(cnt as usize)
+    }
+    async fn update_where(
+        &self,
+        table: &str,
+        assignments: &[query::Assignment],
+        expr: BoolExpr,
+    ) -> Result<usize> {
+        let (sql, values) = update_where_sql(table, assignments, expr, self.q());
+        let stmt = self.prepare(&sql, &[]).await?;
+        let cnt = self.client.execute(&stmt, &params(&values)?).await?;
+        Ok(cnt as usize)
+    }
+    async fn has_table(&self, table: &str) -> Result<bool> {
+        let table = stored_name(table, self.identifier_case);
+        let stmt = self.prepare(HAS_TABLE_SQL, &[]).await?;
+        let rows = self.client.query(&stmt, &[&table.as_ref()]).await?;
+        Ok(!rows.is_empty())
+    }
+    async fn execute_batch(&self, statements: &[Statement]) -> Result<()> {
+        let mut prepared = Vec::with_capacity(statements.len());
+        for statement in statements {
+            prepared.push(self.prepare(&statement.sql, &[]).await?);
+        }
+        let values: Vec<Vec<&DynToSqlPg>> = statements
+            .iter()
+            .map(|s| params(&s.values))
+            .collect::<Result<_>>()?;
+        // The statements are sent as their futures are first polled, in
+        // order, so all are sent before the first result is awaited. A
+        // failed statement aborts the transaction, failing the rest.
+        let executes = prepared
+            .iter()
+            .zip(&values)
+            .map(|(stmt, values)| self.client.execute(stmt, values));
+        let result = futures_util::future::try_join(
+            self.client.batch_execute("BEGIN"),
+            futures_util::future::try_join_all(executes),
+        )
+        .await;
+        match result {
+            Ok(_) => self.client.batch_execute("COMMIT").await?,
+            Err(e) => {
+                self.client.batch_execute("ROLLBACK").await?;
+                return Err(e.into());
+            }
+        }
+        Ok(())
+    }
+}
+#[async_trait]
+impl AsyncConnection for AsyncPgConnection {
+    fn backend_name(&self) -> &'static str {
+        self.dialect.backend_name()
+    }
+    async fn ping(&self) -> Result<()> {
+        // Unlike execute, leaves cached statements in place
+        self.client.batch_execute("SELECT 1").await?;
+        Ok(())
+    }
+    fn is_closed(&self) -> bool {
+        self.client.is_closed()
+    }
+    fn statement_cache_stats(&self) -> Option<StatementCacheStats> {
+        Some(self.statements.stats())
+    }
+}
@@ -0,0 +1,80 @@
+//! Connection pooling with [r2d2], for sharing connections of any
+//! backend between threads, e.g. those of a web server.
+//!
+//! ```ignore
+//! let pool = butane::db::pool::builder()
+//!     .max_size(8)
+//!     .build(ConnectionManager::new(spec))?;
+//! let conn = pool.get()?;
+//! ```
+use super::connmethods::ConnectionMethodWrapper;
+use super::*;
+use crate::Result;
+use std::time::Duration;
+
+pub use r2d2;
+
+/// A pool of butane connections.
+pub type Pool = r2d2::Pool<ConnectionManager>;
+/// A connection checked out of a [Pool]. It implements
+/// [ConnectionMethods], and is returned to the pool when dropped.
+pub type PooledConnection = r2d2::PooledConnection<ConnectionManager>;
+
+/// How long a connection may be idle in a pool made with [builder]
+/// before it is closed.
+pub const IDLE_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+/// How long a connection may be kept in a pool made with [builder]
+/// before it is replaced with a new one.
+pub const MAX_LIFETIME: Duration = Duration::from_secs(30 * 60);
+
+/// R2D2 support for Butane. Implements [`r2d2::ManageConnection`].
+pub struct ConnectionManager {
+    spec: ConnectionSpec,
+}
+impl ConnectionManager {
+    pub fn new(spec: ConnectionSpec) -> Self {
+        ConnectionManager { spec }
+    }
+}
+
+impl r2d2::ManageConnection for ConnectionManager {
+    type Connection = Connection;
+    type Error = crate::Error;
+
+    fn connect(&self) -> Result<Self::Connection> {
+        crate::db::connect(&self.spec)
+    }
+
+    fn is_valid(&self, conn: &mut Self::Connection) -> Result<()> {
+        conn.ping()
+    }
+
+    fn has_broken(&self, conn: &mut Self::Connection) -> bool {
+        conn.is_closed()
+    }
+}
+
+/// A builder for a [Pool] which checks connections with a query as
+/// they are checked out, closes those idle for [IDLE_TIMEOUT] and
+/// replaces those older than [MAX_LIFETIME]. Its other settings are
+/// r2d2's defaults.
+pub fn builder() -> r2d2::Builder<ConnectionManager> {
+    r2d2::Pool::builder()
+        .test_on_check_out(true)
+        .idle_timeout(Some(IDLE_TIMEOUT))
+        .max_lifetime(Some(MAX_LIFETIME))
+}
+
+/// Create a [Pool] of connections to `spec`, with the settings of [builder].
+pub fn new(spec: ConnectionSpec) -> std::result::Result<Pool, r2d2::Error> {
+    builder().build(ConnectionManager::new(spec))
+}
+
+impl ConnectionMethodWrapper for r2d2::PooledConnection<ConnectionManager> {
+    type Wrapped = Connection;
+    fn wrapped_connection_methods(&self) -> Result<&Connection> {
+        Ok(self.deref())
+    }
+}
+
+connection_method_wrapper!(r2d2::PooledConnection<ConnectionManager>);
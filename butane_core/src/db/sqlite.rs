@@ -1,38 +1,132 @@
 //! SQLite database backend
+use super::cache::StatementCache;
 use super::helper;
+use super::logging;
 use super::*;
 use crate::db::connmethods::BackendRows;
 use crate::debug;
-use crate::migrations::adb::{AColumn, ATable, Operation, TypeIdentifier, ADB};
+use crate::migrations::adb::{
+    ACheckConstraint, AColumn, AForeignKey, AIndex, ATable, AUniqueConstraint, AView,
+    AutoIncrement, DeferredSqlType, Operation, TypeIdentifier, ADB,
+};
 use crate::query;
-use crate::query::Order;
+use crate::query::{AggregateExpr, Distinct, Having, Order};
+use crate::search::{SearchHit, INDEX_TABLE};
 use crate::{Result, SqlType, SqlVal, SqlValRef};
 #[cfg(feature = "datetime")]
 use chrono::naive::NaiveDateTime;
 use fallible_streaming_iterator::FallibleStreamingIterator;
 use pin_project::pin_project;
 use std::borrow::Cow;
+use std::collections::HashMap;
+use std::convert::TryFrom;
 use std::fmt::Write;
 use std::pin::Pin;
+use std::sync::Arc;
 
 #[cfg(feature = "datetime")]
 const SQLITE_DT_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+#[cfg(feature = "datetime")]
+const RFC3339_FORMAT: &str = "%Y-%m-%dT%H:%M:%S%.6fZ";
 
 /// The name of the sqlite backend.
 pub const BACKEND_NAME: &str = "sqlite";
 
 /// SQLite [Backend][crate::db::Backend] implementation.
 #[derive(Default)]
-pub struct SQLiteBackend {}
+pub struct SQLiteBackend {
+    timestamp_format: TimestampFormat,
+    capabilities: Option<Capabilities>,
+    options: SqliteOptions,
+    statement_cache_capacity: Option<usize>,
+    timeouts: Timeouts,
+}
 impl SQLiteBackend {
     pub fn new() -> SQLiteBackend {
-        SQLiteBackend {}
+        SQLiteBackend::default()
+    }
+    /// Store timestamps in `timestamp_format`, both in the columns
+    /// declared by migrations and in the queries of connections made by
+    /// this backend.
+    pub fn with_timestamp_format(mut self, timestamp_format: TimestampFormat) -> SQLiteBackend {
+        self.timestamp_format = timestamp_format;
+        self
+    }
+    /// Assume the SQLite library of connections made by this backend
+    /// has `capabilities`, if given, rather than detecting them from
+    /// its version.
+    pub fn with_capabilities(mut self, capabilities: Option<Capabilities>) -> SQLiteBackend {
+        self.capabilities = capabilities;
+        self
+    }
+    /// Apply `options` to connections made by this backend, unless
+    /// overridden by those of the connection string.
+    pub fn with_options(mut self, options: SqliteOptions) -> SQLiteBackend {
+        self.options = options;
+        self
+    }
+    /// Cache up to `capacity` prepared statements in each connection
+    /// made by this backend, rather than
+    /// [DEFAULT_STATEMENT_CACHE_CAPACITY].
+    pub fn with_statement_cache_capacity(mut self, capacity: Option<usize>) -> SQLiteBackend {
+        self.statement_cache_capacity = capacity;
+        self
+    }
+    /// Wait up to the lock timeout of `timeouts` for locks held by
+    /// other connections, unless the options give a busy timeout.
+    /// SQLite has no statement timeout, so connecting fails if one is
+    /// given.
+    pub fn with_timeouts(mut self, timeouts: Timeouts) -> SQLiteBackend {
+        self.timeouts = timeouts;
+        self
     }
 }
 impl SQLiteBackend {
-    fn connect(&self, path: &str) -> Result<SQLiteConnection> {
-        SQLiteConnection::open(Path::new(path))
+    fn connect(&self, conn_str: &str) -> Result<SQLiteConnection> {
+        if self.timeouts.statement.is_some() {
+            return Err(Error::TimeoutUnsupported("statement", BACKEND_NAME));
+        }
+        let (path, mut options) = self.options.parse_conn_str(conn_str)?;
+        if let (None, Some(lock)) = (options.busy_timeout, self.timeouts.lock) {
+            options.busy_timeout = Some(u32::try_from(lock.as_millis()).unwrap_or(u32::MAX));
+        }
+        let conn = SQLiteConnection::open(
+            Path::new(path),
+            options.mode,
+            self.timestamp_format,
+            self.capabilities,
+            self.statement_cache_capacity
+                .unwrap_or(DEFAULT_STATEMENT_CACHE_CAPACITY),
+        )?;
+        apply_options(&conn.client.conn, &options)?;
+        Ok(conn)
+    }
+}
+
+/// Set the pragmas of `options` on `conn`.
+fn apply_options(conn: &rusqlite::Connection, options: &SqliteOptions) -> Result<()> {
+    if let Some(timeout) = options.busy_timeout {
+        conn.busy_timeout(std::time::Duration::from_millis(timeout.into()))?;
     }
+    let mut sql = String::new();
+    if let Some(mode) = options.journal_mode {
+        write!(sql, "PRAGMA journal_mode = {};", mode.name()).unwrap();
+    }
+    if let Some(foreign_keys) = options.foreign_keys {
+        write!(
+            sql,
+            "PRAGMA foreign_keys = {};",
+            if foreign_keys { "ON" } else { "OFF" }
+        )
+        .unwrap();
+    }
+    if let Some(synchronous) = options.synchronous {
+        write!(sql, "PRAGMA synchronous = {};", synchronous.name()).unwrap();
+    }
+    if !sql.is_empty() {
+        conn.execute_batch(&sql)?;
+    }
+    Ok(())
 }
 impl Backend for SQLiteBackend {
     fn name(&self) -> &'static str {
@@ -40,16 +134,25 @@ impl Backend for SQLiteBackend {
     }
 
     fn create_migration_sql(&self, current: &ADB, ops: Vec<Operation>) -> Result<String> {
-        let mut current: ADB = (*current).clone();
-        Ok(ops
-            .into_iter()
-            .map(|o| {
-                let sql = sql_for_op(&mut current, &o);
-                current.transform_with(o);
-                sql
-            })
-            .collect::<Result<Vec<String>>>()?
-            .join("\n"))
+        migration_sql(current, ops, false, self.timestamp_format)
+    }
+
+    fn create_idempotent_migration_sql(
+        &self,
+        current: &ADB,
+        ops: Vec<Operation>,
+    ) -> Result<String> {
+        migration_sql(current, ops, true, self.timestamp_format)
+    }
+
+    fn as_introspected(&self, db: &ADB) -> ADB {
+        // SQLite ignores type parameters, so they are not declared
+        helper::as_introspected(db, false, false, false, |typeid| match typeid {
+            TypeIdentifier::Ty(SqlType::Custom(_)) => typeid,
+            TypeIdentifier::Ty(ty) => sqltype_from_sql(sqltype(&ty, self.timestamp_format)),
+            TypeIdentifier::Name(_) => typeid,
+            TypeIdentifier::Enum(_) => TypeIdentifier::Ty(SqlType::Text),
+        })
     }
 
     fn connect(&self, path: &str) -> Result<Connection> {
@@ -60,48 +163,431 @@ impl Backend for SQLiteBackend {
 }
 
 /// SQLite database connection.
+struct SqliteCancelHandle(rusqlite::InterruptHandle);
+impl CancelHandle for SqliteCancelHandle {
+    fn cancel(&self) -> Result<()> {
+        self.0.interrupt();
+        Ok(())
+    }
+}
+
 pub struct SQLiteConnection {
-    conn: rusqlite::Connection,
+    client: SQLiteClient<rusqlite::Connection>,
 }
 impl SQLiteConnection {
-    fn open(path: impl AsRef<Path>) -> Result<Self> {
-        rusqlite::Connection::open(path)
-            .map(|conn| SQLiteConnection { conn })
-            .map_err(|e| e.into())
+    fn open(
+        path: impl AsRef<Path>,
+        mode: Option<OpenMode>,
+        timestamp_format: TimestampFormat,
+        capabilities: Option<Capabilities>,
+        statement_cache_capacity: usize,
+    ) -> Result<Self> {
+        use rusqlite::OpenFlags;
+        // As rusqlite's default flags, but for the access mode
+        let flags = OpenFlags::SQLITE_OPEN_URI | OpenFlags::SQLITE_OPEN_NO_MUTEX;
+        let flags = flags
+            | match mode.unwrap_or(OpenMode::ReadWriteCreate) {
+                OpenMode::ReadOnly => OpenFlags::SQLITE_OPEN_READ_ONLY,
+                OpenMode::ReadWrite => OpenFlags::SQLITE_OPEN_READ_WRITE,
+                OpenMode::ReadWriteCreate => {
+                    OpenFlags::SQLITE_OPEN_READ_WRITE | OpenFlags::SQLITE_OPEN_CREATE
+                }
+                OpenMode::Memory => {
+                    OpenFlags::SQLITE_OPEN_MEMORY
+                        | OpenFlags::SQLITE_OPEN_READ_WRITE
+                        | OpenFlags::SQLITE_OPEN_CREATE
+                }
+            };
+        let conn = rusqlite::Connection::open_with_flags(path, flags)?;
+        conn.set_prepared_statement_cache_capacity(statement_cache_capacity);
+        Ok(SQLiteConnection {
+            client: SQLiteClient {
+                conn,
+                timestamp_format,
+                capabilities: capabilities
+                    .unwrap_or_else(|| Capabilities::sqlite(rusqlite::version_number())),
+                statements: Arc::new(StatementCache::new(statement_cache_capacity)),
+                logger: None,
+            },
+        })
     }
 
     // For use with connection_method_wrapper macro
     #[allow(clippy::unnecessary_wraps)]
-    fn wrapped_connection_methods(&self) -> Result<&rusqlite::Connection> {
-        Ok(&self.conn)
+    fn wrapped_connection_methods(&self) -> Result<&SQLiteClient<rusqlite::Connection>> {
+        Ok(&self.client)
     }
 }
 connection_method_wrapper!(SQLiteConnection);
 
 impl BackendConnection for SQLiteConnection {
-    fn transaction(&mut self) -> Result<Transaction<'_>> {
-        let trans: rusqlite::Transaction<'_> = self.conn.transaction()?;
-        let trans = Box::new(SqliteTransaction::new(trans));
+    fn transaction_with(&mut self, options: TransactionOptions) -> Result<Transaction<'_>> {
+        if options.timeouts != Timeouts::default() {
+            return Err(Error::TimeoutUnsupported("transaction", BACKEND_NAME));
+        }
+        // Every level is met by SQLite, whose transactions are serializable
+        let trans: rusqlite::Transaction<'_> = self.client.conn.transaction()?;
+        let trans = Box::new(SqliteTransaction::new(
+            trans,
+            self.client.timestamp_format,
+            self.client.capabilities,
+            self.client.statements.clone(),
+            self.client.logger.clone(),
+        ));
         Ok(Transaction::new(trans))
     }
     fn backend(&self) -> Box<dyn Backend> {
-        Box::new(SQLiteBackend {})
+        Box::new(
+            SQLiteBackend::new()
+                .with_timestamp_format(self.client.timestamp_format)
+                .with_capabilities(Some(self.client.capabilities))
+                .with_statement_cache_capacity(Some(self.client.statements.stats().capacity)),
+        )
     }
     fn backend_name(&self) -> &'static str {
         "sqlite"
     }
+    fn capabilities(&self) -> Capabilities {
+        self.client.capabilities
+    }
     fn is_closed(&self) -> bool {
         false
     }
+    fn set_auto_reconnect(&mut self, _enabled: bool) -> Result<()> {
+        // Connections to a database file are not lost
+        Ok(())
+    }
+    fn cancel_handle(&self) -> Result<Box<dyn CancelHandle>> {
+        Ok(Box::new(SqliteCancelHandle(
+            self.client.conn.get_interrupt_handle(),
+        )))
+    }
+    fn statement_cache_stats(&self) -> Option<StatementCacheStats> {
+        Some(self.client.statements.stats())
+    }
+    fn set_query_logger(&mut self, logger: Option<QueryLogger>) -> Result<()> {
+        self.client.logger = logger;
+        Ok(())
+    }
+    fn introspect(&self) -> Result<ADB> {
+        let mut db = ADB::new();
+        let mut stmt = self.client.conn.prepare(
+            "SELECT name FROM sqlite_master WHERE type='table' AND name NOT LIKE 'sqlite_%';",
+        )?;
+        let names = stmt
+            .query_map([], |row| row.get::<_, String>(0))?
+            .collect::<rusqlite::Result<Vec<String>>>()?;
+        for name in names {
+            db.replace_table(introspect_table(&self.client.conn, name)?);
+        }
+        Ok(db)
+    }
+    fn managed_tables(&self) -> Result<Vec<String>> {
+        let mut stmt = self
+            .client
+            .conn
+            .prepare("SELECT name FROM sqlite_master WHERE type='table' AND instr(sql, ?1) > 0;")?;
+        let names = stmt
+            .query_map([managed_marker_comment()], |row| row.get::<_, String>(0))?
+            .collect::<rusqlite::Result<Vec<String>>>()?;
+        Ok(names)
+    }
+    fn unmanaged_objects(&self) -> Result<Vec<(String, TableObject)>> {
+        // Constraints are part of the table's definition, so only
+        // indexes are marked on their own
+        let mut stmt = self.client.conn.prepare(
+            "SELECT tbl_name, name FROM sqlite_master WHERE type='index' AND sql IS NOT NULL \
+             AND instr(sql, ?1) = 0;",
+        )?;
+        let indexes = stmt
+            .query_map([managed_marker_comment()], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+            })?
+            .collect::<rusqlite::Result<Vec<(String, String)>>>()?;
+        let mut objects = Vec::new();
+        for (table, name) in indexes {
+            if let Some(index) = introspect_index(&self.client.conn, &name)? {
+                objects.push((table, TableObject::Index(index)));
+            }
+        }
+        Ok(objects)
+    }
+    fn create_search_index(&self) -> Result<()> {
+        ConnectionMethods::execute(
+            self,
+            &format!(
+                "CREATE VIRTUAL TABLE IF NOT EXISTS {} USING fts5(model UNINDEXED, pk UNINDEXED, body);",
+                INDEX_TABLE
+            ),
+        )
+    }
+    fn search(&self, terms: &str, limit: Option<i32>) -> Result<Vec<SearchHit>> {
+        // Each word is quoted, so that FTS5 query syntax in the terms is
+        // matched literally
+        let words: Vec<String> = terms
+            .split_whitespace()
+            .map(|word| format!("\"{}\"", word.replace('"', "\"\"")))
+            .collect();
+        if words.is_empty() {
+            return Ok(Vec::new());
+        }
+        let sql = format!(
+            "SELECT model, pk, snippet({0}, 2, '<b>', '</b>', '...', 16) FROM {0} \
+             WHERE {0} MATCH ?1 ORDER BY rank LIMIT ?2;",
+            INDEX_TABLE
+        );
+        debug!("search sql {}", sql);
+        let mut stmt = self.client.conn.prepare(&sql)?;
+        // A negative limit is no limit
+        let rows = stmt.query_map(
+            rusqlite::params![words.join(" "), limit.unwrap_or(-1)],
+            |row| Ok((row.get(0)?, row.get::<_, String>(1)?, row.get(2)?)),
+        )?;
+        rows.map(|row| {
+            let (model, pk, snippet) = row?;
+            SearchHit::from_index(model, &pk, snippet)
+        })
+        .collect()
+    }
+}
+
+fn introspect_table(conn: &rusqlite::Connection, name: String) -> Result<ATable> {
+    let mut table = ATable::new(name);
+
+    // Columns which are unique on their own, rather than in combination
+    let mut unique: Vec<String> = Vec::new();
+    let mut stmt = conn.prepare(&format!("PRAGMA index_list({});", table.name))?;
+    let indexes = stmt
+        .query_map([], |row| {
+            Ok((row.get::<_, String>(1)?, row.get::<_, String>(3)?))
+        })?
+        .collect::<rusqlite::Result<Vec<(String, String)>>>()?;
+    for (index, origin) in indexes {
+        // Indexes created by CREATE INDEX, rather than by constraints
+        if origin == "c" {
+            if let Some(index) = introspect_index(conn, &index)? {
+                table.add_index(index);
+            }
+            continue;
+        }
+        // Otherwise only indexes created by UNIQUE constraints
+        if origin != "u" {
+            continue;
+        }
+        let mut stmt = conn.prepare(&format!("PRAGMA index_info({});", index))?;
+        let mut columns = stmt
+            .query_map([], |row| row.get::<_, String>(2))?
+            .collect::<rusqlite::Result<Vec<String>>>()?;
+        if columns.len() == 1 {
+            unique.append(&mut columns);
+        } else {
+            table.add_unique_constraint(AUniqueConstraint::new(columns));
+        }
+    }
+
+    let mut references: HashMap<String, AForeignKey> = HashMap::new();
+    let mut stmt = conn.prepare(&format!("PRAGMA foreign_key_list({});", table.name))?;
+    let mut rows = stmt.query([])?;
+    while let Some(row) = rows.next()? {
+        let mut reference = AForeignKey::new(row.get::<_, String>(2)?)
+            .with_on_update(helper::referential_action_from_sql(
+                &row.get::<_, String>(5)?,
+            ))
+            .with_on_delete(helper::referential_action_from_sql(
+                &row.get::<_, String>(6)?,
+            ));
+        if let Some(to) = row.get::<_, Option<String>>(4)? {
+            reference = reference.with_column(to);
+        }
+        references.insert(row.get(3)?, reference);
+    }
+
+    let mut stmt = conn.prepare(&format!("PRAGMA table_info({});", table.name))?;
+    let mut rows = stmt.query([])?;
+    while let Some(row) = rows.next()? {
+        let name: String = row.get(1)?;
+        let ty: String = row.get(2)?;
+        let notnull: bool = row.get(3)?;
+        let pk: i32 = row.get(5)?;
+        let mut col = AColumn::new(
+            name.as_str(),
+            DeferredSqlType::KnownId(sqltype_from_sql(&ty)),
+            !notnull,
+            pk > 0,
+            false, // auto. An INTEGER PRIMARY KEY is always an alias for ROWID
+            unique.contains(&name),
+            None,
+        );
+        if let Some(reference) = references.remove(&name) {
+            col = col.with_reference(reference);
+        }
+        table.add_column(col);
+    }
+    Ok(table)
+}
+
+/// The index `name` created by CREATE INDEX, unless it is unique or
+/// indexes expressions, which butane does not declare.
+fn introspect_index(conn: &rusqlite::Connection, name: &str) -> Result<Option<AIndex>> {
+    let (unique, sql): (bool, Option<String>) = conn.query_row(
+        "SELECT sql LIKE 'CREATE UNIQUE%', sql FROM sqlite_master WHERE type='index' AND name=?1;",
+        [name],
+        |row| Ok((row.get(0)?, row.get(1)?)),
+    )?;
+    if unique {
+        return Ok(None);
+    }
+    let mut stmt = conn.prepare(&format!("PRAGMA index_info({});", name))?;
+    let columns = stmt
+        .query_map([], |row| row.get::<_, Option<String>>(2))?
+        .collect::<rusqlite::Result<Option<Vec<String>>>>()?;
+    let mut index = match columns {
+        Some(columns) => AIndex::new(columns),
+        None => return Ok(None),
+    };
+    // The predicate of a partial index is only kept in its SQL, which
+    // follows the parenthesized columns
+    let sql = sql.unwrap_or_default();
+    let end = sql
+        .find('(')
+        .and_then(|start| sql[start..].find(')').map(|end| start + end));
+    if let Some(end) = end {
+        let rest = sql[end + 1..].trim_start();
+        if rest.len() > 5 && rest[..5].eq_ignore_ascii_case("WHERE") {
+            index = index.with_predicate(rest[5..].trim());
+        }
+    }
+    Ok(Some(index))
+}
+
+/// The type butane would have declared as `sql`. Integers and text are
+/// ambiguous, so are read as `BigInt` and `Text`.
+fn sqltype_from_sql(sql: &str) -> TypeIdentifier {
+    match sql.to_uppercase().as_str() {
+        "INTEGER" => TypeIdentifier::Ty(SqlType::BigInt),
+        "REAL" => TypeIdentifier::Ty(SqlType::Real),
+        "TEXT" => TypeIdentifier::Ty(SqlType::Text),
+        "BLOB" => TypeIdentifier::Ty(SqlType::Blob),
+        _ => TypeIdentifier::Name(sql.to_string()),
+    }
+}
+
+/// A rusqlite connection, or a transaction on one.
+trait AsRusqlite {
+    fn rusqlite(&self) -> &rusqlite::Connection;
+}
+impl AsRusqlite for rusqlite::Connection {
+    fn rusqlite(&self) -> &rusqlite::Connection {
+        self
+    }
+}
+impl AsRusqlite for rusqlite::Transaction<'_> {
+    fn rusqlite(&self) -> &rusqlite::Connection {
+        self
+    }
+}
+impl AsRusqlite for rusqlite::Savepoint<'_> {
+    fn rusqlite(&self) -> &rusqlite::Connection {
+        self
+    }
+}
+
+/// A rusqlite transaction, or a savepoint nested within one.
+trait RusqliteTransaction: AsRusqlite + Sized {
+    fn commit(self) -> rusqlite::Result<()>;
+    fn rollback(self) -> rusqlite::Result<()>;
+    fn savepoint(&mut self) -> rusqlite::Result<rusqlite::Savepoint<'_>>;
+}
+impl RusqliteTransaction for rusqlite::Transaction<'_> {
+    fn commit(self) -> rusqlite::Result<()> {
+        rusqlite::Transaction::commit(self)
+    }
+    fn rollback(self) -> rusqlite::Result<()> {
+        rusqlite::Transaction::rollback(self)
+    }
+    fn savepoint(&mut self) -> rusqlite::Result<rusqlite::Savepoint<'_>> {
+        rusqlite::Transaction::savepoint(self)
+    }
+}
+impl RusqliteTransaction for rusqlite::Savepoint<'_> {
+    fn commit(self) -> rusqlite::Result<()> {
+        rusqlite::Savepoint::commit(self)
+    }
+    fn rollback(mut self) -> rusqlite::Result<()> {
+        // Rolling back a savepoint leaves it open, so it is then released
+        rusqlite::Savepoint::rollback(&mut self)?;
+        rusqlite::Savepoint::commit(self)
+    }
+    fn savepoint(&mut self) -> rusqlite::Result<rusqlite::Savepoint<'_>> {
+        rusqlite::Savepoint::savepoint(self)
+    }
+}
+
+/// Shared functionality between connection and transaction: a rusqlite
+/// connection or transaction with the options of the connection it
+/// belongs to.
+struct SQLiteClient<C> {
+    conn: C,
+    timestamp_format: TimestampFormat,
+    capabilities: Capabilities,
+    /// Mirrors the statement cache of rusqlite, which keeps the
+    /// statements themselves, for its statistics.
+    statements: Arc<StatementCache<()>>,
+    logger: Option<QueryLogger>,
+}
+impl<C: AsRusqlite> SQLiteClient<C> {
+    /// `values` as parameters of a statement.
+    fn params<'a>(
+        &self,
+        values: impl Iterator<Item = SqlValRef<'a>> + 'a,
+    ) -> impl rusqlite::Params + 'a {
+        let timestamp_format = self.timestamp_format;
+        rusqlite::params_from_iter(values.map(move |v| sqlvalref_to_sqlite(&v, timestamp_format)))
+    }
+    /// The statement for `sql`, prepared unless cached.
+    fn prepare(&self, sql: &str) -> Result<rusqlite::CachedStatement<'_>> {
+        self.statements
+            .get_or_prepare(sql, || Ok::<(), Error>(()))?;
+        Ok(self.conn.rusqlite().prepare_cached(sql)?)
+    }
+    /// Run `f`, which runs `sql` with `values`, logging it.
+    fn logged<'v, T>(
+        &self,
+        sql: &str,
+        values: impl IntoIterator<Item = SqlValRef<'v>>,
+        f: impl FnOnce() -> Result<T>,
+    ) -> Result<T> {
+        logging::log(self.logger.as_ref(), sql, values, f)
+    }
+    /// Run the statement `sql` with `values`.
+    fn run(&self, sql: &str, values: &[SqlValRef<'_>]) -> Result<usize> {
+        self.logged(sql, values.iter().cloned(), || {
+            Ok(self
+                .prepare(sql)?
+                .execute(self.params(values.iter().cloned()))?)
+        })
+    }
+    /// The pk in the first column of the single row returned by `sql`.
+    fn query_pk(&self, sql: &str, values: &[SqlValRef<'_>], pkcol: &Column) -> Result<SqlVal> {
+        self.logged(sql, values.iter().cloned(), || {
+            let mut stmt = self.prepare(sql)?;
+            let mut rows = stmt.query(self.params(values.iter().cloned()))?;
+            match rows.next()? {
+                Some(row) => {
+                    sql_val_from_rusqlite(row.get_ref_unwrap(0), pkcol, self.timestamp_format)
+                }
+                None => Err(rusqlite::Error::QueryReturnedNoRows.into()),
+            }
+        })
+    }
 }
 
-impl ConnectionMethods for rusqlite::Connection {
+impl<C: AsRusqlite> ConnectionMethods for SQLiteClient<C> {
     fn execute(&self, sql: &str) -> Result<()> {
         if cfg!(feature = "log") {
             debug!("execute sql {}", sql);
         }
-        self.execute_batch(sql.as_ref())?;
-        Ok(())
+        self.logged(sql, [], || Ok(self.conn.rusqlite().execute_batch(sql)?))
     }
 
     fn query<'a, 'b, 'c: 'a>(
@@ -113,41 +599,55 @@ impl ConnectionMethods for rusqlite::Connection {
         offset: Option<i32>,
         order: Option<&[Order]>,
     ) -> Result<RawQueryResult<'a>> {
-        let mut sqlquery = String::new();
-        helper::sql_select(columns, table, &mut sqlquery);
-        let mut values: Vec<SqlVal> = Vec::new();
-        if let Some(expr) = expr {
-            sqlquery.write_str(" WHERE ").unwrap();
-            sql_for_expr(
-                query::Expr::Condition(Box::new(expr)),
-                &mut values,
-                &mut SQLitePlaceholderSource::new(),
-                &mut sqlquery,
-            );
-        }
-
-        if let Some(order) = order {
-            helper::sql_order(order, &mut sqlquery)
-        }
-
-        if let Some(limit) = limit {
-            helper::sql_limit(limit, &mut sqlquery)
-        }
+        let (sqlquery, values) = query_sql(
+            table,
+            columns,
+            None,
+            expr,
+            limit,
+            offset,
+            order,
+            self.timestamp_format,
+        );
+        debug!("query sql {}", sqlquery);
 
-        if let Some(offset) = offset {
-            if limit.is_none() {
-                // Sqlite only supports offset in conjunction with
-                // limit, so add a max limit if we don't have one
-                // already.
-                helper::sql_limit(i32::MAX, &mut sqlquery)
-            }
-            helper::sql_offset(offset, &mut sqlquery)
+        let adapter = self.logged(&sqlquery, values.iter().map(SqlVal::as_ref), || {
+            let stmt = self.prepare(&sqlquery)?;
+            let params = self.params(values.iter().map(SqlVal::as_ref));
+            QueryAdapter::new(stmt, params, self.timestamp_format)
+        })?;
+        Ok(Box::new(adapter))
+    }
+    fn query_distinct<'a, 'b, 'c: 'a>(
+        &'c self,
+        table: &str,
+        columns: &'b [Column],
+        distinct: &Distinct,
+        expr: Option<BoolExpr>,
+        limit: Option<i32>,
+        offset: Option<i32>,
+        order: Option<&[Order]>,
+    ) -> Result<RawQueryResult<'a>> {
+        if let Distinct::On(_) = distinct {
+            return Err(Error::DistinctOnUnsupported(BACKEND_NAME));
         }
-
+        let (sqlquery, values) = query_sql(
+            table,
+            columns,
+            Some(distinct),
+            expr,
+            limit,
+            offset,
+            order,
+            self.timestamp_format,
+        );
         debug!("query sql {}", sqlquery);
 
-        let stmt = self.prepare(&sqlquery)?;
-        let adapter = QueryAdapter::new(stmt, rusqlite::params_from_iter(values))?;
+        let adapter = self.logged(&sqlquery, values.iter().map(SqlVal::as_ref), || {
+            let stmt = self.prepare(&sqlquery)?;
+            let params = self.params(values.iter().map(SqlVal::as_ref));
+            QueryAdapter::new(stmt, params, self.timestamp_format)
+        })?;
         Ok(Box::new(adapter))
     }
     fn insert_returning_pk(
@@ -163,21 +663,28 @@ impl ConnectionMethods for rusqlite::Connection {
             columns,
             &mut SQLitePlaceholderSource::new(),
             &mut sql,
+            helper::bare_ident,
         );
+        if self.capabilities.returning {
+            write!(&mut sql, " RETURNING {}", pkcol.name()).unwrap();
+            if cfg!(feature = "log") {
+                debug!("insert sql {}", sql);
+            }
+            return self.query_pk(&sql, values, pkcol);
+        }
         if cfg!(feature = "log") {
             debug!("insert sql {}", sql);
         }
-        self.execute(&sql, rusqlite::params_from_iter(values))?;
-        let pk: SqlVal = self.query_row_and_then(
+        self.run(&sql, values)?;
+        self.query_pk(
             &format!(
                 "SELECT {} FROM {} WHERE ROWID = last_insert_rowid()",
                 pkcol.name(),
                 table
             ),
-            [],
-            |row| sql_val_from_rusqlite(row.get_ref_unwrap(0), pkcol),
-        )?;
-        Ok(pk)
+            &[],
+            pkcol,
+        )
     }
     fn insert_only(&self, table: &str, columns: &[Column], values: &[SqlValRef<'_>]) -> Result<()> {
         let mut sql = String::new();
@@ -186,11 +693,12 @@ impl ConnectionMethods for rusqlite::Connection {
             columns,
             &mut SQLitePlaceholderSource::new(),
             &mut sql,
+            helper::bare_ident,
         );
         if cfg!(feature = "log") {
             debug!("insert sql {}", sql);
         }
-        self.execute(&sql, rusqlite::params_from_iter(values))?;
+        self.run(&sql, values)?;
         Ok(())
     }
     fn insert_or_replace(
@@ -202,7 +710,7 @@ impl ConnectionMethods for rusqlite::Connection {
     ) -> Result<()> {
         let mut sql = String::new();
         sql_insert_or_update(table, columns, &mut sql);
-        self.execute(&sql, rusqlite::params_from_iter(values))?;
+        self.run(&sql, values)?;
         Ok(())
     }
     fn update(
@@ -220,12 +728,13 @@ impl ConnectionMethods for rusqlite::Connection {
             columns,
             &mut SQLitePlaceholderSource::new(),
             &mut sql,
+            helper::bare_ident,
         );
         let placeholder_values = [values, &[pk]].concat();
         if cfg!(feature = "log") {
             debug!("update sql {}", sql);
         }
-        self.execute(&sql, rusqlite::params_from_iter(placeholder_values))?;
+        self.run(&sql, &placeholder_values)?;
         Ok(())
     }
     fn delete_where(&self, table: &str, expr: BoolExpr) -> Result<usize> {
@@ -237,52 +746,262 @@ impl ConnectionMethods for rusqlite::Connection {
             &mut values,
             &mut SQLitePlaceholderSource::new(),
             &mut sql,
+            self.timestamp_format,
         );
-        let cnt = self.execute(&sql, rusqlite::params_from_iter(values))?;
-        Ok(cnt)
+        let values: Vec<SqlValRef<'_>> = values.iter().map(SqlVal::as_ref).collect();
+        self.run(&sql, &values)
+    }
+    fn update_where(
+        &self,
+        table: &str,
+        assignments: &[query::Assignment],
+        expr: BoolExpr,
+    ) -> Result<usize> {
+        let mut sql = String::new();
+        let mut values: Vec<SqlVal> = Vec::new();
+        let mut pls = SQLitePlaceholderSource::new();
+        helper::sql_update_set(
+            table,
+            assignments,
+            &mut values,
+            &mut pls,
+            &mut sql,
+            helper::bare_ident,
+        );
+        sql.push_str(" WHERE ");
+        sql_for_expr(
+            query::Expr::Condition(Box::new(expr)),
+            &mut values,
+            &mut pls,
+            &mut sql,
+            self.timestamp_format,
+        );
+        if cfg!(feature = "log") {
+            debug!("update sql {}", sql);
+        }
+        let values: Vec<SqlValRef<'_>> = values.iter().map(SqlVal::as_ref).collect();
+        self.run(&sql, &values)
     }
     fn has_table(&self, table: &str) -> Result<bool> {
-        let mut stmt =
-            self.prepare("SELECT name FROM sqlite_master WHERE type='table' AND name=?;")?;
-        let mut rows = stmt.query([table])?;
-        Ok(rows.next()?.is_some())
+        let sql = "SELECT name FROM sqlite_master WHERE type='table' AND name=?;";
+        self.logged(sql, [SqlValRef::Text(table)], || {
+            let mut stmt = self.prepare(sql)?;
+            let mut rows = stmt.query([table])?;
+            Ok(rows.next()?.is_some())
+        })
+    }
+    fn explain(
+        &self,
+        table: &str,
+        columns: &[Column],
+        expr: Option<BoolExpr>,
+        limit: Option<i32>,
+        offset: Option<i32>,
+        order: Option<&[Order]>,
+        _analyze: bool,
+    ) -> Result<QueryPlan> {
+        // SQLite cannot measure the query as it runs, so analyze is
+        // ignored
+        let (sqlquery, values) = query_sql(
+            table,
+            columns,
+            None,
+            expr,
+            limit,
+            offset,
+            order,
+            self.timestamp_format,
+        );
+        let sql = format!("EXPLAIN QUERY PLAN {}", sqlquery);
+        // Each row is (id, parent, notused, detail), with top-level
+        // steps having parent 0
+        let steps: Vec<(i64, i64, String)> =
+            self.logged(&sql, values.iter().map(SqlVal::as_ref), || {
+                let mut stmt = self.prepare(&sql)?;
+                let rows = stmt
+                    .query_map(self.params(values.iter().map(SqlVal::as_ref)), |row| {
+                        Ok((row.get(0)?, row.get(1)?, row.get(3)?))
+                    })?;
+                Ok(rows.collect::<rusqlite::Result<_>>()?)
+            })?;
+        Ok(QueryPlan {
+            sql: sqlquery,
+            nodes: plan_nodes(&steps, 0),
+        })
+    }
+    fn aggregate(
+        &self,
+        table: &str,
+        groups: &[Column],
+        aggregates: &[AggregateExpr],
+        expr: Option<BoolExpr>,
+        having: Option<Having>,
+    ) -> Result<Vec<Vec<SqlVal>>> {
+        let mut sqlquery = String::new();
+        let mut values: Vec<SqlVal> = Vec::new();
+        let mut pls = SQLitePlaceholderSource::new();
+        let timestamp_format = self.timestamp_format;
+        let f =
+            |expr, values: &mut Vec<SqlVal>, pls: &mut SQLitePlaceholderSource, w: &mut String| {
+                sql_for_expr(expr, values, pls, w, timestamp_format)
+            };
+        helper::sql_select_aggregates(
+            groups,
+            aggregates,
+            table,
+            f,
+            &mut values,
+            &mut pls,
+            &mut sqlquery,
+            helper::bare_ident,
+            |_| None,
+        );
+        if let Some(expr) = expr {
+            sqlquery.write_str(" WHERE ").unwrap();
+            f(
+                query::Expr::Condition(Box::new(expr)),
+                &mut values,
+                &mut pls,
+                &mut sqlquery,
+            );
+        }
+        helper::sql_group_by(
+            groups,
+            having,
+            f,
+            &mut values,
+            &mut pls,
+            &mut sqlquery,
+            helper::bare_ident,
+        );
+        debug!("aggregate sql {}", sqlquery);
+
+        let adapter = self.logged(&sqlquery, values.iter().map(SqlVal::as_ref), || {
+            let stmt = self.prepare(&sqlquery)?;
+            let params = self.params(values.iter().map(SqlVal::as_ref));
+            QueryAdapter::new(stmt, params, self.timestamp_format)
+        })?;
+        helper::aggregate_rows(Box::new(adapter), groups, aggregates)
+    }
+    fn query_raw_rows<'a, 'c: 'a>(
+        &'c self,
+        sql: &str,
+        params: &[SqlVal],
+    ) -> Result<RawQueryResult<'a>> {
+        let sqlquery = helper::sql_raw(sql, params, &mut SQLitePlaceholderSource::new())?;
+        debug!("query sql {}", sqlquery);
+
+        let adapter = self.logged(&sqlquery, params.iter().map(SqlVal::as_ref), || {
+            let stmt = self.prepare(&sqlquery)?;
+            let params = self.params(params.iter().map(SqlVal::as_ref));
+            QueryAdapter::new(stmt, params, self.timestamp_format)
+        })?;
+        Ok(Box::new(adapter))
+    }
+    fn execute_batch(&self, statements: &[Statement]) -> Result<()> {
+        let conn = self.conn.rusqlite();
+        // Begins a transaction unless already in one
+        conn.execute_batch("SAVEPOINT butane_batch;")?;
+        let result = statements.iter().try_for_each(|statement| {
+            if cfg!(feature = "log") {
+                debug!("batch sql {}", statement.sql);
+            }
+            let values: Vec<SqlValRef<'_>> = statement.values.iter().map(SqlVal::as_ref).collect();
+            self.run(&statement.sql, &values)?;
+            Ok(())
+        });
+        match result {
+            Ok(()) => conn.execute_batch("RELEASE butane_batch;")?,
+            Err(_) => conn.execute_batch("ROLLBACK TO butane_batch; RELEASE butane_batch;")?,
+        }
+        result
+    }
+    fn insert_only_many(
+        &self,
+        table: &str,
+        columns: &[Column],
+        rows: &[Vec<SqlValRef<'_>>],
+    ) -> Result<()> {
+        let mut sql = String::new();
+        helper::sql_insert_with_placeholders(
+            table,
+            columns,
+            &mut SQLitePlaceholderSource::new(),
+            &mut sql,
+            helper::bare_ident,
+        );
+        self.execute_batch(&helper::statements_for_rows(&sql, rows))
     }
 }
 
-struct SqliteTransaction<'c> {
-    trans: Option<rusqlite::Transaction<'c>>,
+/// A transaction, or a savepoint nested within one when `T` is a
+/// [rusqlite::Savepoint].
+struct SqliteTransaction<T> {
+    trans: Option<SQLiteClient<T>>,
 }
-impl<'c> SqliteTransaction<'c> {
-    fn new(trans: rusqlite::Transaction<'c>) -> Self {
-        SqliteTransaction { trans: Some(trans) }
+impl<T: RusqliteTransaction> SqliteTransaction<T> {
+    fn new(
+        trans: T,
+        timestamp_format: TimestampFormat,
+        capabilities: Capabilities,
+        statements: Arc<StatementCache<()>>,
+        logger: Option<QueryLogger>,
+    ) -> Self {
+        SqliteTransaction {
+            trans: Some(SQLiteClient {
+                conn: trans,
+                timestamp_format,
+                capabilities,
+                statements,
+                logger,
+            }),
+        }
     }
-    fn get(&self) -> Result<&rusqlite::Transaction<'c>> {
+    fn get(&self) -> Result<&SQLiteClient<T>> {
         match &self.trans {
             None => Err(Self::already_consumed()),
             Some(trans) => Ok(trans),
         }
     }
-    fn wrapped_connection_methods(&self) -> Result<&rusqlite::Connection> {
-        Ok(self.get()?.deref())
+    fn wrapped_connection_methods(&self) -> Result<&SQLiteClient<T>> {
+        self.get()
     }
     fn already_consumed() -> Error {
         Error::Internal("transaction has already been consumed".to_string())
     }
 }
-connection_method_wrapper!(SqliteTransaction<'_>);
-impl<'c> BackendTransaction<'c> for SqliteTransaction<'c> {
+connection_method_wrapper!(SqliteTransaction<rusqlite::Transaction<'_>>);
+connection_method_wrapper!(SqliteTransaction<rusqlite::Savepoint<'_>>);
+impl<'c, T: RusqliteTransaction + 'c> BackendTransaction<'c> for SqliteTransaction<T>
+where
+    SqliteTransaction<T>: ConnectionMethods,
+{
     fn commit(&mut self) -> Result<()> {
         match self.trans.take() {
             None => Err(Self::already_consumed()),
-            Some(trans) => Ok(trans.commit()?),
+            Some(trans) => Ok(trans.conn.commit()?),
         }
     }
     fn rollback(&mut self) -> Result<()> {
         match self.trans.take() {
             None => Err(Self::already_consumed()),
-            Some(trans) => Ok(trans.rollback()?),
+            Some(trans) => Ok(trans.conn.rollback()?),
         }
     }
+    fn prepare(&mut self, _gid: &str) -> Result<()> {
+        Err(Error::TwoPhaseUnsupported(BACKEND_NAME))
+    }
+    fn savepoint(&mut self) -> Result<Box<dyn BackendTransaction<'_> + '_>> {
+        let client = self.trans.as_mut().ok_or_else(Self::already_consumed)?;
+        let savepoint = client.conn.savepoint()?;
+        Ok(Box::new(SqliteTransaction::new(
+            savepoint,
+            client.timestamp_format,
+            client.capabilities,
+            client.statements.clone(),
+            client.logger.clone(),
+        )))
+    }
     // Workaround for https://github.com/rust-lang/rfcs/issues/2765
     fn connection_methods(&self) -> &dyn ConnectionMethods {
         self
@@ -292,19 +1011,26 @@ impl<'c> BackendTransaction<'c> for SqliteTransaction<'c> {
     }
 }
 
+// Timestamps are written in the default format
 impl rusqlite::ToSql for SqlVal {
     fn to_sql(&self) -> rusqlite::Result<rusqlite::types::ToSqlOutput<'_>> {
-        Ok(sqlvalref_to_sqlite(&self.as_ref()))
+        Ok(sqlvalref_to_sqlite(
+            &self.as_ref(),
+            TimestampFormat::default(),
+        ))
     }
 }
 
 impl<'a> rusqlite::ToSql for SqlValRef<'a> {
     fn to_sql<'b>(&'b self) -> rusqlite::Result<rusqlite::types::ToSqlOutput<'a>> {
-        Ok(sqlvalref_to_sqlite(self))
+        Ok(sqlvalref_to_sqlite(self, TimestampFormat::default()))
     }
 }
 
-fn sqlvalref_to_sqlite<'a, 'b>(valref: &'b SqlValRef<'a>) -> rusqlite::types::ToSqlOutput<'a> {
+fn sqlvalref_to_sqlite<'a>(
+    valref: &SqlValRef<'a>,
+    timestamp_format: TimestampFormat,
+) -> rusqlite::types::ToSqlOutput<'a> {
     use rusqlite::types::{ToSqlOutput::Borrowed, ToSqlOutput::Owned, Value, ValueRef};
     use SqlValRef::*;
     match valref {
@@ -315,10 +1041,12 @@ fn sqlvalref_to_sqlite<'a, 'b>(valref: &'b SqlValRef<'a>) -> rusqlite::types::To
         Text(t) => Borrowed(ValueRef::Text(t.as_bytes())),
         Blob(b) => Borrowed(ValueRef::Blob(b)),
         #[cfg(feature = "datetime")]
-        Timestamp(dt) => {
-            let f = dt.format(SQLITE_DT_FORMAT);
-            Owned(Value::Text(f.to_string()))
-        }
+        Timestamp(dt) => match timestamp_format {
+            TimestampFormat::Text => Owned(Value::Text(dt.format(SQLITE_DT_FORMAT).to_string())),
+            TimestampFormat::Rfc3339 => Owned(Value::Text(dt.format(RFC3339_FORMAT).to_string())),
+            TimestampFormat::UnixSeconds => Owned(Value::Integer(dt.timestamp())),
+            TimestampFormat::UnixMillis => Owned(Value::Integer(dt.timestamp_millis())),
+        },
         Null => Owned(Value::Null),
         Custom(_) => panic!("Custom types not supported in sqlite"),
     }
@@ -326,22 +1054,26 @@ fn sqlvalref_to_sqlite<'a, 'b>(valref: &'b SqlValRef<'a>) -> rusqlite::types::To
 
 #[pin_project]
 struct QueryAdapterInner<'a> {
-    stmt: rusqlite::Statement<'a>,
     // will always be Some when the constructor has finished. We use an option only to get the
-    // stmt in place before we can reference it.
+    // stmt in place before we can reference it. Declared first so as to be dropped before the
+    // stmt it references, which is then returned to the statement cache.
     rows: Option<rusqlite::Rows<'a>>,
+    stmt: rusqlite::CachedStatement<'a>,
 }
 
 impl<'a> QueryAdapterInner<'a> {
-    fn new(stmt: rusqlite::Statement<'a>, params: impl rusqlite::Params) -> Result<Pin<Box<Self>>> {
-        let mut q = Box::pin(QueryAdapterInner { stmt, rows: None });
+    fn new(
+        stmt: rusqlite::CachedStatement<'a>,
+        params: impl rusqlite::Params,
+    ) -> Result<Pin<Box<Self>>> {
+        let mut q = Box::pin(QueryAdapterInner { rows: None, stmt });
         unsafe {
             //Soundness: we pin a QueryAdapterInner value containing
             //  both the stmt and the rows referencing the statement
             //  together. It is not possible to drop/move the stmt without
             //  bringing the referencing rows along with it.
             let q_ref = Pin::get_unchecked_mut(Pin::as_mut(&mut q));
-            let stmt_ref: *mut rusqlite::Statement<'a> = &mut q_ref.stmt;
+            let stmt_ref: *mut rusqlite::Statement<'a> = &mut *q_ref.stmt;
             q_ref.rows = Some((*stmt_ref).query(params)?)
         }
         Ok(q)
@@ -361,38 +1093,134 @@ impl<'a> QueryAdapterInner<'a> {
 
 struct QueryAdapter<'a> {
     inner: Pin<Box<QueryAdapterInner<'a>>>,
+    timestamp_format: TimestampFormat,
 }
 impl<'a> QueryAdapter<'a> {
-    fn new(stmt: rusqlite::Statement<'a>, params: impl rusqlite::Params) -> Result<Self> {
+    fn new(
+        stmt: rusqlite::CachedStatement<'a>,
+        params: impl rusqlite::Params,
+        timestamp_format: TimestampFormat,
+    ) -> Result<Self> {
         Ok(QueryAdapter {
             inner: QueryAdapterInner::new(stmt, params)?,
+            timestamp_format,
         })
     }
+    fn row(&self) -> Result<&rusqlite::Row<'_>> {
+        self.inner
+            .as_ref()
+            .current()
+            .ok_or_else(|| Error::Internal("no current row".to_string()))
+    }
 }
 
+// The adapter is its own current row, as decoding the row depends on
+// the timestamp format.
 impl<'a> BackendRows for QueryAdapter<'a> {
     fn next<'b>(&'b mut self) -> Result<Option<&'b (dyn BackendRow + 'b)>> {
-        Ok(self
-            .inner
-            .as_mut()
-            .next()?
-            .map(|row| row as &dyn BackendRow))
+        if self.inner.as_mut().next()?.is_none() {
+            return Ok(None);
+        }
+        Ok(Some(self as &dyn BackendRow))
     }
     fn current<'b>(&'b self) -> Option<&'b (dyn BackendRow + 'b)> {
         self.inner
             .as_ref()
             .current()
-            .map(|row| row as &dyn BackendRow)
+            .map(|_| self as &dyn BackendRow)
     }
 }
 
-impl BackendRow for rusqlite::Row<'_> {
+impl BackendRow for QueryAdapter<'_> {
     fn get(&self, idx: usize, ty: SqlType) -> Result<SqlValRef> {
-        sql_valref_from_rusqlite(self.get_ref(idx)?, &ty)
+        sql_valref_from_rusqlite(self.row()?.get_ref(idx)?, &ty, self.timestamp_format)
     }
     fn len(&self) -> usize {
-        self.as_ref().column_count()
+        self.row().map_or(0, |row| row.as_ref().column_count())
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn query_sql(
+    table: &str,
+    columns: &[Column],
+    distinct: Option<&Distinct>,
+    expr: Option<BoolExpr>,
+    limit: Option<i32>,
+    offset: Option<i32>,
+    order: Option<&[Order]>,
+    timestamp_format: TimestampFormat,
+) -> (String, Vec<SqlVal>) {
+    let mut sqlquery = String::new();
+    let mut values: Vec<SqlVal> = Vec::new();
+    let mut pls = SQLitePlaceholderSource::new();
+    let f = |expr, values: &mut Vec<SqlVal>, pls: &mut SQLitePlaceholderSource, w: &mut String| {
+        sql_for_expr(expr, values, pls, w, timestamp_format)
+    };
+    match distinct {
+        Some(distinct) => helper::sql_select_distinct(
+            columns,
+            distinct,
+            table,
+            f,
+            &mut values,
+            &mut pls,
+            &mut sqlquery,
+            helper::bare_ident,
+        ),
+        None => helper::sql_select(
+            columns,
+            table,
+            f,
+            &mut values,
+            &mut pls,
+            &mut sqlquery,
+            helper::bare_ident,
+        ),
+    }
+    if let Some(expr) = expr {
+        sqlquery.write_str(" WHERE ").unwrap();
+        f(
+            query::Expr::Condition(Box::new(expr)),
+            &mut values,
+            &mut pls,
+            &mut sqlquery,
+        );
+    }
+
+    if let Some(order) = order {
+        helper::sql_order(order, &mut sqlquery, helper::bare_ident)
     }
+
+    if let Some(limit) = limit {
+        helper::sql_limit(limit, &mut sqlquery)
+    }
+
+    if let Some(offset) = offset {
+        if limit.is_none() {
+            // Sqlite only supports offset in conjunction with
+            // limit, so add a max limit if we don't have one
+            // already.
+            helper::sql_limit(i32::MAX, &mut sqlquery)
+        }
+        helper::sql_offset(offset, &mut sqlquery)
+    }
+
+    (sqlquery, values)
+}
+
+/// The steps of `steps` whose parent is `parent`, each with its own
+/// children.
+fn plan_nodes(steps: &[(i64, i64, String)], parent: i64) -> Vec<PlanNode> {
+    steps
+        .iter()
+        .filter(|(_, p, _)| *p == parent)
+        .map(|(id, _, detail)| PlanNode {
+            detail: detail.clone(),
+            children: plan_nodes(steps, *id),
+            ..Default::default()
+        })
+        .collect()
 }
 
 fn sql_for_expr<W>(
@@ -400,19 +1228,135 @@ fn sql_for_expr<W>(
     values: &mut Vec<SqlVal>,
     pls: &mut SQLitePlaceholderSource,
     w: &mut W,
+    timestamp_format: TimestampFormat,
 ) where
     W: Write,
 {
-    helper::sql_for_expr(expr, sql_for_expr, values, pls, w)
+    if let query::Expr::Date(date) = expr {
+        sql_for_date(&date, w, timestamp_format);
+        return;
+    }
+    let expr = match expr {
+        query::Expr::Condition(c) => match *c {
+            query::BoolExpr::In(col, vals) if helper::in_list_as_param(&vals) => {
+                match json_array(&vals, timestamp_format) {
+                    Some(array) => {
+                        write!(w, "{} IN (SELECT value FROM json_each(", col).unwrap();
+                        let array = query::Expr::Val(SqlVal::Text(array));
+                        sql_for_expr(array, values, pls, w, timestamp_format);
+                        write!(w, "))").unwrap();
+                        return;
+                    }
+                    None => query::Expr::Condition(Box::new(query::BoolExpr::In(col, vals))),
+                }
+            }
+            c => query::Expr::Condition(Box::new(c)),
+        },
+        expr => expr,
+    };
+    let like = matches!(&expr, query::Expr::Condition(c)
+        if matches!(**c, query::BoolExpr::Like(..) | query::BoolExpr::ILike(..)));
+    helper::sql_for_expr(
+        expr,
+        |expr, values, pls, w| sql_for_expr(expr, values, pls, w, timestamp_format),
+        values,
+        pls,
+        w,
+        helper::bare_ident,
+    );
+    if like {
+        // SQLite alone has no default escape character
+        w.write_str(" ESCAPE '\\'").unwrap();
+    }
+}
+
+/// `vals`, as stored with `timestamp_format`, as the text of a JSON
+/// array, unless one cannot be represented in JSON.
+fn json_array(vals: &[SqlVal], timestamp_format: TimestampFormat) -> Option<String> {
+    use rusqlite::types::{ToSqlOutput, Value};
+    let mut array = Vec::with_capacity(vals.len());
+    for val in vals {
+        let val = match sqlvalref_to_sqlite(&val.as_ref(), timestamp_format) {
+            ToSqlOutput::Owned(val) => val,
+            ToSqlOutput::Borrowed(val) => val.into(),
+            _ => return None,
+        };
+        array.push(match val {
+            Value::Integer(i) => serde_json::Value::from(i),
+            Value::Real(r) => serde_json::Number::from_f64(r)?.into(),
+            Value::Text(t) => serde_json::Value::from(t),
+            Value::Null | Value::Blob(_) => return None,
+        });
+    }
+    Some(serde_json::Value::Array(array).to_string())
+}
+
+/// Writes a function of a timestamp column with the date functions of
+/// SQLite, reading and writing times in `timestamp_format`.
+fn sql_for_date<W: Write>(date: &query::DateExpr, w: &mut W, timestamp_format: TimestampFormat) {
+    let time = match timestamp_format {
+        TimestampFormat::Text | TimestampFormat::Rfc3339 => date.column.to_string(),
+        TimestampFormat::UnixSeconds => format!("{}, 'unixepoch'", date.column),
+        TimestampFormat::UnixMillis => format!("{} / 1000.0, 'unixepoch'", date.column),
+    };
+    let part = match date.function {
+        query::DateFunction::Extract(part) => {
+            let format = match part {
+                query::DatePart::Year => "%Y",
+                query::DatePart::Month => "%m",
+                query::DatePart::Day => "%d",
+                query::DatePart::Hour => "%H",
+                query::DatePart::Minute => "%M",
+                query::DatePart::Second => "%S",
+            };
+            write!(w, "CAST(strftime('{}', {}) AS INTEGER)", format, time).unwrap();
+            return;
+        }
+        query::DateFunction::Trunc(part) => part,
+    };
+    // The start of the part, as text in the default format
+    let start = match part {
+        query::DatePart::Year => "%Y-01-01 00:00:00",
+        query::DatePart::Month => "%Y-%m-01 00:00:00",
+        query::DatePart::Day => "%Y-%m-%d 00:00:00",
+        query::DatePart::Hour => "%Y-%m-%d %H:00:00",
+        query::DatePart::Minute => "%Y-%m-%d %H:%M:00",
+        query::DatePart::Second => "%Y-%m-%d %H:%M:%S",
+    };
+    match timestamp_format {
+        TimestampFormat::Text => write!(w, "strftime('{}', {})", start, time),
+        TimestampFormat::Rfc3339 => write!(
+            w,
+            "strftime('{}.000000Z', {})",
+            start.replace(' ', "T"),
+            time
+        ),
+        TimestampFormat::UnixSeconds => write!(
+            w,
+            "CAST(strftime('%s', strftime('{}', {})) AS INTEGER)",
+            start, time
+        ),
+        TimestampFormat::UnixMillis => write!(
+            w,
+            "CAST(strftime('%s', strftime('{}', {})) AS INTEGER) * 1000",
+            start, time
+        ),
+    }
+    .unwrap()
 }
 
-fn sql_val_from_rusqlite(val: rusqlite::types::ValueRef, col: &Column) -> Result<SqlVal> {
-    sql_valref_from_rusqlite(val, col.ty()).map(|v| v.into())
+fn sql_val_from_rusqlite(
+    val: rusqlite::types::ValueRef,
+    col: &Column,
+    timestamp_format: TimestampFormat,
+) -> Result<SqlVal> {
+    sql_valref_from_rusqlite(val, col.ty(), timestamp_format).map(|v| v.into())
 }
 
 fn sql_valref_from_rusqlite<'a>(
     val: rusqlite::types::ValueRef<'a>,
     ty: &SqlType,
+    timestamp_format: TimestampFormat,
 ) -> Result<SqlValRef<'a>> {
     if let rusqlite::types::ValueRef::Null = val {
         return Ok(SqlValRef::Null);
@@ -424,10 +1368,7 @@ fn sql_valref_from_rusqlite<'a>(
         SqlType::Real => SqlValRef::Real(val.as_f64()?),
         SqlType::Text => SqlValRef::Text(val.as_str()?),
         #[cfg(feature = "datetime")]
-        SqlType::Timestamp => SqlValRef::Timestamp(NaiveDateTime::parse_from_str(
-            val.as_str()?,
-            SQLITE_DT_FORMAT,
-        )?),
+        SqlType::Timestamp => SqlValRef::Timestamp(timestamp_from_sqlite(val, timestamp_format)?),
         SqlType::Blob => SqlValRef::Blob(val.as_blob()?),
         SqlType::Custom(v) => {
             return Err(Error::IncompatibleCustomT(v.deref().clone(), BACKEND_NAME))
@@ -435,63 +1376,354 @@ fn sql_valref_from_rusqlite<'a>(
     })
 }
 
-fn sql_for_op(current: &mut ADB, op: &Operation) -> Result<String> {
+#[cfg(feature = "datetime")]
+fn timestamp_from_sqlite(
+    val: rusqlite::types::ValueRef,
+    timestamp_format: TimestampFormat,
+) -> Result<NaiveDateTime> {
+    use rusqlite::types::FromSqlError;
+    Ok(match timestamp_format {
+        TimestampFormat::Text => NaiveDateTime::parse_from_str(val.as_str()?, SQLITE_DT_FORMAT)?,
+        TimestampFormat::Rfc3339 => NaiveDateTime::parse_from_str(val.as_str()?, RFC3339_FORMAT)?,
+        TimestampFormat::UnixSeconds => {
+            let secs = val.as_i64()?;
+            NaiveDateTime::from_timestamp_opt(secs, 0).ok_or(FromSqlError::OutOfRange(secs))?
+        }
+        TimestampFormat::UnixMillis => {
+            let millis = val.as_i64()?;
+            NaiveDateTime::from_timestamp_opt(
+                millis.div_euclid(1000),
+                (millis.rem_euclid(1000) * 1_000_000) as u32,
+            )
+            .ok_or(FromSqlError::OutOfRange(millis))?
+        }
+    })
+}
+
+fn migration_sql(
+    current: &ADB,
+    ops: Vec<Operation>,
+    idempotent: bool,
+    ts: TimestampFormat,
+) -> Result<String> {
+    let mut current: ADB = (*current).clone();
+    Ok(ops
+        .into_iter()
+        .map(|o| {
+            let sql = sql_for_op(&mut current, &o, idempotent, ts);
+            current.transform_with(o);
+            sql
+        })
+        .collect::<Result<Vec<String>>>()?
+        .join("\n"))
+}
+
+fn sql_for_op(
+    current: &mut ADB,
+    op: &Operation,
+    idempotent: bool,
+    ts: TimestampFormat,
+) -> Result<String> {
     match op {
-        Operation::AddTable(table) => Ok(create_table(table, false)),
-        Operation::AddTableIfNotExists(table) => Ok(create_table(table, true)),
-        Operation::RemoveTable(name) => Ok(drop_table(name)),
-        Operation::AddColumn(tbl, col) => add_column(tbl, col),
-        Operation::RemoveColumn(tbl, name) => Ok(remove_column(current, tbl, name)),
-        Operation::ChangeColumn(tbl, old, new) => Ok(change_column(current, tbl, old, Some(new))),
+        Operation::AddTable(table) => {
+            check_auto(table)?;
+            Ok(create_table_and_indexes(table, idempotent, ts))
+        }
+        Operation::AddTableIfNotExists(table) => {
+            check_auto(table)?;
+            Ok(create_table_and_indexes(table, true, ts))
+        }
+        Operation::RemoveTable(name) => Ok(drop_table(name, idempotent)),
+        Operation::AddColumn(tbl, col) if col.is_auto() => {
+            // Only the primary key can be auto, and it cannot be added
+            Err(Error::UnsupportedAuto(
+                BACKEND_NAME,
+                format!("{}.{}", tbl, col.name()),
+            ))
+        }
+        Operation::AddColumn(tbl, col) if col.default_expr().is_some() => {
+            // SQLite cannot add a column with a non-constant default
+            Ok(rebuild_table(current, tbl, ts, |table| {
+                table.add_column(col.clone())
+            }))
+        }
+        Operation::AddColumn(tbl, col) => add_column(tbl, col, ts),
+        Operation::RemoveColumn(tbl, name) => Ok(remove_column(current, tbl, name, ts)),
+        Operation::ChangeColumn(tbl, old, new) => {
+            if let Some(table) = current.get_table(tbl) {
+                let mut table = table.clone();
+                table.replace_column(new.clone());
+                check_auto(&table)?;
+            }
+            Ok(change_column(current, tbl, old, Some(new), ts))
+        }
+        Operation::ChangeColumnDefault(tbl, name, default) => {
+            // SQLite cannot alter a column's default
+            Ok(rebuild_table(current, tbl, ts, |table| {
+                if let Some(col) = table.columns.iter_mut().find(|c| c.name() == name) {
+                    col.set_default(default.clone());
+                }
+            }))
+        }
+        Operation::ChangeColumnDefaultExpr(tbl, name, expr) => {
+            Ok(rebuild_table(current, tbl, ts, |table| {
+                if let Some(col) = table.columns.iter_mut().find(|c| c.name() == name) {
+                    col.set_default_expr(expr.clone());
+                }
+            }))
+        }
+        Operation::AddUniqueConstraint(tbl, constraint) => {
+            // SQLite cannot add a constraint to an existing table
+            Ok(rebuild_table(current, tbl, ts, |table| {
+                table.add_unique_constraint(constraint.clone())
+            }))
+        }
+        Operation::RemoveUniqueConstraint(tbl, constraint) => {
+            Ok(rebuild_table(current, tbl, ts, |table| {
+                table.remove_unique_constraint(constraint)
+            }))
+        }
+        Operation::AddCheck(tbl, name, expr) => Ok(rebuild_table(current, tbl, ts, |table| {
+            table.add_check(ACheckConstraint::new(name, expr))
+        })),
+        Operation::RemoveCheck(tbl, name) => Ok(rebuild_table(current, tbl, ts, |table| {
+            table.remove_check(name)
+        })),
+        Operation::AddForeignKey(tbl, column, reference) => {
+            // SQLite cannot add a constraint to an existing table
+            Ok(rebuild_table(current, tbl, ts, |table| {
+                if let Some(col) = table.columns.iter_mut().find(|c| c.name() == column) {
+                    col.set_reference(Some(reference.clone()));
+                }
+            }))
+        }
+        Operation::RemoveForeignKey(tbl, column) => Ok(rebuild_table(current, tbl, ts, |table| {
+            if let Some(col) = table.columns.iter_mut().find(|c| c.name() == column) {
+                col.set_reference(None);
+            }
+        })),
+        Operation::SetNotNull(tbl, name, backfill) => {
+            // SQLite cannot alter a column's nullability. Copying the
+            // rows into the rebuilt table fails on any NULL left.
+            let rebuild = rebuild_table(current, tbl, ts, |table| {
+                if let Some(col) = table.columns.iter_mut().find(|c| c.name() == name) {
+                    col.set_nullable(false);
+                }
+            });
+            Ok(match backfill {
+                Some(backfill) => format!(
+                    "UPDATE {} SET {} = ({}) WHERE {} IS NULL;\n{}",
+                    tbl, name, backfill.expr, name, rebuild
+                ),
+                None => rebuild,
+            })
+        }
+        Operation::AddIndex(tbl, index) => Ok(create_index(tbl, index, idempotent)),
+        Operation::RenameTable(old, new) => Ok(rename_table(current, old, new, idempotent)),
+        Operation::RenameColumn(tbl, old, new) => {
+            Ok(rename_column(current, tbl, old, new, idempotent))
+        }
+        Operation::RemoveIndex(tbl, index) => Ok(drop_index(tbl, index, idempotent)),
+        Operation::AddView(view) => Ok(create_view(view, idempotent)),
+        Operation::RemoveView(name) => Ok(drop_view(name, idempotent)),
+        Operation::ReplaceView(view) => Ok(format!(
+            "{}\n{}",
+            drop_view(&view.name, idempotent),
+            create_view(view, idempotent)
+        )),
+        // SQLite has no schemas, all tables are in the main database
+        Operation::SetTableSchema(..) => Ok(String::new()),
+        // SQLite cannot store comments
+        Operation::SetTableComment(..) | Operation::SetColumnComment(..) => Ok(String::new()),
+        // SQLite has no enum types, enum values are stored as text
+        Operation::AddEnum(_) | Operation::RemoveEnum(_) | Operation::AddEnumValue(..) => {
+            Ok(String::new())
+        }
     }
 }
 
-fn create_table(table: &ATable, allow_exists: bool) -> String {
+fn create_table(table: &ATable, allow_exists: bool, ts: TimestampFormat) -> String {
+    let composite_pk = helper::sql_composite_pk(table, helper::bare_ident);
+    let inline_pk = composite_pk.is_none();
     let coldefs = table
         .columns
         .iter()
-        .map(define_column)
+        .map(|col| define_column(col, inline_pk, ts))
+        .chain(composite_pk)
+        .chain(
+            table
+                .unique_constraints
+                .iter()
+                .map(define_unique_constraint),
+        )
+        .chain(table.checks.iter().map(define_check))
         .collect::<Vec<String>>()
         .join(",\n");
     let modifier = if allow_exists { "IF NOT EXISTS " } else { "" };
-    format!("CREATE TABLE {}{} (\n{}\n);", modifier, table.name, coldefs)
+    format!(
+        "CREATE TABLE {}{} (\n{}\n{}\n);",
+        modifier,
+        table.name,
+        managed_marker_comment(),
+        coldefs
+    )
+}
+
+/// The comment marking tables and indexes as created by butane. SQLite
+/// keeps comments within a table's column definitions through renames
+/// and added columns, but not those elsewhere in the statement.
+fn managed_marker_comment() -> String {
+    format!("/* {} */", MANAGED_MARKER)
+}
+
+fn create_table_and_indexes(table: &ATable, allow_exists: bool, ts: TimestampFormat) -> String {
+    std::iter::once(create_table(table, allow_exists, ts))
+        .chain(create_indexes(table, allow_exists))
+        .collect::<Vec<String>>()
+        .join("\n")
 }
 
-fn define_column(col: &AColumn) -> String {
+fn create_indexes(table: &ATable, allow_exists: bool) -> impl Iterator<Item = String> + '_ {
+    table
+        .indexes
+        .iter()
+        .map(move |index| create_index(&table.name, index, allow_exists))
+}
+
+fn create_index(tbl_name: &str, index: &AIndex, allow_exists: bool) -> String {
+    let modifier = if allow_exists { "IF NOT EXISTS " } else { "" };
+    format!(
+        "CREATE INDEX {}{} ON {} ({}{}){};",
+        modifier,
+        index.name(tbl_name),
+        tbl_name,
+        managed_marker_comment(),
+        index.columns().join(", "),
+        helper::sql_index_predicate(index)
+    )
+}
+
+fn drop_index(tbl_name: &str, index: &AIndex, idempotent: bool) -> String {
+    format!(
+        "DROP INDEX {}{};",
+        if_exists(idempotent),
+        index.name(tbl_name)
+    )
+}
+
+/// Renames the table `old` to `new`. Index names are derived from the
+/// table name, so its indexes are recreated under their new names.
+fn rename_table(current: &ADB, old: &str, new: &str, idempotent: bool) -> String {
+    let mut stmts: Vec<String> = helper::rename_steps(old, new)
+        .into_iter()
+        .map(|(from, to)| format!("ALTER TABLE {} RENAME TO {};", from, to))
+        .collect();
+    if let Some(table) = current.get_table(old) {
+        for index in &table.indexes {
+            stmts.push(drop_index(old, index, idempotent));
+            stmts.push(create_index(new, index, idempotent));
+        }
+    }
+    stmts.join("\n")
+}
+
+/// Renames the column `old` of the table `tbl_name` to `new`,
+/// recreating the indexes of the column under their new names.
+fn rename_column(current: &ADB, tbl_name: &str, old: &str, new: &str, idempotent: bool) -> String {
+    let mut stmts: Vec<String> = helper::rename_steps(old, new)
+        .into_iter()
+        .map(|(from, to)| format!("ALTER TABLE {} RENAME COLUMN {} TO {};", tbl_name, from, to))
+        .collect();
+    if let Some(table) = current.get_table(tbl_name) {
+        let mut renamed = table.clone();
+        renamed.rename_column(old, new);
+        for (index, renamed) in table.indexes.iter().zip(&renamed.indexes) {
+            if index != renamed {
+                stmts.push(drop_index(tbl_name, index, idempotent));
+                stmts.push(create_index(tbl_name, renamed, idempotent));
+            }
+        }
+    }
+    stmts.join("\n")
+}
+
+/// The definition of `col`, declaring it the primary key if it is part
+/// of the key and `inline_pk`.
+fn define_column(col: &AColumn, inline_pk: bool, ts: TimestampFormat) -> String {
     let mut constraints: Vec<String> = Vec::new();
     if !col.nullable() {
         constraints.push("NOT NULL".to_string());
     }
-    if col.is_pk() {
+    if col.is_pk() && inline_pk {
         constraints.push("PRIMARY KEY".to_string());
-    }
-    if col.is_auto() && !col.is_pk() {
-        // integer primary key is automatically an alias for ROWID,
-        // and we only allow auto on integer types
-        constraints.push("AUTOINCREMENT".to_string());
+        // An integer primary key is an alias for the rowid, which is
+        // generated even without AUTOINCREMENT
+        if col.auto_increment() == Some(AutoIncrement::Identity) {
+            constraints.push("AUTOINCREMENT".to_string());
+        }
     }
     if col.unique() {
         constraints.push("UNIQUE".to_string());
     }
+    if let Some(reference) = col.reference() {
+        constraints.push(helper::sql_references(
+            reference,
+            reference.table(),
+            helper::bare_ident,
+        ));
+    }
+    if let Some(expr) = col.default_expr() {
+        constraints.push(format!("DEFAULT ({})", expr));
+    } else if let Some(Ok(default)) = col.default().clone().map(helper::sql_literal_value) {
+        // SQLite has no custom types, so every default has a literal
+        constraints.push(format!("DEFAULT {}", default));
+    }
     format!(
         "{} {} {}",
         &col.name(),
-        col_sqltype(col),
+        col_sqltype(col, ts),
         constraints.join(" ")
     )
 }
 
-fn col_sqltype(col: &AColumn) -> Cow<str> {
+/// Check that SQLite can generate the values of the auto columns of
+/// `table`, which it can only for a lone `INTEGER PRIMARY KEY`.
+fn check_auto(table: &ATable) -> Result<()> {
+    let lone_pk = table.pk().len() == 1;
+    match table
+        .columns
+        .iter()
+        .find(|col| col.is_auto() && !(col.is_pk() && lone_pk))
+    {
+        Some(col) => Err(Error::UnsupportedAuto(
+            BACKEND_NAME,
+            format!("{}.{}", table.name, col.name()),
+        )),
+        None => Ok(()),
+    }
+}
+
+fn define_unique_constraint(constraint: &AUniqueConstraint) -> String {
+    // SQLite cannot drop constraints by name, so there is no need to name them
+    format!("UNIQUE ({})", constraint.columns().join(", "))
+}
+
+fn define_check(check: &ACheckConstraint) -> String {
+    format!("CONSTRAINT {} CHECK ({})", check.name(), check.expr())
+}
+
+fn col_sqltype(col: &AColumn, ts: TimestampFormat) -> Cow<str> {
     match col.typeid() {
-        Ok(TypeIdentifier::Ty(ty)) => Cow::Borrowed(sqltype(&ty)),
+        Ok(TypeIdentifier::Ty(ty)) => Cow::Borrowed(sqltype(&ty, ts)),
         Ok(TypeIdentifier::Name(name)) => Cow::Owned(name),
+        Ok(TypeIdentifier::Enum(_)) => Cow::Borrowed(sqltype(&SqlType::Text, ts)),
         // sqlite doesn't actually require that the column type be
         // specified
         Err(_) => Cow::Borrowed(""),
     }
 }
 
-fn sqltype(ty: &SqlType) -> &'static str {
+fn sqltype(ty: &SqlType, ts: TimestampFormat) -> &'static str {
     match ty {
         SqlType::Bool => "INTEGER",
         SqlType::Int => "INTEGER",
@@ -499,33 +1731,61 @@ fn sqltype(ty: &SqlType) -> &'static str {
         SqlType::Real => "REAL",
         SqlType::Text => "TEXT",
         #[cfg(feature = "datetime")]
-        SqlType::Timestamp => "TEXT",
+        SqlType::Timestamp => match ts {
+            TimestampFormat::Text | TimestampFormat::Rfc3339 => "TEXT",
+            TimestampFormat::UnixSeconds | TimestampFormat::UnixMillis => "INTEGER",
+        },
         SqlType::Blob => "BLOB",
         SqlType::Custom(_) => panic!("Custom types not supported by sqlite backend"),
     }
 }
 
-fn drop_table(name: &str) -> String {
-    format!("DROP TABLE {};", name)
+fn drop_table(name: &str, idempotent: bool) -> String {
+    format!("DROP TABLE {}{};", if_exists(idempotent), name)
 }
 
-fn add_column(tbl_name: &str, col: &AColumn) -> Result<String> {
+fn create_view(view: &AView, allow_exists: bool) -> String {
+    let modifier = if allow_exists { "IF NOT EXISTS " } else { "" };
+    format!("CREATE VIEW {}{} AS {};", modifier, view.name, view.query)
+}
+
+fn drop_view(name: &str, idempotent: bool) -> String {
+    format!("DROP VIEW {}{};", if_exists(idempotent), name)
+}
+
+fn if_exists(idempotent: bool) -> &'static str {
+    if idempotent {
+        "IF EXISTS "
+    } else {
+        ""
+    }
+}
+
+fn add_column(tbl_name: &str, col: &AColumn, ts: TimestampFormat) -> Result<String> {
+    if col.default().is_some() {
+        // Declared by the column definition
+        return Ok(format!(
+            "ALTER TABLE {} ADD COLUMN {};",
+            tbl_name,
+            define_column(col, true, ts)
+        ));
+    }
     let default: SqlVal = helper::column_default(col)?;
     Ok(format!(
         "ALTER TABLE {} ADD COLUMN {} DEFAULT {};",
         tbl_name,
-        define_column(col),
+        define_column(col, true, ts),
         helper::sql_literal_value(default)?
     ))
 }
 
-fn remove_column(current: &mut ADB, tbl_name: &str, name: &str) -> String {
+fn remove_column(current: &mut ADB, tbl_name: &str, name: &str, ts: TimestampFormat) -> String {
     let old = current
         .get_table(tbl_name)
         .and_then(|table| table.column(name))
         .cloned();
     match old {
-        Some(col) => change_column(current, tbl_name, &col, None),
+        Some(col) => change_column(current, tbl_name, &col, None, ts),
         None => {
             crate::warn!(
                 "Cannot remove column {} that does not exist from table {}",
@@ -537,16 +1797,34 @@ fn remove_column(current: &mut ADB, tbl_name: &str, name: &str) -> String {
     }
 }
 
-fn copy_table(old: &ATable, new: &ATable) -> String {
-    let column_names = new
+/// Copies the rows of `old` into `new`, selecting each column with the
+/// expression given by `select`.
+fn copy_table(old: &ATable, new: &ATable, select: impl Fn(&AColumn) -> String) -> String {
+    let copied: Vec<&AColumn> = new
         .columns
         .iter()
-        .map(|col| col.name())
-        .collect::<Vec<&str>>()
+        .filter(|col| old.column(col.name()).is_some())
+        .collect();
+    let selected = copied
+        .iter()
+        .map(|col| select(col))
+        .collect::<Vec<String>>()
         .join(", ");
+    if copied.len() < new.columns.len() {
+        // Columns not in the old table take their defaults
+        let column_names = copied
+            .iter()
+            .map(|col| col.name())
+            .collect::<Vec<&str>>()
+            .join(", ");
+        return format!(
+            "INSERT INTO {} ({}) SELECT {} FROM {};",
+            &new.name, column_names, selected, &old.name
+        );
+    }
     format!(
         "INSERT INTO {} SELECT {} FROM {};",
-        &new.name, column_names, &old.name
+        &new.name, selected, &old.name
     )
 }
 
@@ -559,39 +1837,168 @@ fn change_column(
     tbl_name: &str,
     old: &AColumn,
     new: Option<&AColumn>,
+    ts: TimestampFormat,
+) -> String {
+    // SQLite keeps values of the old type in a column whose type
+    // changes, so they are converted as they are copied.
+    let cast = new
+        .map(|new| col_sqltype(new, ts))
+        .filter(|new_type| *new_type != col_sqltype(old, ts));
+    rebuild_table_selecting(
+        current,
+        tbl_name,
+        ts,
+        |table| match new {
+            Some(col) => table.replace_column(col.clone()),
+            None => table.remove_column(old.name()),
+        },
+        |col| match &cast {
+            Some(new_type) if col.name() == old.name() => {
+                format!("CAST({} AS {})", col.name(), new_type)
+            }
+            _ => col.name().to_string(),
+        },
+    )
+}
+
+/// Recreates the table `tbl_name` with the modifications made by `f`,
+/// preserving the data in the columns which remain.
+fn rebuild_table(
+    current: &mut ADB,
+    tbl_name: &str,
+    ts: TimestampFormat,
+    f: impl FnOnce(&mut ATable),
+) -> String {
+    rebuild_table_selecting(current, tbl_name, ts, f, |col| col.name().to_string())
+}
+
+/// Like `rebuild_table`, but the data of each remaining column is
+/// copied as selected by `select`.
+fn rebuild_table_selecting(
+    current: &mut ADB,
+    tbl_name: &str,
+    ts: TimestampFormat,
+    f: impl FnOnce(&mut ATable),
+    select: impl Fn(&AColumn) -> String,
 ) -> String {
     let table = current.get_table(tbl_name);
     if table.is_none() {
-        crate::warn!(
-            "Cannot alter column {} from table {} that does not exist",
-            &old.name(),
-            tbl_name
-        );
+        crate::warn!("Cannot alter table {} that does not exist", tbl_name);
         return "".to_string();
     }
     let old_table = table.unwrap();
     let mut new_table = old_table.clone();
     new_table.name = tmp_table_name(&new_table.name);
-    match new {
-        Some(col) => new_table.replace_column(col.clone()),
-        None => new_table.remove_column(old.name()),
-    }
-    let stmts: [&str; 4] = [
-        &create_table(&new_table, false),
-        &copy_table(old_table, &new_table),
-        &drop_table(&old_table.name),
-        &format!("ALTER TABLE {} RENAME TO {};", &new_table.name, tbl_name),
-    ];
-    let result = stmts.join("\n");
+    f(&mut new_table);
+    // SQLite refuses to rename a table while a view refers to a missing
+    // one, so the views are dropped for the duration.
+    let mut views: Vec<&AView> = current.views().collect();
+    views.sort_by(|a, b| a.name.cmp(&b.name));
+    let mut stmts: Vec<String> = views
+        .iter()
+        .map(|view| drop_view(&view.name, false))
+        .chain([
+            create_table(&new_table, false, ts),
+            copy_table(old_table, &new_table, select),
+            drop_table(&old_table.name, false),
+            format!("ALTER TABLE {} RENAME TO {};", &new_table.name, tbl_name),
+        ])
+        .collect();
     new_table.name = old_table.name.clone();
+    // Dropping the old table dropped its indexes
+    stmts.extend(create_indexes(&new_table, false));
+    stmts.extend(views.iter().map(|view| create_view(view, false)));
+    let result = stmts.join("\n");
     current.replace_table(new_table);
     result
 }
 
+/// SQL converting the timestamp columns of the tables of `db` from
+/// values stored in `from` to values stored in `to`, redeclaring them if
+/// their type differs. Add it to a migration with
+/// [add_sql][crate::migrations::MigrationMut::add_sql] when changing
+/// the [TimestampFormat] of an existing database. Values are converted
+/// with the date functions of SQLite, which keep at most milliseconds.
+#[cfg(feature = "datetime")]
+pub fn convert_timestamps_sql(db: &ADB, from: TimestampFormat, to: TimestampFormat) -> String {
+    if from == to {
+        return String::new();
+    }
+    let is_timestamp =
+        |col: &AColumn| col.typeid().ok() == Some(TypeIdentifier::Ty(SqlType::Timestamp));
+    let mut tables: Vec<&ATable> = db
+        .tables()
+        .filter(|table| table.columns.iter().any(is_timestamp))
+        .collect();
+    tables.sort_by(|a, b| a.name.cmp(&b.name));
+    let mut current = db.clone();
+    let mut stmts: Vec<String> = Vec::new();
+    for table in tables {
+        if sqltype(&SqlType::Timestamp, from) == sqltype(&SqlType::Timestamp, to) {
+            let sets = table
+                .columns
+                .iter()
+                .filter(|col| is_timestamp(col))
+                .map(|col| {
+                    format!(
+                        "{} = {}",
+                        col.name(),
+                        convert_timestamp(col.name(), from, to)
+                    )
+                })
+                .collect::<Vec<String>>()
+                .join(", ");
+            stmts.push(format!("UPDATE {} SET {};", table.name, sets));
+        } else {
+            stmts.push(rebuild_table_selecting(
+                &mut current,
+                &table.name,
+                to,
+                |_| (),
+                |col| {
+                    if is_timestamp(col) {
+                        convert_timestamp(col.name(), from, to)
+                    } else {
+                        col.name().to_string()
+                    }
+                },
+            ));
+        }
+    }
+    stmts.join("\n")
+}
+
+/// SQL expression converting the timestamp in the column `name` from
+/// `from` to `to`, by way of milliseconds since the epoch.
+#[cfg(feature = "datetime")]
+fn convert_timestamp(name: &str, from: TimestampFormat, to: TimestampFormat) -> String {
+    let millis = match from {
+        TimestampFormat::Text | TimestampFormat::Rfc3339 => format!(
+            "CAST(round((julianday({}) - 2440587.5) * 86400000) AS INTEGER)",
+            name
+        ),
+        TimestampFormat::UnixSeconds => format!("{} * 1000", name),
+        TimestampFormat::UnixMillis => name.to_string(),
+    };
+    match to {
+        TimestampFormat::Text => format!(
+            "strftime('%Y-%m-%d %H:%M:%S', {} / 1000.0, 'unixepoch')",
+            millis
+        ),
+        // strftime only has milliseconds
+        TimestampFormat::Rfc3339 => format!(
+            "strftime('%Y-%m-%dT%H:%M:%f', {} / 1000.0, 'unixepoch') || '000Z'",
+            millis
+        ),
+        TimestampFormat::UnixSeconds => format!("({}) / 1000", millis),
+        TimestampFormat::UnixMillis => millis,
+    }
+}
+
 pub fn sql_insert_or_update(table: &str, columns: &[Column], w: &mut impl Write) {
     write!(w, "INSERT OR REPLACE ").unwrap();
     write!(w, "INTO {} (", table).unwrap();
-    helper::list_columns(columns, w);
+    helper::list_columns(columns, w, helper::bare_ident);
     write!(w, ") VALUES (").unwrap();
     columns.iter().fold("", |sep, _| {
         write!(w, "{}?", sep).unwrap();
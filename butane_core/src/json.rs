@@ -0,0 +1,138 @@
+//! JSON fields, which may be queried by path, see [Json].
+
+use crate::{
+    Error::CannotConvertSqlVal, FieldType, FromSql, Result, SqlType, SqlVal, SqlValRef, ToSql,
+};
+use once_cell::unsync::OnceCell;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt::{Debug, Formatter};
+use std::ops::{Deref, DerefMut};
+
+/// A field stored as JSON, by default any [serde_json::Value].
+///
+/// It is stored as text on every backend, and may be queried by path
+/// with the JSON functions of the database, as in
+///
+/// ```ignore
+/// #[model]
+/// struct Customer {
+///   id: i64,
+///   data: Json,
+/// }
+///
+/// let parisians = filter!(Customer, data["address"]["city"] == "Paris");
+/// let vips = filter!(Customer, data.contains(json!({"vip": true})));
+/// ```
+///
+/// The value is used through `Deref` and `DerefMut`.
+pub struct Json<T = serde_json::Value> {
+    value: T,
+    // The serialized value, to which the value saved to the database
+    // refers. Cleared whenever the value may be changed.
+    text: OnceCell<String>,
+}
+impl<T> Json<T> {
+    pub fn new(value: T) -> Self {
+        Json {
+            value,
+            text: OnceCell::new(),
+        }
+    }
+    pub fn into_inner(self) -> T {
+        self.value
+    }
+}
+impl<T: Serialize> Json<T> {
+    fn text(&self) -> &str {
+        self.text.get_or_init(|| {
+            // A value deserialized from JSON may always be serialized
+            // to it again, unless its Serialize impl fails, as for a
+            // map with non-string keys
+            serde_json::to_string(&self.value).expect("JSON field cannot be serialized")
+        })
+    }
+}
+
+impl<T> Deref for Json<T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        &self.value
+    }
+}
+impl<T> DerefMut for Json<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.text.take();
+        &mut self.value
+    }
+}
+impl<T> From<T> for Json<T> {
+    fn from(value: T) -> Self {
+        Json::new(value)
+    }
+}
+impl<T: Clone> Clone for Json<T> {
+    fn clone(&self) -> Self {
+        Json {
+            value: self.value.clone(),
+            text: self.text.clone(),
+        }
+    }
+}
+impl<T: Debug> Debug for Json<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("Json").field(&self.value).finish()
+    }
+}
+impl<T: Default> Default for Json<T> {
+    fn default() -> Self {
+        Json::new(T::default())
+    }
+}
+impl<T: PartialEq> PartialEq for Json<T> {
+    fn eq(&self, other: &Json<T>) -> bool {
+        self.value == other.value
+    }
+}
+impl<T: Eq> Eq for Json<T> {}
+
+impl<T: Serialize> Serialize for Json<T> {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        self.value.serialize(serializer)
+    }
+}
+impl<'de, T: Deserialize<'de>> Deserialize<'de> for Json<T> {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        T::deserialize(deserializer).map(Json::new)
+    }
+}
+
+impl<T: Serialize> ToSql for Json<T> {
+    fn to_sql(&self) -> SqlVal {
+        SqlVal::Text(self.text().to_string())
+    }
+    fn to_sql_ref(&self) -> SqlValRef<'_> {
+        SqlValRef::Text(self.text())
+    }
+}
+impl<T: DeserializeOwned> FromSql for Json<T> {
+    fn from_sql_ref(valref: SqlValRef) -> Result<Self> {
+        if let SqlValRef::Text(text) = valref {
+            if let Ok(value) = serde_json::from_str(text) {
+                return Ok(Json::new(value));
+            }
+        }
+        Err(CannotConvertSqlVal(SqlType::Text, valref.into()))
+    }
+}
+
+impl<T: Serialize + DeserializeOwned> FieldType for Json<T> {
+    const SQLTYPE: SqlType = SqlType::Text;
+    type RefType = Self;
+}
@@ -6,14 +6,24 @@ use std::cmp::{Eq, PartialEq};
 use std::default::Default;
 use thiserror::Error as ThisError;
 
+#[cfg(feature = "cbor")]
+mod cbor;
 pub mod codegen;
+pub mod compat;
 pub mod custom;
 pub mod db;
 pub mod fkey;
+pub mod json;
 pub mod many;
 pub mod migrations;
 pub mod query;
+pub mod retry;
+pub mod router;
+pub mod scope;
+pub mod search;
 pub mod sqlval;
+pub mod sync;
+pub mod twophase;
 
 #[cfg(feature = "uuid")]
 pub mod uuid;
@@ -22,6 +32,9 @@ use db::{BackendRow, Column, ConnectionMethods};
 
 use custom::SqlTypeCustom;
 pub use query::Query;
+// For use by generated code
+#[doc(hidden)]
+pub use serde;
 pub use sqlval::*;
 
 pub type Result<T> = std::result::Result<T, crate::Error>;
@@ -103,6 +116,43 @@ pub trait DataObject: DataResult<DBO = Self> {
     fn delete(&self, conn: &impl ConnectionMethods) -> Result<()>;
 }
 
+/// Asynchronous counterparts of the methods of [DataObject], for use
+/// with an [AsyncConnectionMethods][db::nonblocking::AsyncConnectionMethods].
+/// Implemented by `#[model]` with the `async` feature. See
+/// [nonblocking][db::nonblocking] on cancelling the returned futures.
+#[cfg(feature = "async")]
+pub trait DataObjectAsync: DataObject + Send {
+    /// Find this object in the database based on primary key.
+    fn get_async<'a>(
+        conn: &'a dyn db::nonblocking::AsyncConnectionMethods,
+        id: impl Borrow<Self::PKType>,
+    ) -> db::nonblocking::BoxFuture<'a, Result<Self>>
+    where
+        Self: 'a,
+    {
+        let query = <Self as DataResult>::query().filter(query::BoolExpr::Eq(
+            Self::PKCOL,
+            query::Expr::Val(id.borrow().to_sql()),
+        ));
+        Box::pin(async move {
+            query
+                .load_first_async(conn)
+                .await?
+                .ok_or(Error::NoSuchObject)
+        })
+    }
+    /// Save the object to the database.
+    fn save_async<'a>(
+        &'a mut self,
+        conn: &'a dyn db::nonblocking::AsyncConnectionMethods,
+    ) -> db::nonblocking::BoxFuture<'a, Result<()>>;
+    /// Delete the object from the database.
+    fn delete_async<'a>(
+        &'a self,
+        conn: &'a dyn db::nonblocking::AsyncConnectionMethods,
+    ) -> db::nonblocking::BoxFuture<'a, Result<()>>;
+}
+
 pub trait ModelTyped {
     type Model: DataObject;
 }
@@ -120,6 +170,8 @@ pub enum Error {
         "Mismatch between sql types and rust types while loading data for column {col}. {detail}"
     )]
     SqlResultTypeMismatch { col: String, detail: String },
+    #[error("No such column {0}")]
+    NoSuchColumn(String),
     #[error("SqlType not known for {0}")]
     UnknownSqlType(String),
     #[error("Value has not been loaded from the database")]
@@ -132,16 +184,88 @@ pub enum Error {
     AlreadyInitialized,
     #[error("Migration error {0}")]
     MigrationError(String),
+    #[error("Schema after {stage} does not match that expected. Differences: {ops:?}")]
+    SchemaMismatch {
+        stage: String,
+        ops: Vec<migrations::adb::Operation>,
+    },
+    #[error("Invalid schema: {}", .0.iter().map(ToString::to_string).collect::<Vec<_>>().join("; "))]
+    InvalidSchema(Vec<migrations::adb::SchemaProblem>),
     #[error("Unknown backend {0}")]
     UnknownBackend(String),
+    #[error("No connection spec found at {0}")]
+    NoConnectionSpec(String),
+    #[error("Invalid connection option {0}")]
+    InvalidConnectionOption(String),
+    #[error("Cannot spool {0} for retry")]
+    CannotSpool(String),
+    #[error("Cannot sync {0}")]
+    CannotSync(String),
+    #[error("Two-phase commit is not supported by the {0} backend")]
+    TwoPhaseUnsupported(&'static str),
+    #[error("Nested transactions cannot be prepared for two-phase commit")]
+    NestedPrepare,
+    #[error("Two-phase commit of {gid} failed after the decision to commit it: {source}")]
+    InDoubt { gid: String, source: Box<Error> },
+    #[error("Migration {migration} failed verification and was rolled back: {check}")]
+    VerificationFailed { migration: String, check: String },
+    #[error("Cancelling statements is not supported by the {0} backend")]
+    CancelUnsupported(&'static str),
+    #[error("A statement on this connection was cancelled, so the connection must be discarded")]
+    ConnectionCancelled,
+    #[error("Migration {name} did not finish within {limit:?} and was rolled back")]
+    MigrationTimeout {
+        name: String,
+        limit: std::time::Duration,
+    },
+    #[error("Statement logging is not supported by the {0} backend")]
+    LoggingUnsupported(&'static str),
+    #[error("The {0} timeout is not supported by the {1} backend")]
+    TimeoutUnsupported(&'static str, &'static str),
+    #[error("Reconnecting is not supported by the {0} backend")]
+    ReconnectUnsupported(&'static str),
+    #[error("Full-text search is not supported by the {0} backend")]
+    SearchUnsupported(&'static str),
+    #[error("Query plans are not available from this connection")]
+    ExplainUnsupported,
+    #[error("Aggregates are not available from this connection")]
+    AggregateUnsupported,
+    #[error("Raw queries are not available from this connection")]
+    RawQueryUnsupported,
+    #[error("Distinct queries are not available from this connection")]
+    DistinctUnsupported,
+    #[error("DISTINCT ON is not supported by the {0} backend")]
+    DistinctOnUnsupported(&'static str),
+    #[error("Queries which update or delete objects cannot have {0}")]
+    BulkWriteUnsupported(&'static str),
+    #[error("Statement has {0} parameters but the {1} backend accepts at most {2}")]
+    TooManyParameters(usize, &'static str, usize),
+    #[error("Raw SQL has {0} placeholders but {1} parameters were given")]
+    RawParameterCount(usize, usize),
+    #[error("Invalid pagination cursor")]
+    InvalidCursor,
+    #[error("Write to {0} outside the scope of the current owner")]
+    OutOfScope(String),
     #[error("Range error")]
     OutOfRange,
     #[error("Internal logic error {0}")]
     Internal(String),
     #[error("Cannot resolve type {0}. Are you missing a #[butane_type] attribute?")]
     CannotResolveType(String),
+    #[error(
+        "Cannot refer to {0}, which has a composite primary key, with a single foreign key column"
+    )]
+    CompositeKeyReference(String),
+    #[error("Length is only supported for text columns and precision and scale for real columns. {0} cannot have them.")]
+    InvalidTypeParams(String),
     #[error("Auto fields are only supported for integer fields. {0} cannot be auto.")]
     InvalidAuto(String),
+    #[error(
+        "The {0} backend only supports auto on a lone integer primary key. {1} cannot be auto."
+    )]
+    UnsupportedAuto(&'static str, String),
+    #[error("Unknown referential action {0}. Expected one of no_action, restrict, cascade, set_null, set_default")]
+    UnknownReferentialAction(String),
     #[error("No implicit default available for custom sql types.")]
     NoCustomDefault,
     #[error("Backend {1} is not compatible with custom SqlVal {0:?}")]
@@ -152,6 +276,9 @@ pub enum Error {
     LiteralForCustomUnsupported(custom::SqlValCustom),
     #[error("(De)serialization error {0}")]
     SerdeJson(#[from] serde_json::Error),
+    #[cfg(feature = "cbor")]
+    #[error("CBOR (de)serialization error {0}")]
+    Cbor(String),
     #[error("IO error {0}")]
     IO(#[from] std::io::Error),
     #[cfg(feature = "sqlite")]
@@ -163,6 +290,9 @@ pub enum Error {
     #[cfg(feature = "pg")]
     #[error("Postgres error {0}")]
     Postgres(#[from] postgres::Error),
+    #[cfg(feature = "mysql")]
+    #[error("MySQL error {0}")]
+    MySql(#[from] mysql::Error),
     #[cfg(feature = "datetime")]
     #[error("Chrono error {0}")]
     Chrono(#[from] chrono::ParseError),
@@ -175,6 +305,168 @@ pub enum Error {
     Generic(#[from] Box<dyn std::error::Error + Sync + Send>),
 }
 
+impl Error {
+    /// The code of this error, if it is one of the common, predictable
+    /// failures described by [`ErrorCode`].
+    pub fn code(&self) -> Option<ErrorCode> {
+        match self {
+            Error::CannotResolveType(_) => Some(ErrorCode::UnresolvedType),
+            Error::NoConnectionSpec(_) => Some(ErrorCode::NoConnectionSpec),
+            #[cfg(feature = "sqlite")]
+            Error::SQLite(rusqlite::Error::SqliteFailure(_, Some(msg)))
+                if msg.starts_with("no such table") || msg.starts_with("no such column") =>
+            {
+                Some(ErrorCode::UnappliedMigration)
+            }
+            #[cfg(feature = "pg")]
+            Error::Postgres(e)
+                if e.code() == Some(&postgres::error::SqlState::UNDEFINED_TABLE)
+                    || e.code() == Some(&postgres::error::SqlState::UNDEFINED_COLUMN) =>
+            {
+                Some(ErrorCode::UnappliedMigration)
+            }
+            // ER_NO_SUCH_TABLE and ER_BAD_FIELD_ERROR
+            #[cfg(feature = "mysql")]
+            Error::MySql(mysql::Error::MySqlError(e)) if e.code == 1146 || e.code == 1054 => {
+                Some(ErrorCode::UnappliedMigration)
+            }
+            _ => None,
+        }
+    }
+
+    /// Whether the error is likely to be transient, e.g. because the
+    /// database is locked or the connection to it was lost, so that the
+    /// operation may succeed if retried later.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            Error::IO(_) => true,
+            #[cfg(feature = "sqlite")]
+            Error::SQLite(rusqlite::Error::SqliteFailure(e, _)) => matches!(
+                e.code,
+                rusqlite::ErrorCode::DatabaseBusy | rusqlite::ErrorCode::DatabaseLocked
+            ),
+            #[cfg(feature = "pg")]
+            Error::Postgres(e) => {
+                use postgres::error::SqlState;
+                const TRANSIENT: &[SqlState] = &[
+                    SqlState::T_R_SERIALIZATION_FAILURE,
+                    SqlState::T_R_DEADLOCK_DETECTED,
+                    SqlState::CONNECTION_EXCEPTION,
+                    SqlState::CONNECTION_FAILURE,
+                    SqlState::SQLCLIENT_UNABLE_TO_ESTABLISH_SQLCONNECTION,
+                    SqlState::ADMIN_SHUTDOWN,
+                    SqlState::CRASH_SHUTDOWN,
+                    SqlState::CANNOT_CONNECT_NOW,
+                    SqlState::TOO_MANY_CONNECTIONS,
+                ];
+                match e.code() {
+                    Some(code) => TRANSIENT.contains(code),
+                    None => {
+                        e.is_closed()
+                            || std::error::Error::source(e)
+                                .is_some_and(|source| source.is::<std::io::Error>())
+                    }
+                }
+            }
+            // ER_LOCK_WAIT_TIMEOUT and ER_LOCK_DEADLOCK
+            #[cfg(feature = "mysql")]
+            Error::MySql(mysql::Error::MySqlError(e)) => e.code == 1205 || e.code == 1213,
+            #[cfg(feature = "mysql")]
+            Error::MySql(e) => e.is_connectivity_error(),
+            _ => false,
+        }
+    }
+
+    /// Whether the error is due to a conflict with a concurrent
+    /// transaction, such as a serialization failure or a deadlock, so
+    /// that the transaction may succeed if run again from the start.
+    /// See [Connection::transaction_retrying](db::Connection::transaction_retrying).
+    pub fn is_serialization_failure(&self) -> bool {
+        match self {
+            #[cfg(feature = "sqlite")]
+            Error::SQLite(rusqlite::Error::SqliteFailure(e, _)) => matches!(
+                e.code,
+                rusqlite::ErrorCode::DatabaseBusy | rusqlite::ErrorCode::DatabaseLocked
+            ),
+            #[cfg(feature = "pg")]
+            Error::Postgres(e) => {
+                use postgres::error::SqlState;
+                e.code().is_some_and(|code| {
+                    *code == SqlState::T_R_SERIALIZATION_FAILURE
+                        || *code == SqlState::T_R_DEADLOCK_DETECTED
+                })
+            }
+            // ER_LOCK_WAIT_TIMEOUT and ER_LOCK_DEADLOCK
+            #[cfg(feature = "mysql")]
+            Error::MySql(mysql::Error::MySqlError(e)) => e.code == 1205 || e.code == 1213,
+            _ => false,
+        }
+    }
+
+    /// A suggestion for how to fix this error, if it is a common one.
+    pub fn hint(&self) -> Option<&'static str> {
+        self.code().map(|code| code.hint())
+    }
+}
+
+/// Common, predictable failures. Each has a stable, machine-readable
+/// code and a hint on how to fix it.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum ErrorCode {
+    /// `B0001`: a model has no primary key. Butane uses the field named
+    /// `id`, or the field marked with `#[pk]`.
+    NoPrimaryKey,
+    /// `B0002`: the sql type of a field could not be determined. The
+    /// field's type must implement [`FieldType`], and types declared
+    /// with `#[butane_type]` must be declared before the models which
+    /// use them are compiled.
+    UnresolvedType,
+    /// `B0003`: the database lacks a table or column the models
+    /// expect, almost always because migrations have not been applied.
+    UnappliedMigration,
+    /// `B0004`: no connection spec was found, so butane does not know
+    /// which database to use.
+    NoConnectionSpec,
+}
+impl ErrorCode {
+    /// The stable code, e.g. `B0001`.
+    pub fn code(&self) -> &'static str {
+        match self {
+            ErrorCode::NoPrimaryKey => "B0001",
+            ErrorCode::UnresolvedType => "B0002",
+            ErrorCode::UnappliedMigration => "B0003",
+            ErrorCode::NoConnectionSpec => "B0004",
+        }
+    }
+
+    /// A suggestion for how to fix the failure.
+    pub fn hint(&self) -> &'static str {
+        match self {
+            ErrorCode::NoPrimaryKey => {
+                "Name the primary key field `id` or mark it with #[pk]."
+            }
+            ErrorCode::UnresolvedType => {
+                "Make sure the field's type implements FieldType. For a custom type, add #[butane_type] to it and declare it before any model using it."
+            }
+            ErrorCode::UnappliedMigration => {
+                "The database schema does not match the models. Create a migration with `butane makemigration` and apply it with `butane migrate`, or apply the unapplied migrations when the program starts."
+            }
+            ErrorCode::NoConnectionSpec => {
+                "Run `butane init <BACKEND> <CONNECTION>` in the project directory to create one."
+            }
+        }
+    }
+
+    /// Link to the documentation of this failure.
+    pub fn docs_url(&self) -> String {
+        format!(
+            "https://docs.rs/butane/latest/butane/enum.ErrorCode.html#variant.{:?}",
+            self
+        )
+    }
+}
+
 #[cfg(feature = "sqlite")]
 impl From<rusqlite::types::FromSqlError> for Error {
     fn from(e: rusqlite::types::FromSqlError) -> Self {
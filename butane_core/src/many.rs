@@ -1,6 +1,8 @@
+#[cfg(feature = "async")]
+use crate::db::nonblocking::AsyncConnectionMethods;
 use crate::db::{Column, ConnectionMethods};
 use crate::query::{BoolExpr, Expr};
-use crate::{DataObject, Error, FieldType, Result, SqlType, SqlVal, ToSql};
+use crate::{DataObject, Error, FieldType, Result, SqlType, SqlVal, SqlValRef, ToSql};
 use once_cell::unsync::OnceCell;
 use serde::{Deserialize, Serialize};
 use std::borrow::Cow;
@@ -104,23 +106,43 @@ where
     /// Used by macro-generated code. You do not need to call this directly.
     pub fn save(&mut self, conn: &impl ConnectionMethods) -> Result<()> {
         let owner = self.owner.as_ref().ok_or(Error::NotInitialized)?;
-        while !self.new_values.is_empty() {
+        if !self.new_values.is_empty() {
+            let rows: Vec<Vec<SqlValRef>> = self
+                .new_values
+                .iter()
+                .map(|value| vec![owner.as_ref(), value.as_ref()])
+                .collect();
+            conn.insert_only_many(&self.item_table, &self.columns(), &rows)?;
+            self.new_values.clear();
+        }
+        if !self.removed_values.is_empty() {
+            conn.delete_where(
+                &self.item_table,
+                BoolExpr::In("has", std::mem::take(&mut self.removed_values)),
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Used by macro-generated code. You do not need to call this directly.
+    #[cfg(feature = "async")]
+    pub async fn save_async(&mut self, conn: &dyn AsyncConnectionMethods) -> Result<()> {
+        let owner = self.owner.as_ref().ok_or(Error::NotInitialized)?;
+        while let Some(new_value) = self.new_values.pop() {
             conn.insert_only(
                 &self.item_table,
                 &self.columns(),
-                &[
-                    owner.as_ref(),
-                    self.new_values.pop().unwrap().as_ref().clone(),
-                ],
-            )?;
+                &[owner.as_ref(), new_value.as_ref()],
+            )
+            .await?;
         }
         if !self.removed_values.is_empty() {
             conn.delete_where(
                 &self.item_table,
                 BoolExpr::In("has", std::mem::take(&mut self.removed_values)),
-            )?;
+            )
+            .await?;
         }
-        self.new_values.clear();
         Ok(())
     }
 
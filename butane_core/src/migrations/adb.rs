@@ -15,6 +15,11 @@ use std::collections::{HashMap, HashSet};
 pub enum TypeIdentifier {
     Ty(SqlType),
     Name(String),
+    /// An enumerated type with the given name, declared with
+    /// `#[butane_type(Enum)]`. Its values are recorded in
+    /// [ADB::enums]. Postgres stores it as a native enum type, other
+    /// backends as `Text`.
+    Enum(String),
 }
 impl From<SqlType> for TypeIdentifier {
     fn from(ty: SqlType) -> Self {
@@ -25,7 +30,11 @@ impl From<SqlType> for TypeIdentifier {
 /// Key used to help resolve `DeferredSqlType`
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub enum TypeKey {
-    /// Represents a type which is the primary key for a table with the given name
+    /// Represents a type which is the primary key for a table with the given name.
+    /// Only single-column keys have a type: a column referring to a table
+    /// with a composite key is not resolved, and fails with
+    /// [Error::CompositeKeyReference](crate::Error::CompositeKeyReference)
+    /// as a foreign key can only hold one value of the key.
     PK(String),
     /// Represents a type which is not natively known to butane but
     /// which butane will be made aware of with the `#\[butane_type\]` macro
@@ -131,17 +140,73 @@ impl TypeResolver {
     }
 }
 
+/// A problem with a schema, found by [ADB::validate].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SchemaProblem {
+    /// The table has no primary key.
+    NoPrimaryKey(String),
+    /// The table has more than one column with the same name.
+    DuplicateColumn { table: String, column: String },
+    /// The type of the column is not known.
+    UnresolvedType {
+        table: String,
+        column: String,
+        key: TypeKey,
+    },
+    /// The column references a table or column which does not exist.
+    MissingReference {
+        table: String,
+        column: String,
+        reference: AForeignKey,
+    },
+}
+impl std::fmt::Display for SchemaProblem {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            SchemaProblem::NoPrimaryKey(table) => write!(f, "{} has no primary key", table),
+            SchemaProblem::DuplicateColumn { table, column } => {
+                write!(f, "{}.{} is declared more than once", table, column)
+            }
+            SchemaProblem::UnresolvedType { table, column, key } => {
+                write!(f, "the type {} of {}.{} is not known", key, table, column)
+            }
+            SchemaProblem::MissingReference {
+                table,
+                column,
+                reference,
+            } => match &reference.column {
+                Some(target) => write!(
+                    f,
+                    "{}.{} references {}.{}, which does not exist",
+                    table, column, reference.table, target
+                ),
+                None => write!(
+                    f,
+                    "{}.{} references {}, which does not exist",
+                    table, column, reference.table
+                ),
+            },
+        }
+    }
+}
+
 /// Abstract representation of a database schema.
 #[derive(Clone, Debug, Default, Serialize, Deserialize)]
 pub struct ADB {
     tables: HashMap<String, ATable>,
     extra_types: HashMap<TypeKey, DeferredSqlType>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    views: HashMap<String, AView>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    enums: HashMap<String, AEnum>,
 }
 impl ADB {
     pub fn new() -> Self {
         ADB {
             tables: HashMap::new(),
             extra_types: HashMap::new(),
+            views: HashMap::new(),
+            enums: HashMap::new(),
         }
     }
     pub fn tables(&self) -> impl Iterator<Item = &ATable> {
@@ -159,22 +224,107 @@ impl ADB {
     pub fn remove_table(&mut self, name: &str) {
         self.tables.remove(name);
     }
+    /// Rename the table `old` to `new`, along with the references to it.
+    pub fn rename_table(&mut self, old: &str, new: &str) {
+        if let Some(mut table) = self.tables.remove(old) {
+            table.name = new.to_string();
+            self.tables.insert(new.to_string(), table);
+        }
+        for table in self.tables.values_mut() {
+            for reference in table
+                .columns
+                .iter_mut()
+                .filter_map(|c| c.reference.as_mut())
+            {
+                if reference.table == old {
+                    reference.table = new.to_string();
+                }
+            }
+        }
+        for ty in self.extra_types.values_mut() {
+            if *ty == DeferredSqlType::Deferred(TypeKey::PK(old.to_string())) {
+                *ty = DeferredSqlType::Deferred(TypeKey::PK(new.to_string()));
+            }
+        }
+    }
+    /// Rename the column `old` of the table `table` to `new`, along with
+    /// the references to it.
+    pub fn rename_column(&mut self, table: &str, old: &str, new: &str) {
+        if let Some(t) = self.tables.get_mut(table) {
+            t.rename_column(old, new);
+        }
+        for t in self.tables.values_mut() {
+            for reference in t.columns.iter_mut().filter_map(|c| c.reference.as_mut()) {
+                if reference.table == table && reference.column.as_deref() == Some(old) {
+                    reference.column = Some(new.to_string());
+                }
+            }
+        }
+    }
     pub fn add_type(&mut self, key: TypeKey, sqltype: DeferredSqlType) {
         self.extra_types.insert(key, sqltype);
     }
+    pub fn views(&self) -> impl Iterator<Item = &AView> {
+        self.views.values()
+    }
+    pub fn get_view<'a>(&'a self, name: &str) -> Option<&'a AView> {
+        self.views.get(name)
+    }
+    pub fn replace_view(&mut self, view: AView) {
+        self.views.insert(view.name.clone(), view);
+    }
+    pub fn remove_view(&mut self, name: &str) {
+        self.views.remove(name);
+    }
+    pub fn enums(&self) -> impl Iterator<Item = &AEnum> {
+        self.enums.values()
+    }
+    pub fn get_enum<'a>(&'a self, name: &str) -> Option<&'a AEnum> {
+        self.enums.get(name)
+    }
+    pub fn replace_enum(&mut self, e: AEnum) {
+        self.enums.insert(e.name.clone(), e);
+    }
+    pub fn remove_enum(&mut self, name: &str) {
+        self.enums.remove(name);
+    }
 
     /// Fixup as many DeferredSqlType::Deferred instances as possible
-    /// into DeferredSqlType::Known
+    /// into DeferredSqlType::Known. Also maps foreign key references
+    /// declared by type name to the name of the referenced table.
     pub fn resolve_types(&mut self) -> Result<()> {
+        // Foreign keys are declared in terms of the referenced type,
+        // which may be stored in a table with a different name.
+        let table_names: HashMap<String, String> = self
+            .extra_types
+            .iter()
+            .filter_map(|(key, ty)| match (key, ty) {
+                (TypeKey::PK(tyname), DeferredSqlType::Deferred(TypeKey::PK(table))) => {
+                    Some((tyname.clone(), table.clone()))
+                }
+                _ => None,
+            })
+            .collect();
+        for table in self.tables.values_mut() {
+            for col in &mut table.columns {
+                if let Some(reference) = &mut col.reference {
+                    if let Some(name) = table_names.get(&reference.table) {
+                        reference.table = name.clone();
+                    }
+                }
+            }
+        }
+
         let mut resolver = TypeResolver::new();
         let mut changed = true;
         while changed {
             changed = false;
             for table in &mut self.tables.values_mut() {
-                if let Some(pk) = table.pk() {
-                    let pktype = pk.typeid();
-                    if let Ok(pktype) = pktype {
-                        changed |= resolver.insert_pk(&table.name, pktype.clone());
+                // A composite key has no single type, so cannot be
+                // referred to by a foreign key column.
+                if let [pk] = table.pk() {
+                    if let Ok(pktype) = pk.typeid() {
+                        changed |= resolver.insert_pk(&table.name, pktype);
                     }
                 }
                 for col in &mut table.columns {
@@ -191,8 +341,14 @@ impl ADB {
                     }
                     DeferredSqlType::Deferred(tykey) => {
                         if let Some(sqltype) = resolver.find_type(tykey) {
-                            *ty = sqltype.into();
-                            changed = true;
+                            if let (TypeKey::PK(_), TypeKey::PK(_)) = (key, &tykey) {
+                                // Keep the mapping from type name to
+                                // table name for resolving foreign keys.
+                                changed |= resolver.insert(key.clone(), sqltype);
+                            } else {
+                                *ty = sqltype.into();
+                                changed = true;
+                            }
                         }
                     }
                 }
@@ -203,6 +359,12 @@ impl ADB {
         for table in &mut self.tables.values() {
             for col in &table.columns {
                 if let DeferredSqlType::Deferred(key) = &col.sqltype {
+                    if let TypeKey::PK(name) = key {
+                        let target = table_names.get(name).unwrap_or(name);
+                        if self.tables.get(target).is_some_and(|t| t.pk().len() > 1) {
+                            return Err(Error::CompositeKeyReference(target.clone()));
+                        }
+                    }
                     return Err(Error::CannotResolveType(key.to_string()));
                 }
             }
@@ -210,6 +372,77 @@ impl ADB {
         Ok(())
     }
 
+    /// Check the schema for problems which would otherwise only be
+    /// found when its SQL is generated or applied: tables without a
+    /// primary key (other than those storing `Many` fields), duplicate
+    /// column names, unresolved column types, and foreign keys
+    /// referring to missing tables or columns. All the problems found
+    /// are reported together in [Error::InvalidSchema].
+    pub fn validate(&self) -> Result<()> {
+        let mut problems = Vec::new();
+        let mut tables: Vec<&ATable> = self.tables.values().collect();
+        tables.sort_by(|a, b| a.name.cmp(&b.name));
+        for table in tables {
+            if table.pk().is_empty() && !self.is_many_table(&table.name) {
+                problems.push(SchemaProblem::NoPrimaryKey(table.name.clone()));
+            }
+            let mut seen: HashSet<&str> = HashSet::new();
+            for col in &table.columns {
+                let column = || (table.name.clone(), col.name.clone());
+                if !seen.insert(&col.name) {
+                    let (table, column) = column();
+                    problems.push(SchemaProblem::DuplicateColumn { table, column });
+                }
+                if let DeferredSqlType::Deferred(key) = &col.sqltype {
+                    let (table, column) = column();
+                    problems.push(SchemaProblem::UnresolvedType {
+                        table,
+                        column,
+                        key: key.clone(),
+                    });
+                }
+                if let Some(reference) = &col.reference {
+                    let missing = match self.tables.get(&reference.table) {
+                        None => true,
+                        Some(target) => reference
+                            .column
+                            .as_ref()
+                            .is_some_and(|c| target.column(c).is_none()),
+                    };
+                    if missing {
+                        let (table, column) = column();
+                        problems.push(SchemaProblem::MissingReference {
+                            table,
+                            column,
+                            reference: reference.clone(),
+                        });
+                    }
+                }
+            }
+        }
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::InvalidSchema(problems))
+        }
+    }
+
+    /// Whether `name` is the table storing a `Many` field of another
+    /// table. These have no primary key. Join tables created before
+    /// they were marked are recognised by their name.
+    fn is_many_table(&self, name: &str) -> bool {
+        if self.tables.get(name).is_some_and(|t| t.many.is_some()) {
+            return true;
+        }
+        name.strip_suffix("_Many").is_some_and(|rest| {
+            self.tables.keys().any(|owner| {
+                rest.len() > owner.len()
+                    && rest.starts_with(owner.as_str())
+                    && rest[owner.len()..].starts_with('_')
+            })
+        })
+    }
+
     pub fn transform_with(&mut self, op: Operation) {
         use Operation::*;
         match op {
@@ -235,6 +468,143 @@ impl ADB {
                     t.replace_column(new);
                 }
             }
+            ChangeColumnDefault(table, name, default) => {
+                if let Some(col) = self
+                    .tables
+                    .get_mut(&table)
+                    .and_then(|t| t.columns.iter_mut().find(|c| c.name == name))
+                {
+                    col.default = default;
+                }
+            }
+            ChangeColumnDefaultExpr(table, name, expr) => {
+                if let Some(col) = self
+                    .tables
+                    .get_mut(&table)
+                    .and_then(|t| t.columns.iter_mut().find(|c| c.name == name))
+                {
+                    col.default_expr = expr;
+                }
+            }
+            AddUniqueConstraint(table, constraint) => {
+                if let Some(t) = self.tables.get_mut(&table) {
+                    t.add_unique_constraint(constraint);
+                }
+            }
+            RemoveUniqueConstraint(table, constraint) => {
+                if let Some(t) = self.tables.get_mut(&table) {
+                    t.remove_unique_constraint(&constraint);
+                }
+            }
+            AddCheck(table, name, expr) => {
+                if let Some(t) = self.tables.get_mut(&table) {
+                    t.add_check(ACheckConstraint::new(name, expr));
+                }
+            }
+            RemoveCheck(table, name) => {
+                if let Some(t) = self.tables.get_mut(&table) {
+                    t.remove_check(&name);
+                }
+            }
+            AddIndex(table, index) => {
+                if let Some(t) = self.tables.get_mut(&table) {
+                    t.add_index(index);
+                }
+            }
+            SetTableSchema(table, schema) => {
+                if let Some(t) = self.tables.get_mut(&table) {
+                    t.schema = schema;
+                }
+            }
+            SetTableComment(table, comment) => {
+                if let Some(t) = self.tables.get_mut(&table) {
+                    t.comment = comment;
+                }
+            }
+            SetColumnComment(table, name, comment) => {
+                if let Some(col) = self
+                    .tables
+                    .get_mut(&table)
+                    .and_then(|t| t.columns.iter_mut().find(|c| c.name == name))
+                {
+                    col.comment = comment;
+                }
+            }
+            RemoveIndex(table, index) => {
+                if let Some(t) = self.tables.get_mut(&table) {
+                    t.remove_index(&index);
+                }
+            }
+            SetNotNull(table, name, _) => {
+                if let Some(col) = self
+                    .tables
+                    .get_mut(&table)
+                    .and_then(|t| t.columns.iter_mut().find(|c| c.name == name))
+                {
+                    col.nullable = false;
+                }
+            }
+            AddForeignKey(table, column, reference) => {
+                if let Some(col) = self
+                    .tables
+                    .get_mut(&table)
+                    .and_then(|t| t.columns.iter_mut().find(|c| c.name == column))
+                {
+                    col.reference = Some(reference);
+                }
+            }
+            RemoveForeignKey(table, column) => {
+                if let Some(col) = self
+                    .tables
+                    .get_mut(&table)
+                    .and_then(|t| t.columns.iter_mut().find(|c| c.name == column))
+                {
+                    col.reference = None;
+                }
+            }
+            RenameTable(old, new) => self.rename_table(&old, &new),
+            RenameColumn(table, old, new) => self.rename_column(&table, &old, &new),
+            AddView(view) | ReplaceView(view) => self.replace_view(view),
+            RemoveView(name) => self.remove_view(&name),
+            AddEnum(e) => self.replace_enum(e),
+            RemoveEnum(name) => self.remove_enum(&name),
+            AddEnumValue(name, value) => {
+                if let Some(e) = self.enums.get_mut(&name) {
+                    e.values.push(value);
+                }
+            }
+        }
+    }
+}
+
+/// Abstract representation of an enumerated type: a named, ordered
+/// set of values.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AEnum {
+    pub name: String,
+    pub values: Vec<String>,
+}
+impl AEnum {
+    pub fn new(name: impl Into<String>, values: Vec<String>) -> Self {
+        AEnum {
+            name: name.into(),
+            values,
+        }
+    }
+}
+
+/// Abstract representation of a database view: a named `SELECT` query.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AView {
+    pub name: String,
+    /// The `SELECT` statement defining the view.
+    pub query: String,
+}
+impl AView {
+    pub fn new(name: impl Into<String>, query: impl Into<String>) -> Self {
+        AView {
+            name: name.into(),
+            query: query.into(),
         }
     }
 }
@@ -243,15 +613,47 @@ impl ADB {
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct ATable {
     pub name: String,
+    /// The Postgres schema (namespace) containing the table, or `None`
+    /// for the first schema of the connection's search path. Backends
+    /// without schemas ignore it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub schema: Option<String>,
     pub columns: Vec<AColumn>,
+    /// Unique constraints spanning one or more columns. Uniqueness of
+    /// a single column may also be expressed with [AColumn::unique].
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub unique_constraints: Vec<AUniqueConstraint>,
+    /// Check constraints which every row must satisfy.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub checks: Vec<ACheckConstraint>,
+    /// Non-unique indexes over one or more columns.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub indexes: Vec<AIndex>,
+    /// Comment documenting the table. Only Postgres stores it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub comment: Option<String>,
+    /// The `Many` field whose values this table stores, if it is a join
+    /// table. Join tables have no primary key.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub many: Option<AManyField>,
 }
 impl ATable {
     pub fn new(name: String) -> ATable {
         ATable {
             name,
+            schema: None,
             columns: Vec::new(),
+            unique_constraints: Vec::new(),
+            checks: Vec::new(),
+            indexes: Vec::new(),
+            comment: None,
+            many: None,
         }
     }
+    /// Add a column, or replace the existing one with the same name.
+    /// Primary key columns are kept next to each other, so a column
+    /// joining or leaving a composite key is moved after the other key
+    /// columns or to the end.
     pub fn add_column(&mut self, col: AColumn) {
         self.replace_column(col);
     }
@@ -260,16 +662,292 @@ impl ATable {
     }
     pub fn replace_column(&mut self, col: AColumn) {
         if let Some(existing) = self.columns.iter_mut().find(|c| c.name == col.name) {
-            *existing = col;
-        } else {
-            self.columns.push(col);
+            if existing.is_pk() == col.is_pk() {
+                *existing = col;
+                return;
+            }
+            self.remove_column(&col.name);
+        }
+        match self.columns.iter().rposition(AColumn::is_pk) {
+            Some(last) if col.is_pk() => self.columns.insert(last + 1, col),
+            _ => self.columns.push(col),
         }
     }
     pub fn remove_column(&mut self, name: &str) {
         self.columns.retain(|c| c.name != name);
     }
-    pub fn pk(&self) -> Option<&AColumn> {
-        self.columns.iter().find(|c| c.is_pk())
+    /// Rename the column `old` to `new`, including in the table's
+    /// constraints and indexes. Check constraints are SQL expressions,
+    /// so are not changed.
+    pub fn rename_column(&mut self, old: &str, new: &str) {
+        let rename = |columns: &mut Vec<String>| {
+            for c in columns.iter_mut().filter(|c| *c == old) {
+                *c = new.to_string();
+            }
+        };
+        if let Some(col) = self.columns.iter_mut().find(|c| c.name == old) {
+            col.name = new.to_string();
+        }
+        for constraint in &mut self.unique_constraints {
+            rename(&mut constraint.columns);
+        }
+        for index in &mut self.indexes {
+            rename(&mut index.columns);
+        }
+    }
+    /// The columns of the primary key, in column order. There is more
+    /// than one if the key is composite, and none if the table has no key.
+    pub fn pk(&self) -> &[AColumn] {
+        match self.columns.iter().position(AColumn::is_pk) {
+            Some(start) => {
+                let len = self.columns[start..]
+                    .iter()
+                    .take_while(|c| c.is_pk())
+                    .count();
+                &self.columns[start..start + len]
+            }
+            None => &[],
+        }
+    }
+    /// Names of the other tables this table references with foreign keys.
+    pub fn referenced_tables(&self) -> impl Iterator<Item = &str> {
+        self.columns
+            .iter()
+            .filter_map(|c| c.reference())
+            .map(|r| r.table())
+            .filter(move |t| *t != self.name)
+    }
+    pub fn add_unique_constraint(&mut self, constraint: AUniqueConstraint) {
+        if !self.unique_constraints.contains(&constraint) {
+            self.unique_constraints.push(constraint);
+        }
+    }
+    pub fn remove_unique_constraint(&mut self, constraint: &AUniqueConstraint) {
+        self.unique_constraints.retain(|c| c != constraint);
+    }
+    /// Add a check constraint, replacing any existing one with the same name.
+    pub fn add_check(&mut self, check: ACheckConstraint) {
+        if let Some(existing) = self.checks.iter_mut().find(|c| c.name == check.name) {
+            *existing = check;
+        } else {
+            self.checks.push(check);
+        }
+    }
+    pub fn remove_check(&mut self, name: &str) {
+        self.checks.retain(|c| c.name != name);
+    }
+    pub fn add_index(&mut self, index: AIndex) {
+        if !self.indexes.contains(&index) {
+            self.indexes.push(index);
+        }
+    }
+    pub fn remove_index(&mut self, index: &AIndex) {
+        self.indexes.retain(|i| i != index);
+    }
+    /// Tests whether filtering on the column `name` can use an index:
+    /// one of the table's indexes, its primary key or a unique
+    /// constraint, all of which the database indexes, begins with it.
+    pub fn is_indexed(&self, name: &str) -> bool {
+        let leading = |columns: &[String]| columns.first().map(String::as_str) == Some(name);
+        self.pk().first().is_some_and(|c| c.name() == name)
+            || self.column(name).is_some_and(|c| c.unique())
+            || self.unique_constraints.iter().any(|c| leading(c.columns()))
+            || self
+                .indexes
+                .iter()
+                .any(|i| i.predicate().is_none() && leading(i.columns()))
+    }
+}
+
+/// A `Many` field of a model, identifying the join table storing its
+/// values however the table is named.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct AManyField {
+    /// Name of the model type declaring the field.
+    pub model: String,
+    pub field: String,
+}
+impl AManyField {
+    pub fn new(model: impl Into<String>, field: impl Into<String>) -> Self {
+        AManyField {
+            model: model.into(),
+            field: field.into(),
+        }
+    }
+}
+
+/// Abstract representation of a unique constraint over a group of columns.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct AUniqueConstraint {
+    columns: Vec<String>,
+}
+impl AUniqueConstraint {
+    pub fn new<S: Into<String>>(columns: impl IntoIterator<Item = S>) -> Self {
+        AUniqueConstraint {
+            columns: columns.into_iter().map(|c| c.into()).collect(),
+        }
+    }
+    /// The names of the columns which must be unique in combination.
+    pub fn columns(&self) -> &[String] {
+        &self.columns
+    }
+    /// The name used for this constraint in the database when it
+    /// belongs to the table `table`.
+    pub fn name(&self, table: &str) -> String {
+        format!("{}_{}_key", table, self.columns.join("_"))
+    }
+}
+
+/// Abstract representation of a non-unique index over a group of
+/// columns, optionally only of the rows satisfying a predicate (a
+/// partial index).
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct AIndex {
+    columns: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    predicate: Option<String>,
+}
+impl AIndex {
+    pub fn new<S: Into<String>>(columns: impl IntoIterator<Item = S>) -> Self {
+        AIndex {
+            columns: columns.into_iter().map(|c| c.into()).collect(),
+            predicate: None,
+        }
+    }
+    /// Index only the rows for which the SQL expression `predicate` is
+    /// true, such as `deleted = false`.
+    pub fn with_predicate(mut self, predicate: impl Into<String>) -> Self {
+        self.predicate = Some(predicate.into());
+        self
+    }
+    /// The names of the indexed columns, in index order.
+    pub fn columns(&self) -> &[String] {
+        &self.columns
+    }
+    /// The SQL expression the indexed rows satisfy, if this is a
+    /// partial index.
+    pub fn predicate(&self) -> Option<&str> {
+        self.predicate.as_deref()
+    }
+    /// The name used for this index in the database when it belongs to
+    /// the table `table`.
+    pub fn name(&self, table: &str) -> String {
+        let kind = if self.predicate.is_some() {
+            "partial_idx"
+        } else {
+            "idx"
+        };
+        format!("{}_{}_{}", table, self.columns.join("_"), kind)
+    }
+}
+
+/// Abstract representation of a named check constraint on a table.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ACheckConstraint {
+    name: String,
+    expr: String,
+}
+impl ACheckConstraint {
+    pub fn new(name: impl Into<String>, expr: impl Into<String>) -> Self {
+        ACheckConstraint {
+            name: name.into(),
+            expr: expr.into(),
+        }
+    }
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+    /// The SQL boolean expression which must hold for each row.
+    pub fn expr(&self) -> &str {
+        &self.expr
+    }
+}
+
+/// Abstract representation of a foreign key constraint on a single column.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct AForeignKey {
+    table: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    column: Option<String>,
+    #[serde(default)]
+    on_delete: ReferentialAction,
+    #[serde(default)]
+    on_update: ReferentialAction,
+}
+impl AForeignKey {
+    /// Reference the primary key of `table`.
+    pub fn new(table: impl Into<String>) -> Self {
+        AForeignKey {
+            table: table.into(),
+            column: None,
+            on_delete: ReferentialAction::default(),
+            on_update: ReferentialAction::default(),
+        }
+    }
+    /// Reference `column` rather than the primary key.
+    pub fn with_column(mut self, column: impl Into<String>) -> Self {
+        self.column = Some(column.into());
+        self
+    }
+    pub fn with_on_delete(mut self, action: ReferentialAction) -> Self {
+        self.on_delete = action;
+        self
+    }
+    pub fn with_on_update(mut self, action: ReferentialAction) -> Self {
+        self.on_update = action;
+        self
+    }
+    /// Name of the referenced table.
+    pub fn table(&self) -> &str {
+        &self.table
+    }
+    /// Name of the referenced column. If `None`, the primary key of
+    /// the referenced table.
+    pub fn column(&self) -> Option<&str> {
+        self.column.as_deref()
+    }
+    pub fn on_delete(&self) -> ReferentialAction {
+        self.on_delete
+    }
+    pub fn on_update(&self) -> ReferentialAction {
+        self.on_update
+    }
+}
+
+/// Action taken by the database when a row referenced by a foreign
+/// key is deleted or its key is updated.
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub enum ReferentialAction {
+    #[default]
+    NoAction,
+    Restrict,
+    Cascade,
+    SetNull,
+    SetDefault,
+}
+impl ReferentialAction {
+    /// The SQL keywords for this action.
+    pub fn sql(&self) -> &'static str {
+        match self {
+            ReferentialAction::NoAction => "NO ACTION",
+            ReferentialAction::Restrict => "RESTRICT",
+            ReferentialAction::Cascade => "CASCADE",
+            ReferentialAction::SetNull => "SET NULL",
+            ReferentialAction::SetDefault => "SET DEFAULT",
+        }
+    }
+}
+impl std::str::FromStr for ReferentialAction {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "no_action" => Ok(ReferentialAction::NoAction),
+            "restrict" => Ok(ReferentialAction::Restrict),
+            "cascade" => Ok(ReferentialAction::Cascade),
+            "set_null" => Ok(ReferentialAction::SetNull),
+            "set_default" => Ok(ReferentialAction::SetDefault),
+            _ => Err(Error::UnknownReferentialAction(s.to_string())),
+        }
     }
 }
 
@@ -312,9 +990,23 @@ pub struct AColumn {
     nullable: bool,
     pk: bool,
     auto: bool,
+    /// How the values of an auto column are generated.
+    #[serde(default, skip_serializing_if = "AutoIncrement::is_serial")]
+    auto_increment: AutoIncrement,
     #[serde(default)]
     unique: bool,
     default: Option<SqlVal>,
+    /// SQL expression computing the default, taking precedence over
+    /// `default`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    default_expr: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    reference: Option<AForeignKey>,
+    #[serde(default, skip_serializing_if = "ATypeParams::is_empty")]
+    params: ATypeParams,
+    /// Comment documenting the column. Only Postgres stores it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    comment: Option<String>,
 }
 impl AColumn {
     pub fn new(
@@ -332,10 +1024,44 @@ impl AColumn {
             nullable,
             pk,
             auto,
+            auto_increment: AutoIncrement::default(),
             unique,
             default,
+            default_expr: None,
+            reference: None,
+            params: ATypeParams::default(),
+            comment: None,
         }
     }
+    /// Declare that this column references another table with a
+    /// foreign key constraint.
+    pub fn with_reference(mut self, reference: AForeignKey) -> Self {
+        self.reference = Some(reference);
+        self
+    }
+    /// Declare that the database generates the values of this column,
+    /// which must be an integer, in the manner of `kind`.
+    pub fn with_auto_increment(mut self, kind: AutoIncrement) -> Self {
+        self.auto = true;
+        self.auto_increment = kind;
+        self
+    }
+    /// Declare that the database computes the default of this column
+    /// with the SQL expression `expr`, such as `CURRENT_TIMESTAMP`.
+    pub fn with_default_expr(mut self, expr: impl Into<String>) -> Self {
+        self.default_expr = Some(expr.into());
+        self
+    }
+    /// Declare the length, precision or scale of the column type.
+    pub fn with_params(mut self, params: ATypeParams) -> Self {
+        self.params = params;
+        self
+    }
+    /// Document the column with `comment`.
+    pub fn with_comment(mut self, comment: impl Into<String>) -> Self {
+        self.comment = Some(comment.into());
+        self
+    }
     /// Simple column that is non-null, non-auto, non-pk, non-unique with no default
     pub fn new_simple(name: impl Into<String>, sqltype: DeferredSqlType) -> Self {
         Self::new(name, sqltype, false, false, false, false, None)
@@ -346,15 +1072,48 @@ impl AColumn {
     pub fn nullable(&self) -> bool {
         self.nullable
     }
+    pub fn set_nullable(&mut self, nullable: bool) {
+        self.nullable = nullable;
+    }
     pub fn unique(&self) -> bool {
         self.unique
     }
+    pub fn set_unique(&mut self, unique: bool) {
+        self.unique = unique;
+    }
     pub fn is_pk(&self) -> bool {
         self.pk
     }
     pub fn default(&self) -> &Option<SqlVal> {
         &self.default
     }
+    pub fn set_default(&mut self, default: Option<SqlVal>) {
+        self.default = default;
+    }
+    /// The SQL expression computing the default of this column, if any.
+    pub fn default_expr(&self) -> Option<&str> {
+        self.default_expr.as_deref()
+    }
+    pub fn set_default_expr(&mut self, expr: Option<String>) {
+        self.default_expr = expr;
+    }
+    /// The foreign key constraint on this column, if any.
+    pub fn reference(&self) -> Option<&AForeignKey> {
+        self.reference.as_ref()
+    }
+    pub fn set_reference(&mut self, reference: Option<AForeignKey>) {
+        self.reference = reference;
+    }
+    pub fn params(&self) -> &ATypeParams {
+        &self.params
+    }
+    /// The comment documenting this column, if any.
+    pub fn comment(&self) -> Option<&str> {
+        self.comment.as_deref()
+    }
+    pub fn set_comment(&mut self, comment: Option<String>) {
+        self.comment = comment;
+    }
     pub fn typeid(&self) -> Result<TypeIdentifier> {
         match &self.sqltype {
             DeferredSqlType::KnownId(t) => Ok(t.clone()),
@@ -377,9 +1136,85 @@ impl AColumn {
     pub fn is_auto(&self) -> bool {
         self.auto
     }
+    /// How the database generates the values of this column, if it is
+    /// auto.
+    pub fn auto_increment(&self) -> Option<AutoIncrement> {
+        if self.auto {
+            Some(self.auto_increment)
+        } else {
+            None
+        }
+    }
+}
+
+/// How the database generates the values of an auto column. Any
+/// column may be auto on Postgres, but only the primary key on SQLite.
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, Eq, PartialEq)]
+pub enum AutoIncrement {
+    /// `SERIAL` or `BIGSERIAL` on Postgres. On SQLite the primary key is
+    /// an alias for the rowid, the greatest value of which may be
+    /// reused once deleted.
+    #[default]
+    Serial,
+    /// `GENERATED BY DEFAULT AS IDENTITY` on Postgres and
+    /// `AUTOINCREMENT` on SQLite, which never reuses values.
+    Identity,
+}
+impl AutoIncrement {
+    fn is_serial(&self) -> bool {
+        *self == AutoIncrement::Serial
+    }
+}
+
+/// Parameters of a column type. Unset parameters leave the type
+/// unbounded.
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, Eq, PartialEq)]
+pub struct ATypeParams {
+    /// Maximum length of a `Text` column, in characters.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub length: Option<u32>,
+    /// Total number of decimal digits of a `Real` column, which is then
+    /// stored as an exact decimal number rather than a float.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub precision: Option<u32>,
+    /// Number of those digits after the decimal point.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scale: Option<u32>,
+}
+impl ATypeParams {
+    pub fn is_empty(&self) -> bool {
+        *self == ATypeParams::default()
+    }
+}
+
+/// How to fill in the NULLs of a column before making it NOT NULL. See
+/// [`Operation::SetNotNull`].
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Backfill {
+    /// SQL expression giving the value of each row, which may refer to
+    /// its other columns. It must not be NULL.
+    pub expr: String,
+    /// Number of rows updated by each statement on Postgres, which
+    /// keeps each statement short on large tables. SQLite updates all
+    /// rows at once.
+    pub batch_size: u32,
+}
+impl Backfill {
+    pub fn new(expr: impl Into<String>) -> Self {
+        Backfill {
+            expr: expr.into(),
+            batch_size: 1000,
+        }
+    }
+    pub fn with_batch_size(mut self, batch_size: u32) -> Self {
+        self.batch_size = batch_size;
+        self
+    }
 }
 
 /// Individual operation use to apply a migration.
+// Operations are few and short-lived, so their size does not matter
+#[allow(clippy::large_enum_variant)]
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum Operation {
     //future improvement: support column renames
@@ -389,39 +1224,319 @@ pub enum Operation {
     AddColumn(String, AColumn),
     RemoveColumn(String, String),
     ChangeColumn(String, AColumn, AColumn),
+    /// Change the default value of a column, leaving it otherwise
+    /// unchanged. Given the table, the column and the new default.
+    ChangeColumnDefault(String, String, Option<SqlVal>),
+    /// Change the expression computing the default of a column, leaving
+    /// it otherwise unchanged. Given the table, the column and the new
+    /// expression.
+    ChangeColumnDefaultExpr(String, String, Option<String>),
+    AddUniqueConstraint(String, AUniqueConstraint),
+    RemoveUniqueConstraint(String, AUniqueConstraint),
+    /// Add a check constraint with the given name and expression to a table.
+    AddCheck(String, String, String),
+    /// Remove the check constraint with the given name from a table.
+    RemoveCheck(String, String),
+    AddIndex(String, AIndex),
+    RemoveIndex(String, AIndex),
+    /// Move a table to the given schema, or to the default one if `None`.
+    SetTableSchema(String, Option<String>),
+    /// Set or remove the comment documenting a table.
+    SetTableComment(String, Option<String>),
+    /// Set or remove the comment documenting a column, leaving it
+    /// otherwise unchanged. Given the table, the column and the comment.
+    SetColumnComment(String, String, Option<String>),
+    /// Make a nullable column of a table NOT NULL, leaving it otherwise
+    /// unchanged. Given the table, the column and how to fill in its
+    /// NULLs first. Without a backfill, the migration fails if the
+    /// column has any NULLs. Never produced by [diff].
+    SetNotNull(String, String, Option<Backfill>),
+    /// Rename a table from the first name to the second. Never
+    /// produced by [diff], which cannot tell a rename from replacing
+    /// one table with another.
+    RenameTable(String, String),
+    /// Rename a column of a table from the second name to the third.
+    /// Never produced by [diff].
+    RenameColumn(String, String, String),
+    /// Add a foreign key constraint to an existing column of a table.
+    /// Used where tables reference each other, so that each can be
+    /// created before the other references it.
+    AddForeignKey(String, String, AForeignKey),
+    /// Remove the foreign key constraint from a column of a table.
+    RemoveForeignKey(String, String),
+    AddView(AView),
+    RemoveView(String),
+    /// Replace the definition of an existing view with the given one.
+    ReplaceView(AView),
+    AddEnum(AEnum),
+    RemoveEnum(String),
+    /// Append a value to the enum with the given name.
+    AddEnumValue(String, String),
 }
 
 /// Determine the operations necessary to move the database schema from `old` to `new`.
 pub fn diff(old: &ADB, new: &ADB) -> Vec<Operation> {
     let mut ops: Vec<Operation> = Vec::new();
+    // Views may depend on any of the tables, so are removed first and
+    // added or replaced last.
+    let mut removed_views: Vec<&String> = old
+        .views
+        .keys()
+        .filter(|name| !new.views.contains_key(*name))
+        .collect();
+    removed_views.sort();
+    for name in removed_views {
+        ops.push(Operation::RemoveView(name.clone()));
+    }
+    // Enums must exist before the tables using them. Values cannot be
+    // removed from a Postgres enum, so values missing from `new` are
+    // left in place.
+    let mut enums: Vec<&AEnum> = new.enums.values().collect();
+    enums.sort_by(|a, b| a.name.cmp(&b.name));
+    for e in enums {
+        match old.enums.get(&e.name) {
+            None => ops.push(Operation::AddEnum(e.clone())),
+            Some(old_enum) => {
+                for value in &e.values {
+                    if !old_enum.values.contains(value) {
+                        ops.push(Operation::AddEnumValue(e.name.clone(), value.clone()));
+                    }
+                }
+            }
+        }
+    }
     let new_names: HashSet<&String> = new.tables.keys().collect();
     let old_names: HashSet<&String> = old.tables.keys().collect();
-    let new_tables = new_names.difference(&old_names);
-    for added in new_tables {
-        let added: &str = added.as_ref();
-        ops.push(Operation::AddTable(
-            new.tables.get(added).expect("no table").clone(),
+    // A join table renamed along with its model, or given a custom
+    // name, is renamed rather than replaced so that its rows are kept.
+    let renamed_joins = renamed_join_tables(old, new, &old_names, &new_names);
+    for (old_table, new_table) in &renamed_joins {
+        ops.push(Operation::RenameTable(
+            old_table.name.clone(),
+            new_table.name.clone(),
         ));
     }
-    for removed in old_names.difference(&new_names) {
-        ops.push(Operation::RemoveTable((*removed).to_string()));
+    let is_renamed = |name: &str| {
+        renamed_joins
+            .iter()
+            .any(|(o, n)| o.name == name || n.name == name)
+    };
+    let new_tables: Vec<&ATable> = new_names
+        .difference(&old_names)
+        .filter(|added| !is_renamed(added))
+        .map(|added| new.tables.get(*added).expect("no table"))
+        .collect();
+    ops.append(&mut add_tables(new_tables, new));
+    let old_tables: Vec<&ATable> = old_names
+        .difference(&new_names)
+        .filter(|removed| !is_renamed(removed))
+        .map(|removed| old.tables.get(*removed).expect("no table"))
+        .collect();
+    let mut kept_names: Vec<&String> = new_names.intersection(&old_names).cloned().collect();
+    kept_names.sort();
+    let kept_old: Vec<ATable> = kept_names
+        .iter()
+        .map(|name| old.tables[*name].clone())
+        .collect();
+    let (mut removals, mut kept_old) = remove_tables(old_tables, kept_old);
+    ops.append(&mut removals);
+    for (old_table, new_table) in renamed_joins {
+        let mut old_table = old_table.clone();
+        old_table.name = new_table.name.clone();
+        kept_old.push(old_table);
     }
-    for table in new_names.intersection(&old_names) {
-        let table: &str = table.as_ref();
+    for old_table in &kept_old {
         ops.append(&mut diff_table(
-            old.tables.get(table).expect("no table"),
-            new.tables.get(table).expect("no table"),
+            old_table,
+            new.tables.get(&old_table.name).expect("no table"),
         ));
     }
+    let mut views: Vec<&AView> = new.views.values().collect();
+    views.sort_by(|a, b| a.name.cmp(&b.name));
+    for view in views {
+        match old.views.get(&view.name) {
+            None => ops.push(Operation::AddView(view.clone())),
+            Some(old_view) if old_view != view => ops.push(Operation::ReplaceView(view.clone())),
+            Some(_) => (),
+        }
+    }
+    let mut removed_enums: Vec<&String> = old
+        .enums
+        .keys()
+        .filter(|name| !new.enums.contains_key(*name))
+        .collect();
+    removed_enums.sort();
+    for name in removed_enums {
+        ops.push(Operation::RemoveEnum(name.clone()));
+    }
     ops
 }
 
+/// The operations adding `tables`, whose foreign keys refer to tables
+/// of `new`. Referenced tables are added before tables with foreign
+/// keys to them. Where tables reference each other, the references are
+/// added once all of them exist.
+fn add_tables(tables: Vec<&ATable>, new: &ADB) -> Vec<Operation> {
+    let mut ops: Vec<Operation> = Vec::new();
+    let (added, deferred) = order_by_references(tables);
+    for table in added {
+        let mut table = table.clone();
+        for col in &mut table.columns {
+            if deferred.contains(&(table.name.as_str(), col.name.as_str())) {
+                col.reference = None;
+            }
+        }
+        ops.push(Operation::AddTable(table));
+    }
+    for (table, column) in deferred {
+        let reference = new.tables[table]
+            .column(column)
+            .and_then(|c| c.reference.clone())
+            .expect("deferred reference");
+        ops.push(Operation::AddForeignKey(
+            table.to_string(),
+            column.to_string(),
+            reference,
+        ));
+    }
+    ops
+}
+
+/// The operations removing `tables`, each after the tables referencing
+/// it. References to them from the `kept` tables, and between removed
+/// tables which reference each other, are removed first. Returns the
+/// operations with the kept tables as they are once the references
+/// are removed.
+fn remove_tables(tables: Vec<&ATable>, mut kept: Vec<ATable>) -> (Vec<Operation>, Vec<ATable>) {
+    let mut ops: Vec<Operation> = Vec::new();
+    let removed_names: HashSet<&str> = tables.iter().map(|t| t.name.as_str()).collect();
+    for table in &mut kept {
+        for col in &mut table.columns {
+            if col
+                .reference
+                .as_ref()
+                .is_some_and(|r| removed_names.contains(r.table.as_str()))
+            {
+                col.reference = None;
+                ops.push(Operation::RemoveForeignKey(
+                    table.name.clone(),
+                    col.name.clone(),
+                ));
+            }
+        }
+    }
+    let (removed, deferred) = order_by_references(tables);
+    for (table, column) in deferred {
+        ops.push(Operation::RemoveForeignKey(
+            table.to_string(),
+            column.to_string(),
+        ));
+    }
+    for table in removed.into_iter().rev() {
+        ops.push(Operation::RemoveTable(table.name.clone()));
+    }
+    (ops, kept)
+}
+
+/// Join tables of `old` which store the same `Many` field as a join
+/// table of `new` with a different name, paired with the latter.
+fn renamed_join_tables<'a>(
+    old: &'a ADB,
+    new: &'a ADB,
+    old_names: &HashSet<&String>,
+    new_names: &HashSet<&String>,
+) -> Vec<(&'a ATable, &'a ATable)> {
+    let mut renamed: Vec<(&ATable, &ATable)> = old_names
+        .difference(new_names)
+        .map(|name| &old.tables[*name])
+        .filter_map(|old_table| {
+            let many = old_table.many.as_ref()?;
+            new_names
+                .difference(old_names)
+                .map(|name| &new.tables[*name])
+                .find(|t| t.many.as_ref() == Some(many))
+                .map(|new_table| (old_table, new_table))
+        })
+        .collect();
+    renamed.sort_by(|a, b| a.0.name.cmp(&b.0.name));
+    renamed
+}
+
+/// Order `tables` so that each table comes after any others in the
+/// set it references. Reference cycles are broken by deferring the
+/// references of a table to tables not yet placed, which are returned
+/// as (table, column) pairs in the order in which they were deferred.
+fn order_by_references(mut tables: Vec<&ATable>) -> (Vec<&ATable>, Vec<(&str, &str)>) {
+    // Sort first so that the result is deterministic
+    tables.sort_by(|a, b| a.name.cmp(&b.name));
+    let names: HashSet<&str> = tables.iter().map(|t| t.name.as_str()).collect();
+    let mut placed: HashSet<&str> = HashSet::new();
+    let mut ordered: Vec<&ATable> = Vec::with_capacity(tables.len());
+    let mut deferred: Vec<(&str, &str)> = Vec::new();
+    // A reference which must wait for its table to be placed. A table
+    // may reference itself.
+    let pending = |t: &ATable, r: &str, placed: &HashSet<&str>| {
+        r != t.name && names.contains(r) && !placed.contains(r)
+    };
+    while !tables.is_empty() {
+        let ready = tables
+            .iter()
+            .position(|t| t.referenced_tables().all(|r| !pending(t, r, &placed)));
+        let table = tables.remove(ready.unwrap_or(0));
+        if ready.is_none() {
+            for col in &table.columns {
+                if let Some(reference) = &col.reference {
+                    if pending(table, &reference.table, &placed) {
+                        deferred.push((table.name.as_str(), col.name.as_str()));
+                    }
+                }
+            }
+        }
+        placed.insert(table.name.as_str());
+        ordered.push(table);
+    }
+    (ordered, deferred)
+}
+
 fn col_by_name<'a>(columns: &'a [AColumn], name: &str) -> Option<&'a AColumn> {
     columns.iter().find(|c| c.name == name)
 }
 
 fn diff_table(old: &ATable, new: &ATable) -> Vec<Operation> {
     let mut ops: Vec<Operation> = Vec::new();
+    if old.schema != new.schema {
+        ops.push(Operation::SetTableSchema(
+            new.name.clone(),
+            new.schema.clone(),
+        ));
+    }
+    if old.comment != new.comment {
+        ops.push(Operation::SetTableComment(
+            new.name.clone(),
+            new.comment.clone(),
+        ));
+    }
+    // Constraints are removed before any columns they might reference
+    // and added only after all column changes.
+    for removed in old
+        .unique_constraints
+        .iter()
+        .filter(|c| !new.unique_constraints.contains(c))
+    {
+        ops.push(Operation::RemoveUniqueConstraint(
+            old.name.clone(),
+            removed.clone(),
+        ));
+    }
+    for removed in old.checks.iter().filter(|c| !new.checks.contains(c)) {
+        ops.push(Operation::RemoveCheck(
+            old.name.clone(),
+            removed.name.clone(),
+        ));
+    }
+    for removed in old.indexes.iter().filter(|i| !new.indexes.contains(i)) {
+        ops.push(Operation::RemoveIndex(old.name.clone(), removed.clone()));
+    }
     let new_names: HashSet<&String> = new.columns.iter().map(|c| &c.name).collect();
     let old_names: HashSet<&String> = old.columns.iter().map(|c| &c.name).collect();
     let added_names = new_names.difference(&old_names);
@@ -445,11 +1560,67 @@ fn diff_table(old: &ATable, new: &ATable) -> Vec<Operation> {
         if col == old_col {
             continue;
         }
+        // Defaults and comments are changed in place
+        let mut old_with_default = old_col.clone();
+        old_with_default.default = col.default.clone();
+        old_with_default.default_expr = col.default_expr.clone();
+        old_with_default.comment = col.comment.clone();
+        if *col == old_with_default {
+            let literal = (col.default != old_col.default).then(|| {
+                Operation::ChangeColumnDefault(
+                    new.name.clone(),
+                    colname.to_string(),
+                    col.default.clone(),
+                )
+            });
+            let expr = (col.default_expr != old_col.default_expr).then(|| {
+                Operation::ChangeColumnDefaultExpr(
+                    new.name.clone(),
+                    colname.to_string(),
+                    col.default_expr.clone(),
+                )
+            });
+            // The default being removed goes first, so as not to remove
+            // the one replacing it
+            if col.default.is_none() {
+                ops.extend(literal.into_iter().chain(expr));
+            } else {
+                ops.extend(expr.into_iter().chain(literal));
+            }
+            if col.comment != old_col.comment {
+                ops.push(Operation::SetColumnComment(
+                    new.name.clone(),
+                    colname.to_string(),
+                    col.comment.clone(),
+                ));
+            }
+            continue;
+        }
         ops.push(Operation::ChangeColumn(
             new.name.clone(),
             old_col.clone(),
             col.clone(),
         ));
     }
+    for added in new
+        .unique_constraints
+        .iter()
+        .filter(|c| !old.unique_constraints.contains(c))
+    {
+        ops.push(Operation::AddUniqueConstraint(
+            new.name.clone(),
+            added.clone(),
+        ));
+    }
+    for added in new.checks.iter().filter(|c| !old.checks.contains(c)) {
+        ops.push(Operation::AddCheck(
+            new.name.clone(),
+            added.name.clone(),
+            added.expr.clone(),
+        ));
+    }
+    for added in new.indexes.iter().filter(|i| !old.indexes.contains(i)) {
+        ops.push(Operation::AddIndex(new.name.clone(), added.clone()));
+    }
     ops
 }
@@ -1,6 +1,10 @@
-use super::adb::{ATable, DeferredSqlType, TypeKey, ADB};
+use super::adb::{AEnum, ATable, AView, DeferredSqlType, TypeKey, ADB};
 use super::fs::{Filesystem, OsFilesystem};
-use super::{Migration, MigrationMut, Migrations, MigrationsMut};
+use super::state::store_or_table;
+use super::{
+    ButaneMigration, Migration, MigrationMut, MigrationStateStore, Migrations, MigrationsMut,
+    Verification,
+};
 use crate::{ConnectionMethods, DataObject, Result};
 use fs2::FileExt;
 use serde::{Deserialize, Serialize};
@@ -11,6 +15,7 @@ use std::fs::{File, OpenOptions};
 use std::io::{Read, Write};
 use std::path::{Path, PathBuf};
 use std::rc::Rc;
+use std::sync::Arc;
 
 type SqlTypeMap = BTreeMap<TypeKey, DeferredSqlType>;
 const TYPES_FILENAME: &str = "types.json";
@@ -21,12 +26,24 @@ struct MigrationInfo {
     /// first migration in the chain
     from_name: Option<String>,
     backends: Vec<String>,
+    /// See `Migration::merged_from`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    merged: Vec<String>,
+    /// The hash of `from_name` when this migration was created.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    from_hash: Option<String>,
+    /// See `Migration::verifications`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    verifications: Vec<Verification>,
 }
 impl MigrationInfo {
     fn new() -> Self {
         MigrationInfo {
             from_name: None,
             backends: Vec::new(),
+            merged: Vec::new(),
+            from_hash: None,
+            verifications: Vec::new(),
         }
     }
 }
@@ -34,10 +51,38 @@ impl MigrationInfo {
 #[derive(Serialize, Deserialize)]
 struct MigrationsState {
     latest: Option<String>,
+    /// See `Migrations::state_table`. None for the default.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    state_table: Option<String>,
 }
 impl MigrationsState {
     fn new() -> Self {
-        MigrationsState { latest: None }
+        MigrationsState {
+            latest: None,
+            state_table: None,
+        }
+    }
+
+    fn read(fs: &dyn Filesystem, root: &Path) -> Result<Self> {
+        let path = root.join("state.json");
+        let fr = fs.read(&path);
+        match fr {
+            Ok(f) => serde_json::from_reader(f).map_err(|e| e.into()),
+            Err(e) => {
+                if e.kind() == std::io::ErrorKind::NotFound {
+                    Ok(MigrationsState::new())
+                } else {
+                    Err(e.into())
+                }
+            }
+        }
+    }
+
+    fn state_table(&self) -> Cow<'_, str> {
+        match &self.state_table {
+            Some(name) => Cow::from(name),
+            None => Cow::Borrowed(ButaneMigration::TABLE),
+        }
     }
 }
 
@@ -45,6 +90,8 @@ impl MigrationsState {
 pub struct FsMigration {
     fs: Rc<dyn Filesystem>,
     root: PathBuf,
+    /// Set from the owning `FsMigrations`.
+    state_store: Option<Arc<dyn MigrationStateStore>>,
 }
 
 impl FsMigration {
@@ -134,6 +181,41 @@ impl MigrationMut for FsMigration {
         Ok(())
     }
 
+    fn tables(&self) -> Result<Vec<ATable>> {
+        self.ensure_dir()?;
+        let _lock = self.lock_shared()?;
+        let mut tables = Vec::new();
+        for entry in self.fs.list_dir(&self.root)? {
+            if entry
+                .file_name()
+                .is_some_and(|name| name.to_string_lossy().ends_with(".table"))
+            {
+                tables.push(serde_json::from_reader(self.fs.read(&entry)?)?);
+            }
+        }
+        Ok(tables)
+    }
+
+    fn write_view(&mut self, view: &AView) -> Result<()> {
+        self.write_contents(
+            &format!("{}.view", view.name),
+            serde_json::to_string(view)?.as_bytes(),
+        )
+    }
+
+    fn delete_view(&mut self, view: &str) -> Result<()> {
+        self.ensure_dir()?;
+        std::fs::remove_file(self.root.join(format!("{}.view", view)))?;
+        Ok(())
+    }
+
+    fn write_enum(&mut self, e: &AEnum) -> Result<()> {
+        self.write_contents(
+            &format!("{}.enum", e.name),
+            serde_json::to_string(e)?.as_bytes(),
+        )
+    }
+
     fn add_sql(&mut self, backend_name: &str, up_sql: &str, down_sql: &str) -> Result<()> {
         self.write_sql(&format!("{}_up", backend_name), up_sql)?;
         self.write_sql(&format!("{}_down", backend_name), down_sql)?;
@@ -173,6 +255,26 @@ impl MigrationMut for FsMigration {
         info.from_name = prev;
         self.write_info(&info)
     }
+
+    fn set_migration_from_hash(&mut self, hash: Option<String>) -> Result<()> {
+        let mut info = self.info()?;
+        info.from_hash = hash;
+        self.write_info(&info)
+    }
+
+    fn set_merged_from(&mut self, names: Vec<String>) -> Result<()> {
+        let mut info = self.info()?;
+        info.merged = names;
+        self.write_info(&info)
+    }
+
+    fn add_verification(&mut self, verification: Verification) -> Result<()> {
+        let mut info = self.info()?;
+        if !info.verifications.contains(&verification) {
+            info.verifications.push(verification);
+        }
+        self.write_info(&info)
+    }
 }
 
 impl Migration for FsMigration {
@@ -189,6 +291,12 @@ impl Migration for FsMigration {
                     if name.ends_with(".table") {
                         let table: ATable = serde_json::from_reader(self.fs.read(&entry)?)?;
                         db.replace_table(table)
+                    } else if name.ends_with(".view") {
+                        let view: AView = serde_json::from_reader(self.fs.read(&entry)?)?;
+                        db.replace_view(view)
+                    } else if name.ends_with(".enum") {
+                        let e: AEnum = serde_json::from_reader(self.fs.read(&entry)?)?;
+                        db.replace_enum(e)
                     } else if name == TYPES_FILENAME {
                         let types: SqlTypeMap = serde_json::from_reader(
                             self.fs.read(&self.root.join(TYPES_FILENAME))?,
@@ -209,6 +317,18 @@ impl Migration for FsMigration {
         Ok(self.info()?.from_name.map(Cow::from))
     }
 
+    fn migration_from_hash(&self) -> Result<Option<String>> {
+        Ok(self.info()?.from_hash)
+    }
+
+    fn merged_from(&self) -> Result<Vec<String>> {
+        Ok(self.info()?.merged)
+    }
+
+    fn verifications(&self) -> Result<Vec<Verification>> {
+        Ok(self.info()?.verifications)
+    }
+
     fn name(&self) -> Cow<str> {
         // There should be no way our root has no name portion
         self.root.file_name().unwrap().to_string_lossy()
@@ -225,6 +345,22 @@ impl Migration for FsMigration {
     fn sql_backends(&self) -> Result<Vec<String>> {
         Ok(self.info()?.backends)
     }
+
+    fn state_table(&self) -> Cow<'_, str> {
+        // The migrations' state lives in the directory containing this migration
+        let state = self
+            .root
+            .parent()
+            .and_then(|root| MigrationsState::read(self.fs.as_ref(), root).ok());
+        match state {
+            Some(state) => Cow::Owned(state.state_table().into_owned()),
+            None => Cow::Borrowed(ButaneMigration::TABLE),
+        }
+    }
+
+    fn state_store(&self) -> Arc<dyn MigrationStateStore> {
+        store_or_table(&self.state_store, &self.state_table())
+    }
 }
 
 impl PartialEq for FsMigration {
@@ -239,6 +375,7 @@ pub struct FsMigrations {
     fs: Rc<dyn Filesystem>,
     root: PathBuf,
     current: FsMigration,
+    state_store: Option<Arc<dyn MigrationStateStore>>,
 }
 impl FsMigrations {
     pub fn new(root: PathBuf) -> Self {
@@ -246,24 +383,79 @@ impl FsMigrations {
         let current = FsMigration {
             fs: fs.clone(),
             root: root.join("current"),
+            state_store: None,
         };
-        FsMigrations { fs, root, current }
+        FsMigrations {
+            fs,
+            root,
+            current,
+            state_store: None,
+        }
+    }
+    fn migration(&self, root: PathBuf) -> FsMigration {
+        FsMigration {
+            fs: self.fs.clone(),
+            root,
+            state_store: self.state_store.clone(),
+        }
     }
     fn get_state(&self) -> Result<MigrationsState> {
-        let path = self.root.join("state.json");
-        let fr = self.fs.read(&path);
-        match fr {
-            Ok(f) => serde_json::from_reader(f).map_err(|e| e.into()),
-            Err(e) => {
-                if e.kind() == std::io::ErrorKind::NotFound {
-                    Ok(MigrationsState::new())
-                } else {
-                    Err(e.into())
-                }
+        MigrationsState::read(self.fs.as_ref(), &self.root)
+    }
+    /// Migrations which no other migration is based on or has
+    /// merged. Normally there is only one, the latest migration, but
+    /// there may be more if migrations were created independently on
+    /// different branches. Sorted by name.
+    pub fn heads(&self) -> Result<Vec<FsMigration>> {
+        let mut all: Vec<FsMigration> = Vec::new();
+        let mut referenced: Vec<String> = Vec::new();
+        for entry in std::fs::read_dir(&self.root)? {
+            let entry = entry?;
+            if !entry.file_type()?.is_dir() || entry.file_name() == "current" {
+                continue;
             }
+            let m = self.migration(entry.path());
+            let info = m.info()?;
+            referenced.extend(info.from_name);
+            referenced.extend(info.merged);
+            all.push(m);
         }
+        let mut heads: Vec<FsMigration> = all
+            .into_iter()
+            .filter(|m| !referenced.iter().any(|name| *name == m.name()))
+            .collect();
+        heads.sort_by(|a, b| a.name().cmp(&b.name()));
+        Ok(heads)
+    }
+    /// Make `m` the latest migration.
+    pub fn set_latest(&mut self, m: &FsMigration) -> Result<()> {
+        let mut state = self.get_state()?;
+        state.latest = Some(m.name().to_string());
+        self.save_state(&state)
+    }
+    /// Use the table `name` rather than `butane_migrations` to record
+    /// which migrations have been applied. This is saved alongside the
+    /// migrations and carried over when they are embedded. It should
+    /// be set before the first migration is created, as that migration
+    /// creates the table.
+    pub fn set_state_table(&mut self, name: &str) -> Result<()> {
+        let mut state = self.get_state()?;
+        state.state_table = if name == ButaneMigration::TABLE {
+            None
+        } else {
+            Some(name.to_string())
+        };
+        self.save_state(&state)
+    }
+    /// Record which migrations have been applied in `store` rather
+    /// than in the [state table](Migrations::state_table). Unlike the
+    /// state table, this is not saved alongside the migrations.
+    pub fn set_state_store(&mut self, store: impl MigrationStateStore + 'static) {
+        self.state_store = Some(Arc::new(store));
+        self.current.state_store = self.state_store.clone();
     }
     fn save_state(&mut self, state: &MigrationsState) -> Result<()> {
+        self.fs.ensure_dir(&self.root)?;
         let path = self.root.join("state.json");
         let mut f = self.fs.write(&path)?;
         f.write_all(serde_json::to_string(state)?.as_bytes())
@@ -278,10 +470,7 @@ impl Migrations for FsMigrations {
         let mut dir = self.root.clone();
         dir.push(name);
         if dir.exists() {
-            Some(FsMigration {
-                fs: self.fs.clone(),
-                root: dir,
-            })
+            Some(self.migration(dir))
         } else {
             None
         }
@@ -295,6 +484,17 @@ impl Migrations for FsMigrations {
             })
             .unwrap_or(None)
     }
+
+    fn state_table(&self) -> Cow<'_, str> {
+        match self.get_state() {
+            Ok(state) => Cow::Owned(state.state_table().into_owned()),
+            Err(_) => Cow::Borrowed(ButaneMigration::TABLE),
+        }
+    }
+
+    fn state_store(&self) -> Arc<dyn MigrationStateStore> {
+        store_or_table(&self.state_store, &self.state_table())
+    }
 }
 
 impl MigrationsMut for FsMigrations {
@@ -304,16 +504,21 @@ impl MigrationsMut for FsMigrations {
     fn new_migration(&self, name: &str) -> Self::M {
         let mut dir = self.root.clone();
         dir.push(name);
-        FsMigration {
-            fs: self.fs.clone(),
-            root: dir,
-        }
+        self.migration(dir)
     }
     fn add_migration(&mut self, m: Self::M) -> Result<()> {
-        // Update state
+        // Update state. A migration based on the latest one, or merging
+        // it, becomes the latest.
         let from_name = m.migration_from()?.map(|s| s.to_string());
+        let merged = m.merged_from()?;
         let mut state = self.get_state()?;
-        if state.latest.is_none() || state.latest == from_name {
+        if state.latest.is_none()
+            || state.latest == from_name
+            || state
+                .latest
+                .as_ref()
+                .is_some_and(|latest| merged.contains(latest))
+        {
             state.latest = Some(m.name().to_string());
             self.save_state(&state)?;
         }
@@ -332,8 +537,7 @@ impl MigrationsMut for FsMigrations {
                 std::fs::remove_file(entry.path())?;
             }
         }
-        conn.delete_where(super::ButaneMigration::TABLE, crate::query::BoolExpr::True)?;
-        Ok(())
+        self.state_store().clear(conn)
     }
 }
 
@@ -1,10 +1,14 @@
-use super::adb::{ATable, DeferredSqlType, TypeKey, ADB};
-use super::{ButaneMigration, Migration, MigrationMut, Migrations, MigrationsMut};
-use crate::query::BoolExpr;
+use super::adb::{AEnum, ATable, AView, DeferredSqlType, TypeKey, ADB};
+use super::state::store_or_table;
+use super::{
+    ButaneMigration, Migration, MigrationMut, MigrationStateStore, Migrations, MigrationsMut,
+    Verification,
+};
 use crate::{ConnectionMethods, DataObject, Result};
 use serde::{Deserialize, Serialize};
 use std::borrow::Cow;
 use std::collections::HashMap;
+use std::sync::Arc;
 
 /// A migration stored in memory.
 #[derive(Clone, Serialize, Deserialize)]
@@ -14,16 +18,36 @@ pub struct MemMigration {
     from: Option<String>,
     up: HashMap<String, String>,
     down: HashMap<String, String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    from_hash: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    merged: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    verifications: Vec<Verification>,
+    /// Set from the owning `MemMigrations` rather than stored per migration.
+    #[serde(skip)]
+    state_table: Option<String>,
+    #[serde(skip)]
+    state_store: Option<Arc<dyn MigrationStateStore>>,
 }
 
 impl MemMigration {
-    fn new(name: String) -> Self {
+    fn new(
+        name: String,
+        state_table: Option<String>,
+        state_store: Option<Arc<dyn MigrationStateStore>>,
+    ) -> Self {
         MemMigration {
             name,
             db: ADB::new(),
             from: None,
             up: HashMap::new(),
             down: HashMap::new(),
+            from_hash: None,
+            merged: Vec::new(),
+            verifications: Vec::new(),
+            state_table,
+            state_store,
         }
     }
 }
@@ -39,6 +63,18 @@ impl Migration for MemMigration {
         Ok(self.from.as_ref().map(Cow::from))
     }
 
+    fn migration_from_hash(&self) -> Result<Option<String>> {
+        Ok(self.from_hash.clone())
+    }
+
+    fn merged_from(&self) -> Result<Vec<String>> {
+        Ok(self.merged.clone())
+    }
+
+    fn verifications(&self) -> Result<Vec<Verification>> {
+        Ok(self.verifications.clone())
+    }
+
     fn name(&self) -> Cow<str> {
         Cow::from(&self.name)
     }
@@ -55,6 +91,17 @@ impl Migration for MemMigration {
     fn sql_backends(&self) -> Result<Vec<String>> {
         Ok(self.up.keys().map(|k| k.to_string()).collect())
     }
+
+    fn state_table(&self) -> Cow<'_, str> {
+        match &self.state_table {
+            Some(name) => Cow::from(name),
+            None => Cow::Borrowed(ButaneMigration::TABLE),
+        }
+    }
+
+    fn state_store(&self) -> Arc<dyn MigrationStateStore> {
+        store_or_table(&self.state_store, &self.state_table())
+    }
 }
 impl PartialEq for MemMigration {
     fn eq(&self, other: &Self) -> bool {
@@ -73,6 +120,21 @@ impl MigrationMut for MemMigration {
         self.db.remove_table(table);
         Ok(())
     }
+    fn tables(&self) -> Result<Vec<ATable>> {
+        Ok(self.db.tables().cloned().collect())
+    }
+    fn write_view(&mut self, view: &AView) -> Result<()> {
+        self.db.replace_view(view.clone());
+        Ok(())
+    }
+    fn delete_view(&mut self, view: &str) -> Result<()> {
+        self.db.remove_view(view);
+        Ok(())
+    }
+    fn write_enum(&mut self, e: &AEnum) -> Result<()> {
+        self.db.replace_enum(e.clone());
+        Ok(())
+    }
     fn add_sql(&mut self, backend_name: &str, up_sql: &str, down_sql: &str) -> Result<()> {
         self.up.insert(backend_name.to_string(), up_sql.to_string());
         self.down
@@ -88,6 +150,20 @@ impl MigrationMut for MemMigration {
         self.from = prev;
         Ok(())
     }
+    fn set_migration_from_hash(&mut self, hash: Option<String>) -> Result<()> {
+        self.from_hash = hash;
+        Ok(())
+    }
+    fn set_merged_from(&mut self, names: Vec<String>) -> Result<()> {
+        self.merged = names;
+        Ok(())
+    }
+    fn add_verification(&mut self, verification: Verification) -> Result<()> {
+        if !self.verifications.contains(&verification) {
+            self.verifications.push(verification);
+        }
+        Ok(())
+    }
 }
 
 /// A collection of migrations stored in memory.
@@ -96,19 +172,55 @@ pub struct MemMigrations {
     migrations: HashMap<String, MemMigration>,
     current: MemMigration,
     latest: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    state_table: Option<String>,
+    #[serde(skip)]
+    state_store: Option<Arc<dyn MigrationStateStore>>,
 }
 
 impl MemMigrations {
     pub fn new() -> Self {
         MemMigrations {
             migrations: HashMap::new(),
-            current: MemMigration::new("current".to_string()),
+            current: MemMigration::new("current".to_string(), None, None),
             latest: None,
+            state_table: None,
+            state_store: None,
         }
     }
+    /// Use the table `name` rather than `butane_migrations` to record
+    /// which migrations have been applied. See
+    /// [`Migrations::state_table`].
+    pub fn set_state_table(&mut self, name: &str) {
+        self.state_table = if name == ButaneMigration::TABLE {
+            None
+        } else {
+            Some(name.to_string())
+        };
+        self.current.state_table = self.state_table.clone();
+    }
+    /// Record which migrations have been applied in `store` rather
+    /// than in the [state table](Migrations::state_table). Unlike the
+    /// state table, this is not serialized.
+    pub fn set_state_store(&mut self, store: impl MigrationStateStore + 'static) {
+        self.state_store = Some(Arc::new(store));
+        self.current.state_store = self.state_store.clone();
+    }
     pub fn from_json(json: &str) -> Result<Self> {
         serde_json::from_str(json).map_err(|e| e.into())
     }
+    /// Deserialize migrations in the compact binary (CBOR) form
+    /// produced by [`to_bytes`](Self::to_bytes).
+    #[cfg(feature = "cbor")]
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        crate::cbor::from_bytes(bytes)
+    }
+    /// Serialize to a compact binary (CBOR) form, which is
+    /// considerably smaller than JSON for large projects.
+    #[cfg(feature = "cbor")]
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        crate::cbor::to_bytes(self)
+    }
 }
 impl Default for MemMigrations {
     fn default() -> Self {
@@ -118,7 +230,11 @@ impl Default for MemMigrations {
 impl Migrations for MemMigrations {
     type M = MemMigration;
     fn get_migration(&self, name: &str) -> Option<Self::M> {
-        self.migrations.get(name).map(MemMigration::clone)
+        self.migrations.get(name).map(|m| MemMigration {
+            state_table: self.state_table.clone(),
+            state_store: self.state_store.clone(),
+            ..m.clone()
+        })
     }
     fn latest(&self) -> Option<Self::M> {
         match &self.latest {
@@ -126,6 +242,15 @@ impl Migrations for MemMigrations {
             Some(name) => self.get_migration(name),
         }
     }
+    fn state_table(&self) -> Cow<'_, str> {
+        match &self.state_table {
+            Some(name) => Cow::from(name),
+            None => Cow::Borrowed(ButaneMigration::TABLE),
+        }
+    }
+    fn state_store(&self) -> Arc<dyn MigrationStateStore> {
+        store_or_table(&self.state_store, &self.state_table())
+    }
 }
 
 impl MigrationsMut for MemMigrations {
@@ -134,15 +259,20 @@ impl MigrationsMut for MemMigrations {
     }
 
     fn new_migration(&self, name: &str) -> Self::M {
-        MemMigration::new(name.to_string())
+        MemMigration::new(
+            name.to_string(),
+            self.state_table.clone(),
+            self.state_store.clone(),
+        )
     }
     fn add_migration(&mut self, m: Self::M) -> Result<()> {
         let new_latest = match &self.latest {
             None => true,
-            Some(latest_name) => match m.migration_from()? {
-                None => false,
-                Some(name) => name.as_ref() == latest_name.as_str(),
-            },
+            // A migration based on the latest one, or merging it
+            Some(latest_name) => {
+                m.migration_from()?.as_deref() == Some(latest_name.as_str())
+                    || m.merged_from()?.contains(latest_name)
+            }
         };
         if new_latest {
             self.latest = Some(m.name().to_string());
@@ -155,7 +285,6 @@ impl MigrationsMut for MemMigrations {
     fn clear_migrations(&mut self, conn: &impl ConnectionMethods) -> Result<()> {
         self.migrations.clear();
         self.latest = None;
-        conn.delete_where(ButaneMigration::TABLE, BoolExpr::True)?;
-        Ok(())
+        self.state_store().clear(conn)
     }
 }
@@ -1,10 +1,14 @@
-use super::adb::{ATable, DeferredSqlType, TypeKey, ADB};
-use super::ButaneMigration;
+use super::adb::{AEnum, ATable, AView, DeferredSqlType, TypeKey, ADB};
+use super::{ButaneMigration, MigrationStateStore, TableStateStore, Verification};
 use crate::db::ConnectionMethods;
-use crate::query::{BoolExpr, Expr};
-use crate::{db, sqlval::ToSql, DataObject, DataResult, Error, Result};
+use crate::{db, DataObject, Error, Result};
+use sha2::{Digest, Sha256};
 use std::borrow::Cow;
 use std::cmp::PartialEq;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
 
 /// Type representing a database migration. A migration describes how
 /// to bring the database from state A to state B. In general, the
@@ -22,6 +26,21 @@ pub trait Migration: PartialEq {
     where
         Self: Sized;
 
+    /// The hash of the migration before this one when this one was
+    /// created, if recorded. If it no longer matches that migration's
+    /// [`hash`](Self::hash), the migration before this one has changed
+    /// since.
+    fn migration_from_hash(&self) -> Result<Option<String>> {
+        Ok(None)
+    }
+
+    /// The names of the other migrations this one is based on, if it
+    /// is a merge of migrations created independently. See
+    /// [`MigrationsMut::create_merge_migration`][crate::migrations::MigrationsMut::create_merge_migration].
+    fn merged_from(&self) -> Result<Vec<String>> {
+        Ok(Vec::new())
+    }
+
     /// The name of this migration.
     fn name(&self) -> Cow<str>;
 
@@ -34,30 +53,143 @@ pub trait Migration: PartialEq {
     /// The names of the backends this migration has sql for.
     fn sql_backends(&self) -> Result<Vec<String>>;
 
+    /// The checks made after applying this migration, in the same
+    /// transaction. If any fails, the migration is rolled back.
+    fn verifications(&self) -> Result<Vec<Verification>> {
+        Ok(Vec::new())
+    }
+
+    /// The SQL [`apply`](Self::apply) would run against a database of
+    /// type `backend`. No connection is needed, so this may be used to
+    /// inspect the generated DDL. Returns [`Error::UnknownBackend`] if
+    /// the migration has no SQL for the backend.
+    fn sql_for_backend(&self, backend: &dyn db::Backend) -> Result<String> {
+        self.up_sql(backend.name())?
+            .ok_or_else(|| Error::UnknownBackend(backend.name().to_string()))
+    }
+
+    /// The SQL [`downgrade`](Self::downgrade) would run against a
+    /// database of type `backend`. See
+    /// [`sql_for_backend`](Self::sql_for_backend).
+    fn down_sql_for_backend(&self, backend: &dyn db::Backend) -> Result<String> {
+        self.down_sql(backend.name())?
+            .ok_or_else(|| Error::UnknownBackend(backend.name().to_string()))
+    }
+
+    /// A hash of the migration's name, the migration it is based on
+    /// and its sql, identifying this version of the migration.
+    fn hash(&self) -> Result<String>
+    where
+        Self: Sized,
+    {
+        let mut hasher = Sha256::new();
+        hasher.update(self.name().as_bytes());
+        if let Some(from) = self.migration_from()? {
+            hasher.update(b"\0from\0");
+            hasher.update(from.as_bytes());
+        }
+        for merged in self.merged_from()? {
+            hasher.update(b"\0merged\0");
+            hasher.update(merged.as_bytes());
+        }
+        let mut backends = self.sql_backends()?;
+        backends.sort();
+        for backend in backends {
+            hasher.update(b"\0backend\0");
+            hasher.update(backend.as_bytes());
+            for sql in [self.up_sql(&backend)?, self.down_sql(&backend)?] {
+                hasher.update(b"\0");
+                hasher.update(sql.unwrap_or_default().as_bytes());
+            }
+        }
+        // Hashed only if present, so as not to change the hashes of
+        // existing migrations
+        let verifications = self.verifications()?;
+        if !verifications.is_empty() {
+            hasher.update(b"\0verifications\0");
+            hasher.update(serde_json::to_string(&verifications)?.as_bytes());
+        }
+        Ok(hex::encode(hasher.finalize()))
+    }
+
+    /// The name of the table recording which migrations have been
+    /// applied. See [`Migrations::state_table`][crate::migrations::Migrations::state_table].
+    fn state_table(&self) -> Cow<'_, str> {
+        Cow::Borrowed(ButaneMigration::TABLE)
+    }
+
+    /// Where the applied migrations are recorded. See
+    /// [`Migrations::state_store`][crate::migrations::Migrations::state_store].
+    fn state_store(&self) -> Arc<dyn MigrationStateStore> {
+        Arc::new(TableStateStore::new(self.state_table()))
+    }
+
     /// Apply the migration to a database connection. The connection
     /// must be for the same type of database as this and the database
-    /// must be in the state of the migration prior to this one
+    /// must be in the state of the migration prior to this one. The
+    /// migration is rolled back if any of its
+    /// [`verifications`](Self::verifications) fails, unless the backend
+    /// cannot make schema changes in a transaction (see
+    /// [`transactional_ddl`](db::Backend::transactional_ddl)).
     fn apply(&self, conn: &mut impl db::BackendConnection) -> Result<()> {
-        let backend_name = conn.backend_name();
+        let backend = conn.backend();
+        let sql = self.sql_for_backend(backend.as_ref())?;
+        if !backend.transactional_ddl() {
+            return apply_in(self, conn, &sql, true);
+        }
         let tx = conn.transaction()?;
-        let sql = self
-            .up_sql(backend_name)?
-            .ok_or_else(|| Error::UnknownBackend(backend_name.to_string()))?;
-        tx.execute(&sql)?;
-        self.mark_applied(&tx)?;
+        apply_in(self, &tx, &sql, false)?;
         tx.commit()
     }
 
+    /// Apply the migration as [`apply`](Self::apply) does, but cancel
+    /// it if it has not finished within `limit`. A cancelled migration
+    /// is rolled back, recorded as failed in the
+    /// [`state_store`](Self::state_store) and reported as
+    /// [`Error::MigrationTimeout`]. The backend must support
+    /// [cancelling statements](db::BackendConnection::cancel_handle).
+    fn apply_within(&self, conn: &mut impl db::BackendConnection, limit: Duration) -> Result<()> {
+        let backend = conn.backend();
+        let sql = self.sql_for_backend(backend.as_ref())?;
+        let cancel = conn.cancel_handle()?;
+        let (finished, watched) = mpsc::channel::<()>();
+        // Wakes when `finished` is dropped, or cancels once the limit
+        // has passed. Returns whether it cancelled.
+        let watchdog = thread::spawn(move || match watched.recv_timeout(limit) {
+            Err(RecvTimeoutError::Timeout) => cancel.cancel().is_ok(),
+            _ => false,
+        });
+        let result = (|| {
+            if !backend.transactional_ddl() {
+                return apply_in(self, &*conn, &sql, true);
+            }
+            let tx = conn.transaction()?;
+            apply_in(self, &tx, &sql, false)?;
+            tx.commit()
+        })();
+        drop(finished);
+        let cancelled = watchdog.join().unwrap_or(false);
+        match result {
+            // The transaction was rolled back when dropped
+            Err(e) if cancelled => {
+                let reason = format!("did not finish within {:?}: {}", limit, e);
+                self.state_store()
+                    .mark_failed(conn, &self.name(), &reason)?;
+                Err(Error::MigrationTimeout {
+                    name: self.name().to_string(),
+                    limit,
+                })
+            }
+            result => result,
+        }
+    }
+
     /// Mark the migration as being applied without doing any
     /// work. Use carefully -- the caller must ensure that the
     /// database schema already matches that expected by this
     /// migration.
     fn mark_applied(&self, conn: &impl db::ConnectionMethods) -> Result<()> {
-        conn.insert_only(
-            ButaneMigration::TABLE,
-            ButaneMigration::COLUMNS,
-            &[self.name().as_ref().to_sql_ref()],
-        )
+        self.state_store().mark_applied(conn, &self.name())
     }
 
     /// Un-apply (downgrade) the migration to a database
@@ -65,21 +197,97 @@ pub trait Migration: PartialEq {
     /// database as this and this must be the latest migration applied
     /// to the database.
     fn downgrade(&self, conn: &mut impl db::BackendConnection) -> Result<()> {
-        let backend_name = conn.backend_name();
+        let backend = conn.backend();
+        let sql = self.down_sql_for_backend(backend.as_ref())?;
+        if !backend.transactional_ddl() {
+            execute_statements(&*conn, &sql)?;
+            return self.state_store().mark_unapplied(&*conn, &self.name());
+        }
         let tx = conn.transaction()?;
-        let sql = self
-            .down_sql(backend_name)?
-            .ok_or_else(|| Error::UnknownBackend(backend_name.to_string()))?;
         tx.execute(&sql)?;
-        let nameval = self.name().as_ref().to_sql();
-        tx.delete_where(
-            ButaneMigration::TABLE,
-            BoolExpr::Eq(ButaneMigration::PKCOL, Expr::Val(nameval)),
-        )?;
+        self.state_store().mark_unapplied(&tx, &self.name())?;
         tx.commit()
     }
 }
 
+/// Run the SQL `sql` of `m` in the transaction `tx`, make its
+/// verifications and record it as applied. If `separately`, `tx` is
+/// a connection outside a transaction, and each statement of `sql` is
+/// run on its own.
+fn apply_in<M: Migration + ?Sized>(
+    m: &M,
+    tx: &impl ConnectionMethods,
+    sql: &str,
+    separately: bool,
+) -> Result<()> {
+    let verifications = m.verifications()?;
+    let before = verifications
+        .iter()
+        .map(|v| v.before(tx))
+        .collect::<Result<Vec<Option<i64>>>>()?;
+    if separately {
+        execute_statements(tx, sql)?;
+    } else {
+        tx.execute(sql)?;
+    }
+    for (verification, before) in verifications.iter().zip(before) {
+        verification.verify(tx, &m.name(), before)?;
+    }
+    m.mark_applied(tx)
+}
+
+/// Run each statement of `sql` on its own, for backends which would
+/// otherwise run them together in one implicit transaction.
+fn execute_statements(conn: &impl ConnectionMethods, sql: &str) -> Result<()> {
+    for statement in split_statements(sql) {
+        conn.execute(statement)?;
+    }
+    Ok(())
+}
+
+/// The statements of `sql`, split at the semicolons which are not in a
+/// string, quoted identifier, dollar-quoted body or comment.
+fn split_statements(sql: &str) -> Vec<&str> {
+    let mut statements = Vec::new();
+    let mut start = 0;
+    let mut rest = sql;
+    let mut pos = 0;
+    while let Some(c) = rest.chars().next() {
+        let skip = match c {
+            '\'' | '"' | '`' => rest[1..].find(c).map_or(rest.len(), |end| end + 2),
+            '$' => {
+                // A dollar quote such as $$ or $body$
+                let tag_len = rest[1..]
+                    .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+                    .filter(|&i| rest[1 + i..].starts_with('$'))
+                    .map(|i| i + 2);
+                match tag_len {
+                    Some(tag_len) => {
+                        let tag = &rest[..tag_len];
+                        rest[tag_len..]
+                            .find(tag)
+                            .map_or(rest.len(), |end| tag_len + end + tag_len)
+                    }
+                    None => 1,
+                }
+            }
+            '-' if rest.starts_with("--") => rest.find('\n').unwrap_or(rest.len()),
+            '/' if rest.starts_with("/*") => rest.find("*/").map_or(rest.len(), |end| end + 2),
+            ';' => {
+                statements.push(sql[start..pos].trim());
+                start = pos + 1;
+                1
+            }
+            c => c.len_utf8(),
+        };
+        pos += skip;
+        rest = &sql[pos..];
+    }
+    statements.push(sql[start..].trim());
+    statements.retain(|s| !s.is_empty());
+    statements
+}
+
 /// A migration which can be modified
 pub trait MigrationMut: Migration {
     /// Adds an abstract table to the migration. The table state should
@@ -94,6 +302,24 @@ pub trait MigrationMut: Migration {
     /// butane cli command `butane delete table <TABLE>`.
     fn delete_table(&mut self, name: &str) -> Result<()>;
 
+    /// The tables written to the migration. Unlike
+    /// [`Migration::db`], their types are not resolved, so may refer
+    /// to tables not yet written.
+    fn tables(&self) -> Result<Vec<ATable>>;
+
+    /// Adds an abstract view to the migration, in the same fashion as
+    /// [`write_table`](Self::write_table).
+    fn write_view(&mut self, view: &AView) -> Result<()>;
+
+    /// Delete the view with the given name. As with tables, views
+    /// remain until explicitly deleted. See also the butane cli command
+    /// `butane delete view <VIEW>`.
+    fn delete_view(&mut self, name: &str) -> Result<()>;
+
+    /// Adds an abstract enum to the migration, in the same fashion as
+    /// [`write_table`](Self::write_table).
+    fn write_enum(&mut self, e: &AEnum) -> Result<()>;
+
     /// Set the backend-specific commands to apply/undo this migration.
     fn add_sql(&mut self, backend_name: &str, up_sql: &str, down_sql: &str) -> Result<()>;
 
@@ -102,4 +328,16 @@ pub trait MigrationMut: Migration {
 
     /// Set the name of the migration before this one.
     fn set_migration_from(&mut self, prev: Option<String>) -> Result<()>;
+
+    /// Set the hash of the migration before this one. See
+    /// [`Migration::migration_from_hash`].
+    fn set_migration_from_hash(&mut self, hash: Option<String>) -> Result<()>;
+
+    /// Set the names of the other migrations this one is based on. See
+    /// [`Migration::merged_from`].
+    fn set_merged_from(&mut self, names: Vec<String>) -> Result<()>;
+
+    /// Add a check to make after applying the migration. See
+    /// [`Migration::verifications`].
+    fn add_verification(&mut self, verification: Verification) -> Result<()>;
 }
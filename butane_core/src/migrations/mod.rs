@@ -1,15 +1,15 @@
 //! For working with migrations. If using the butane CLI tool, it is
 //! not necessary to use these types directly.
-use crate::db::BackendRows;
 use crate::db::{Column, ConnectionMethods};
 use crate::sqlval::{FromSql, SqlValRef, ToSql};
 use crate::{db, query, DataObject, DataResult, Error, Result, SqlType};
 
-use fallible_iterator::FallibleIterator;
+use std::borrow::Cow;
 use std::path::Path;
+use std::sync::Arc;
 
 pub mod adb;
-use adb::{AColumn, ATable, DeferredSqlType, Operation, TypeIdentifier, ADB};
+use adb::{AColumn, ATable, DeferredSqlType, Operation, TypeIdentifier, TypeKey, ADB};
 
 mod migration;
 pub use migration::{Migration, MigrationMut};
@@ -20,6 +20,11 @@ mod fsmigrations;
 pub use fsmigrations::{FsMigration, FsMigrations};
 mod memmigrations;
 pub use memmigrations::{MemMigration, MemMigrations};
+mod state;
+pub use state::{ConnectionStateStore, MigrationStateStore, TableStateStore};
+mod verification;
+pub use verification::Verification;
+pub mod testing;
 
 /// A collection of migrations.
 pub trait Migrations {
@@ -32,80 +37,169 @@ pub trait Migrations {
     /// no migrations have been created.
     fn latest(&self) -> Option<Self::M>;
 
-    /// Returns migrations since the given migration.
-    fn migrations_since(&self, since: &Self::M) -> Result<Vec<Self::M>> {
-        let mut last = self.latest();
-        let mut accum: Vec<Self::M> = Vec::new();
-        while let Some(m) = last {
-            if m != *since {
-                last = match m.migration_from()? {
-                    None => None,
-                    Some(name) => self.get_migration(&name),
-                };
-                accum.push(m);
-                continue;
-            }
+    /// The name of the table recording which migrations have been
+    /// applied to the database. Defaults to `butane_migrations`, but
+    /// may be changed (e.g. with
+    /// [`FsMigrations::set_state_table`]) so that several sets of
+    /// migrations can share one database.
+    fn state_table(&self) -> Cow<'_, str> {
+        Cow::Borrowed(ButaneMigration::TABLE)
+    }
+
+    /// Where the applied migrations are recorded. Defaults to the
+    /// [`state_table`](Self::state_table) in the migrated database, but
+    /// may be changed (e.g. with [`MemMigrations::set_state_store`]) to
+    /// keep the record elsewhere.
+    fn state_store(&self) -> Arc<dyn MigrationStateStore> {
+        Arc::new(TableStateStore::new(self.state_table()))
+    }
 
-            return Ok(accum.into_iter().rev().collect());
+    /// Returns the migrations the latest one is based on which the
+    /// given migration is not, and the latest one itself, in the order
+    /// to apply them.
+    fn migrations_since(&self, since: &Self::M) -> Result<Vec<Self::M>> {
+        let all = self.all_migrations()?;
+        if !all.contains(since) {
+            return Err(Error::MigrationError("Migration not in chain".to_string()));
         }
-        Err(Error::MigrationError("Migration not in chain".to_string()))
+        let before = self.ancestry(since)?;
+        Ok(all.into_iter().filter(|m| !before.contains(m)).collect())
     }
 
-    /// Returns all migrations
+    /// Returns all migrations the latest one is based on, and the latest
+    /// one itself, in the order to apply them: each after the migrations
+    /// it is based on, including all those a merge migration merges.
     fn all_migrations(&self) -> Result<Vec<Self::M>> {
-        let mut last = self.latest();
-        let mut accum: Vec<Self::M> = Vec::new();
-        while let Some(m) = last {
-            last = match m.migration_from()? {
+        match self.latest() {
+            None => Ok(Vec::new()),
+            Some(latest) => topological(self, latest),
+        }
+    }
+
+    /// The migration `m` followed by each of the migrations it is based
+    /// on, each before the migrations it is in turn based on.
+    fn ancestry(&self, m: &Self::M) -> Result<Vec<Self::M>> {
+        let mut accum = match self.get_migration(&m.name()) {
+            None => Vec::new(),
+            Some(m) => topological(self, m)?,
+        };
+        accum.reverse();
+        Ok(accum)
+    }
+
+    /// The operations needed to move the schema of migration `from` to
+    /// that of migration `to`. Neither need be based on the other, so
+    /// this may span several migrations in either direction.
+    fn diff(&self, from: &Self::M, to: &Self::M) -> Result<Vec<Operation>> {
+        Ok(adb::diff(&from.db()?, &to.db()?))
+    }
+
+    /// Compare the schema of the live database with that of the last
+    /// migration applied to it. Returns the operations which would turn
+    /// the migration's schema into the database's, which are empty
+    /// unless the database has drifted, e.g. through manual changes.
+    /// Column defaults, check constraints, views and the predicates of
+    /// partial indexes are not compared. Tables, indexes and
+    /// constraints which the migration lacks are ignored unless they
+    /// carry [MANAGED_MARKER][db::MANAGED_MARKER], as they were created
+    /// by other means and are not butane's to remove.
+    fn check_drift(&self, conn: &impl db::BackendConnection) -> Result<Vec<Operation>> {
+        let expected = match self.last_applied_migration(conn)? {
+            Some(m) => m.db()?,
+            None => ADB::new(),
+        };
+        let backend = conn.backend();
+        let expected = backend.as_introspected(&expected);
+        let mut live = conn.introspect()?;
+        live.remove_table(&self.state_table());
+        let managed = conn.managed_tables()?;
+        let unmanaged: Vec<String> = live
+            .tables()
+            .filter(|t| expected.get_table(&t.name).is_none() && !managed.contains(&t.name))
+            .map(|t| t.name.clone())
+            .collect();
+        for name in unmanaged {
+            live.remove_table(&name);
+        }
+        remove_unmanaged_objects(conn, &expected, &mut live)?;
+        align_index_predicates(&expected, &mut live);
+        Ok(adb::diff(&expected, &live))
+    }
+
+    /// Ways in which the chain of migrations ending at the latest one
+    /// does not line up with the migrations applied to the database,
+    /// e.g. because migrations were created concurrently by members of
+    /// a team sharing a database. Applying migrations to a diverged
+    /// database is likely to fail part way through, so check this
+    /// first.
+    fn divergence(&self, conn: &impl ConnectionMethods) -> Result<Vec<Divergence>> {
+        let chain = self.all_migrations()?;
+        let mut ret = Vec::new();
+        for m in &chain {
+            let parent = match m.migration_from()? {
+                Some(name) => chain.iter().find(|parent| parent.name() == name),
                 None => None,
-                Some(name) => self.get_migration(&name),
             };
-            accum.push(m);
+            if let (Some(parent), Some(hash)) = (parent, m.migration_from_hash()?) {
+                if hash != parent.hash()? {
+                    ret.push(Divergence::ParentChanged {
+                        name: m.name().to_string(),
+                        parent: parent.name().to_string(),
+                    });
+                }
+            }
         }
-        Ok(accum.into_iter().rev().collect())
+        for name in self.state_store().applied(conn)? {
+            if !chain.iter().any(|m| m.name() == name) {
+                let known = self.get_migration(&name).is_some();
+                ret.push(Divergence::UnknownApplied { name, known });
+            }
+        }
+        Ok(ret)
     }
 
-    /// Get migrations which have not yet been applied to the database
+    /// Get migrations which have not yet been applied to the database,
+    /// in the order to apply them. After a merge, these include the
+    /// migrations of whichever branches the database lacks.
     fn unapplied_migrations(&self, conn: &impl ConnectionMethods) -> Result<Vec<Self::M>> {
-        match self.last_applied_migration(conn)? {
-            None => self.all_migrations(),
-            Some(m) => self.migrations_since(&m),
-        }
+        let applied = self.state_store().applied(conn)?;
+        Ok(self
+            .all_migrations()?
+            .into_iter()
+            .filter(|m| !applied.iter().any(|name| *name == m.name()))
+            .collect())
     }
 
     /// Get the last migration that has been applied to the database or None
     /// if no migrations have been applied
     fn last_applied_migration(&self, conn: &impl ConnectionMethods) -> Result<Option<Self::M>> {
-        if !conn.has_table(ButaneMigration::TABLE)? {
-            return Ok(None);
-        }
-        let migrations: Vec<ButaneMigration> = conn
-            .query(
-                ButaneMigration::TABLE,
-                ButaneMigration::COLUMNS,
-                None,
-                None,
-                None,
-                None,
-            )?
-            .mapped(ButaneMigration::from_row)
-            .collect()?;
-
-        let mut m_opt = self.latest();
-        while let Some(m) = m_opt {
-            if migrations.contains(&ButaneMigration {
-                name: m.name().to_string(),
-            }) {
-                return Ok(Some(m));
-            }
-            m_opt = m
-                .migration_from()?
-                .and_then(|name| self.get_migration(&name))
-        }
-        Ok(None)
+        let applied = self.state_store().applied(conn)?;
+        let latest = match self.latest() {
+            Some(latest) => latest,
+            None => return Ok(None),
+        };
+        Ok(self
+            .ancestry(&latest)?
+            .into_iter()
+            .find(|m| applied.iter().any(|name| *name == m.name())))
     }
 }
 
+/// A way in which the migrations have diverged from those applied to
+/// a database. See [`Migrations::divergence`].
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Divergence {
+    /// The migration `name` has been applied to the database but is
+    /// not in the chain ending at the latest migration. If it is
+    /// `known`, it is on another branch of the migrations and should be
+    /// merged; otherwise it has not been pulled in.
+    UnknownApplied { name: String, known: bool },
+    /// The migration `name` was created from a different version of
+    /// the migration `parent` than the one now present, and should be
+    /// recreated from it.
+    ParentChanged { name: String, parent: String },
+}
+
 pub trait MigrationsMut: Migrations
 where
     Self::M: MigrationMut,
@@ -159,36 +253,214 @@ where
         from: Option<&Self::M>,
         to_db: ADB,
     ) -> Result<bool> {
-        let empty_db = Ok(ADB::new());
-        let from_none = from.is_none();
-        let from_db = from.map_or(empty_db, |m| m.db())?;
-        let mut ops = adb::diff(&from_db, &to_db);
-        if ops.is_empty() {
-            return Ok(false);
-        }
+        create_migration_with(self, backend, name, from, to_db, false)
+    }
 
-        if from_none {
-            // This may be the first migration. Create the butane_migration table
-            ops.push(Operation::AddTableIfNotExists(migrations_table()));
+    /// Create a migration from `from` named `name` which makes the
+    /// changes `ops`, and is undone by `down_ops`. Unlike
+    /// `create_migration`, the changes are given rather than found by
+    /// comparing schemas, so may include renames, which a comparison
+    /// cannot tell apart from removing one table or column and adding
+    /// another.
+    fn create_migration_with_ops(
+        &mut self,
+        backend: &impl db::Backend,
+        name: &str,
+        from: &Self::M,
+        ops: Vec<Operation>,
+        down_ops: Vec<Operation>,
+    ) -> Result<()> {
+        let from_db = from.db()?;
+        let mut to_db = from_db.clone();
+        for op in ops.iter().cloned() {
+            to_db.transform_with(op);
         }
-
+        to_db.validate()?;
         let up_sql = backend.create_migration_sql(&from_db, ops)?;
-        let down_sql = backend.create_migration_sql(&to_db, adb::diff(&to_db, &from_db))?;
+        let down_sql = backend.create_migration_sql(&to_db, down_ops)?;
+        add_migration_for(self, backend, name, Some(from), &to_db, &up_sql, &down_sql)
+    }
+
+    /// Like `create_migration`, but the migration's SQL guards its
+    /// statements with `IF EXISTS`/`IF NOT EXISTS` where the backend
+    /// supports it, so that it may safely be re-run after being
+    /// interrupted part way through.
+    fn create_idempotent_migration(
+        &mut self,
+        backend: &impl db::Backend,
+        name: &str,
+        from: Option<&Self::M>,
+    ) -> Result<bool> {
+        let to_db = self.current().db()?;
+        create_migration_with(self, backend, name, from, to_db, true)
+    }
+
+    /// Create a merge migration named `name`, based on both `base` and
+    /// each migration in `others`. This reconciles migrations created
+    /// independently (e.g. on separate branches of version control)
+    /// from a common ancestor. The merge has no SQL of its own: a
+    /// database gets the changes of whichever branches it lacks by
+    /// applying their migrations, which
+    /// [`unapplied_migrations`](Migrations::unapplied_migrations)
+    /// orders before the merge. Its schema is that of `base` with the
+    /// changes each of `others` made since the ancestor it shares with
+    /// `base`. The merge becomes the latest migration if one of the
+    /// migrations it is based on was.
+    fn create_merge_migration(
+        &mut self,
+        backend: &impl db::Backend,
+        name: &str,
+        base: &Self::M,
+        others: &[Self::M],
+    ) -> Result<()> {
+        let base_chain = self.ancestry(base)?;
+        let mut merged = base.db()?;
+        for other in others {
+            let ancestor_db = match self
+                .ancestry(other)?
+                .into_iter()
+                .find(|m| base_chain.contains(m))
+            {
+                Some(m) => m.db()?,
+                None => ADB::new(),
+            };
+            for op in adb::diff(&ancestor_db, &other.db()?) {
+                merged.transform_with(op);
+            }
+        }
+        merged.validate()?;
         let mut m = self.new_migration(name);
-        // Save the DB for use by other migrations from this one
-        for table in to_db.tables() {
+        for table in merged.tables() {
             m.write_table(table)?;
         }
-        m.add_sql(backend.name(), &up_sql, &down_sql)?;
-        m.set_migration_from(from.map(|m| m.name().to_string()))?;
+        for view in merged.views() {
+            m.write_view(view)?;
+        }
+        for e in merged.enums() {
+            m.write_enum(e)?;
+        }
+        m.add_sql(backend.name(), "", "")?;
+        m.set_migration_from(Some(base.name().to_string()))?;
+        m.set_migration_from_hash(Some(base.hash()?))?;
+        m.set_merged_from(others.iter().map(|m| m.name().to_string()).collect())?;
+        self.add_migration(m)
+    }
+}
 
-        self.add_migration(m)?;
-        Ok(true)
+/// `head` and all the migrations it is based on, each after those it
+/// is based on.
+fn topological<Ms>(ms: &Ms, head: Ms::M) -> Result<Vec<Ms::M>>
+where
+    Ms: Migrations + ?Sized,
+{
+    let mut order = Vec::new();
+    let mut seen: Vec<String> = Vec::new();
+    // Each migration is visited, pushing the migrations it is based
+    // on, and then finished once they have been
+    let mut stack = vec![(head, false)];
+    while let Some((m, visited)) = stack.pop() {
+        if visited {
+            order.push(m);
+            continue;
+        }
+        let name = m.name().to_string();
+        if seen.contains(&name) {
+            continue;
+        }
+        seen.push(name);
+        let mut parents: Vec<String> = m
+            .migration_from()?
+            .into_iter()
+            .map(Cow::into_owned)
+            .collect();
+        parents.extend(m.merged_from()?);
+        stack.push((m, true));
+        for parent in parents.iter().rev() {
+            if let Some(parent) = ms.get_migration(parent) {
+                stack.push((parent, false));
+            }
+        }
     }
+    Ok(order)
 }
 
-fn migrations_table() -> ATable {
-    let mut table = ATable::new("butane_migrations".to_string());
+fn create_migration_with<Ms>(
+    ms: &mut Ms,
+    backend: &impl db::Backend,
+    name: &str,
+    from: Option<&Ms::M>,
+    to_db: ADB,
+    idempotent: bool,
+) -> Result<bool>
+where
+    Ms: MigrationsMut + ?Sized,
+    Ms::M: MigrationMut,
+{
+    to_db.validate()?;
+    let empty_db = Ok(ADB::new());
+    let from_none = from.is_none();
+    let from_db = from.map_or(empty_db, |m| m.db())?;
+    let mut ops = adb::diff(&from_db, &to_db);
+    if ops.is_empty() {
+        return Ok(false);
+    }
+
+    if from_none {
+        // This may be the first migration. Create the butane_migration table
+        ops.push(Operation::AddTableIfNotExists(migrations_table(
+            &ms.state_table(),
+        )));
+    }
+
+    let down_ops = adb::diff(&to_db, &from_db);
+    let (up_sql, down_sql) = if idempotent {
+        (
+            backend.create_idempotent_migration_sql(&from_db, ops)?,
+            backend.create_idempotent_migration_sql(&to_db, down_ops)?,
+        )
+    } else {
+        (
+            backend.create_migration_sql(&from_db, ops)?,
+            backend.create_migration_sql(&to_db, down_ops)?,
+        )
+    };
+    add_migration_for(ms, backend, name, from, &to_db, &up_sql, &down_sql)?;
+    Ok(true)
+}
+
+/// Add the migration `name` from `from` to `to_db`, made by `up_sql`.
+fn add_migration_for<Ms>(
+    ms: &mut Ms,
+    backend: &impl db::Backend,
+    name: &str,
+    from: Option<&Ms::M>,
+    to_db: &ADB,
+    up_sql: &str,
+    down_sql: &str,
+) -> Result<()>
+where
+    Ms: MigrationsMut + ?Sized,
+    Ms::M: MigrationMut,
+{
+    let mut m = ms.new_migration(name);
+    // Save the DB for use by other migrations from this one
+    for table in to_db.tables() {
+        m.write_table(table)?;
+    }
+    for view in to_db.views() {
+        m.write_view(view)?;
+    }
+    for e in to_db.enums() {
+        m.write_enum(e)?;
+    }
+    m.add_sql(backend.name(), up_sql, down_sql)?;
+    m.set_migration_from(from.map(|m| m.name().to_string()))?;
+    m.set_migration_from_hash(from.map(|m| m.hash()).transpose()?)?;
+    ms.add_migration(m)
+}
+
+fn migrations_table(name: &str) -> ATable {
+    let mut table = ATable::new(name.to_string());
     let col = AColumn::new(
         "name",
         DeferredSqlType::KnownId(TypeIdentifier::Ty(SqlType::Text)),
@@ -209,13 +481,54 @@ pub fn from_root<P: AsRef<Path>>(path: P) -> FsMigrations {
     FsMigrations::new(path.as_ref().to_path_buf())
 }
 
+/// Create a `Migrations` for the migration group `group` from a
+/// filesystem location. Models declared with
+/// `#[butane(migration_group = "...")]` write their migration
+/// information to `.butane/groups/<group>/migrations` rather than to
+/// the main migrations, so the group's schema can be migrated
+/// independently. Unless another state table has been set, which of the
+/// group's migrations have been applied is recorded in
+/// `butane_migrations_<group>`.
+pub fn group_from_root<P: AsRef<Path>>(path: P, group: &str) -> Result<FsMigrations> {
+    let mut ms = from_root(path);
+    if ms.state_table() == ButaneMigration::TABLE {
+        ms.set_state_table(&format!("{}_{}", ButaneMigration::TABLE, group))?;
+    }
+    Ok(ms)
+}
+
+/// Makes the types known to `from` known to `to`: custom types and the
+/// primary key types of its tables. This lets the models of a
+/// migration group use the types and reference the models of the main
+/// migrations.
+pub fn copy_types(from: &impl Migration, to: &mut impl MigrationMut) -> Result<()> {
+    let db = from.db()?;
+    for (k, v) in db.types() {
+        to.add_type(k.clone(), v.clone())?;
+    }
+    for table in db.tables() {
+        if let [pk] = table.pk() {
+            to.add_type(TypeKey::PK(table.name.clone()), pk.typeid()?.into())?;
+        }
+    }
+    Ok(())
+}
+
 /// Copies the data in `from` to `to`.
 pub fn copy_migration(from: &impl Migration, to: &mut impl MigrationMut) -> Result<()> {
     to.set_migration_from(from.migration_from()?.map(|s| s.to_string()))?;
+    to.set_migration_from_hash(from.migration_from_hash()?)?;
+    to.set_merged_from(from.merged_from()?)?;
     let db = from.db()?;
     for table in db.tables() {
         to.write_table(table)?;
     }
+    for view in db.views() {
+        to.write_view(view)?;
+    }
+    for e in db.enums() {
+        to.write_enum(e)?;
+    }
     for (k, v) in db.types() {
         to.add_type(k.clone(), v.clone())?;
     }
@@ -226,6 +539,128 @@ pub fn copy_migration(from: &impl Migration, to: &mut impl MigrationMut) -> Resu
             to.add_sql(&backend_name, &up_sql, &down_sql)?;
         }
     }
+    for verification in from.verifications()? {
+        to.add_verification(verification)?;
+    }
+    Ok(())
+}
+
+/// Selects the tables kept by [`copy_migration_filtered`]. A table
+/// matches if it is named, or if it holds a [`Many`](crate::many::Many)
+/// field of a named table.
+#[derive(Clone, Debug, Default)]
+pub struct TableFilter {
+    names: Vec<String>,
+}
+impl TableFilter {
+    pub fn new<S: Into<String>>(names: impl IntoIterator<Item = S>) -> Self {
+        TableFilter {
+            names: names.into_iter().map(|s| s.into()).collect(),
+        }
+    }
+    /// Returns true if the table named `table` was selected.
+    pub fn matches(&self, table: &str) -> bool {
+        self.names.iter().any(|name| {
+            table == name
+                || (table.starts_with(name.as_str())
+                    && table[name.len()..].starts_with('_')
+                    && table.ends_with("_Many"))
+        })
+    }
+    /// The selected tables of `db`, along with the tables they
+    /// reference with foreign key constraints. Views named in the filter are kept, as are all
+    /// custom types and enums.
+    fn apply(&self, db: &ADB) -> ADB {
+        let mut keep: Vec<&str> = db
+            .tables()
+            .map(|t| t.name.as_str())
+            .filter(|name| self.matches(name))
+            .collect();
+        let mut i = 0;
+        while i < keep.len() {
+            if let Some(table) = db.get_table(keep[i]) {
+                for referenced in table.referenced_tables() {
+                    if !keep.contains(&referenced) {
+                        keep.push(referenced);
+                    }
+                }
+            }
+            i += 1;
+        }
+        let mut filtered = ADB::new();
+        for table in db.tables().filter(|t| keep.contains(&t.name.as_str())) {
+            filtered.replace_table(table.clone());
+        }
+        for view in db.views().filter(|v| self.matches(&v.name)) {
+            filtered.replace_view(view.clone());
+        }
+        for e in db.enums() {
+            filtered.replace_enum(e.clone());
+        }
+        for (k, v) in db.types() {
+            filtered.add_type(k.clone(), v.clone());
+        }
+        filtered
+    }
+}
+
+/// Copies the data in `from` to `to`, keeping only the tables selected
+/// by `filter` and the tables they reference with a foreign key
+/// constraint (`#[butane(foreign_key)]`). `prev` must be the
+/// migration `from` follows, if any. The SQL for each backend of `from`
+/// is regenerated to create only the kept tables, so a binary may embed
+/// only the migrations for the models it uses. Migrations which do not
+/// touch the kept tables, and merge migrations, are still copied, with
+/// empty SQL, so that the history is unchanged.
+///
+/// The regenerated SQL uses each backend's default settings (such as
+/// identifier case). Binaries which share one database should embed the
+/// same tables, as the applied migrations are recorded by name only.
+pub fn copy_migration_filtered<M: Migration>(
+    from: &M,
+    prev: Option<&M>,
+    to: &mut impl MigrationMut,
+    filter: &TableFilter,
+) -> Result<()> {
+    to.set_migration_from(from.migration_from()?.map(|s| s.to_string()))?;
+    // The filtered migration no longer matches the hash of its predecessor
+    to.set_migration_from_hash(None)?;
+    let merged = from.merged_from()?;
+    let merge = !merged.is_empty();
+    to.set_merged_from(merged)?;
+    let from_db = prev.map_or_else(|| Ok(ADB::new()), |m| m.db())?;
+    let from_db = filter.apply(&from_db);
+    let to_db = filter.apply(&from.db()?);
+    for table in to_db.tables() {
+        to.write_table(table)?;
+    }
+    for view in to_db.views() {
+        to.write_view(view)?;
+    }
+    for e in to_db.enums() {
+        to.write_enum(e)?;
+    }
+    for (k, v) in to_db.types() {
+        to.add_type(k.clone(), v.clone())?;
+    }
+    let mut ops = adb::diff(&from_db, &to_db);
+    if prev.is_none() {
+        ops.push(Operation::AddTableIfNotExists(migrations_table(
+            &from.state_table(),
+        )));
+    }
+    let down_ops = adb::diff(&to_db, &from_db);
+    for backend_name in from.sql_backends()? {
+        if merge {
+            to.add_sql(&backend_name, "", "")?;
+            continue;
+        }
+        let backend = db::get_backend(&backend_name)
+            .ok_or_else(|| Error::UnknownBackend(backend_name.clone()))?;
+        let up_sql = backend.create_migration_sql(&from_db, ops.clone())?;
+        let down_sql = backend.create_migration_sql(&to_db, down_ops.clone())?;
+        to.add_sql(&backend_name, &up_sql, &down_sql)?;
+    }
     Ok(())
 }
 
@@ -273,3 +708,78 @@ impl DataObject for ButaneMigration {
         conn.delete(Self::TABLE, Self::PKCOL, self.pk().to_sql())
     }
 }
+
+/// Removes from the live schema `live` the indexes and constraints
+/// which neither carry [MANAGED_MARKER][db::MANAGED_MARKER] nor are
+/// in `expected`, as they were created by other means.
+pub(crate) fn remove_unmanaged_objects(
+    conn: &impl db::BackendConnection,
+    expected: &ADB,
+    live: &mut ADB,
+) -> Result<()> {
+    for (tbl_name, object) in conn.unmanaged_objects()? {
+        let mut table = match live.get_table(&tbl_name) {
+            Some(table) => table.clone(),
+            None => continue,
+        };
+        let declared = expected.get_table(&tbl_name);
+        match object {
+            db::TableObject::Index(index) => {
+                let is_declared = declared
+                    .is_some_and(|t| t.indexes.iter().any(|i| i.columns() == index.columns()));
+                if !is_declared {
+                    table.remove_index(&index);
+                }
+            }
+            db::TableObject::UniqueConstraint(constraint) => {
+                let is_declared = declared.is_some_and(|t| match constraint.columns() {
+                    [name] => t.column(name).is_some_and(|c| c.unique()),
+                    _ => t.unique_constraints.contains(&constraint),
+                });
+                if !is_declared {
+                    table.remove_unique_constraint(&constraint);
+                    if let [name] = constraint.columns() {
+                        if let Some(mut col) = table.column(name).cloned() {
+                            col.set_unique(false);
+                            table.replace_column(col);
+                        }
+                    }
+                }
+            }
+            db::TableObject::ForeignKey(name) => {
+                let is_declared = declared
+                    .and_then(|t| t.column(&name))
+                    .is_some_and(|c| c.reference().is_some());
+                if let (false, Some(mut col)) = (is_declared, table.column(&name).cloned()) {
+                    col.set_reference(None);
+                    table.replace_column(col);
+                }
+            }
+        }
+        live.replace_table(table);
+    }
+    Ok(())
+}
+
+/// Replaces the predicates of the partial indexes of `live` with those
+/// of the indexes of the same columns in `expected`, as databases may
+/// rewrite predicates, e.g. adding parentheses.
+pub(crate) fn align_index_predicates(expected: &ADB, live: &mut ADB) {
+    let tables: Vec<ATable> = live.tables().cloned().collect();
+    for mut table in tables {
+        let declared = match expected.get_table(&table.name) {
+            Some(declared) => declared,
+            None => continue,
+        };
+        for index in &mut table.indexes {
+            let matching = declared
+                .indexes
+                .iter()
+                .find(|i| i.columns() == index.columns() && i.predicate().is_some());
+            if let (Some(matching), Some(_)) = (matching, index.predicate()) {
+                *index = matching.clone();
+            }
+        }
+        live.replace_table(table);
+    }
+}
@@ -0,0 +1,176 @@
+//! Where the record of which migrations have been applied is kept.
+use super::{migrations_table, ButaneMigration};
+use crate::db::{self, BackendConnection, BackendRows, Column, ConnectionMethods, ConnectionSpec};
+use crate::migrations::adb::{Operation, ADB};
+use crate::query::{BoolExpr, Expr};
+use crate::sqlval::ToSql;
+use crate::{DataObject, DataResult, Result, SqlType};
+use fallible_iterator::FallibleIterator;
+use std::sync::Arc;
+
+/// Records which migrations have been applied to a database.
+///
+/// By default this is the table [TableStateStore] in the database
+/// itself, but it may be kept anywhere, e.g. in another database with
+/// [ConnectionStateStore] or in a key-value store. Set the store with
+/// [`MemMigrations::set_state_store`][super::MemMigrations::set_state_store]
+/// or [`FsMigrations::set_state_store`][super::FsMigrations::set_state_store].
+pub trait MigrationStateStore: Send + Sync {
+    /// Names of the migrations which have been applied to the database
+    /// `conn` is connected to.
+    fn applied(&self, conn: &dyn ConnectionMethods) -> Result<Vec<String>>;
+
+    /// Record that the migration `name` has been applied. `conn` is the
+    /// transaction applying it, which is committed afterwards, so the
+    /// record is only atomic with the migration if it is kept through
+    /// `conn`.
+    fn mark_applied(&self, conn: &dyn ConnectionMethods, name: &str) -> Result<()>;
+
+    /// Record that the migration `name` has been un-applied.
+    fn mark_unapplied(&self, conn: &dyn ConnectionMethods, name: &str) -> Result<()>;
+
+    /// Record that applying the migration `name` failed for `reason`
+    /// and was rolled back. Stores which cannot record failures ignore
+    /// them.
+    fn mark_failed(&self, _conn: &dyn ConnectionMethods, _name: &str, _reason: &str) -> Result<()> {
+        Ok(())
+    }
+
+    /// Forget that any migrations have been applied.
+    fn clear(&self, conn: &dyn ConnectionMethods) -> Result<()>;
+}
+
+/// Keeps the applied migrations in a table of the migrated database.
+/// This is the default. The table is created by the first migration.
+/// Migrations which failed to apply are kept in another table, named
+/// after it with the suffix `_failures`, which is created by the first
+/// failure.
+pub struct TableStateStore {
+    table: String,
+}
+impl TableStateStore {
+    const FAILURE_COLUMNS: &'static [Column] = &[
+        Column::new("name", SqlType::Text),
+        Column::new("reason", SqlType::Text),
+    ];
+
+    pub fn new(table: impl Into<String>) -> Self {
+        TableStateStore {
+            table: table.into(),
+        }
+    }
+    fn failures_table(&self) -> String {
+        format!("{}_failures", self.table)
+    }
+}
+impl MigrationStateStore for TableStateStore {
+    fn applied(&self, conn: &dyn ConnectionMethods) -> Result<Vec<String>> {
+        if !conn.has_table(&self.table)? {
+            return Ok(Vec::new());
+        }
+        conn.query(
+            &self.table,
+            ButaneMigration::COLUMNS,
+            None,
+            None,
+            None,
+            None,
+        )?
+        .mapped(ButaneMigration::from_row)
+        .map(|m| Ok(m.name))
+        .collect()
+    }
+    fn mark_applied(&self, conn: &dyn ConnectionMethods, name: &str) -> Result<()> {
+        let failures = self.failures_table();
+        if conn.has_table(&failures)? {
+            conn.delete_where(
+                &failures,
+                BoolExpr::Eq(ButaneMigration::PKCOL, Expr::Val(name.to_sql())),
+            )?;
+        }
+        conn.insert_only(&self.table, ButaneMigration::COLUMNS, &[name.to_sql_ref()])
+    }
+    fn mark_unapplied(&self, conn: &dyn ConnectionMethods, name: &str) -> Result<()> {
+        conn.delete_where(
+            &self.table,
+            BoolExpr::Eq(ButaneMigration::PKCOL, Expr::Val(name.to_sql())),
+        )?;
+        Ok(())
+    }
+    fn mark_failed(&self, conn: &dyn ConnectionMethods, name: &str, reason: &str) -> Result<()> {
+        let failures = self.failures_table();
+        // The same on every backend, so no migration is needed
+        conn.execute(&format!(
+            "CREATE TABLE IF NOT EXISTS {} (name VARCHAR(255) NOT NULL PRIMARY KEY, reason TEXT NOT NULL);",
+            failures
+        ))?;
+        conn.insert_or_replace(
+            &failures,
+            Self::FAILURE_COLUMNS,
+            &Self::FAILURE_COLUMNS[0],
+            &[name.to_sql_ref(), reason.to_sql_ref()],
+        )
+    }
+    fn clear(&self, conn: &dyn ConnectionMethods) -> Result<()> {
+        conn.delete_where(&self.table, BoolExpr::True)?;
+        Ok(())
+    }
+}
+
+/// Keeps the applied migrations in a table of a database other than
+/// the migrated one, connecting to it with a [ConnectionSpec] each
+/// time. With Postgres, the spec may simply use a different schema,
+/// e.g. where the application's user has no DDL rights on its own. The
+/// table is created if it does not exist.
+pub struct ConnectionStateStore {
+    spec: ConnectionSpec,
+    table: TableStateStore,
+    table_name: String,
+}
+impl ConnectionStateStore {
+    pub fn new(spec: ConnectionSpec, table: impl Into<String>) -> Self {
+        let table_name = table.into();
+        ConnectionStateStore {
+            spec,
+            table: TableStateStore::new(table_name.clone()),
+            table_name,
+        }
+    }
+    fn connect(&self) -> Result<db::Connection> {
+        let conn = db::connect(&self.spec)?;
+        if !conn.has_table(&self.table_name)? {
+            let op = Operation::AddTableIfNotExists(migrations_table(&self.table_name));
+            let sql = conn.backend().create_migration_sql(&ADB::new(), vec![op])?;
+            conn.execute(&sql)?;
+        }
+        Ok(conn)
+    }
+}
+impl MigrationStateStore for ConnectionStateStore {
+    fn applied(&self, _conn: &dyn ConnectionMethods) -> Result<Vec<String>> {
+        self.table.applied(&self.connect()?)
+    }
+    fn mark_applied(&self, _conn: &dyn ConnectionMethods, name: &str) -> Result<()> {
+        self.table.mark_applied(&self.connect()?, name)
+    }
+    fn mark_unapplied(&self, _conn: &dyn ConnectionMethods, name: &str) -> Result<()> {
+        self.table.mark_unapplied(&self.connect()?, name)
+    }
+    fn mark_failed(&self, _conn: &dyn ConnectionMethods, name: &str, reason: &str) -> Result<()> {
+        self.table.mark_failed(&self.connect()?, name, reason)
+    }
+    fn clear(&self, _conn: &dyn ConnectionMethods) -> Result<()> {
+        self.table.clear(&self.connect()?)
+    }
+}
+
+/// `store` if set, otherwise the default store using `table`.
+pub(super) fn store_or_table(
+    store: &Option<Arc<dyn MigrationStateStore>>,
+    table: &str,
+) -> Arc<dyn MigrationStateStore> {
+    match store {
+        Some(store) => store.clone(),
+        None => Arc::new(TableStateStore::new(table)),
+    }
+}
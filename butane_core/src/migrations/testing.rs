@@ -0,0 +1,71 @@
+//! Helpers for testing migrations, e.g. in CI against each backend
+//! an application supports.
+
+use super::adb::{self, ADB};
+use super::{Migration, Migrations};
+use crate::db::BackendConnection;
+use crate::{Error, Result};
+
+/// Checks that `ms` can be applied, rolled back and applied again.
+///
+/// `conn` should be a connection to a fresh, empty database. All
+/// migrations are applied and the schema of the database is compared
+/// to `expected`, e.g. the schema of the latest migration. All
+/// migrations are then rolled back, which must leave no tables behind,
+/// and applied again, after which the schema is compared to `expected`
+/// once more. Returns [`Error::SchemaMismatch`] describing the first
+/// difference found.
+///
+/// As with [`Migrations::check_drift`], column defaults, check
+/// constraints, views and the predicates of partial indexes are not
+/// compared, nor are indexes and constraints created by other means.
+///
+/// E.g.
+/// ```ignore
+/// let ms = butane::migrations::from_root(".butane/migrations");
+/// let expected = ms.latest().unwrap().db()?;
+/// butane::migrations::testing::round_trip(&ms, &mut conn, &expected)?;
+/// ```
+pub fn round_trip(
+    ms: &impl Migrations,
+    conn: &mut impl BackendConnection,
+    expected: &ADB,
+) -> Result<()> {
+    let all = ms.all_migrations()?;
+    for m in &all {
+        m.apply(conn)?;
+    }
+    compare(ms, conn, expected, "applying migrations")?;
+
+    for m in all.iter().rev() {
+        m.downgrade(conn)?;
+    }
+    compare(ms, conn, &ADB::new(), "rolling back migrations")?;
+
+    for m in &all {
+        m.apply(conn)?;
+    }
+    compare(ms, conn, expected, "re-applying migrations")
+}
+
+fn compare(
+    ms: &impl Migrations,
+    conn: &impl BackendConnection,
+    expected: &ADB,
+    stage: &str,
+) -> Result<()> {
+    let expected = conn.backend().as_introspected(expected);
+    let mut live = conn.introspect()?;
+    live.remove_table(&ms.state_table());
+    super::remove_unmanaged_objects(conn, &expected, &mut live)?;
+    super::align_index_predicates(&expected, &mut live);
+    let ops = adb::diff(&expected, &live);
+    if ops.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::SchemaMismatch {
+            stage: stage.to_string(),
+            ops,
+        })
+    }
+}
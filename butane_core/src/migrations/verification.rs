@@ -0,0 +1,83 @@
+//! Checks run after a migration is applied, in the same transaction,
+//! so that a migration which leaves the data in a bad state is rolled
+//! back.
+use crate::db::ConnectionMethods;
+use crate::{Error, Result};
+use serde::{Deserialize, Serialize};
+
+/// A check made once a migration has been applied. Table and column
+/// names are written into SQL as given, so must be quoted where the
+/// backend requires it.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Verification {
+    /// The existing table has as many rows after the migration as
+    /// before it, e.g. when the migration rebuilds the table.
+    RowCountUnchanged(String),
+    /// No row of the table has NULL in the column.
+    NotNull { table: String, column: String },
+    /// The SQL query returns no rows. It is run as by
+    /// [query_raw](crate::db::ConnectionMethods::query_raw), so a `?`
+    /// outside quotes is written `??`.
+    NoRows(String),
+}
+impl Verification {
+    /// The number of rows to compare with after the migration, if this
+    /// check needs one.
+    pub(super) fn before(&self, conn: &impl ConnectionMethods) -> Result<Option<i64>> {
+        match self {
+            Verification::RowCountUnchanged(table) => {
+                Ok(Some(count(conn, &format!("SELECT * FROM {}", table))?))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Make the check, given the result of [`before`](Self::before).
+    /// Returns [`Error::VerificationFailed`] if it fails.
+    pub(super) fn verify(
+        &self,
+        conn: &impl ConnectionMethods,
+        migration: &str,
+        before: Option<i64>,
+    ) -> Result<()> {
+        let failure = match self {
+            Verification::RowCountUnchanged(table) => {
+                let after = count(conn, &format!("SELECT * FROM {}", table))?;
+                let before = before.unwrap_or_default();
+                (after != before).then(|| {
+                    format!(
+                        "{} has {} rows, but had {} before the migration",
+                        table, after, before
+                    )
+                })
+            }
+            Verification::NotNull { table, column } => {
+                let nulls = count(
+                    conn,
+                    &format!("SELECT * FROM {} WHERE {} IS NULL", table, column),
+                )?;
+                (nulls > 0).then(|| format!("{} rows have {}.{} NULL", nulls, table, column))
+            }
+            Verification::NoRows(sql) => {
+                let rows = count(conn, sql.trim().trim_end_matches(';'))?;
+                (rows > 0).then(|| format!("{} rows returned by {}", rows, sql))
+            }
+        };
+        match failure {
+            Some(check) => Err(Error::VerificationFailed {
+                migration: migration.to_string(),
+                check,
+            }),
+            None => Ok(()),
+        }
+    }
+}
+
+/// The number of rows returned by the query `sql`.
+fn count(conn: &impl ConnectionMethods, sql: &str) -> Result<i64> {
+    let rows: Vec<(i64,)> = conn.query_raw(
+        &format!("SELECT count(*) FROM ({}) AS butane_check", sql),
+        &[],
+    )?;
+    Ok(rows.first().map_or(0, |(n,)| *n))
+}
@@ -0,0 +1,401 @@
+//! Aggregate functions of the objects matched by a query, see
+//! [Query::aggregate][super::Query::aggregate], and of groups of them,
+//! see [Query::group_by][super::Query::group_by].
+
+use super::FieldExpr;
+use crate::db::Column;
+use crate::sqlval::{FieldType, FromSql, ToSql};
+use crate::{Error, Result, SqlType, SqlVal};
+use std::marker::PhantomData;
+
+/// An SQL aggregate function.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum AggregateFunction {
+    Count,
+    Sum,
+    Avg,
+    Min,
+    Max,
+}
+impl AggregateFunction {
+    /// The function's name in SQL.
+    pub fn sql_name(&self) -> &'static str {
+        match self {
+            AggregateFunction::Count => "COUNT",
+            AggregateFunction::Sum => "SUM",
+            AggregateFunction::Avg => "AVG",
+            AggregateFunction::Min => "MIN",
+            AggregateFunction::Max => "MAX",
+        }
+    }
+}
+
+/// An aggregate function of a column, or of the rows themselves for
+/// `COUNT(*)`, as computed by
+/// [ConnectionMethods::aggregate][crate::db::ConnectionMethods::aggregate].
+#[derive(Clone, Debug, PartialEq)]
+pub struct AggregateExpr {
+    pub function: AggregateFunction,
+    /// The column aggregated, or `None` for all rows.
+    pub column: Option<&'static str>,
+    /// The type the value is read as.
+    pub ty: SqlType,
+}
+
+/// An aggregate whose value is read as `R`. Constructed with [count],
+/// [sum], [avg], [min] and [max].
+#[derive(Clone, Debug)]
+pub struct Aggregate<R> {
+    expr: AggregateExpr,
+    name: String,
+    phantom: PhantomData<R>,
+}
+impl<R> Aggregate<R> {
+    fn new(function: AggregateFunction, column: Option<&'static str>, ty: SqlType) -> Self {
+        let function_name = function.sql_name().to_lowercase();
+        Aggregate {
+            expr: AggregateExpr {
+                function,
+                column,
+                ty,
+            },
+            name: match column {
+                Some(column) => format!("{}_{}", function_name, column),
+                None => function_name,
+            },
+            phantom: PhantomData,
+        }
+    }
+    pub fn expr(&self) -> &AggregateExpr {
+        &self.expr
+    }
+    /// The name of the field of a [FromRow] the aggregate is read
+    /// into, by default its function and column, as `count` or
+    /// `sum_likes`.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+    /// Names the aggregate `name`, see [name](Self::name). Returns
+    /// `self` as this method is expected to be chained.
+    pub fn named(mut self, name: &str) -> Self {
+        self.name = name.to_string();
+        self
+    }
+    /// The aggregate is equal to `val`.
+    pub fn eq(&self, val: impl ToSql) -> Having {
+        Having::Eq(self.expr.clone(), val.to_sql())
+    }
+    /// The aggregate is not equal to `val`.
+    pub fn ne(&self, val: impl ToSql) -> Having {
+        Having::Ne(self.expr.clone(), val.to_sql())
+    }
+    /// The aggregate is less than `val`.
+    pub fn lt(&self, val: impl ToSql) -> Having {
+        Having::Lt(self.expr.clone(), val.to_sql())
+    }
+    /// The aggregate is greater than `val`.
+    pub fn gt(&self, val: impl ToSql) -> Having {
+        Having::Gt(self.expr.clone(), val.to_sql())
+    }
+    /// The aggregate is less than or equal to `val`.
+    pub fn le(&self, val: impl ToSql) -> Having {
+        Having::Le(self.expr.clone(), val.to_sql())
+    }
+    /// The aggregate is greater than or equal to `val`.
+    pub fn ge(&self, val: impl ToSql) -> Having {
+        Having::Ge(self.expr.clone(), val.to_sql())
+    }
+}
+
+/// A condition on the aggregates of a group (HAVING in SQL), see
+/// [GroupedQuery::having][super::GroupedQuery::having]. Constructed
+/// by comparing an [Aggregate], as `count().gt(1)`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Having {
+    Eq(AggregateExpr, SqlVal),
+    Ne(AggregateExpr, SqlVal),
+    Lt(AggregateExpr, SqlVal),
+    Gt(AggregateExpr, SqlVal),
+    Le(AggregateExpr, SqlVal),
+    Ge(AggregateExpr, SqlVal),
+    And(Box<Having>, Box<Having>),
+    Or(Box<Having>, Box<Having>),
+    Not(Box<Having>),
+}
+impl Having {
+    /// Both this condition and `other` hold.
+    pub fn and(self, other: Having) -> Having {
+        Having::And(Box::new(self), Box::new(other))
+    }
+    /// This condition or `other` holds.
+    pub fn or(self, other: Having) -> Having {
+        Having::Or(Box::new(self), Box::new(other))
+    }
+}
+
+/// The number of objects matched.
+pub fn count() -> Aggregate<i64> {
+    Aggregate::new(AggregateFunction::Count, None, SqlType::BigInt)
+}
+
+/// The sum of `field` over the objects matched, or `None` if there
+/// are none. Integers are summed as `i64` and reals as `f64`.
+pub fn sum<T: Summable>(field: FieldExpr<T>) -> Aggregate<Option<T::Sum>> {
+    Aggregate::new(AggregateFunction::Sum, Some(field.name()), T::SUM_TYPE)
+}
+
+/// The mean of `field` over the objects matched, or `None` if there
+/// are none.
+pub fn avg<T: Summable>(field: FieldExpr<T>) -> Aggregate<Option<f64>> {
+    Aggregate::new(AggregateFunction::Avg, Some(field.name()), SqlType::Real)
+}
+
+/// The smallest value of `field` among the objects matched, or `None`
+/// if there are none.
+pub fn min<T: FieldType + Into<SqlVal>>(field: FieldExpr<T>) -> Aggregate<Option<T>> {
+    Aggregate::new(AggregateFunction::Min, Some(field.name()), T::SQLTYPE)
+}
+
+/// The largest value of `field` among the objects matched, or `None`
+/// if there are none.
+pub fn max<T: FieldType + Into<SqlVal>>(field: FieldExpr<T>) -> Aggregate<Option<T>> {
+    Aggregate::new(AggregateFunction::Max, Some(field.name()), T::SQLTYPE)
+}
+
+/// Field types which can be summed and averaged.
+pub trait Summable: Into<SqlVal> {
+    /// The type of their sum.
+    type Sum: FromSql;
+    const SUM_TYPE: SqlType;
+}
+
+macro_rules! impl_summable {
+    ($sum:ty, $sqltype:ident, $($prim:ty),+) => {
+        $(
+            impl Summable for $prim {
+                type Sum = $sum;
+                const SUM_TYPE: SqlType = SqlType::$sqltype;
+            }
+        )+
+    };
+}
+
+impl_summable!(i64, BigInt, i8, u8, i16, u16, i32, u32, i64);
+impl_summable!(f64, Real, f32, f64);
+
+impl<T: Summable> Summable for Option<T>
+where
+    Option<T>: Into<SqlVal>,
+{
+    type Sum = T::Sum;
+    const SUM_TYPE: SqlType = T::SUM_TYPE;
+}
+
+/// One or more aggregates computed together: an [Aggregate], or a
+/// tuple of up to four of them.
+pub trait Aggregates {
+    /// The values of the aggregates.
+    type Output;
+    fn exprs(&self) -> Vec<AggregateExpr>;
+    /// The [names](Aggregate::name) of the aggregates.
+    fn names(&self) -> Vec<String>;
+    /// Read the values computed for [exprs](Self::exprs) from the
+    /// next of `values`.
+    fn read(values: &mut dyn Iterator<Item = SqlVal>) -> Result<Self::Output>;
+}
+
+impl<R: FromSql> Aggregates for Aggregate<R> {
+    type Output = R;
+    fn exprs(&self) -> Vec<AggregateExpr> {
+        vec![self.expr.clone()]
+    }
+    fn names(&self) -> Vec<String> {
+        vec![self.name.clone()]
+    }
+    fn read(values: &mut dyn Iterator<Item = SqlVal>) -> Result<R> {
+        R::from_sql(values.next().ok_or_else(missing_value)?)
+    }
+}
+
+macro_rules! impl_aggregates_tuple {
+    ($($r:ident),+) => {
+        impl<$($r: FromSql),+> Aggregates for ($(Aggregate<$r>,)+) {
+            type Output = ($($r,)+);
+            #[allow(non_snake_case)]
+            fn exprs(&self) -> Vec<AggregateExpr> {
+                let ($($r,)+) = self;
+                vec![$($r.expr.clone()),+]
+            }
+            #[allow(non_snake_case)]
+            fn names(&self) -> Vec<String> {
+                let ($($r,)+) = self;
+                vec![$($r.name.clone()),+]
+            }
+            fn read(values: &mut dyn Iterator<Item = SqlVal>) -> Result<Self::Output> {
+                Ok(($($r::from_sql(values.next().ok_or_else(missing_value)?)?,)+))
+            }
+        }
+    };
+}
+
+impl_aggregates_tuple!(A, B);
+impl_aggregates_tuple!(A, B, C);
+impl_aggregates_tuple!(A, B, C, D);
+
+/// Fields read together, as those objects are grouped by or those
+/// selected: a [FieldExpr], a function of one such as a
+/// [DateFieldExpr][super::DateFieldExpr], or a tuple of up to four of
+/// them.
+pub trait GroupKeys {
+    /// The values of the fields.
+    type Output;
+    fn columns(&self) -> Vec<Column>;
+    /// Read the values of the [columns](Self::columns) from the next
+    /// of `values`.
+    fn read(values: &mut dyn Iterator<Item = SqlVal>) -> Result<Self::Output>;
+}
+
+impl<T: FieldType + Into<SqlVal>> GroupKeys for FieldExpr<T> {
+    type Output = T;
+    fn columns(&self) -> Vec<Column> {
+        vec![Column::new(self.name(), T::SQLTYPE)]
+    }
+    fn read(values: &mut dyn Iterator<Item = SqlVal>) -> Result<T> {
+        T::from_sql(values.next().ok_or_else(missing_value)?)
+    }
+}
+
+macro_rules! impl_group_keys_tuple {
+    ($($k:ident),+) => {
+        impl<$($k: GroupKeys),+> GroupKeys for ($($k,)+) {
+            type Output = ($($k::Output,)+);
+            #[allow(non_snake_case)]
+            fn columns(&self) -> Vec<Column> {
+                let ($($k,)+) = self;
+                [$($k.columns()),+].concat()
+            }
+            fn read(values: &mut dyn Iterator<Item = SqlVal>) -> Result<Self::Output> {
+                Ok(($($k::read(values)?,)+))
+            }
+        }
+    };
+}
+
+impl_group_keys_tuple!(A, B);
+impl_group_keys_tuple!(A, B, C);
+impl_group_keys_tuple!(A, B, C, D);
+
+/// A struct read from the rows of a grouped or aggregate query, each
+/// field from the column of the same name: that of a field grouped by
+/// or selected, or an aggregate [named](Aggregate::named) after it.
+/// Derive it with `#[derive(FromRow)]`.
+///
+/// The type of each field must match that of its column, as checked
+/// before the query is run. Aggregates other than [count] are NULL
+/// where there is nothing to aggregate, so must be read into `Option`
+/// fields.
+pub trait FromRow: Sized {
+    /// The fields, in the order [read](Self::read) reads them.
+    fn fields() -> Vec<RowField>;
+    /// Read the fields from the next of `values`.
+    fn read(values: &mut dyn Iterator<Item = SqlVal>) -> Result<Self>;
+}
+
+/// A field of a [FromRow].
+#[derive(Clone, Debug, PartialEq)]
+pub struct RowField {
+    pub name: &'static str,
+    /// The type of the field, or of its value if it is an `Option`.
+    pub ty: SqlType,
+    /// Whether the field is an `Option`.
+    pub optional: bool,
+}
+impl RowField {
+    pub fn new(name: &'static str, ty: SqlType, optional: bool) -> Self {
+        RowField { name, ty, optional }
+    }
+}
+
+/// A column of the rows of a query, matched to a [RowField].
+struct ResultColumn {
+    name: String,
+    ty: SqlType,
+    nullable: bool,
+}
+
+/// Reads the rows of the columns grouped by or selected followed by
+/// those of the aggregates into a [FromRow].
+pub(super) struct RowReader {
+    /// For each field, the index of its column.
+    indices: Vec<usize>,
+}
+impl RowReader {
+    /// Matches the fields of `R` to the columns of `keys` followed by
+    /// those of `aggregates` with `names`, failing if any is missing or
+    /// of another type.
+    pub(super) fn new<R: FromRow>(
+        keys: &[Column],
+        aggregates: Vec<AggregateExpr>,
+        names: Vec<String>,
+    ) -> Result<Self> {
+        let columns: Vec<ResultColumn> = keys
+            .iter()
+            .map(|col| ResultColumn {
+                name: col.name().to_string(),
+                ty: col.ty().clone(),
+                nullable: false,
+            })
+            .chain(
+                aggregates
+                    .into_iter()
+                    .zip(names)
+                    .map(|(expr, name)| ResultColumn {
+                        name,
+                        nullable: expr.function != AggregateFunction::Count,
+                        ty: expr.ty,
+                    }),
+            )
+            .collect();
+        let indices = R::fields()
+            .into_iter()
+            .map(|field| {
+                let index = columns
+                    .iter()
+                    .position(|col| col.name == field.name)
+                    .ok_or_else(|| Error::NoSuchColumn(field.name.to_string()))?;
+                let column = &columns[index];
+                if column.ty != field.ty {
+                    return Err(Error::SqlResultTypeMismatch {
+                        col: field.name.to_string(),
+                        detail: format!(
+                            "The field is of type {} but the column of type {}",
+                            field.ty, column.ty
+                        ),
+                    });
+                }
+                if column.nullable && !field.optional {
+                    return Err(Error::SqlResultTypeMismatch {
+                        col: field.name.to_string(),
+                        detail: "The column is NULL where there is nothing to aggregate, so the \
+                                 field must be an Option"
+                            .to_string(),
+                    });
+                }
+                Ok(index)
+            })
+            .collect::<Result<_>>()?;
+        Ok(RowReader { indices })
+    }
+
+    pub(super) fn read<R: FromRow>(&self, mut row: Vec<SqlVal>) -> Result<R> {
+        let mut values = self
+            .indices
+            .iter()
+            .map_while(|&i| row.get_mut(i).map(|v| std::mem::replace(v, SqlVal::Null)));
+        R::read(&mut values)
+    }
+}
+
+pub(super) fn missing_value() -> Error {
+    Error::Internal("query returned too few values".to_string())
+}
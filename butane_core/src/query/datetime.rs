@@ -0,0 +1,165 @@
+//! Functions of timestamp fields, such as the year or the start of
+//! the month of a time, to filter and group objects by, see
+//! [FieldExpr::extract] and [FieldExpr::date_trunc].
+
+use super::aggregate::missing_value;
+use super::{BoolExpr, DataOrd, GroupKeys};
+use crate::db::Column;
+use crate::sqlval::{FieldType, ToSql};
+use crate::{Result, SqlVal};
+use std::marker::PhantomData;
+
+#[cfg(feature = "datetime")]
+use super::FieldExpr;
+#[cfg(feature = "datetime")]
+use chrono::naive::NaiveDateTime;
+
+/// A part of a time, as extracted from it or to which it is truncated.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum DatePart {
+    Year,
+    Month,
+    Day,
+    Hour,
+    Minute,
+    Second,
+}
+impl DatePart {
+    /// The part's name in SQL, as for `EXTRACT`.
+    pub fn sql_name(&self) -> &'static str {
+        match self {
+            DatePart::Year => "YEAR",
+            DatePart::Month => "MONTH",
+            DatePart::Day => "DAY",
+            DatePart::Hour => "HOUR",
+            DatePart::Minute => "MINUTE",
+            DatePart::Second => "SECOND",
+        }
+    }
+}
+
+/// A function of a timestamp.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum DateFunction {
+    /// The part of the time, as an integer counted from 1 for months
+    /// and days and from 0 otherwise.
+    Extract(DatePart),
+    /// The time at the start of the part containing it, as midnight on
+    /// the 1st for a month.
+    Trunc(DatePart),
+}
+
+/// A function of a timestamp column.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct DateExpr {
+    pub column: &'static str,
+    pub function: DateFunction,
+}
+
+/// A comparison of values, as for [BoolExpr::Date].
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Comparison {
+    Eq,
+    Ne,
+    Lt,
+    Gt,
+    Le,
+    Ge,
+}
+impl Comparison {
+    /// The operator in SQL.
+    pub fn sql_op(&self) -> &'static str {
+        match self {
+            Comparison::Eq => "=",
+            Comparison::Ne => "<>",
+            Comparison::Lt => "<",
+            Comparison::Gt => ">",
+            Comparison::Le => "<=",
+            Comparison::Ge => ">=",
+        }
+    }
+}
+
+/// A function of a timestamp field whose value is of type `R`, from
+/// [FieldExpr::extract] or [FieldExpr::date_trunc]. It may be compared
+/// with values in filters, and objects may be grouped by it with
+/// [Query::group_by][super::Query::group_by].
+#[derive(Clone, Debug)]
+pub struct DateFieldExpr<R> {
+    expr: DateExpr,
+    phantom: PhantomData<R>,
+}
+
+macro_rules! date_op {
+    ($func_name:ident, $cmp:ident) => {
+        pub fn $func_name<U>(&self, val: &U) -> BoolExpr
+        where
+            R: DataOrd<U>,
+            U: ToSql,
+        {
+            BoolExpr::Date(self.expr.clone(), Comparison::$cmp, val.to_sql())
+        }
+    };
+}
+
+impl<R> DateFieldExpr<R> {
+    #[cfg_attr(not(feature = "datetime"), allow(dead_code))]
+    fn new(column: &'static str, function: DateFunction) -> Self {
+        DateFieldExpr {
+            expr: DateExpr { column, function },
+            phantom: PhantomData,
+        }
+    }
+
+    pub fn expr(&self) -> &DateExpr {
+        &self.expr
+    }
+
+    date_op!(eq, Eq);
+    date_op!(ne, Ne);
+    date_op!(lt, Lt);
+    date_op!(gt, Gt);
+    date_op!(le, Le);
+    date_op!(ge, Ge);
+
+    /// Is between `low` and `high`, inclusive.
+    pub fn between<U>(&self, low: &U, high: &U) -> BoolExpr
+    where
+        R: DataOrd<U>,
+        U: ToSql,
+    {
+        BoolExpr::And(Box::new(self.ge(low)), Box::new(self.le(high)))
+    }
+}
+
+impl<R: FieldType + Into<SqlVal>> GroupKeys for DateFieldExpr<R> {
+    type Output = R;
+    fn columns(&self) -> Vec<Column> {
+        vec![Column::new(self.expr.column, R::SQLTYPE).with_date_function(self.expr.function)]
+    }
+    fn read(values: &mut dyn Iterator<Item = SqlVal>) -> Result<R> {
+        R::from_sql(values.next().ok_or_else(missing_value)?)
+    }
+}
+
+#[cfg(feature = "datetime")]
+macro_rules! timestamp_fns {
+    ($ty:ty, $part:ty) => {
+        impl FieldExpr<$ty> {
+            /// The `part` of the time, such as its year.
+            pub fn extract(&self, part: DatePart) -> DateFieldExpr<$part> {
+                DateFieldExpr::new(self.name(), DateFunction::Extract(part))
+            }
+            /// The time at the start of the `part` containing it, such
+            /// as the start of its month.
+            pub fn date_trunc(&self, part: DatePart) -> DateFieldExpr<$ty> {
+                DateFieldExpr::new(self.name(), DateFunction::Trunc(part))
+            }
+        }
+    };
+}
+
+#[cfg(feature = "datetime")]
+timestamp_fns!(NaiveDateTime, i32);
+#[cfg(feature = "datetime")]
+timestamp_fns!(Option<NaiveDateTime>, Option<i32>);
@@ -0,0 +1,126 @@
+//! Filters on columns named at runtime, see [Expr::col], for when the
+//! conditions are not known until then, as on a search screen.
+
+use super::{BoolExpr, Expr};
+use crate::{DataResult, Error, Result, SqlType, SqlVal};
+
+impl Expr {
+    /// The column `name` of the model `T`, for building a filter at
+    /// runtime. Fails with [Error::NoSuchColumn] if `T` has no such
+    /// column. For example
+    ///
+    /// ```ignore
+    /// let expr = Expr::col::<Post>("title")?
+    ///     .eq("Sir Charles")?
+    ///     .and(Expr::col::<Post>("likes")?.gt(10)?);
+    /// ```
+    ///
+    /// Values compared with the column must be of its type, as with
+    /// the `filter!` macro, checked as the condition is built.
+    pub fn col<T: DataResult>(name: &str) -> Result<DynColumn> {
+        <T::DBO as DataResult>::COLUMNS
+            .iter()
+            .find(|c| c.name() == name)
+            .map(|c| DynColumn {
+                name: c.name(),
+                ty: c.ty().clone(),
+            })
+            .ok_or_else(|| Error::NoSuchColumn(name.to_string()))
+    }
+}
+
+/// A column named at runtime, from [Expr::col]. Its conditions take
+/// Rust values or [SqlVal]s, and fail with [Error::CannotConvertSqlVal]
+/// if given a value of another type. Null values are allowed, as the
+/// column may be nullable.
+#[derive(Clone, Debug)]
+pub struct DynColumn {
+    name: &'static str,
+    ty: SqlType,
+}
+
+macro_rules! dyn_op {
+    ($func_name:ident, $cond:ident) => {
+        pub fn $func_name(&self, val: impl Into<SqlVal>) -> Result<BoolExpr> {
+            Ok(BoolExpr::$cond(self.name, Expr::Val(self.check(val)?)))
+        }
+    };
+}
+
+impl DynColumn {
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    dyn_op!(eq, Eq);
+    dyn_op!(ne, Ne);
+    dyn_op!(lt, Lt);
+    dyn_op!(gt, Gt);
+    dyn_op!(le, Le);
+    dyn_op!(ge, Ge);
+    dyn_op!(like, Like);
+    dyn_op!(ilike, ILike);
+    dyn_op!(eq_ignore_case, EqIgnoreCase);
+
+    /// Is equal to one of `vals`. With no values, matches nothing.
+    pub fn is_in<I>(&self, vals: I) -> Result<BoolExpr>
+    where
+        I: IntoIterator,
+        I::Item: Into<SqlVal>,
+    {
+        let vals = vals
+            .into_iter()
+            .map(|v| self.check(v))
+            .collect::<Result<Vec<SqlVal>>>()?;
+        Ok(BoolExpr::In(self.name, vals))
+    }
+
+    /// Is null.
+    pub fn is_null(&self) -> BoolExpr {
+        BoolExpr::Eq(self.name, Expr::Val(SqlVal::Null))
+    }
+
+    /// Is not null.
+    pub fn is_not_null(&self) -> BoolExpr {
+        BoolExpr::Ne(self.name, Expr::Val(SqlVal::Null))
+    }
+
+    fn check(&self, val: impl Into<SqlVal>) -> Result<SqlVal> {
+        let val = val.into();
+        if val.is_compatible(&self.ty, true) {
+            Ok(val)
+        } else {
+            Err(Error::CannotConvertSqlVal(self.ty.clone(), val))
+        }
+    }
+}
+
+impl BoolExpr {
+    /// True if both `self` and `other` are.
+    pub fn and(self, other: BoolExpr) -> BoolExpr {
+        BoolExpr::And(Box::new(self), Box::new(other))
+    }
+
+    /// True if either `self` or `other` is.
+    pub fn or(self, other: BoolExpr) -> BoolExpr {
+        BoolExpr::Or(Box::new(self), Box::new(other))
+    }
+
+    /// True if all of `exprs` are, or if there are none, as for
+    /// combining the optional conditions of a search.
+    pub fn all(exprs: impl IntoIterator<Item = BoolExpr>) -> BoolExpr {
+        let exprs: Vec<BoolExpr> = exprs.into_iter().collect();
+        if exprs.is_empty() {
+            BoolExpr::True
+        } else {
+            BoolExpr::AllOf(exprs)
+        }
+    }
+}
+
+impl std::ops::Not for BoolExpr {
+    type Output = BoolExpr;
+    fn not(self) -> BoolExpr {
+        BoolExpr::Not(Box::new(self))
+    }
+}
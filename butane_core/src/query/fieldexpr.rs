@@ -1,7 +1,7 @@
 //! Not expected to be used directly.
 
 use crate::fkey::ForeignKey;
-use crate::query::{BoolExpr, Column, Expr, Join};
+use crate::query::{Assignment, BoolExpr, Column, Expr, Join};
 use crate::sqlval::{FieldType, SqlVal, ToSql};
 use crate::DataObject;
 use std::borrow::{Borrow, Cow};
@@ -67,12 +67,115 @@ where
     binary_op!(le, DataOrd<U>, Le);
     binary_op!(ge, DataOrd<U>, Ge);
 
+    /// Is between `low` and `high`, inclusive.
+    pub fn between<U>(&self, low: &U, high: &U) -> BoolExpr
+    where
+        T: DataOrd<U>,
+        U: ToSql,
+    {
+        BoolExpr::And(Box::new(self.ge(low)), Box::new(self.le(high)))
+    }
+
     pub fn like<U>(&self, val: U) -> BoolExpr
     where
         U: ToSql,
     {
         BoolExpr::Like(self.name, Expr::Val(val.to_sql()))
     }
+
+    /// Sets the field to `val`, in [Query::update][crate::query::Query::update].
+    pub fn set<U>(&self, val: U) -> Assignment
+    where
+        U: Into<T>,
+    {
+        Assignment {
+            column: self.name,
+            value: val.into().into(),
+        }
+    }
+
+    /// Is equal to one of `vals`. With no values, matches nothing.
+    pub fn is_in<I>(&self, vals: I) -> BoolExpr
+    where
+        I: IntoIterator,
+        I::Item: ToSql,
+        T: PartialEq<I::Item>,
+    {
+        BoolExpr::In(self.name, vals.into_iter().map(|v| v.to_sql()).collect())
+    }
+}
+
+impl<T> FieldExpr<Option<T>>
+where
+    Option<T>: Into<SqlVal>,
+{
+    /// Is null (`None`).
+    pub fn is_null(&self) -> BoolExpr {
+        BoolExpr::Eq(self.name, Expr::Val(SqlVal::Null))
+    }
+    /// Is not null (`Some`).
+    pub fn is_not_null(&self) -> BoolExpr {
+        BoolExpr::Ne(self.name, Expr::Val(SqlVal::Null))
+    }
+}
+
+macro_rules! string_ops {
+    ($ty:ty) => {
+        impl FieldExpr<$ty> {
+            /// Matches a SQL LIKE pattern, ignoring case.
+            pub fn ilike<U>(&self, val: U) -> BoolExpr
+            where
+                U: ToSql,
+            {
+                BoolExpr::ILike(self.name, Expr::Val(val.to_sql()))
+            }
+            /// Is equal to `val`, ignoring case.
+            pub fn eq_ignore_case(&self, val: impl AsRef<str>) -> BoolExpr {
+                BoolExpr::EqIgnoreCase(self.name, Expr::Val(val.as_ref().to_sql()))
+            }
+            /// Starts with `prefix`, in which wildcards are matched literally.
+            pub fn starts_with(&self, prefix: impl AsRef<str>) -> BoolExpr {
+                self.like(format!("{}%", escape_like(prefix.as_ref())))
+            }
+            /// Ends with `suffix`, in which wildcards are matched literally.
+            pub fn ends_with(&self, suffix: impl AsRef<str>) -> BoolExpr {
+                self.like(format!("%{}", escape_like(suffix.as_ref())))
+            }
+            /// Contains `substring`, in which wildcards are matched literally.
+            pub fn contains(&self, substring: impl AsRef<str>) -> BoolExpr {
+                self.like(format!("%{}%", escape_like(substring.as_ref())))
+            }
+        }
+        impl<L: AsRef<str>> ContainsLiteral<L> for FieldExpr<$ty> {
+            fn contains_literal(&self, lit: L) -> BoolExpr {
+                self.contains(lit)
+            }
+        }
+    };
+}
+
+string_ops!(String);
+string_ops!(Option<String>);
+
+/// Escapes the LIKE wildcards `%` and `_`, and the escape character
+/// `\` itself, so that `s` is matched literally as part of a pattern
+/// for [BoolExpr::Like].
+pub fn escape_like(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        if matches!(c, '\\' | '%' | '_') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Used to implement `contains` with a literal in the `query!` and
+/// `filter!` macros, which for a [ManyFieldExpr] is the primary key of
+/// a related object and for a string field a substring.
+pub trait ContainsLiteral<L> {
+    fn contains_literal(&self, lit: L) -> BoolExpr;
 }
 impl<F: DataObject> FieldExpr<ForeignKey<F>> {
     pub fn subfilter(&self, q: BoolExpr) -> BoolExpr {
@@ -89,6 +192,52 @@ impl<F: DataObject> FieldExpr<ForeignKey<F>> {
             crate::query::Expr::Val(pk.into_sql()),
         ))
     }
+    /// True if an object of `O`, of which this foreign key is a
+    /// field, refers to the object filtered and matches `q`. Used for
+    /// `exists` in the `filter!` macro.
+    pub fn exists<O: DataObject>(&self, q: BoolExpr) -> BoolExpr {
+        BoolExpr::Exists {
+            tbl: Cow::Borrowed(F::TABLE),
+            col: F::PKCOL,
+            tbl2: Cow::Borrowed(O::TABLE),
+            tbl2_col: self.name,
+            expr: Box::new(q),
+        }
+    }
+    pub fn fields(&self) -> F::Fields {
+        F::Fields::default()
+    }
+}
+impl<F: DataObject> FieldExpr<Option<ForeignKey<F>>>
+where
+    Option<ForeignKey<F>>: Into<SqlVal>,
+{
+    pub fn subfilter(&self, q: BoolExpr) -> BoolExpr {
+        BoolExpr::Subquery {
+            col: self.name,
+            tbl2: Cow::Borrowed(F::TABLE),
+            tbl2_col: F::PKCOL,
+            expr: Box::new(q),
+        }
+    }
+    pub fn subfilterpk(&self, pk: F::PKType) -> BoolExpr {
+        self.subfilter(BoolExpr::Eq(
+            F::PKCOL,
+            crate::query::Expr::Val(pk.into_sql()),
+        ))
+    }
+    /// True if an object of `O`, of which this foreign key is a
+    /// field, refers to the object filtered and matches `q`. Used for
+    /// `exists` in the `filter!` macro.
+    pub fn exists<O: DataObject>(&self, q: BoolExpr) -> BoolExpr {
+        BoolExpr::Exists {
+            tbl: Cow::Borrowed(F::TABLE),
+            col: F::PKCOL,
+            tbl2: Cow::Borrowed(O::TABLE),
+            tbl2_col: self.name,
+            expr: Box::new(q),
+        }
+    }
     pub fn fields(&self) -> F::Fields {
         F::Fields::default()
     }
@@ -139,3 +288,13 @@ where
         T::Fields::default()
     }
 }
+impl<O, T, L> ContainsLiteral<L> for ManyFieldExpr<O, T>
+where
+    O: DataObject,
+    T: DataObject,
+    L: Borrow<<T::PKType as FieldType>::RefType>,
+{
+    fn contains_literal(&self, lit: L) -> BoolExpr {
+        self.containspk(lit)
+    }
+}
@@ -0,0 +1,150 @@
+//! Conditions on the values within [Json] fields, as
+//! `data["address"]["city"] == "Paris"` in the `filter!` macro.
+
+use super::{BoolExpr, ContainsLiteral, FieldExpr};
+use crate::json::Json;
+use crate::SqlVal;
+use serde_json::Value;
+
+/// A step in the path to a value within a JSON column: the field of
+/// an object with the given name, or the element of an array at the
+/// given index.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum JsonKey {
+    Field(String),
+    Index(usize),
+}
+impl From<&str> for JsonKey {
+    fn from(name: &str) -> Self {
+        JsonKey::Field(name.to_string())
+    }
+}
+impl From<String> for JsonKey {
+    fn from(name: String) -> Self {
+        JsonKey::Field(name)
+    }
+}
+impl From<usize> for JsonKey {
+    fn from(index: usize) -> Self {
+        JsonKey::Index(index)
+    }
+}
+
+/// The value at a path within a JSON column, from
+/// [FieldExpr::get][FieldExpr<Json<T>>::get].
+#[derive(Clone, Debug)]
+pub struct JsonPathExpr {
+    col: &'static str,
+    path: Vec<JsonKey>,
+}
+impl JsonPathExpr {
+    /// The value at `key` within this one.
+    pub fn get(mut self, key: impl Into<JsonKey>) -> JsonPathExpr {
+        self.path.push(key.into());
+        self
+    }
+    /// Is equal to `val`. False if there is no value at the path.
+    pub fn eq<U>(&self, val: &U) -> BoolExpr
+    where
+        U: Clone + Into<Value>,
+    {
+        BoolExpr::JsonEq {
+            col: self.col,
+            path: self.path.clone(),
+            value: val.clone().into(),
+        }
+    }
+    /// Is not equal to `val`. False if there is no value at the path.
+    pub fn ne<U>(&self, val: &U) -> BoolExpr
+    where
+        U: Clone + Into<Value>,
+    {
+        !self.eq(val)
+    }
+    /// Contains `val`, as for [BoolExpr::JsonContains].
+    pub fn contains(&self, val: impl Into<Value>) -> BoolExpr {
+        BoolExpr::JsonContains {
+            col: self.col,
+            path: self.path.clone(),
+            value: val.into(),
+        }
+    }
+}
+impl<L: Into<Value>> ContainsLiteral<L> for JsonPathExpr {
+    fn contains_literal(&self, lit: L) -> BoolExpr {
+        self.contains(lit)
+    }
+}
+
+impl<T> FieldExpr<Json<T>>
+where
+    Json<T>: Into<SqlVal>,
+{
+    /// The value at `key` within the JSON, a field name or an array
+    /// index.
+    pub fn get(&self, key: impl Into<JsonKey>) -> JsonPathExpr {
+        JsonPathExpr {
+            col: self.name(),
+            path: vec![key.into()],
+        }
+    }
+    /// Contains `val`, as for [BoolExpr::JsonContains].
+    pub fn contains(&self, val: impl Into<Value>) -> BoolExpr {
+        BoolExpr::JsonContains {
+            col: self.name(),
+            path: Vec::new(),
+            value: val.into(),
+        }
+    }
+}
+impl<T, L: Into<Value>> ContainsLiteral<L> for FieldExpr<Json<T>>
+where
+    Json<T>: Into<SqlVal>,
+{
+    fn contains_literal(&self, lit: L) -> BoolExpr {
+        self.contains(lit)
+    }
+}
+
+impl<T> FieldExpr<Option<Json<T>>>
+where
+    Option<Json<T>>: Into<SqlVal>,
+{
+    /// The value at `key` within the JSON, a field name or an array
+    /// index.
+    pub fn get(&self, key: impl Into<JsonKey>) -> JsonPathExpr {
+        JsonPathExpr {
+            col: self.name(),
+            path: vec![key.into()],
+        }
+    }
+    /// Contains `val`, as for [BoolExpr::JsonContains].
+    pub fn contains(&self, val: impl Into<Value>) -> BoolExpr {
+        BoolExpr::JsonContains {
+            col: self.name(),
+            path: Vec::new(),
+            value: val.into(),
+        }
+    }
+}
+impl<T, L: Into<Value>> ContainsLiteral<L> for FieldExpr<Option<Json<T>>>
+where
+    Option<Json<T>>: Into<SqlVal>,
+{
+    fn contains_literal(&self, lit: L) -> BoolExpr {
+        self.contains(lit)
+    }
+}
+
+/// The path to a value within JSON, as written for the JSON functions
+/// of SQLite and MySQL, e.g. `$."address"."lines"[0]`.
+pub(crate) fn json_path(path: &[JsonKey]) -> String {
+    let mut s = String::from("$");
+    for key in path {
+        match key {
+            JsonKey::Field(name) => s.push_str(&format!(".\"{}\"", name)),
+            JsonKey::Index(index) => s.push_str(&format!("[{}]", index)),
+        }
+    }
+    s
+}
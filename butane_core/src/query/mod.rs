@@ -2,15 +2,33 @@
 //! the `query!`, `filter!`, and `find!` macros instead of using this
 //! module directly.
 
-use crate::db::{BackendRows, ConnectionMethods, QueryResult};
-use crate::{DataResult, Result, SqlVal};
+#[cfg(feature = "async")]
+use crate::db::nonblocking::AsyncConnectionMethods;
+use crate::db::{BackendRows, ConnectionMethods, OwnedRow, QueryPlan, QueryResult, RawQueryResult};
+use crate::sqlval::FieldType;
+use crate::{DataObject, DataResult, Result, SqlVal, ToSql};
 use fallible_iterator::FallibleIterator;
 use std::borrow::Cow;
 use std::marker::PhantomData;
 
+mod aggregate;
+mod datetime;
+mod dynamic;
 mod fieldexpr;
+mod json;
+mod paginate;
 
-pub use fieldexpr::{DataOrd, FieldExpr, ManyFieldExpr};
+use aggregate::RowReader;
+pub use aggregate::{
+    avg, count, max, min, sum, Aggregate, AggregateExpr, AggregateFunction, Aggregates, FromRow,
+    GroupKeys, Having, RowField, Summable,
+};
+pub use datetime::{Comparison, DateExpr, DateFieldExpr, DateFunction, DatePart};
+pub use dynamic::DynColumn;
+pub use fieldexpr::{escape_like, ContainsLiteral, DataOrd, FieldExpr, ManyFieldExpr};
+pub(crate) use json::json_path;
+pub use json::{JsonKey, JsonPathExpr};
+pub use paginate::{Cursor, Page, Paginator};
 
 type TblName = Cow<'static, str>;
 
@@ -25,6 +43,8 @@ pub enum Expr {
     Placeholder,
     /// A boolean condition.
     Condition(Box<BoolExpr>),
+    /// A function of a timestamp column.
+    Date(DateExpr),
 }
 
 /// Abstract representation of a boolean expression.
@@ -37,7 +57,13 @@ pub enum BoolExpr {
     Gt(&'static str, Expr),
     Le(&'static str, Expr),
     Ge(&'static str, Expr),
+    /// True if the value of the column matches a SQL LIKE pattern, in
+    /// which `\` escapes the wildcards `%` and `_` on every backend.
     Like(&'static str, Expr),
+    /// As [Like](Self::Like), ignoring case.
+    ILike(&'static str, Expr),
+    /// As [Eq](Self::Eq) for text, ignoring case.
+    EqIgnoreCase(&'static str, Expr),
     AllOf(Vec<BoolExpr>),
     And(Box<BoolExpr>, Box<BoolExpr>),
     Or(Box<BoolExpr>, Box<BoolExpr>),
@@ -51,6 +77,8 @@ pub enum BoolExpr {
         tbl2_col: &'static str,
         expr: Box<BoolExpr>,
     },
+    /// True if the value of the column is one of the values. An empty
+    /// list matches nothing.
     In(&'static str, Vec<SqlVal>),
     /// Expression which is true if the value of `col` is present in
     /// the set of values of `col2` where `expr` evaluated on a row
@@ -62,6 +90,82 @@ pub enum BoolExpr {
         joins: Vec<Join>,
         expr: Box<BoolExpr>,
     },
+    /// A fragment of SQL, for conditions which cannot otherwise be
+    /// expressed. Each `?` in `sql` is a placeholder for the next of
+    /// `params`; `??` is a literal `?`. Constructed with
+    /// [raw](Self::raw).
+    Raw {
+        sql: String,
+        params: Vec<SqlVal>,
+    },
+    /// Expression which is true if there is a row in `tbl2` for which
+    /// `expr` is true and the value of `tbl2_col` is equal to the value
+    /// of `col` in the row of `tbl` being filtered (a correlated
+    /// `EXISTS` subquery).
+    Exists {
+        tbl: TblName,
+        col: &'static str,
+        tbl2: TblName,
+        tbl2_col: &'static str,
+        expr: Box<BoolExpr>,
+    },
+    /// True if the value of a function of a timestamp column compares
+    /// with the value as given.
+    Date(DateExpr, Comparison, SqlVal),
+    /// True if the value at `path` within the JSON in `col` is equal
+    /// to `value`.
+    JsonEq {
+        col: &'static str,
+        path: Vec<JsonKey>,
+        value: serde_json::Value,
+    },
+    /// True if the value at `path` within the JSON in `col`, the whole
+    /// of it if `path` is empty, contains `value`: as an object with
+    /// each of its fields, containing their values, or an array with
+    /// an element containing each of its elements, or otherwise by
+    /// being equal to it.
+    JsonContains {
+        col: &'static str,
+        path: Vec<JsonKey>,
+        value: serde_json::Value,
+    },
+}
+
+impl BoolExpr {
+    /// The SQL condition `sql`, in which each `?` is a placeholder for
+    /// the next of `params` and `??` is a literal `?`, as for the
+    /// Postgres `jsonb` operators. Column names are not checked or
+    /// quoted, and the condition is passed to the backend as written.
+    ///
+    /// ```ignore
+    /// let expr = BoolExpr::raw("tags @> ?::jsonb", [r#"["cats"]"#]);
+    /// ```
+    ///
+    /// # Panics
+    /// If the number of placeholders differs from that of `params`.
+    pub fn raw<P: ToSql>(sql: impl Into<String>, params: impl IntoIterator<Item = P>) -> Self {
+        let sql = sql.into();
+        let params: Vec<SqlVal> = params.into_iter().map(|p| p.to_sql()).collect();
+        let placeholders = crate::db::helper::sql_raw_parts(&sql).len() - 1;
+        assert_eq!(
+            placeholders,
+            params.len(),
+            "raw SQL has {} placeholders but {} parameters",
+            placeholders,
+            params.len()
+        );
+        BoolExpr::Raw { sql, params }
+    }
+}
+
+/// Which rows of a query are left out as duplicates.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Distinct {
+    /// Rows equal in all of their columns (`DISTINCT`).
+    Rows,
+    /// Rows equal in the given columns, of which only the first in
+    /// the query's order is kept (`DISTINCT ON`), on Postgres only.
+    On(Vec<&'static str>),
 }
 
 /// Represents the direction of a sort.
@@ -71,11 +175,31 @@ pub enum OrderDirection {
     Descending,
 }
 
+/// Where null values are sorted, if not where the backend puts them
+/// by default.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum NullsOrder {
+    First,
+    Last,
+}
+
 /// Represents a sorting term (ORDER BY in SQL).
 #[derive(Clone)]
 pub struct Order {
     pub direction: OrderDirection,
     pub column: &'static str,
+    /// Where nulls are sorted, or `None` for the backend's default:
+    /// as the largest values on Postgres, and the smallest on SQLite
+    /// and MySQL.
+    pub nulls: Option<NullsOrder>,
+}
+
+/// A column set to a value by [Query::update]. Usually made with the
+/// `set!` macro or [FieldExpr::set].
+#[derive(Clone, Debug, PartialEq)]
+pub struct Assignment {
+    pub column: &'static str,
+    pub value: SqlVal,
 }
 
 #[derive(Clone)]
@@ -112,6 +236,168 @@ impl Column {
     }
 }
 
+/// Objects read from the database as they are iterated over, from
+/// [Query::stream]. Iteration ends after the first error.
+pub struct QueryStream<'a, T> {
+    rows: RawQueryResult<'a>,
+    failed: bool,
+    phantom: PhantomData<T>,
+}
+impl<T: DataResult> Iterator for QueryStream<'_, T> {
+    type Item = Result<T>;
+    fn next(&mut self) -> Option<Result<T>> {
+        if self.failed {
+            return None;
+        }
+        let next = match self.rows.next() {
+            Ok(row) => row.map(|row| T::from_row(row)),
+            Err(e) => Some(Err(e)),
+        };
+        self.failed = matches!(next, Some(Err(_)));
+        next
+    }
+}
+
+/// A query whose objects are grouped by the values of their fields
+/// `K`, from [Query::group_by].
+#[derive(Clone)]
+pub struct GroupedQuery<T: DataResult, K> {
+    query: Query<T>,
+    keys: K,
+    having: Option<Having>,
+}
+impl<T: DataResult, K: GroupKeys> GroupedQuery<T, K> {
+    /// Restricts the groups, in addition to any condition already
+    /// given, to those whose aggregates satisfy `having`. Returns
+    /// `self` as this method is expected to be chained.
+    pub fn having(mut self, having: Having) -> Self {
+        self.having = Some(match self.having.take() {
+            Some(prev) => prev.and(having),
+            None => having,
+        });
+        self
+    }
+
+    /// The number of objects in each group, with the values of the
+    /// group's fields.
+    pub fn count(self, conn: &impl ConnectionMethods) -> Result<Vec<(K::Output, i64)>> {
+        self.aggregate(conn, count())
+    }
+
+    /// The values of `aggregates` over each group, with the values of
+    /// the group's fields, in the order of those values. The limit,
+    /// offset and order of the query are ignored.
+    pub fn aggregate<A: Aggregates>(
+        self,
+        conn: &impl ConnectionMethods,
+        aggregates: A,
+    ) -> Result<Vec<(K::Output, A::Output)>> {
+        conn.aggregate(
+            &self.query.table,
+            &self.keys.columns(),
+            &aggregates.exprs(),
+            self.query.filter,
+            self.having,
+        )?
+        .into_iter()
+        .map(|row| {
+            let mut values = row.into_iter();
+            Ok((K::read(&mut values)?, A::read(&mut values)?))
+        })
+        .collect()
+    }
+
+    /// Like [aggregate](Self::aggregate), but reads each group into
+    /// `R`, whose fields are those grouped by and `aggregates`, matched
+    /// by name. For example
+    ///
+    /// ```ignore
+    /// #[derive(FromRow)]
+    /// struct BlogLikes {
+    ///     blog: ForeignKey<Blog>,
+    ///     posts: i64,
+    ///     sum_likes: Option<i64>,
+    /// }
+    /// let likes: Vec<BlogLikes> = Post::query()
+    ///     .group_by(Post::fields().blog())
+    ///     .aggregate_into(&conn, (count().named("posts"), sum(Post::fields().likes())))?;
+    /// ```
+    ///
+    /// Fails before running the query if a field of `R` has no column
+    /// of its name and type, see [FromRow].
+    pub fn aggregate_into<R: FromRow, A: Aggregates>(
+        self,
+        conn: &impl ConnectionMethods,
+        aggregates: A,
+    ) -> Result<Vec<R>> {
+        let columns = self.keys.columns();
+        let reader = RowReader::new::<R>(&columns, aggregates.exprs(), aggregates.names())?;
+        conn.aggregate(
+            &self.query.table,
+            &columns,
+            &aggregates.exprs(),
+            self.query.filter,
+            self.having,
+        )?
+        .into_iter()
+        .map(|row| reader.read(row))
+        .collect()
+    }
+}
+
+/// A query loading only the fields `K` of its objects, from
+/// [Query::select].
+#[derive(Clone)]
+pub struct SelectQuery<T: DataResult, K> {
+    query: Query<T>,
+    keys: K,
+}
+impl<T: DataResult, K: GroupKeys> SelectQuery<T, K> {
+    /// Executes the query against `conn`, returning the values of the
+    /// fields of each object matched.
+    pub fn load(self, conn: &impl ConnectionMethods) -> Result<Vec<K::Output>> {
+        let columns = self.keys.columns();
+        let limit = self.query.limit;
+        let mut rows = self.query.rows(conn, &columns, limit)?;
+        let mut results = Vec::new();
+        while let Some(row) = rows.next()? {
+            let row = OwnedRow::from_row(row, &columns)?;
+            results.push(K::read(&mut row.0.into_iter())?);
+        }
+        Ok(results)
+    }
+
+    /// Executes the query against `conn` and returns the values of the
+    /// fields of the first object matched (if any), after the offset
+    /// and in the order given.
+    pub fn load_first(self, conn: &impl ConnectionMethods) -> Result<Option<K::Output>> {
+        let columns = self.keys.columns();
+        let mut rows = self.query.rows(conn, &columns, Some(1))?;
+        match rows.next()? {
+            Some(row) => {
+                let row = OwnedRow::from_row(row, &columns)?;
+                Ok(Some(K::read(&mut row.0.into_iter())?))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Like [load](Self::load), but reads the fields of each object
+    /// into `R`, matched by name. Fails before running the query if a
+    /// field of `R` has no column of its name and type, see [FromRow].
+    pub fn load_into<R: FromRow>(self, conn: &impl ConnectionMethods) -> Result<Vec<R>> {
+        let columns = self.keys.columns();
+        let reader = RowReader::new::<R>(&columns, Vec::new(), Vec::new())?;
+        let limit = self.query.limit;
+        let mut rows = self.query.rows(conn, &columns, limit)?;
+        let mut results = Vec::new();
+        while let Some(row) = rows.next()? {
+            results.push(reader.read(OwnedRow::from_row(row, &columns)?.0)?);
+        }
+        Ok(results)
+    }
+}
+
 /// Representation of a database query.
 #[derive(Clone)]
 pub struct Query<T: DataResult> {
@@ -120,6 +406,7 @@ pub struct Query<T: DataResult> {
     limit: Option<i32>,
     offset: Option<i32>,
     sort: Vec<Order>,
+    distinct: Option<Distinct>,
     phantom: PhantomData<T>,
 }
 impl<T: DataResult> Query<T> {
@@ -133,6 +420,7 @@ impl<T: DataResult> Query<T> {
             limit: None,
             offset: None,
             sort: Vec::new(),
+            distinct: None,
             phantom: PhantomData,
         }
     }
@@ -145,6 +433,125 @@ impl<T: DataResult> Query<T> {
         self
     }
 
+    /// Restricts the query, in addition to any filter already given, to
+    /// objects for which `related` matches at least one object whose
+    /// `related_col` is equal to the object's `col`. For example, the
+    /// blogs with a published post are
+    ///
+    /// ```ignore
+    /// Blog::query().where_exists("id", query!(Post, published == true), "blog")
+    /// ```
+    ///
+    /// The limit, offset and order of `related` are ignored. Returns
+    /// `self` as this method is expected to be chained.
+    pub fn where_exists<U: DataResult>(
+        self,
+        col: &'static str,
+        related: Query<U>,
+        related_col: &'static str,
+    ) -> Query<T> {
+        let exists = self.exists(col, related, related_col);
+        self.and_filter(exists)
+    }
+
+    /// Restricts the query, in addition to any filter already given, to
+    /// objects for which `related` matches no object whose
+    /// `related_col` is equal to the object's `col`. For example, the
+    /// blogs without any posts are
+    ///
+    /// ```ignore
+    /// Blog::query().where_not_exists("id", Post::query(), "blog")
+    /// ```
+    ///
+    /// The limit, offset and order of `related` are ignored. Returns
+    /// `self` as this method is expected to be chained.
+    pub fn where_not_exists<U: DataResult>(
+        self,
+        col: &'static str,
+        related: Query<U>,
+        related_col: &'static str,
+    ) -> Query<T> {
+        let exists = self.exists(col, related, related_col);
+        self.and_filter(BoolExpr::Not(Box::new(exists)))
+    }
+
+    /// Restricts the query, in addition to any filter already given, to
+    /// objects whose `col` is among the values of `related_col` of the
+    /// objects matched by `related`, selected by a nested query. For
+    /// example, the blogs with a post with over 100 likes are
+    ///
+    /// ```ignore
+    /// Blog::query().where_in("id", query!(Post, likes > 100), "blog")
+    /// ```
+    ///
+    /// The limit, offset and order of `related` are ignored. Returns
+    /// `self` as this method is expected to be chained.
+    pub fn where_in<U: DataResult>(
+        self,
+        col: &'static str,
+        related: Query<U>,
+        related_col: &'static str,
+    ) -> Query<T> {
+        let expr = related.filter.unwrap_or(BoolExpr::True);
+        self.and_filter(BoolExpr::Subquery {
+            col,
+            tbl2: related.table,
+            tbl2_col: related_col,
+            expr: Box::new(expr),
+        })
+    }
+
+    /// Restricts the query, in addition to any filter already given, to
+    /// objects whose `col` is not among the values of `related_col` of
+    /// the objects matched by `related`, as the opposite of
+    /// [where_in](Self::where_in). Null values of `related_col` are
+    /// left out of those compared, as in SQL a single null would
+    /// otherwise exclude every object.
+    ///
+    /// The limit, offset and order of `related` are ignored. Returns
+    /// `self` as this method is expected to be chained.
+    pub fn where_not_in<U: DataResult>(
+        self,
+        col: &'static str,
+        related: Query<U>,
+        related_col: &'static str,
+    ) -> Query<T> {
+        let not_null = BoolExpr::Ne(related_col, Expr::Val(SqlVal::Null));
+        let expr = match related.filter {
+            Some(filter) => BoolExpr::And(Box::new(not_null), Box::new(filter)),
+            None => not_null,
+        };
+        self.and_filter(BoolExpr::Not(Box::new(BoolExpr::Subquery {
+            col,
+            tbl2: related.table,
+            tbl2_col: related_col,
+            expr: Box::new(expr),
+        })))
+    }
+
+    fn exists<U: DataResult>(
+        &self,
+        col: &'static str,
+        related: Query<U>,
+        related_col: &'static str,
+    ) -> BoolExpr {
+        BoolExpr::Exists {
+            tbl: self.table.clone(),
+            col,
+            tbl2: related.table,
+            tbl2_col: related_col,
+            expr: Box::new(related.filter.unwrap_or(BoolExpr::True)),
+        }
+    }
+
+    fn and_filter(mut self, expr: BoolExpr) -> Query<T> {
+        self.filter = Some(match self.filter.take() {
+            Some(filter) => BoolExpr::And(Box::new(filter), Box::new(expr)),
+            None => expr,
+        });
+        self
+    }
+
     /// Limits the query to matching the first `lim` objects. Returns
     /// `self` as this method is expected to be chained.
     pub fn limit(mut self, lim: i32) -> Query<T> {
@@ -164,7 +571,76 @@ impl<T: DataResult> Query<T> {
     /// It is recommended to use the `colname!`
     /// macro to construct the column name in a typesafe manner.
     pub fn order(mut self, column: &'static str, direction: OrderDirection) -> Query<T> {
-        self.sort.push(Order { direction, column });
+        self.sort.push(Order {
+            direction,
+            column,
+            nulls: None,
+        });
+        self
+    }
+
+    /// Order the query results by `field`, as given by the `fields()`
+    /// of a model. Multiple calls to this method may be made, with
+    /// earlier calls taking precedence. For example, the most liked
+    /// posts first, then by title, are
+    ///
+    /// ```ignore
+    /// Post::query()
+    ///     .order_by(Post::fields().likes(), OrderDirection::Descending)
+    ///     .order_by(Post::fields().title(), OrderDirection::Ascending)
+    /// ```
+    pub fn order_by<U: Into<SqlVal>>(
+        self,
+        field: FieldExpr<U>,
+        direction: OrderDirection,
+    ) -> Query<T> {
+        self.order(field.name(), direction)
+    }
+
+    /// Leave out duplicate results, which are equal in all of the
+    /// columns loaded. Returns `self` as this method is expected to be
+    /// chained.
+    pub fn distinct(mut self) -> Query<T> {
+        self.distinct = Some(Distinct::Rows);
+        self
+    }
+
+    /// Leave out results with the same value of `field` as an earlier
+    /// result in the query's order, which must begin with `field`.
+    /// Multiple calls to this method may be made, for results with the
+    /// same values of all of the fields. Only Postgres supports this
+    /// (`DISTINCT ON`). For example, the most liked post of each blog is
+    ///
+    /// ```ignore
+    /// Post::query()
+    ///     .distinct_on(Post::fields().blog())
+    ///     .order_by(Post::fields().blog(), OrderDirection::Ascending)
+    ///     .order_by(Post::fields().likes(), OrderDirection::Descending)
+    /// ```
+    pub fn distinct_on<U: Into<SqlVal>>(mut self, field: FieldExpr<U>) -> Query<T> {
+        match &mut self.distinct {
+            Some(Distinct::On(columns)) => columns.push(field.name()),
+            _ => self.distinct = Some(Distinct::On(vec![field.name()])),
+        }
+        self
+    }
+
+    /// Sort null values of the last order given before the others.
+    /// Has no effect if no order has been given.
+    pub fn nulls_first(self) -> Query<T> {
+        self.nulls(NullsOrder::First)
+    }
+
+    /// Sort null values of the last order given after the others.
+    /// Has no effect if no order has been given.
+    pub fn nulls_last(self) -> Query<T> {
+        self.nulls(NullsOrder::Last)
+    }
+
+    fn nulls(mut self, nulls: NullsOrder) -> Query<T> {
+        if let Some(order) = self.sort.last_mut() {
+            order.nulls = Some(nulls);
+        }
         self
     }
 
@@ -178,27 +654,350 @@ impl<T: DataResult> Query<T> {
         self.order(column, OrderDirection::Descending)
     }
 
-    /// Executes the query against `conn` and returns the first result (if any).
+    /// Executes the query against `conn` and returns the first result
+    /// (if any), after the offset and in the order given.
     pub fn load_first(self, conn: &impl ConnectionMethods) -> Result<Option<T>> {
-        conn.query(&self.table, T::COLUMNS, self.filter, Some(1), None, None)?
+        self.rows(conn, T::COLUMNS, Some(1))?
             .mapped(T::from_row)
             .nth(0)
     }
 
     /// Executes the query against `conn`.
     pub fn load(self, conn: &impl ConnectionMethods) -> Result<QueryResult<T>> {
+        let limit = self.limit;
+        self.rows(conn, T::COLUMNS, limit)?
+            .mapped(T::from_row)
+            .collect()
+    }
+
+    /// Loads only `keys`, a field or a tuple of fields, of the objects
+    /// matched, rather than all of their columns. For example, the ids
+    /// and titles of the published posts are
+    ///
+    /// ```ignore
+    /// let posts: Vec<(i64, String)> = query!(Post, published == true)
+    ///     .select((Post::fields().id(), Post::fields().title()))
+    ///     .load(&conn)?;
+    /// ```
+    ///
+    /// To load a subset of the fields into a struct instead, see the
+    /// `dataresult` attribute, or [SelectQuery::load_into].
+    pub fn select<K: GroupKeys>(self, keys: K) -> SelectQuery<T, K> {
+        SelectQuery { query: self, keys }
+    }
+
+    /// The rows of `columns` of the objects matched, with `limit`.
+    fn rows<'c>(
+        self,
+        conn: &'c impl ConnectionMethods,
+        columns: &[crate::db::Column],
+        limit: Option<i32>,
+    ) -> Result<RawQueryResult<'c>> {
         let sort = if self.sort.is_empty() {
             None
         } else {
             Some(self.sort.as_slice())
         };
-        conn.query(&self.table, T::COLUMNS, self.filter, self.limit, self.offset, sort)?
-            .mapped(T::from_row)
-            .collect()
+        match &self.distinct {
+            Some(distinct) => conn.query_distinct(
+                &self.table,
+                columns,
+                distinct,
+                self.filter,
+                limit,
+                self.offset,
+                sort,
+            ),
+            None => conn.query(&self.table, columns, self.filter, limit, self.offset, sort),
+        }
+    }
+
+    /// The plan `conn` would use to run the query, as given by
+    /// `EXPLAIN QUERY PLAN` on SQLite and `EXPLAIN (FORMAT JSON)` on
+    /// Postgres. The query is not run, and whether it is distinct is
+    /// not taken into account.
+    pub fn explain(self, conn: &impl ConnectionMethods) -> Result<QueryPlan> {
+        self.explain_with(conn, false)
+    }
+
+    /// Like [explain](Self::explain), but on Postgres the query is run
+    /// with `EXPLAIN (ANALYZE, FORMAT JSON)`, giving the actual time
+    /// and rows of each step. SQLite gives only the plan, as for
+    /// `explain`.
+    pub fn explain_analyze(self, conn: &impl ConnectionMethods) -> Result<QueryPlan> {
+        self.explain_with(conn, true)
+    }
+
+    fn explain_with(self, conn: &impl ConnectionMethods, analyze: bool) -> Result<QueryPlan> {
+        let sort = if self.sort.is_empty() {
+            None
+        } else {
+            Some(self.sort.as_slice())
+        };
+        conn.explain(
+            &self.table,
+            T::COLUMNS,
+            self.filter,
+            self.limit,
+            self.offset,
+            sort,
+            analyze,
+        )
+    }
+
+    /// Executes the query against `conn`, reading the matching objects
+    /// as they are iterated over rather than all at once, for queries
+    /// matching more objects than fit comfortably in memory. On
+    /// Postgres the rows are fetched in batches through a cursor, in a
+    /// transaction begun for it unless `conn` is a transaction, which
+    /// is committed when the stream is dropped. Distinct results are
+    /// read all at once.
+    pub fn stream<'c>(self, conn: &'c impl ConnectionMethods) -> Result<QueryStream<'c, T>> {
+        let sort = if self.sort.is_empty() {
+            None
+        } else {
+            Some(self.sort.as_slice())
+        };
+        let rows = match &self.distinct {
+            Some(distinct) => conn.query_distinct(
+                &self.table,
+                T::COLUMNS,
+                distinct,
+                self.filter,
+                self.limit,
+                self.offset,
+                sort,
+            )?,
+            None => conn.query_stream(
+                &self.table,
+                T::COLUMNS,
+                self.filter,
+                self.limit,
+                self.offset,
+                sort,
+            )?,
+        };
+        Ok(QueryStream {
+            rows,
+            failed: false,
+            phantom: PhantomData,
+        })
+    }
+
+    /// The number of objects the query matches, counted by `conn`
+    /// without loading them. The limit, offset, order and whether the
+    /// query is distinct are ignored.
+    pub fn count(self, conn: &impl ConnectionMethods) -> Result<i64> {
+        self.aggregate(conn, count())
+    }
+
+    /// The values of `aggregates` over the objects the query matches,
+    /// computed by `conn` without loading them. `aggregates` is one
+    /// [Aggregate] or a tuple of them, for example
+    ///
+    /// ```ignore
+    /// let (posts, likes) = query!(Post, published == true)
+    ///     .aggregate(&conn, (count(), sum(Post::fields().likes())))?;
+    /// ```
+    ///
+    /// The limit, offset and order are ignored.
+    pub fn aggregate<A: Aggregates>(
+        self,
+        conn: &impl ConnectionMethods,
+        aggregates: A,
+    ) -> Result<A::Output> {
+        let rows = conn.aggregate(&self.table, &[], &aggregates.exprs(), self.filter, None)?;
+        let row = rows
+            .into_iter()
+            .next()
+            .ok_or_else(|| crate::Error::Internal("aggregate query returned no row".to_string()))?;
+        A::read(&mut row.into_iter())
+    }
+
+    /// Like [aggregate](Self::aggregate), but reads the values into
+    /// `R`, whose fields are matched to `aggregates` by name, see
+    /// [GroupedQuery::aggregate_into].
+    pub fn aggregate_into<R: FromRow, A: Aggregates>(
+        self,
+        conn: &impl ConnectionMethods,
+        aggregates: A,
+    ) -> Result<R> {
+        let reader = RowReader::new::<R>(&[], aggregates.exprs(), aggregates.names())?;
+        let rows = conn.aggregate(&self.table, &[], &aggregates.exprs(), self.filter, None)?;
+        let row = rows
+            .into_iter()
+            .next()
+            .ok_or_else(|| crate::Error::Internal("aggregate query returned no row".to_string()))?;
+        reader.read(row)
+    }
+
+    /// Pages of `size` objects matched by the query, in the order of
+    /// `key`, found with keyset pagination rather than an offset. For
+    /// example, to list posts by their publication time
+    ///
+    /// ```ignore
+    /// let pages = Post::query().paginate(Post::fields().pub_time(), OrderDirection::Descending, 20);
+    /// let page = pages.page(&conn, None)?;
+    /// // Later, with the cursor passed back as a string
+    /// let cursor = page.next.map(|c| c.encode());
+    /// let page = pages.page(&conn, Some(&Cursor::decode(&cursor.unwrap())?))?;
+    /// ```
+    ///
+    /// The offset and order of the query are replaced by those of the
+    /// pages. See [Paginator].
+    ///
+    /// # Panics
+    /// If `size` is not positive.
+    pub fn paginate<K: FieldType>(
+        self,
+        key: FieldExpr<K>,
+        direction: OrderDirection,
+        size: i32,
+    ) -> Paginator<T> {
+        assert!(size > 0, "page size must be positive");
+        Paginator::new::<K>(self, key.name(), direction, size)
+    }
+
+    /// Groups the objects the query matches by the values of `keys`,
+    /// a field or a tuple of fields, for aggregates of each group. For
+    /// example, the number of posts of each blog with more than one is
+    ///
+    /// ```ignore
+    /// let posts: Vec<(ForeignKey<Blog>, i64)> = Post::query()
+    ///     .group_by(Post::fields().blog())
+    ///     .having(count().gt(1))
+    ///     .count(&conn)?;
+    /// ```
+    pub fn group_by<K: GroupKeys>(self, keys: K) -> GroupedQuery<T, K> {
+        GroupedQuery {
+            query: self,
+            keys,
+            having: None,
+        }
+    }
+
+    /// Fails if the query selects objects in a way a single `UPDATE`
+    /// or `DELETE` statement cannot.
+    fn check_bulk_write(&self) -> Result<()> {
+        let unsupported = if self.limit.is_some() {
+            "a limit"
+        } else if self.offset.is_some() {
+            "an offset"
+        } else if !self.sort.is_empty() {
+            "an order"
+        } else if self.distinct.is_some() {
+            "distinctness"
+        } else {
+            return Ok(());
+        };
+        Err(crate::Error::BulkWriteUnsupported(unsupported))
     }
 
-    /// Executes the query against `conn` and deletes all matching objects.
+    /// Executes the query against `conn` and deletes all matching
+    /// objects in a single statement, without loading them.
+    ///
+    /// Returns the number of objects deleted. Fails with
+    /// [BulkWriteUnsupported] if the query has a limit, offset, order
+    /// or distinctness, rather than delete more objects than it would
+    /// load.
+    ///
+    /// [BulkWriteUnsupported]: crate::Error::BulkWriteUnsupported
     pub fn delete(self, conn: &impl ConnectionMethods) -> Result<usize> {
+        self.check_bulk_write()?;
+        conn.delete_where(&self.table, self.filter.unwrap_or(BoolExpr::True))
+    }
+
+    /// Executes the query against `conn` and updates all matching
+    /// objects in a single statement, setting the fields given by
+    /// `set`, which is passed the fields of the objects. The `set!`
+    /// macro makes such a function, for example
+    ///
+    /// ```ignore
+    /// query!(Post, blog == {blog.id}).update(&conn, set!(published = false, likes = 0))?;
+    /// ```
+    ///
+    /// Returns the number of objects updated. Objects loaded before
+    /// are not updated. Fails with [BulkWriteUnsupported] if the query
+    /// has a limit, offset, order or distinctness, rather than update
+    /// more objects than it would load.
+    ///
+    /// [BulkWriteUnsupported]: crate::Error::BulkWriteUnsupported
+    pub fn update(
+        self,
+        conn: &impl ConnectionMethods,
+        set: impl FnOnce(<T::DBO as DataObject>::Fields) -> Vec<Assignment>,
+    ) -> Result<usize> {
+        self.check_bulk_write()?;
+        let assignments = set(Default::default());
+        if assignments.is_empty() {
+            return Ok(0);
+        }
+        conn.update_where(
+            &self.table,
+            &assignments,
+            self.filter.unwrap_or(BoolExpr::True),
+        )
+    }
+
+    /// Executes the query against `conn` asynchronously and returns the
+    /// first result (if any), after the offset and in the order given.
+    /// Distinct queries are not supported.
+    #[cfg(feature = "async")]
+    pub async fn load_first_async(self, conn: &dyn AsyncConnectionMethods) -> Result<Option<T>> {
+        if self.distinct.is_some() {
+            return Err(crate::Error::DistinctUnsupported);
+        }
+        let sort = if self.sort.is_empty() {
+            None
+        } else {
+            Some(self.sort.as_slice())
+        };
+        conn.query(
+            &self.table,
+            T::COLUMNS,
+            self.filter,
+            Some(1),
+            self.offset,
+            sort,
+        )
+        .await?
+        .first()
+        .map(|row| T::from_row(row))
+        .transpose()
+    }
+
+    /// Executes the query against `conn` asynchronously. Distinct
+    /// queries are not supported.
+    #[cfg(feature = "async")]
+    pub async fn load_async(self, conn: &dyn AsyncConnectionMethods) -> Result<QueryResult<T>> {
+        if self.distinct.is_some() {
+            return Err(crate::Error::DistinctUnsupported);
+        }
+        let sort = if self.sort.is_empty() {
+            None
+        } else {
+            Some(self.sort.as_slice())
+        };
+        conn.query(
+            &self.table,
+            T::COLUMNS,
+            self.filter,
+            self.limit,
+            self.offset,
+            sort,
+        )
+        .await?
+        .iter()
+        .map(|row| T::from_row(row))
+        .collect()
+    }
+
+    /// Executes the query against `conn` asynchronously and deletes all
+    /// matching objects. See [delete](Self::delete).
+    #[cfg(feature = "async")]
+    pub async fn delete_async(self, conn: &dyn AsyncConnectionMethods) -> Result<usize> {
+        self.check_bulk_write()?;
         conn.delete_where(&self.table, self.filter.unwrap_or(BoolExpr::True))
+            .await
     }
 }
@@ -0,0 +1,152 @@
+//! Keyset pagination of the objects matched by a query, see
+//! [Query::paginate][super::Query::paginate].
+
+use super::{BoolExpr, Expr, Order, OrderDirection, Query};
+use crate::db::{Column, ConnectionMethods, OwnedRow};
+use crate::sqlval::FieldType;
+use crate::{DataObject, DataResult, Error, Result, SqlType, SqlVal};
+use std::marker::PhantomData;
+
+/// The position after the last object of a [Page], from which the
+/// next page continues. It is opaque to users, who may pass it between
+/// requests in its [encoded](Self::encode) form.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Cursor {
+    key: SqlVal,
+    pk: SqlVal,
+}
+impl Cursor {
+    /// A string for the cursor, safe to use in a URL.
+    pub fn encode(&self) -> String {
+        // Serializing a pair of values cannot fail
+        hex::encode(serde_json::to_vec(&(&self.key, &self.pk)).unwrap())
+    }
+    /// The cursor [encoded](Self::encode) as `s`. Fails with
+    /// [Error::InvalidCursor] if `s` is not such a string.
+    pub fn decode(s: &str) -> Result<Self> {
+        let bytes = hex::decode(s).map_err(|_| Error::InvalidCursor)?;
+        let (key, pk) = serde_json::from_slice(&bytes).map_err(|_| Error::InvalidCursor)?;
+        Ok(Cursor { key, pk })
+    }
+}
+
+/// A page of objects, from [Paginator::page].
+#[derive(Clone, Debug)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    /// Where the next page starts, or `None` if this is the last page.
+    pub next: Option<Cursor>,
+}
+
+/// Pages of the objects matched by a query, in the order of a key,
+/// from [Query::paginate][super::Query::paginate].
+///
+/// Each page is found by filtering on the key, rather than with an
+/// offset, so that later pages are as quick to find as the first if
+/// the key is indexed, and objects are neither skipped nor repeated if
+/// others are added or deleted between pages. Objects with equal keys
+/// are ordered by their primary key, so the key need not be unique. It
+/// should not be null.
+#[derive(Clone)]
+pub struct Paginator<T: DataResult> {
+    query: Query<T>,
+    key: Column,
+    direction: OrderDirection,
+    size: i32,
+}
+impl<T: DataResult> Paginator<T> {
+    pub(super) fn new<K: FieldType>(
+        query: Query<T>,
+        key: &'static str,
+        direction: OrderDirection,
+        size: i32,
+    ) -> Self {
+        Paginator {
+            query,
+            key: Column::new(key, K::SQLTYPE),
+            direction,
+            size,
+        }
+    }
+
+    /// Loads the page following `after`, or the first page if `after`
+    /// is `None`.
+    pub fn page(&self, conn: &impl ConnectionMethods, after: Option<&Cursor>) -> Result<Page<T>> {
+        let pkcol = <T::DBO as DataObject>::PKCOL;
+        let pktype: SqlType = <T::DBO as DataObject>::PKType::SQLTYPE;
+        let mut sort = vec![self.order(self.key.name())];
+        if self.key.name() != pkcol {
+            sort.push(self.order(pkcol));
+        }
+        let mut query: Query<T> = Query {
+            table: self.query.table.clone(),
+            filter: self.query.filter.clone(),
+            limit: None,
+            offset: None,
+            sort,
+            distinct: self.query.distinct.clone(),
+            phantom: PhantomData,
+        };
+        if let Some(cursor) = after {
+            let after = self.after(pkcol, cursor);
+            query = query.and_filter(after);
+        }
+        let mut columns = T::COLUMNS.to_vec();
+        columns.push(self.key.clone());
+        columns.push(Column::new(pkcol, pktype));
+
+        // One more object than a page is loaded to tell whether there
+        // is a next page
+        let mut rows = query.rows(conn, &columns, Some(self.size + 1))?;
+        let mut objects = Vec::new();
+        let mut last = None;
+        while let Some(row) = rows.next()? {
+            if objects.len() == self.size as usize {
+                return self.finish(objects, last);
+            }
+            let mut row = OwnedRow::from_row(row, &columns)?;
+            let pk = row.0.pop().unwrap();
+            let key = row.0.pop().unwrap();
+            last = Some(Cursor { key, pk });
+            objects.push(row);
+        }
+        self.finish(objects, None)
+    }
+
+    fn finish(&self, objects: Vec<OwnedRow>, next: Option<Cursor>) -> Result<Page<T>> {
+        let mut rows = OwnedRow::into_rows(objects);
+        let mut items = Vec::new();
+        while let Some(row) = rows.next()? {
+            items.push(T::from_row(row)?);
+        }
+        Ok(Page { items, next })
+    }
+
+    fn order(&self, column: &'static str) -> Order {
+        Order {
+            direction: self.direction.clone(),
+            column,
+            nulls: None,
+        }
+    }
+
+    /// The objects after `cursor` in the order of the pages.
+    fn after(&self, pkcol: &'static str, cursor: &Cursor) -> BoolExpr {
+        let past = |col, val: &SqlVal| match self.direction {
+            OrderDirection::Ascending => BoolExpr::Gt(col, Expr::Val(val.clone())),
+            OrderDirection::Descending => BoolExpr::Lt(col, Expr::Val(val.clone())),
+        };
+        let key = self.key.name();
+        if key == pkcol {
+            return past(pkcol, &cursor.pk);
+        }
+        // key > k OR (key = k AND pk > p), descending alike
+        BoolExpr::Or(
+            Box::new(past(key, &cursor.key)),
+            Box::new(BoolExpr::And(
+                Box::new(BoolExpr::Eq(key, Expr::Val(cursor.key.clone()))),
+                Box::new(past(pkcol, &cursor.pk)),
+            )),
+        )
+    }
+}
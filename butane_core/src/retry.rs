@@ -0,0 +1,499 @@
+//! Durable queue of writes which failed with transient errors, to be
+//! replayed later.
+//!
+//! Useful for edge or offline-first applications: rather than losing a
+//! write when the database is locked or unreachable, it is spooled to a
+//! local file or table and replayed once the database is available
+//! again, e.g. with `butane replay-failed`.
+use crate::db::{
+    self, BackendConnection, BackendRows, Column, ConnectionMethods, ConnectionSpec,
+    RawQueryResult, Statement,
+};
+use crate::migrations::adb::{AColumn, ATable, DeferredSqlType, Operation, TypeIdentifier, ADB};
+use crate::query::{Assignment, BoolExpr, Expr, Order, OrderDirection};
+use crate::{DataObject, Error, FromSql, Result, SqlType, SqlVal, SqlValRef};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::fs::{self, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// The default name of the table used by [RetryQueue::in_table].
+pub const DEFAULT_TABLE: &str = "butane_retry_queue";
+
+/// Queue of writes which failed with a transient error (see
+/// [`Error::is_transient`]). Writes are replayed in the order in which
+/// they were queued.
+///
+/// Inserts are spooled as inserts which replace any existing row with
+/// the same primary key, so that replaying a write which in fact
+/// succeeded is harmless. Objects with an automatic primary key cannot
+/// be spooled before they are first saved, as their key is only known
+/// once inserted.
+pub struct RetryQueue {
+    spool: Spool,
+    per_second: Option<u32>,
+}
+
+enum Spool {
+    File(PathBuf),
+    Table(Box<ConnectionSpec>, String),
+}
+
+impl RetryQueue {
+    /// Spool failed writes to the file at `path`, one per line.
+    pub fn in_file(path: impl Into<PathBuf>) -> Self {
+        RetryQueue {
+            spool: Spool::File(path.into()),
+            per_second: None,
+        }
+    }
+
+    /// Spool failed writes to the table `table` of the database
+    /// described by `spec`, usually a local SQLite database other than
+    /// the one being written to. The table is created if necessary.
+    pub fn in_table(spec: ConnectionSpec, table: impl Into<String>) -> Self {
+        RetryQueue {
+            spool: Spool::Table(Box::new(spec), table.into()),
+            per_second: None,
+        }
+    }
+
+    /// Replay at most `per_second` writes each second, to avoid
+    /// overwhelming a database which has just become available again.
+    pub fn with_rate_limit(mut self, per_second: u32) -> Self {
+        self.per_second = Some(per_second);
+        self
+    }
+
+    /// Save `obj`, queueing the save if it fails with a transient error.
+    pub fn save<T: DataObject>(&self, conn: &impl ConnectionMethods, obj: &mut T) -> Result<()> {
+        match obj.save(conn) {
+            Err(e) if e.is_transient() => self.defer_save(obj),
+            result => result,
+        }
+    }
+
+    /// Delete `obj`, queueing the deletion if it fails with a transient
+    /// error.
+    pub fn delete<T: DataObject>(&self, conn: &impl ConnectionMethods, obj: &T) -> Result<()> {
+        match obj.delete(conn) {
+            Err(e) if e.is_transient() => self.push(|recorder| obj.delete(recorder)),
+            result => result,
+        }
+    }
+
+    /// Queue the save of `obj` without attempting it, e.g. when it is
+    /// already known that the database is unavailable.
+    pub fn defer_save<T: DataObject>(&self, obj: &mut T) -> Result<()> {
+        self.push(|recorder| obj.save(recorder))
+    }
+
+    /// The number of queued writes.
+    pub fn len(&self) -> Result<usize> {
+        Ok(self.pending()?.len())
+    }
+
+    /// Whether there are no queued writes.
+    pub fn is_empty(&self) -> Result<bool> {
+        Ok(self.len()? == 0)
+    }
+
+    /// Replay the queued writes to `conn`, in order. Stops at the first
+    /// write which fails, leaving it and any later writes queued, and
+    /// returns that error. Otherwise returns the number of writes
+    /// replayed.
+    pub fn replay(&self, conn: &impl ConnectionMethods) -> Result<usize> {
+        let pending = self.pending()?;
+        let mut replayed = 0;
+        let mut result = Ok(());
+        for (id, write) in &pending {
+            if replayed > 0 {
+                if let Some(per_second) = self.per_second {
+                    std::thread::sleep(Duration::from_secs(1) / per_second.max(1));
+                }
+            }
+            result = write.replay(conn);
+            if result.is_err() {
+                break;
+            }
+            if let Spool::Table(..) = self.spool {
+                self.remove_from_table(*id)?;
+            }
+            replayed += 1;
+        }
+        if let Spool::File(path) = &self.spool {
+            let remaining: Vec<&SpooledWrite> =
+                pending.iter().skip(replayed).map(|(_, w)| w).collect();
+            write_file(path, &remaining)?;
+        }
+        result.map(|_| replayed)
+    }
+
+    fn push(&self, f: impl FnOnce(&Recorder) -> Result<()>) -> Result<()> {
+        for write in Recorder::record(f)? {
+            match &self.spool {
+                Spool::File(path) => {
+                    let mut f = OpenOptions::new().create(true).append(true).open(path)?;
+                    writeln!(f, "{}", serde_json::to_string(&write)?)?;
+                }
+                Spool::Table(_, table) => {
+                    self.table_connection()?.insert_only(
+                        table,
+                        &[Column::new("data", SqlType::Text)],
+                        &[SqlValRef::Text(&serde_json::to_string(&write)?)],
+                    )?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn pending(&self) -> Result<Vec<(i64, SpooledWrite)>> {
+        match &self.spool {
+            Spool::File(path) => {
+                if !path.exists() {
+                    return Ok(Vec::new());
+                }
+                BufReader::new(fs::File::open(path)?)
+                    .lines()
+                    .enumerate()
+                    .filter(|(_, line)| !matches!(line, Ok(line) if line.trim().is_empty()))
+                    .map(|(i, line)| Ok((i as i64, serde_json::from_str(&line?)?)))
+                    .collect()
+            }
+            Spool::Table(_, table) => {
+                let conn = self.table_connection()?;
+                let columns = [
+                    Column::new("id", SqlType::BigInt),
+                    Column::new("data", SqlType::Text),
+                ];
+                let order = [Order {
+                    column: "id",
+                    direction: OrderDirection::Ascending,
+                    nulls: None,
+                }];
+                let mut rows = conn.query(table, &columns, None, None, None, Some(&order))?;
+                let mut pending = Vec::new();
+                while let Some(row) = rows.next()? {
+                    let id = i64::from_sql_ref(row.get(0, SqlType::BigInt)?)?;
+                    let data = String::from_sql_ref(row.get(1, SqlType::Text)?)?;
+                    pending.push((id, serde_json::from_str(&data)?));
+                }
+                Ok(pending)
+            }
+        }
+    }
+
+    fn remove_from_table(&self, id: i64) -> Result<()> {
+        if let Spool::Table(_, table) = &self.spool {
+            self.table_connection()?
+                .delete_where(table, BoolExpr::Eq("id", Expr::Val(SqlVal::BigInt(id))))?;
+        }
+        Ok(())
+    }
+
+    fn table_connection(&self) -> Result<db::Connection> {
+        let (spec, table) = match &self.spool {
+            Spool::Table(spec, table) => (spec, table),
+            Spool::File(_) => return Err(Error::Internal("not a table spool".to_string())),
+        };
+        let conn = db::connect(spec)?;
+        if !conn.has_table(table)? {
+            let sql = conn.backend().create_migration_sql(
+                &ADB::new(),
+                vec![Operation::AddTableIfNotExists(spool_table(table))],
+            )?;
+            conn.execute(&sql)?;
+        }
+        Ok(conn)
+    }
+}
+
+fn spool_table(name: &str) -> ATable {
+    let mut table = ATable::new(name.to_string());
+    let ty = |ty| DeferredSqlType::KnownId(TypeIdentifier::Ty(ty));
+    table.add_column(AColumn::new(
+        "id",
+        ty(SqlType::BigInt),
+        false, // nullable
+        true,  // pk
+        true,  // auto
+        false, // unique
+        None,
+    ));
+    table.add_column(AColumn::new_simple("data", ty(SqlType::Text)));
+    table
+}
+
+fn write_file(path: &std::path::Path, writes: &[&SpooledWrite]) -> Result<()> {
+    let mut f = fs::File::create(path)?;
+    for write in writes {
+        writeln!(f, "{}", serde_json::to_string(write)?)?;
+    }
+    Ok(())
+}
+
+/// A write recorded for replay. Column names are stored owned and
+/// interned again on replay.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub(crate) enum SpooledWrite {
+    InsertOrReplace {
+        table: String,
+        columns: Vec<(String, SqlType)>,
+        pkcol: (String, SqlType),
+        values: Vec<SqlVal>,
+    },
+    InsertOnly {
+        table: String,
+        columns: Vec<(String, SqlType)>,
+        values: Vec<SqlVal>,
+    },
+    Update {
+        table: String,
+        pkcol: (String, SqlType),
+        pk: SqlVal,
+        columns: Vec<(String, SqlType)>,
+        values: Vec<SqlVal>,
+    },
+    /// Delete the rows of `table` where `col` has one of `values`.
+    DeleteWhereIn {
+        table: String,
+        col: String,
+        values: Vec<SqlVal>,
+    },
+}
+
+impl SpooledWrite {
+    pub(crate) fn table(&self) -> &str {
+        match self {
+            SpooledWrite::InsertOrReplace { table, .. }
+            | SpooledWrite::InsertOnly { table, .. }
+            | SpooledWrite::Update { table, .. }
+            | SpooledWrite::DeleteWhereIn { table, .. } => table,
+        }
+    }
+
+    /// The primary keys of the rows written. Rows inserted without a
+    /// primary key are not identified.
+    pub(crate) fn keys(&self) -> Vec<&SqlVal> {
+        match self {
+            SpooledWrite::InsertOrReplace {
+                columns,
+                pkcol,
+                values,
+                ..
+            } => columns
+                .iter()
+                .position(|(name, _)| *name == pkcol.0)
+                .map(|i| vec![&values[i]])
+                .unwrap_or_default(),
+            SpooledWrite::Update { pk, .. } => vec![pk],
+            SpooledWrite::DeleteWhereIn { values, .. } => values.iter().collect(),
+            SpooledWrite::InsertOnly { .. } => Vec::new(),
+        }
+    }
+
+    pub(crate) fn replay(&self, conn: &impl ConnectionMethods) -> Result<()> {
+        match self {
+            SpooledWrite::InsertOrReplace {
+                table,
+                columns,
+                pkcol,
+                values,
+            } => conn.insert_or_replace(
+                table,
+                &to_columns(columns),
+                &to_column(pkcol),
+                &to_refs(values),
+            ),
+            SpooledWrite::InsertOnly {
+                table,
+                columns,
+                values,
+            } => conn.insert_only(table, &to_columns(columns), &to_refs(values)),
+            SpooledWrite::Update {
+                table,
+                pkcol,
+                pk,
+                columns,
+                values,
+            } => conn.update(
+                table,
+                to_column(pkcol),
+                pk.as_ref(),
+                &to_columns(columns),
+                &to_refs(values),
+            ),
+            SpooledWrite::DeleteWhereIn { table, col, values } => {
+                conn.delete_where(table, BoolExpr::In(intern(col), values.clone()))?;
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Column names are `&'static str`, so names read back from the spool
+/// are leaked, once each.
+fn intern(name: &str) -> &'static str {
+    static NAMES: Lazy<Mutex<HashSet<&'static str>>> = Lazy::new(|| Mutex::new(HashSet::new()));
+    let mut names = NAMES.lock().unwrap();
+    match names.get(name) {
+        Some(interned) => interned,
+        None => {
+            let interned: &'static str = Box::leak(name.to_string().into_boxed_str());
+            names.insert(interned);
+            interned
+        }
+    }
+}
+
+fn to_column((name, ty): &(String, SqlType)) -> Column {
+    Column::new(intern(name), ty.clone())
+}
+
+fn to_columns(columns: &[(String, SqlType)]) -> Vec<Column> {
+    columns.iter().map(to_column).collect()
+}
+
+fn to_refs(values: &[SqlVal]) -> Vec<SqlValRef<'_>> {
+    values.iter().map(|v| v.as_ref()).collect()
+}
+
+fn from_column(col: &Column) -> (String, SqlType) {
+    (col.name().to_string(), col.ty().clone())
+}
+
+fn from_refs(values: &[SqlValRef<'_>]) -> Vec<SqlVal> {
+    values.iter().map(|v| v.clone().into()).collect()
+}
+
+/// Stands in for a connection to record the writes made to it.
+#[derive(Default)]
+pub(crate) struct Recorder {
+    writes: RefCell<Vec<SpooledWrite>>,
+}
+
+impl Recorder {
+    /// The writes `f` makes to the recorder.
+    pub(crate) fn record(f: impl FnOnce(&Recorder) -> Result<()>) -> Result<Vec<SpooledWrite>> {
+        let recorder = Recorder::default();
+        f(&recorder)?;
+        Ok(recorder.writes.into_inner())
+    }
+}
+
+impl ConnectionMethods for Recorder {
+    fn execute(&self, _sql: &str) -> Result<()> {
+        Err(Error::CannotSpool("raw SQL".to_string()))
+    }
+    fn query<'a, 'b, 'c: 'a>(
+        &'c self,
+        table: &str,
+        _columns: &'b [Column],
+        _expr: Option<BoolExpr>,
+        _limit: Option<i32>,
+        _offset: Option<i32>,
+        _sort: Option<&[Order]>,
+    ) -> Result<RawQueryResult<'a>> {
+        Err(Error::CannotSpool(format!("query of {}", table)))
+    }
+    fn insert_returning_pk(
+        &self,
+        table: &str,
+        columns: &[Column],
+        pkcol: &Column,
+        values: &[SqlValRef<'_>],
+    ) -> Result<SqlVal> {
+        let pk = columns
+            .iter()
+            .position(|c| c.name() == pkcol.name())
+            .map(|i| SqlVal::from(values[i].clone()))
+            .ok_or_else(|| {
+                Error::CannotSpool(format!(
+                    "insert into {} with an automatic primary key",
+                    table
+                ))
+            })?;
+        self.insert_or_replace(table, columns, pkcol, values)?;
+        Ok(pk)
+    }
+    fn insert_only(&self, table: &str, columns: &[Column], values: &[SqlValRef<'_>]) -> Result<()> {
+        self.writes.borrow_mut().push(SpooledWrite::InsertOnly {
+            table: table.to_string(),
+            columns: columns.iter().map(from_column).collect(),
+            values: from_refs(values),
+        });
+        Ok(())
+    }
+    fn insert_or_replace(
+        &self,
+        table: &str,
+        columns: &[Column],
+        pkcol: &Column,
+        values: &[SqlValRef<'_>],
+    ) -> Result<()> {
+        self.writes
+            .borrow_mut()
+            .push(SpooledWrite::InsertOrReplace {
+                table: table.to_string(),
+                columns: columns.iter().map(from_column).collect(),
+                pkcol: from_column(pkcol),
+                values: from_refs(values),
+            });
+        Ok(())
+    }
+    fn update(
+        &self,
+        table: &str,
+        pkcol: Column,
+        pk: SqlValRef,
+        columns: &[Column],
+        values: &[SqlValRef<'_>],
+    ) -> Result<()> {
+        self.writes.borrow_mut().push(SpooledWrite::Update {
+            table: table.to_string(),
+            pkcol: from_column(&pkcol),
+            pk: pk.into(),
+            columns: columns.iter().map(from_column).collect(),
+            values: from_refs(values),
+        });
+        Ok(())
+    }
+    fn delete_where(&self, table: &str, expr: BoolExpr) -> Result<usize> {
+        let (col, values) = match expr {
+            BoolExpr::Eq(col, Expr::Val(val)) => (col, vec![val]),
+            BoolExpr::In(col, values) => (col, values),
+            _ => {
+                return Err(Error::CannotSpool(format!(
+                    "deletion from {} with a complex condition",
+                    table
+                )))
+            }
+        };
+        self.writes.borrow_mut().push(SpooledWrite::DeleteWhereIn {
+            table: table.to_string(),
+            col: col.to_string(),
+            values,
+        });
+        Ok(0)
+    }
+    fn update_where(
+        &self,
+        table: &str,
+        _assignments: &[Assignment],
+        _expr: BoolExpr,
+    ) -> Result<usize> {
+        Err(Error::CannotSpool(format!("bulk update of {}", table)))
+    }
+    fn has_table(&self, table: &str) -> Result<bool> {
+        Err(Error::CannotSpool(format!("existence check of {}", table)))
+    }
+    fn execute_batch(&self, _statements: &[Statement]) -> Result<()> {
+        Err(Error::CannotSpool("raw SQL".to_string()))
+    }
+}
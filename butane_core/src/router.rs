@@ -0,0 +1,237 @@
+//! Routing of reads to read replicas.
+//!
+//! A [Router] wraps a connection to the primary database and
+//! connections to its replicas. Queries go to the replicas in turn,
+//! spreading the load of reading, while writes go to the primary.
+//!
+//! Replicas lag behind the primary, so a query made just after a write
+//! may not see it. Once [pinned](Router::pin), as it is after each
+//! write with [pin_after_write](Router::pin_after_write), the router
+//! sends queries to the primary too. Routers are typically made for a
+//! request, so that pinning lasts until its end.
+//!
+//! ```ignore
+//! let conn = Router::new(primary, vec![replica1, replica2]).pin_after_write(true);
+//! let posts = Post::query().load(&conn)?; // from a replica
+//! post.save(&conn)?; // to the primary, which now serves queries
+//! ```
+//!
+//! Raw SQL run with [execute](ConnectionMethods::execute) and
+//! transactions, begun on [primary_mut](Router::primary_mut), use the
+//! primary.
+use crate::db::{Column, ConnectionMethods, QueryPlan, RawQueryResult, Statement};
+use crate::query::{AggregateExpr, Assignment, BoolExpr, Having, Order};
+use crate::{Result, SqlVal, SqlValRef};
+use std::cell::Cell;
+
+/// Connection wrapper sending queries to read replicas and writes to
+/// the primary. See the [module documentation](self).
+pub struct Router<C> {
+    primary: C,
+    replicas: Vec<C>,
+    /// The replica for the next query.
+    next: Cell<usize>,
+    pinned: Cell<bool>,
+    pin_after_write: bool,
+}
+
+impl<C: ConnectionMethods> Router<C> {
+    /// Route between `primary` and `replicas`. Without replicas,
+    /// everything goes to the primary.
+    pub fn new(primary: C, replicas: Vec<C>) -> Self {
+        Router {
+            primary,
+            replicas,
+            next: Cell::new(0),
+            pinned: Cell::new(false),
+            pin_after_write: false,
+        }
+    }
+
+    /// Whether to [pin](Self::pin) the router to the primary after
+    /// each write, so that later queries see it. Returns `self` as this
+    /// method is expected to be chained.
+    pub fn pin_after_write(mut self, pin: bool) -> Self {
+        self.pin_after_write = pin;
+        self
+    }
+
+    /// Send queries to the primary, until [unpin](Self::unpin)ned.
+    pub fn pin(&self) {
+        self.pinned.set(true);
+    }
+
+    /// Send queries to the replicas again.
+    pub fn unpin(&self) {
+        self.pinned.set(false);
+    }
+
+    pub fn is_pinned(&self) -> bool {
+        self.pinned.get()
+    }
+
+    pub fn primary(&self) -> &C {
+        &self.primary
+    }
+
+    /// The primary connection, e.g. to begin a transaction.
+    pub fn primary_mut(&mut self) -> &mut C {
+        &mut self.primary
+    }
+
+    pub fn replicas(&self) -> &[C] {
+        &self.replicas
+    }
+
+    /// The connection for the next query.
+    fn reader(&self) -> &C {
+        if self.pinned.get() || self.replicas.is_empty() {
+            return &self.primary;
+        }
+        let i = self.next.get() % self.replicas.len();
+        self.next.set(i + 1);
+        &self.replicas[i]
+    }
+
+    /// The connection for a write.
+    fn writer(&self) -> &C {
+        if self.pin_after_write {
+            self.pin();
+        }
+        &self.primary
+    }
+}
+
+impl<C: ConnectionMethods> ConnectionMethods for Router<C> {
+    fn execute(&self, sql: &str) -> Result<()> {
+        self.writer().execute(sql)
+    }
+    fn query<'a, 'b, 'c: 'a>(
+        &'c self,
+        table: &str,
+        columns: &'b [Column],
+        expr: Option<BoolExpr>,
+        limit: Option<i32>,
+        offset: Option<i32>,
+        sort: Option<&[Order]>,
+    ) -> Result<RawQueryResult<'a>> {
+        self.reader()
+            .query(table, columns, expr, limit, offset, sort)
+    }
+    fn query_stream<'a, 'b, 'c: 'a>(
+        &'c self,
+        table: &str,
+        columns: &'b [Column],
+        expr: Option<BoolExpr>,
+        limit: Option<i32>,
+        offset: Option<i32>,
+        sort: Option<&[Order]>,
+    ) -> Result<RawQueryResult<'a>> {
+        self.reader()
+            .query_stream(table, columns, expr, limit, offset, sort)
+    }
+    #[allow(clippy::too_many_arguments)]
+    fn query_distinct<'a, 'b, 'c: 'a>(
+        &'c self,
+        table: &str,
+        columns: &'b [Column],
+        distinct: &crate::query::Distinct,
+        expr: Option<BoolExpr>,
+        limit: Option<i32>,
+        offset: Option<i32>,
+        sort: Option<&[Order]>,
+    ) -> Result<RawQueryResult<'a>> {
+        self.reader()
+            .query_distinct(table, columns, distinct, expr, limit, offset, sort)
+    }
+    fn insert_returning_pk(
+        &self,
+        table: &str,
+        columns: &[Column],
+        pkcol: &Column,
+        values: &[SqlValRef<'_>],
+    ) -> Result<SqlVal> {
+        self.writer()
+            .insert_returning_pk(table, columns, pkcol, values)
+    }
+    fn insert_only(&self, table: &str, columns: &[Column], values: &[SqlValRef<'_>]) -> Result<()> {
+        self.writer().insert_only(table, columns, values)
+    }
+    fn insert_or_replace(
+        &self,
+        table: &str,
+        columns: &[Column],
+        pkcol: &Column,
+        values: &[SqlValRef<'_>],
+    ) -> Result<()> {
+        self.writer()
+            .insert_or_replace(table, columns, pkcol, values)
+    }
+    fn update(
+        &self,
+        table: &str,
+        pkcol: Column,
+        pk: SqlValRef,
+        columns: &[Column],
+        values: &[SqlValRef<'_>],
+    ) -> Result<()> {
+        self.writer().update(table, pkcol, pk, columns, values)
+    }
+    fn delete_where(&self, table: &str, expr: BoolExpr) -> Result<usize> {
+        self.writer().delete_where(table, expr)
+    }
+    fn update_where(
+        &self,
+        table: &str,
+        assignments: &[Assignment],
+        expr: BoolExpr,
+    ) -> Result<usize> {
+        self.writer().update_where(table, assignments, expr)
+    }
+    fn has_table(&self, table: &str) -> Result<bool> {
+        // Asked of the primary, as tables are created there first
+        self.primary.has_table(table)
+    }
+    fn execute_batch(&self, statements: &[Statement]) -> Result<()> {
+        self.writer().execute_batch(statements)
+    }
+    fn insert_only_many(
+        &self,
+        table: &str,
+        columns: &[Column],
+        rows: &[Vec<SqlValRef<'_>>],
+    ) -> Result<()> {
+        self.writer().insert_only_many(table, columns, rows)
+    }
+    fn explain(
+        &self,
+        table: &str,
+        columns: &[Column],
+        expr: Option<BoolExpr>,
+        limit: Option<i32>,
+        offset: Option<i32>,
+        sort: Option<&[Order]>,
+        analyze: bool,
+    ) -> Result<QueryPlan> {
+        self.reader()
+            .explain(table, columns, expr, limit, offset, sort, analyze)
+    }
+    fn aggregate(
+        &self,
+        table: &str,
+        groups: &[Column],
+        aggregates: &[AggregateExpr],
+        expr: Option<BoolExpr>,
+        having: Option<Having>,
+    ) -> Result<Vec<Vec<SqlVal>>> {
+        self.reader()
+            .aggregate(table, groups, aggregates, expr, having)
+    }
+    fn query_raw_rows<'a, 'c: 'a>(
+        &'c self,
+        sql: &str,
+        params: &[SqlVal],
+    ) -> Result<RawQueryResult<'a>> {
+        self.reader().query_raw_rows(sql, params)
+    }
+}
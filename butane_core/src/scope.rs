@@ -0,0 +1,292 @@
+//! Scoping of queries to the rows owned by a user.
+//!
+//! In applications serving several users, most rows belong to one of
+//! them and must not be read or written on behalf of another. Rather
+//! than adding the ownership condition to every query by hand, models
+//! implement [Owned] to name their owner column, and requests go
+//! through a [Scoped] connection for the current user. Queries and
+//! deletes on those models then only see the user's rows, and writes
+//! to other users' rows fail.
+//!
+//! ```ignore
+//! impl Owned for Note {
+//!     const OWNER_COLUMN: &'static str = "owner";
+//! }
+//!
+//! let conn = Scoped::new(&conn, &session.user).model::<Note>();
+//! let notes = Note::query().load(&conn)?; // only the user's notes
+//! ```
+//!
+//! Raw SQL run with [execute](ConnectionMethods::execute) is not
+//! scoped.
+use crate::db::{Column, ConnectionMethods, QueryPlan, RawQueryResult, Statement};
+use crate::query::{AggregateExpr, Assignment, BoolExpr, Expr, Having, Order};
+use crate::{DataObject, Error, Result, SqlVal, SqlValRef, ToSql};
+use std::collections::HashMap;
+
+/// A model whose rows each belong to one owner.
+pub trait Owned: DataObject {
+    /// The name of the column holding the owner of a row.
+    const OWNER_COLUMN: &'static str;
+}
+
+/// Connection wrapper restricting reads and writes of [Owned] models
+/// to the rows of one owner. See the [module documentation](self).
+///
+/// Models are scoped once registered with [model](Self::model); the
+/// tables of other models are passed through unchanged.
+pub struct Scoped<'c, C> {
+    conn: &'c C,
+    owner: SqlVal,
+    /// The owner column of each scoped table.
+    columns: HashMap<&'static str, &'static str>,
+}
+
+impl<'c, C: ConnectionMethods> Scoped<'c, C> {
+    /// Wrap `conn`, scoping it to the rows owned by `owner`.
+    pub fn new(conn: &'c C, owner: impl ToSql) -> Self {
+        Scoped {
+            conn,
+            owner: owner.to_sql(),
+            columns: HashMap::new(),
+        }
+    }
+
+    /// Scope the queries and writes of the model `T`.
+    pub fn model<T: Owned>(mut self) -> Self {
+        self.columns.insert(T::TABLE, T::OWNER_COLUMN);
+        self
+    }
+
+    /// The value of the owner column of rows in scope.
+    pub fn owner(&self) -> &SqlVal {
+        &self.owner
+    }
+
+    fn owned(&self, column: &'static str) -> BoolExpr {
+        BoolExpr::Eq(column, Expr::Val(self.owner.clone()))
+    }
+
+    /// `expr` restricted to the rows in scope, if `table` is scoped.
+    fn restrict(&self, table: &str, expr: BoolExpr) -> BoolExpr {
+        match self.columns.get(table) {
+            Some(col) => BoolExpr::And(Box::new(expr), Box::new(self.owned(col))),
+            None => expr,
+        }
+    }
+
+    /// Check that the values to be written to `table` keep the row in
+    /// scope, requiring the owner column if `required`.
+    fn check_values(
+        &self,
+        table: &str,
+        columns: &[Column],
+        values: &[SqlValRef<'_>],
+        required: bool,
+    ) -> Result<()> {
+        let owner_col = match self.columns.get(table) {
+            Some(col) => *col,
+            None => return Ok(()),
+        };
+        match columns.iter().position(|c| c.name() == owner_col) {
+            Some(idx) if SqlVal::from(values[idx].clone()) == self.owner => Ok(()),
+            None if !required => Ok(()),
+            _ => Err(Error::OutOfScope(table.to_string())),
+        }
+    }
+
+    /// Whether the row of `table` with primary key `pk` exists and is
+    /// in scope. Rows of tables which are not scoped are all in scope.
+    fn in_scope(&self, table: &str, pkcol: &Column, pk: SqlVal) -> Result<bool> {
+        if !self.columns.contains_key(table) {
+            return Ok(true);
+        }
+        let expr = self.restrict(table, BoolExpr::Eq(pkcol.name(), Expr::Val(pk)));
+        let mut rows = self.conn.query(
+            table,
+            std::slice::from_ref(pkcol),
+            Some(expr),
+            Some(1),
+            None,
+            None,
+        )?;
+        Ok(rows.next()?.is_some())
+    }
+
+    /// Whether any row of `table` has primary key `pk`.
+    fn exists(&self, table: &str, pkcol: &Column, pk: SqlVal) -> Result<bool> {
+        let mut rows = self.conn.query(
+            table,
+            std::slice::from_ref(pkcol),
+            Some(BoolExpr::Eq(pkcol.name(), Expr::Val(pk))),
+            Some(1),
+            None,
+            None,
+        )?;
+        Ok(rows.next()?.is_some())
+    }
+}
+
+impl<'c, C: ConnectionMethods> ConnectionMethods for Scoped<'c, C> {
+    fn execute(&self, sql: &str) -> Result<()> {
+        self.conn.execute(sql)
+    }
+    fn query<'a, 'b, 'd: 'a>(
+        &'d self,
+        table: &str,
+        columns: &'b [Column],
+        expr: Option<BoolExpr>,
+        limit: Option<i32>,
+        offset: Option<i32>,
+        sort: Option<&[Order]>,
+    ) -> Result<RawQueryResult<'a>> {
+        let expr = match (self.columns.get(table), expr) {
+            (_, Some(expr)) => Some(self.restrict(table, expr)),
+            (Some(col), None) => Some(self.owned(col)),
+            (None, None) => None,
+        };
+        self.conn.query(table, columns, expr, limit, offset, sort)
+    }
+    fn query_stream<'a, 'b, 'd: 'a>(
+        &'d self,
+        table: &str,
+        columns: &'b [Column],
+        expr: Option<BoolExpr>,
+        limit: Option<i32>,
+        offset: Option<i32>,
+        sort: Option<&[Order]>,
+    ) -> Result<RawQueryResult<'a>> {
+        let expr = match (self.columns.get(table), expr) {
+            (_, Some(expr)) => Some(self.restrict(table, expr)),
+            (Some(col), None) => Some(self.owned(col)),
+            (None, None) => None,
+        };
+        self.conn
+            .query_stream(table, columns, expr, limit, offset, sort)
+    }
+    #[allow(clippy::too_many_arguments)]
+    fn query_distinct<'a, 'b, 'd: 'a>(
+        &'d self,
+        table: &str,
+        columns: &'b [Column],
+        distinct: &crate::query::Distinct,
+        expr: Option<BoolExpr>,
+        limit: Option<i32>,
+        offset: Option<i32>,
+        sort: Option<&[Order]>,
+    ) -> Result<RawQueryResult<'a>> {
+        let expr = match (self.columns.get(table), expr) {
+            (_, Some(expr)) => Some(self.restrict(table, expr)),
+            (Some(col), None) => Some(self.owned(col)),
+            (None, None) => None,
+        };
+        self.conn
+            .query_distinct(table, columns, distinct, expr, limit, offset, sort)
+    }
+    fn insert_returning_pk(
+        &self,
+        table: &str,
+        columns: &[Column],
+        pkcol: &Column,
+        values: &[SqlValRef<'_>],
+    ) -> Result<SqlVal> {
+        self.check_values(table, columns, values, true)?;
+        self.conn.insert_returning_pk(table, columns, pkcol, values)
+    }
+    fn insert_only(&self, table: &str, columns: &[Column], values: &[SqlValRef<'_>]) -> Result<()> {
+        self.check_values(table, columns, values, true)?;
+        self.conn.insert_only(table, columns, values)
+    }
+    fn insert_or_replace(
+        &self,
+        table: &str,
+        columns: &[Column],
+        pkcol: &Column,
+        values: &[SqlValRef<'_>],
+    ) -> Result<()> {
+        self.check_values(table, columns, values, true)?;
+        if let Some(idx) = columns.iter().position(|c| c.name() == pkcol.name()) {
+            let pk = SqlVal::from(values[idx].clone());
+            // Replacing another owner's row would take it over
+            if !self.in_scope(table, pkcol, pk.clone())? && self.exists(table, pkcol, pk)? {
+                return Err(Error::OutOfScope(table.to_string()));
+            }
+        }
+        self.conn.insert_or_replace(table, columns, pkcol, values)
+    }
+    fn update(
+        &self,
+        table: &str,
+        pkcol: Column,
+        pk: SqlValRef,
+        columns: &[Column],
+        values: &[SqlValRef<'_>],
+    ) -> Result<()> {
+        // Rows out of scope are reported as missing, as when queried
+        if !self.in_scope(table, &pkcol, SqlVal::from(pk.clone()))? {
+            return Err(Error::NoSuchObject);
+        }
+        self.check_values(table, columns, values, false)?;
+        self.conn.update(table, pkcol, pk, columns, values)
+    }
+    fn delete_where(&self, table: &str, expr: BoolExpr) -> Result<usize> {
+        self.conn.delete_where(table, self.restrict(table, expr))
+    }
+    fn update_where(
+        &self,
+        table: &str,
+        assignments: &[Assignment],
+        expr: BoolExpr,
+    ) -> Result<usize> {
+        self.conn
+            .update_where(table, assignments, self.restrict(table, expr))
+    }
+    fn has_table(&self, table: &str) -> Result<bool> {
+        self.conn.has_table(table)
+    }
+    fn execute_batch(&self, statements: &[Statement]) -> Result<()> {
+        // Raw SQL is not scoped, as with execute
+        self.conn.execute_batch(statements)
+    }
+    fn explain(
+        &self,
+        table: &str,
+        columns: &[Column],
+        expr: Option<BoolExpr>,
+        limit: Option<i32>,
+        offset: Option<i32>,
+        sort: Option<&[Order]>,
+        analyze: bool,
+    ) -> Result<QueryPlan> {
+        let expr = match (self.columns.get(table), expr) {
+            (_, Some(expr)) => Some(self.restrict(table, expr)),
+            (Some(col), None) => Some(self.owned(col)),
+            (None, None) => None,
+        };
+        self.conn
+            .explain(table, columns, expr, limit, offset, sort, analyze)
+    }
+    fn aggregate(
+        &self,
+        table: &str,
+        groups: &[Column],
+        aggregates: &[AggregateExpr],
+        expr: Option<BoolExpr>,
+        having: Option<Having>,
+    ) -> Result<Vec<Vec<SqlVal>>> {
+        let expr = match (self.columns.get(table), expr) {
+            (_, Some(expr)) => Some(self.restrict(table, expr)),
+            (Some(col), None) => Some(self.owned(col)),
+            (None, None) => None,
+        };
+        self.conn.aggregate(table, groups, aggregates, expr, having)
+    }
+    fn query_raw_rows<'a, 'd: 'a>(
+        &'d self,
+        sql: &str,
+        params: &[SqlVal],
+    ) -> Result<RawQueryResult<'a>> {
+        // Raw SQL is not scoped, as with execute
+        self.conn.query_raw_rows(sql, params)
+    }
+}
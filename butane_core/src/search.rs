@@ -0,0 +1,450 @@
+//! Full-text search across models.
+//!
+//! A [SearchIndex] keeps one index table, [INDEX_TABLE], covering the
+//! text columns of every model registered with it. Models implement
+//! [Searchable] to name those columns, and writes made through
+//! [SearchIndex::track] keep the index up to date. [query] then finds
+//! the rows of any of the models containing all of the given words,
+//! best matches first.
+//!
+//! ```ignore
+//! impl Searchable for Post {
+//!     const SEARCH_COLUMNS: &'static [&'static str] = &["title", "body"];
+//! }
+//!
+//! let index = SearchIndex::new().model::<Post>().model::<Comment>();
+//! index.prepare(&conn)?;
+//! post.save(&index.track(&conn))?;
+//! for hit in search::query(&conn, "rust orm")? {
+//!     if let Some(post) = hit.load::<Post>(&conn)? { ... }
+//! }
+//! ```
+//!
+//! SQLite indexes with FTS5 and Postgres with a `tsvector` column.
+//! Words are matched exactly, without stemming, so that both backends
+//! find the same rows.
+use crate::db::{
+    BackendConnection, Column, ConnectionMethods, QueryPlan, RawQueryResult, Statement,
+};
+use crate::query::{AggregateExpr, Assignment, BoolExpr, Expr, Having, Order};
+use crate::{DataObject, FieldType, FromSql, Result, SqlType, SqlVal, SqlValRef};
+use std::collections::HashMap;
+
+/// The table holding the index of all searchable models.
+pub const INDEX_TABLE: &str = "butane_search_index";
+
+/// A model whose text is indexed for search.
+pub trait Searchable: DataObject {
+    /// The names of the text columns indexed. `NULL` values are skipped.
+    const SEARCH_COLUMNS: &'static [&'static str];
+}
+
+/// A row found by [query].
+#[derive(Clone, Debug, PartialEq)]
+pub struct SearchHit {
+    model: String,
+    pk: SqlVal,
+    snippet: String,
+}
+
+impl SearchHit {
+    /// Read a hit from the `model` and `pk` columns of the index and a
+    /// snippet of its text.
+    pub(crate) fn from_index(model: String, pk: &str, snippet: String) -> Result<Self> {
+        Ok(SearchHit {
+            model,
+            pk: serde_json::from_str(pk)?,
+            snippet,
+        })
+    }
+
+    /// The table of the model the row belongs to.
+    pub fn model(&self) -> &str {
+        &self.model
+    }
+
+    /// The primary key of the row.
+    pub fn pk_val(&self) -> &SqlVal {
+        &self.pk
+    }
+
+    /// An extract of the indexed text around the matched words, which
+    /// are enclosed in `<b>` and `</b>`.
+    pub fn snippet(&self) -> &str {
+        &self.snippet
+    }
+
+    /// Whether the row belongs to the model `T`.
+    pub fn is<T: DataObject>(&self) -> bool {
+        self.model == T::TABLE
+    }
+
+    /// The primary key of the row, if it belongs to the model `T`.
+    pub fn pk<T: DataObject>(&self) -> Result<Option<T::PKType>> {
+        if !self.is::<T>() {
+            return Ok(None);
+        }
+        Ok(Some(T::PKType::from_sql_ref(self.pk.as_ref())?))
+    }
+
+    /// Load the row, if it belongs to the model `T`.
+    pub fn load<T: DataObject>(&self, conn: &impl ConnectionMethods) -> Result<Option<T>> {
+        match self.pk::<T>()? {
+            Some(pk) => Ok(Some(T::get(conn, pk)?)),
+            None => Ok(None),
+        }
+    }
+}
+
+/// Find the rows containing all of the words of `terms`, best matches
+/// first.
+pub fn query(conn: &impl BackendConnection, terms: &str) -> Result<Vec<SearchHit>> {
+    conn.search(terms, None)
+}
+
+/// Like [query], returning at most `limit` hits.
+pub fn query_top(conn: &impl BackendConnection, terms: &str, limit: i32) -> Result<Vec<SearchHit>> {
+    conn.search(terms, Some(limit))
+}
+
+struct IndexedModel {
+    pkcol: Column,
+    columns: &'static [&'static str],
+}
+
+/// The models indexed for search. See the [module documentation](self).
+#[derive(Default)]
+pub struct SearchIndex {
+    /// Keyed by table.
+    models: HashMap<&'static str, IndexedModel>,
+}
+
+impl SearchIndex {
+    pub fn new() -> Self {
+        SearchIndex::default()
+    }
+
+    /// Index the model `T`.
+    pub fn model<T: Searchable>(mut self) -> Self {
+        self.models.insert(
+            T::TABLE,
+            IndexedModel {
+                pkcol: Column::new(T::PKCOL, <T::PKType as FieldType>::SQLTYPE),
+                columns: T::SEARCH_COLUMNS,
+            },
+        );
+        self
+    }
+
+    /// Create the index table, if necessary. Must be called before
+    /// writes are tracked.
+    pub fn prepare(&self, conn: &impl BackendConnection) -> Result<()> {
+        conn.create_search_index()
+    }
+
+    /// Wrap `conn` so that writes made through it to the indexed models
+    /// update the index. The index is updated with the same connection,
+    /// so within the same transaction. Raw SQL run with `execute` is
+    /// not indexed.
+    pub fn track<'c, C: ConnectionMethods>(&'c self, conn: &'c C) -> Indexed<'c, C> {
+        Indexed { index: self, conn }
+    }
+
+    /// Index every row of the indexed models afresh, e.g. after adding
+    /// a model to the index or writing without tracking.
+    pub fn rebuild(&self, conn: &impl ConnectionMethods) -> Result<()> {
+        for (table, model) in &self.models {
+            conn.delete_where(INDEX_TABLE, model_entries(table))?;
+            for pk in self.pks(conn, table, model, None)? {
+                self.reindex(conn, table, pk)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The primary keys of the rows of `table` matching `expr`.
+    fn pks(
+        &self,
+        conn: &impl ConnectionMethods,
+        table: &str,
+        model: &IndexedModel,
+        expr: Option<BoolExpr>,
+    ) -> Result<Vec<SqlVal>> {
+        let mut rows = conn.query(
+            table,
+            std::slice::from_ref(&model.pkcol),
+            expr,
+            None,
+            None,
+            None,
+        )?;
+        let mut pks = Vec::new();
+        while let Some(row) = rows.next()? {
+            pks.push(row.get(0, model.pkcol.ty().clone())?.into());
+        }
+        Ok(pks)
+    }
+
+    /// Replace the index entry of the row of `table` with primary key
+    /// `pk`, removing it if the row no longer exists.
+    fn reindex(&self, conn: &impl ConnectionMethods, table: &str, pk: SqlVal) -> Result<()> {
+        let model = match self.models.get(table) {
+            Some(model) => model,
+            None => return Ok(()),
+        };
+        let columns: Vec<Column> = std::iter::once(model.pkcol.clone())
+            .chain(model.columns.iter().map(|c| Column::new(c, SqlType::Text)))
+            .collect();
+        let expr = BoolExpr::Eq(model.pkcol.name(), Expr::Val(pk.clone()));
+        let entry = {
+            let mut rows = conn.query(table, &columns, Some(expr), Some(1), None, None)?;
+            match rows.next()? {
+                Some(row) => {
+                    let mut text: Vec<String> = Vec::new();
+                    for i in 1..columns.len() {
+                        if let Some(t) = Option::<String>::from_sql_ref(row.get(i, SqlType::Text)?)?
+                        {
+                            text.push(t);
+                        }
+                    }
+                    Some((SqlVal::from(row.get(0, model.pkcol.ty().clone())?), text))
+                }
+                None => None,
+            }
+        };
+        // The primary key given may be of another type than that read,
+        // e.g. BigInt for an Int column
+        let mut stale = vec![pk];
+        if let Some((read, _)) = &entry {
+            if *read != stale[0] {
+                stale.push(read.clone());
+            }
+        }
+        self.remove(conn, table, stale)?;
+        if let Some((pk, text)) = entry {
+            conn.insert_only(
+                INDEX_TABLE,
+                &index_columns(),
+                &[
+                    SqlValRef::Text(table),
+                    SqlValRef::Text(&key(&pk)?),
+                    SqlValRef::Text(&text.join("\n")),
+                ],
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Remove the index entries of the rows of `table` with the given
+    /// primary keys.
+    fn remove(&self, conn: &impl ConnectionMethods, table: &str, pks: Vec<SqlVal>) -> Result<()> {
+        if pks.is_empty() {
+            return Ok(());
+        }
+        let keys = pks
+            .iter()
+            .map(|pk| Ok(SqlVal::Text(key(pk)?)))
+            .collect::<Result<Vec<SqlVal>>>()?;
+        conn.delete_where(
+            INDEX_TABLE,
+            BoolExpr::And(
+                Box::new(model_entries(table)),
+                Box::new(BoolExpr::In("pk", keys)),
+            ),
+        )?;
+        Ok(())
+    }
+}
+
+/// A connection whose writes to indexed models update the search
+/// index. Created by [SearchIndex::track].
+pub struct Indexed<'c, C> {
+    index: &'c SearchIndex,
+    conn: &'c C,
+}
+
+impl<'c, C: ConnectionMethods> Indexed<'c, C> {
+    /// The primary key among `values`, if `table` is indexed.
+    fn pk_of(&self, table: &str, columns: &[Column], values: &[SqlValRef<'_>]) -> Option<SqlVal> {
+        let model = self.index.models.get(table)?;
+        columns
+            .iter()
+            .position(|c| c.name() == model.pkcol.name())
+            .map(|i| values[i].clone().into())
+    }
+}
+
+impl<'c, C: ConnectionMethods> ConnectionMethods for Indexed<'c, C> {
+    fn execute(&self, sql: &str) -> Result<()> {
+        self.conn.execute(sql)
+    }
+    fn query<'a, 'b, 'd: 'a>(
+        &'d self,
+        table: &str,
+        columns: &'b [Column],
+        expr: Option<BoolExpr>,
+        limit: Option<i32>,
+        offset: Option<i32>,
+        sort: Option<&[Order]>,
+    ) -> Result<RawQueryResult<'a>> {
+        self.conn.query(table, columns, expr, limit, offset, sort)
+    }
+    fn query_stream<'a, 'b, 'd: 'a>(
+        &'d self,
+        table: &str,
+        columns: &'b [Column],
+        expr: Option<BoolExpr>,
+        limit: Option<i32>,
+        offset: Option<i32>,
+        sort: Option<&[Order]>,
+    ) -> Result<RawQueryResult<'a>> {
+        self.conn
+            .query_stream(table, columns, expr, limit, offset, sort)
+    }
+    #[allow(clippy::too_many_arguments)]
+    fn query_distinct<'a, 'b, 'd: 'a>(
+        &'d self,
+        table: &str,
+        columns: &'b [Column],
+        distinct: &crate::query::Distinct,
+        expr: Option<BoolExpr>,
+        limit: Option<i32>,
+        offset: Option<i32>,
+        sort: Option<&[Order]>,
+    ) -> Result<RawQueryResult<'a>> {
+        self.conn
+            .query_distinct(table, columns, distinct, expr, limit, offset, sort)
+    }
+    fn insert_returning_pk(
+        &self,
+        table: &str,
+        columns: &[Column],
+        pkcol: &Column,
+        values: &[SqlValRef<'_>],
+    ) -> Result<SqlVal> {
+        let pk = self
+            .conn
+            .insert_returning_pk(table, columns, pkcol, values)?;
+        self.index.reindex(self.conn, table, pk.clone())?;
+        Ok(pk)
+    }
+    fn insert_only(&self, table: &str, columns: &[Column], values: &[SqlValRef<'_>]) -> Result<()> {
+        self.conn.insert_only(table, columns, values)?;
+        match self.pk_of(table, columns, values) {
+            Some(pk) => self.index.reindex(self.conn, table, pk),
+            None => Ok(()),
+        }
+    }
+    fn insert_or_replace(
+        &self,
+        table: &str,
+        columns: &[Column],
+        pkcol: &Column,
+        values: &[SqlValRef<'_>],
+    ) -> Result<()> {
+        self.conn.insert_or_replace(table, columns, pkcol, values)?;
+        match self.pk_of(table, columns, values) {
+            Some(pk) => self.index.reindex(self.conn, table, pk),
+            None => Ok(()),
+        }
+    }
+    fn update(
+        &self,
+        table: &str,
+        pkcol: Column,
+        pk: SqlValRef,
+        columns: &[Column],
+        values: &[SqlValRef<'_>],
+    ) -> Result<()> {
+        self.conn
+            .update(table, pkcol, pk.clone(), columns, values)?;
+        self.index.reindex(self.conn, table, pk.into())
+    }
+    fn delete_where(&self, table: &str, expr: BoolExpr) -> Result<usize> {
+        let model = match self.index.models.get(table) {
+            Some(model) => model,
+            None => return self.conn.delete_where(table, expr),
+        };
+        // The rows are found first, as the condition may not hold after
+        let pks = self
+            .index
+            .pks(self.conn, table, model, Some(expr.clone()))?;
+        let deleted = self.conn.delete_where(table, expr)?;
+        self.index.remove(self.conn, table, pks)?;
+        Ok(deleted)
+    }
+    fn update_where(
+        &self,
+        table: &str,
+        assignments: &[Assignment],
+        expr: BoolExpr,
+    ) -> Result<usize> {
+        let model = match self.index.models.get(table) {
+            Some(model) => model,
+            None => return self.conn.update_where(table, assignments, expr),
+        };
+        // The rows are found first, as the condition may not hold after
+        let pks = self
+            .index
+            .pks(self.conn, table, model, Some(expr.clone()))?;
+        let updated = self.conn.update_where(table, assignments, expr)?;
+        for pk in pks {
+            self.index.reindex(self.conn, table, pk)?;
+        }
+        Ok(updated)
+    }
+    fn has_table(&self, table: &str) -> Result<bool> {
+        self.conn.has_table(table)
+    }
+    fn execute_batch(&self, statements: &[Statement]) -> Result<()> {
+        // Raw SQL is not indexed, as with execute
+        self.conn.execute_batch(statements)
+    }
+    fn explain(
+        &self,
+        table: &str,
+        columns: &[Column],
+        expr: Option<BoolExpr>,
+        limit: Option<i32>,
+        offset: Option<i32>,
+        sort: Option<&[Order]>,
+        analyze: bool,
+    ) -> Result<QueryPlan> {
+        self.conn
+            .explain(table, columns, expr, limit, offset, sort, analyze)
+    }
+    fn aggregate(
+        &self,
+        table: &str,
+        groups: &[Column],
+        aggregates: &[AggregateExpr],
+        expr: Option<BoolExpr>,
+        having: Option<Having>,
+    ) -> Result<Vec<Vec<SqlVal>>> {
+        self.conn.aggregate(table, groups, aggregates, expr, having)
+    }
+    fn query_raw_rows<'a, 'd: 'a>(
+        &'d self,
+        sql: &str,
+        params: &[SqlVal],
+    ) -> Result<RawQueryResult<'a>> {
+        self.conn.query_raw_rows(sql, params)
+    }
+}
+
+fn index_columns() -> [Column; 3] {
+    [
+        Column::new("model", SqlType::Text),
+        Column::new("pk", SqlType::Text),
+        Column::new("body", SqlType::Text),
+    ]
+}
+
+fn model_entries(table: &str) -> BoolExpr {
+    BoolExpr::Eq("model", Expr::Val(SqlVal::Text(table.to_string())))
+}
+
+/// The primary key as stored in the index.
+fn key(pk: &SqlVal) -> Result<String> {
+    Ok(serde_json::to_string(pk)?)
+}
@@ -55,6 +55,12 @@ impl SqlValRef<'_> {
 ///
 /// For conversion between `SqlVal` and other types, see [`FromSql`] and [`ToSql`].
 ///
+/// Values serialize with serde tagged by the name of their variant,
+/// e.g. `{"Int":1}` in JSON, and timestamps as ISO 8601 text. This
+/// representation is stable, so serialized values may be kept, e.g. in
+/// a cache, across versions of butane. With the `cbor` feature,
+/// [`to_bytes`](SqlVal::to_bytes) gives a compact binary form.
+///
 /// [`FromSql`]: crate::FromSql
 /// [`ToSql`]: crate::ToSql
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
@@ -75,6 +81,17 @@ impl SqlVal {
         SqlValRef::from(self)
     }
 
+    /// Serialize to a compact binary (CBOR) form.
+    #[cfg(feature = "cbor")]
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        crate::cbor::to_bytes(self)
+    }
+    /// Deserialize the binary form produced by [to_bytes](Self::to_bytes).
+    #[cfg(feature = "cbor")]
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        crate::cbor::from_bytes(bytes)
+    }
+
     pub fn bool(&self) -> Result<bool> {
         match self {
             SqlVal::Bool(val) => Ok(*val),
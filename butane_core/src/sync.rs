@@ -0,0 +1,680 @@
+//! Offline-first synchronization between a local database, usually
+//! SQLite, and a remote one, usually Postgres.
+//!
+//! Writes made to the local database through [SyncEngine::track] are
+//! applied locally and recorded in an outbox. When the remote database
+//! is reachable, [SyncEngine::sync] pulls the changes other replicas
+//! have pushed since the last sync and then pushes the local ones. The
+//! remote database keeps a log of all pushed changes, from which each
+//! replica pulls.
+//!
+//! Rows are identified by table and primary key. When a row was
+//! changed both locally and remotely since the last sync, the
+//! [ConflictStrategy] decides which change wins.
+//!
+//! Pushes to the log may commit in another order than that of their
+//! ids, so each pull looks back over the last ids pulled for any
+//! committed since, as set by [SyncEngine::with_look_back].
+use crate::db::{
+    BackendConnection, BackendRows, Column, ConnectionMethods, QueryPlan, RawQueryResult, Statement,
+};
+use crate::migrations::adb::{AColumn, ATable, DeferredSqlType, Operation, TypeIdentifier, ADB};
+use crate::query::{AggregateExpr, Assignment, BoolExpr, Expr, Having, Order, OrderDirection};
+use crate::retry::{Recorder, SpooledWrite};
+use crate::{Error, FromSql, Result, SqlType, SqlVal, SqlValRef};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The table of the local database recording changes not yet pushed.
+pub const OUTBOX_TABLE: &str = "butane_sync_outbox";
+/// The table of the local database recording how far the remote log
+/// has been pulled.
+pub const STATE_TABLE: &str = "butane_sync_state";
+/// The table of the local database recording the ids of the log
+/// entries pulled within the look-back window.
+pub const PULLED_TABLE: &str = "butane_sync_pulled";
+/// The table of the remote database logging all pushed changes.
+pub const LOG_TABLE: &str = "butane_sync_log";
+
+/// The number of log ids a pull looks back over by default.
+pub const DEFAULT_LOOK_BACK: i64 = 1000;
+
+/// A change to a row, made by one replica.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Change {
+    write: SpooledWrite,
+    origin: String,
+    changed_at: i64,
+}
+
+impl Change {
+    /// The table changed.
+    pub fn table(&self) -> &str {
+        self.write.table()
+    }
+
+    /// Whether the change deleted the row.
+    pub fn is_delete(&self) -> bool {
+        matches!(self.write, SpooledWrite::DeleteWhereIn { .. })
+    }
+
+    /// The value the change wrote to `column`, if any.
+    pub fn value(&self, column: &str) -> Option<&SqlVal> {
+        match &self.write {
+            SpooledWrite::InsertOrReplace {
+                columns, values, ..
+            }
+            | SpooledWrite::InsertOnly {
+                columns, values, ..
+            }
+            | SpooledWrite::Update {
+                columns, values, ..
+            } => columns
+                .iter()
+                .position(|(name, _)| name == column)
+                .map(|i| &values[i]),
+            SpooledWrite::DeleteWhereIn { .. } => None,
+        }
+    }
+
+    /// The name of the replica which made the change.
+    pub fn origin(&self) -> &str {
+        &self.origin
+    }
+
+    /// When the change was made, in milliseconds since the Unix epoch,
+    /// according to the clock of the replica which made it.
+    pub fn changed_at(&self) -> i64 {
+        self.changed_at
+    }
+
+    fn overlaps(&self, other: &Change) -> bool {
+        self.table() == other.table()
+            && self
+                .write
+                .keys()
+                .iter()
+                .any(|key| other.write.keys().contains(key))
+    }
+
+    /// This change with the values of `merged` written to the given
+    /// columns.
+    fn merge(&self, merged: &HashMap<String, SqlVal>) -> Result<Change> {
+        let mut change = self.clone();
+        match &mut change.write {
+            SpooledWrite::InsertOrReplace {
+                columns, values, ..
+            }
+            | SpooledWrite::Update {
+                columns, values, ..
+            } => {
+                for (i, (name, _)) in columns.iter().enumerate() {
+                    if let Some(val) = merged.get(name) {
+                        values[i] = val.clone();
+                    }
+                }
+            }
+            _ => {
+                return Err(Error::CannotSync(format!(
+                    "merge with a deletion from {}",
+                    self.table()
+                )))
+            }
+        }
+        change.changed_at = now();
+        Ok(change)
+    }
+}
+
+/// How to resolve a conflict between a local change and a remote one.
+pub enum Resolution {
+    /// Keep the local change, which will overwrite the remote one when
+    /// pushed.
+    KeepLocal,
+    /// Discard the local change in favour of the remote one.
+    KeepRemote,
+    /// Keep the local change with the given column values, e.g.
+    /// combining the values of both changes. The local change must not
+    /// be a deletion.
+    Merged(HashMap<String, SqlVal>),
+}
+
+/// Decides which of two conflicting changes to the same row wins.
+pub trait ConflictStrategy: Send + Sync {
+    fn resolve(&self, local: &Change, remote: &Change) -> Resolution;
+}
+
+impl<F> ConflictStrategy for F
+where
+    F: Fn(&Change, &Change) -> Resolution + Send + Sync,
+{
+    fn resolve(&self, local: &Change, remote: &Change) -> Resolution {
+        self(local, remote)
+    }
+}
+
+/// The most recently made change wins. The remote one wins a tie.
+pub struct LastWriteWins;
+
+impl ConflictStrategy for LastWriteWins {
+    fn resolve(&self, local: &Change, remote: &Change) -> Resolution {
+        if local.changed_at > remote.changed_at {
+            Resolution::KeepLocal
+        } else {
+            Resolution::KeepRemote
+        }
+    }
+}
+
+/// Counts of what a [SyncEngine::sync] did.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SyncReport {
+    /// Remote changes applied locally.
+    pub pulled: usize,
+    /// Local changes applied remotely.
+    pub pushed: usize,
+    /// Remote changes which conflicted with local ones.
+    pub conflicts: usize,
+}
+
+/// Synchronizes a local database with a remote one. See the
+/// [module documentation](self).
+pub struct SyncEngine {
+    replica: String,
+    strategy: Box<dyn ConflictStrategy>,
+    look_back: i64,
+}
+
+impl SyncEngine {
+    /// An engine for the replica named `replica`, which must be unique
+    /// among those syncing with the same remote database. Conflicts
+    /// are resolved with [LastWriteWins].
+    pub fn new(replica: impl Into<String>) -> Self {
+        SyncEngine {
+            replica: replica.into(),
+            strategy: Box::new(LastWriteWins),
+            look_back: DEFAULT_LOOK_BACK,
+        }
+    }
+
+    /// Resolve conflicts with `strategy`.
+    pub fn with_strategy(mut self, strategy: impl ConflictStrategy + 'static) -> Self {
+        self.strategy = Box::new(strategy);
+        self
+    }
+
+    /// Look back over the last `ids` log ids on each pull, for changes
+    /// pushed before those already pulled but committed after them.
+    /// Changes committed later still are not pulled. Defaults to
+    /// [DEFAULT_LOOK_BACK].
+    pub fn with_look_back(mut self, ids: i64) -> Self {
+        self.look_back = ids;
+        self
+    }
+
+    /// Create the tables the engine uses in the local database, if
+    /// necessary. Must be called before writes are tracked.
+    pub fn prepare(&self, local: &impl BackendConnection) -> Result<()> {
+        ensure_table(local, change_table(OUTBOX_TABLE))?;
+        ensure_table(local, state_table())?;
+        ensure_table(local, pulled_table())
+    }
+
+    /// Wrap the local connection `conn` so that writes made through it
+    /// are recorded for pushing. Writes are recorded with the same
+    /// connection, so that they are recorded if and only if a
+    /// transaction making them commits. Raw SQL run with `execute` is
+    /// not recorded.
+    pub fn track<'c, C: ConnectionMethods>(&'c self, conn: &'c C) -> Tracked<'c, C> {
+        Tracked { engine: self, conn }
+    }
+
+    /// The number of local changes not yet pushed.
+    pub fn pending(&self, local: &impl ConnectionMethods) -> Result<usize> {
+        Ok(outbox(local)?.len())
+    }
+
+    /// Pull remote changes and then push local ones. Writes are
+    /// idempotent, so a sync which was interrupted may simply be run
+    /// again.
+    pub fn sync(
+        &self,
+        local: &impl BackendConnection,
+        remote: &impl BackendConnection,
+    ) -> Result<SyncReport> {
+        let mut report = self.pull(local, remote)?;
+        report.pushed = self.push(local, remote)?;
+        Ok(report)
+    }
+
+    /// Apply the changes other replicas have pushed since the last pull
+    /// to the local database, resolving conflicts with unpushed local
+    /// changes.
+    pub fn pull(
+        &self,
+        local: &impl BackendConnection,
+        remote: &impl BackendConnection,
+    ) -> Result<SyncReport> {
+        self.prepare(local)?;
+        ensure_table(remote, change_table(LOG_TABLE))?;
+        let mut report = SyncReport::default();
+        let mut pulled = pulled_to(local)?;
+        let recent = pulled_ids(local)?;
+        let from = pulled.saturating_sub(self.look_back);
+        let expr = BoolExpr::Gt("id", Expr::Val(SqlVal::BigInt(from)));
+        for (id, remote_change) in read_changes(remote, LOG_TABLE, Some(expr))? {
+            if recent.contains(&id) {
+                continue;
+            }
+            pulled = pulled.max(id);
+            if remote_change.origin == self.replica {
+                add_pulled_id(local, id)?;
+                continue;
+            }
+            let conflicting: Vec<(i64, Change)> = outbox(local)?
+                .into_iter()
+                .filter(|(_, local_change)| local_change.overlaps(&remote_change))
+                .collect();
+            match conflicting.last() {
+                None => remote_change.write.replay(local)?,
+                Some((_, local_change)) => {
+                    report.conflicts += 1;
+                    match self.strategy.resolve(local_change, &remote_change) {
+                        Resolution::KeepLocal => (),
+                        Resolution::KeepRemote => {
+                            remote_change.write.replay(local)?;
+                            remove_from_outbox(local, &conflicting)?;
+                        }
+                        Resolution::Merged(values) => {
+                            let merged = local_change.merge(&values)?;
+                            merged.write.replay(local)?;
+                            remove_from_outbox(local, &conflicting)?;
+                            add_to_outbox(local, &merged)?;
+                        }
+                    }
+                }
+            }
+            add_pulled_id(local, id)?;
+            report.pulled += 1;
+        }
+        set_pulled_to(local, pulled)?;
+        let before = pulled.saturating_sub(self.look_back);
+        local.delete_where(
+            PULLED_TABLE,
+            BoolExpr::Le("id", Expr::Val(SqlVal::BigInt(before))),
+        )?;
+        Ok(report)
+    }
+
+    /// Apply unpushed local changes to the remote database and log them
+    /// there for other replicas to pull. Returns the number of changes
+    /// pushed.
+    pub fn push(
+        &self,
+        local: &impl BackendConnection,
+        remote: &impl BackendConnection,
+    ) -> Result<usize> {
+        ensure_table(remote, change_table(LOG_TABLE))?;
+        let pending = outbox(local)?;
+        for (id, change) in &pending {
+            change.write.replay(remote)?;
+            remote.insert_only(
+                LOG_TABLE,
+                &[Column::new("data", SqlType::Text)],
+                &[SqlValRef::Text(&serde_json::to_string(change)?)],
+            )?;
+            local.delete_where(
+                OUTBOX_TABLE,
+                BoolExpr::Eq("id", Expr::Val(SqlVal::BigInt(*id))),
+            )?;
+        }
+        Ok(pending.len())
+    }
+
+    fn record(&self, conn: &impl ConnectionMethods, writes: Vec<SpooledWrite>) -> Result<()> {
+        for write in writes {
+            add_to_outbox(
+                conn,
+                &Change {
+                    write,
+                    origin: self.replica.clone(),
+                    changed_at: now(),
+                },
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// A local connection whose writes are recorded for syncing. Created by
+/// [SyncEngine::track].
+pub struct Tracked<'c, C> {
+    engine: &'c SyncEngine,
+    conn: &'c C,
+}
+
+impl<'c, C: ConnectionMethods> Tracked<'c, C> {
+    fn check_prepared(&self) -> Result<()> {
+        if self.conn.has_table(OUTBOX_TABLE)? {
+            Ok(())
+        } else {
+            Err(Error::CannotSync(
+                "writes before SyncEngine::prepare".to_string(),
+            ))
+        }
+    }
+
+    fn track(&self, f: impl FnOnce(&Recorder) -> Result<()>) -> Result<()> {
+        let writes = Recorder::record(f).map_err(|e| match e {
+            Error::CannotSpool(what) => Error::CannotSync(what),
+            e => e,
+        })?;
+        self.engine.record(self.conn, writes)
+    }
+}
+
+impl<'c, C: ConnectionMethods> ConnectionMethods for Tracked<'c, C> {
+    fn execute(&self, sql: &str) -> Result<()> {
+        self.conn.execute(sql)
+    }
+    fn query<'a, 'b, 'd: 'a>(
+        &'d self,
+        table: &str,
+        columns: &'b [Column],
+        expr: Option<BoolExpr>,
+        limit: Option<i32>,
+        offset: Option<i32>,
+        sort: Option<&[Order]>,
+    ) -> Result<RawQueryResult<'a>> {
+        self.conn.query(table, columns, expr, limit, offset, sort)
+    }
+    fn query_stream<'a, 'b, 'd: 'a>(
+        &'d self,
+        table: &str,
+        columns: &'b [Column],
+        expr: Option<BoolExpr>,
+        limit: Option<i32>,
+        offset: Option<i32>,
+        sort: Option<&[Order]>,
+    ) -> Result<RawQueryResult<'a>> {
+        self.conn
+            .query_stream(table, columns, expr, limit, offset, sort)
+    }
+    #[allow(clippy::too_many_arguments)]
+    fn query_distinct<'a, 'b, 'd: 'a>(
+        &'d self,
+        table: &str,
+        columns: &'b [Column],
+        distinct: &crate::query::Distinct,
+        expr: Option<BoolExpr>,
+        limit: Option<i32>,
+        offset: Option<i32>,
+        sort: Option<&[Order]>,
+    ) -> Result<RawQueryResult<'a>> {
+        self.conn
+            .query_distinct(table, columns, distinct, expr, limit, offset, sort)
+    }
+    fn insert_returning_pk(
+        &self,
+        table: &str,
+        columns: &[Column],
+        pkcol: &Column,
+        values: &[SqlValRef<'_>],
+    ) -> Result<SqlVal> {
+        self.check_prepared()?;
+        let pk = self
+            .conn
+            .insert_returning_pk(table, columns, pkcol, values)?;
+        // An automatic primary key is only known once inserted
+        let mut columns = columns.to_vec();
+        let mut values = values.to_vec();
+        if !columns.iter().any(|c| c.name() == pkcol.name()) {
+            columns.push(pkcol.clone());
+            values.push(pk.as_ref());
+        }
+        self.track(|r| r.insert_or_replace(table, &columns, pkcol, &values))?;
+        Ok(pk)
+    }
+    fn insert_only(&self, table: &str, columns: &[Column], values: &[SqlValRef<'_>]) -> Result<()> {
+        self.check_prepared()?;
+        self.conn.insert_only(table, columns, values)?;
+        self.track(|r| r.insert_only(table, columns, values))
+    }
+    fn insert_or_replace(
+        &self,
+        table: &str,
+        columns: &[Column],
+        pkcol: &Column,
+        values: &[SqlValRef<'_>],
+    ) -> Result<()> {
+        self.check_prepared()?;
+        self.conn.insert_or_replace(table, columns, pkcol, values)?;
+        self.track(|r| r.insert_or_replace(table, columns, pkcol, values))
+    }
+    fn update(
+        &self,
+        table: &str,
+        pkcol: Column,
+        pk: SqlValRef,
+        columns: &[Column],
+        values: &[SqlValRef<'_>],
+    ) -> Result<()> {
+        self.check_prepared()?;
+        self.conn
+            .update(table, pkcol.clone(), pk.clone(), columns, values)?;
+        self.track(|r| r.update(table, pkcol, pk, columns, values))
+    }
+    fn delete_where(&self, table: &str, expr: BoolExpr) -> Result<usize> {
+        self.check_prepared()?;
+        let mut deleted = 0;
+        // Recorded first, as not all conditions can be
+        self.track(|r| {
+            r.delete_where(table, expr.clone())?;
+            deleted = self.conn.delete_where(table, expr)?;
+            Ok(())
+        })?;
+        Ok(deleted)
+    }
+    fn update_where(
+        &self,
+        table: &str,
+        assignments: &[Assignment],
+        expr: BoolExpr,
+    ) -> Result<usize> {
+        self.check_prepared()?;
+        let mut updated = 0;
+        // Recorded first, as bulk updates cannot be
+        self.track(|r| {
+            r.update_where(table, assignments, expr.clone())?;
+            updated = self.conn.update_where(table, assignments, expr)?;
+            Ok(())
+        })?;
+        Ok(updated)
+    }
+    fn has_table(&self, table: &str) -> Result<bool> {
+        self.conn.has_table(table)
+    }
+    fn execute_batch(&self, statements: &[Statement]) -> Result<()> {
+        // Raw SQL is not tracked, as with execute
+        self.conn.execute_batch(statements)
+    }
+    fn explain(
+        &self,
+        table: &str,
+        columns: &[Column],
+        expr: Option<BoolExpr>,
+        limit: Option<i32>,
+        offset: Option<i32>,
+        sort: Option<&[Order]>,
+        analyze: bool,
+    ) -> Result<QueryPlan> {
+        self.conn
+            .explain(table, columns, expr, limit, offset, sort, analyze)
+    }
+    fn aggregate(
+        &self,
+        table: &str,
+        groups: &[Column],
+        aggregates: &[AggregateExpr],
+        expr: Option<BoolExpr>,
+        having: Option<Having>,
+    ) -> Result<Vec<Vec<SqlVal>>> {
+        self.conn.aggregate(table, groups, aggregates, expr, having)
+    }
+    fn query_raw_rows<'a, 'd: 'a>(
+        &'d self,
+        sql: &str,
+        params: &[SqlVal],
+    ) -> Result<RawQueryResult<'a>> {
+        self.conn.query_raw_rows(sql, params)
+    }
+}
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as i64)
+        .unwrap_or(0)
+}
+
+fn ensure_table(conn: &impl BackendConnection, table: ATable) -> Result<()> {
+    if !conn.has_table(&table.name)? {
+        let sql = conn
+            .backend()
+            .create_migration_sql(&ADB::new(), vec![Operation::AddTableIfNotExists(table)])?;
+        conn.execute(&sql)?;
+    }
+    Ok(())
+}
+
+fn change_table(name: &str) -> ATable {
+    let mut table = ATable::new(name.to_string());
+    table.add_column(AColumn::new(
+        "id",
+        known(SqlType::BigInt),
+        false, // nullable
+        true,  // pk
+        true,  // auto
+        false, // unique
+        None,
+    ));
+    table.add_column(AColumn::new_simple("data", known(SqlType::Text)));
+    table
+}
+
+fn state_table() -> ATable {
+    let mut table = ATable::new(STATE_TABLE.to_string());
+    table.add_column(AColumn::new(
+        "name",
+        known(SqlType::Text),
+        false, // nullable
+        true,  // pk
+        false, // auto
+        false, // unique
+        None,
+    ));
+    table.add_column(AColumn::new_simple("value", known(SqlType::BigInt)));
+    table
+}
+
+fn pulled_table() -> ATable {
+    let mut table = ATable::new(PULLED_TABLE.to_string());
+    table.add_column(AColumn::new(
+        "id",
+        known(SqlType::BigInt),
+        false, // nullable
+        true,  // pk
+        false, // auto
+        false, // unique
+        None,
+    ));
+    table
+}
+
+fn known(ty: SqlType) -> DeferredSqlType {
+    DeferredSqlType::KnownId(TypeIdentifier::Ty(ty))
+}
+
+fn read_changes(
+    conn: &impl ConnectionMethods,
+    table: &str,
+    expr: Option<BoolExpr>,
+) -> Result<Vec<(i64, Change)>> {
+    let columns = [
+        Column::new("id", SqlType::BigInt),
+        Column::new("data", SqlType::Text),
+    ];
+    let order = [Order {
+        column: "id",
+        direction: OrderDirection::Ascending,
+        nulls: None,
+    }];
+    let mut rows = conn.query(table, &columns, expr, None, None, Some(&order))?;
+    let mut changes = Vec::new();
+    while let Some(row) = rows.next()? {
+        let id = i64::from_sql_ref(row.get(0, SqlType::BigInt)?)?;
+        let data = String::from_sql_ref(row.get(1, SqlType::Text)?)?;
+        changes.push((id, serde_json::from_str(&data)?));
+    }
+    Ok(changes)
+}
+
+fn outbox(conn: &impl ConnectionMethods) -> Result<Vec<(i64, Change)>> {
+    if !conn.has_table(OUTBOX_TABLE)? {
+        return Ok(Vec::new());
+    }
+    read_changes(conn, OUTBOX_TABLE, None)
+}
+
+fn add_to_outbox(conn: &impl ConnectionMethods, change: &Change) -> Result<()> {
+    conn.insert_only(
+        OUTBOX_TABLE,
+        &[Column::new("data", SqlType::Text)],
+        &[SqlValRef::Text(&serde_json::to_string(change)?)],
+    )
+}
+
+fn remove_from_outbox(conn: &impl ConnectionMethods, changes: &[(i64, Change)]) -> Result<()> {
+    let ids = changes.iter().map(|(id, _)| SqlVal::BigInt(*id)).collect();
+    conn.delete_where(OUTBOX_TABLE, BoolExpr::In("id", ids))?;
+    Ok(())
+}
+
+fn pulled_to(conn: &impl ConnectionMethods) -> Result<i64> {
+    let columns = [Column::new("value", SqlType::BigInt)];
+    let expr = BoolExpr::Eq("name", Expr::Val(SqlVal::Text("pulled".to_string())));
+    let mut rows = conn.query(STATE_TABLE, &columns, Some(expr), Some(1), None, None)?;
+    match rows.next()? {
+        Some(row) => i64::from_sql_ref(row.get(0, SqlType::BigInt)?),
+        None => Ok(0),
+    }
+}
+
+fn set_pulled_to(conn: &impl ConnectionMethods, id: i64) -> Result<()> {
+    let pkcol = Column::new("name", SqlType::Text);
+    conn.insert_or_replace(
+        STATE_TABLE,
+        &[pkcol.clone(), Column::new("value", SqlType::BigInt)],
+        &pkcol,
+        &[SqlValRef::Text("pulled"), SqlValRef::BigInt(id)],
+    )
+}
+
+fn pulled_ids(conn: &impl ConnectionMethods) -> Result<HashSet<i64>> {
+    let columns = [Column::new("id", SqlType::BigInt)];
+    let mut rows = conn.query(PULLED_TABLE, &columns, None, None, None, None)?;
+    let mut ids = HashSet::new();
+    while let Some(row) = rows.next()? {
+        ids.insert(i64::from_sql_ref(row.get(0, SqlType::BigInt)?)?);
+    }
+    Ok(ids)
+}
+
+fn add_pulled_id(conn: &impl ConnectionMethods, id: i64) -> Result<()> {
+    conn.insert_only(
+        PULLED_TABLE,
+        &[Column::new("id", SqlType::BigInt)],
+        &[SqlValRef::BigInt(id)],
+    )
+}
@@ -0,0 +1,192 @@
+//! Two-phase commit of writes to several databases.
+//!
+//! A [Coordinator] commits a transaction on each of several Postgres
+//! databases such that either all of them or none of them are
+//! committed. Each transaction is first prepared with
+//! [Transaction::prepare], which makes its changes durable without
+//! making them visible. Once all are prepared, the decision to commit
+//! is recorded in the coordinator's log and they are committed.
+//!
+//! A crash between the two phases leaves transactions prepared but
+//! neither committed nor rolled back. These are in doubt: they hold
+//! their locks until resolved. [Coordinator::recover], called at
+//! startup, commits those for which the decision to commit was logged
+//! and rolls back the others.
+//!
+//! Prepared transactions require the Postgres setting
+//! `max_prepared_transactions` to be greater than zero.
+use crate::db::{BackendConnection, Transaction};
+use crate::{Error, Result};
+use std::collections::HashSet;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, ErrorKind, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Counter distinguishing transactions begun in the same instant.
+static NEXT_TXID: AtomicU64 = AtomicU64::new(0);
+
+/// Coordinator of transactions committed together on several databases.
+///
+/// The prepared transactions are named `{name}:{txid}:{participant}`.
+/// The name must be unique among the coordinators using the same
+/// databases, as recovery resolves all the prepared transactions
+/// carrying it.
+pub struct Coordinator {
+    name: String,
+    log: PathBuf,
+}
+
+impl Coordinator {
+    /// Create a coordinator named `name` logging its decisions to the
+    /// file at `log`, one transaction per line. The log must be kept
+    /// across restarts for recovery.
+    pub fn new(name: impl Into<String>, log: impl Into<PathBuf>) -> Self {
+        Coordinator {
+            name: name.into(),
+            log: log.into(),
+        }
+    }
+
+    /// Begin a transaction on each of `conns`, run `f` with them, and
+    /// commit them all together if it succeeds. The transactions are
+    /// given to `f` in the same order as `conns`. If `f` fails or any
+    /// of the transactions cannot be prepared, all are rolled back.
+    ///
+    /// Once all have been prepared, the transactions are committed even
+    /// if some cannot be at once: the first failure is returned as
+    /// [Error::InDoubt], and [recover][Self::recover] commits the
+    /// transactions left prepared.
+    pub fn execute<C, T, F>(&self, conns: &mut [&mut C], f: F) -> Result<T>
+    where
+        C: BackendConnection,
+        F: FnOnce(&[Transaction]) -> Result<T>,
+    {
+        let txid = new_txid();
+        let transactions = conns
+            .iter_mut()
+            .map(|conn| conn.transaction())
+            .collect::<Result<Vec<Transaction>>>()?;
+        let value = f(&transactions)?;
+
+        let mut prepared = 0;
+        let mut failure = None;
+        for (i, trans) in transactions.into_iter().enumerate() {
+            // Transactions after a failure are rolled back as they are dropped
+            if let Err(e) = trans.prepare(&self.gid(&txid, i)) {
+                failure = Some(e);
+                break;
+            }
+            prepared += 1;
+        }
+        if failure.is_none() {
+            failure = self.log_decision(&txid).err();
+        }
+        if let Some(e) = failure {
+            for (i, conn) in conns.iter().enumerate().take(prepared) {
+                // On failure the transaction is left for recovery, which
+                // rolls back undecided transactions
+                conn.rollback_prepared(&self.gid(&txid, i)).ok();
+            }
+            return Err(e);
+        }
+
+        let mut failure = None;
+        for (i, conn) in conns.iter().enumerate() {
+            let gid = self.gid(&txid, i);
+            if let Err(e) = conn.commit_prepared(&gid) {
+                failure.get_or_insert(Error::InDoubt {
+                    gid,
+                    source: Box::new(e),
+                });
+            }
+        }
+        match failure {
+            Some(e) => Err(e),
+            None => Ok(value),
+        }
+    }
+
+    /// Resolve the transactions of this coordinator left prepared on
+    /// `conns`, committing those logged as decided and rolling back the
+    /// others. Must be called before any [execute][Self::execute], as
+    /// it would roll back transactions being prepared.
+    ///
+    /// Decisions are removed from the log once resolved, so `conns`
+    /// must include every database this coordinator commits to.
+    pub fn recover<C: BackendConnection>(&self, conns: &[&C]) -> Result<RecoveryReport> {
+        let decided = self.decisions()?;
+        let mut report = RecoveryReport::default();
+        for conn in conns {
+            for gid in conn.prepared_transactions()? {
+                let txid = match self.txid_of(&gid) {
+                    Some(txid) => txid,
+                    None => continue,
+                };
+                if decided.contains(txid) {
+                    conn.commit_prepared(&gid)?;
+                    report.committed += 1;
+                } else {
+                    conn.rollback_prepared(&gid)?;
+                    report.rolled_back += 1;
+                }
+            }
+        }
+        match fs::remove_file(&self.log) {
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(report),
+        }
+    }
+
+    fn gid(&self, txid: &str, participant: usize) -> String {
+        format!("{}:{}:{}", self.name, txid, participant)
+    }
+
+    /// The transaction `gid` is part of, if prepared by this coordinator.
+    fn txid_of<'a>(&self, gid: &'a str) -> Option<&'a str> {
+        let rest = gid.strip_prefix(&self.name)?.strip_prefix(':')?;
+        match rest.split(':').collect::<Vec<&str>>().as_slice() {
+            [txid, participant] if participant.parse::<usize>().is_ok() => Some(txid),
+            _ => None,
+        }
+    }
+
+    fn log_decision(&self, txid: &str) -> Result<()> {
+        let mut f = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.log)?;
+        writeln!(f, "{}", txid)?;
+        f.sync_all()?;
+        Ok(())
+    }
+
+    fn decisions(&self) -> Result<HashSet<String>> {
+        match File::open(&self.log) {
+            Ok(f) => Ok(BufReader::new(f).lines().collect::<std::io::Result<_>>()?),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(HashSet::new()),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+/// Counts of the in-doubt transactions resolved by
+/// [Coordinator::recover].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RecoveryReport {
+    pub committed: usize,
+    pub rolled_back: usize,
+}
+
+fn new_txid() -> String {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_nanos());
+    format!(
+        "{:x}-{:x}-{:x}",
+        nanos,
+        std::process::id(),
+        NEXT_TXID.fetch_add(1, Ordering::Relaxed)
+    )
+}